@@ -0,0 +1,5 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/billing.proto")
+        .expect("Failed to compile proto/billing.proto");
+}
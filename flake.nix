@@ -17,6 +17,7 @@
             pkgs.clippy
             pkgs.pkg-config
             pkgs.openssl
+            pkgs.protobuf
             pkgs.sqlite
             pkgs.diesel-cli
             pkgs.flyctl
@@ -0,0 +1 @@
+ALTER TABLE purchase_tokens ADD COLUMN environment VARCHAR(20) NOT NULL DEFAULT 'production';
@@ -0,0 +1,9 @@
+CREATE TABLE token_transfers (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    purchase_token TEXT NOT NULL,
+    from_user_id VARCHAR(255) NOT NULL,
+    to_user_id VARCHAR(255) NOT NULL,
+    transferred_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_token_transfers_purchase_token ON token_transfers (purchase_token);
@@ -0,0 +1,2 @@
+ALTER TABLE purchase_tokens DROP COLUMN notified_at;
+ALTER TABLE purchase_tokens DROP COLUMN auto_renewing;
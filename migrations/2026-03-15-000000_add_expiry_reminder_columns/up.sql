@@ -0,0 +1,2 @@
+ALTER TABLE purchase_tokens ADD COLUMN auto_renewing BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE purchase_tokens ADD COLUMN notified_at TIMESTAMP;
@@ -0,0 +1,13 @@
+CREATE TABLE refund_requests (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    purchase_token TEXT NOT NULL,
+    order_id VARCHAR(255),
+    reason TEXT NOT NULL,
+    status VARCHAR(50) NOT NULL DEFAULT 'requested',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_refund_requests_status ON refund_requests (status);
+CREATE INDEX idx_refund_requests_purchase_token ON refund_requests (purchase_token);
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS unhandled_notifications;
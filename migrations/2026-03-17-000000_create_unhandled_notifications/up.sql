@@ -0,0 +1,10 @@
+CREATE TABLE unhandled_notifications (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    kind VARCHAR(50) NOT NULL,
+    notification_type INTEGER,
+    purchase_token TEXT,
+    payload TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_unhandled_notifications_kind ON unhandled_notifications (kind);
@@ -0,0 +1,12 @@
+CREATE TABLE orders (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    order_id VARCHAR(255) NOT NULL UNIQUE,
+    purchase_token TEXT NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    region_code VARCHAR(10),
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_orders_recorded_at ON orders (recorded_at);
+CREATE INDEX idx_orders_purchase_token ON orders (purchase_token);
@@ -0,0 +1,5 @@
+CREATE TABLE feature_flags (
+    name VARCHAR(255) PRIMARY KEY NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
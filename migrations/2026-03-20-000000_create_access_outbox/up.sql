@@ -0,0 +1,14 @@
+CREATE TABLE access_outbox (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    action VARCHAR(20) NOT NULL,
+    product_id VARCHAR(255),
+    purchase_token TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_access_outbox_status ON access_outbox (status, created_at);
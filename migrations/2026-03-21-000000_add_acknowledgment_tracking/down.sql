@@ -0,0 +1,2 @@
+ALTER TABLE purchase_tokens DROP COLUMN acknowledged_at;
+ALTER TABLE purchase_tokens DROP COLUMN package_name;
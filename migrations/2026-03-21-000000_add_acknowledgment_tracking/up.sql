@@ -0,0 +1,5 @@
+ALTER TABLE purchase_tokens ADD COLUMN package_name VARCHAR(255);
+ALTER TABLE purchase_tokens ADD COLUMN acknowledged_at TIMESTAMP;
+
+-- Tokens stored so far were acknowledged before they were written
+UPDATE purchase_tokens SET acknowledged_at = created_at;
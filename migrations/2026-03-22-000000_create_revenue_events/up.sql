@@ -0,0 +1,15 @@
+CREATE TABLE revenue_events (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    order_id VARCHAR(255) NOT NULL UNIQUE,
+    purchase_token TEXT NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    base_plan_id VARCHAR(255),
+    offer_id VARCHAR(255),
+    region_code VARCHAR(10),
+    currency_code VARCHAR(3) NOT NULL,
+    price_micros BIGINT NOT NULL,
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_revenue_events_recorded_at ON revenue_events (recorded_at);
@@ -0,0 +1,11 @@
+CREATE TABLE admin_audit_log (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    operator VARCHAR(255) NOT NULL,
+    action VARCHAR(20) NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    purchase_token TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_admin_audit_log_user_id ON admin_audit_log (user_id, created_at);
@@ -0,0 +1,11 @@
+CREATE TABLE products (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    package_name VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    base_plan_id VARCHAR(255) NOT NULL,
+    state VARCHAR(30) NOT NULL,
+    billing_period VARCHAR(20),
+    archived BOOLEAN NOT NULL DEFAULT FALSE,
+    synced_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (package_name, product_id, base_plan_id)
+);
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_delete;
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_update;
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_insert;
+DROP TABLE IF EXISTS subscriptions;
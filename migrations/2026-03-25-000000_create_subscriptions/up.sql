@@ -0,0 +1,72 @@
+-- One row per subscription a user holds, whatever it was bought through
+CREATE TABLE subscriptions (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    source VARCHAR(30) NOT NULL,
+    -- Identifier of the subscription at the source, e.g. the Google purchase token
+    source_ref TEXT NOT NULL,
+    status VARCHAR(30) NOT NULL,
+    product_id VARCHAR(255),
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    auto_renewing BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (source, source_ref)
+);
+
+CREATE INDEX idx_subscriptions_user_status ON subscriptions (user_id, status, expires_at);
+
+INSERT INTO subscriptions (id, user_id, source, source_ref, status, started_at, expires_at, auto_renewing, updated_at)
+SELECT
+    id,
+    user_id,
+    CASE environment WHEN 'manual' THEN 'manual' ELSE 'google_play' END,
+    purchase_token,
+    CASE status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+    created_at,
+    expiry_at,
+    auto_renewing,
+    CURRENT_TIMESTAMP
+FROM purchase_tokens;
+
+-- Google Play and manual grants keep living in purchase_tokens, mirror every change
+CREATE TRIGGER purchase_tokens_subscriptions_insert AFTER INSERT ON purchase_tokens
+BEGIN
+    INSERT INTO subscriptions (id, user_id, source, source_ref, status, started_at, expires_at, auto_renewing, updated_at)
+    VALUES (
+        NEW.id,
+        NEW.user_id,
+        CASE NEW.environment WHEN 'manual' THEN 'manual' ELSE 'google_play' END,
+        NEW.purchase_token,
+        CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        NEW.created_at,
+        NEW.expiry_at,
+        NEW.auto_renewing,
+        CURRENT_TIMESTAMP
+    )
+    ON CONFLICT (source, source_ref) DO UPDATE SET
+        user_id = excluded.user_id,
+        status = excluded.status,
+        started_at = excluded.started_at,
+        expires_at = excluded.expires_at,
+        auto_renewing = excluded.auto_renewing,
+        updated_at = excluded.updated_at;
+END;
+
+CREATE TRIGGER purchase_tokens_subscriptions_update AFTER UPDATE ON purchase_tokens
+BEGIN
+    UPDATE subscriptions SET
+        user_id = NEW.user_id,
+        status = CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        started_at = NEW.created_at,
+        expires_at = NEW.expiry_at,
+        auto_renewing = NEW.auto_renewing,
+        updated_at = CURRENT_TIMESTAMP
+    WHERE source IN ('google_play', 'manual') AND source_ref = OLD.purchase_token;
+END;
+
+CREATE TRIGGER purchase_tokens_subscriptions_delete AFTER DELETE ON purchase_tokens
+BEGIN
+    DELETE FROM subscriptions
+    WHERE source IN ('google_play', 'manual') AND source_ref = OLD.purchase_token;
+END;
@@ -0,0 +1 @@
+ALTER TABLE purchase_tokens DROP COLUMN last_event_time;
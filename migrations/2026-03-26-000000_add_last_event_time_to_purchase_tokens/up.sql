@@ -0,0 +1,2 @@
+-- eventTimeMillis of the last RTDN applied to the token, older notifications are discarded
+ALTER TABLE purchase_tokens ADD COLUMN last_event_time TIMESTAMP;
@@ -0,0 +1,3 @@
+ALTER TABLE access_outbox DROP COLUMN run_after;
+ALTER TABLE purchase_tokens DROP COLUMN resumes_at;
+ALTER TABLE purchase_tokens DROP COLUMN paused_from;
@@ -0,0 +1,6 @@
+-- Pause window Google reported for the subscription, access is revoked in between
+ALTER TABLE purchase_tokens ADD COLUMN paused_from TIMESTAMP;
+ALTER TABLE purchase_tokens ADD COLUMN resumes_at TIMESTAMP;
+
+-- Outbox entries are not applied before this time, unset means as soon as possible
+ALTER TABLE access_outbox ADD COLUMN run_after TIMESTAMP;
@@ -0,0 +1,11 @@
+CREATE TABLE user_risk (
+    user_id VARCHAR(255) PRIMARY KEY NOT NULL,
+    revoked_count INTEGER NOT NULL DEFAULT 0,
+    voided_count INTEGER NOT NULL DEFAULT 0,
+    flagged_at TIMESTAMP,
+    approved_at TIMESTAMP,
+    approved_by VARCHAR(255),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_user_risk_flagged_at ON user_risk (flagged_at);
@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS purchase_tokens_touch_updated_at;
+DROP INDEX IF EXISTS idx_purchase_tokens_updated_at;
+ALTER TABLE purchase_tokens DROP COLUMN updated_at;
@@ -0,0 +1,12 @@
+-- Last change to the token, lets the warehouse export load tokens incrementally
+ALTER TABLE purchase_tokens ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE purchase_tokens SET updated_at = COALESCE(last_event_time, created_at);
+
+CREATE INDEX idx_purchase_tokens_updated_at ON purchase_tokens (updated_at, id);
+
+-- Tokens are updated from many places, bump the timestamp for all of them
+CREATE TRIGGER purchase_tokens_touch_updated_at AFTER UPDATE ON purchase_tokens
+WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE purchase_tokens SET updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = NEW.id;
+END;
@@ -0,0 +1,8 @@
+CREATE TABLE subscription_snapshots (
+    purchase_token VARCHAR(255) NOT NULL,
+    fetched_at TIMESTAMP NOT NULL,
+    body BLOB NOT NULL,
+    PRIMARY KEY (purchase_token, fetched_at)
+);
+
+CREATE INDEX idx_subscription_snapshots_fetched_at ON subscription_snapshots (fetched_at);
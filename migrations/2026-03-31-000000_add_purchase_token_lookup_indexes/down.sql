@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_purchase_tokens_unacknowledged;
+DROP INDEX IF EXISTS idx_purchase_tokens_status_expiry;
+DROP INDEX IF EXISTS idx_purchase_tokens_user_status_expiry;
+
+CREATE INDEX idx_user_id ON purchase_tokens (user_id);
+CREATE INDEX idx_status ON purchase_tokens (status);
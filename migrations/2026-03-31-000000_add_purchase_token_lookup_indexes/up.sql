@@ -0,0 +1,12 @@
+-- `purchase_token` is already indexed through its UNIQUE constraint
+
+-- Entitlement, transfer and admin lookups filter a user's tokens by status and expiry
+DROP INDEX IF EXISTS idx_user_id;
+CREATE INDEX idx_purchase_tokens_user_status_expiry ON purchase_tokens (user_id, status, expiry_at);
+
+-- Expiry sweep, expiry reminders and pending reconciliation page through one status by expiry
+DROP INDEX IF EXISTS idx_status;
+CREATE INDEX idx_purchase_tokens_status_expiry ON purchase_tokens (status, expiry_at, id);
+
+-- Acknowledgment monitor pages through unacknowledged grants, oldest first
+CREATE INDEX idx_purchase_tokens_unacknowledged ON purchase_tokens (status, acknowledged_at, created_at, id);
@@ -0,0 +1,30 @@
+CREATE TABLE subscription_events (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    purchase_token TEXT NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    order_id VARCHAR(255),
+    product_id VARCHAR(255) NOT NULL,
+    occurred_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_subscription_events_user ON subscription_events (user_id, occurred_at, id);
+
+-- Purchases and renewals recorded before the timeline existed, the first order of a token is its purchase
+INSERT INTO subscription_events (id, user_id, purchase_token, kind, order_id, product_id, occurred_at)
+SELECT
+    id,
+    user_id,
+    purchase_token,
+    CASE
+        WHEN EXISTS (
+            SELECT 1 FROM orders AS earlier
+            WHERE earlier.purchase_token = orders.purchase_token
+              AND earlier.recorded_at < orders.recorded_at
+        ) THEN 'renewal'
+        ELSE 'purchase'
+    END,
+    order_id,
+    product_id,
+    recorded_at
+FROM orders;
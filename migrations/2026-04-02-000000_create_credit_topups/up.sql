@@ -0,0 +1,9 @@
+-- One row per Google order whose credit allotment was added, so redelivered renewals add nothing
+CREATE TABLE credit_topups (
+    order_id VARCHAR(255) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    amount INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_credit_topups_user_id ON credit_topups (user_id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS compensation_grants;
+DROP TABLE IF EXISTS compensation_batches;
@@ -0,0 +1,27 @@
+-- Pro days or credits granted to users affected by an incident, one row per batch
+CREATE TABLE compensation_batches (
+    id VARCHAR(255) PRIMARY KEY NOT NULL,
+    incident VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    amount INTEGER NOT NULL,
+    operator VARCHAR(255) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_compensation_batches_incident ON compensation_batches (incident);
+
+-- Result for each user of a batch, rows still `pending` are picked up when the batch resumes
+CREATE TABLE compensation_grants (
+    batch_id VARCHAR(255) NOT NULL REFERENCES compensation_batches (id),
+    user_id VARCHAR(255) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    error TEXT,
+    purchase_token VARCHAR(255),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (batch_id, user_id)
+);
+
+CREATE INDEX idx_compensation_grants_status ON compensation_grants (batch_id, status);
@@ -0,0 +1,13 @@
+CREATE TABLE held_notifications (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    purchase_token TEXT NOT NULL,
+    event_time TIMESTAMP NOT NULL,
+    notification_type INTEGER NOT NULL,
+    payload TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    release_at TIMESTAMP NOT NULL
+);
+
+-- Pub/Sub redeliveries of a held notification are stored once
+CREATE UNIQUE INDEX idx_held_notifications_token_event ON held_notifications (purchase_token, event_time);
+CREATE INDEX idx_held_notifications_release_at ON held_notifications (release_at);
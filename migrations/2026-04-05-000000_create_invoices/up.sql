@@ -0,0 +1,32 @@
+-- One invoice per Google Play order, i.e. per purchase and renewal
+CREATE TABLE invoices (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    order_id VARCHAR(255) NOT NULL UNIQUE,
+    user_id VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    base_plan_id VARCHAR(255),
+    offer_id VARCHAR(255),
+    region_code VARCHAR(10),
+    -- Both NULL when Play reported no price for the order
+    currency_code VARCHAR(3),
+    amount_micros BIGINT,
+    issued_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_invoices_user_issued_at ON invoices (user_id, issued_at);
+CREATE INDEX idx_invoices_issued_at ON invoices (issued_at);
+
+INSERT INTO invoices (id, order_id, user_id, product_id, base_plan_id, offer_id, region_code, currency_code, amount_micros, issued_at)
+SELECT
+    orders.id,
+    orders.order_id,
+    orders.user_id,
+    orders.product_id,
+    revenue_events.base_plan_id,
+    revenue_events.offer_id,
+    orders.region_code,
+    revenue_events.currency_code,
+    revenue_events.price_micros,
+    orders.recorded_at
+FROM orders
+LEFT JOIN revenue_events ON revenue_events.order_id = orders.order_id;
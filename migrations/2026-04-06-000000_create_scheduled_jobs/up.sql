@@ -0,0 +1,12 @@
+-- Run status of each scheduled job, and the lease that lets one replica run it at a time
+CREATE TABLE scheduled_jobs (
+    name TEXT PRIMARY KEY NOT NULL,
+    schedule TEXT NOT NULL,
+    locked_by TEXT,
+    locked_until TIMESTAMP,
+    last_started_at TIMESTAMP,
+    last_finished_at TIMESTAMP,
+    last_outcome TEXT,
+    last_error TEXT,
+    last_duration_ms BIGINT
+);
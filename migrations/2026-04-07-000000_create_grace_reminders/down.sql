@@ -0,0 +1 @@
+DROP TABLE IF EXISTS grace_reminders;
@@ -0,0 +1,14 @@
+-- Payment-declined reminders of a subscription in its grace period, one row per token
+CREATE TABLE grace_reminders (
+    purchase_token TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    product_id TEXT NOT NULL,
+    started_at TIMESTAMP NOT NULL,
+    grace_ends_at TIMESTAMP NOT NULL,
+    reminders_sent INTEGER NOT NULL DEFAULT 0,
+    last_sent_at TIMESTAMP,
+    -- Set once payment is recovered or the subscription ends, no reminder follows
+    stopped_at TIMESTAMP
+);
+
+CREATE INDEX idx_grace_reminders_grace_ends_at ON grace_reminders (grace_ends_at);
@@ -0,0 +1 @@
+ALTER TABLE purchase_tokens DROP COLUMN replaced_by;
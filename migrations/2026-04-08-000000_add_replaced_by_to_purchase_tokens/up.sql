@@ -0,0 +1,2 @@
+-- Purchase token that replaced this one, when the user bought again from another Google account
+ALTER TABLE purchase_tokens ADD COLUMN replaced_by TEXT;
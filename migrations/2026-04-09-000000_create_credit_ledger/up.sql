@@ -0,0 +1,16 @@
+-- Every change billing made to a user's credits on the IC, and balancing entries for the rest
+CREATE TABLE credit_ledger (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    -- Always positive, `direction` says which way
+    amount INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- Caller label, e.g. `service:video`, `user:<sub>` or `billing`
+    actor TEXT NOT NULL,
+    -- Caller supplied id, or the order or compensation claim the change was made for
+    correlation_id TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_credit_ledger_user_id ON credit_ledger (user_id, created_at, id);
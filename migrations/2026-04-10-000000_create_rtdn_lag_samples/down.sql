@@ -0,0 +1 @@
+DROP TABLE IF EXISTS rtdn_lag_samples;
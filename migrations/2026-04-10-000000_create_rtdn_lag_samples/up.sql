@@ -0,0 +1,9 @@
+CREATE TABLE rtdn_lag_samples (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    notification_type VARCHAR(64) NOT NULL,
+    event_lag_ms BIGINT,
+    delivery_lag_ms BIGINT,
+    processed_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_rtdn_lag_samples_processed_at ON rtdn_lag_samples (processed_at);
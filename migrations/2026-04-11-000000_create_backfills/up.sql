@@ -0,0 +1,12 @@
+-- Progress of each data backfill, so an interrupted run resumes after the last finished batch
+CREATE TABLE backfills (
+    name TEXT PRIMARY KEY NOT NULL,
+    last_token_id TEXT,
+    processed BIGINT NOT NULL DEFAULT 0,
+    updated BIGINT NOT NULL DEFAULT 0,
+    failed BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    started_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP
+);
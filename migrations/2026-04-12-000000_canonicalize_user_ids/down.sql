@@ -0,0 +1,2 @@
+-- Canonical ids can't be told apart from what clients sent, there is nothing to undo
+SELECT 1;
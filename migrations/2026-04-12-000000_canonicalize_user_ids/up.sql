@@ -0,0 +1,95 @@
+-- User ids were stored as clients sent them, so one principal could appear in several
+-- casings or with surrounding whitespace. Principal text is lower case, so values made only
+-- of principal characters are trimmed and lowercased; anything else is left as it was.
+
+-- One risk row per user: counts add up and the user stays flagged unless every flag was approved
+CREATE TEMP TABLE user_risk_merged AS
+SELECT
+    lower(trim(user_id, ' ' || char(9, 10, 13))) AS user_id,
+    SUM(revoked_count) AS revoked_count,
+    SUM(voided_count) AS voided_count,
+    MAX(flagged_at) AS flagged_at,
+    CASE WHEN SUM(flagged_at IS NOT NULL AND approved_at IS NULL) > 0 THEN NULL ELSE MAX(approved_at) END AS approved_at,
+    CASE WHEN SUM(flagged_at IS NOT NULL AND approved_at IS NULL) > 0 THEN NULL ELSE MAX(approved_by) END AS approved_by,
+    MAX(updated_at) AS updated_at
+FROM user_risk
+WHERE trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*'
+GROUP BY lower(trim(user_id, ' ' || char(9, 10, 13)))
+HAVING COUNT(*) > 1;
+
+DELETE FROM user_risk
+WHERE trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*' AND lower(trim(user_id, ' ' || char(9, 10, 13))) IN (SELECT user_id FROM user_risk_merged);
+
+INSERT INTO user_risk (user_id, revoked_count, voided_count, flagged_at, approved_at, approved_by, updated_at)
+SELECT user_id, revoked_count, voided_count, flagged_at, approved_at, approved_by, updated_at
+FROM user_risk_merged;
+
+DROP TABLE user_risk_merged;
+
+-- One result per user of a compensation batch, a granted one wins over a pending or failed one
+DELETE FROM compensation_grants
+WHERE rowid IN (
+    SELECT rowid FROM (
+        SELECT
+            rowid,
+            ROW_NUMBER() OVER (
+                PARTITION BY batch_id, lower(trim(user_id, ' ' || char(9, 10, 13)))
+                ORDER BY status = 'granted' DESC, updated_at DESC
+            ) AS position
+        FROM compensation_grants
+        WHERE trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*'
+    )
+    WHERE position > 1
+);
+
+-- The trigger on purchase_tokens updates their subscriptions, other sources' are updated below
+UPDATE purchase_tokens SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE subscriptions SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE bot_chat_access SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE refund_requests SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE orders SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE access_outbox SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE revenue_events SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE admin_audit_log SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE user_risk SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE subscription_events SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE credit_topups SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE compensation_grants SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE invoices SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE credit_ledger SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE grace_reminders SET user_id = lower(trim(user_id, ' ' || char(9, 10, 13)))
+WHERE user_id <> lower(trim(user_id, ' ' || char(9, 10, 13))) AND trim(user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE token_transfers SET from_user_id = lower(trim(from_user_id, ' ' || char(9, 10, 13)))
+WHERE from_user_id <> lower(trim(from_user_id, ' ' || char(9, 10, 13))) AND trim(from_user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
+
+UPDATE token_transfers SET to_user_id = lower(trim(to_user_id, ' ' || char(9, 10, 13)))
+WHERE to_user_id <> lower(trim(to_user_id, ' ' || char(9, 10, 13))) AND trim(to_user_id, ' ' || char(9, 10, 13)) NOT GLOB '*[^a-zA-Z0-9-]*';
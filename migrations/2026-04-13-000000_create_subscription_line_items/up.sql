@@ -0,0 +1,14 @@
+-- Every line item Google reports for a subscription, add-ons and multi-line plans included
+CREATE TABLE subscription_line_items (
+    purchase_token VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    base_plan_id VARCHAR(255),
+    offer_id VARCHAR(255),
+    expiry_at TIMESTAMP,
+    auto_renewing BOOLEAN NOT NULL DEFAULT TRUE,
+    expired BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (purchase_token, product_id)
+);
+
+CREATE INDEX idx_subscription_line_items_expiry_at ON subscription_line_items (expired, expiry_at);
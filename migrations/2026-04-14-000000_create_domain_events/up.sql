@@ -0,0 +1,13 @@
+-- Domain events published for side-effect consumers, kept until every consumer handled them
+CREATE TABLE domain_events (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    event_type VARCHAR(50) NOT NULL,
+    payload TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_domain_events_status ON domain_events (status, updated_at);
@@ -0,0 +1,13 @@
+-- Cancellation intents the app reported before the user left through the Play Store
+CREATE TABLE cancel_intents (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    purchase_token TEXT NOT NULL,
+    reason VARCHAR(50) NOT NULL,
+    comment TEXT,
+    retention_offered BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_cancel_intents_purchase_token ON cancel_intents (purchase_token);
+CREATE INDEX idx_cancel_intents_created_at ON cancel_intents (created_at);
@@ -0,0 +1,14 @@
+-- Subscriptions bought through a Play offer, for conversion reporting per offer
+CREATE TABLE offer_redemptions (
+    purchase_token TEXT NOT NULL,
+    offer_id VARCHAR(255) NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    base_plan_id VARCHAR(255),
+    -- Comma-separated offer tags from the Play Console
+    offer_tags TEXT NOT NULL DEFAULT '',
+    redeemed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (purchase_token, offer_id)
+);
+
+CREATE INDEX idx_offer_redemptions_redeemed_at ON offer_redemptions (redeemed_at);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS verification_quarantine;
+DROP TABLE IF EXISTS verify_attempts;
@@ -0,0 +1,28 @@
+-- Verify attempts, kept to spot tokens shared between users and users verifying too fast
+CREATE TABLE verify_attempts (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    purchase_token TEXT NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    attempted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_verify_attempts_purchase_token ON verify_attempts (purchase_token);
+CREATE INDEX idx_verify_attempts_user_id ON verify_attempts (user_id, attempted_at);
+
+-- Suspicious verifications held for an operator instead of granting access
+CREATE TABLE verification_quarantine (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    account_id VARCHAR(255) NOT NULL,
+    purchase_token TEXT NOT NULL,
+    package_name VARCHAR(255) NOT NULL,
+    product_id VARCHAR(255) NOT NULL,
+    reason VARCHAR(50) NOT NULL,
+    status VARCHAR(50) NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    decided_at TIMESTAMP,
+    decided_by VARCHAR(255)
+);
+
+CREATE INDEX idx_verification_quarantine_purchase_token ON verification_quarantine (purchase_token);
+CREATE INDEX idx_verification_quarantine_status ON verification_quarantine (status, created_at);
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS subscriptions_entitlements_delete;
+DROP TRIGGER IF EXISTS subscriptions_entitlements_update;
+DROP TRIGGER IF EXISTS subscriptions_entitlements_insert;
+DROP TABLE IF EXISTS entitlements;
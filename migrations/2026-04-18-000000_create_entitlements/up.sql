@@ -0,0 +1,123 @@
+-- Read model for entitlement checks, one row per user holding or having held a subscription
+--
+-- Kept current by triggers on `subscriptions`, which purchase_tokens changes are mirrored to,
+-- so it changes in the same transaction as the status it is derived from. The subscription
+-- lasting longest is kept, whether it is still valid is decided at read time. As a WITHOUT
+-- ROWID table its primary key b-tree holds every column, so a lookup is one covering index seek.
+CREATE TABLE entitlements (
+    user_id VARCHAR(255) PRIMARY KEY NOT NULL,
+    plan VARCHAR(30) NOT NULL,
+    source VARCHAR(30),
+    valid_from TIMESTAMP,
+    valid_until TIMESTAMP,
+    auto_renewing BOOLEAN,
+    paused_from TIMESTAMP,
+    resumes_at TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+) WITHOUT ROWID;
+
+CREATE TRIGGER subscriptions_entitlements_insert AFTER INSERT ON subscriptions
+BEGIN
+    INSERT OR REPLACE INTO entitlements (user_id, plan, source, valid_from, valid_until, auto_renewing, paused_from, resumes_at, updated_at)
+    SELECT
+        NEW.user_id,
+        CASE WHEN active.source IS NULL THEN 'free' ELSE 'pro' END,
+        active.source,
+        active.started_at,
+        active.expires_at,
+        active.auto_renewing,
+        pause.paused_from,
+        pause.resumes_at,
+        CURRENT_TIMESTAMP
+    FROM (SELECT 1)
+    LEFT JOIN (
+        SELECT source, started_at, expires_at, auto_renewing FROM subscriptions
+        WHERE user_id = NEW.user_id AND status = 'active'
+        ORDER BY expires_at DESC LIMIT 1
+    ) AS active ON 1
+    LEFT JOIN (
+        SELECT paused_from, resumes_at FROM purchase_tokens
+        WHERE user_id = NEW.user_id AND resumes_at IS NOT NULL
+        ORDER BY resumes_at DESC LIMIT 1
+    ) AS pause ON 1;
+END;
+
+CREATE TRIGGER subscriptions_entitlements_update AFTER UPDATE ON subscriptions
+BEGIN
+    INSERT OR REPLACE INTO entitlements (user_id, plan, source, valid_from, valid_until, auto_renewing, paused_from, resumes_at, updated_at)
+    SELECT
+        NEW.user_id,
+        CASE WHEN active.source IS NULL THEN 'free' ELSE 'pro' END,
+        active.source,
+        active.started_at,
+        active.expires_at,
+        active.auto_renewing,
+        pause.paused_from,
+        pause.resumes_at,
+        CURRENT_TIMESTAMP
+    FROM (SELECT 1)
+    LEFT JOIN (
+        SELECT source, started_at, expires_at, auto_renewing FROM subscriptions
+        WHERE user_id = NEW.user_id AND status = 'active'
+        ORDER BY expires_at DESC LIMIT 1
+    ) AS active ON 1
+    LEFT JOIN (
+        SELECT paused_from, resumes_at FROM purchase_tokens
+        WHERE user_id = NEW.user_id AND resumes_at IS NOT NULL
+        ORDER BY resumes_at DESC LIMIT 1
+    ) AS pause ON 1;
+
+    -- A transferred subscription leaves its previous owner
+    INSERT OR REPLACE INTO entitlements (user_id, plan, source, valid_from, valid_until, auto_renewing, paused_from, resumes_at, updated_at)
+    SELECT
+        OLD.user_id,
+        CASE WHEN active.source IS NULL THEN 'free' ELSE 'pro' END,
+        active.source,
+        active.started_at,
+        active.expires_at,
+        active.auto_renewing,
+        pause.paused_from,
+        pause.resumes_at,
+        CURRENT_TIMESTAMP
+    FROM (SELECT 1)
+    LEFT JOIN (
+        SELECT source, started_at, expires_at, auto_renewing FROM subscriptions
+        WHERE user_id = OLD.user_id AND status = 'active'
+        ORDER BY expires_at DESC LIMIT 1
+    ) AS active ON 1
+    LEFT JOIN (
+        SELECT paused_from, resumes_at FROM purchase_tokens
+        WHERE user_id = OLD.user_id AND resumes_at IS NOT NULL
+        ORDER BY resumes_at DESC LIMIT 1
+    ) AS pause ON 1
+    WHERE OLD.user_id <> NEW.user_id;
+END;
+
+CREATE TRIGGER subscriptions_entitlements_delete AFTER DELETE ON subscriptions
+BEGIN
+    INSERT OR REPLACE INTO entitlements (user_id, plan, source, valid_from, valid_until, auto_renewing, paused_from, resumes_at, updated_at)
+    SELECT
+        OLD.user_id,
+        CASE WHEN active.source IS NULL THEN 'free' ELSE 'pro' END,
+        active.source,
+        active.started_at,
+        active.expires_at,
+        active.auto_renewing,
+        pause.paused_from,
+        pause.resumes_at,
+        CURRENT_TIMESTAMP
+    FROM (SELECT 1)
+    LEFT JOIN (
+        SELECT source, started_at, expires_at, auto_renewing FROM subscriptions
+        WHERE user_id = OLD.user_id AND status = 'active'
+        ORDER BY expires_at DESC LIMIT 1
+    ) AS active ON 1
+    LEFT JOIN (
+        SELECT paused_from, resumes_at FROM purchase_tokens
+        WHERE user_id = OLD.user_id AND resumes_at IS NOT NULL
+        ORDER BY resumes_at DESC LIMIT 1
+    ) AS pause ON 1;
+END;
+
+-- Fill the read model for every existing user through the update trigger
+UPDATE subscriptions SET updated_at = updated_at;
@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS purchase_tokens_bump_version;
+ALTER TABLE purchase_tokens DROP COLUMN version;
@@ -0,0 +1,10 @@
+-- Bumped on every change to the token, so writers can tell whether it changed since they
+-- loaded it
+ALTER TABLE purchase_tokens ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+
+-- Writers that don't bump the version themselves still move it on
+CREATE TRIGGER purchase_tokens_bump_version AFTER UPDATE ON purchase_tokens
+WHEN NEW.version = OLD.version
+BEGIN
+    UPDATE purchase_tokens SET version = OLD.version + 1 WHERE id = NEW.id;
+END;
@@ -0,0 +1,16 @@
+-- Subscription analytics events waiting to be sent to the analytics ingestion endpoint, kept
+-- after sending so each event is sent once per purchase token
+CREATE TABLE analytics_events (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    event_type VARCHAR(50) NOT NULL,
+    purchase_token TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (event_type, purchase_token)
+);
+
+CREATE INDEX idx_analytics_events_status ON analytics_events (status, created_at);
@@ -0,0 +1,9 @@
+-- Users behind the obfuscated profile ids the app sets on purchases, registered by the app
+-- before it starts a purchase that carries no obfuscated account id
+CREATE TABLE purchase_profiles (
+    obfuscated_profile_id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_purchase_profiles_user_id ON purchase_profiles (user_id);
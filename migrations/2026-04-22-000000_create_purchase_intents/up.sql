@@ -0,0 +1,16 @@
+-- Purchases the app announced before launching the billing flow, with the obfuscated ids it
+-- set on them, so their owner is known before verify and without a principal account id
+CREATE TABLE purchase_intents (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    package_name TEXT NOT NULL,
+    product_id TEXT NOT NULL,
+    obfuscated_account_id TEXT,
+    obfuscated_profile_id TEXT,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_purchase_intents_account_id ON purchase_intents (obfuscated_account_id);
+CREATE INDEX idx_purchase_intents_profile_id ON purchase_intents (obfuscated_profile_id);
+CREATE INDEX idx_purchase_intents_expires_at ON purchase_intents (expires_at);
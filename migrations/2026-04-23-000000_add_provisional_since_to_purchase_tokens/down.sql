@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_purchase_tokens_provisional_since;
+ALTER TABLE purchase_tokens DROP COLUMN provisional_since;
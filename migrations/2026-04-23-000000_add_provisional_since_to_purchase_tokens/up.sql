@@ -0,0 +1,4 @@
+-- Set on rows stored from an RTDN before the purchase's owner was known, cleared once claimed
+ALTER TABLE purchase_tokens ADD COLUMN provisional_since TIMESTAMP;
+
+CREATE INDEX idx_purchase_tokens_provisional_since ON purchase_tokens (provisional_since);
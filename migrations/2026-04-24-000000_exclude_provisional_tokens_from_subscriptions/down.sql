@@ -0,0 +1,37 @@
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_update;
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_insert;
+
+CREATE TRIGGER purchase_tokens_subscriptions_insert AFTER INSERT ON purchase_tokens
+BEGIN
+    INSERT INTO subscriptions (id, user_id, source, source_ref, status, started_at, expires_at, auto_renewing, updated_at)
+    VALUES (
+        NEW.id,
+        NEW.user_id,
+        CASE NEW.environment WHEN 'manual' THEN 'manual' ELSE 'google_play' END,
+        NEW.purchase_token,
+        CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        NEW.created_at,
+        NEW.expiry_at,
+        NEW.auto_renewing,
+        CURRENT_TIMESTAMP
+    )
+    ON CONFLICT (source, source_ref) DO UPDATE SET
+        user_id = excluded.user_id,
+        status = excluded.status,
+        started_at = excluded.started_at,
+        expires_at = excluded.expires_at,
+        auto_renewing = excluded.auto_renewing,
+        updated_at = excluded.updated_at;
+END;
+
+CREATE TRIGGER purchase_tokens_subscriptions_update AFTER UPDATE ON purchase_tokens
+BEGIN
+    UPDATE subscriptions SET
+        user_id = NEW.user_id,
+        status = CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        started_at = NEW.created_at,
+        expires_at = NEW.expiry_at,
+        auto_renewing = NEW.auto_renewing,
+        updated_at = CURRENT_TIMESTAMP
+    WHERE source IN ('google_play', 'manual') AND source_ref = OLD.purchase_token;
+END;
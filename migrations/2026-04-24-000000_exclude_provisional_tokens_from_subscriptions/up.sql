@@ -0,0 +1,58 @@
+-- Provisional tokens have no owner yet, keep them out of subscriptions and entitlements until
+-- they are claimed
+DELETE FROM subscriptions
+WHERE source IN ('google_play', 'manual')
+    AND source_ref IN (SELECT purchase_token FROM purchase_tokens WHERE provisional_since IS NOT NULL);
+DELETE FROM entitlements WHERE user_id = '';
+
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_insert;
+DROP TRIGGER IF EXISTS purchase_tokens_subscriptions_update;
+
+CREATE TRIGGER purchase_tokens_subscriptions_insert AFTER INSERT ON purchase_tokens
+WHEN NEW.provisional_since IS NULL
+BEGIN
+    INSERT INTO subscriptions (id, user_id, source, source_ref, status, started_at, expires_at, auto_renewing, updated_at)
+    VALUES (
+        NEW.id,
+        NEW.user_id,
+        CASE NEW.environment WHEN 'manual' THEN 'manual' ELSE 'google_play' END,
+        NEW.purchase_token,
+        CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        NEW.created_at,
+        NEW.expiry_at,
+        NEW.auto_renewing,
+        CURRENT_TIMESTAMP
+    )
+    ON CONFLICT (source, source_ref) DO UPDATE SET
+        user_id = excluded.user_id,
+        status = excluded.status,
+        started_at = excluded.started_at,
+        expires_at = excluded.expires_at,
+        auto_renewing = excluded.auto_renewing,
+        updated_at = excluded.updated_at;
+END;
+
+-- Inserts the row of a token claimed by this update, updates it otherwise
+CREATE TRIGGER purchase_tokens_subscriptions_update AFTER UPDATE ON purchase_tokens
+WHEN NEW.provisional_since IS NULL
+BEGIN
+    INSERT INTO subscriptions (id, user_id, source, source_ref, status, started_at, expires_at, auto_renewing, updated_at)
+    VALUES (
+        NEW.id,
+        NEW.user_id,
+        CASE NEW.environment WHEN 'manual' THEN 'manual' ELSE 'google_play' END,
+        NEW.purchase_token,
+        CASE NEW.status WHEN 'access_granted' THEN 'active' WHEN 'pending' THEN 'pending' ELSE 'expired' END,
+        NEW.created_at,
+        NEW.expiry_at,
+        NEW.auto_renewing,
+        CURRENT_TIMESTAMP
+    )
+    ON CONFLICT (source, source_ref) DO UPDATE SET
+        user_id = excluded.user_id,
+        status = excluded.status,
+        started_at = excluded.started_at,
+        expires_at = excluded.expires_at,
+        auto_renewing = excluded.auto_renewing,
+        updated_at = excluded.updated_at;
+END;
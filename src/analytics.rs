@@ -0,0 +1,316 @@
+//! Subscription analytics for the analytics pipeline
+//!
+//! `AnalyticsConsumer` turns domain events into purchase, trial and churn events and stores
+//! them in `analytics_events`, at most one of each type per purchase token. The analytics
+//! export job sends pending ones in batches to `ANALYTICS_INGEST_URL` and retries a batch the
+//! endpoint refused on its next run. Sent events are kept, they are what makes a redelivered
+//! domain event or a second renewal not count twice.
+
+use std::env;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::error::{AppError, AppResult};
+use crate::events::{DomainEvent, EventConsumer};
+use crate::model::{AnalyticsEventRecord, PurchaseToken};
+use crate::types::PurchaseTokenStatus;
+use crate::AppState;
+
+/// Play Console offer tag marking offers that start with a free trial
+pub const TRIAL_OFFER_TAG: &str = "trial";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalyticsEventType {
+    /// Paid subscription granted
+    Purchase,
+    /// Subscription granted through a trial offer
+    TrialStart,
+    /// First renewal of a subscription that started with a trial
+    TrialConversion,
+    /// Subscription lapsed
+    Churn,
+    /// Subscription bought by a user whose earlier one lapsed
+    Winback,
+}
+
+impl AnalyticsEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnalyticsEventType::Purchase => "purchase",
+            AnalyticsEventType::TrialStart => "trial_start",
+            AnalyticsEventType::TrialConversion => "trial_conversion",
+            AnalyticsEventType::Churn => "churn",
+            AnalyticsEventType::Winback => "winback",
+        }
+    }
+}
+
+/// Event as stored, the ingestion endpoint gets it with its id added
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalyticsEvent {
+    pub event: AnalyticsEventType,
+    pub user_id: String,
+    pub purchase_token: String,
+    /// Absent for churn, which isn't tied to a product
+    pub product_id: Option<String>,
+    /// When we learned of it, in RFC 3339
+    pub occurred_at: String,
+}
+
+/// Event as sent, `id` lets the pipeline drop one sent again after a lost response
+#[derive(Debug, Serialize)]
+pub struct IngestedEvent {
+    pub id: String,
+    #[serde(flatten)]
+    pub event: AnalyticsEvent,
+}
+
+#[derive(Clone)]
+struct IngestEndpoint {
+    url: String,
+    api_key: String,
+}
+
+/// Client of the analytics ingestion endpoint
+///
+/// Without `ANALYTICS_INGEST_URL` analytics are off: the consumer isn't subscribed and
+/// nothing is stored.
+#[derive(Clone)]
+pub struct AnalyticsSink {
+    endpoint: Option<IngestEndpoint>,
+    client: reqwest::Client,
+}
+
+impl AnalyticsSink {
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, ConfigError> {
+        let endpoint = match (url, api_key) {
+            (Some(url), Some(api_key)) => Some(IngestEndpoint { url, api_key }),
+            (Some(_), None) => {
+                return Err(ConfigError::Analytics(
+                    "ANALYTICS_API_KEY is required with ANALYTICS_INGEST_URL".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+        Ok(Self {
+            endpoint,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Read `ANALYTICS_INGEST_URL` and `ANALYTICS_API_KEY`
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::new(
+            env::var("ANALYTICS_INGEST_URL").ok(),
+            env::var("ANALYTICS_API_KEY").ok(),
+        )
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.endpoint.is_some()
+    }
+
+    /// Send `events` in one request, failing unless the endpoint accepted all of them
+    pub async fn send_batch(&self, events: &[IngestedEvent]) -> AppResult<()> {
+        let Some(endpoint) = &self.endpoint else {
+            return Err(AppError::InternalError(
+                "Analytics ingestion is not configured".to_string(),
+            ));
+        };
+
+        let res = self
+            .client
+            .post(&endpoint.url)
+            .bearer_auth(&endpoint.api_key)
+            .json(&serde_json::json!({ "events": events }))
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(AppError::InternalError(format!(
+                "Analytics endpoint returned error status: {}",
+                res.status()
+            )))
+        }
+    }
+}
+
+/// Store `event` for the export job, unless one of its type is stored for the token already
+pub fn record_analytics_event(
+    conn: &mut SqliteConnection,
+    event: &AnalyticsEvent,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::analytics_events;
+
+    let payload = serde_json::to_string(event)
+        .map_err(|e| AppError::InternalError(format!("Failed to encode analytics event: {}", e)))?;
+    let record = AnalyticsEventRecord::new(
+        event.event.as_str().to_string(),
+        event.purchase_token.clone(),
+        payload,
+        now,
+    );
+
+    diesel::insert_or_ignore_into(analytics_events::table)
+        .values(&record)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Derives analytics events from domain events
+pub struct AnalyticsConsumer;
+
+impl AnalyticsConsumer {
+    /// Whether the token was bought through a trial offer, asking Google for its offer tags
+    async fn started_with_trial(app_state: &AppState, token: &PurchaseToken) -> AppResult<bool> {
+        let Some(package_name) = token.package_name.as_deref() else {
+            return Ok(false);
+        };
+        let subscription = app_state
+            .google_play
+            .fetch_subscription(package_name, &token.purchase_token)
+            .await?;
+        Ok(subscription.line_items.iter().any(|item| {
+            item.offer_details
+                .as_ref()
+                .is_some_and(|offer| offer.offer_tags.iter().any(|tag| tag == TRIAL_OFFER_TAG))
+        }))
+    }
+}
+
+fn find_token(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+) -> AppResult<Option<PurchaseToken>> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    Ok(purchase_tokens
+        .filter(purchase_token.eq(purchase_token_param))
+        .first(conn)
+        .optional()?)
+}
+
+/// Whether `user_id_param` let another subscription lapse before buying `purchase_token_param`
+fn churned_before(
+    conn: &mut SqliteConnection,
+    user_id_param: &str,
+    purchase_token_param: &str,
+) -> AppResult<bool> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let lapsed: i64 = purchase_tokens
+        .filter(user_id.eq(user_id_param))
+        .filter(purchase_token.ne(purchase_token_param))
+        .filter(status.eq(PurchaseTokenStatus::Expired))
+        .filter(replaced_by.is_null())
+        .count()
+        .get_result(conn)?;
+    Ok(lapsed > 0)
+}
+
+#[async_trait]
+impl EventConsumer for AnalyticsConsumer {
+    fn name(&self) -> &'static str {
+        "analytics"
+    }
+
+    async fn handle(&self, app_state: &AppState, event: &DomainEvent) -> AppResult<()> {
+        let now = app_state.clock.now_naive();
+        let analytics_event =
+            |event, user_id: &str, purchase_token: &str, product_id| AnalyticsEvent {
+                event,
+                user_id: user_id.to_string(),
+                purchase_token: purchase_token.to_string(),
+                product_id,
+                occurred_at: now.and_utc().to_rfc3339(),
+            };
+
+        match event {
+            DomainEvent::PurchaseVerified {
+                user_id,
+                purchase_token,
+                product_id,
+            }
+            | DomainEvent::AccessGranted {
+                user_id,
+                purchase_token,
+                product_id,
+            } => {
+                let token = find_token(&mut app_state.get_db_connection()?, purchase_token)?;
+                // Still waiting for payment, `AccessGranted` follows once it completes
+                let Some(token) =
+                    token.filter(|token| token.status == PurchaseTokenStatus::AccessGranted)
+                else {
+                    return Ok(());
+                };
+
+                let kind = if Self::started_with_trial(app_state, &token).await? {
+                    AnalyticsEventType::TrialStart
+                } else {
+                    AnalyticsEventType::Purchase
+                };
+                let mut conn = app_state.get_db_connection()?;
+                let product = Some(product_id.clone());
+                record_analytics_event(
+                    &mut conn,
+                    &analytics_event(kind, user_id, purchase_token, product.clone()),
+                    now,
+                )?;
+                if churned_before(&mut conn, user_id, purchase_token)? {
+                    record_analytics_event(
+                        &mut conn,
+                        &analytics_event(
+                            AnalyticsEventType::Winback,
+                            user_id,
+                            purchase_token,
+                            product,
+                        ),
+                        now,
+                    )?;
+                }
+                Ok(())
+            }
+            DomainEvent::SubscriptionRenewed {
+                user_id,
+                purchase_token,
+                product_id,
+            } => {
+                let token = find_token(&mut app_state.get_db_connection()?, purchase_token)?;
+                let Some(token) = token else {
+                    return Ok(());
+                };
+                // Later renewals are ignored as the conversion is stored already
+                if Self::started_with_trial(app_state, &token).await? {
+                    record_analytics_event(
+                        &mut app_state.get_db_connection()?,
+                        &analytics_event(
+                            AnalyticsEventType::TrialConversion,
+                            user_id,
+                            purchase_token,
+                            Some(product_id.clone()),
+                        ),
+                        now,
+                    )?;
+                }
+                Ok(())
+            }
+            DomainEvent::SubscriptionExpired {
+                user_id,
+                purchase_token,
+            } => record_analytics_event(
+                &mut app_state.get_db_connection()?,
+                &analytics_event(AnalyticsEventType::Churn, user_id, purchase_token, None),
+                now,
+            ),
+            DomainEvent::RetentionOfferRequested { .. } => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+/// Header a client may send to pick a version on unprefixed paths, echoed on every response
+pub const API_VERSION_HEADER: &str = "x-api-version";
+
+/// Versions of the public API, each mounted under its own path prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// Every version the router serves
+    pub const ALL: [ApiVersion; 1] = [ApiVersion::V1];
+    /// Version behind the unprefixed paths that shipped Android builds call
+    pub const LEGACY: ApiVersion = ApiVersion::V1;
+
+    /// Value of `API_VERSION_HEADER`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "1",
+        }
+    }
+
+    /// Path prefix the version's routes are nested under
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/v1",
+        }
+    }
+
+    /// Accepts `1` as well as `v1`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let number = value
+            .strip_prefix('v')
+            .or_else(|| value.strip_prefix('V'))
+            .unwrap_or(value);
+        Self::ALL
+            .into_iter()
+            .find(|version| version.as_str() == number)
+    }
+
+    /// Version whose prefix `path` starts with
+    pub fn from_path(path: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|version| {
+            path.strip_prefix(version.prefix())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+/// Resolve the API version of a request and add it to the request extensions
+///
+/// A version prefix in the path wins. Unprefixed paths use `API_VERSION_HEADER` and fall back
+/// to `ApiVersion::LEGACY`; an unknown version in the header is refused with 406. Must run
+/// outside the nested version routers, which strip the prefix from the URI.
+pub async fn negotiate_version(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+    let version = match ApiVersion::from_path(req.uri().path()) {
+        Some(version) => version,
+        None => match req.headers().get(API_VERSION_HEADER) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(ApiVersion::parse)
+                .ok_or(StatusCode::NOT_ACCEPTABLE)?,
+            None => ApiVersion::LEGACY,
+        },
+    };
+
+    req.extensions_mut().insert(version);
+    let mut response = next.run(req).await;
+    response.headers_mut().insert(
+        API_VERSION_HEADER,
+        HeaderValue::from_static(version.as_str()),
+    );
+    Ok(response)
+}
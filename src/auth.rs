@@ -1,16 +1,21 @@
 use axum::{
     extract::Request,
-    http::{header::AUTHORIZATION, StatusCode},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
 use chrono::DateTime;
 use google_cloud_auth::credentials::CredentialsFile;
-use google_cloud_auth::project::{create_token_source_from_credentials, Config};
+use google_cloud_auth::project::{
+    create_token_source_from_credentials, create_token_source_from_project, project, Config,
+    Project,
+};
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use reqwest::header::EXPIRES;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::sync::RwLock;
 
 /// Ed25519 public key for JWT verification
@@ -18,27 +23,103 @@ pub const JWT_PUBKEY: &str = "-----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAn4Vbu7ZX4fDX3SNCiDYMoOs4KITJP1h2dw+MBnu6pPw=
 -----END PUBLIC KEY-----";
 
+/// Scope a JWT must carry to change a user's access through the admin API
+pub const ADMIN_SCOPE: &str = "billing:admin";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub aud: String,
     pub exp: usize,
+    /// Caller identity, recorded as the operator of admin changes
+    #[serde(default)]
+    pub sub: Option<String>,
+    /// Space-separated scopes granted to the caller
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl Claims {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_deref()
+            .map(|scopes| scopes.split_whitespace().any(|s| s == scope))
+            .unwrap_or(false)
+    }
+}
+
+/// Where Google credentials are loaded from
+#[derive(Clone)]
+pub enum CredentialSource {
+    /// Service account key JSON stored directly in `GOOGLE_SERVICE_ACCOUNT_JSON`
+    Env(Box<CredentialsFile>),
+    /// Credentials file at `GOOGLE_APPLICATION_CREDENTIALS`, re-read for every token so a
+    /// rotated key is picked up without a restart
+    File(PathBuf),
+    /// Application default credentials: the gcloud credentials file, or the metadata server
+    /// when running on GCP, e.g. with GKE workload identity
+    ApplicationDefault(Arc<Project>),
+}
+
+fn read_credentials_file(path: &Path) -> Result<CredentialsFile, String> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Invalid credentials in {}: {}", path.display(), e))
+}
+
+impl CredentialSource {
+    /// Pick the credential source from the environment
+    ///
+    /// `GOOGLE_SERVICE_ACCOUNT_JSON` wins over `GOOGLE_APPLICATION_CREDENTIALS`, and application
+    /// default credentials are only looked up when neither is set.
+    pub async fn from_env() -> Result<Self, String> {
+        if let Ok(json) = env::var("GOOGLE_SERVICE_ACCOUNT_JSON") {
+            let credentials: CredentialsFile = serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid GOOGLE_SERVICE_ACCOUNT_JSON: {}", e))?;
+            return Ok(CredentialSource::Env(Box::new(credentials)));
+        }
+        if let Ok(path) = env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            let path = PathBuf::from(path);
+            // Fail at startup on a missing or malformed file rather than on the first token
+            read_credentials_file(&path)?;
+            return Ok(CredentialSource::File(path));
+        }
+
+        let detected = project().await.map_err(|e| {
+            format!(
+                "Set GOOGLE_SERVICE_ACCOUNT_JSON or GOOGLE_APPLICATION_CREDENTIALS, or run on GCP \
+                 with a service account attached ({})",
+                e
+            )
+        })?;
+        Ok(CredentialSource::ApplicationDefault(Arc::new(detected)))
+    }
+
+    /// Human readable description for logs
+    pub fn describe(&self) -> String {
+        match self {
+            CredentialSource::Env(_) => "env:GOOGLE_SERVICE_ACCOUNT_JSON".to_string(),
+            CredentialSource::File(path) => format!("file:{}", path.display()),
+            CredentialSource::ApplicationDefault(detected) => match detected.as_ref() {
+                Project::FromFile(_) => "application-default:file".to_string(),
+                Project::FromMetadataServer(_) => "application-default:metadata-server".to_string(),
+            },
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct GoogleAuth {
-    credentials: CredentialsFile,
+    source: CredentialSource,
 }
 
 impl GoogleAuth {
-    /// Create a new GoogleAuth instance from environment variables
-    /// This is lightweight - just parsing JSON credentials once
-    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
-        let service_account_json = env::var("GOOGLE_SERVICE_ACCOUNT_JSON")
-            .map_err(|_| "GOOGLE_SERVICE_ACCOUNT_JSON environment variable must be set")?;
+    /// Create a new GoogleAuth instance from the credential source the environment selects
+    pub async fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let source = CredentialSource::from_env().await?;
+        println!("Using Google credentials from {}", source.describe());
 
-        let credentials: CredentialsFile = serde_json::from_str(&service_account_json)?;
-
-        Ok(Self { credentials })
+        Ok(Self { source })
     }
 
     pub async fn get_token(&self, scopes: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
@@ -48,8 +129,19 @@ impl GoogleAuth {
             ..Default::default()
         };
 
-        // Create token source from credentials with scopes
-        let token_source = create_token_source_from_credentials(&self.credentials, &config).await?;
+        // Create token source from the credentials with scopes
+        let token_source = match &self.source {
+            CredentialSource::Env(credentials) => {
+                create_token_source_from_credentials(credentials, &config).await?
+            }
+            CredentialSource::File(path) => {
+                let credentials = read_credentials_file(path)?;
+                create_token_source_from_credentials(&credentials, &config).await?
+            }
+            CredentialSource::ApplicationDefault(detected) => {
+                create_token_source_from_project(detected, config).await?
+            }
+        };
 
         // Get the token
         let token = token_source.token().await?;
@@ -168,13 +260,11 @@ impl GooglePublicKey {
     }
 }
 
-/// JWT authentication middleware
-/// Validates JWT token in Authorization header
+/// Decode the bearer JWT in the Authorization header
 /// Note: Does not check expiry as per requirements
-pub async fn jwt_auth_middleware(req: Request, next: Next) -> Result<Response, StatusCode> {
+pub fn claims_from_headers(headers: &HeaderMap) -> Result<Claims, StatusCode> {
     // Get Authorization header
-    let auth_header = req
-        .headers()
+    let auth_header = headers
         .get(AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
         .ok_or(StatusCode::UNAUTHORIZED)?;
@@ -189,13 +279,38 @@ pub async fn jwt_auth_middleware(req: Request, next: Next) -> Result<Response, S
     validation.validate_exp = false; // Don't check expiry as per requirements
     validation.validate_aud = false;
 
-    let _claims = decode::<Claims>(
+    let token_data = decode::<Claims>(
         token,
         &DecodingKey::from_ed_pem(JWT_PUBKEY.as_bytes()).map_err(|_| StatusCode::UNAUTHORIZED)?,
         &validation,
     )
     .map_err(|_| StatusCode::UNAUTHORIZED)?;
 
+    Ok(token_data.claims)
+}
+
+/// JWT authentication middleware
+/// Validates JWT token in Authorization header and makes its `Claims` available to handlers
+pub async fn jwt_auth_middleware(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+    let claims = claims_from_headers(req.headers())?;
+
     // Token is valid, continue with request
+    req.extensions_mut().insert(claims);
+    Ok(next.run(req).await)
+}
+
+/// Reject callers whose JWT lacks `ADMIN_SCOPE` or a `sub` to audit them by
+///
+/// Must run inside `jwt_auth_middleware`.
+pub async fn require_admin_scope(req: Request, next: Next) -> Result<Response, StatusCode> {
+    let claims = req
+        .extensions()
+        .get::<Claims>()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !claims.has_scope(ADMIN_SCOPE) || claims.sub.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     Ok(next.run(req).await)
 }
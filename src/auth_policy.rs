@@ -0,0 +1,178 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::api_version::ApiVersion;
+use crate::auth::{claims_from_headers, Claims, ADMIN_SCOPE};
+use crate::service_auth::{authenticate_caller, Caller};
+use crate::AppState;
+
+/// How callers of a route must authenticate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthPolicy {
+    /// Anyone, e.g. the app before sign-in or health checks
+    Public,
+    /// Google Pub/Sub push with an OIDC token, checked by `AppState::push_verifier`
+    PubSubPush,
+    /// User JWT, its `Claims` are added to the request extensions
+    ClientJwt,
+    /// User JWT carrying `ADMIN_SCOPE` and a `sub` to record as the operator
+    AdminJwt,
+    /// HMAC-signed internal service, see `service_auth`
+    Service,
+    /// Internal service or user JWT, the `Caller` says which
+    ServiceOrClientJwt,
+}
+
+/// Policy of every API route, by the path template it is registered under
+///
+/// Paths are listed without a version prefix. Routes missing here are refused, so adding a
+/// route means adding its policy.
+pub const ROUTE_POLICIES: &[(&str, AuthPolicy)] = &[
+    ("/health", AuthPolicy::Public),
+    ("/google/verify", AuthPolicy::Public),
+    ("/google/chat-access/grant", AuthPolicy::Public),
+    ("/google/chat-access/check", AuthPolicy::Public),
+    ("/google/manage-url", AuthPolicy::Public),
+    ("/support/refund-request", AuthPolicy::Public),
+    ("/google/rtdn-webhook", AuthPolicy::PubSubPush),
+    ("/google/verify/preview", AuthPolicy::ClientJwt),
+    ("/google/transfer", AuthPolicy::ClientJwt),
+    ("/google/cancel-intent", AuthPolicy::ClientJwt),
+    ("/google/profiles", AuthPolicy::ClientJwt),
+    ("/google/purchase-intent", AuthPolicy::ClientJwt),
+    ("/entitlements/{user_id}", AuthPolicy::ClientJwt),
+    ("/entitlements/token", AuthPolicy::ClientJwt),
+    ("/billing/history/{user_id}", AuthPolicy::ClientJwt),
+    ("/users/{user_id}/tokens", AuthPolicy::ClientJwt),
+    ("/billing/invoices/{user_id}", AuthPolicy::ClientJwt),
+    ("/admin/ic-identity", AuthPolicy::AdminJwt),
+    ("/admin/ic-identity/reload", AuthPolicy::AdminJwt),
+    ("/admin/refund-requests", AuthPolicy::AdminJwt),
+    ("/admin/refund-requests/{id}", AuthPolicy::AdminJwt),
+    ("/admin/risk/users", AuthPolicy::AdminJwt),
+    ("/admin/quarantine", AuthPolicy::AdminJwt),
+    ("/admin/orders/export", AuthPolicy::AdminJwt),
+    ("/admin/offers/conversions", AuthPolicy::AdminJwt),
+    ("/admin/invoices/export", AuthPolicy::AdminJwt),
+    ("/admin/export/tokens", AuthPolicy::AdminJwt),
+    ("/admin/export/events", AuthPolicy::AdminJwt),
+    ("/admin/tokens", AuthPolicy::AdminJwt),
+    ("/admin/tokens/{id}/diff", AuthPolicy::AdminJwt),
+    ("/admin/events", AuthPolicy::AdminJwt),
+    ("/admin/users/plans", AuthPolicy::AdminJwt),
+    ("/admin/users/{user_id}/credit-ledger", AuthPolicy::AdminJwt),
+    ("/admin/stats", AuthPolicy::AdminJwt),
+    ("/admin/stats/rtdn-lag", AuthPolicy::AdminJwt),
+    ("/metrics", AuthPolicy::AdminJwt),
+    ("/admin/feature-flags", AuthPolicy::AdminJwt),
+    ("/admin/feature-flags/{name}", AuthPolicy::AdminJwt),
+    ("/admin/debug/requests", AuthPolicy::AdminJwt),
+    ("/admin/jobs", AuthPolicy::AdminJwt),
+    ("/admin/cluster", AuthPolicy::AdminJwt),
+    ("/admin/queues", AuthPolicy::AdminJwt),
+    ("/admin/db/integrity", AuthPolicy::AdminJwt),
+    (
+        "/admin/subscriptions/{token}/snapshots",
+        AuthPolicy::AdminJwt,
+    ),
+    (
+        "/admin/subscriptions/{token}/line-items",
+        AuthPolicy::AdminJwt,
+    ),
+    ("/admin/catalog", AuthPolicy::AdminJwt),
+    ("/admin/catalog/sync", AuthPolicy::AdminJwt),
+    ("/admin/access/grant", AuthPolicy::AdminJwt),
+    ("/admin/access/revoke", AuthPolicy::AdminJwt),
+    ("/admin/subscriptions/{token}/defer", AuthPolicy::AdminJwt),
+    (
+        "/admin/subscriptions/{token}/reverify",
+        AuthPolicy::AdminJwt,
+    ),
+    ("/admin/risk/users/{user_id}/approve", AuthPolicy::AdminJwt),
+    ("/admin/quarantine/{id}/approve", AuthPolicy::AdminJwt),
+    ("/admin/quarantine/{id}/reject", AuthPolicy::AdminJwt),
+    ("/admin/compensations", AuthPolicy::AdminJwt),
+    ("/admin/compensations/{id}", AuthPolicy::AdminJwt),
+    ("/admin/compensations/{id}/resume", AuthPolicy::AdminJwt),
+    ("/credits/deduct", AuthPolicy::ServiceOrClientJwt),
+    ("/credits/increment", AuthPolicy::ServiceOrClientJwt),
+    ("/internal/users/{user_id}/teardown", AuthPolicy::Service),
+    ("/internal/introspect", AuthPolicy::Service),
+];
+
+/// Policy of the route registered under `path`, with or without a version prefix
+pub fn policy_for(path: &str) -> Option<AuthPolicy> {
+    let path = match ApiVersion::from_path(path) {
+        Some(version) => &path[version.prefix().len()..],
+        None => path,
+    };
+    ROUTE_POLICIES
+        .iter()
+        .find(|(route, _)| *route == path)
+        .map(|(_, policy)| *policy)
+}
+
+/// Authenticate every API request by the policy of the route it matched
+///
+/// Requests that matched no route pass through to the 404 fallback. Use with
+/// `middleware::from_fn_with_state(app_state, ...)` on the API router, after all routes are
+/// added.
+pub async fn enforce_auth_policy(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let Some(path) = req.extensions().get::<MatchedPath>() else {
+        return Ok(next.run(req).await);
+    };
+    let Some(policy) = policy_for(path.as_str()) else {
+        eprintln!("No auth policy for route {}, refusing", path.as_str());
+        return Err(StatusCode::FORBIDDEN);
+    };
+
+    let req = authenticate(&app_state, policy, req).await?;
+    Ok(next.run(req).await)
+}
+
+/// Whether a verified user JWT may call a route with `policy`
+///
+/// Admin routes need `ADMIN_SCOPE` and a `sub` to record as the operator.
+pub fn authorize_claims(policy: AuthPolicy, claims: &Claims) -> Result<(), StatusCode> {
+    if policy == AuthPolicy::AdminJwt && (!claims.has_scope(ADMIN_SCOPE) || claims.sub.is_none()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+async fn authenticate(
+    app_state: &AppState,
+    policy: AuthPolicy,
+    mut req: Request,
+) -> Result<Request, StatusCode> {
+    match policy {
+        AuthPolicy::Public => {}
+        AuthPolicy::PubSubPush => {
+            if let Err(e) = app_state
+                .push_verifier
+                .verify(req.headers().get(AUTHORIZATION))
+                .await
+            {
+                eprintln!("Authentication failed: {}", e);
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        }
+        AuthPolicy::ClientJwt | AuthPolicy::AdminJwt => {
+            let claims = claims_from_headers(req.headers())?;
+            authorize_claims(policy, &claims)?;
+            req.extensions_mut().insert(Caller::User(claims.clone()));
+            req.extensions_mut().insert(claims);
+        }
+        AuthPolicy::Service => return authenticate_caller(app_state, req, false).await,
+        AuthPolicy::ServiceOrClientJwt => return authenticate_caller(app_state, req, true).await,
+    }
+    Ok(req)
+}
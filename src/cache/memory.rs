@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::Cache;
+use crate::error::AppResult;
+
+/// Cache kept in this process, for single-replica deployments and tests
+#[derive(Default)]
+pub struct MemoryCache {
+    /// Value of each key with the instant it expires at
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl MemoryCache {
+    /// Lock the entries with expired ones dropped, so unused keys don't pile up
+    fn live_entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Instant, String)>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (expires_at, _)| *expires_at > now);
+        entries
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: &str) -> AppResult<Option<String>> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries
+            .get(key)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, value)| value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()> {
+        self.live_entries()
+            .insert(key.to_string(), (Instant::now() + ttl, value.to_string()));
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool> {
+        let mut entries = self.live_entries();
+        if entries.contains_key(key) {
+            return Ok(false);
+        }
+        entries.insert(key.to_string(), (Instant::now() + ttl, value.to_string()));
+        Ok(true)
+    }
+
+    async fn extend_if_equal(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool> {
+        let mut entries = self.live_entries();
+        match entries.get_mut(key) {
+            Some((expires_at, stored)) if *stored == value => {
+                *expires_at = Instant::now() + ttl;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> AppResult<u64> {
+        let mut entries = self.live_entries();
+        let (_, value) = entries
+            .entry(key.to_string())
+            .or_insert_with(|| (Instant::now() + ttl, "0".to_string()));
+        let count = value.parse::<u64>().unwrap_or(0) + 1;
+        *value = count.to_string();
+        Ok(count)
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
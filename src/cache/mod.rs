@@ -0,0 +1,74 @@
+pub mod memory;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::config::ConfigError;
+use crate::error::AppResult;
+
+pub use memory::MemoryCache;
+#[cfg(feature = "redis")]
+pub use redis_store::RedisCache;
+
+/// Expiring key/value store for state all replicas have to agree on
+///
+/// Backs the Google Play response cache, the replay check of signed service requests and
+/// per-service rate limits. Entries disappear once their TTL passes.
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> AppResult<Option<String>>;
+
+    /// Store `value` under `key` for `ttl`, replacing any previous value
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()>;
+
+    /// Store `value` under `key` for `ttl` unless the key is taken, returns whether it was stored
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool>;
+
+    /// Restart the TTL of `key` at `ttl` if it still holds `value`, returns whether it did
+    ///
+    /// Check and extension are one step, so a lease another replica took over meanwhile is
+    /// never extended.
+    async fn extend_if_equal(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool>;
+
+    /// Add one to the counter under `key` and return the new count
+    ///
+    /// A counter starts at zero and lives for `ttl` from its first increment.
+    async fn increment(&self, key: &str, ttl: Duration) -> AppResult<u64>;
+
+    async fn delete(&self, key: &str) -> AppResult<()>;
+
+    /// Whether other replicas see the same entries
+    fn is_shared(&self) -> bool;
+}
+
+/// Redis at `REDIS_URL` when set, otherwise a cache local to this process
+///
+/// Redis needs a build with the `redis` feature; setting `REDIS_URL` without it is refused
+/// rather than silently keeping state per replica.
+pub async fn cache_from_env() -> Result<Arc<dyn Cache>, ConfigError> {
+    match env::var("REDIS_URL") {
+        Ok(url) if !url.trim().is_empty() => connect_redis(url.trim()).await,
+        _ => Ok(Arc::new(MemoryCache::default())),
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn connect_redis(url: &str) -> Result<Arc<dyn Cache>, ConfigError> {
+    let cache = RedisCache::connect(url)
+        .await
+        .map_err(|e| ConfigError::Cache(e.to_string()))?;
+    println!("Using Redis for shared cache state");
+    Ok(Arc::new(cache))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn connect_redis(_url: &str) -> Result<Arc<dyn Cache>, ConfigError> {
+    Err(ConfigError::Cache(
+        "REDIS_URL is set but the service was built without the `redis` feature".to_string(),
+    ))
+}
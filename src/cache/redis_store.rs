@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+
+use super::Cache;
+use crate::error::{AppError, AppResult};
+
+/// Prefix of every key, so the billing service can share a Redis instance
+const KEY_PREFIX: &str = "yral-billing:";
+
+/// Cache in Redis, shared by every replica pointed at the same instance
+///
+/// The connection manager reconnects on its own after Redis restarts.
+#[derive(Clone)]
+pub struct RedisCache {
+    conn: ConnectionManager,
+}
+
+impl RedisCache {
+    pub async fn connect(url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self { conn })
+    }
+}
+
+fn prefixed(key: &str) -> String {
+    format!("{}{}", KEY_PREFIX, key)
+}
+
+/// Extends the key's TTL only while it holds the expected value, atomic in Redis
+const EXTEND_IF_EQUAL_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// TTL in milliseconds, at least one since Redis refuses zero
+fn ttl_millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+fn redis_error(e: redis::RedisError) -> AppError {
+    AppError::InternalError(format!("Redis error: {}", e))
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        conn.get(prefixed(key)).await.map_err(redis_error)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        redis::cmd("SET")
+            .arg(prefixed(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        // `OK` when stored, nil when the key was taken
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(prefixed(key))
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error)?;
+        Ok(stored.is_some())
+    }
+
+    async fn extend_if_equal(&self, key: &str, value: &str, ttl: Duration) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let extended: i64 = redis::Script::new(EXTEND_IF_EQUAL_SCRIPT)
+            .key(prefixed(key))
+            .arg(value)
+            .arg(ttl_millis(ttl))
+            .invoke_async(&mut conn)
+            .await
+            .map_err(redis_error)?;
+        Ok(extended == 1)
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> AppResult<u64> {
+        let mut conn = self.conn.clone();
+        let key = prefixed(key);
+        let count: u64 = conn.incr(&key, 1).await.map_err(redis_error)?;
+        if count == 1 {
+            let _: bool = conn
+                .pexpire(&key, ttl_millis(ttl) as i64)
+                .await
+                .map_err(redis_error)?;
+        }
+        Ok(count)
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let _: u64 = conn.del(prefixed(key)).await.map_err(redis_error)?;
+        Ok(())
+    }
+
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::error::{AppError, AppResult};
+use crate::jobs::backfills::{run_backfill, Backfill};
+use crate::jobs::expiry_sweep::sweep_expired_tokens;
+use crate::jobs::pending_purchases::{
+    reconcile_pending_purchases, reverify_purchase_token, ReverifyOutcome,
+};
+use crate::jobs::purchase_import::import_purchases;
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::AppState;
+
+/// Billing service for Yral Pro subscriptions
+#[derive(Debug, Parser)]
+#[command(name = "yral-billing", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Same as the `self-test` subcommand, kept for existing deploy pipelines
+    #[arg(long, hide = true)]
+    self_test: bool,
+
+    /// Same as the `seed` subcommand
+    #[arg(long, hide = true)]
+    seed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Run the HTTP service and background jobs (default)
+    Serve,
+    /// Run pending database migrations and exit
+    Migrate,
+    /// Ask Google about every pending purchase once and grant the completed ones
+    Reconcile,
+    /// Re-run verification of one stored purchase token against Google
+    Reverify {
+        /// Purchase token to verify again
+        token: String,
+    },
+    /// Expire tokens whose expiry has passed and queue their access revocation
+    ExpireSweep,
+    /// Import purchases made before this service existed from a CSV export
+    ///
+    /// Needs `purchase_token`, `user_id` and `package_name` columns, and reads `expiry_time`
+    /// and `voided_time` when present. Safe to run again with the same file.
+    Import {
+        /// CSV file with a header line
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Fill columns of stored purchase tokens from Google, resuming where the last run stopped
+    ///
+    /// Backfills are `environment`, `orders`, `linked_tokens` and `line_items`. Progress is
+    /// kept in the `backfills` table, a completed backfill only runs again with `--restart`.
+    Backfill {
+        /// Backfill to run
+        name: Backfill,
+        /// Stop after this many batches of tokens
+        #[arg(long)]
+        max_batches: Option<usize>,
+        /// Forget earlier progress and visit every token again
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Check credentials and connectivity without serving
+    SelfTest,
+    /// Exercise a running deployment through its API and print a pass/fail report
+    ///
+    /// Runs health, OpenAPI, a mock-mode verification, an RTDN test notification and a
+    /// credits round-trip. Tokens are read from `SMOKE_TEST_JWT` and `SMOKE_TEST_PUSH_TOKEN`
+    /// so they stay out of the process list, the credits check is skipped without a JWT.
+    SmokeTest {
+        /// Base URL of the deployment, e.g. `https://billing-staging.yral.com`
+        base_url: String,
+        /// Principal whose credits are added and taken back, the mock account by default
+        #[arg(long)]
+        principal: Option<String>,
+    },
+    /// Fill the local database with sample data
+    Seed,
+}
+
+impl Cli {
+    /// Subcommand to run, falling back to the legacy flags and then to `serve`
+    pub fn command(&self) -> Command {
+        match &self.command {
+            Some(command) => command.clone(),
+            None if self.self_test || crate::self_test::requested() => Command::SelfTest,
+            None if self.seed => Command::Seed,
+            None => Command::Serve,
+        }
+    }
+}
+
+/// Run one of the one-off maintenance commands against `app_state`
+///
+/// Returns a summary line for the operator. `serve`, `migrate`, `self-test`, `smoke-test` and
+/// `seed` are not maintenance commands and are rejected.
+pub async fn run_maintenance(command: &Command, app_state: &AppState) -> AppResult<String> {
+    let mut conn = app_state.get_db_connection()?;
+
+    match command {
+        Command::Reconcile => {
+            let granted = reconcile_pending_purchases(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                app_state.clock.as_ref(),
+                app_state.provisional_purchase_timeout,
+            )
+            .await?;
+            Ok(format!("Granted {} pending purchases", granted))
+        }
+        Command::Reverify { token } => {
+            let outcome = reverify_purchase_token(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                app_state.clock.as_ref(),
+                token,
+            )
+            .await?;
+            Ok(match outcome {
+                ReverifyOutcome::Granted => format!("Purchase token {} is active", token),
+                ReverifyOutcome::StillPending => {
+                    format!("Purchase token {} is still pending payment", token)
+                }
+                ReverifyOutcome::Ended { expiry_at } => format!(
+                    "Purchase token {} no longer renews, expiry set to {}",
+                    token,
+                    expiry_at.and_utc().to_rfc3339()
+                ),
+            })
+        }
+        Command::ExpireSweep => {
+            let expired =
+                sweep_expired_tokens(&mut conn, app_state.clock.as_ref(), &app_state.metrics)?;
+            Ok(format!("Expired {} purchase tokens", expired))
+        }
+        Command::Import { file } => {
+            let text = std::fs::read_to_string(file).map_err(|e| {
+                AppError::BadRequest(format!("Can't read {}: {}", file.display(), e))
+            })?;
+            let report = import_purchases(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                &text,
+                app_state.clock.now_naive(),
+            )
+            .await?;
+            Ok(report.to_string())
+        }
+        Command::Backfill {
+            name,
+            max_batches,
+            restart,
+        } => {
+            let report = run_backfill(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                app_state.clock.as_ref(),
+                *name,
+                JOB_BATCH_SIZE,
+                *max_batches,
+                *restart,
+            )
+            .await?;
+            Ok(report.to_string())
+        }
+        other => Err(AppError::InternalError(format!(
+            "{:?} is not a maintenance command",
+            other
+        ))),
+    }
+}
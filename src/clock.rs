@@ -0,0 +1,52 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::sync::RwLock;
+
+/// Source of the current time, so expiry logic can be driven deterministically in tests
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current time as the naive UTC timestamp stored in the database
+    fn now_naive(&self) -> NaiveDateTime {
+        self.now().naive_utc()
+    }
+}
+
+/// Wall clock used in production
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Manually controlled clock for tests
+#[derive(Debug)]
+pub struct TestClock {
+    now: RwLock<DateTime<Utc>>,
+}
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: RwLock::new(now),
+        }
+    }
+
+    /// Set the clock to a fixed point in time
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.write().unwrap() = now;
+    }
+
+    /// Move the clock forward by the given duration
+    pub fn advance(&self, duration: Duration) {
+        *self.now.write().unwrap() += duration;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.read().unwrap()
+    }
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Invalid configuration or environment found while starting the service
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("{name} must be a valid number, got `{value}`")]
+    InvalidNumber { name: &'static str, value: String },
+
+    #[error("{name} must be greater than zero")]
+    ZeroInterval { name: &'static str },
+
+    #[error("{name} must be a number of seconds or a cron expression: {reason}")]
+    InvalidSchedule { name: &'static str, reason: String },
+
+    #[error("Failed to set up the database: {0}")]
+    Database(String),
+
+    #[error("Failed to run migrations: {0}")]
+    Migrations(String),
+
+    #[error("Failed to initialize integrations: {0}")]
+    Integrations(String),
+
+    #[error("SERVICE_AUTH_KEYS is invalid: {0}")]
+    ServiceAuthKeys(String),
+
+    #[error("GRACE_REMINDER_OFFSETS is invalid: {0}")]
+    GraceReminderOffsets(String),
+
+    #[error("ACK_STRATEGY or ACK_STRATEGIES is invalid: {0}")]
+    AckStrategies(String),
+
+    #[error("INTERNAL_ALLOWLIST is invalid: {0}")]
+    InternalAllowlist(String),
+
+    #[error("Entitlement signing keys are invalid: {0}")]
+    EntitlementSigningKey(String),
+
+    #[error("Failed to set up the cache: {0}")]
+    Cache(String),
+
+    #[error("Analytics ingestion is misconfigured: {0}")]
+    Analytics(String),
+
+    #[error("Failed to listen on {addr}: {reason}")]
+    Bind { addr: SocketAddr, reason: String },
+}
+
+/// Read a numeric environment variable, falling back to `default` when it is unset
+pub fn env_number<T: FromStr>(name: &'static str, default: T) -> Result<T, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| ConfigError::InvalidNumber { name, value }),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read a job interval in seconds, zero is rejected since it would never tick
+pub fn env_interval_secs(name: &'static str, default: u64) -> Result<Duration, ConfigError> {
+    match env_number(name, default)? {
+        0 => Err(ConfigError::ZeroInterval { name }),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+/// Report an unrecoverable startup error and exit
+pub fn exit_on_startup_error(err: &ConfigError) -> ! {
+    sentry::capture_message(&format!("Startup failed: {}", err), sentry::Level::Error);
+    eprintln!("Startup failed: {}", err);
+    std::process::exit(1);
+}
@@ -1 +1,2 @@
-pub static YRAL_PRO_CREDIT_ALLOTMENT: u32 = 30;
+/// Google Play product id of the Yral Pro subscription
+pub static YRAL_PRO_PLAN_PRODUCT_ID: &str = "yral_pro_plan";
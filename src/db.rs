@@ -0,0 +1,125 @@
+pub mod pagination;
+pub mod token_versions;
+pub mod transactions;
+
+use std::env;
+
+use diesel::{
+    connection::SimpleConnection,
+    prelude::*,
+    r2d2::{self, ConnectionManager, CustomizeConnection, Pool},
+    sql_query,
+    sql_types::{BigInt, Text},
+};
+use diesel_migrations::MigrationHarness;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::AppResult;
+use crate::MIGRATIONS;
+
+/// `DATABASE_URL` of a private in-memory database, gone when its pool is dropped
+pub const MEMORY_DATABASE_URL: &str = "sqlite::memory:";
+
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+
+/// `DATABASE_URL`, `billing.db` when unset
+pub fn database_url_from_env() -> String {
+    env::var("DATABASE_URL").unwrap_or_else(|_| "billing.db".to_string())
+}
+
+/// Whether `database_url` asks for an in-memory database
+pub fn is_memory_url(database_url: &str) -> bool {
+    matches!(database_url, MEMORY_DATABASE_URL | ":memory:")
+}
+
+/// Pragmas set on every pooled connection
+#[derive(Debug, Clone, Copy)]
+pub struct SqlitePragmas {
+    /// How long a statement waits for another connection's lock before failing as busy
+    pub busy_timeout_ms: u32,
+    /// Write-ahead logging, readers no longer block the writer. Not for in-memory databases.
+    pub wal: bool,
+}
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        // The timeout first, switching the journal mode may have to wait for a lock itself
+        let mut pragmas = format!(
+            "PRAGMA busy_timeout = {}; PRAGMA foreign_keys = ON;",
+            self.busy_timeout_ms
+        );
+        if self.wal {
+            pragmas.push_str(" PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;");
+        }
+        conn.batch_execute(&pragmas)
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// Open a pool on `database_url` and run pending migrations through it
+///
+/// Every connection to a plain `:memory:` database gets its own empty one, so in-memory URLs
+/// are opened as a uniquely named shared-cache database instead. The pool then keeps its
+/// connections open for good, the database lives as long as one of them does.
+///
+/// Connections wait up to `SQLITE_BUSY_TIMEOUT_MS` (default 5000) for a lock.
+pub fn connect_pool(database_url: &str) -> Result<DbPool, ConfigError> {
+    let pragmas = SqlitePragmas {
+        busy_timeout_ms: env_number("SQLITE_BUSY_TIMEOUT_MS", 5000)?,
+        wal: !is_memory_url(database_url),
+    };
+    let builder = Pool::builder().connection_customizer(Box::new(pragmas));
+    let (sqlite_url, builder) = if is_memory_url(database_url) {
+        let name = format!(
+            "file:billing-{}?mode=memory&cache=shared",
+            uuid::Uuid::new_v4()
+        );
+        (name, builder.idle_timeout(None).max_lifetime(None))
+    } else {
+        (database_url.to_string(), builder)
+    };
+
+    let pool = builder
+        .build(ConnectionManager::<SqliteConnection>::new(sqlite_url))
+        .map_err(|e| ConfigError::Database(e.to_string()))?;
+
+    let mut conn = pool
+        .get()
+        .map_err(|e| ConfigError::Database(e.to_string()))?;
+    conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|e| ConfigError::Migrations(e.to_string()))?;
+    println!("Database migrations completed successfully");
+
+    Ok(pool)
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheckRow {
+    #[diesel(sql_type = Text)]
+    integrity_check: String,
+}
+
+/// Problems `PRAGMA integrity_check` finds, empty for a sound database
+pub fn integrity_problems(conn: &mut SqliteConnection) -> AppResult<Vec<String>> {
+    let rows: Vec<IntegrityCheckRow> = sql_query("PRAGMA integrity_check").load(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .filter(|message| message != "ok")
+        .collect())
+}
+
+#[derive(QueryableByName)]
+struct DatabaseSizeRow {
+    #[diesel(sql_type = BigInt)]
+    size_bytes: i64,
+}
+
+/// Size of the database file, free pages included
+pub fn database_size_bytes(conn: &mut SqliteConnection) -> AppResult<i64> {
+    let row: DatabaseSizeRow = sql_query(
+        "SELECT page_count * page_size AS size_bytes FROM pragma_page_count(), pragma_page_size()",
+    )
+    .get_result(conn)?;
+    Ok(row.size_bytes)
+}
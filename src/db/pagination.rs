@@ -0,0 +1,122 @@
+use base64::prelude::*;
+use chrono::{DateTime, NaiveDateTime};
+use diesel::expression::{is_aggregate, ValidGrouping};
+use diesel::prelude::*;
+use diesel::query_builder::QueryFragment;
+use diesel::sql_types::{Bool, Text, Timestamp};
+use diesel::sqlite::Sqlite;
+
+use crate::error::{AppError, AppResult};
+
+/// Keyset filter on a boxed query over `QS`
+pub type KeysetFilter<QS> = Box<dyn BoxableExpression<QS, Sqlite, SqlType = Bool>>;
+
+/// Position in a listing ordered by a timestamp and then id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub at: NaiveDateTime,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        // Full precision, a rounded time would point before the record and repeat it
+        let nanos = self.at.and_utc().timestamp_nanos_opt().unwrap_or_default();
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}|{}", nanos, self.id))
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let raw = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(value).ok()?).ok()?;
+        let (nanos, id) = raw.split_once('|')?;
+        let at = DateTime::from_timestamp_nanos(nanos.parse().ok()?).naive_utc();
+        Some(Self {
+            at,
+            id: id.to_string(),
+        })
+    }
+}
+
+/// Decode the `cursor` query parameter of a `listing`, e.g. "export"
+pub fn decode_cursor(value: Option<&str>, listing: &str) -> AppResult<Option<Cursor>> {
+    value
+        .map(|value| {
+            Cursor::decode(value)
+                .ok_or_else(|| AppError::BadRequest(format!("Invalid {} cursor", listing)))
+        })
+        .transpose()
+}
+
+/// The `limit` query parameter, `default` when absent and at most `max`
+pub fn page_limit(limit: Option<i64>, default: i64, max: i64) -> AppResult<i64> {
+    let limit = limit.unwrap_or(default);
+    if !(1..=max).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            max
+        )));
+    }
+    Ok(limit)
+}
+
+/// Rows after `cursor` in a listing ordered by `(at, id)` ascending
+pub fn after_cursor<QS, At, Id>(at: At, id: Id, cursor: &Cursor) -> KeysetFilter<QS>
+where
+    At: Expression<SqlType = Timestamp>
+        + SelectableExpression<QS>
+        + ValidGrouping<(), IsAggregate = is_aggregate::No>
+        + QueryFragment<Sqlite>
+        + Send
+        + Copy
+        + 'static,
+    Id: Expression<SqlType = Text>
+        + SelectableExpression<QS>
+        + ValidGrouping<(), IsAggregate = is_aggregate::No>
+        + QueryFragment<Sqlite>
+        + Send
+        + 'static,
+{
+    Box::new(
+        at.gt(cursor.at)
+            .or(at.eq(cursor.at).and(id.gt(cursor.id.clone()))),
+    )
+}
+
+/// Rows after `cursor` in a listing ordered by `(at, id)` descending, i.e. older ones
+pub fn before_cursor<QS, At, Id>(at: At, id: Id, cursor: &Cursor) -> KeysetFilter<QS>
+where
+    At: Expression<SqlType = Timestamp>
+        + SelectableExpression<QS>
+        + ValidGrouping<(), IsAggregate = is_aggregate::No>
+        + QueryFragment<Sqlite>
+        + Send
+        + Copy
+        + 'static,
+    Id: Expression<SqlType = Text>
+        + SelectableExpression<QS>
+        + ValidGrouping<(), IsAggregate = is_aggregate::No>
+        + QueryFragment<Sqlite>
+        + Send
+        + 'static,
+{
+    Box::new(
+        at.lt(cursor.at)
+            .or(at.eq(cursor.at).and(id.lt(cursor.id.clone()))),
+    )
+}
+
+/// Cut rows loaded with a limit of `limit + 1` down to a page
+///
+/// The extra row only tells whether another page follows; if it does, the cursor of the
+/// last row kept is returned for it.
+pub fn into_page<T>(
+    mut rows: Vec<T>,
+    limit: i64,
+    cursor_of: impl Fn(&T) -> Cursor,
+) -> (Vec<T>, Option<String>) {
+    if rows.len() as i64 <= limit {
+        return (rows, None);
+    }
+    rows.truncate(limit as usize);
+    let next_cursor = rows.last().map(|row| cursor_of(row).encode());
+    (rows, next_cursor)
+}
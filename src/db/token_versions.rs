@@ -0,0 +1,67 @@
+//! Compare-and-swap updates of purchase tokens
+//!
+//! Verification, RTDN, reconciliation and admin actions all load a token, decide on its new
+//! state and write it back, and can interleave on the same token. Every update bumps the
+//! token's `version` (the `purchase_tokens_bump_version` trigger), so a writer that filters its
+//! update on the version it loaded updates nothing when another writer got there first. It
+//! then fails with the retryable `TokenConflict` instead of writing over a revocation it never
+//! saw; the retry reloads the token and decides again.
+//!
+//! Changes that grant or revoke Pro on the IC `reserve_token` first, so a stale writer fails
+//! before it touches the IC rather than after.
+
+use diesel::prelude::*;
+
+use crate::error::{AppError, AppResult};
+use crate::model::PurchaseToken;
+
+/// Fail with `TokenConflict` unless an update filtered on `token.version` changed a row
+pub fn ensure_unchanged(updated: usize, token: &PurchaseToken) -> AppResult<()> {
+    if updated == 0 {
+        eprintln!(
+            "Purchase token {} changed since version {} was loaded",
+            token.purchase_token, token.version
+        );
+        return Err(AppError::TokenConflict(token.purchase_token.clone()));
+    }
+    Ok(())
+}
+
+/// Take `token` for a change with side effects, failing with `TokenConflict` if it changed
+/// since it was loaded
+///
+/// Moves the version on, so other writers holding the same copy conflict from now on. Returns
+/// the token at its new version, which the write following the side effect filters on.
+pub fn reserve_token(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+) -> AppResult<PurchaseToken> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set(version.eq(token.version + 1))
+    .execute(conn)?;
+    ensure_unchanged(updated, token)?;
+
+    Ok(PurchaseToken {
+        version: token.version + 1,
+        ..token.clone()
+    })
+}
+
+/// Stored row of `purchase_token_param`, for writers that only know the purchase token
+pub fn current_token(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+) -> AppResult<Option<PurchaseToken>> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    Ok(purchase_tokens
+        .filter(purchase_token.eq(purchase_token_param))
+        .first(conn)
+        .optional()?)
+}
@@ -0,0 +1,39 @@
+//! Transactions around async work
+//!
+//! `Connection::transaction` takes a synchronous closure. Handlers that call the IC inside a
+//! transaction open it with `begin_immediate` and close it with `finish` instead.
+
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use diesel::SqliteConnection;
+
+use crate::error::AppResult;
+
+/// Start a transaction holding the write lock from its first statement
+///
+/// A deferred transaction that reads first can fail as busy on its first write, after acting
+/// on what it read. Transactions `Connection::transaction` opens on the same connection become
+/// savepoints. A pooled connection dropped with the transaction still open, e.g. by a cancelled
+/// request, is discarded by the pool and SQLite rolls the transaction back.
+pub fn begin_immediate(conn: &mut SqliteConnection) -> AppResult<()> {
+    AnsiTransactionManager::begin_transaction_sql(conn, "BEGIN IMMEDIATE")?;
+    Ok(())
+}
+
+/// Commit the transaction `begin_immediate` opened if `result` is ok, roll it back otherwise
+pub fn finish<T, E>(conn: &mut SqliteConnection, result: Result<T, E>) -> Result<T, E>
+where
+    E: From<diesel::result::Error>,
+{
+    match result {
+        Ok(value) => {
+            AnsiTransactionManager::commit_transaction(conn)?;
+            Ok(value)
+        }
+        Err(e) => {
+            if let Err(rollback_error) = AnsiTransactionManager::rollback_transaction(conn) {
+                eprintln!("Failed to roll back transaction: {}", rollback_error);
+            }
+            Err(e)
+        }
+    }
+}
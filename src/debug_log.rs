@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::prelude::*;
+use serde_json::Value;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::AppError;
+use crate::feature_flags::{FeatureFlags, DEBUG_REQUEST_LOG};
+use crate::request_limits::RTDN_BODY_LIMIT;
+use crate::types::{DebugLogDirection, DebugLogEntry};
+use crate::AppState;
+
+/// Replacement for every redacted value
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Keys holding purchase tokens or personal data (SubscribeWithGoogleInfo), compared
+/// case-insensitively with underscores removed
+const REDACTED_KEYS: &[&str] = &[
+    "purchasetoken",
+    "linkedpurchasetoken",
+    "purchasetokens",
+    "token",
+    "email",
+    "emailaddress",
+    "givenname",
+    "familyname",
+    "profilename",
+];
+
+/// Personal data keys of SubscribeWithGoogleInfo, the subset of `REDACTED_KEYS` that stored
+/// Google responses must not keep
+const PERSONAL_DATA_KEYS: &[&str] = &[
+    "email",
+    "emailaddress",
+    "givenname",
+    "familyname",
+    "profilename",
+];
+
+fn normalize_key(key: &str) -> String {
+    key.replace('_', "").to_ascii_lowercase()
+}
+
+fn is_redacted_key(key: &str) -> bool {
+    REDACTED_KEYS.contains(&normalize_key(key).as_str())
+}
+
+/// Replace purchase tokens, emails and names anywhere in a JSON document
+///
+/// The base64 `message.data` of Pub/Sub pushes is decoded first so the notification inside
+/// is redacted too.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_redacted_key(key) {
+                    *value = Value::String(REDACTED.to_string());
+                    continue;
+                }
+                if key == "message" {
+                    decode_pubsub_data(value);
+                }
+                redact(value);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn decode_pubsub_data(message: &mut Value) {
+    let Some(data) = message.get_mut("data") else {
+        return;
+    };
+    let decoded = data
+        .as_str()
+        .and_then(|encoded| BASE64_STANDARD.decode(encoded).ok())
+        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+    // Undecodable data may still carry a token, so it is never kept as is
+    *data = decoded.unwrap_or_else(|| Value::String(REDACTED.to_string()));
+}
+
+/// Replace emails and names anywhere in a JSON document, keeping purchase tokens
+pub fn redact_personal_data(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if PERSONAL_DATA_KEYS.contains(&normalize_key(key).as_str()) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_personal_data(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_personal_data),
+        _ => {}
+    }
+}
+
+/// Redacted JSON form of a raw body, non-JSON bodies are replaced by their size
+pub fn redact_body(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value
+        }
+        Err(_) => Value::String(format!("<{} bytes of non-JSON body>", body.len())),
+    }
+}
+
+/// Opt-in ring buffer of redacted request and Google response bodies
+///
+/// Only records while the `debug_request_log` flag is on. Served at `/admin/debug/requests`.
+#[derive(Clone)]
+pub struct DebugLog {
+    flags: FeatureFlags,
+    capacity: usize,
+    next_id: Arc<AtomicU64>,
+    entries: Arc<Mutex<VecDeque<DebugLogEntry>>>,
+}
+
+impl DebugLog {
+    pub fn new(flags: FeatureFlags, capacity: usize) -> Self {
+        Self {
+            flags,
+            capacity,
+            next_id: Arc::new(AtomicU64::new(1)),
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Buffer sized by `DEBUG_LOG_CAPACITY` (default 200)
+    pub fn from_env(flags: FeatureFlags) -> Result<Self, ConfigError> {
+        let capacity = env_number("DEBUG_LOG_CAPACITY", 200)?;
+        Ok(Self::new(flags, capacity))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && self.flags.is_enabled(DEBUG_REQUEST_LOG)
+    }
+
+    /// Store a redacted copy of `body`, evicting the oldest entry when full
+    pub fn record(
+        &self,
+        direction: DebugLogDirection,
+        method: &str,
+        target: &str,
+        status: Option<u16>,
+        body: &[u8],
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let entry = DebugLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            recorded_at: chrono::Utc::now().to_rfc3339(),
+            direction,
+            method: method.to_string(),
+            target: target.to_string(),
+            status,
+            body: redact_body(body),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Buffered entries, newest first
+    pub fn entries(&self) -> Vec<DebugLogEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+/// Record inbound request bodies in the debug log while it is enabled
+///
+/// Bodies are buffered up to the largest route body limit; the per-route limits still apply
+/// afterwards. Use with `middleware::from_fn_with_state(app_state, capture_requests)`.
+pub async fn capture_requests(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !app_state.debug_log.is_enabled() {
+        return Ok(next.run(req).await);
+    }
+
+    let (parts, body) = req.into_parts();
+    let bytes = axum::body::to_bytes(body, RTDN_BODY_LIMIT)
+        .await
+        .map_err(|_| AppError::PayloadTooLarge(RTDN_BODY_LIMIT))?;
+
+    let method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+    let response = next
+        .run(Request::from_parts(parts, Body::from(bytes.clone())))
+        .await;
+
+    app_state.debug_log.record(
+        DebugLogDirection::Inbound,
+        &method,
+        &path,
+        Some(response.status().as_u16()),
+        &bytes,
+    );
+
+    Ok(response)
+}
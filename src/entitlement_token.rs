@@ -0,0 +1,196 @@
+use std::env;
+
+use base64::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{env_interval_secs, ConfigError};
+use crate::error::{AppError, AppResult};
+use crate::plans::plan_definition;
+use crate::types::Plan;
+
+/// `iss` of every entitlement token
+pub const ENTITLEMENT_ISSUER: &str = "yral-billing";
+
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, the raw 32 byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Claims of an entitlement token, what downstream services read after checking the signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntitlementClaims {
+    pub iss: String,
+    /// User the entitlement belongs to
+    pub sub: String,
+    pub plan: Plan,
+    pub features: Vec<String>,
+    /// When the paid plan lapses unless renewed (unix seconds), absent on the free plan
+    pub plan_expires_at: Option<i64>,
+    pub iat: i64,
+    /// Never after `plan_expires_at`, so a token can't outlive the plan it vouches for
+    pub exp: i64,
+}
+
+/// Public key in JWK form, as published at `/.well-known/jwks.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub kid: String,
+    /// Base64url of the raw public key
+    pub x: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Signs short-lived entitlement JWTs with an Ed25519 key
+#[derive(Clone)]
+pub struct EntitlementSigner {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+    ttl: Duration,
+}
+
+impl EntitlementSigner {
+    /// Signer for a PKCS#8 private key and its SPKI public key, both PEM
+    pub fn new(private_pem: &str, public_pem: &str, ttl: Duration) -> Result<Self, String> {
+        let encoding_key = EncodingKey::from_ed_pem(private_pem.as_bytes())
+            .map_err(|e| format!("invalid private key: {}", e))?;
+        let decoding_key = DecodingKey::from_ed_pem(public_pem.as_bytes())
+            .map_err(|e| format!("invalid public key: {}", e))?;
+        let public_key = raw_public_key(public_pem)?;
+
+        let kid = hex::encode(&Sha256::digest(&public_key)[..8]);
+        let signer = Self {
+            encoding_key,
+            decoding_key,
+            jwk: Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                alg: "EdDSA".to_string(),
+                key_use: "sig".to_string(),
+                kid,
+                x: BASE64_URL_SAFE_NO_PAD.encode(public_key),
+            },
+            ttl,
+        };
+
+        // A public key from another pair would publish a JWKS nothing we sign verifies against
+        let now = chrono::Utc::now().naive_utc();
+        let (probe, _) = signer
+            .sign("key-check", Plan::Free, None, now)
+            .map_err(|e| e.to_string())?;
+        signer
+            .verify(&probe)
+            .map_err(|_| "the public key doesn't belong to the private key".to_string())?;
+
+        Ok(signer)
+    }
+
+    /// `ENTITLEMENT_SIGNING_KEY` and `ENTITLEMENT_PUBLIC_KEY`, tokens aren't issued when unset
+    ///
+    /// Tokens last `ENTITLEMENT_TOKEN_TTL_SECS` (default 900). Newlines in the PEMs may be
+    /// written as `\n`, as secret stores often keep them on one line.
+    pub fn from_env() -> Result<Option<Self>, ConfigError> {
+        let ttl = env_interval_secs("ENTITLEMENT_TOKEN_TTL_SECS", 900)?;
+        let (private_pem, public_pem) = match (
+            env::var("ENTITLEMENT_SIGNING_KEY"),
+            env::var("ENTITLEMENT_PUBLIC_KEY"),
+        ) {
+            (Ok(private_pem), Ok(public_pem)) => (private_pem, public_pem),
+            (Err(_), Err(_)) => return Ok(None),
+            _ => {
+                return Err(ConfigError::EntitlementSigningKey(
+                    "ENTITLEMENT_SIGNING_KEY and ENTITLEMENT_PUBLIC_KEY must be set together"
+                        .to_string(),
+                ))
+            }
+        };
+        let ttl = Duration::from_std(ttl)
+            .map_err(|e| ConfigError::EntitlementSigningKey(e.to_string()))?;
+
+        Self::new(
+            &private_pem.replace("\\n", "\n"),
+            &public_pem.replace("\\n", "\n"),
+            ttl,
+        )
+        .map(Some)
+        .map_err(ConfigError::EntitlementSigningKey)
+    }
+
+    /// Signed token stating `user` has `plan` until `plan_expires_at`, with the claims signed
+    pub fn sign(
+        &self,
+        user: &str,
+        plan: Plan,
+        plan_expires_at: Option<NaiveDateTime>,
+        now: NaiveDateTime,
+    ) -> AppResult<(String, EntitlementClaims)> {
+        let exp = match plan_expires_at {
+            Some(expires_at) => (now + self.ttl).min(expires_at),
+            None => now + self.ttl,
+        };
+        let claims = EntitlementClaims {
+            iss: ENTITLEMENT_ISSUER.to_string(),
+            sub: user.to_string(),
+            plan,
+            features: plan_definition(plan)
+                .features
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            plan_expires_at: plan_expires_at.map(|time| time.and_utc().timestamp()),
+            iat: now.and_utc().timestamp(),
+            exp: exp.and_utc().timestamp(),
+        };
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.jwk.kid.clone());
+        let token = encode(&header, &claims, &self.encoding_key)
+            .map_err(|e| AppError::InternalError(format!("Failed to sign entitlement: {}", e)))?;
+        Ok((token, claims))
+    }
+
+    /// Claims of a token this signer issued, rejecting expired or foreign tokens
+    pub fn verify(&self, token: &str) -> AppResult<EntitlementClaims> {
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[ENTITLEMENT_ISSUER]);
+        validation.validate_aud = false;
+        decode::<EntitlementClaims>(token, &self.decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| AppError::BadRequest(format!("Invalid entitlement token: {}", e)))
+    }
+
+    /// Keys downstream services verify tokens with
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: vec![self.jwk.clone()],
+        }
+    }
+}
+
+/// The 32 byte Ed25519 key inside a PEM `SubjectPublicKeyInfo`
+fn raw_public_key(public_pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = public_pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    let der = BASE64_STANDARD
+        .decode(body)
+        .map_err(|e| format!("invalid public key: {}", e))?;
+    match der.strip_prefix(&ED25519_SPKI_PREFIX[..]) {
+        Some(key) if key.len() == 32 => Ok(key.to_vec()),
+        _ => Err("the public key is not an Ed25519 key".to_string()),
+    }
+}
@@ -1,6 +1,12 @@
-use crate::types::ApiResponse;
-use axum::http::StatusCode;
-use axum::response::{IntoResponse, Json};
+use crate::messages::MessageKey;
+use crate::token_state::TransitionReason;
+use crate::types::{ApiResponse, ErrorCode, PurchaseTokenStatus};
+use axum::http::{header::RETRY_AFTER, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use std::any::Any;
+
+/// Response header carrying the id that ties a panic response to its Sentry report
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
 
 /// Application-specific error types
 #[derive(Debug, thiserror::Error)]
@@ -14,6 +20,22 @@ pub enum AppError {
     #[error("Google Play API error: {0}")]
     GooglePlayApi(String),
 
+    #[error("Google Play is throttling requests: {message}")]
+    GooglePlayThrottled {
+        message: String,
+        /// Seconds the caller should wait before retrying
+        retry_after_secs: u64,
+    },
+
+    #[error("Purchase token not found on Google Play: {0}")]
+    GooglePlayTokenNotFound(String),
+
+    #[error("Google Play denied access: {0}")]
+    GooglePlayPermissionDenied(String),
+
+    #[error("Google Play is unavailable: {0}")]
+    GooglePlayUnavailable(String),
+
     #[error("Google Play verification failed: {0}")]
     GooglePlayVerification(String),
 
@@ -68,6 +90,18 @@ pub enum AppError {
     #[error("Network error: {0}")]
     NetworkError(String),
 
+    #[error("User {0} is not registered with the user info canister")]
+    UserNotFound(String),
+
+    #[error("User {0} already has the Pro plan")]
+    AlreadyPro(String),
+
+    #[error("User info canister is stopped: {0}")]
+    CanisterStopped(String),
+
+    #[error("User info canister rejected the call: {0}")]
+    CanisterRejected(String),
+
     #[error("Internal server error: {0}")]
     InternalError(String),
 
@@ -76,11 +110,60 @@ pub enum AppError {
 
     #[error("External account identifiers are missing")]
     ExternalAccountIdentifiersMissing,
+
+    #[error("Sandbox purchases are not honored in this environment")]
+    SandboxPurchaseNotHonored,
+
+    #[error("Request body exceeds the {0} byte limit")]
+    PayloadTooLarge(usize),
+
+    #[error("Content-Type must be application/json")]
+    UnsupportedMediaType,
+
+    #[error("Purchase belongs to a different account")]
+    AccountMismatch,
+
+    #[error("Billing is disabled for package {0}")]
+    PackageDisabled(String),
+
+    #[error("Purchases for this account need manual approval")]
+    ManualApprovalRequired,
+
+    #[error("Verification of this purchase was rejected after review")]
+    VerificationRejected,
+
+    #[error("Callers can only access their own purchases")]
+    NotOwner,
+
+    #[error("Device integrity check failed: {0}")]
+    IntegrityCheckFailed(String),
+
+    #[error("Entitlement tokens are not configured")]
+    EntitlementTokensDisabled,
+
+    #[error("Too many requests, retry in {retry_after_secs} seconds")]
+    RateLimited {
+        /// Seconds until the caller's window resets
+        retry_after_secs: u64,
+    },
+
+    #[error("Purchase token cannot move from {from:?} to {to:?} ({reason:?})")]
+    InvalidTransition {
+        from: PurchaseTokenStatus,
+        to: PurchaseTokenStatus,
+        reason: TransitionReason,
+    },
+
+    #[error("Purchase token {0} was changed by another request, retry")]
+    TokenConflict(String),
+
+    #[error("Profile {0} is registered to another user")]
+    ProfileAlreadyRegistered(String),
 }
 
 impl AppError {
     /// Get the appropriate HTTP status code for this error
-    fn status_code(&self) -> StatusCode {
+    pub(crate) fn status_code(&self) -> StatusCode {
         match self {
             AppError::DatabaseConnection
             | AppError::DatabaseOperation(_)
@@ -88,6 +171,7 @@ impl AppError {
             | AppError::AdminIcAgentMissing
             | AppError::AccessTokenFailed(_)
             | AppError::ServiceAccessFailed(_)
+            | AppError::GooglePlayResponseParse(_)
             | AppError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
             AppError::GooglePlayApi(_)
@@ -99,23 +183,157 @@ impl AppError {
             | AppError::SubscriptionInvalidLineItems
             | AppError::SubscriptionInvalidState
             | AppError::SubscriptionNoState
-            | AppError::GooglePlayResponseParse(_)
             | AppError::AcknowledgmentFailed
             | AppError::ExternalAccountIdentifiersMissing
+            | AppError::SandboxPurchaseNotHonored
+            | AppError::AccountMismatch
+            | AppError::GooglePlayTokenNotFound(_)
             | AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
 
             AppError::SubscriptionOnHold | AppError::SubscriptionPaused => StatusCode::ACCEPTED, // 202 - acknowledged but not processed
 
-            AppError::GooglePlayConnection(_) | AppError::NetworkError(_) => {
-                StatusCode::BAD_GATEWAY
+            AppError::GooglePlayConnection(_)
+            | AppError::NetworkError(_)
+            | AppError::GooglePlayPermissionDenied(_)
+            | AppError::GooglePlayUnavailable(_)
+            | AppError::CanisterRejected(_) => StatusCode::BAD_GATEWAY,
+
+            AppError::UserNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::AlreadyPro(_) => StatusCode::CONFLICT,
+            AppError::CanisterStopped(_) => StatusCode::SERVICE_UNAVAILABLE,
+
+            AppError::GooglePlayThrottled { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::PackageDisabled(_) | AppError::EntitlementTokensDisabled => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AppError::ManualApprovalRequired
+            | AppError::VerificationRejected
+            | AppError::NotOwner => StatusCode::FORBIDDEN,
+            AppError::IntegrityCheckFailed(_) => StatusCode::FORBIDDEN,
+            AppError::InvalidTransition { .. }
+            | AppError::TokenConflict(_)
+            | AppError::ProfileAlreadyRegistered(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    /// Get the stable machine-readable code for this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::DatabaseConnection => ErrorCode::DatabaseConnection,
+            AppError::DatabaseOperation(_) => ErrorCode::DatabaseOperation,
+            AppError::GooglePlayApi(_) => ErrorCode::GooglePlayApi,
+            AppError::GooglePlayThrottled { .. } => ErrorCode::GooglePlayThrottled,
+            AppError::GooglePlayTokenNotFound(_) => ErrorCode::GooglePlayTokenNotFound,
+            AppError::GooglePlayPermissionDenied(_) => ErrorCode::GooglePlayPermissionDenied,
+            AppError::GooglePlayUnavailable(_) => ErrorCode::GooglePlayUnavailable,
+            AppError::GooglePlayVerification(_) => ErrorCode::GooglePlayVerification,
+            AppError::AuthServiceUnavailable => ErrorCode::AuthServiceUnavailable,
+            AppError::AdminIcAgentMissing => ErrorCode::AdminIcAgentMissing,
+            AppError::AccessTokenFailed(_) => ErrorCode::AccessTokenFailed,
+            AppError::TokenAlreadyUsed => ErrorCode::TokenAlreadyUsed,
+            AppError::TokenExpired => ErrorCode::TokenExpired,
+            AppError::SubscriptionCanceled => ErrorCode::SubscriptionCanceled,
+            AppError::SubscriptionExpired => ErrorCode::SubscriptionExpired,
+            AppError::SubscriptionOnHold => ErrorCode::SubscriptionOnHold,
+            AppError::SubscriptionPaused => ErrorCode::SubscriptionPaused,
+            AppError::SubscriptionInvalidLineItems => ErrorCode::SubscriptionInvalidLineItems,
+            AppError::SubscriptionInvalidState => ErrorCode::SubscriptionInvalidState,
+            AppError::SubscriptionNoState => ErrorCode::SubscriptionNoState,
+            AppError::GooglePlayResponseParse(_) => ErrorCode::GooglePlayResponseParse,
+            AppError::GooglePlayConnection(_) => ErrorCode::GooglePlayConnection,
+            AppError::AcknowledgmentFailed => ErrorCode::AcknowledgmentFailed,
+            AppError::ServiceAccessFailed(_) => ErrorCode::ServiceAccessFailed,
+            AppError::NetworkError(_) => ErrorCode::NetworkError,
+            AppError::UserNotFound(_) => ErrorCode::UserNotFound,
+            AppError::AlreadyPro(_) => ErrorCode::AlreadyPro,
+            AppError::CanisterStopped(_) => ErrorCode::CanisterStopped,
+            AppError::CanisterRejected(_) => ErrorCode::CanisterRejected,
+            AppError::InternalError(_) => ErrorCode::InternalError,
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::ExternalAccountIdentifiersMissing => {
+                ErrorCode::ExternalAccountIdentifiersMissing
             }
+            AppError::SandboxPurchaseNotHonored => ErrorCode::SandboxPurchaseNotHonored,
+            AppError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            AppError::UnsupportedMediaType => ErrorCode::UnsupportedMediaType,
+            AppError::AccountMismatch => ErrorCode::AccountMismatch,
+            AppError::PackageDisabled(_) => ErrorCode::PackageDisabled,
+            AppError::ManualApprovalRequired => ErrorCode::ManualApprovalRequired,
+            AppError::VerificationRejected => ErrorCode::VerificationRejected,
+            AppError::NotOwner => ErrorCode::NotOwner,
+            AppError::IntegrityCheckFailed(_) => ErrorCode::IntegrityCheckFailed,
+            AppError::InvalidTransition { .. } => ErrorCode::InvalidTransition,
+            AppError::TokenConflict(_) => ErrorCode::TokenConflict,
+            AppError::ProfileAlreadyRegistered(_) => ErrorCode::ProfileAlreadyRegistered,
+            AppError::EntitlementTokensDisabled => ErrorCode::EntitlementTokensDisabled,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
         }
     }
 
     /// Get the error message
-    fn message(&self) -> String {
+    pub(crate) fn message(&self) -> String {
         self.to_string()
     }
+
+    /// Catalog entry for the message in other locales, see `messages`
+    ///
+    /// Only values meant for the user become placeholders, internal details stay out.
+    pub fn message_key(&self) -> MessageKey {
+        let key = MessageKey::new(self.code());
+        match self {
+            AppError::GooglePlayThrottled {
+                retry_after_secs, ..
+            }
+            | AppError::RateLimited { retry_after_secs } => {
+                key.arg("retry_after_secs", retry_after_secs)
+            }
+            AppError::PayloadTooLarge(limit) => key.arg("limit", limit),
+            _ => key,
+        }
+    }
+
+    /// Whether the call to the IC canister failed, as opposed to what it was asked to do
+    pub fn is_ic_failure(&self) -> bool {
+        matches!(
+            self,
+            AppError::ServiceAccessFailed(_)
+                | AppError::NetworkError(_)
+                | AppError::CanisterStopped(_)
+        )
+    }
+
+    /// Whether the same call may succeed later, so background workers should try it again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            AppError::DatabaseConnection
+                | AppError::DatabaseOperation(_)
+                | AppError::AuthServiceUnavailable
+                | AppError::AccessTokenFailed(_)
+                | AppError::GooglePlayThrottled { .. }
+                | AppError::GooglePlayUnavailable(_)
+                | AppError::GooglePlayConnection(_)
+                | AppError::ServiceAccessFailed(_)
+                | AppError::NetworkError(_)
+                | AppError::CanisterStopped(_)
+                | AppError::TokenConflict(_)
+        )
+    }
+
+    /// Seconds a client should wait before retrying, sent as `Retry-After`
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            AppError::GooglePlayThrottled {
+                retry_after_secs, ..
+            }
+            | AppError::RateLimited { retry_after_secs } => Some(*retry_after_secs),
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
@@ -123,12 +341,52 @@ impl IntoResponse for AppError {
         let status_code = self.status_code();
         let error_message = self.message();
 
-        let response_body = ApiResponse::<()>::error(error_message);
+        let response_body = ApiResponse::<()>::error_with_code(self.code(), error_message);
 
-        (status_code, Json(response_body)).into_response()
+        let mut response = (status_code, Json(response_body)).into_response();
+        response.extensions_mut().insert(self.message_key());
+        if let Some(secs) = self.retry_after_secs() {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
 
+/// Turn a panic in a handler into a 500 `ApiResponse` instead of a dropped connection
+///
+/// Used with `CatchPanicLayer::custom`. The correlation id is returned to the client and
+/// attached to the Sentry report.
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let details = if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        "unknown panic payload".to_string()
+    };
+
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    sentry::capture_message(
+        &format!("Handler panicked [{}]: {}", correlation_id, details),
+        sentry::Level::Fatal,
+    );
+    eprintln!("Handler panicked [{}]: {}", correlation_id, details);
+
+    let response_body = ApiResponse::<()>::error_with_code(
+        ErrorCode::InternalError,
+        format!("Internal server error (correlation id {})", correlation_id),
+    );
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [(CORRELATION_ID_HEADER, correlation_id)],
+        Json(response_body),
+    )
+        .into_response()
+}
+
 /// Result type for application operations
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -139,9 +397,15 @@ impl From<diesel::result::Error> for AppError {
     }
 }
 
+/// Transport failures are network errors, a body that can't be decoded is a parse error and
+/// anything else, e.g. a redirect loop, a connection error
+///
+/// Error statuses aren't transport failures, see `map_google_error` for Google's.
 impl From<reqwest::Error> for AppError {
     fn from(err: reqwest::Error) -> Self {
-        if err.is_connect() || err.is_timeout() {
+        if err.is_decode() {
+            AppError::GooglePlayResponseParse(err.to_string())
+        } else if err.is_connect() || err.is_timeout() || err.is_request() || err.is_body() {
             AppError::NetworkError(err.to_string())
         } else {
             AppError::GooglePlayConnection(err.to_string())
@@ -0,0 +1,272 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::error::{AppError, AppResult};
+use crate::metrics::{DOMAIN_EVENTS_DELIVERED_TOTAL, DOMAIN_EVENT_ERRORS_TOTAL};
+use crate::model::DomainEventRecord;
+use crate::types::{CancelReason, OutboxStatus};
+use crate::AppState;
+
+/// Published events waiting for the dispatcher, later ones are left to the redelivery job
+const DISPATCH_QUEUE_CAPACITY: usize = 1024;
+
+/// Attempts after which an event is marked `Failed` and left for manual follow-up
+pub const MAX_EVENT_ATTEMPTS: i32 = 10;
+
+/// Something that happened to a purchase, for consumers outside the request path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DomainEvent {
+    /// Verify accepted a purchase token, granted or waiting for payment
+    PurchaseVerified {
+        user_id: String,
+        purchase_token: String,
+        product_id: String,
+    },
+    /// Pro was granted for a purchase whose payment completed
+    AccessGranted {
+        user_id: String,
+        purchase_token: String,
+        product_id: String,
+    },
+    /// Google charged the next billing period of a granted subscription
+    SubscriptionRenewed {
+        user_id: String,
+        purchase_token: String,
+        product_id: String,
+    },
+    /// A granted purchase token lapsed
+    SubscriptionExpired {
+        user_id: String,
+        purchase_token: String,
+    },
+    /// The app reported the user is about to cancel, they should get a retention offer
+    RetentionOfferRequested {
+        user_id: String,
+        purchase_token: String,
+        reason: CancelReason,
+        /// When access ends if the user cancels, in RFC 3339
+        expires_at: String,
+    },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::PurchaseVerified { .. } => "purchase_verified",
+            DomainEvent::AccessGranted { .. } => "access_granted",
+            DomainEvent::SubscriptionRenewed { .. } => "subscription_renewed",
+            DomainEvent::SubscriptionExpired { .. } => "subscription_expired",
+            DomainEvent::RetentionOfferRequested { .. } => "retention_offer_requested",
+        }
+    }
+}
+
+/// Side effect run for every published event
+///
+/// An event is delivered again to every consumer when any of them fails on it, so handling
+/// must be idempotent. Consumers ignore events they aren't interested in.
+#[async_trait]
+pub trait EventConsumer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, app_state: &AppState, event: &DomainEvent) -> AppResult<()>;
+}
+
+/// In-process queue in front of the stored `domain_events`
+///
+/// Events are stored before they are queued, so one the dispatcher never got to, e.g. because
+/// the queue was full or the process stopped, is delivered by the redelivery job instead.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: mpsc::Sender<String>,
+    receiver: Arc<Mutex<Option<mpsc::Receiver<String>>>>,
+    consumers: Arc<RwLock<Vec<Arc<dyn EventConsumer>>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(DISPATCH_QUEUE_CAPACITY);
+        Self {
+            sender,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+            consumers: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Run `consumer` for every event delivered from now on
+    pub fn subscribe(&self, consumer: Arc<dyn EventConsumer>) {
+        self.consumers
+            .write()
+            .expect("event consumers lock poisoned")
+            .push(consumer);
+    }
+
+    fn consumers(&self) -> Vec<Arc<dyn EventConsumer>> {
+        self.consumers
+            .read()
+            .expect("event consumers lock poisoned")
+            .clone()
+    }
+
+    /// Store `event` and hand it to the dispatcher
+    ///
+    /// Inside a transaction the dispatcher may look for the event before it is committed, it
+    /// is then delivered by the redelivery job.
+    pub fn publish(
+        &self,
+        conn: &mut SqliteConnection,
+        event: &DomainEvent,
+        now: NaiveDateTime,
+    ) -> AppResult<()> {
+        let event_id = record_event(conn, event, now)?;
+        self.dispatch(event_id);
+        Ok(())
+    }
+
+    /// Hand an event stored with `record_event` to the dispatcher, e.g. once its transaction
+    /// committed
+    pub fn dispatch(&self, event_id: String) {
+        if let Err(e) = self.sender.try_send(event_id) {
+            println!(
+                "Event queue full, leaving event {} to the redelivery job",
+                e.into_inner()
+            );
+        }
+    }
+}
+
+/// Store `event` for delivery without queueing it, returning its id
+///
+/// For code without access to the `EventBus`, the redelivery job delivers the event.
+pub fn record_event(
+    conn: &mut SqliteConnection,
+    event: &DomainEvent,
+    now: NaiveDateTime,
+) -> AppResult<String> {
+    use crate::schema::domain_events;
+
+    let payload = serde_json::to_string(event)
+        .map_err(|e| AppError::InternalError(format!("Failed to encode event: {}", e)))?;
+    let record = DomainEventRecord::new(event.name().to_string(), payload, now);
+
+    diesel::insert_into(domain_events::table)
+        .values(&record)
+        .execute(conn)?;
+
+    Ok(record.id)
+}
+
+/// Run every consumer on a stored event that is still pending
+///
+/// The event is marked `Done` once all consumers succeeded, otherwise it stays pending for
+/// the redelivery job until it runs out of attempts. Returns whether it was delivered.
+pub async fn deliver_event(app_state: &AppState, event_id: &str) -> AppResult<bool> {
+    use crate::schema::domain_events::dsl::*;
+
+    let record: Option<DomainEventRecord> = domain_events
+        .filter(id.eq(event_id))
+        .filter(status.eq(OutboxStatus::Pending))
+        .first(&mut app_state.get_db_connection()?)
+        .optional()?;
+    let Some(record) = record else {
+        return Ok(false);
+    };
+
+    let mut errors = Vec::new();
+    match serde_json::from_str::<DomainEvent>(&record.payload) {
+        Ok(event) => {
+            for consumer in app_state.events.consumers() {
+                if let Err(e) = consumer.handle(app_state, &event).await {
+                    eprintln!(
+                        "Consumer {} failed on {} event {}: {}",
+                        consumer.name(),
+                        record.event_type,
+                        record.id,
+                        e
+                    );
+                    app_state.metrics.inc_counter(
+                        DOMAIN_EVENT_ERRORS_TOTAL,
+                        &[
+                            ("event", record.event_type.as_str()),
+                            ("consumer", consumer.name()),
+                        ],
+                        1,
+                    );
+                    errors.push(format!("{}: {}", consumer.name(), e));
+                }
+            }
+        }
+        // Can't get better by retrying
+        Err(e) => {
+            diesel::update(domain_events.filter(id.eq(&record.id)))
+                .set((
+                    status.eq(OutboxStatus::Failed),
+                    last_error.eq(Some(format!("Undecodable event: {}", e))),
+                    updated_at.eq(app_state.clock.now_naive()),
+                ))
+                .execute(&mut app_state.get_db_connection()?)?;
+            return Ok(false);
+        }
+    }
+
+    let delivered = errors.is_empty();
+    let next_status = if delivered {
+        OutboxStatus::Done
+    } else if record.attempts + 1 >= MAX_EVENT_ATTEMPTS {
+        OutboxStatus::Failed
+    } else {
+        OutboxStatus::Pending
+    };
+    diesel::update(domain_events.filter(id.eq(&record.id)))
+        .set((
+            status.eq(next_status),
+            attempts.eq(record.attempts + 1),
+            last_error.eq((!delivered).then(|| errors.join("; "))),
+            updated_at.eq(app_state.clock.now_naive()),
+        ))
+        .execute(&mut app_state.get_db_connection()?)?;
+
+    if delivered {
+        app_state.metrics.inc_counter(
+            DOMAIN_EVENTS_DELIVERED_TOTAL,
+            &[("event", record.event_type.as_str())],
+            1,
+        );
+    }
+    Ok(delivered)
+}
+
+/// Deliver published events as they are queued, for the life of the process
+///
+/// Only the first call starts a dispatcher, the bus has a single queue.
+pub fn spawn_event_dispatcher(app_state: AppState) {
+    let receiver = app_state
+        .events
+        .receiver
+        .lock()
+        .expect("event queue lock poisoned")
+        .take();
+    let Some(mut receiver) = receiver else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        while let Some(event_id) = receiver.recv().await {
+            if let Err(e) = deliver_event(&app_state, &event_id).await {
+                eprintln!("Failed to deliver event {}: {}", event_id, e);
+            }
+        }
+    });
+}
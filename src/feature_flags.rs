@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::error::AppResult;
+use crate::model::FeatureFlag;
+use crate::types::FeatureFlagSource;
+
+/// Reject purchases whose Google account id doesn't match the user verifying them
+pub const STRICT_ACCOUNT_MATCH: &str = "strict_account_match";
+/// Grant real access for license tester (sandbox) purchases
+pub const HONOR_SANDBOX_PURCHASES: &str = "honor_sandbox_purchases";
+/// Act on voided purchase RTDN notifications instead of only storing them
+pub const RTDN_VOIDED_PURCHASES: &str = "rtdn_voided_purchases";
+/// Keep redacted request and Google response bodies for `/admin/debug/requests`
+pub const DEBUG_REQUEST_LOG: &str = "debug_request_log";
+/// Hold verifications of users flagged for refund abuse until an operator approves them
+pub const RISK_MANUAL_APPROVAL: &str = "risk_manual_approval";
+/// Check Play Integrity tokens sent with verify and reject failing devices
+pub const PLAY_INTEGRITY_CHECK: &str = "play_integrity_check";
+/// Reject verify requests without a Play Integrity token, needs `play_integrity_check`
+pub const PLAY_INTEGRITY_REQUIRED: &str = "play_integrity_required";
+/// Accept verifications while the IC is unreachable and grant Pro through the access outbox
+pub const QUEUE_GRANTS_ON_IC_OUTAGE: &str = "queue_grants_on_ic_outage";
+/// Hold suspicious verifications in quarantine until an operator approves them
+pub const QUARANTINE_SUSPICIOUS_VERIFICATIONS: &str = "quarantine_suspicious_verifications";
+/// Run the candidate verification rules next to the current ones and log where they differ
+pub const SHADOW_VERIFICATION: &str = "shadow_verification";
+/// Answer verify with the candidate verification rules, the current ones keep running in shadow
+pub const CANDIDATE_VERIFICATION: &str = "candidate_verification";
+
+/// Flag that switches billing on or off for a single Android package
+pub fn package_flag(package_name: &str) -> String {
+    format!("package.{}", package_name)
+}
+
+/// Environment variable that overrides a flag, e.g. `HONOR_SANDBOX_PURCHASES`
+pub fn env_override_name(flag: &str) -> String {
+    flag.to_uppercase().replace(['.', '-'], "_")
+}
+
+/// Runtime switches for risky billing behaviors
+///
+/// Precedence is env override, then the value stored in the database, then the built-in
+/// default. Database values are cached and updated in place when flipped through the admin
+/// endpoint. Unknown flags (e.g. package switches) default to enabled.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    defaults: Arc<HashMap<String, bool>>,
+    stored: Arc<RwLock<HashMap<String, bool>>>,
+}
+
+impl FeatureFlags {
+    pub fn new(defaults: HashMap<String, bool>, stored: HashMap<String, bool>) -> Self {
+        Self {
+            defaults: Arc::new(defaults),
+            stored: Arc::new(RwLock::new(stored)),
+        }
+    }
+
+    /// Built-in defaults
+    ///
+    /// Sandbox purchases are only honored once `honor_sandbox_purchases` is switched on, e.g.
+    /// with `HONOR_SANDBOX_PURCHASES=true` on staging, so a deployment missing its `APP_ENV`
+    /// doesn't hand out Pro for test purchases.
+    pub fn default_values() -> HashMap<String, bool> {
+        HashMap::from([
+            (STRICT_ACCOUNT_MATCH.to_string(), false),
+            (HONOR_SANDBOX_PURCHASES.to_string(), false),
+            (RTDN_VOIDED_PURCHASES.to_string(), true),
+            (DEBUG_REQUEST_LOG.to_string(), false),
+            (RISK_MANUAL_APPROVAL.to_string(), false),
+            (PLAY_INTEGRITY_CHECK.to_string(), false),
+            (PLAY_INTEGRITY_REQUIRED.to_string(), false),
+            (QUEUE_GRANTS_ON_IC_OUTAGE.to_string(), false),
+            (QUARANTINE_SUSPICIOUS_VERIFICATIONS.to_string(), false),
+            (SHADOW_VERIFICATION.to_string(), false),
+            (CANDIDATE_VERIFICATION.to_string(), false),
+        ])
+    }
+
+    pub fn load(conn: &mut SqliteConnection) -> AppResult<Self> {
+        use crate::schema::feature_flags::dsl::*;
+
+        let rows: Vec<FeatureFlag> = feature_flags.load(conn)?;
+        let stored = rows
+            .into_iter()
+            .map(|row| (row.name, row.enabled))
+            .collect();
+
+        Ok(Self::new(Self::default_values(), stored))
+    }
+
+    pub fn is_enabled(&self, flag: &str) -> bool {
+        self.resolve(flag).0
+    }
+
+    /// Effective value of a flag and where it came from
+    pub fn resolve(&self, flag: &str) -> (bool, FeatureFlagSource) {
+        if let Ok(value) = env::var(env_override_name(flag)) {
+            return (value == "true", FeatureFlagSource::Env);
+        }
+        if let Some(value) = self.stored.read().unwrap().get(flag) {
+            return (*value, FeatureFlagSource::Database);
+        }
+        match self.defaults.get(flag) {
+            Some(value) => (*value, FeatureFlagSource::Default),
+            None => (true, FeatureFlagSource::Default),
+        }
+    }
+
+    /// Every known or stored flag, sorted by name
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .defaults
+            .keys()
+            .chain(self.stored.read().unwrap().keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Persist a flag value and update the cache
+    pub fn set(
+        &self,
+        conn: &mut SqliteConnection,
+        flag: &str,
+        value: bool,
+        now: NaiveDateTime,
+    ) -> AppResult<()> {
+        use crate::schema::feature_flags::dsl::*;
+
+        diesel::replace_into(feature_flags)
+            .values(&FeatureFlag {
+                name: flag.to_string(),
+                enabled: value,
+                updated_at: now,
+            })
+            .execute(conn)?;
+
+        self.stored.write().unwrap().insert(flag.to_string(), value);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Internal gRPC API, built with the `grpc` feature
+//!
+//! Calls go through the same service functions as the HTTP routes and need the same JWT,
+//! passed in the `authorization` metadata.
+
+use std::net::SocketAddr;
+
+use axum::http::StatusCode;
+use tonic::metadata::MetadataValue;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+
+use crate::auth::{claims_from_headers, Claims};
+use crate::config::{env_number, ConfigError};
+use crate::error::AppError;
+use crate::metrics::VERIFICATIONS_QUARANTINED_TOTAL;
+use crate::routes::credits::{apply_credit_change, CreditChange};
+use crate::routes::entitlements::load_entitlements;
+use crate::routes::purchase::{
+    process_purchase_token, publish_purchase_verified, VerifyOutcome, VerifyResult,
+};
+use crate::service_auth::Caller;
+use crate::types::{CreditRequest, EntitlementResponse, VerifyRequest};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+pub mod proto {
+    tonic::include_proto!("yral.billing.v1");
+}
+
+use proto::adjust_credits_request::Direction;
+use proto::billing_server::{Billing, BillingServer};
+use proto::verify_purchase_response::Outcome;
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let code = match error.status_code() {
+            StatusCode::BAD_REQUEST
+            | StatusCode::PAYLOAD_TOO_LARGE
+            | StatusCode::UNSUPPORTED_MEDIA_TYPE => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            // On hold or paused, Google has the purchase but access can't be granted yet
+            StatusCode::ACCEPTED => Code::FailedPrecondition,
+            StatusCode::CONFLICT => Code::FailedPrecondition,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Internal,
+        };
+
+        let mut status = Status::new(code, error.message());
+        // Same codes as the `code` field of HTTP error responses
+        if let Ok(value) = serde_json::to_value(error.code()) {
+            if let Some(value) = value.as_str().and_then(|v| MetadataValue::try_from(v).ok()) {
+                status.metadata_mut().insert("x-error-code", value);
+            }
+        }
+        status
+    }
+}
+
+fn authenticate<T>(request: &Request<T>) -> Result<Claims, Status> {
+    claims_from_headers(&request.metadata().clone().into_headers())
+        .map_err(|_| Status::unauthenticated("Invalid or missing JWT token"))
+}
+
+fn entitlements_message(entitlements: EntitlementResponse) -> proto::Entitlements {
+    let plan = serde_json::to_value(entitlements.plan)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let source_store = entitlements.source_store.and_then(|store| {
+        serde_json::to_value(store)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+    });
+
+    proto::Entitlements {
+        user_id: entitlements.user_id,
+        plan,
+        features: entitlements.features,
+        credit_allotment: entitlements.credit_allotment,
+        valid_from: entitlements.valid_from,
+        valid_until: entitlements.valid_until,
+        auto_renewing: entitlements.auto_renewing,
+        source_store,
+        paused_from: entitlements.paused_from,
+        resumes_at: entitlements.resumes_at,
+        bot_chat_access: entitlements
+            .bot_chat_access
+            .into_iter()
+            .map(|access| proto::BotChatEntitlement {
+                bot_id: access.bot_id,
+                expires_at: access.expires_at,
+            })
+            .collect(),
+    }
+}
+
+pub struct BillingService {
+    app_state: AppState,
+}
+
+impl BillingService {
+    pub fn new(app_state: AppState) -> Self {
+        Self { app_state }
+    }
+}
+
+#[tonic::async_trait]
+impl Billing for BillingService {
+    async fn verify_purchase(
+        &self,
+        request: Request<proto::VerifyPurchaseRequest>,
+    ) -> Result<Response<proto::VerifyPurchaseResponse>, Status> {
+        authenticate(&request)?;
+        let request = request.into_inner();
+        let payload = VerifyRequest {
+            user_id: canonical_user_id(&request.user_id)?,
+            package_name: request.package_name,
+            product_id: request.product_id,
+            purchase_token: request.purchase_token,
+            integrity_token: Some(request.integrity_token).filter(|token| !token.is_empty()),
+        };
+
+        let state = &self.app_state;
+        let mut conn = state.get_db_connection()?;
+        let VerifyResult { outcome, .. } = process_purchase_token(
+            &mut conn,
+            state.google_play.as_ref(),
+            &state.ack_strategies,
+            state.play_integrity.as_ref(),
+            state.user_info.as_ref(),
+            &state.feature_flags,
+            state.clock.as_ref(),
+            &state.metrics,
+            state.expiry_refresh_window,
+            &state.quarantine_policy,
+            &payload,
+        )
+        .await?;
+
+        if let VerifyOutcome::Quarantined(reason) = outcome {
+            state.metrics.inc_counter(
+                VERIFICATIONS_QUARANTINED_TOTAL,
+                &[("reason", reason.as_str())],
+                1,
+            );
+        } else {
+            publish_purchase_verified(state, &mut conn, &payload);
+        }
+
+        let outcome = match outcome {
+            VerifyOutcome::Granted => Outcome::Granted,
+            VerifyOutcome::Pending => Outcome::Pending,
+            VerifyOutcome::GrantQueued => Outcome::GrantQueued,
+            VerifyOutcome::Quarantined(_) => Outcome::Quarantined,
+        };
+        Ok(Response::new(proto::VerifyPurchaseResponse {
+            outcome: outcome.into(),
+        }))
+    }
+
+    async fn get_entitlements(
+        &self,
+        request: Request<proto::GetEntitlementsRequest>,
+    ) -> Result<Response<proto::Entitlements>, Status> {
+        authenticate(&request)?;
+        let user_id = canonical_user_id(&request.into_inner().user_id)?;
+
+        let mut conn = self.app_state.get_db_connection()?;
+        let entitlements = load_entitlements(
+            &mut conn,
+            user_id,
+            &self.app_state.credit_allotments,
+            self.app_state.clock.now_naive(),
+        )?;
+
+        Ok(Response::new(entitlements_message(entitlements)))
+    }
+
+    async fn adjust_credits(
+        &self,
+        request: Request<proto::AdjustCreditsRequest>,
+    ) -> Result<Response<proto::AdjustCreditsResponse>, Status> {
+        let caller = Caller::User(authenticate(&request)?);
+        let request = request.into_inner();
+        let change = match request.direction() {
+            Direction::Deduct => CreditChange::Deduct,
+            Direction::Increment => CreditChange::Increment,
+            Direction::Unspecified => {
+                return Err(Status::invalid_argument("direction is required"));
+            }
+        };
+        let payload = CreditRequest {
+            user_principal: request.user_principal,
+            amount: request.amount,
+            correlation_id: Some(request.correlation_id).filter(|id| !id.is_empty()),
+        };
+
+        apply_credit_change(
+            &self.app_state,
+            &mut self.app_state.get_db_connection()?,
+            &caller,
+            change,
+            change.api_reason(),
+            &payload,
+        )
+        .await?;
+
+        Ok(Response::new(proto::AdjustCreditsResponse {}))
+    }
+}
+
+/// Serve the gRPC API on `GRPC_PORT` in the background
+pub fn spawn_grpc_server(app_state: AppState) -> Result<(), ConfigError> {
+    let port: u16 = env_number("GRPC_PORT", 50051)?;
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    // Bind up front so a taken port fails startup like the HTTP listener does
+    let listener = std::net::TcpListener::bind(addr).map_err(|e| ConfigError::Bind {
+        addr,
+        reason: e.to_string(),
+    })?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| ConfigError::Bind {
+            addr,
+            reason: e.to_string(),
+        })?;
+    println!("gRPC listening on {}", addr);
+
+    tokio::spawn(async move {
+        let incoming = match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => TcpIncoming::from_listener(listener, true, None),
+            Err(e) => Err(e.into()),
+        };
+        let result = match incoming {
+            Ok(incoming) => {
+                Server::builder()
+                    .add_service(BillingServer::new(BillingService::new(app_state)))
+                    .serve_with_incoming(incoming)
+                    .await
+            }
+            Err(e) => {
+                eprintln!("gRPC server error: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = result {
+            sentry::capture_message(&format!("gRPC server error: {}", e), sentry::Level::Error);
+            eprintln!("gRPC server error: {}", e);
+        }
+    });
+
+    Ok(())
+}
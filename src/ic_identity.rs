@@ -0,0 +1,174 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use ic_agent::agent::EnvelopeContent;
+use ic_agent::export::Principal;
+use ic_agent::identity::{Delegation, Secp256k1Identity, SignedDelegation};
+use ic_agent::{Identity, Signature};
+
+/// Where the admin IC identity key is loaded from
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// PEM stored directly in `BACKEND_ADMIN_SECRET_KEY`
+    Env,
+    /// PEM file at `BACKEND_ADMIN_SECRET_KEY_FILE`, e.g. a secret mounted by the secrets manager
+    File(PathBuf),
+}
+
+impl KeySource {
+    /// Pick the key source from the environment, preferring the file path when both are set
+    pub fn from_env() -> Result<Self, String> {
+        if let Ok(path) = env::var("BACKEND_ADMIN_SECRET_KEY_FILE") {
+            return Ok(KeySource::File(PathBuf::from(path)));
+        }
+        if env::var("BACKEND_ADMIN_SECRET_KEY").is_ok() {
+            return Ok(KeySource::Env);
+        }
+        Err("BACKEND_ADMIN_SECRET_KEY_FILE or BACKEND_ADMIN_SECRET_KEY must be set".to_string())
+    }
+
+    fn read_pem(&self) -> Result<String, String> {
+        match self {
+            KeySource::Env => env::var("BACKEND_ADMIN_SECRET_KEY")
+                .map_err(|_| "BACKEND_ADMIN_SECRET_KEY is not set".to_string()),
+            KeySource::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Human readable description for the admin endpoint
+    pub fn describe(&self) -> String {
+        match self {
+            KeySource::Env => "env:BACKEND_ADMIN_SECRET_KEY".to_string(),
+            KeySource::File(path) => format!("file:{}", path.display()),
+        }
+    }
+}
+
+struct LoadedKey {
+    pem: String,
+    identity: Secp256k1Identity,
+    principal: Principal,
+    loaded_at: DateTime<Utc>,
+}
+
+impl LoadedKey {
+    fn from_pem(pem: String) -> Result<Self, String> {
+        let identity = Secp256k1Identity::from_pem(pem.as_bytes())
+            .map_err(|e| format!("Unable to create identity: {e:?}"))?;
+        let principal = identity.sender()?;
+
+        Ok(Self {
+            pem,
+            identity,
+            principal,
+            loaded_at: Utc::now(),
+        })
+    }
+}
+
+/// Admin identity used by the IC agent whose key can be swapped without a restart
+///
+/// Clones share the same key, so the agent picks up a rotated key on its next call.
+#[derive(Clone)]
+pub struct AdminIdentity {
+    source: KeySource,
+    current: Arc<RwLock<LoadedKey>>,
+}
+
+impl AdminIdentity {
+    pub fn load(source: KeySource) -> Result<Self, String> {
+        let key = LoadedKey::from_pem(source.read_pem()?)?;
+
+        Ok(Self {
+            source,
+            current: Arc::new(RwLock::new(key)),
+        })
+    }
+
+    /// Re-read the key from its source, returning whether the key changed
+    pub fn reload(&self) -> Result<bool, String> {
+        let pem = self.source.read_pem()?;
+        if self.current.read().unwrap().pem == pem {
+            return Ok(false);
+        }
+
+        let key = LoadedKey::from_pem(pem)?;
+        println!("Rotated admin IC identity to principal {}", key.principal);
+        *self.current.write().unwrap() = key;
+
+        Ok(true)
+    }
+
+    /// Periodically reload file-backed keys so rotations are picked up automatically
+    pub fn spawn_reload_task(&self, interval: Duration) {
+        if !matches!(self.source, KeySource::File(_)) {
+            return;
+        }
+
+        let identity = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = identity.reload() {
+                    sentry::capture_message(
+                        &format!("Failed to reload admin IC identity: {}", e),
+                        sentry::Level::Error,
+                    );
+                    eprintln!("Failed to reload admin IC identity: {}", e);
+                }
+            }
+        });
+    }
+
+    pub fn principal(&self) -> Principal {
+        self.current.read().unwrap().principal
+    }
+
+    pub fn loaded_at(&self) -> DateTime<Utc> {
+        self.current.read().unwrap().loaded_at
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+}
+
+impl Identity for AdminIdentity {
+    fn sender(&self) -> Result<Principal, String> {
+        Ok(self.principal())
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        self.current.read().unwrap().identity.public_key()
+    }
+
+    fn sign(&self, content: &EnvelopeContent) -> Result<Signature, String> {
+        self.current.read().unwrap().identity.sign(content)
+    }
+
+    fn sign_delegation(&self, content: &Delegation) -> Result<Signature, String> {
+        self.current
+            .read()
+            .unwrap()
+            .identity
+            .sign_delegation(content)
+    }
+
+    fn sign_arbitrary(&self, content: &[u8]) -> Result<Signature, String> {
+        self.current
+            .read()
+            .unwrap()
+            .identity
+            .sign_arbitrary(content)
+    }
+
+    fn delegation_chain(&self) -> Vec<SignedDelegation> {
+        self.current.read().unwrap().identity.delegation_chain()
+    }
+}
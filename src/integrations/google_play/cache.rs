@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex as AsyncMutex;
+
+use super::GooglePlayApi;
+use crate::cache::{Cache, MemoryCache};
+use crate::config::{env_number, ConfigError};
+use crate::error::AppResult;
+use crate::metrics::{Metrics, GOOGLE_PLAY_CACHE_TOTAL};
+use crate::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+};
+
+/// Held while fetching one purchase token, so concurrent callers in this process wait
+type Slot = Arc<AsyncMutex<()>>;
+
+fn cache_key(purchase_token: &str) -> String {
+    format!("google_play:subscription:{}", purchase_token)
+}
+
+/// Caches subscription details per purchase token and coalesces concurrent fetches
+///
+/// Verification, reconciliation and RTDN handling often look up the same token within
+/// seconds. Callers arriving while a fetch is in flight wait for it and share its response
+/// instead of spending Android Publisher quota on their own call. Failed fetches are not
+/// cached. Acknowledging or deferring a subscription drops its cached details.
+///
+/// Responses are kept in a `Cache`, which replicas can share; coalescing is per process.
+/// A cache that can't be reached is treated as a miss.
+pub struct CachingGooglePlay {
+    inner: Arc<dyn GooglePlayApi>,
+    ttl: Duration,
+    metrics: Metrics,
+    cache: Arc<dyn Cache>,
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl CachingGooglePlay {
+    pub fn new(inner: Arc<dyn GooglePlayApi>, ttl: Duration, metrics: Metrics) -> Self {
+        Self {
+            inner,
+            ttl,
+            metrics,
+            cache: Arc::new(MemoryCache::default()),
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep responses in `cache` instead of this process
+    pub fn with_cache(mut self, cache: Arc<dyn Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Wrap `inner` with the TTL from `GOOGLE_PLAY_CACHE_TTL_SECS` (default 60), keeping
+    /// responses in `cache`
+    ///
+    /// Zero turns caching off and returns `inner` unchanged.
+    pub fn wrap_from_env(
+        inner: Arc<dyn GooglePlayApi>,
+        metrics: Metrics,
+        cache: Arc<dyn Cache>,
+    ) -> Result<Arc<dyn GooglePlayApi>, ConfigError> {
+        match env_number("GOOGLE_PLAY_CACHE_TTL_SECS", 60)? {
+            0 => Ok(inner),
+            secs => Ok(Arc::new(
+                Self::new(inner, Duration::from_secs(secs), metrics).with_cache(cache),
+            )),
+        }
+    }
+
+    fn slot(&self, purchase_token: &str) -> Slot {
+        let mut slots = self.slots.lock().unwrap();
+        // Slots nobody holds would otherwise pile up for every token seen
+        slots.retain(|_, slot| Arc::strong_count(slot) > 1);
+        slots.entry(purchase_token.to_string()).or_default().clone()
+    }
+
+    async fn cached(&self, purchase_token: &str) -> Option<GooglePlaySubscriptionResponse> {
+        match self.cache.get(&cache_key(purchase_token)).await {
+            Ok(cached) => cached.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                eprintln!("Google Play cache lookup failed: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn store(&self, purchase_token: &str, response: &GooglePlaySubscriptionResponse) {
+        let Ok(value) = serde_json::to_string(response) else {
+            return;
+        };
+        if let Err(e) = self
+            .cache
+            .set(&cache_key(purchase_token), &value, self.ttl)
+            .await
+        {
+            eprintln!("Failed to cache Google Play response: {}", e);
+        }
+    }
+
+    fn record(&self, result: &str) {
+        self.metrics
+            .inc_counter(GOOGLE_PLAY_CACHE_TOTAL, &[("result", result)], 1);
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for CachingGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        let slot = self.slot(purchase_token);
+        let (_fetching, waited) = match slot.try_lock() {
+            Ok(guard) => (guard, false),
+            Err(_) => (slot.lock().await, true),
+        };
+
+        if let Some(response) = self.cached(purchase_token).await {
+            self.record(if waited { "coalesced" } else { "hit" });
+            return Ok(response);
+        }
+
+        self.record("miss");
+        let response = self
+            .inner
+            .fetch_subscription(package_name, purchase_token)
+            .await?;
+        self.store(purchase_token, &response).await;
+        Ok(response)
+    }
+
+    async fn invalidate_subscription(&self, purchase_token: &str) {
+        if let Err(e) = self.cache.delete(&cache_key(purchase_token)).await {
+            eprintln!("Failed to drop cached Google Play response: {}", e);
+        }
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        let result = self
+            .inner
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await;
+        self.invalidate_subscription(purchase_token).await;
+        result
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        self.inner.fetch_product(package_name, purchase_token).await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        self.inner
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        let result = self
+            .inner
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await;
+        self.invalidate_subscription(purchase_token).await;
+        result
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        let result = self
+            .inner
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await;
+        self.invalidate_subscription(purchase_token).await;
+        result
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        self.inner.list_subscription_products(package_name).await
+    }
+}
@@ -0,0 +1,598 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::auth::GoogleAuth;
+use crate::debug_log::DebugLog;
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::snapshots::SubscriptionSnapshots;
+use crate::metrics::{Metrics, GOOGLE_PLAY_ERRORS_TOTAL};
+use crate::types::{
+    DebugLogDirection, GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse,
+    ListSubscriptionsResponse, VoidedPurchasesResponse,
+};
+
+pub const ANDROID_PUBLISHER_URL: &str =
+    "https://androidpublisher.googleapis.com/androidpublisher/v3";
+
+/// Google access tokens live for an hour, refresh a little before that
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+/// Wait suggested to our callers when Google throttles without a `Retry-After` header
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+
+/// Google error reasons that mean a rate limit or quota was hit
+const THROTTLE_REASONS: &[&str] = &[
+    "rateLimitExceeded",
+    "userRateLimitExceeded",
+    "quotaExceeded",
+    "dailyLimitExceeded",
+];
+
+/// Source of OAuth access tokens for the Android Publisher scope
+#[async_trait]
+pub trait TokenSource: Send + Sync {
+    async fn fetch_token(&self) -> AppResult<String>;
+}
+
+#[async_trait]
+impl TokenSource for GoogleAuth {
+    async fn fetch_token(&self) -> AppResult<String> {
+        self.get_token_for_default_scopes()
+            .await
+            .map_err(|e| AppError::AccessTokenFailed(e.to_string()))
+    }
+}
+
+/// How much of the price Google refunds when a subscription is revoked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationRefund {
+    Full,
+    /// Refund the unused part of the current billing period
+    Prorated,
+}
+
+/// Filters for `list_voided_purchases`
+#[derive(Debug, Clone, Default)]
+pub struct VoidedPurchasesQuery {
+    /// Oldest void to return, Google keeps 30 days of history
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub max_results: Option<u32>,
+    /// `next_page_token` of the previous page
+    pub page_token: Option<String>,
+    /// Include voided subscriptions, not only one-time products
+    pub include_subscriptions: bool,
+}
+
+struct CachedToken {
+    value: String,
+    fetched_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct GoogleErrorBody {
+    error: GoogleErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct GoogleErrorDetail {
+    message: String,
+    /// Canonical status such as `RESOURCE_EXHAUSTED`
+    status: Option<String>,
+    #[serde(default)]
+    errors: Vec<GoogleErrorItem>,
+}
+
+#[derive(Deserialize)]
+struct GoogleErrorItem {
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeferResponse {
+    #[serde(rename = "newExpiryTimeMillis")]
+    new_expiry_time_millis: String,
+}
+
+/// Typed Android Publisher API client
+///
+/// Caches the access token and refreshes it once when Google rejects it. Every response body
+/// is recorded in the debug log while it is enabled, every failed call is counted by
+/// `error_kind` in `GOOGLE_PLAY_ERRORS_TOTAL`.
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    tokens: Box<dyn TokenSource>,
+    cached_token: Mutex<Option<CachedToken>>,
+    debug_log: DebugLog,
+    snapshots: SubscriptionSnapshots,
+    metrics: Metrics,
+}
+
+impl Client {
+    pub fn new(tokens: impl TokenSource + 'static, debug_log: DebugLog) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: ANDROID_PUBLISHER_URL.to_string(),
+            tokens: Box::new(tokens),
+            cached_token: Mutex::new(None),
+            debug_log,
+            snapshots: SubscriptionSnapshots::default(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Point the client at another API root, e.g. a local fake in tests
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Store every subscriptionsv2 body fetched from now on in `snapshots`
+    pub fn with_snapshots(mut self, snapshots: SubscriptionSnapshots) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Count failed calls in `metrics` instead of a registry of the client's own
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// `purchases.subscriptionsv2.get`
+    pub async fn get_subscription_v2(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        let path = format!(
+            "/applications/{}/purchases/subscriptionsv2/tokens/{}",
+            package_name, purchase_token
+        );
+        let body = self
+            .call(
+                Method::GET,
+                "purchases.subscriptionsv2.get",
+                &path,
+                &[],
+                None,
+            )
+            .await?;
+        // Stored before parsing, so a body we fail to decode can be replayed once fixed
+        self.snapshots.record(purchase_token, &body);
+        self.parse("purchases.subscriptionsv2.get", &body)
+    }
+
+    /// `purchases.subscriptions.acknowledge`
+    ///
+    /// A purchase the app acknowledged first succeeds too, Google's refusal would only reach
+    /// the user as an error.
+    pub async fn acknowledge(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        let path = format!(
+            "/applications/{}/purchases/subscriptions/{}/tokens/{}:acknowledge",
+            package_name, subscription_id, purchase_token
+        );
+        match self
+            .call(
+                Method::POST,
+                "purchases.subscriptions.acknowledge",
+                &path,
+                &[],
+                Some(json!({})),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(AppError::GooglePlayApi(details)) if is_already_acknowledged(&details) => {
+                println!(
+                    "Purchase token {} was already acknowledged: {}",
+                    purchase_token, details
+                );
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `purchases.subscriptionsv2.revoke`, ends access immediately and refunds the user
+    pub async fn revoke(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        refund: RevocationRefund,
+    ) -> AppResult<()> {
+        let path = format!(
+            "/applications/{}/purchases/subscriptionsv2/tokens/{}:revoke",
+            package_name, purchase_token
+        );
+        let context = match refund {
+            RevocationRefund::Full => json!({ "fullRefund": {} }),
+            RevocationRefund::Prorated => json!({ "proratedRefund": {} }),
+        };
+        self.call(
+            Method::POST,
+            "purchases.subscriptionsv2.revoke",
+            &path,
+            &[],
+            Some(json!({ "revocationContext": context })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `purchases.subscriptions.defer`, moves the next renewal to `desired_expiry`
+    ///
+    /// `expected_expiry` must match Google's current expiry. Returns the new expiry.
+    pub async fn defer(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        let path = format!(
+            "/applications/{}/purchases/subscriptions/{}/tokens/{}:defer",
+            package_name, subscription_id, purchase_token
+        );
+        let request = json!({
+            "deferralInfo": {
+                "expectedExpiryTimeMillis": expected_expiry.timestamp_millis().to_string(),
+                "desiredExpiryTimeMillis": desired_expiry.timestamp_millis().to_string(),
+            }
+        });
+        let body = self
+            .call(
+                Method::POST,
+                "purchases.subscriptions.defer",
+                &path,
+                &[],
+                Some(request),
+            )
+            .await?;
+
+        let response: DeferResponse = self.parse("purchases.subscriptions.defer", &body)?;
+        parse_millis(&response.new_expiry_time_millis)
+    }
+
+    /// `purchases.subscriptions.cancel`, turns off auto-renew without refunding
+    pub async fn cancel(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        let path = format!(
+            "/applications/{}/purchases/subscriptions/{}/tokens/{}:cancel",
+            package_name, subscription_id, purchase_token
+        );
+        self.call(
+            Method::POST,
+            "purchases.subscriptions.cancel",
+            &path,
+            &[],
+            Some(json!({})),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `purchases.productsv2.get`
+    pub async fn get_product_purchase(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        let path = format!(
+            "/applications/{}/purchases/productsv2/tokens/{}",
+            package_name, purchase_token
+        );
+        let body = self
+            .call(Method::GET, "purchases.productsv2.get", &path, &[], None)
+            .await?;
+        self.parse("purchases.productsv2.get", &body)
+    }
+
+    /// `purchases.products.consume`
+    pub async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        let path = format!(
+            "/applications/{}/purchases/products/{}/tokens/{}:consume",
+            package_name, product_id, purchase_token
+        );
+        self.call(
+            Method::POST,
+            "purchases.products.consume",
+            &path,
+            &[],
+            Some(json!({})),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `purchases.voidedpurchases.list`, one page of refunded, canceled or charged back purchases
+    pub async fn list_voided_purchases(
+        &self,
+        package_name: &str,
+        query: &VoidedPurchasesQuery,
+    ) -> AppResult<VoidedPurchasesResponse> {
+        let path = format!("/applications/{}/purchases/voidedpurchases", package_name);
+
+        let mut params = Vec::new();
+        if let Some(start_time) = query.start_time {
+            params.push(("startTime", start_time.timestamp_millis().to_string()));
+        }
+        if let Some(end_time) = query.end_time {
+            params.push(("endTime", end_time.timestamp_millis().to_string()));
+        }
+        if let Some(max_results) = query.max_results {
+            params.push(("maxResults", max_results.to_string()));
+        }
+        if let Some(page_token) = &query.page_token {
+            params.push(("token", page_token.clone()));
+        }
+        if query.include_subscriptions {
+            params.push(("type", "1".to_string()));
+        }
+
+        let body = self
+            .call(
+                Method::GET,
+                "purchases.voidedpurchases.list",
+                &path,
+                &params,
+                None,
+            )
+            .await?;
+        self.parse("purchases.voidedpurchases.list", &body)
+    }
+
+    /// `monetization.subscriptions.list`, one page of the subscription products in the Console
+    pub async fn list_subscriptions(
+        &self,
+        package_name: &str,
+        page_token: Option<&str>,
+    ) -> AppResult<ListSubscriptionsResponse> {
+        let path = format!("/applications/{}/subscriptions", package_name);
+
+        let mut params = vec![("pageSize", "100".to_string())];
+        if let Some(page_token) = page_token {
+            params.push(("pageToken", page_token.to_string()));
+        }
+
+        let body = self
+            .call(
+                Method::GET,
+                "monetization.subscriptions.list",
+                &path,
+                &params,
+                None,
+            )
+            .await?;
+        self.parse("monetization.subscriptions.list", &body)
+    }
+
+    async fn access_token(&self, refresh: bool) -> AppResult<String> {
+        let mut cached = self.cached_token.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if !refresh && token.fetched_at.elapsed() < TOKEN_LIFETIME {
+                return Ok(token.value.clone());
+            }
+        }
+
+        let value = self.tokens.fetch_token().await?;
+        *cached = Some(CachedToken {
+            value: value.clone(),
+            fetched_at: Instant::now(),
+        });
+        Ok(value)
+    }
+
+    /// Decode a successful response body of `operation`
+    ///
+    /// Google answering with a body we can't decode is our bug or an API change rather than
+    /// something the caller can fix, so it is reported to Sentry.
+    fn parse<T: DeserializeOwned>(&self, operation: &str, body: &[u8]) -> AppResult<T> {
+        let result = parse(body);
+        if let Err(e) = &result {
+            self.count_error(operation, e);
+            sentry::capture_message(
+                &format!("Failed to decode {} response: {}", operation, e),
+                sentry::Level::Error,
+            );
+        }
+        result
+    }
+
+    fn count_error(&self, operation: &str, error: &AppError) {
+        self.metrics.inc_counter(
+            GOOGLE_PLAY_ERRORS_TOTAL,
+            &[("operation", operation), ("kind", error_kind(error))],
+            1,
+        );
+    }
+
+    /// Send a request and return the body of a successful response
+    ///
+    /// A 401 is retried once with a freshly fetched token.
+    async fn call(
+        &self,
+        method: Method,
+        operation: &str,
+        path: &str,
+        params: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> AppResult<Vec<u8>> {
+        let result = self.send(method, operation, path, params, body).await;
+        if let Err(e) = &result {
+            self.count_error(operation, e);
+        }
+        result
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        operation: &str,
+        path: &str,
+        params: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> AppResult<Vec<u8>> {
+        let url = format!("{}{}", self.base_url, path);
+
+        let mut refresh = false;
+        loop {
+            let access_token = self.access_token(refresh).await?;
+
+            let mut request = self
+                .http
+                .request(method.clone(), &url)
+                .bearer_auth(&access_token)
+                .query(params);
+            if let Some(body) = &body {
+                request = request.json(body);
+            }
+
+            let res = request.send().await.map_err(AppError::from)?;
+            let status = res.status();
+            let retry_after = res
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let bytes = res.bytes().await.map_err(AppError::from)?;
+            self.debug_log.record(
+                DebugLogDirection::GoogleResponse,
+                method.as_str(),
+                operation,
+                Some(status.as_u16()),
+                &bytes,
+            );
+
+            if status == StatusCode::UNAUTHORIZED && !refresh {
+                refresh = true;
+                continue;
+            }
+            if status.is_success() {
+                return Ok(bytes.to_vec());
+            }
+            return Err(map_google_error(
+                operation,
+                status,
+                retry_after.as_deref(),
+                &bytes,
+            ));
+        }
+    }
+}
+
+/// Whether a refused acknowledgment says the purchase was acknowledged before
+pub fn is_already_acknowledged(details: &str) -> bool {
+    let details = details.to_ascii_lowercase();
+    details.contains("already acknowledged") || details.contains("alreadyacknowledged")
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> AppResult<T> {
+    serde_json::from_slice(body).map_err(|e| AppError::GooglePlayResponseParse(e.to_string()))
+}
+
+fn parse_millis(millis: &str) -> AppResult<DateTime<Utc>> {
+    millis
+        .parse::<i64>()
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .ok_or_else(|| {
+            AppError::GooglePlayResponseParse(format!("Invalid timestamp millis: {}", millis))
+        })
+}
+
+/// Which side a failed Android Publisher call went wrong on, the `kind` label of
+/// `GOOGLE_PLAY_ERRORS_TOTAL`
+///
+/// `client` errors are for the caller to resolve, `throttled` and `upstream` ones may succeed
+/// when retried later.
+pub fn error_kind(error: &AppError) -> &'static str {
+    match error {
+        AppError::NetworkError(_)
+        | AppError::GooglePlayConnection(_)
+        | AppError::AccessTokenFailed(_) => "network",
+        AppError::GooglePlayResponseParse(_) => "decode",
+        AppError::GooglePlayThrottled { .. } => "throttled",
+        AppError::GooglePlayUnavailable(_) => "upstream",
+        _ => "client",
+    }
+}
+
+/// Turn a failed Android Publisher response into the matching `AppError`
+///
+/// Rate limits and quota errors become `GooglePlayThrottled` with Google's `Retry-After` (or
+/// `DEFAULT_RETRY_AFTER_SECS`), unknown tokens `GooglePlayTokenNotFound`, auth failures
+/// `GooglePlayPermissionDenied`, server errors `GooglePlayUnavailable` and other 4xx
+/// `GooglePlayApi`. Google's error reason is kept in the message.
+pub fn map_google_error(
+    operation: &str,
+    status: StatusCode,
+    retry_after: Option<&str>,
+    body: &[u8],
+) -> AppError {
+    let (message, reason) = match serde_json::from_slice::<GoogleErrorBody>(body) {
+        Ok(error_body) => {
+            let reason = error_body
+                .error
+                .errors
+                .iter()
+                .find_map(|item| item.reason.clone())
+                .or(error_body.error.status);
+            (error_body.error.message, reason)
+        }
+        Err(_) => (String::from_utf8_lossy(body).into_owned(), None),
+    };
+
+    let details = match &reason {
+        Some(reason) => format!(
+            "{} returned {} ({}): {}",
+            operation, status, reason, message
+        ),
+        None => format!("{} returned {}: {}", operation, status, message),
+    };
+
+    let throttled = status == StatusCode::TOO_MANY_REQUESTS
+        || reason.as_deref().is_some_and(|reason| {
+            reason == "RESOURCE_EXHAUSTED" || THROTTLE_REASONS.contains(&reason)
+        });
+
+    if throttled {
+        AppError::GooglePlayThrottled {
+            message: details,
+            retry_after_secs: retry_after
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+        }
+    } else if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+        AppError::GooglePlayTokenNotFound(details)
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        AppError::GooglePlayPermissionDenied(details)
+    } else if status.is_server_error() {
+        AppError::GooglePlayUnavailable(details)
+    } else {
+        AppError::GooglePlayApi(details)
+    }
+}
@@ -0,0 +1,371 @@
+pub mod cache;
+pub mod client;
+pub mod scenarios;
+pub mod snapshots;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::auth::GoogleAuth;
+use crate::debug_log::DebugLog;
+use crate::error::{AppError, AppResult};
+use crate::metrics::Metrics;
+use crate::types::{
+    google_play_consumption_state, google_play_product_purchase_state, AcknowledgementState,
+    AutoRenewingPlan, BasePlan, BillingPeriodType, ExternalAccountIdentifiers,
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription, Money,
+    OfferDetails, ProductLineItem, ProductOfferDetails, PurchaseStateContext, SubscriptionLineItem,
+    SubscriptionState,
+};
+
+pub use cache::CachingGooglePlay;
+pub use client::Client;
+pub use scenarios::{MockScenario, ScriptedGooglePlay};
+pub use snapshots::SubscriptionSnapshots;
+
+/// Google Play Developer API calls used to verify and settle purchases
+#[async_trait]
+pub trait GooglePlayApi: Send + Sync {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse>;
+
+    /// Forget cached details of `purchase_token` so the next fetch asks Google
+    ///
+    /// Called when Google reports a change, e.g. through RTDN. A no-op without a cache.
+    async fn invalidate_subscription(&self, _purchase_token: &str) {}
+
+    /// Acknowledge a subscription so Google doesn't refund it, one acknowledged before counts
+    /// as success
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()>;
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2>;
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()>;
+
+    /// Move a subscription's next renewal from `expected_expiry` to `desired_expiry`
+    ///
+    /// Returns the expiry Google settled on.
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>>;
+
+    /// Stop a subscription from renewing, access lasts until the current period ends
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()>;
+
+    /// Every subscription product configured for the package, across all pages
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>>;
+}
+
+/// Android Publisher API client authenticated with the service account
+pub struct LiveGooglePlay {
+    client: Client,
+}
+
+impl LiveGooglePlay {
+    pub fn new(auth: GoogleAuth, debug_log: DebugLog) -> Self {
+        Self {
+            client: Client::new(auth, debug_log),
+        }
+    }
+
+    /// Keep every fetched subscription response in `snapshots`
+    pub fn with_snapshots(mut self, snapshots: SubscriptionSnapshots) -> Self {
+        self.client = self.client.with_snapshots(snapshots);
+        self
+    }
+
+    /// Count failed calls in `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.client = self.client.with_metrics(metrics);
+        self
+    }
+
+    /// Typed client for API calls not covered by `GooglePlayApi`
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for LiveGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        self.client
+            .get_subscription_v2(package_name, purchase_token)
+            .await
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        let subscription_id = subscription_response
+            .line_items
+            .first()
+            .map(|item| item.product_id.as_str())
+            .ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+        self.client
+            .acknowledge(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        self.client
+            .get_product_purchase(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        self.client
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        self.client
+            .defer(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        self.client
+            .cancel(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        let mut products = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let page = self
+                .client
+                .list_subscriptions(package_name, page_token.as_deref())
+                .await?;
+            products.extend(page.subscriptions);
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(products),
+            }
+        }
+    }
+}
+
+/// Account `MockGooglePlay` reports subscriptions for, a principal like the app sets
+pub const MOCK_SUBSCRIPTION_ACCOUNT_ID: &str = "oty7s-jtnn5-rwwll-pmjth-k43dm-f2gkz-bnnfs-a";
+
+/// Account `MockGooglePlay` reports one-time products for
+pub const MOCK_PRODUCT_ACCOUNT_ID: &str = "4xtk4-l3nn5-rwwll-vonsx-elljm-q";
+
+/// Days from now at which `MockGooglePlay` subscriptions expire
+pub const MOCK_SUBSCRIPTION_DAYS: i64 = 30;
+
+/// Fake Google Play that reports every purchase as active and owned by a fixed mock account
+pub struct MockGooglePlay;
+
+impl MockGooglePlay {
+    /// Subscription every mock fetch reports: active, unacknowledged and expiring
+    /// `MOCK_SUBSCRIPTION_DAYS` from now
+    pub fn subscription() -> GooglePlaySubscriptionResponse {
+        let expiry = Utc::now() + chrono::Duration::days(MOCK_SUBSCRIPTION_DAYS);
+        GooglePlaySubscriptionResponse {
+            kind: "androidpublisher#subscriptionPurchaseV2".to_string(),
+            start_time: Some("2023-01-01T00:00:00.000Z".to_string()),
+            region_code: Some("US".to_string()),
+            subscription_state: SubscriptionState::Active,
+            latest_order_id: Some("GPA.0000-0000-0000-00000".to_string()),
+            acknowledgement_state: AcknowledgementState::Pending,
+            line_items: vec![SubscriptionLineItem {
+                product_id: "mock-product-id".to_string(),
+                expiry_time: Some(expiry.to_rfc3339()),
+                auto_renewing: Some(true),
+                price_change_state: Some("PRICE_CHANGE_STATE_APPLIED".to_string()),
+                offer_details: Some(OfferDetails {
+                    base_plan_id: Some("mock-base-plan".to_string()),
+                    offer_id: None,
+                    offer_tags: Vec::new(),
+                }),
+                auto_renewing_plan: Some(AutoRenewingPlan {
+                    auto_renew_enabled: None,
+                    recurring_price: Some(Money {
+                        currency_code: "USD".to_string(),
+                        units: Some("4".to_string()),
+                        nanos: Some(990_000_000),
+                    }),
+                }),
+            }],
+            linked_purchase_token: None,
+            external_account_identifiers: Some(ExternalAccountIdentifiers {
+                external_account_id: Some("mock-external-account-id".to_string()),
+                obfuscated_external_account_id: Some(MOCK_SUBSCRIPTION_ACCOUNT_ID.to_string()),
+                obfuscated_external_profile_id: Some("mock-obfuscated-profile-id".to_string()),
+            }),
+            subscribe_with_google_info: None,
+            test_purchase: None,
+            paused_state_context: None,
+        }
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for MockGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        _package_name: &str,
+        _purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        Ok(Self::subscription())
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        _package_name: &str,
+        _purchase_token: &str,
+        _subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn fetch_product(
+        &self,
+        _package_name: &str,
+        _purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        Ok(GooglePlayProductPurchaseV2 {
+            kind: Some("androidpublisher#productPurchaseV2".to_string()),
+            product_line_item: Some(vec![ProductLineItem {
+                product_id: "mock-product-id".to_string(),
+                product_offer_details: Some(ProductOfferDetails {
+                    quantity: Some(1),
+                    refundable_quantity: None,
+                    consumption_state: Some(
+                        google_play_consumption_state::NOT_CONSUMED.to_string(),
+                    ),
+                }),
+            }]),
+            purchase_state_context: Some(PurchaseStateContext {
+                purchase_state: Some(
+                    google_play_product_purchase_state::PURCHASE_STATE_PURCHASED.to_string(),
+                ),
+            }),
+            order_id: None,
+            obfuscated_external_account_id: Some(MOCK_PRODUCT_ACCOUNT_ID.to_string()),
+            obfuscated_external_profile_id: None,
+            region_code: Some("US".to_string()),
+            purchase_completion_time: Some("2024-11-14T22:13:20Z".to_string()),
+            acknowledgement_state: None,
+        })
+    }
+
+    async fn consume_product(
+        &self,
+        _package_name: &str,
+        _product_id: &str,
+        _purchase_token: &str,
+    ) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn defer_subscription(
+        &self,
+        _package_name: &str,
+        _subscription_id: &str,
+        _purchase_token: &str,
+        _expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        Ok(desired_expiry)
+    }
+
+    async fn cancel_subscription(
+        &self,
+        _package_name: &str,
+        _subscription_id: &str,
+        _purchase_token: &str,
+    ) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn list_subscription_products(
+        &self,
+        _package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        Ok(vec![MonetizationSubscription {
+            product_id: "mock-product-id".to_string(),
+            base_plans: vec![BasePlan {
+                base_plan_id: "mock-base-plan".to_string(),
+                state: Some("ACTIVE".to_string()),
+                auto_renewing_base_plan_type: Some(BillingPeriodType {
+                    billing_period_duration: Some("P1M".to_string()),
+                }),
+                prepaid_base_plan_type: None,
+            }],
+            archived: false,
+        }])
+    }
+}
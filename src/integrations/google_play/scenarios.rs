@@ -0,0 +1,253 @@
+use std::env;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{GooglePlayApi, MockGooglePlay};
+use crate::config::env_interval_secs;
+use crate::error::{AppError, AppResult};
+use crate::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+    SubscriptionState,
+};
+
+/// Account the `account_mismatch` scenario reports purchases for
+pub const MISMATCHED_ACCOUNT_ID: &str = "mock-other-account";
+
+/// How mock Google Play answers, for walking through failure paths by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockScenario {
+    /// Every subscription active and unacknowledged
+    Active,
+    /// Subscriptions expired a day ago
+    Expired,
+    /// Subscriptions canceled but paid up until their expiry
+    Canceled,
+    /// Renewal payments failing, no access
+    OnHold,
+    /// Purchases waiting on a slow payment method
+    Pending,
+    /// Purchases made by an account other than the mock one
+    AccountMismatch,
+    /// Google doesn't know the purchase token
+    NotFound,
+    /// Google asks to retry later
+    Throttled,
+    /// Google answers with server errors
+    Unavailable,
+}
+
+impl MockScenario {
+    pub const ALL: [MockScenario; 9] = [
+        MockScenario::Active,
+        MockScenario::Expired,
+        MockScenario::Canceled,
+        MockScenario::OnHold,
+        MockScenario::Pending,
+        MockScenario::AccountMismatch,
+        MockScenario::NotFound,
+        MockScenario::Throttled,
+        MockScenario::Unavailable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MockScenario::Active => "active",
+            MockScenario::Expired => "expired",
+            MockScenario::Canceled => "canceled",
+            MockScenario::OnHold => "on_hold",
+            MockScenario::Pending => "pending",
+            MockScenario::AccountMismatch => "account_mismatch",
+            MockScenario::NotFound => "not_found",
+            MockScenario::Throttled => "throttled",
+            MockScenario::Unavailable => "unavailable",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.as_str() == value)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(MockScenario::as_str).collect();
+                format!(
+                    "MOCK_GOOGLE_PLAY_SCENARIO must be one of {}, got `{}`",
+                    names.join(", "),
+                    value
+                )
+            })
+    }
+
+    /// Error every call fails with, `None` for scenarios Google answers
+    fn failure(&self, purchase_token: &str) -> Option<AppError> {
+        match self {
+            MockScenario::NotFound => Some(AppError::GooglePlayTokenNotFound(format!(
+                "mock scenario not_found for {}",
+                purchase_token
+            ))),
+            MockScenario::Throttled => Some(AppError::GooglePlayThrottled {
+                message: "mock scenario throttled".to_string(),
+                retry_after_secs: 30,
+            }),
+            MockScenario::Unavailable => Some(AppError::GooglePlayUnavailable(
+                "mock scenario unavailable".to_string(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// `MockGooglePlay` answering as a chosen scenario, with subscriptions expiring a set time
+/// from now
+///
+/// Only used with mock integrations. Lets a local run reach the paths real purchases take
+/// when they lapse, fail or belong to someone else, without a Play Console test track.
+pub struct ScriptedGooglePlay {
+    scenario: MockScenario,
+    /// How far from now active subscriptions expire
+    expiry: chrono::Duration,
+}
+
+impl ScriptedGooglePlay {
+    pub fn new(scenario: MockScenario, expiry: chrono::Duration) -> Self {
+        Self { scenario, expiry }
+    }
+
+    /// Read `MOCK_GOOGLE_PLAY_SCENARIO` (default `active`) and `MOCK_GOOGLE_PLAY_EXPIRY_SECS`
+    /// (default 30 days)
+    pub fn from_env() -> Result<Self, String> {
+        let scenario = match env::var("MOCK_GOOGLE_PLAY_SCENARIO") {
+            Ok(value) => MockScenario::parse(&value)?,
+            Err(_) => MockScenario::Active,
+        };
+        let expiry = env_interval_secs("MOCK_GOOGLE_PLAY_EXPIRY_SECS", 30 * 86400)
+            .map_err(|e| e.to_string())?;
+        let expiry = chrono::Duration::from_std(expiry).map_err(|e| e.to_string())?;
+        Ok(Self::new(scenario, expiry))
+    }
+
+    pub fn scenario(&self) -> MockScenario {
+        self.scenario
+    }
+
+    /// Subscription fetches report under the scenario
+    pub fn subscription(&self) -> GooglePlaySubscriptionResponse {
+        let now = Utc::now();
+        let mut response = MockGooglePlay::subscription();
+        let (state, expiry) = match self.scenario {
+            MockScenario::Expired => (SubscriptionState::Expired, now - chrono::Duration::days(1)),
+            MockScenario::Canceled => (SubscriptionState::Canceled, now + self.expiry),
+            MockScenario::OnHold => (SubscriptionState::OnHold, now - chrono::Duration::days(1)),
+            MockScenario::Pending => (SubscriptionState::Pending, now + self.expiry),
+            _ => (SubscriptionState::Active, now + self.expiry),
+        };
+        response.subscription_state = state;
+        for item in &mut response.line_items {
+            item.expiry_time = Some(expiry.to_rfc3339());
+            item.auto_renewing = Some(state == SubscriptionState::Active);
+        }
+        if self.scenario == MockScenario::AccountMismatch {
+            if let Some(identifiers) = response.external_account_identifiers.as_mut() {
+                identifiers.obfuscated_external_account_id =
+                    Some(MISMATCHED_ACCOUNT_ID.to_string());
+            }
+        }
+        response
+    }
+
+    fn check(&self, purchase_token: &str) -> AppResult<()> {
+        match self.scenario.failure(purchase_token) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for ScriptedGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        _package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        self.check(purchase_token)?;
+        Ok(self.subscription())
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        self.check(purchase_token)?;
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        self.check(purchase_token)?;
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        self.check(purchase_token)?;
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        self.check(purchase_token)?;
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        self.check(purchase_token)?;
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        self.check(package_name)?;
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Read;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use crate::debug_log::redact_personal_data;
+use crate::error::{AppError, AppResult};
+use crate::model::SubscriptionSnapshot;
+
+/// Keeps every subscriptionsv2 body fetched from Google so changed verification logic can be
+/// replayed against real responses
+///
+/// Bodies are stored gzip-compressed with emails and names redacted. Without a database, e.g.
+/// for a client built in tests, nothing is stored.
+#[derive(Clone, Default)]
+pub struct SubscriptionSnapshots {
+    pool: Option<Pool<ConnectionManager<SqliteConnection>>>,
+}
+
+impl SubscriptionSnapshots {
+    pub fn new(pool: Pool<ConnectionManager<SqliteConnection>>) -> Self {
+        Self { pool: Some(pool) }
+    }
+
+    /// Store `body` as fetched now, logging failures so they never fail the fetch
+    pub fn record(&self, purchase_token: &str, body: &[u8]) {
+        let Some(pool) = &self.pool else {
+            return;
+        };
+
+        let now = chrono::Utc::now().naive_utc();
+        let result = pool
+            .get()
+            .map_err(|_| AppError::DatabaseConnection)
+            .and_then(|mut conn| store_snapshot(&mut conn, purchase_token, body, now));
+        if let Err(e) = result {
+            eprintln!("Failed to store subscription snapshot: {}", e);
+        }
+    }
+}
+
+/// Redact and compress a raw subscriptionsv2 body and store it under `purchase_token`
+pub fn store_snapshot(
+    conn: &mut SqliteConnection,
+    purchase_token: &str,
+    body: &[u8],
+    fetched_at: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::subscription_snapshots;
+
+    let mut value: Value = serde_json::from_slice(body)
+        .map_err(|e| AppError::GooglePlayResponseParse(e.to_string()))?;
+    redact_personal_data(&mut value);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, &value)
+        .map_err(|e| AppError::InternalError(e.to_string()))?;
+    let compressed = encoder
+        .finish()
+        .map_err(|e| AppError::InternalError(e.to_string()))?;
+
+    let snapshot = SubscriptionSnapshot {
+        purchase_token: purchase_token.to_string(),
+        fetched_at,
+        body: compressed,
+    };
+    diesel::insert_or_ignore_into(subscription_snapshots::table)
+        .values(&snapshot)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Decompressed body of a stored snapshot
+pub fn snapshot_body(snapshot: &SubscriptionSnapshot) -> AppResult<Value> {
+    let mut json = Vec::new();
+    GzDecoder::new(snapshot.body.as_slice())
+        .read_to_end(&mut json)
+        .map_err(|e| AppError::InternalError(format!("Corrupt subscription snapshot: {}", e)))?;
+    serde_json::from_slice(&json)
+        .map_err(|e| AppError::InternalError(format!("Corrupt subscription snapshot: {}", e)))
+}
+
+/// Snapshots of `token`, oldest first
+pub fn snapshots_for_token(
+    conn: &mut SqliteConnection,
+    token: &str,
+) -> AppResult<Vec<SubscriptionSnapshot>> {
+    use crate::schema::subscription_snapshots::dsl::*;
+
+    Ok(subscription_snapshots
+        .filter(purchase_token.eq(token))
+        .order(fetched_at.asc())
+        .load(conn)?)
+}
+
+/// Delete snapshots fetched before `cutoff`, returns how many were deleted
+pub fn prune_snapshots(conn: &mut SqliteConnection, cutoff: NaiveDateTime) -> AppResult<usize> {
+    use crate::schema::subscription_snapshots::dsl::*;
+
+    Ok(diesel::delete(subscription_snapshots.filter(fetched_at.lt(cutoff))).execute(conn)?)
+}
@@ -0,0 +1,186 @@
+pub mod google_play;
+pub mod play_integrity;
+pub mod push_auth;
+pub mod user_info;
+
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::auth::{GoogleAuth, GooglePublicKey};
+use crate::config::env_interval_secs;
+use crate::debug_log::DebugLog;
+use crate::ic_identity::{AdminIdentity, KeySource};
+use crate::metrics::Metrics;
+use crate::plans::{CreditAllotments, CreditEvent};
+use crate::types::Plan;
+use google_play::{
+    GooglePlayApi, LiveGooglePlay, MockGooglePlay, MockScenario, ScriptedGooglePlay,
+    SubscriptionSnapshots,
+};
+use play_integrity::{LivePlayIntegrity, MockPlayIntegrity, PlayIntegrityApi};
+use push_auth::{GooglePushVerifier, PushVerifier, StaticPushVerifier, MOCK_PUSH_TOKEN};
+use user_info::{IcConfig, LiveUserInfo, MockUserInfo, UserInfoApi};
+
+/// Which implementations of the external services the service talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrationMode {
+    /// Google Play, Play Integrity, the IC and Pub/Sub push auth for real
+    Live,
+    /// In-process fakes that accept every purchase, for local development and tests; push
+    /// requests still need a token
+    Mock,
+}
+
+impl fmt::Display for IntegrationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrationMode::Live => write!(f, "live"),
+            IntegrationMode::Mock => write!(f, "mock"),
+        }
+    }
+}
+
+impl IntegrationMode {
+    /// Read `BILLING_INTEGRATIONS` (`live` or `mock`)
+    ///
+    /// Builds with the `local` feature default to mock, every other build defaults to live.
+    /// Mock integrations are refused when `APP_ENV=production`.
+    pub fn from_env() -> Result<Self, String> {
+        let mode = match env::var("BILLING_INTEGRATIONS") {
+            Ok(value) => match value.as_str() {
+                "live" => IntegrationMode::Live,
+                "mock" => IntegrationMode::Mock,
+                other => {
+                    return Err(format!(
+                        "BILLING_INTEGRATIONS must be `live` or `mock`, got `{}`",
+                        other
+                    ))
+                }
+            },
+            Err(_) if cfg!(feature = "local") => IntegrationMode::Mock,
+            Err(_) => IntegrationMode::Live,
+        };
+
+        let production = env::var("APP_ENV")
+            .map(|app_env| app_env == "production")
+            .unwrap_or(false);
+        if mode == IntegrationMode::Mock && production {
+            return Err("Mock integrations are not allowed when APP_ENV=production".to_string());
+        }
+
+        Ok(mode)
+    }
+}
+
+/// External services selected at startup
+#[derive(Clone)]
+pub struct Integrations {
+    pub mode: IntegrationMode,
+    pub google_play: Arc<dyn GooglePlayApi>,
+    pub user_info: Arc<dyn UserInfoApi>,
+    pub push_verifier: Arc<dyn PushVerifier>,
+    pub play_integrity: Arc<dyn PlayIntegrityApi>,
+    /// Rotatable identity backing the live user info client, absent in mock mode
+    pub admin_identity: Option<AdminIdentity>,
+}
+
+impl Integrations {
+    pub fn mock() -> Self {
+        Self {
+            mode: IntegrationMode::Mock,
+            google_play: Arc::new(MockGooglePlay),
+            user_info: Arc::new(MockUserInfo),
+            push_verifier: Arc::new(StaticPushVerifier::new(MOCK_PUSH_TOKEN)),
+            play_integrity: Arc::new(MockPlayIntegrity),
+            admin_identity: None,
+        }
+    }
+
+    /// Mock integrations with Google Play answering as `MOCK_GOOGLE_PLAY_SCENARIO` says, see
+    /// [`ScriptedGooglePlay::from_env`], and push requests authenticated with `MOCK_PUSH_TOKEN`
+    /// (default [`MOCK_PUSH_TOKEN`])
+    pub fn mock_from_env() -> Result<Self, String> {
+        let google_play = ScriptedGooglePlay::from_env()?;
+        if google_play.scenario() != MockScenario::Active {
+            println!(
+                "Mock Google Play answers as scenario {}",
+                google_play.scenario().as_str()
+            );
+        }
+        let push_token =
+            env::var("MOCK_PUSH_TOKEN").unwrap_or_else(|_| MOCK_PUSH_TOKEN.to_string());
+        Ok(Self {
+            google_play: Arc::new(google_play),
+            push_verifier: Arc::new(StaticPushVerifier::new(&push_token)),
+            ..Self::mock()
+        })
+    }
+
+    /// Build the live clients, failing if any credential is missing or invalid
+    ///
+    /// Google responses are captured in `debug_log` while it is enabled, and subscription
+    /// responses are kept in `snapshots`.
+    pub async fn live(
+        debug_log: DebugLog,
+        snapshots: SubscriptionSnapshots,
+        metrics: Metrics,
+    ) -> Result<Self, String> {
+        let google_auth = GoogleAuth::from_env()
+            .await
+            .map_err(|e| format!("Failed to initialize Google Auth: {}", e))?;
+        println!("Google Auth initialized successfully");
+
+        let key_source = KeySource::from_env()?;
+        let admin_identity = AdminIdentity::load(key_source)
+            .map_err(|e| format!("Unable to create identity: {}", e))?;
+
+        let reload_interval =
+            env_interval_secs("BACKEND_ADMIN_KEY_RELOAD_SECS", 300).map_err(|e| e.to_string())?;
+        admin_identity.spawn_reload_task(reload_interval);
+
+        let ic_config = IcConfig::from_env()?;
+        let admin_ic_agent = ic_config.agent(admin_identity.clone()).await?;
+        println!(
+            "Using user info canister {} on {}",
+            ic_config.user_info_canister_id, ic_config.url
+        );
+
+        let credit_allotments = CreditAllotments::from_env().map_err(|e| e.to_string())?;
+
+        let google_public_key = GooglePublicKey::new()
+            .await
+            .map_err(|e| format!("Failed to fetch google public key: {}", e))?;
+
+        Ok(Self {
+            mode: IntegrationMode::Live,
+            play_integrity: Arc::new(LivePlayIntegrity::new(google_auth.clone())),
+            google_play: Arc::new(
+                LiveGooglePlay::new(google_auth, debug_log)
+                    .with_snapshots(snapshots)
+                    .with_metrics(metrics.clone()),
+            ),
+            user_info: Arc::new(
+                LiveUserInfo::new(
+                    admin_ic_agent,
+                    ic_config.user_info_canister_id,
+                    credit_allotments.credits_for(Plan::Pro, CreditEvent::Purchase, None),
+                )
+                .with_metrics(metrics),
+            ),
+            push_verifier: Arc::new(GooglePushVerifier::new(google_public_key)),
+            admin_identity: Some(admin_identity),
+        })
+    }
+
+    pub async fn from_env(
+        debug_log: DebugLog,
+        snapshots: SubscriptionSnapshots,
+        metrics: Metrics,
+    ) -> Result<Self, String> {
+        match IntegrationMode::from_env()? {
+            IntegrationMode::Live => Self::live(debug_log, snapshots, metrics).await,
+            IntegrationMode::Mock => Self::mock_from_env(),
+        }
+    }
+}
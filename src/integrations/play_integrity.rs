@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::auth::GoogleAuth;
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::client::map_google_error;
+
+pub const PLAY_INTEGRITY_URL: &str = "https://playintegrity.googleapis.com/v1";
+
+const PLAY_INTEGRITY_SCOPE: &str = "https://www.googleapis.com/auth/playintegrity";
+
+/// Integrity tokens older than this are rejected, a fresh one is requested per purchase
+pub const MAX_TOKEN_AGE: chrono::Duration = chrono::Duration::minutes(10);
+
+/// App recognition verdict of a build installed from Google Play
+const PLAY_RECOGNIZED: &str = "PLAY_RECOGNIZED";
+
+/// Device recognition verdicts of a genuine, certified Android device
+const TRUSTED_DEVICE_VERDICTS: &[&str] = &["MEETS_DEVICE_INTEGRITY", "MEETS_STRONG_INTEGRITY"];
+
+/// Decoded Play Integrity token, `tokenPayloadExternal` of `decodeIntegrityToken`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityVerdict {
+    #[serde(default)]
+    pub request_details: RequestDetails,
+    #[serde(default)]
+    pub app_integrity: AppIntegrity,
+    #[serde(default)]
+    pub device_integrity: DeviceIntegrity,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestDetails {
+    pub request_package_name: Option<String>,
+    /// When the app requested the token, in milliseconds since the epoch
+    pub timestamp_millis: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppIntegrity {
+    pub app_recognition_verdict: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceIntegrity {
+    #[serde(default)]
+    pub device_recognition_verdict: Vec<String>,
+}
+
+impl IntegrityVerdict {
+    /// Passing verdict for `package_name` installed from Play on a certified device
+    pub fn trusted(package_name: &str, requested_at: NaiveDateTime) -> Self {
+        Self {
+            request_details: RequestDetails {
+                request_package_name: Some(package_name.to_string()),
+                timestamp_millis: Some(requested_at.and_utc().timestamp_millis().to_string()),
+            },
+            app_integrity: AppIntegrity {
+                app_recognition_verdict: Some(PLAY_RECOGNIZED.to_string()),
+            },
+            device_integrity: DeviceIntegrity {
+                device_recognition_verdict: vec![TRUSTED_DEVICE_VERDICTS[0].to_string()],
+            },
+        }
+    }
+
+    /// Reject verdicts for another package, stale tokens, sideloaded builds and untrusted devices
+    pub fn check(&self, package_name: &str, now: NaiveDateTime) -> AppResult<()> {
+        if self.request_details.request_package_name.as_deref() != Some(package_name) {
+            return Err(AppError::IntegrityCheckFailed(format!(
+                "token was requested for another package than {}",
+                package_name
+            )));
+        }
+
+        let requested_at = self
+            .request_details
+            .timestamp_millis
+            .as_deref()
+            .and_then(|millis| millis.parse::<i64>().ok())
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|requested_at| requested_at.naive_utc())
+            .ok_or_else(|| {
+                AppError::IntegrityCheckFailed("token has no request timestamp".to_string())
+            })?;
+        if now - requested_at > MAX_TOKEN_AGE {
+            return Err(AppError::IntegrityCheckFailed(
+                "token is too old, request a new one".to_string(),
+            ));
+        }
+
+        let app_verdict = self.app_integrity.app_recognition_verdict.as_deref();
+        if app_verdict != Some(PLAY_RECOGNIZED) {
+            return Err(AppError::IntegrityCheckFailed(format!(
+                "app is not recognized by Google Play ({})",
+                app_verdict.unwrap_or("UNEVALUATED")
+            )));
+        }
+
+        let trusted_device = self
+            .device_integrity
+            .device_recognition_verdict
+            .iter()
+            .any(|verdict| TRUSTED_DEVICE_VERDICTS.contains(&verdict.as_str()));
+        if !trusted_device {
+            return Err(AppError::IntegrityCheckFailed(
+                "device does not meet device integrity".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes Play Integrity tokens the app attaches to purchases
+#[async_trait]
+pub trait PlayIntegrityApi: Send + Sync {
+    async fn decode_integrity_token(
+        &self,
+        package_name: &str,
+        integrity_token: &str,
+    ) -> AppResult<IntegrityVerdict>;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DecodeIntegrityTokenResponse {
+    token_payload_external: IntegrityVerdict,
+}
+
+/// Play Integrity API client, authenticated with the service's Google credentials
+pub struct LivePlayIntegrity {
+    http: reqwest::Client,
+    base_url: String,
+    google_auth: GoogleAuth,
+}
+
+impl LivePlayIntegrity {
+    pub fn new(google_auth: GoogleAuth) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: PLAY_INTEGRITY_URL.to_string(),
+            google_auth,
+        }
+    }
+
+    /// Point the client at another API root, e.g. a local fake in tests
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+}
+
+#[async_trait]
+impl PlayIntegrityApi for LivePlayIntegrity {
+    async fn decode_integrity_token(
+        &self,
+        package_name: &str,
+        integrity_token: &str,
+    ) -> AppResult<IntegrityVerdict> {
+        let access_token = self
+            .google_auth
+            .get_token(&[PLAY_INTEGRITY_SCOPE])
+            .await
+            .map_err(|e| AppError::AccessTokenFailed(e.to_string()))?;
+
+        let url = format!("{}/{}:decodeIntegrityToken", self.base_url, package_name);
+        let response = self
+            .http
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(&json!({ "integrityToken": integrity_token }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await?;
+
+        // Google answers 400 for tokens it can't decode, which a tampered client would send
+        if status == reqwest::StatusCode::BAD_REQUEST {
+            return Err(AppError::IntegrityCheckFailed(
+                "Google could not decode the integrity token".to_string(),
+            ));
+        }
+        if !status.is_success() {
+            return Err(map_google_error(
+                "decodeIntegrityToken",
+                status,
+                retry_after.as_deref(),
+                &body,
+            ));
+        }
+
+        let decoded: DecodeIntegrityTokenResponse = serde_json::from_slice(&body)
+            .map_err(|e| AppError::GooglePlayResponseParse(e.to_string()))?;
+        Ok(decoded.token_payload_external)
+    }
+}
+
+/// Vouches for every token, integrity tokens can't be minted locally
+pub struct MockPlayIntegrity;
+
+#[async_trait]
+impl PlayIntegrityApi for MockPlayIntegrity {
+    async fn decode_integrity_token(
+        &self,
+        package_name: &str,
+        _integrity_token: &str,
+    ) -> AppResult<IntegrityVerdict> {
+        Ok(IntegrityVerdict::trusted(
+            package_name,
+            chrono::Utc::now().naive_utc(),
+        ))
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use ic_agent::agent::RejectCode;
+use ic_agent::export::Principal;
+use ic_agent::{Agent, AgentError, Identity};
+use yral_canisters_client::{
+    ic::USER_INFO_SERVICE_ID,
+    user_info_service::{Result_, SubscriptionPlan, UserInfoService, YralProSubscription},
+};
+
+use crate::cache::{Cache, MemoryCache};
+use crate::config::{env_number, ConfigError};
+use crate::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use crate::error::{AppError, AppResult};
+use crate::metrics::{Metrics, IC_CALLS_TOTAL, USER_PLAN_CACHE_TOTAL};
+use crate::types::Plan;
+
+/// Plan lookups of one batch in flight on the canister at a time
+pub const PLAN_LOOKUP_CONCURRENCY: usize = 8;
+
+/// IC mainnet API boundary nodes
+pub const IC_MAINNET_URL: &str = "https://ic0.app";
+
+/// Network and user info canister the live client talks to
+///
+/// Lets staging canisters and a local dfx replica run the same binary as production.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcConfig {
+    /// Replica or boundary node the agent calls
+    pub url: String,
+    pub user_info_canister_id: Principal,
+}
+
+impl IcConfig {
+    /// `url` with the canister `user_info_canister_id` names, mainnet and the production
+    /// canister when absent
+    pub fn new(url: Option<&str>, user_info_canister_id: Option<&str>) -> Result<Self, String> {
+        let user_info_canister_id = match user_info_canister_id {
+            Some(text) => Principal::from_text(text).map_err(|e| {
+                format!("USER_INFO_CANISTER_ID `{}` is not a principal: {}", text, e)
+            })?,
+            None => USER_INFO_SERVICE_ID,
+        };
+        Ok(Self {
+            url: url
+                .unwrap_or(IC_MAINNET_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            user_info_canister_id,
+        })
+    }
+
+    /// Read `IC_URL` and `USER_INFO_CANISTER_ID`
+    pub fn from_env() -> Result<Self, String> {
+        let url = env::var("IC_URL").ok();
+        let canister_id = env::var("USER_INFO_CANISTER_ID").ok();
+        Self::new(url.as_deref(), canister_id.as_deref())
+    }
+
+    pub fn is_mainnet(&self) -> bool {
+        self.url == IC_MAINNET_URL
+    }
+
+    /// Agent calling as `identity`
+    ///
+    /// Off mainnet the replica's root key is fetched and trusted, as local dfx replicas have
+    /// their own.
+    pub async fn agent(&self, identity: impl Identity + 'static) -> Result<Agent, String> {
+        let agent = Agent::builder()
+            .with_url(&self.url)
+            .with_identity(identity)
+            .build()
+            .map_err(|e| format!("Failed to create IC agent for {}: {}", self.url, e))?;
+        if !self.is_mainnet() {
+            agent
+                .fetch_root_key()
+                .await
+                .map_err(|e| format!("Failed to fetch the root key of {}: {}", self.url, e))?;
+        }
+        Ok(agent)
+    }
+}
+
+/// Plan and credit changes on the user info canister
+#[async_trait]
+pub trait UserInfoApi: Send + Sync {
+    /// Move the user to the Pro plan, a no-op for products other than Pro
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()>;
+
+    /// Keep the user on the Pro plan for a renewed period, leaving their credits as they are
+    ///
+    /// The period's credits come from the renewal top-up, which adds them once per order.
+    async fn renew_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        if self.get_plan(user_id).await? == Plan::Pro {
+            return Ok(());
+        }
+        self.grant_pro_plan(product_id, user_id).await
+    }
+
+    /// Move the user back to the Free plan
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()>;
+
+    /// Plan the canister has for the user
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan>;
+
+    /// Plans of several users, looked up a few at a time
+    async fn get_plans(&self, user_ids: &[String]) -> AppResult<HashMap<String, Plan>> {
+        stream::iter(user_ids.iter().cloned())
+            .map(|user_id| async move {
+                let plan = self.get_plan(&user_id).await?;
+                Ok::<_, AppError>((user_id, plan))
+            })
+            .buffer_unordered(PLAN_LOOKUP_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Credits the user has left on the canister, none on the Free plan
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32>;
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()>;
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()>;
+}
+
+fn parse_user_principal(user_id: &str) -> AppResult<Principal> {
+    Principal::from_text(user_id).map_err(|e| AppError::InternalError(e.to_string()))
+}
+
+/// IC error codes of calls to a canister that is stopping or stopped, e.g. during an upgrade
+const STOPPED_CANISTER_ERROR_CODES: &[&str] = &["IC0508", "IC0509"];
+
+/// Turn an error the user info service reported in its own words into the matching
+/// `AppError`, if it is one callers handle
+///
+/// The service answers with free text, so it is matched loosely.
+pub fn decode_canister_message(user_id: &str, message: &str) -> Option<AppError> {
+    let message = message.to_lowercase();
+    if message.contains("not found")
+        || message.contains("not registered")
+        || message.contains("does not exist")
+    {
+        Some(AppError::UserNotFound(user_id.to_string()))
+    } else if message.contains("already") && message.contains("pro") {
+        Some(AppError::AlreadyPro(user_id.to_string()))
+    } else {
+        None
+    }
+}
+
+/// Turn a reject of `method` into the matching `AppError`
+///
+/// Stopped canisters and transient system rejects may succeed when retried, anything else
+/// the canister refused is final unless it is an error callers handle.
+pub fn decode_reject(
+    method: &str,
+    user_id: &str,
+    reject_code: RejectCode,
+    error_code: Option<&str>,
+    message: &str,
+) -> AppError {
+    let details = format!("{} rejected with {:?}: {}", method, reject_code, message);
+    if error_code.is_some_and(|code| STOPPED_CANISTER_ERROR_CODES.contains(&code)) {
+        AppError::CanisterStopped(details)
+    } else if reject_code == RejectCode::SysTransient {
+        AppError::ServiceAccessFailed(details)
+    } else {
+        decode_canister_message(user_id, message)
+            .unwrap_or_else(|| AppError::CanisterRejected(details))
+    }
+}
+
+/// The `AppError` of a failed call of `method`, `unanswered` for calls the canister never
+/// answered, e.g. because the IC couldn't be reached
+fn canister_error(
+    method: &str,
+    user_id: &str,
+    error: AgentError,
+    unanswered: impl FnOnce(String) -> AppError,
+) -> AppError {
+    match &error {
+        AgentError::CertifiedReject { reject, .. }
+        | AgentError::UncertifiedReject { reject, .. } => decode_reject(
+            method,
+            user_id,
+            reject.reject_code,
+            reject.error_code.as_deref(),
+            &reject.reject_message,
+        ),
+        _ => unanswered(error.to_string()),
+    }
+}
+
+fn check_canister_result(user_id: &str, result: Result_) -> AppResult<()> {
+    match result {
+        Result_::Ok => Ok(()),
+        Result_::Err(e) => Err(decode_canister_message(user_id, &e)
+            .unwrap_or_else(|| AppError::BadRequest(format!("Canister returned error: {}", e)))),
+    }
+}
+
+/// How a failed canister call ended, the `result` label of `IC_CALLS_TOTAL`
+pub fn ic_error_kind(error: &AppError) -> &'static str {
+    match error {
+        AppError::UserNotFound(_) => "user_not_found",
+        AppError::AlreadyPro(_) => "already_pro",
+        AppError::CanisterStopped(_) => "canister_stopped",
+        AppError::CanisterRejected(_) | AppError::BadRequest(_) => "rejected",
+        e if e.is_retryable() => "transient",
+        _ => "error",
+    }
+}
+
+/// User info canister client acting as the backend admin
+pub struct LiveUserInfo {
+    agent: Agent,
+    canister_id: Principal,
+    /// Video credits a Pro grant starts the user with
+    credit_allotment: u32,
+    metrics: Metrics,
+}
+
+impl LiveUserInfo {
+    /// Client of the canister `canister_id`, see [`IcConfig`]
+    pub fn new(agent: Agent, canister_id: Principal, credit_allotment: u32) -> Self {
+        Self {
+            agent,
+            canister_id,
+            credit_allotment,
+            metrics: Metrics::new(),
+        }
+    }
+
+    fn service(&self) -> UserInfoService<'_> {
+        UserInfoService(self.canister_id, &self.agent)
+    }
+
+    /// Count calls in `metrics` instead of a registry of the client's own
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Run a call of `method` for `user_id`, logging how long it took and how it ended
+    async fn traced<T>(
+        &self,
+        method: &str,
+        user_id: &str,
+        call: impl Future<Output = AppResult<T>>,
+    ) -> AppResult<T> {
+        let started = Instant::now();
+        let result = call.await;
+        let elapsed_ms = started.elapsed().as_millis();
+
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(e) => ic_error_kind(e),
+        };
+        self.metrics.inc_counter(
+            IC_CALLS_TOTAL,
+            &[("method", method), ("result", outcome)],
+            1,
+        );
+        match &result {
+            Ok(_) => println!(
+                "IC {} for {} succeeded in {}ms",
+                method, user_id, elapsed_ms
+            ),
+            Err(e) => eprintln!(
+                "IC {} for {} failed in {}ms ({}, retryable: {}): {}",
+                method,
+                user_id,
+                elapsed_ms,
+                outcome,
+                e.is_retryable(),
+                e
+            ),
+        }
+        result
+    }
+
+    async fn subscription_plan(&self, user_id: &str) -> AppResult<SubscriptionPlan> {
+        let user_principal = parse_user_principal(user_id)?;
+        let method = "get_subscription_plan";
+
+        self.traced(method, user_id, async {
+            self.service()
+                .get_subscription_plan(user_principal)
+                .await
+                .map_err(|e| canister_error(method, user_id, e, AppError::ServiceAccessFailed))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl UserInfoApi for LiveUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        if product_id != YRAL_PRO_PLAN_PRODUCT_ID {
+            return Ok(());
+        }
+
+        let user_principal = parse_user_principal(user_id)?;
+        let method = "change_subscription_plan";
+
+        self.traced(method, user_id, async {
+            self.service()
+                .change_subscription_plan(
+                    user_principal,
+                    SubscriptionPlan::Pro(YralProSubscription {
+                        total_video_credits_alloted: self.credit_allotment,
+                        free_video_credits_left: self.credit_allotment, //default value
+                    }),
+                )
+                .await
+                .map_err(|e| canister_error(method, user_id, e, AppError::ServiceAccessFailed))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn renew_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        if product_id != YRAL_PRO_PLAN_PRODUCT_ID {
+            return Ok(());
+        }
+        if let SubscriptionPlan::Pro(_) = self.subscription_plan(user_id).await? {
+            return Ok(());
+        }
+
+        let user_principal = parse_user_principal(user_id)?;
+        let method = "change_subscription_plan";
+
+        // Back from Free with nothing left, the renewal top-up adds the period's credits
+        self.traced(method, user_id, async {
+            self.service()
+                .change_subscription_plan(
+                    user_principal,
+                    SubscriptionPlan::Pro(YralProSubscription {
+                        total_video_credits_alloted: self.credit_allotment,
+                        free_video_credits_left: 0,
+                    }),
+                )
+                .await
+                .map_err(|e| canister_error(method, user_id, e, AppError::ServiceAccessFailed))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        let user_principal = parse_user_principal(user_id)?;
+        let method = "change_subscription_plan";
+
+        self.traced(method, user_id, async {
+            self.service()
+                .change_subscription_plan(user_principal, SubscriptionPlan::Free)
+                .await
+                .map_err(|e| canister_error(method, user_id, e, AppError::ServiceAccessFailed))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        let plan = self.subscription_plan(user_id).await?;
+
+        Ok(match plan {
+            SubscriptionPlan::Free => Plan::Free,
+            SubscriptionPlan::Pro(_) => Plan::Pro,
+        })
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        let plan = self.subscription_plan(user_id).await?;
+
+        Ok(match plan {
+            SubscriptionPlan::Free => 0,
+            SubscriptionPlan::Pro(subscription) => subscription.free_video_credits_left,
+        })
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        let user_id = user_principal.to_text();
+        let method = "remove_pro_plan_free_video_credits";
+
+        self.traced(method, &user_id, async {
+            let result = self
+                .service()
+                .remove_pro_plan_free_video_credits(user_principal, amount)
+                .await
+                .map_err(|e| {
+                    canister_error(method, &user_id, e, |e| {
+                        AppError::NetworkError(format!("Failed to deduct credits: {}", e))
+                    })
+                })?;
+
+            check_canister_result(&user_id, result)
+        })
+        .await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        let user_id = user_principal.to_text();
+        let method = "add_pro_plan_free_video_credits";
+
+        self.traced(method, &user_id, async {
+            let result = self
+                .service()
+                .add_pro_plan_free_video_credits(user_principal, amount)
+                .await
+                .map_err(|e| {
+                    canister_error(method, &user_id, e, |e| {
+                        AppError::NetworkError(format!("Failed to increment credits: {}", e))
+                    })
+                })?;
+
+            check_canister_result(&user_id, result)
+        })
+        .await
+    }
+}
+
+/// Logs plan and credit changes instead of calling the canister
+pub struct MockUserInfo;
+
+#[async_trait]
+impl UserInfoApi for MockUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        println!("MOCK: Granting {} access to user {}", product_id, user_id);
+        Ok(())
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        println!("MOCK: Revoking pro plan access from user {}", user_id);
+        Ok(())
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        println!("MOCK: Reporting the free plan for user {}", user_id);
+        Ok(Plan::Free)
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        println!("MOCK: Reporting no credits for user {}", user_id);
+        Ok(0)
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        println!(
+            "MOCK: Deducting {} credits from user {}",
+            amount, user_principal
+        );
+        Ok(())
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        println!("MOCK: Adding {} credits to user {}", amount, user_principal);
+        Ok(())
+    }
+}
+
+fn plan_cache_key(user_id: &str) -> String {
+    format!("user_info:plan:{}", user_id)
+}
+
+/// Caches plan lookups per user, dropping a user's entry whenever we change their plan
+///
+/// Entitlement checks and admin listings would otherwise make one canister query call per
+/// user. Plans changed outside this service show up once the TTL passes. Failed lookups are
+/// not cached, and a cache that can't be reached is treated as a miss.
+pub struct CachingUserInfo {
+    inner: Arc<dyn UserInfoApi>,
+    ttl: Duration,
+    metrics: Metrics,
+    cache: Arc<dyn Cache>,
+}
+
+impl CachingUserInfo {
+    pub fn new(inner: Arc<dyn UserInfoApi>, ttl: Duration, metrics: Metrics) -> Self {
+        Self {
+            inner,
+            ttl,
+            metrics,
+            cache: Arc::new(MemoryCache::default()),
+        }
+    }
+
+    /// Keep plans in `cache` instead of this process
+    pub fn with_cache(mut self, cache: Arc<dyn Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Wrap `inner` with the TTL from `USER_PLAN_CACHE_TTL_SECS` (default 300), keeping plans
+    /// in `cache`
+    ///
+    /// Zero turns caching off and returns `inner` unchanged.
+    pub fn wrap_from_env(
+        inner: Arc<dyn UserInfoApi>,
+        metrics: Metrics,
+        cache: Arc<dyn Cache>,
+    ) -> Result<Arc<dyn UserInfoApi>, ConfigError> {
+        match env_number("USER_PLAN_CACHE_TTL_SECS", 300)? {
+            0 => Ok(inner),
+            secs => Ok(Arc::new(
+                Self::new(inner, Duration::from_secs(secs), metrics).with_cache(cache),
+            )),
+        }
+    }
+
+    async fn cached(&self, user_id: &str) -> Option<Plan> {
+        let plan = match self.cache.get(&plan_cache_key(user_id)).await {
+            Ok(cached) => cached.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                eprintln!("User plan cache lookup failed: {}", e);
+                None
+            }
+        };
+        self.metrics.inc_counter(
+            USER_PLAN_CACHE_TOTAL,
+            &[("result", if plan.is_some() { "hit" } else { "miss" })],
+            1,
+        );
+        plan
+    }
+
+    async fn store(&self, user_id: &str, plan: Plan) {
+        let Ok(value) = serde_json::to_string(&plan) else {
+            return;
+        };
+        if let Err(e) = self
+            .cache
+            .set(&plan_cache_key(user_id), &value, self.ttl)
+            .await
+        {
+            eprintln!("Failed to cache user plan: {}", e);
+        }
+    }
+
+    async fn invalidate(&self, user_id: &str) {
+        if let Err(e) = self.cache.delete(&plan_cache_key(user_id)).await {
+            eprintln!("Failed to drop cached user plan: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl UserInfoApi for CachingUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        let result = self.inner.grant_pro_plan(product_id, user_id).await;
+        // Even a failed call may have reached the canister
+        self.invalidate(user_id).await;
+        result
+    }
+
+    async fn renew_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        let result = self.inner.renew_pro_plan(product_id, user_id).await;
+        self.invalidate(user_id).await;
+        result
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        let result = self.inner.revoke_pro_plan(user_id).await;
+        self.invalidate(user_id).await;
+        result
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        if let Some(plan) = self.cached(user_id).await {
+            return Ok(plan);
+        }
+
+        let plan = self.inner.get_plan(user_id).await?;
+        self.store(user_id, plan).await;
+        Ok(plan)
+    }
+
+    async fn get_plans(&self, user_ids: &[String]) -> AppResult<HashMap<String, Plan>> {
+        let mut plans = HashMap::new();
+        let mut missing = Vec::new();
+        for user_id in user_ids {
+            match self.cached(user_id).await {
+                Some(plan) => {
+                    plans.insert(user_id.clone(), plan);
+                }
+                None => missing.push(user_id.clone()),
+            }
+        }
+
+        // Only the users not cached go to the canister, in one batch
+        if !missing.is_empty() {
+            for (user_id, plan) in self.inner.get_plans(&missing).await? {
+                self.store(&user_id, plan).await;
+                plans.insert(user_id, plan);
+            }
+        }
+        Ok(plans)
+    }
+
+    // Balances change with every generation, not worth caching
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        self.inner.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        self.inner.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        self.inner.increment_credits(user_principal, amount).await
+    }
+}
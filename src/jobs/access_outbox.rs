@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::ConfigError;
+use crate::error::{AppError, AppResult};
+use crate::integrations::user_info::UserInfoApi;
+use crate::metrics::{Metrics, ACCESS_OUTBOX_APPLIED_TOTAL, ACCESS_OUTBOX_ERRORS_TOTAL};
+use crate::model::AccessOutboxEntry;
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::{OutboxAction, OutboxStatus};
+
+/// Attempts after which an entry is marked `Failed` and left for manual follow-up
+pub const MAX_OUTBOX_ATTEMPTS: i32 = 10;
+
+/// Queue a plan change on the IC to be applied by the outbox worker
+pub fn enqueue_access_change(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    action: OutboxAction,
+    product_id: Option<&str>,
+    purchase_token: Option<&str>,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::access_outbox;
+
+    let entry = AccessOutboxEntry::new(
+        user_id.to_string(),
+        action,
+        product_id.map(str::to_string),
+        purchase_token.map(str::to_string),
+        now,
+    );
+
+    diesel::insert_into(access_outbox::table)
+        .values(&entry)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Queue a plan change on the IC that the outbox worker applies once `run_after` has passed
+pub fn schedule_access_change(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    action: OutboxAction,
+    product_id: Option<&str>,
+    purchase_token: &str,
+    run_after: NaiveDateTime,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::access_outbox;
+
+    let mut entry = AccessOutboxEntry::new(
+        user_id.to_string(),
+        action,
+        product_id.map(str::to_string),
+        Some(purchase_token.to_string()),
+        now,
+    );
+    entry.run_after = Some(run_after);
+
+    diesel::insert_into(access_outbox::table)
+        .values(&entry)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Whether an unapplied `action` is already queued for `purchase_token`
+pub fn has_pending_access_change(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    action_param: OutboxAction,
+) -> AppResult<bool> {
+    use crate::schema::access_outbox::dsl::*;
+
+    Ok(diesel::select(diesel::dsl::exists(
+        access_outbox
+            .filter(purchase_token.eq(purchase_token_param))
+            .filter(action.eq(action_param))
+            .filter(status.eq(OutboxStatus::Pending)),
+    ))
+    .get_result(conn)?)
+}
+
+/// Drop scheduled changes for `purchase_token` that haven't been applied yet
+///
+/// Returns the number of entries dropped.
+pub fn cancel_scheduled_access_changes(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+) -> AppResult<usize> {
+    use crate::schema::access_outbox::dsl::*;
+
+    Ok(diesel::delete(
+        access_outbox
+            .filter(purchase_token.eq(purchase_token_param))
+            .filter(status.eq(OutboxStatus::Pending))
+            .filter(run_after.is_not_null()),
+    )
+    .execute(conn)?)
+}
+
+async fn apply_entry(user_info: &dyn UserInfoApi, entry: &AccessOutboxEntry) -> AppResult<()> {
+    match entry.action {
+        OutboxAction::GrantPro => {
+            let product_id = entry.product_id.as_deref().ok_or_else(|| {
+                AppError::InternalError(format!("Outbox grant {} has no product id", entry.id))
+            })?;
+            match user_info.grant_pro_plan(product_id, &entry.user_id).await {
+                // The user ending up on Pro is all the grant asked for
+                Err(AppError::AlreadyPro(_)) => Ok(()),
+                result => result,
+            }
+        }
+        OutboxAction::RevokePro => user_info.revoke_pro_plan(&entry.user_id).await,
+    }
+}
+
+/// Apply up to `batch_size` pending outbox entries that are due, oldest first
+///
+/// Entries for a user are applied in order, so once one fails the user's later entries wait
+/// for the next run. Scheduled entries wait until their `run_after`. Errors that won't go away
+/// on a retry, e.g. an unknown user, mark the entry `Failed` right away. Returns the number of
+/// entries applied.
+pub async fn drain_access_outbox(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+    batch_size: i64,
+) -> AppResult<usize> {
+    use crate::schema::access_outbox::dsl::*;
+
+    let pending: Vec<AccessOutboxEntry> = access_outbox
+        .filter(status.eq(OutboxStatus::Pending))
+        .filter(run_after.is_null().or(run_after.le(clock.now_naive())))
+        .order(created_at.asc())
+        // A pause queues its revoke and restore together, the revoke runs first
+        .then_order_by(run_after.asc())
+        .limit(batch_size)
+        .load(conn)?;
+
+    let mut blocked_users = HashSet::new();
+    let mut applied = 0;
+    for entry in pending {
+        if blocked_users.contains(&entry.user_id) {
+            continue;
+        }
+
+        let action_label = format!("{:?}", entry.action);
+        let labels = [("action", action_label.as_str())];
+
+        match apply_entry(user_info, &entry).await {
+            Ok(()) => {
+                diesel::update(access_outbox.filter(id.eq(&entry.id)))
+                    .set((
+                        status.eq(OutboxStatus::Done),
+                        attempts.eq(entry.attempts + 1),
+                        updated_at.eq(clock.now_naive()),
+                    ))
+                    .execute(conn)?;
+                metrics.inc_counter(ACCESS_OUTBOX_APPLIED_TOTAL, &labels, 1);
+                applied += 1;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to apply outbox entry {} for user {}: {}",
+                    entry.id, entry.user_id, e
+                );
+                let next_status = if !e.is_retryable() || entry.attempts + 1 >= MAX_OUTBOX_ATTEMPTS
+                {
+                    OutboxStatus::Failed
+                } else {
+                    OutboxStatus::Pending
+                };
+                diesel::update(access_outbox.filter(id.eq(&entry.id)))
+                    .set((
+                        status.eq(next_status),
+                        attempts.eq(entry.attempts + 1),
+                        last_error.eq(Some(e.to_string())),
+                        updated_at.eq(clock.now_naive()),
+                    ))
+                    .execute(conn)?;
+                metrics.inc_counter(ACCESS_OUTBOX_ERRORS_TOTAL, &labels, 1);
+                blocked_users.insert(entry.user_id.clone());
+            }
+        }
+    }
+
+    Ok(applied)
+}
+
+/// Run `drain_access_outbox` on its schedule
+///
+/// Scheduled by `ACCESS_OUTBOX_SCHEDULE`, or every `ACCESS_OUTBOX_INTERVAL_SECS` (default 30).
+pub fn register_access_outbox_worker(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env("ACCESS_OUTBOX_SCHEDULE", "ACCESS_OUTBOX_INTERVAL_SECS", 30)?;
+
+    scheduler.register("access_outbox", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let applied = drain_access_outbox(
+            &mut conn,
+            app_state.user_info.as_ref(),
+            app_state.clock.as_ref(),
+            &app_state.metrics,
+            100,
+        )
+        .await?;
+        Ok((applied > 0).then(|| format!("Applied {} access outbox entries", applied)))
+    });
+
+    Ok(())
+}
+
+/// Pending entries are due from their `run_after`, or from when they were queued
+pub struct AccessOutboxQueue;
+
+impl QueueStats for AccessOutboxQueue {
+    fn name(&self) -> &'static str {
+        "access_outbox"
+    }
+
+    fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::minutes(15)
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        now: NaiveDateTime,
+    ) -> AppResult<QueueSnapshot> {
+        use crate::schema::access_outbox::dsl::*;
+        use diesel::dsl::min;
+
+        let count_with = |conn: &mut SqliteConnection, wanted: OutboxStatus| -> AppResult<i64> {
+            Ok(access_outbox
+                .filter(status.eq(wanted))
+                .count()
+                .get_result(conn)?)
+        };
+        let pending = count_with(conn, OutboxStatus::Pending)?;
+        let failed = count_with(conn, OutboxStatus::Failed)?;
+
+        let oldest_queued: Option<NaiveDateTime> = access_outbox
+            .filter(status.eq(OutboxStatus::Pending))
+            .filter(run_after.is_null())
+            .select(min(created_at))
+            .first(conn)?;
+        let oldest_scheduled: Option<NaiveDateTime> = access_outbox
+            .filter(status.eq(OutboxStatus::Pending))
+            .filter(run_after.le(now))
+            .select(min(run_after))
+            .first(conn)?;
+
+        let processed: i64 = access_outbox
+            .filter(status.eq(OutboxStatus::Done))
+            .filter(updated_at.ge(now - chrono::Duration::hours(1)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(QueueSnapshot {
+            pending,
+            failed: Some(failed),
+            oldest_due_at: oldest_queued.into_iter().chain(oldest_scheduled).min(),
+            processed_last_hour: Some(processed),
+        })
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::{env_number, ConfigError};
+use crate::db::token_versions::{current_token, ensure_unchanged};
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::metrics::{
+    Metrics, ACKNOWLEDGMENT_DEADLINE_AT_RISK_TOKENS, ACKNOWLEDGMENT_RETRIES_TOTAL,
+    UNACKNOWLEDGED_TOKENS,
+};
+use crate::model::PurchaseToken;
+use crate::notifier::{BillingEvent, Notifier};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::{AcknowledgementState, GooglePlaySubscriptionResponse, PurchaseTokenStatus};
+
+/// Google voids purchases that are not acknowledged within this many days
+pub const ACKNOWLEDGMENT_DEADLINE_DAYS: i64 = 3;
+
+/// Whether we acknowledge a package's purchases with Google or leave it to the app
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AckStrategy {
+    /// Acknowledge every purchase, even one Google already reports acknowledged
+    Always,
+    /// Acknowledge purchases Google reports as pending acknowledgment
+    #[default]
+    OnlyIfPending,
+    /// The app acknowledges, we only record it once Google reports it done
+    Never,
+}
+
+impl AckStrategy {
+    pub fn should_acknowledge(self, state: AcknowledgementState) -> bool {
+        match self {
+            AckStrategy::Always => true,
+            AckStrategy::OnlyIfPending => state == AcknowledgementState::Pending,
+            AckStrategy::Never => false,
+        }
+    }
+}
+
+impl FromStr for AckStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "always" => Ok(AckStrategy::Always),
+            "only_if_pending" | "only-if-pending" => Ok(AckStrategy::OnlyIfPending),
+            "never" => Ok(AckStrategy::Never),
+            other => Err(format!(
+                "unknown strategy `{}`, expected always, only_if_pending or never",
+                other
+            )),
+        }
+    }
+}
+
+/// Acknowledgment strategy of each package
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AckStrategies {
+    default: AckStrategy,
+    packages: HashMap<String, AckStrategy>,
+}
+
+impl AckStrategies {
+    pub fn new(default: AckStrategy, packages: HashMap<String, AckStrategy>) -> Self {
+        Self { default, packages }
+    }
+
+    /// `ACK_STRATEGY` for every package (default `only_if_pending`), overridden per package by
+    /// `ACK_STRATEGIES`, e.g. `com.yral.android=never,com.yral.lite=always`
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let default = match env::var("ACK_STRATEGY") {
+            Ok(value) => value.parse().map_err(ConfigError::AckStrategies)?,
+            Err(_) => AckStrategy::default(),
+        };
+        let packages = match env::var("ACK_STRATEGIES") {
+            Ok(value) => parse_package_strategies(&value).map_err(ConfigError::AckStrategies)?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(Self::new(default, packages))
+    }
+
+    pub fn for_package(&self, package_name: &str) -> AckStrategy {
+        self.packages
+            .get(package_name)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Comma-separated `package=strategy` pairs
+pub fn parse_package_strategies(value: &str) -> Result<HashMap<String, AckStrategy>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (package, strategy) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("`{}` is not package=strategy", entry))?;
+            Ok((package.trim().to_string(), strategy.parse()?))
+        })
+        .collect()
+}
+
+/// Acknowledge a stored purchase as its package's strategy says and record when it happened
+///
+/// Returns whether the purchase is acknowledged. One left to the app is recorded once Google
+/// reports it acknowledged, until then the monitor job keeps checking on it.
+pub async fn acknowledge_purchase(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    package_name: &str,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let state = subscription_response.acknowledgement_state;
+    let acknowledged = if ack_strategies
+        .for_package(package_name)
+        .should_acknowledge(state)
+    {
+        google_play
+            .acknowledge_subscription(package_name, purchase_token_param, subscription_response)
+            .await?;
+        true
+    } else {
+        state != AcknowledgementState::Pending
+    };
+
+    if !acknowledged {
+        return Ok(false);
+    }
+    if let Some(token) = current_token(conn, purchase_token_param)? {
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set(acknowledged_at.eq(Some(now)))
+        .execute(conn)?;
+        ensure_unchanged(updated, &token)?;
+    }
+
+    Ok(true)
+}
+
+/// Outcome of one acknowledgment monitor run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AcknowledgmentReport {
+    /// Tokens acknowledged by this run
+    pub acknowledged: usize,
+    /// Tokens still unacknowledged after this run
+    pub pending: usize,
+    /// Pending tokens whose deadline is within the alert window
+    pub at_risk: usize,
+}
+
+async fn retry_acknowledgment(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    token: &PurchaseToken,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    let package_name = token.package_name.as_deref().ok_or_else(|| {
+        AppError::InternalError("Package name unknown, cannot acknowledge".to_string())
+    })?;
+
+    let subscription_response = google_play
+        .fetch_subscription(package_name, &token.purchase_token)
+        .await?;
+
+    acknowledge_purchase(
+        conn,
+        google_play,
+        ack_strategies,
+        package_name,
+        &token.purchase_token,
+        &subscription_response,
+        now,
+    )
+    .await
+}
+
+/// Retry acknowledgment of stored purchases that are still unacknowledged
+///
+/// Purchases the app acknowledges are only checked with Google. Tokens that stay
+/// unacknowledged within `alert_window` of Google's deadline are reported through the notifier
+/// on every run until they are acknowledged or voided.
+pub async fn check_pending_acknowledgments(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    notifier: &Notifier,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+    alert_window: chrono::Duration,
+) -> AppResult<AcknowledgmentReport> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let now = clock.now_naive();
+
+    let mut report = AcknowledgmentReport::default();
+    // Tokens that fail stay unacknowledged, so batches continue after the last one seen
+    let mut after: Option<(NaiveDateTime, String)> = None;
+    loop {
+        let mut query = purchase_tokens
+            .filter(acknowledged_at.is_null())
+            .filter(status.eq(PurchaseTokenStatus::AccessGranted))
+            .order((created_at.asc(), id.asc()))
+            .limit(JOB_BATCH_SIZE)
+            .into_boxed();
+        if let Some((last_created, last_id)) = &after {
+            query = query.filter(
+                created_at
+                    .gt(*last_created)
+                    .or(created_at.eq(*last_created).and(id.gt(last_id.clone()))),
+            );
+        }
+        let unacknowledged: Vec<PurchaseToken> = query.load(conn)?;
+        let full = unacknowledged.len() as i64 == JOB_BATCH_SIZE;
+        after = unacknowledged
+            .last()
+            .map(|token| (token.created_at, token.id.clone()));
+
+        for token in unacknowledged {
+            match retry_acknowledgment(conn, google_play, ack_strategies, &token, now).await {
+                Ok(true) => {
+                    metrics.inc_counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "ok")], 1);
+                    report.acknowledged += 1;
+                    continue;
+                }
+                // Still waiting for the app to acknowledge
+                Ok(false) => {}
+                Err(e) => {
+                    metrics.inc_counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "error")], 1);
+                    eprintln!(
+                        "Failed to acknowledge purchase token {}: {}",
+                        token.purchase_token, e
+                    );
+                }
+            }
+
+            report.pending += 1;
+
+            let deadline = token.created_at + chrono::Duration::days(ACKNOWLEDGMENT_DEADLINE_DAYS);
+            if deadline - now > alert_window {
+                continue;
+            }
+
+            report.at_risk += 1;
+            sentry::capture_message(
+                &format!(
+                    "Purchase token {} is unacknowledged and will be voided at {}",
+                    token.purchase_token, deadline
+                ),
+                sentry::Level::Error,
+            );
+
+            let event = BillingEvent::AcknowledgmentDeadlineApproaching {
+                user_id: token.user_id.clone(),
+                purchase_token: token.purchase_token.clone(),
+                deadline: deadline.and_utc().to_rfc3339(),
+            };
+            if let Err(e) = notifier.send(&event).await {
+                eprintln!(
+                    "Failed to send acknowledgment deadline alert for {}: {}",
+                    token.purchase_token, e
+                );
+            }
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    metrics.set_gauge(UNACKNOWLEDGED_TOKENS, &[], report.pending as i64);
+    metrics.set_gauge(
+        ACKNOWLEDGMENT_DEADLINE_AT_RISK_TOKENS,
+        &[],
+        report.at_risk as i64,
+    );
+
+    Ok(report)
+}
+
+/// Run `check_pending_acknowledgments` on its schedule
+///
+/// Scheduled by `ACK_MONITOR_SCHEDULE`, or every `ACK_MONITOR_INTERVAL_SECS` (default 900).
+/// Alerts after `ACK_ALERT_HOURS` (default 24).
+pub fn register_acknowledgment_monitor_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env("ACK_MONITOR_SCHEDULE", "ACK_MONITOR_INTERVAL_SECS", 900)?;
+    let alert_hours: i64 = env_number("ACK_ALERT_HOURS", 24)?;
+
+    scheduler.register(
+        "acknowledgment_monitor",
+        schedule,
+        move |app_state| async move {
+            let mut conn = app_state.get_db_connection()?;
+            let report = check_pending_acknowledgments(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                &app_state.notifier,
+                app_state.clock.as_ref(),
+                &app_state.metrics,
+                chrono::Duration::hours(alert_hours),
+            )
+            .await?;
+            Ok((report != AcknowledgmentReport::default()).then(|| {
+                format!(
+                    "Acknowledgment monitor: {} acknowledged, {} pending, {} at risk",
+                    report.acknowledged, report.pending, report.at_risk
+                )
+            }))
+        },
+    );
+
+    Ok(())
+}
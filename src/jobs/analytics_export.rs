@@ -0,0 +1,175 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::analytics::{AnalyticsEvent, IngestedEvent};
+use crate::config::{env_number, ConfigError};
+use crate::error::AppResult;
+use crate::metrics::ANALYTICS_EVENTS_EXPORTED_TOTAL;
+use crate::model::AnalyticsEventRecord;
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::OutboxStatus;
+use crate::AppState;
+
+/// Attempts after which an event is marked `Failed` and no longer sent
+pub const MAX_ANALYTICS_ATTEMPTS: i32 = 20;
+
+/// Send up to `batch_size` pending analytics events in one request, oldest first
+///
+/// When the endpoint refuses the batch every event in it stays pending for the next run,
+/// until it runs out of attempts. Returns the number of events sent.
+pub async fn export_analytics_events(app_state: &AppState, batch_size: i64) -> AppResult<usize> {
+    use crate::schema::analytics_events::dsl::*;
+
+    let pending: Vec<AnalyticsEventRecord> = analytics_events
+        .filter(status.eq(OutboxStatus::Pending))
+        .order(created_at.asc())
+        .limit(batch_size)
+        .load(&mut app_state.get_db_connection()?)?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut batch = Vec::with_capacity(pending.len());
+    let mut undecodable = Vec::new();
+    for record in &pending {
+        match serde_json::from_str::<AnalyticsEvent>(&record.payload) {
+            Ok(event) => batch.push(IngestedEvent {
+                id: record.id.clone(),
+                event,
+            }),
+            Err(e) => undecodable.push((record.id.clone(), e.to_string())),
+        }
+    }
+
+    let now = app_state.clock.now_naive();
+    let mut conn = app_state.get_db_connection()?;
+    // Can't get better by retrying
+    for (record_id, error) in undecodable {
+        diesel::update(analytics_events.filter(id.eq(record_id)))
+            .set((
+                status.eq(OutboxStatus::Failed),
+                last_error.eq(Some(format!("Undecodable event: {}", error))),
+                updated_at.eq(now),
+            ))
+            .execute(&mut conn)?;
+    }
+    drop(conn);
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    let sent_ids: Vec<&str> = batch.iter().map(|event| event.id.as_str()).collect();
+    let sent = app_state.analytics.send_batch(&batch).await;
+    let mut conn = app_state.get_db_connection()?;
+    if let Err(e) = sent {
+        eprintln!("Failed to send {} analytics events: {}", batch.len(), e);
+        diesel::update(analytics_events.filter(id.eq_any(sent_ids.iter().copied())))
+            .set((
+                attempts.eq(attempts + 1),
+                last_error.eq(Some(e.to_string())),
+                updated_at.eq(now),
+            ))
+            .execute(&mut conn)?;
+        diesel::update(
+            analytics_events
+                .filter(id.eq_any(sent_ids.iter().copied()))
+                .filter(attempts.ge(MAX_ANALYTICS_ATTEMPTS)),
+        )
+        .set(status.eq(OutboxStatus::Failed))
+        .execute(&mut conn)?;
+        return Err(e);
+    }
+
+    diesel::update(analytics_events.filter(id.eq_any(sent_ids.iter().copied())))
+        .set((
+            status.eq(OutboxStatus::Done),
+            attempts.eq(attempts + 1),
+            last_error.eq(None::<String>),
+            updated_at.eq(now),
+        ))
+        .execute(&mut conn)?;
+    for ingested in &batch {
+        app_state.metrics.inc_counter(
+            ANALYTICS_EVENTS_EXPORTED_TOTAL,
+            &[("event", ingested.event.event.as_str())],
+            1,
+        );
+    }
+    Ok(batch.len())
+}
+
+/// Run `export_analytics_events` on its schedule until no pending event is left
+///
+/// Sends `ANALYTICS_EXPORT_BATCH_SIZE` (default 100) events per request. Scheduled by
+/// `ANALYTICS_EXPORT_SCHEDULE`, or every `ANALYTICS_EXPORT_INTERVAL_SECS` (default 60). Only
+/// registered when analytics are configured.
+pub fn register_analytics_export_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let batch_size: i64 = env_number("ANALYTICS_EXPORT_BATCH_SIZE", 100)?;
+    let schedule = Schedule::from_env(
+        "ANALYTICS_EXPORT_SCHEDULE",
+        "ANALYTICS_EXPORT_INTERVAL_SECS",
+        60,
+    )?;
+
+    scheduler.register("analytics_export", schedule, move |app_state| async move {
+        let mut sent = 0;
+        loop {
+            let batch = export_analytics_events(&app_state, batch_size).await?;
+            sent += batch;
+            if (batch as i64) < batch_size {
+                break;
+            }
+        }
+
+        Ok((sent > 0).then(|| format!("Sent {} analytics events", sent)))
+    });
+
+    Ok(())
+}
+
+/// Analytics events waiting to be sent, due as soon as they are recorded
+pub struct AnalyticsEventQueue;
+
+impl QueueStats for AnalyticsEventQueue {
+    fn name(&self) -> &'static str {
+        "analytics_events"
+    }
+
+    fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::minutes(30)
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        now: NaiveDateTime,
+    ) -> AppResult<QueueSnapshot> {
+        use crate::schema::analytics_events::dsl::*;
+
+        let count_with = |conn: &mut SqliteConnection, wanted: OutboxStatus| -> AppResult<i64> {
+            Ok(analytics_events
+                .filter(status.eq(wanted))
+                .count()
+                .get_result(conn)?)
+        };
+        let pending = count_with(conn, OutboxStatus::Pending)?;
+        let failed = count_with(conn, OutboxStatus::Failed)?;
+        let oldest_due_at: Option<NaiveDateTime> = analytics_events
+            .filter(status.eq(OutboxStatus::Pending))
+            .select(diesel::dsl::min(created_at))
+            .first(conn)?;
+        let processed: i64 = analytics_events
+            .filter(status.eq(OutboxStatus::Done))
+            .filter(updated_at.ge(now - chrono::Duration::hours(1)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(QueueSnapshot {
+            pending,
+            failed: Some(failed),
+            oldest_due_at,
+            processed_last_hour: Some(processed),
+        })
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::str::FromStr;
+
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::db::token_versions::{current_token, ensure_unchanged};
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::line_items::record_line_items;
+use crate::model::{BackfillProgress, PurchaseToken};
+use crate::routes::orders::record_order;
+use crate::types::GooglePlaySubscriptionResponse;
+
+/// Columns filled in for rows stored before they existed, from what Google reports today
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backfill {
+    /// `environment`, every row predating it was stored as production
+    Environment,
+    /// The latest order of each token, with its order id, base plan and price
+    Orders,
+    /// `replaced_by` of the token a subscription's `linkedPurchaseToken` points to
+    LinkedTokens,
+    /// Every line item of a subscription, stored since multi-line subscriptions were supported
+    LineItems,
+}
+
+impl Backfill {
+    pub const ALL: [Backfill; 4] = [
+        Backfill::Environment,
+        Backfill::Orders,
+        Backfill::LinkedTokens,
+        Backfill::LineItems,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backfill::Environment => "environment",
+            Backfill::Orders => "orders",
+            Backfill::LinkedTokens => "linked_tokens",
+            Backfill::LineItems => "line_items",
+        }
+    }
+
+    /// Fill the columns for one token from Google's response, returning whether anything changed
+    fn apply(
+        &self,
+        conn: &mut SqliteConnection,
+        token: &PurchaseToken,
+        subscription_response: &GooglePlaySubscriptionResponse,
+        now: chrono::NaiveDateTime,
+    ) -> AppResult<bool> {
+        use crate::schema::{orders, purchase_tokens, subscription_line_items};
+
+        match self {
+            Backfill::Environment => {
+                let reported = subscription_response.environment();
+                if reported == token.environment {
+                    return Ok(false);
+                }
+                let updated = diesel::update(
+                    purchase_tokens::table
+                        .filter(purchase_tokens::id.eq(&token.id))
+                        .filter(purchase_tokens::version.eq(token.version)),
+                )
+                .set(purchase_tokens::environment.eq(reported))
+                .execute(conn)?;
+                ensure_unchanged(updated, token)?;
+                Ok(true)
+            }
+            Backfill::Orders => {
+                let Some(order_id) = &subscription_response.latest_order_id else {
+                    return Ok(false);
+                };
+                let known: i64 = orders::table
+                    .filter(orders::order_id.eq(order_id))
+                    .count()
+                    .get_result(conn)?;
+                if known > 0 {
+                    return Ok(false);
+                }
+                record_order(conn, &token.purchase_token, subscription_response, now)?;
+                Ok(true)
+            }
+            Backfill::LinkedTokens => {
+                let Some(linked) = &subscription_response.linked_purchase_token else {
+                    return Ok(false);
+                };
+                if linked == &token.purchase_token {
+                    return Ok(false);
+                }
+                let Some(linked) =
+                    current_token(conn, linked)?.filter(|linked| linked.replaced_by.is_none())
+                else {
+                    return Ok(false);
+                };
+                let updated = diesel::update(
+                    purchase_tokens::table
+                        .filter(purchase_tokens::id.eq(&linked.id))
+                        .filter(purchase_tokens::version.eq(linked.version)),
+                )
+                .set(purchase_tokens::replaced_by.eq(Some(&token.purchase_token)))
+                .execute(conn)?;
+                ensure_unchanged(updated, &linked)?;
+                Ok(true)
+            }
+            Backfill::LineItems => {
+                let stored: i64 = subscription_line_items::table
+                    .filter(subscription_line_items::purchase_token.eq(&token.purchase_token))
+                    .count()
+                    .get_result(conn)?;
+                if stored > 0 {
+                    return Ok(false);
+                }
+                record_line_items(conn, &token.purchase_token, subscription_response, now)?;
+                Ok(!subscription_response.line_items.is_empty())
+            }
+        }
+    }
+}
+
+impl FromStr for Backfill {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Backfill::ALL
+            .into_iter()
+            .find(|backfill| backfill.name() == value)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Backfill::ALL.iter().map(Backfill::name).collect();
+                format!(
+                    "unknown backfill `{}`, expected one of {}",
+                    value,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Where a backfill stands after a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillReport {
+    pub name: &'static str,
+    /// Tokens visited by this run
+    pub processed: usize,
+    /// Tokens whose stored data this run changed
+    pub updated: usize,
+    /// Tokens Google couldn't be asked about, retried only when the backfill is restarted
+    pub failed: usize,
+    /// Whether every token has been visited, over all runs
+    pub completed: bool,
+}
+
+impl fmt::Display for BackfillReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Backfill {} visited {} tokens, updated {}, {} failed",
+            self.name, self.processed, self.updated, self.failed
+        )?;
+        if self.completed {
+            write!(f, ", complete")
+        } else {
+            write!(f, ", run again to continue")
+        }
+    }
+}
+
+fn load_progress(
+    conn: &mut SqliteConnection,
+    backfill: Backfill,
+    restart: bool,
+    now: chrono::NaiveDateTime,
+) -> AppResult<BackfillProgress> {
+    use crate::schema::backfills::dsl::*;
+
+    if restart {
+        diesel::delete(backfills.find(backfill.name())).execute(conn)?;
+    }
+    diesel::insert_or_ignore_into(backfills)
+        .values(&BackfillProgress::new(backfill.name().to_string(), now))
+        .execute(conn)?;
+    Ok(backfills.find(backfill.name()).first(conn)?)
+}
+
+/// Run `backfill` over every stored subscription, `batch_size` tokens at a time
+///
+/// Tokens are visited in id order and progress is stored after each batch, so a run that is
+/// interrupted, or stopped by Google throttling, continues after the last finished batch when
+/// run again. A completed backfill does nothing until run with `restart`. Stops after
+/// `max_batches` batches when given.
+pub async fn run_backfill(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    clock: &dyn Clock,
+    backfill: Backfill,
+    batch_size: i64,
+    max_batches: Option<usize>,
+    restart: bool,
+) -> AppResult<BackfillReport> {
+    use crate::schema::backfills::dsl as progress;
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let mut state = load_progress(conn, backfill, restart, clock.now_naive())?;
+    let mut report = BackfillReport {
+        name: backfill.name(),
+        processed: 0,
+        updated: 0,
+        failed: 0,
+        completed: state.completed_at.is_some(),
+    };
+
+    let mut batches = 0;
+    while !report.completed && max_batches != Some(batches) {
+        let mut query = purchase_tokens
+            .filter(package_name.is_not_null())
+            .order(id.asc())
+            .limit(batch_size)
+            .into_boxed();
+        if let Some(after) = &state.last_token_id {
+            query = query.filter(id.gt(after.clone()));
+        }
+        let batch: Vec<PurchaseToken> = query.load(conn)?;
+
+        let now = clock.now_naive();
+        let Some(last) = batch.last() else {
+            report.completed = true;
+            diesel::update(progress::backfills.find(backfill.name()))
+                .set((
+                    progress::completed_at.eq(Some(now)),
+                    progress::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+            break;
+        };
+        let last_id = last.id.clone();
+
+        let (mut updated, mut failed) = (0, 0);
+        let mut last_error = None;
+        for token in &batch {
+            let Some(package) = token.package_name.as_deref() else {
+                continue;
+            };
+            let result = match google_play
+                .fetch_subscription(package, &token.purchase_token)
+                .await
+            {
+                Ok(subscription_response) => {
+                    backfill.apply(conn, token, &subscription_response, now)
+                }
+                // Stop for now, this batch's progress isn't stored so the next run visits it again
+                Err(e @ AppError::GooglePlayThrottled { .. }) => return Err(e),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(true) => updated += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!(
+                        "Backfill {} failed for purchase token {}: {}",
+                        backfill.name(),
+                        token.purchase_token,
+                        e
+                    );
+                    failed += 1;
+                    last_error = Some(e.to_string());
+                }
+            }
+        }
+
+        state.processed += batch.len() as i64;
+        state.updated += updated;
+        state.failed += failed;
+        if last_error.is_some() {
+            state.last_error = last_error;
+        }
+        diesel::update(progress::backfills.find(backfill.name()))
+            .set((
+                progress::last_token_id.eq(Some(&last_id)),
+                progress::processed.eq(state.processed),
+                progress::updated.eq(state.updated),
+                progress::failed.eq(state.failed),
+                progress::last_error.eq(state.last_error.as_deref()),
+                progress::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+        state.last_token_id = Some(last_id);
+
+        report.processed += batch.len();
+        report.updated += updated as usize;
+        report.failed += failed as usize;
+        batches += 1;
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,118 @@
+use std::env;
+
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::ConfigError;
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::model::Product;
+use crate::plans::PLAN_CATALOG;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Replace the stored catalog of `package_name` with the subscriptions configured in the Console
+///
+/// Each base plan becomes one `products` row. Returns the stored rows.
+pub async fn sync_product_catalog(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    package_name: &str,
+    clock: &dyn Clock,
+) -> AppResult<Vec<Product>> {
+    use crate::schema::products;
+
+    let subscriptions = google_play.list_subscription_products(package_name).await?;
+    let now = clock.now_naive();
+
+    let rows: Vec<Product> = subscriptions
+        .iter()
+        .flat_map(|subscription| {
+            subscription.base_plans.iter().map(|base_plan| {
+                Product::new(
+                    package_name.to_string(),
+                    subscription.product_id.clone(),
+                    base_plan.base_plan_id.clone(),
+                    base_plan
+                        .state
+                        .clone()
+                        .unwrap_or_else(|| "STATE_UNSPECIFIED".to_string()),
+                    base_plan.billing_period(),
+                    subscription.archived,
+                    now,
+                )
+            })
+        })
+        .collect();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        diesel::delete(products::table.filter(products::package_name.eq(package_name)))
+            .execute(conn)?;
+        diesel::insert_into(products::table)
+            .values(&rows)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    Ok(rows)
+}
+
+/// Plan product ids that have no active base plan among `products`
+///
+/// A non-empty result means a plan in `PLAN_CATALOG` can't currently be bought.
+pub fn missing_plan_products(products: &[Product]) -> Vec<String> {
+    PLAN_CATALOG
+        .iter()
+        .filter_map(|definition| definition.product_id)
+        .filter(|plan_product| {
+            !products
+                .iter()
+                .any(|product| product.product_id == *plan_product && product.is_active())
+        })
+        .map(|plan_product| plan_product.to_string())
+        .collect()
+}
+
+/// Run `sync_product_catalog` on startup and then on its schedule
+///
+/// Syncs the package in `GOOGLE_PLAY_PACKAGE_NAME`, the job is skipped when it is unset.
+/// Scheduled by `CATALOG_SYNC_SCHEDULE`, or every `CATALOG_SYNC_INTERVAL_SECS` (default 21600).
+pub fn register_catalog_sync_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule =
+        Schedule::from_env("CATALOG_SYNC_SCHEDULE", "CATALOG_SYNC_INTERVAL_SECS", 21600)?;
+    let Ok(package_name) = env::var("GOOGLE_PLAY_PACKAGE_NAME") else {
+        println!("GOOGLE_PLAY_PACKAGE_NAME is not set, product catalog sync disabled");
+        return Ok(());
+    };
+
+    scheduler.register("catalog_sync", schedule, move |app_state| {
+        let package_name = package_name.clone();
+        async move {
+            let mut conn = app_state.get_db_connection()?;
+            let products = sync_product_catalog(
+                &mut conn,
+                app_state.google_play.as_ref(),
+                &package_name,
+                app_state.clock.as_ref(),
+            )
+            .await?;
+
+            let missing = missing_plan_products(&products);
+            if !missing.is_empty() {
+                sentry::capture_message(
+                    &format!(
+                        "Plan products missing from the Play Console catalog: {}",
+                        missing.join(", ")
+                    ),
+                    sentry::Level::Warning,
+                );
+                eprintln!(
+                    "Plan products missing from the Play Console catalog: {}",
+                    missing.join(", ")
+                );
+            }
+            Ok(None)
+        }
+    });
+
+    Ok(())
+}
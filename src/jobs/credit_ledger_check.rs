@@ -0,0 +1,118 @@
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::ConfigError;
+use crate::error::AppResult;
+use crate::integrations::user_info::UserInfoApi;
+use crate::metrics::{Metrics, CREDIT_LEDGER_MISMATCHES_TOTAL};
+use crate::model::CreditLedgerEntry;
+use crate::routes::credits::{ledger_balance, BILLING_SERVICE};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::{CreditDirection, CreditReason};
+
+/// Result of one consistency check run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LedgerCheckReport {
+    /// Users whose IC credits were compared with their ledger
+    pub checked: usize,
+    /// Users whose ledger got a balancing entry
+    pub balanced: usize,
+    /// Users whose credits couldn't be read from the IC
+    pub failed: usize,
+}
+
+/// Compare every ledger user's balance with the credits the IC reports, balancing differences
+///
+/// Credits also change outside the credits API, e.g. a Pro grant resets them to the
+/// allotment, so a difference is recorded as a `balancing` entry rather than corrected on
+/// the IC. Each one is logged and counted in `CREDIT_LEDGER_MISMATCHES_TOTAL`.
+pub async fn check_credit_ledger(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+) -> AppResult<LedgerCheckReport> {
+    use crate::schema::credit_ledger;
+
+    let users: Vec<String> = credit_ledger::table
+        .select(credit_ledger::user_id)
+        .distinct()
+        .order(credit_ledger::user_id.asc())
+        .load(conn)?;
+
+    let mut report = LedgerCheckReport::default();
+    for user in users {
+        let credits = match user_info.get_credits(&user).await {
+            Ok(credits) => credits,
+            Err(e) => {
+                eprintln!("Failed to read credits of {} from the IC: {}", user, e);
+                report.failed += 1;
+                continue;
+            }
+        };
+        report.checked += 1;
+
+        let balance = ledger_balance(conn, &user)?;
+        let difference = credits as i64 - balance;
+        if difference == 0 {
+            continue;
+        }
+
+        println!(
+            "Credits of {} on the IC ({}) differ from the ledger ({}), balancing",
+            user, credits, balance
+        );
+        let direction = if difference > 0 {
+            CreditDirection::Increment
+        } else {
+            CreditDirection::Deduct
+        };
+        let entry = CreditLedgerEntry::new(
+            user,
+            direction,
+            difference.unsigned_abs() as u32,
+            CreditReason::Balancing,
+            BILLING_SERVICE.to_string(),
+            None,
+            clock.now_naive(),
+        );
+        diesel::insert_into(credit_ledger::table)
+            .values(&entry)
+            .execute(conn)?;
+        metrics.inc_counter(CREDIT_LEDGER_MISMATCHES_TOTAL, &[], 1);
+        report.balanced += 1;
+    }
+
+    Ok(report)
+}
+
+/// Run `check_credit_ledger` on its schedule
+///
+/// Scheduled by `CREDIT_LEDGER_CHECK_SCHEDULE`, e.g. `0 3 * * *` for nightly, or every
+/// `CREDIT_LEDGER_CHECK_INTERVAL_SECS` (default 86400).
+pub fn register_credit_ledger_check_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env(
+        "CREDIT_LEDGER_CHECK_SCHEDULE",
+        "CREDIT_LEDGER_CHECK_INTERVAL_SECS",
+        86400,
+    )?;
+
+    scheduler.register("credit_ledger_check", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let report = check_credit_ledger(
+            &mut conn,
+            app_state.user_info.as_ref(),
+            app_state.clock.as_ref(),
+            &app_state.metrics,
+        )
+        .await?;
+        Ok((report.balanced > 0 || report.failed > 0).then(|| {
+            format!(
+                "Checked credits of {} users, balanced {}, {} failed",
+                report.checked, report.balanced, report.failed
+            )
+        }))
+    });
+
+    Ok(())
+}
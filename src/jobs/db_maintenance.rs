@@ -0,0 +1,41 @@
+use diesel::connection::SimpleConnection;
+use diesel::SqliteConnection;
+
+use crate::config::ConfigError;
+use crate::db::database_size_bytes;
+use crate::error::AppResult;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Fold the WAL back into the database, rebuild the file and refresh planner statistics
+///
+/// Returns how many bytes the database file shrank by. `VACUUM` holds the write lock while it
+/// copies the whole database, other writers wait on their busy timeout meanwhile.
+pub fn run_db_maintenance(conn: &mut SqliteConnection) -> AppResult<i64> {
+    let size_before = database_size_bytes(conn)?;
+    conn.batch_execute("PRAGMA wal_checkpoint(TRUNCATE); VACUUM; ANALYZE;")?;
+    let size_after = database_size_bytes(conn)?;
+    Ok(size_before - size_after)
+}
+
+/// Run `run_db_maintenance` on its schedule
+///
+/// Scheduled by `DB_MAINTENANCE_SCHEDULE`, or every `DB_MAINTENANCE_INTERVAL_SECS` (default
+/// 604800, a week).
+pub fn register_db_maintenance_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env(
+        "DB_MAINTENANCE_SCHEDULE",
+        "DB_MAINTENANCE_INTERVAL_SECS",
+        604800,
+    )?;
+
+    scheduler.register("db_maintenance", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let freed = run_db_maintenance(&mut conn)?;
+        Ok(Some(format!(
+            "Vacuumed and analyzed the database, {} bytes freed",
+            freed
+        )))
+    });
+
+    Ok(())
+}
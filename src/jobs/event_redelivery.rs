@@ -0,0 +1,129 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::AppResult;
+use crate::events::deliver_event;
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::OutboxStatus;
+use crate::AppState;
+
+/// Seconds a pending event is left to the dispatcher before the redelivery job takes it
+const DISPATCH_GRACE_SECS: i64 = 30;
+
+/// Deliver up to `batch_size` stored events the dispatcher didn't, oldest first
+///
+/// Covers events recorded without the bus, left behind by a full queue or a stopped process,
+/// and events a consumer failed on. Returns the number of events delivered.
+pub async fn redeliver_events(app_state: &AppState, batch_size: i64) -> AppResult<usize> {
+    use crate::schema::domain_events::dsl::*;
+
+    let cutoff = app_state.clock.now_naive() - chrono::Duration::seconds(DISPATCH_GRACE_SECS);
+    let pending: Vec<String> = domain_events
+        .filter(status.eq(OutboxStatus::Pending))
+        .filter(updated_at.le(cutoff))
+        .order(created_at.asc())
+        .select(id)
+        .limit(batch_size)
+        .load(&mut app_state.get_db_connection()?)?;
+
+    let mut delivered = 0;
+    for event_id in pending {
+        if deliver_event(app_state, &event_id).await? {
+            delivered += 1;
+        }
+    }
+
+    Ok(delivered)
+}
+
+/// Delete events every consumer handled before `cutoff`, returning how many were deleted
+pub fn prune_delivered_events(
+    conn: &mut SqliteConnection,
+    cutoff: NaiveDateTime,
+) -> AppResult<usize> {
+    use crate::schema::domain_events::dsl::*;
+
+    Ok(diesel::delete(
+        domain_events
+            .filter(status.eq(OutboxStatus::Done))
+            .filter(updated_at.lt(cutoff)),
+    )
+    .execute(conn)?)
+}
+
+/// Run `redeliver_events` on its schedule, pruning delivered events past their retention
+///
+/// Delivered events are kept for `DOMAIN_EVENT_RETENTION_DAYS` (default 7). Scheduled by
+/// `EVENT_REDELIVERY_SCHEDULE`, or every `EVENT_REDELIVERY_INTERVAL_SECS` (default 60).
+pub fn register_event_redelivery_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let retention_days: i64 = env_number("DOMAIN_EVENT_RETENTION_DAYS", 7)?;
+    let schedule = Schedule::from_env(
+        "EVENT_REDELIVERY_SCHEDULE",
+        "EVENT_REDELIVERY_INTERVAL_SECS",
+        60,
+    )?;
+
+    scheduler.register("event_redelivery", schedule, move |app_state| async move {
+        let delivered = redeliver_events(&app_state, 100).await?;
+
+        let cutoff = app_state.clock.now_naive() - chrono::Duration::days(retention_days);
+        let pruned = prune_delivered_events(&mut app_state.get_db_connection()?, cutoff)?;
+
+        Ok((delivered > 0 || pruned > 0).then(|| {
+            format!(
+                "Redelivered {} domain events, pruned {} delivered ones",
+                delivered, pruned
+            )
+        }))
+    });
+
+    Ok(())
+}
+
+/// Stored domain events, due as soon as they are recorded
+pub struct DomainEventQueue;
+
+impl QueueStats for DomainEventQueue {
+    fn name(&self) -> &'static str {
+        "domain_events"
+    }
+
+    fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::minutes(10)
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        now: NaiveDateTime,
+    ) -> AppResult<QueueSnapshot> {
+        use crate::schema::domain_events::dsl::*;
+
+        let count_with = |conn: &mut SqliteConnection, wanted: OutboxStatus| -> AppResult<i64> {
+            Ok(domain_events
+                .filter(status.eq(wanted))
+                .count()
+                .get_result(conn)?)
+        };
+        let pending = count_with(conn, OutboxStatus::Pending)?;
+        let failed = count_with(conn, OutboxStatus::Failed)?;
+        let oldest_due_at: Option<NaiveDateTime> = domain_events
+            .filter(status.eq(OutboxStatus::Pending))
+            .select(diesel::dsl::min(created_at))
+            .first(conn)?;
+        let processed: i64 = domain_events
+            .filter(status.eq(OutboxStatus::Done))
+            .filter(updated_at.ge(now - chrono::Duration::hours(1)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(QueueSnapshot {
+            pending,
+            failed: Some(failed),
+            oldest_due_at,
+            processed_last_hour: Some(processed),
+        })
+    }
+}
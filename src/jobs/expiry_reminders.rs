@@ -0,0 +1,117 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::{env_number, ConfigError};
+use crate::db::token_versions::{ensure_unchanged, reserve_token};
+use crate::error::{AppError, AppResult};
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::model::PurchaseToken;
+use crate::notifier::{BillingEvent, Notifier};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::PurchaseTokenStatus;
+
+/// Notify users whose subscriptions expire within `window` and won't auto-renew
+///
+/// Each token is notified once; `notified_at` is cleared again when the subscription renews
+/// or auto-renew is turned back on. Returns the number of reminders sent.
+pub async fn send_expiry_reminders(
+    conn: &mut SqliteConnection,
+    notifier: &Notifier,
+    clock: &dyn Clock,
+    window: chrono::Duration,
+) -> AppResult<usize> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let now = clock.now_naive();
+
+    let mut sent = 0;
+    // Tokens that fail stay due, so batches continue after the last one seen
+    let mut after: Option<(NaiveDateTime, String)> = None;
+    loop {
+        let mut query = purchase_tokens
+            .filter(status.eq(PurchaseTokenStatus::AccessGranted))
+            .filter(auto_renewing.eq(false))
+            .filter(notified_at.is_null())
+            .filter(expiry_at.gt(now))
+            .filter(expiry_at.le(now + window))
+            .order((expiry_at.asc(), id.asc()))
+            .limit(JOB_BATCH_SIZE)
+            .into_boxed();
+        if let Some((last_expiry, last_id)) = &after {
+            query = query.filter(
+                expiry_at
+                    .gt(*last_expiry)
+                    .or(expiry_at.eq(*last_expiry).and(id.gt(last_id.clone()))),
+            );
+        }
+        let due: Vec<PurchaseToken> = query.load(conn)?;
+        let full = due.len() as i64 == JOB_BATCH_SIZE;
+        after = due.last().map(|token| (token.expiry_at, token.id.clone()));
+
+        for token in due {
+            // A token changed since it was loaded is left for the next run to look at again
+            let token = match reserve_token(conn, &token) {
+                Ok(token) => token,
+                Err(AppError::TokenConflict(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let event = BillingEvent::SubscriptionExpiringSoon {
+                user_id: token.user_id.clone(),
+                expires_at: token.expiry_at.and_utc().to_rfc3339(),
+            };
+
+            // Leave the token unmarked on failure so the next run retries it
+            if let Err(e) = notifier.send(&event).await {
+                eprintln!(
+                    "Failed to send expiry reminder for user {}: {}",
+                    token.user_id, e
+                );
+                continue;
+            }
+
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set(notified_at.eq(Some(now)))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
+            sent += 1;
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Run `send_expiry_reminders` on its schedule
+///
+/// Reminds `EXPIRY_REMINDER_DAYS` (default 3) ahead. Scheduled by `EXPIRY_REMINDER_SCHEDULE`,
+/// or every `EXPIRY_REMINDER_INTERVAL_SECS` (default 3600).
+pub fn register_expiry_reminder_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let window_days: i64 = env_number("EXPIRY_REMINDER_DAYS", 3)?;
+    let schedule = Schedule::from_env(
+        "EXPIRY_REMINDER_SCHEDULE",
+        "EXPIRY_REMINDER_INTERVAL_SECS",
+        3600,
+    )?;
+
+    scheduler.register("expiry_reminders", schedule, move |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let sent = send_expiry_reminders(
+            &mut conn,
+            &app_state.notifier,
+            app_state.clock.as_ref(),
+            chrono::Duration::days(window_days),
+        )
+        .await?;
+        Ok((sent > 0).then(|| format!("Sent {} subscription expiry reminders", sent)))
+    });
+
+    Ok(())
+}
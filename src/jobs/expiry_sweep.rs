@@ -0,0 +1,128 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::ConfigError;
+use crate::db::token_versions::ensure_unchanged;
+use crate::error::{AppError, AppResult};
+use crate::events::{record_event, DomainEvent};
+use crate::jobs::access_outbox::enqueue_access_change;
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::line_items::expire_line_items;
+use crate::metrics::{Metrics, EXPIRY_SWEEP_EXPIRED_TOTAL};
+use crate::model::PurchaseToken;
+use crate::scheduler::{Schedule, Scheduler};
+use crate::subscriptions::active_subscription;
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{OutboxAction, PurchaseTokenStatus};
+
+/// Expire tokens still marked `AccessGranted` after their `expiry_at` has passed
+///
+/// Catches expiries whose RTDN was missed, e.g. while the service was down. Pro access is
+/// revoked through the access outbox, unless the user still holds another active subscription
+/// from any source. Tokens are expired in batches of `JOB_BATCH_SIZE`, one transaction each.
+/// Returns the number of tokens expired.
+pub fn sweep_expired_tokens(
+    conn: &mut SqliteConnection,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+) -> AppResult<usize> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let now = clock.now_naive();
+
+    // Each line item lapses on its own, a token only once its access expiry passed
+    expire_line_items(conn, now)?;
+
+    let mut expired = 0;
+    loop {
+        // Expired tokens leave the filter, so every batch starts from the front again
+        let batch = conn.transaction::<_, AppError, _>(|conn| {
+            let lapsed: Vec<PurchaseToken> = purchase_tokens
+                .filter(status.eq(PurchaseTokenStatus::AccessGranted))
+                .filter(expiry_at.lt(now))
+                .order((expiry_at.asc(), id.asc()))
+                .limit(JOB_BATCH_SIZE)
+                .load(conn)?;
+
+            expire_tokens(conn, &lapsed, now)?;
+
+            Ok(lapsed.len())
+        })?;
+
+        metrics.inc_counter(EXPIRY_SWEEP_EXPIRED_TOTAL, &[], batch as u64);
+        expired += batch;
+
+        if (batch as i64) < JOB_BATCH_SIZE {
+            break;
+        }
+    }
+
+    Ok(expired)
+}
+
+/// Mark `lapsed` tokens `Expired` and revoke Pro from owners left without a subscription
+///
+/// Run inside the caller's transaction so the status change and the revoke land together.
+pub fn expire_tokens(
+    conn: &mut SqliteConnection,
+    lapsed: &[PurchaseToken],
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    for token in lapsed {
+        let next_status = TokenStateMachine::next(token.status, TransitionReason::Lapsed)?;
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set(status.eq(next_status))
+        .execute(conn)?;
+        ensure_unchanged(updated, token)?;
+        record_event(
+            conn,
+            &DomainEvent::SubscriptionExpired {
+                user_id: token.user_id.clone(),
+                purchase_token: token.purchase_token.clone(),
+            },
+            now,
+        )?;
+    }
+
+    let mut users: Vec<&str> = lapsed.iter().map(|token| token.user_id.as_str()).collect();
+    users.sort();
+    users.dedup();
+
+    for user in users {
+        // Another token or a subscription from another source still grants Pro
+        if active_subscription(conn, user, now)?.is_some() {
+            continue;
+        }
+
+        let token = lapsed
+            .iter()
+            .find(|token| token.user_id == user)
+            .map(|token| token.purchase_token.as_str());
+        enqueue_access_change(conn, user, OutboxAction::RevokePro, None, token, now)?;
+    }
+
+    Ok(())
+}
+
+/// Run `sweep_expired_tokens` on startup and then on its schedule
+///
+/// Scheduled by `EXPIRY_SWEEP_SCHEDULE`, or every `EXPIRY_SWEEP_INTERVAL_SECS` (default 3600).
+pub fn register_expiry_sweep_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env("EXPIRY_SWEEP_SCHEDULE", "EXPIRY_SWEEP_INTERVAL_SECS", 3600)?;
+
+    scheduler.register("expiry_sweep", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let expired =
+            sweep_expired_tokens(&mut conn, app_state.clock.as_ref(), &app_state.metrics)?;
+        Ok((expired > 0).then(|| format!("Expiry sweep expired {} purchase tokens", expired)))
+    });
+
+    Ok(())
+}
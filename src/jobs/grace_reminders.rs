@@ -0,0 +1,328 @@
+use std::env;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::ConfigError;
+use crate::error::{AppError, AppResult};
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::line_items::{access_expiry, granting_product};
+use crate::model::GraceReminder;
+use crate::notifier::{BillingEvent, Notifier};
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::GooglePlaySubscriptionResponse;
+
+/// Reminders sent unless `GRACE_REMINDER_OFFSETS` says otherwise
+pub const DEFAULT_GRACE_REMINDER_OFFSETS: &str = "0,3,final";
+
+/// When in a grace period a reminder is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraceOffset {
+    /// Days after the grace period started, 0 right away
+    Days(u32),
+    /// A day before the grace period ends
+    FinalDay,
+}
+
+impl GraceOffset {
+    fn due_at(&self, reminder: &GraceReminder) -> NaiveDateTime {
+        match self {
+            GraceOffset::Days(days) => reminder.started_at + chrono::Duration::days(*days as i64),
+            GraceOffset::FinalDay => reminder.grace_ends_at - chrono::Duration::days(1),
+        }
+    }
+}
+
+/// Comma separated days after the grace period starts, and `final` for its last day
+pub fn parse_grace_offsets(value: &str) -> Result<Vec<GraceOffset>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .map(|offset| match offset {
+            "final" => Ok(GraceOffset::FinalDay),
+            days => days
+                .parse()
+                .map(GraceOffset::Days)
+                .map_err(|_| format!("`{}` is neither a number of days nor `final`", days)),
+        })
+        .collect()
+}
+
+/// `GRACE_REMINDER_OFFSETS`, or the defaults when it is unset
+pub fn grace_offsets_from_env() -> Result<Vec<GraceOffset>, ConfigError> {
+    let offsets = env::var("GRACE_REMINDER_OFFSETS")
+        .unwrap_or_else(|_| DEFAULT_GRACE_REMINDER_OFFSETS.to_string());
+    parse_grace_offsets(&offsets).map_err(ConfigError::GraceReminderOffsets)
+}
+
+/// When each reminder of `reminder` is due, in order, leaving out those after the grace period
+fn due_times(reminder: &GraceReminder, offsets: &[GraceOffset]) -> Vec<NaiveDateTime> {
+    let mut due_times: Vec<NaiveDateTime> = offsets
+        .iter()
+        .map(|offset| offset.due_at(reminder))
+        .filter(|due_at| *due_at < reminder.grace_ends_at)
+        .collect();
+    due_times.sort();
+    due_times
+}
+
+/// Number of the reminder due at `now` that wasn't sent yet, and whether it is the last one
+fn due_reminder_number(
+    reminder: &GraceReminder,
+    offsets: &[GraceOffset],
+    now: NaiveDateTime,
+) -> Option<(u32, bool)> {
+    if reminder.stopped_at.is_some() || reminder.grace_ends_at <= now {
+        return None;
+    }
+
+    let due_times = due_times(reminder, offsets);
+    let due = due_times.iter().filter(|due_at| **due_at <= now).count();
+    if due <= reminder.reminders_sent.max(0) as usize {
+        return None;
+    }
+
+    Some((due as u32, due == due_times.len()))
+}
+
+/// Reminder due for `reminder` at `now` that wasn't sent yet, if any
+///
+/// Reminders are sent in due order, those falling after the grace period are dropped. When
+/// several are due, e.g. after downtime, only the latest is sent so the user gets one.
+pub fn due_grace_reminder(
+    reminder: &GraceReminder,
+    offsets: &[GraceOffset],
+    now: NaiveDateTime,
+) -> Option<BillingEvent> {
+    let (number, final_reminder) = due_reminder_number(reminder, offsets, now)?;
+    Some(reminder_event(reminder, number, final_reminder))
+}
+
+fn reminder_event(reminder: &GraceReminder, number: u32, final_reminder: bool) -> BillingEvent {
+    BillingEvent::PaymentDeclinedReminder {
+        user_id: reminder.user_id.clone(),
+        purchase_token: reminder.purchase_token.clone(),
+        product_id: reminder.product_id.clone(),
+        reminder: number,
+        final_reminder,
+        grace_ends_at: reminder.grace_ends_at.and_utc().to_rfc3339(),
+    }
+}
+
+/// Start sending payment-declined reminders for a subscription that entered its grace period
+///
+/// A redelivered notification keeps the reminders already sent. Returns false when no new
+/// grace period started, or when Google reported no expiry to end it at.
+pub fn start_grace_reminders(
+    conn: &mut SqliteConnection,
+    token: &str,
+    user: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::grace_reminders::dsl::*;
+
+    let Some(ends_at) = access_expiry(subscription_response) else {
+        println!("No grace period end reported for purchase token {}", token);
+        return Ok(false);
+    };
+    let product = granting_product(subscription_response)
+        .unwrap_or_default()
+        .to_string();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let existing: Option<GraceReminder> = grace_reminders.find(token).first(conn).optional()?;
+        if let Some(existing) = existing {
+            if existing.stopped_at.is_none() && existing.grace_ends_at > now {
+                diesel::update(grace_reminders.find(token))
+                    .set(grace_ends_at.eq(ends_at))
+                    .execute(conn)?;
+                return Ok(false);
+            }
+        }
+
+        // A grace period of an earlier billing period is replaced
+        diesel::replace_into(grace_reminders)
+            .values(&GraceReminder::new(
+                token.to_string(),
+                user.to_string(),
+                product,
+                now,
+                ends_at,
+            ))
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+/// Stop the reminders of `token`, e.g. once payment is recovered
+pub fn stop_grace_reminders(
+    conn: &mut SqliteConnection,
+    token: &str,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::grace_reminders::dsl::*;
+
+    diesel::update(grace_reminders.find(token).filter(stopped_at.is_null()))
+        .set(stopped_at.eq(now))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Send the payment-declined reminders that are due
+///
+/// A reminder that fails to send stays due for the next run. Returns the number sent.
+pub async fn send_grace_reminders(
+    conn: &mut SqliteConnection,
+    notifier: &Notifier,
+    clock: &dyn Clock,
+    offsets: &[GraceOffset],
+) -> AppResult<usize> {
+    use crate::schema::grace_reminders::dsl::*;
+
+    let now = clock.now_naive();
+
+    let mut sent = 0;
+    let mut after: Option<(NaiveDateTime, String)> = None;
+    loop {
+        let mut query = grace_reminders
+            .filter(stopped_at.is_null())
+            .filter(grace_ends_at.gt(now))
+            .order((grace_ends_at.asc(), purchase_token.asc()))
+            .limit(JOB_BATCH_SIZE)
+            .into_boxed();
+        if let Some((last_end, last_token)) = &after {
+            query = query.filter(
+                grace_ends_at.gt(*last_end).or(grace_ends_at
+                    .eq(*last_end)
+                    .and(purchase_token.gt(last_token.clone()))),
+            );
+        }
+        let active: Vec<GraceReminder> = query.load(conn)?;
+        let full = active.len() as i64 == JOB_BATCH_SIZE;
+        after = active
+            .last()
+            .map(|reminder| (reminder.grace_ends_at, reminder.purchase_token.clone()));
+
+        for reminder in active {
+            let Some((number, final_reminder)) = due_reminder_number(&reminder, offsets, now)
+            else {
+                continue;
+            };
+            let event = reminder_event(&reminder, number, final_reminder);
+
+            if let Err(e) = notifier.send(&event).await {
+                eprintln!(
+                    "Failed to send payment declined reminder for user {}: {}",
+                    reminder.user_id, e
+                );
+                continue;
+            }
+
+            diesel::update(grace_reminders.find(&reminder.purchase_token))
+                .set((reminders_sent.eq(number as i32), last_sent_at.eq(Some(now))))
+                .execute(conn)?;
+            sent += 1;
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Run `send_grace_reminders` on its schedule
+///
+/// Reminders go out at `GRACE_REMINDER_OFFSETS` (default `0,3,final`). Scheduled by
+/// `GRACE_REMINDER_SCHEDULE`, or every `GRACE_REMINDER_INTERVAL_SECS` (default 3600).
+pub fn register_grace_reminder_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let offsets = grace_offsets_from_env()?;
+    let schedule = Schedule::from_env(
+        "GRACE_REMINDER_SCHEDULE",
+        "GRACE_REMINDER_INTERVAL_SECS",
+        3600,
+    )?;
+
+    scheduler.register("grace_reminders", schedule, move |app_state| {
+        let offsets = offsets.clone();
+        async move {
+            let mut conn = app_state.get_db_connection()?;
+            let sent = send_grace_reminders(
+                &mut conn,
+                &app_state.notifier,
+                app_state.clock.as_ref(),
+                &offsets,
+            )
+            .await?;
+            Ok((sent > 0).then(|| format!("Sent {} payment declined reminders", sent)))
+        }
+    });
+
+    Ok(())
+}
+
+/// Grace periods still being reminded about, due from their first unsent reminder
+pub struct GraceReminderQueue {
+    offsets: Vec<GraceOffset>,
+}
+
+impl GraceReminderQueue {
+    pub fn new(offsets: Vec<GraceOffset>) -> Self {
+        Self { offsets }
+    }
+
+    /// Reminders at `GRACE_REMINDER_OFFSETS`, the defaults if it is invalid as the reminder job
+    /// refuses to start then anyway
+    pub fn from_env() -> Self {
+        Self::new(grace_offsets_from_env().unwrap_or_else(|_| {
+            parse_grace_offsets(DEFAULT_GRACE_REMINDER_OFFSETS).expect("valid default offsets")
+        }))
+    }
+}
+
+impl QueueStats for GraceReminderQueue {
+    fn name(&self) -> &'static str {
+        "grace_reminders"
+    }
+
+    /// The reminder job runs hourly by default
+    fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::hours(3)
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        now: NaiveDateTime,
+    ) -> AppResult<QueueSnapshot> {
+        use crate::schema::grace_reminders::dsl::*;
+
+        let active: Vec<GraceReminder> = grace_reminders
+            .filter(stopped_at.is_null())
+            .filter(grace_ends_at.gt(now))
+            .load(conn)?;
+        let oldest_due_at = active
+            .iter()
+            .filter_map(|reminder| {
+                let sent = reminder.reminders_sent.max(0) as usize;
+                due_times(reminder, &self.offsets).get(sent).copied()
+            })
+            .filter(|due_at| *due_at <= now)
+            .min();
+        let processed: i64 = grace_reminders
+            .filter(last_sent_at.ge(now - chrono::Duration::hours(1)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(QueueSnapshot {
+            pending: active.len() as i64,
+            failed: None,
+            oldest_due_at,
+            processed_last_hour: Some(processed),
+        })
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::NaiveDateTime;
+use diesel::dsl::max;
+use diesel::prelude::*;
+
+use crate::config::ConfigError;
+use crate::error::AppError;
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::model::HeldNotification;
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::routes::rtdn::apply_held_notification;
+use crate::scheduler::{Schedule, Scheduler};
+use crate::types::DeveloperNotification;
+use crate::AppState;
+
+/// Apply held notifications once their window has passed
+///
+/// Scheduled by `RTDN_HOLD_FLUSH_SCHEDULE`, or every `RTDN_HOLD_FLUSH_INTERVAL_SECS` (default
+/// 5). Nothing is held unless `RTDN_HOLD_WINDOW_SECS` is set, so the job is idle by default.
+pub fn register_held_notification_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env(
+        "RTDN_HOLD_FLUSH_SCHEDULE",
+        "RTDN_HOLD_FLUSH_INTERVAL_SECS",
+        5,
+    )?;
+
+    scheduler.register("held_notifications", schedule, |app_state| async move {
+        let now = app_state.clock.now_naive();
+        let released = release_held_notifications(&app_state, now).await?;
+        Ok((released > 0).then(|| format!("Released {} held notifications", released)))
+    });
+
+    Ok(())
+}
+
+/// Apply the held notifications of every token with one due at `now`, oldest event first
+///
+/// A due notification releases the token's others with earlier event times too, even ones
+/// received later and not due yet, so a token's notifications are always applied in event
+/// order. Those with later event times stay held. Released notifications are applied through
+/// the RTDN handler, which discards any that a newer applied notification made stale. A
+/// failure leaves the rest of that token held for the next run. Returns how many were released.
+pub async fn release_held_notifications(
+    app_state: &AppState,
+    now: NaiveDateTime,
+) -> Result<usize, AppError> {
+    use crate::schema::held_notifications::dsl::*;
+
+    let mut conn = app_state.get_db_connection()?;
+    let due_tokens: Vec<String> = held_notifications
+        .filter(release_at.le(now))
+        .select(purchase_token)
+        .distinct()
+        .limit(JOB_BATCH_SIZE)
+        .load(&mut conn)?;
+
+    let mut released = 0;
+    for token in due_tokens {
+        let latest_due: Option<NaiveDateTime> = held_notifications
+            .filter(purchase_token.eq(&token))
+            .filter(release_at.le(now))
+            .select(max(event_time))
+            .first(&mut conn)?;
+        let Some(latest_due) = latest_due else {
+            continue;
+        };
+        let held: Vec<HeldNotification> = held_notifications
+            .filter(purchase_token.eq(&token))
+            .filter(event_time.le(latest_due))
+            .order(event_time.asc())
+            .load(&mut conn)?;
+
+        for notification in held {
+            if let Err(e) = release(app_state, &notification).await {
+                eprintln!(
+                    "Failed to apply held notification {} for token {}: {}",
+                    notification.id, token, e
+                );
+                break;
+            }
+            diesel::delete(held_notifications.find(&notification.id)).execute(&mut conn)?;
+            released += 1;
+        }
+    }
+
+    Ok(released)
+}
+
+async fn release(app_state: &AppState, held: &HeldNotification) -> Result<(), String> {
+    let notification: DeveloperNotification =
+        serde_json::from_str(&held.payload).map_err(|e| e.to_string())?;
+    match apply_held_notification(&notification, &held.payload, app_state).await {
+        Ok(()) => Ok(()),
+        // As on the webhook, retrying can't make the change valid
+        Err(e)
+            if matches!(
+                e.downcast_ref::<AppError>(),
+                Some(AppError::InvalidTransition { .. })
+            ) =>
+        {
+            eprintln!("Ignoring held notification {}: {}", held.id, e);
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Held RTDNs, due once their hold window passed; released ones are deleted, so nothing is
+/// counted as processed
+pub struct HeldNotificationQueue;
+
+impl QueueStats for HeldNotificationQueue {
+    fn name(&self) -> &'static str {
+        "held_notifications"
+    }
+
+    fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::minutes(5)
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        now: NaiveDateTime,
+    ) -> Result<QueueSnapshot, AppError> {
+        use crate::schema::held_notifications::dsl::*;
+
+        let pending: i64 = held_notifications.count().get_result(conn)?;
+        let oldest_due_at: Option<NaiveDateTime> = held_notifications
+            .filter(release_at.le(now))
+            .select(diesel::dsl::min(release_at))
+            .first(conn)?;
+
+        Ok(QueueSnapshot {
+            pending,
+            failed: None,
+            oldest_due_at,
+            processed_last_hour: None,
+        })
+    }
+}
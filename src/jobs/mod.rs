@@ -0,0 +1,21 @@
+pub mod access_outbox;
+pub mod acknowledgments;
+pub mod analytics_export;
+pub mod backfills;
+pub mod catalog_sync;
+pub mod credit_ledger_check;
+pub mod db_maintenance;
+pub mod event_redelivery;
+pub mod expiry_reminders;
+pub mod expiry_sweep;
+pub mod grace_reminders;
+pub mod held_notifications;
+pub mod pending_purchases;
+pub mod purchase_import;
+pub mod purchase_intent_pruning;
+pub mod queue_monitor;
+pub mod rtdn_lag_pruning;
+pub mod snapshot_pruning;
+
+/// Rows a background job loads from `purchase_tokens` per query
+pub const JOB_BATCH_SIZE: i64 = 500;
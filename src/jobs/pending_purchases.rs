@@ -0,0 +1,581 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::config::{env_number, ConfigError};
+use crate::db::token_versions::{ensure_unchanged, reserve_token};
+use crate::error::{AppError, AppResult};
+use crate::events::{record_event, DomainEvent};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::acknowledgments::{acknowledge_purchase, AckStrategies};
+use crate::jobs::JOB_BATCH_SIZE;
+use crate::line_items::{access_expiry, granting_product, record_line_items};
+use crate::model::PurchaseToken;
+use crate::profiles::resolve_account_id;
+use crate::queues::{QueueSnapshot, QueueStats};
+use crate::routes::offers::record_offer_redemptions;
+use crate::routes::orders::record_order;
+use crate::scheduler::{Schedule, Scheduler};
+use crate::subscriptions::{grant_pro_for_subscription, replace_duplicate_tokens};
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{
+    GooglePlaySubscriptionResponse, PurchaseEnvironment, PurchaseTokenStatus, SubscriptionSource,
+    SubscriptionState,
+};
+use crate::user_id::normalize_user_id;
+
+/// What happened to a pending purchase when Google was asked about it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingPurchaseOutcome {
+    /// Payment has not completed yet
+    StillPending,
+    /// Payment completed and access was granted
+    Granted,
+    /// Payment was canceled or the subscription ended before it completed
+    Canceled,
+}
+
+/// How long a provisional purchase may wait for its owner before its queue counts as stalled,
+/// unless `PROVISIONAL_PURCHASE_TIMEOUT_SECS` says otherwise
+pub const DEFAULT_PROVISIONAL_PURCHASE_TIMEOUT: chrono::Duration = chrono::Duration::hours(6);
+
+/// How long a provisional purchase may wait for its owner, from
+/// `PROVISIONAL_PURCHASE_TIMEOUT_SECS` (default 6 hours)
+pub fn provisional_timeout_from_env() -> Result<chrono::Duration, ConfigError> {
+    let secs: i64 = env_number(
+        "PROVISIONAL_PURCHASE_TIMEOUT_SECS",
+        DEFAULT_PROVISIONAL_PURCHASE_TIMEOUT.num_seconds(),
+    )?;
+    Ok(chrono::Duration::seconds(secs))
+}
+
+/// Store a purchase an RTDN reported before its owner could be resolved
+///
+/// The row waits as `Pending` without a user, and isn't mirrored into `subscriptions` until it
+/// has one. The pending purchase job tries to resolve its owner on every run and grants access
+/// once it does, `ProvisionalPurchaseQueue` alerts when it waits too long.
+///
+/// Returns whether a row was stored, an existing row is left alone.
+pub fn record_provisional_purchase(
+    conn: &mut SqliteConnection,
+    package_name: &str,
+    purchase_token: &str,
+    environment: PurchaseEnvironment,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::purchase_tokens;
+
+    let token = PurchaseToken::provisional(
+        purchase_token.to_string(),
+        package_name.to_string(),
+        environment,
+        now,
+    );
+    let stored = diesel::insert_or_ignore_into(purchase_tokens::table)
+        .values(&token)
+        .execute(conn)?;
+    Ok(stored > 0)
+}
+
+/// Give a provisional row its owner, rows that have one are left alone
+///
+/// Returns the token as it is stored now, for the writes that follow.
+pub fn claim_provisional_token(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+    owner: &str,
+) -> AppResult<PurchaseToken> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    if token.provisional_since.is_none() {
+        return Ok(token.clone());
+    }
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set((
+        user_id.eq(owner),
+        provisional_since.eq(None::<NaiveDateTime>),
+    ))
+    .execute(conn)?;
+    ensure_unchanged(updated, token)?;
+    Ok(purchase_tokens.find(&token.id).first(conn)?)
+}
+
+/// User a stored pending purchase is granted to
+///
+/// Google's obfuscated account id wins over the stored user. Provisional rows have none, their
+/// owner is resolved like verify does and the row claimed for them, failing with
+/// `ExternalAccountIdentifiersMissing` while nobody can be found. Returns the owner with the
+/// token as it is stored now.
+pub fn pending_purchase_owner(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<(String, PurchaseToken)> {
+    let identifiers = subscription_response.external_account_identifiers.as_ref();
+    let account_id = identifiers.and_then(|ids| ids.obfuscated_external_account_id.as_deref());
+    if token.provisional_since.is_none() {
+        let owner = account_id
+            .map(normalize_user_id)
+            .unwrap_or_else(|| token.user_id.clone());
+        return Ok((owner, token.clone()));
+    }
+
+    let owner = resolve_account_id(
+        conn,
+        account_id,
+        identifiers.and_then(|ids| ids.obfuscated_external_profile_id.as_deref()),
+        granting_product(subscription_response).unwrap_or_default(),
+        now,
+    )?;
+    let claimed = claim_provisional_token(conn, token, &owner)?;
+    Ok((owner, claimed))
+}
+
+/// Grant access for a stored pending purchase once Google reports it active
+///
+/// Access goes to `user_id`, the account Google reports for the purchase. `token` must be the
+/// row as stored, it is reserved before Pro is granted so a concurrent change fails the grant
+/// instead of being overwritten. The order is recorded and the purchase acknowledged as the
+/// package's strategy says, a failed acknowledgment is left to the monitor.
+#[allow(clippy::too_many_arguments)]
+pub async fn complete_pending_purchase(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    token: &PurchaseToken,
+    user_id: &str,
+    package_name: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: chrono::NaiveDateTime,
+) -> AppResult<PendingPurchaseOutcome> {
+    use crate::schema::purchase_tokens::dsl;
+
+    match subscription_response.subscription_state {
+        SubscriptionState::Active | SubscriptionState::InGracePeriod => {}
+        SubscriptionState::Pending => return Ok(PendingPurchaseOutcome::StillPending),
+        _ => {
+            let next_status =
+                TokenStateMachine::next(token.status, TransitionReason::PaymentAbandoned)?;
+            let updated = diesel::update(
+                dsl::purchase_tokens
+                    .filter(dsl::id.eq(&token.id))
+                    .filter(dsl::version.eq(token.version)),
+            )
+            .set(dsl::status.eq(next_status))
+            .execute(conn)?;
+            ensure_unchanged(updated, token)?;
+            return Ok(PendingPurchaseOutcome::Canceled);
+        }
+    }
+    let next_status = TokenStateMachine::next(token.status, TransitionReason::PaymentCompleted)?;
+
+    let product_id =
+        granting_product(subscription_response).ok_or(AppError::SubscriptionInvalidLineItems)?;
+    let expiry =
+        access_expiry(subscription_response).ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+    let reserved = reserve_token(conn, token)?;
+    grant_pro_for_subscription(
+        conn,
+        user_info,
+        product_id,
+        user_id,
+        SubscriptionSource::for_environment(token.environment),
+        &token.purchase_token,
+        now,
+    )
+    .await?;
+
+    let updated = diesel::update(
+        dsl::purchase_tokens
+            .filter(dsl::id.eq(&reserved.id))
+            .filter(dsl::version.eq(reserved.version)),
+    )
+    .set((
+        dsl::status.eq(next_status),
+        dsl::expiry_at.eq(expiry),
+        dsl::auto_renewing.eq(subscription_response.auto_renewing()),
+        dsl::notified_at.eq(None::<chrono::NaiveDateTime>),
+        dsl::package_name.eq(Some(package_name)),
+    ))
+    .execute(conn)?;
+    ensure_unchanged(updated, &reserved)?;
+
+    // The user may still hold a token bought from another Google account
+    replace_duplicate_tokens(conn, user_id)?;
+
+    record_order(conn, &token.purchase_token, subscription_response, now)?;
+    record_line_items(conn, &token.purchase_token, subscription_response, now)?;
+    record_offer_redemptions(
+        conn,
+        &token.purchase_token,
+        user_id,
+        subscription_response,
+        now,
+    )?;
+    record_event(
+        conn,
+        &DomainEvent::AccessGranted {
+            user_id: user_id.to_string(),
+            purchase_token: token.purchase_token.clone(),
+            product_id: product_id.to_string(),
+        },
+        now,
+    )?;
+
+    if let Err(e) = acknowledge_purchase(
+        conn,
+        google_play,
+        ack_strategies,
+        package_name,
+        &token.purchase_token,
+        subscription_response,
+        now,
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to acknowledge purchase token {}, leaving it for retry: {}",
+            token.purchase_token, e
+        );
+    }
+
+    Ok(PendingPurchaseOutcome::Granted)
+}
+
+/// Ask Google about every pending purchase and grant the ones whose payment completed
+///
+/// Covers missed RTDN notifications. Provisional purchases whose owner is still unknown are
+/// left waiting, see `handle_unclaimed_purchase`. Returns the number of purchases granted.
+pub async fn reconcile_pending_purchases(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    clock: &dyn Clock,
+    provisional_timeout: chrono::Duration,
+) -> AppResult<usize> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let mut granted = 0;
+    // Tokens that can't be completed stay pending, so batches continue after the last one seen
+    let mut after: Option<(chrono::NaiveDateTime, String)> = None;
+    loop {
+        let mut query = purchase_tokens
+            .filter(status.eq(PurchaseTokenStatus::Pending))
+            .filter(package_name.is_not_null())
+            .order((expiry_at.asc(), id.asc()))
+            .limit(JOB_BATCH_SIZE)
+            .into_boxed();
+        if let Some((last_expiry, last_id)) = &after {
+            query = query.filter(
+                expiry_at
+                    .gt(*last_expiry)
+                    .or(expiry_at.eq(*last_expiry).and(id.gt(last_id.clone()))),
+            );
+        }
+        let pending: Vec<PurchaseToken> = query.load(conn)?;
+        let full = pending.len() as i64 == JOB_BATCH_SIZE;
+        after = pending
+            .last()
+            .map(|token| (token.expiry_at, token.id.clone()));
+
+        for token in pending {
+            let Some(package) = token.package_name.as_deref() else {
+                continue;
+            };
+
+            let result = async {
+                let subscription_response = google_play
+                    .fetch_subscription(package, &token.purchase_token)
+                    .await?;
+                let (account_id, current) = match pending_purchase_owner(
+                    conn,
+                    &token,
+                    &subscription_response,
+                    clock.now_naive(),
+                ) {
+                    Err(AppError::ExternalAccountIdentifiersMissing)
+                        if token.provisional_since.is_some() =>
+                    {
+                        return handle_unclaimed_purchase(
+                            conn,
+                            &token,
+                            &subscription_response,
+                            clock.now_naive(),
+                            provisional_timeout,
+                        );
+                    }
+                    owner => owner?,
+                };
+
+                complete_pending_purchase(
+                    conn,
+                    google_play,
+                    ack_strategies,
+                    user_info,
+                    &current,
+                    &account_id,
+                    package,
+                    &subscription_response,
+                    clock.now_naive(),
+                )
+                .await
+            }
+            .await;
+
+            match result {
+                Ok(PendingPurchaseOutcome::Granted) => granted += 1,
+                Ok(_) => {}
+                // One failing token must not block the others
+                Err(e) => eprintln!(
+                    "Failed to reconcile pending purchase token {}: {}",
+                    token.purchase_token, e
+                ),
+            }
+        }
+
+        if !full {
+            break;
+        }
+    }
+
+    Ok(granted)
+}
+
+/// Handle a provisional purchase nobody could be found for yet
+///
+/// While the subscription lasts it keeps waiting, logged once it waited past `timeout`, as
+/// `ProvisionalPurchaseQueue` alerts then too. One whose subscription ended meanwhile expired
+/// unclaimed: it is marked expired and reported to Sentry, as an operator has to refund or
+/// credit it by hand. A later verify naming its owner still claims it.
+fn handle_unclaimed_purchase(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+    timeout: chrono::Duration,
+) -> AppResult<PendingPurchaseOutcome> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let waiting_since = token.provisional_since.unwrap_or(now);
+    match subscription_response.subscription_state {
+        SubscriptionState::Active
+        | SubscriptionState::InGracePeriod
+        | SubscriptionState::Pending => {
+            if now - waiting_since > timeout {
+                eprintln!(
+                    "Provisional purchase token {} has no owner {} minutes after it was stored",
+                    token.purchase_token,
+                    (now - waiting_since).num_minutes()
+                );
+            }
+            Ok(PendingPurchaseOutcome::StillPending)
+        }
+        _ => {
+            let next_status =
+                TokenStateMachine::next(token.status, TransitionReason::PaymentAbandoned)?;
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set(status.eq(next_status))
+            .execute(conn)?;
+            ensure_unchanged(updated, token)?;
+
+            let message = format!(
+                "Provisional purchase token {} ended unclaimed, stored {}",
+                token.purchase_token, waiting_since
+            );
+            eprintln!("{}", message);
+            sentry::capture_message(&message, sentry::Level::Warning);
+            Ok(PendingPurchaseOutcome::Canceled)
+        }
+    }
+}
+
+/// Result of re-running verification for one stored purchase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReverifyOutcome {
+    /// Google reports the subscription active, access was (re)granted and the row refreshed
+    Granted,
+    /// Payment has not completed yet
+    StillPending,
+    /// The subscription no longer renews, the stored expiry was corrected to Google's
+    Ended { expiry_at: chrono::NaiveDateTime },
+}
+
+/// Ask Google about a stored purchase token again and bring the stored row in line
+///
+/// Active subscriptions go through `complete_pending_purchase`, which grants access, records
+/// the order and acknowledges if needed. Ended subscriptions only get their expiry corrected,
+/// access is revoked by the next expiry sweep.
+pub async fn reverify_purchase_token(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    clock: &dyn Clock,
+    token_param: &str,
+) -> AppResult<ReverifyOutcome> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let token: PurchaseToken = purchase_tokens
+        .filter(purchase_token.eq(token_param))
+        .first(conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest("Unknown purchase token".to_string()))?;
+    let package = token.package_name.clone().ok_or_else(|| {
+        AppError::BadRequest("Purchase token has no package name to verify against".to_string())
+    })?;
+
+    // An operator asked for Google's current answer, not a recently cached one
+    google_play
+        .invalidate_subscription(&token.purchase_token)
+        .await;
+    let subscription_response = google_play
+        .fetch_subscription(&package, &token.purchase_token)
+        .await?;
+    let now = clock.now_naive();
+
+    match subscription_response.subscription_state {
+        SubscriptionState::Active | SubscriptionState::InGracePeriod => {
+            let (account_id, current) =
+                pending_purchase_owner(conn, &token, &subscription_response, now)?;
+
+            complete_pending_purchase(
+                conn,
+                google_play,
+                ack_strategies,
+                user_info,
+                &current,
+                &account_id,
+                &package,
+                &subscription_response,
+                now,
+            )
+            .await?;
+            Ok(ReverifyOutcome::Granted)
+        }
+        SubscriptionState::Pending => Ok(ReverifyOutcome::StillPending),
+        _ if token.status == PurchaseTokenStatus::Pending => {
+            let next_status =
+                TokenStateMachine::next(token.status, TransitionReason::PaymentAbandoned)?;
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set(status.eq(next_status))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
+            Ok(ReverifyOutcome::Ended { expiry_at: now })
+        }
+        _ => {
+            let ended_at = access_expiry(&subscription_response).unwrap_or(now);
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set((expiry_at.eq(ended_at), auto_renewing.eq(false)))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
+            Ok(ReverifyOutcome::Ended {
+                expiry_at: ended_at,
+            })
+        }
+    }
+}
+
+/// Run `reconcile_pending_purchases` on its schedule
+///
+/// Scheduled by `PENDING_PURCHASE_SCHEDULE`, or every `PENDING_PURCHASE_INTERVAL_SECS`
+/// (default 1800).
+pub fn register_pending_purchase_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env(
+        "PENDING_PURCHASE_SCHEDULE",
+        "PENDING_PURCHASE_INTERVAL_SECS",
+        1800,
+    )?;
+
+    scheduler.register("pending_purchases", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let granted = reconcile_pending_purchases(
+            &mut conn,
+            app_state.google_play.as_ref(),
+            &app_state.ack_strategies,
+            app_state.user_info.as_ref(),
+            app_state.clock.as_ref(),
+            app_state.provisional_purchase_timeout,
+        )
+        .await?;
+        Ok((granted > 0).then(|| format!("Granted {} completed pending purchases", granted)))
+    });
+
+    Ok(())
+}
+
+/// Purchases stored from an RTDN that wait for their owner, due as soon as they are stored
+///
+/// One still waiting after `timeout` was paid for by someone the app never verified or
+/// announced it for, an operator has to find them.
+pub struct ProvisionalPurchaseQueue {
+    timeout: chrono::Duration,
+}
+
+impl ProvisionalPurchaseQueue {
+    pub fn new(timeout: chrono::Duration) -> Self {
+        Self { timeout }
+    }
+
+    /// Timeout from `PROVISIONAL_PURCHASE_TIMEOUT_SECS`, the default if it is invalid as the
+    /// server refuses to start then anyway
+    pub fn from_env() -> Self {
+        Self::new(provisional_timeout_from_env().unwrap_or(DEFAULT_PROVISIONAL_PURCHASE_TIMEOUT))
+    }
+}
+
+impl QueueStats for ProvisionalPurchaseQueue {
+    fn name(&self) -> &'static str {
+        "provisional_purchases"
+    }
+
+    fn max_age(&self) -> chrono::Duration {
+        self.timeout
+    }
+
+    fn snapshot(
+        &self,
+        conn: &mut SqliteConnection,
+        _now: NaiveDateTime,
+    ) -> AppResult<QueueSnapshot> {
+        use crate::schema::purchase_tokens::dsl::*;
+
+        // Ones that ended unclaimed were reported when they did
+        let pending: i64 = purchase_tokens
+            .filter(provisional_since.is_not_null())
+            .filter(status.eq(PurchaseTokenStatus::Pending))
+            .count()
+            .get_result(conn)?;
+        let oldest_due_at: Option<NaiveDateTime> = purchase_tokens
+            .filter(status.eq(PurchaseTokenStatus::Pending))
+            .select(diesel::dsl::min(provisional_since))
+            .first(conn)?;
+
+        Ok(QueueSnapshot {
+            pending,
+            failed: None,
+            oldest_due_at,
+            processed_last_hour: None,
+        })
+    }
+}
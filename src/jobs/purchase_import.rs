@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::acknowledgments::AckStrategies;
+use crate::jobs::pending_purchases::{
+    complete_pending_purchase, pending_purchase_owner, PendingPurchaseOutcome,
+};
+use crate::line_items::access_expiry;
+use crate::model::PurchaseToken;
+use crate::types::{PurchaseEnvironment, PurchaseTokenStatus, SubscriptionState};
+
+/// Columns every import file needs, any others are ignored
+pub const REQUIRED_COLUMNS: [&str; 3] = ["purchase_token", "user_id", "package_name"];
+
+/// One purchase from an import file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportRow {
+    /// Line of the file the row starts on, for the report
+    pub line: usize,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub package_name: String,
+    /// Expiry from the export, rows expired by then are stored without asking Google
+    pub expiry_at: Option<NaiveDateTime>,
+    /// When the purchase was voided (refunded or charged back), if it was
+    pub voided_at: Option<NaiveDateTime>,
+}
+
+/// Counts of what an import did with each row
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Active subscriptions stored with access granted
+    pub granted: usize,
+    /// Subscriptions whose payment is still pending, completed by the pending purchase job
+    pub pending: usize,
+    /// Subscriptions that already ended, stored expired
+    pub expired: usize,
+    /// Voided purchases, stored expired
+    pub voided: usize,
+    /// Tokens already in the database, left as they are
+    pub already_imported: usize,
+    /// Line and reason of each row that wasn't imported, a re-run tries them again
+    pub failed: Vec<(usize, String)>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Imported {} granted, {} pending, {} expired and {} voided purchases, skipped {} already imported, {} failed",
+            self.granted,
+            self.pending,
+            self.expired,
+            self.voided,
+            self.already_imported,
+            self.failed.len()
+        )?;
+        for (line, reason) in &self.failed {
+            write!(f, "\n  line {}: {}", line, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split CSV text into records, with quoted fields that may hold commas, quotes and newlines
+///
+/// Each record comes with the line it starts on. Blank lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|value| !value.trim().is_empty()) {
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                line += 1;
+                record_line = line;
+            }
+            _ => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(format!(
+            "unterminated quote in the record on line {}",
+            record_line
+        ));
+    }
+    record.push(field);
+    if record.iter().any(|value| !value.trim().is_empty()) {
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+/// RFC 3339, or `YYYY-MM-DD HH:MM:SS` taken as UTC
+fn parse_time(value: &str) -> Result<Option<NaiveDateTime>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .map(Some)
+        .map_err(|_| format!("`{}` is not a timestamp", value))
+}
+
+fn row_from_record(
+    line: usize,
+    record: &[String],
+    columns: &HashMap<String, usize>,
+) -> Result<ImportRow, String> {
+    let value = |column: &str| {
+        columns
+            .get(column)
+            .and_then(|index| record.get(*index))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    if let Some(column) = REQUIRED_COLUMNS
+        .iter()
+        .find(|column| value(column).is_empty())
+    {
+        return Err(format!("`{}` is empty", column));
+    }
+    Ok(ImportRow {
+        line,
+        purchase_token: value("purchase_token"),
+        user_id: value("user_id"),
+        package_name: value("package_name"),
+        expiry_at: parse_time(&value("expiry_time"))?,
+        voided_at: parse_time(&value("voided_time"))?,
+    })
+}
+
+/// Read purchases from an export with a header line
+///
+/// Needs the `REQUIRED_COLUMNS`; `expiry_time` and `voided_time` are read when present.
+/// Returns the rows that parsed and the line and reason of those that didn't.
+pub fn parse_import_file(text: &str) -> AppResult<(Vec<ImportRow>, Vec<(usize, String)>)> {
+    let mut records = parse_csv(text)
+        .map_err(|e| AppError::BadRequest(format!("Invalid import file: {}", e)))?
+        .into_iter();
+    let (_, header) = records
+        .next()
+        .ok_or_else(|| AppError::BadRequest("Import file is empty".to_string()))?;
+    let columns: HashMap<String, usize> = header
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.trim().to_ascii_lowercase(), index))
+        .collect();
+    if let Some(missing) = REQUIRED_COLUMNS
+        .iter()
+        .find(|column| !columns.contains_key(**column))
+    {
+        return Err(AppError::BadRequest(format!(
+            "Import file has no `{}` column",
+            missing
+        )));
+    }
+
+    let mut rows = Vec::new();
+    let mut invalid = Vec::new();
+    for (line, record) in records {
+        match row_from_record(line, &record, &columns) {
+            Ok(row) => rows.push(row),
+            Err(reason) => invalid.push((line, reason)),
+        }
+    }
+
+    Ok((rows, invalid))
+}
+
+/// How one row was imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RowOutcome {
+    Granted,
+    Pending,
+    Expired,
+    Voided,
+    AlreadyImported,
+}
+
+fn insert_token(
+    conn: &mut SqliteConnection,
+    row: &ImportRow,
+    status: PurchaseTokenStatus,
+    expiry: NaiveDateTime,
+    environment: PurchaseEnvironment,
+) -> AppResult<PurchaseToken> {
+    use crate::schema::purchase_tokens;
+
+    let mut token = PurchaseToken::new(
+        row.user_id.clone(),
+        row.purchase_token.clone(),
+        expiry,
+        status,
+        environment,
+    );
+    token.package_name = Some(row.package_name.clone());
+    if status == PurchaseTokenStatus::Expired {
+        token.auto_renewing = false;
+    }
+
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(conn)?;
+    Ok(token)
+}
+
+async fn import_row(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    row: &ImportRow,
+    now: NaiveDateTime,
+) -> AppResult<RowOutcome> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let existing: Option<PurchaseToken> = purchase_tokens
+        .filter(purchase_token.eq(&row.purchase_token))
+        .first(conn)
+        .optional()?;
+    // Rows a previous run left pending are asked about again, anything else is settled
+    let existing = match existing {
+        Some(token) if token.status == PurchaseTokenStatus::Pending => Some(token),
+        Some(_) => return Ok(RowOutcome::AlreadyImported),
+        None => None,
+    };
+
+    if existing.is_none() {
+        if let Some(voided_at) = row.voided_at {
+            let environment = PurchaseEnvironment::Production;
+            insert_token(
+                conn,
+                row,
+                PurchaseTokenStatus::Expired,
+                voided_at,
+                environment,
+            )?;
+            return Ok(RowOutcome::Voided);
+        }
+        if let Some(expired_at) = row.expiry_at.filter(|expiry| *expiry <= now) {
+            let environment = PurchaseEnvironment::Production;
+            insert_token(
+                conn,
+                row,
+                PurchaseTokenStatus::Expired,
+                expired_at,
+                environment,
+            )?;
+            return Ok(RowOutcome::Expired);
+        }
+    }
+
+    let subscription_response = google_play
+        .fetch_subscription(&row.package_name, &row.purchase_token)
+        .await?;
+    let token = match existing {
+        Some(token) => token,
+        None => {
+            let ended_at = access_expiry(&subscription_response).unwrap_or(now);
+            let ongoing = matches!(
+                subscription_response.subscription_state,
+                SubscriptionState::Active
+                    | SubscriptionState::InGracePeriod
+                    | SubscriptionState::Pending
+            );
+            if !ongoing {
+                insert_token(
+                    conn,
+                    row,
+                    PurchaseTokenStatus::Expired,
+                    ended_at.min(now),
+                    subscription_response.environment(),
+                )?;
+                return Ok(RowOutcome::Expired);
+            }
+            // Stored pending first, like a verify, so a failed grant is resumed later
+            insert_token(
+                conn,
+                row,
+                PurchaseTokenStatus::Pending,
+                now,
+                subscription_response.environment(),
+            )?
+        }
+    };
+
+    let (account_id, token) = pending_purchase_owner(conn, &token, &subscription_response, now)?;
+    let outcome = complete_pending_purchase(
+        conn,
+        google_play,
+        ack_strategies,
+        user_info,
+        &token,
+        &account_id,
+        &row.package_name,
+        &subscription_response,
+        now,
+    )
+    .await?;
+
+    Ok(match outcome {
+        PendingPurchaseOutcome::Granted => RowOutcome::Granted,
+        PendingPurchaseOutcome::StillPending => RowOutcome::Pending,
+        PendingPurchaseOutcome::Canceled => RowOutcome::Expired,
+    })
+}
+
+/// Import purchases made before this service tracked them
+///
+/// Voided rows and rows whose exported expiry has passed are stored expired. The rest are
+/// verified with Google: active ones are stored and granted like a completed verify, ended
+/// ones are stored expired. Tokens already stored are skipped, except those a failed grant
+/// left pending, so the import can be run again with the same file.
+pub async fn import_purchases(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    text: &str,
+    now: NaiveDateTime,
+) -> AppResult<ImportReport> {
+    let (rows, invalid) = parse_import_file(text)?;
+    let mut report = ImportReport {
+        failed: invalid,
+        ..Default::default()
+    };
+
+    for row in &rows {
+        match import_row(conn, google_play, ack_strategies, user_info, row, now).await {
+            Ok(RowOutcome::Granted) => report.granted += 1,
+            Ok(RowOutcome::Pending) => report.pending += 1,
+            Ok(RowOutcome::Expired) => report.expired += 1,
+            Ok(RowOutcome::Voided) => report.voided += 1,
+            Ok(RowOutcome::AlreadyImported) => report.already_imported += 1,
+            // One failing row must not stop the import
+            Err(e) => report.failed.push((row.line, e.to_string())),
+        }
+    }
+    report.failed.sort();
+
+    Ok(report)
+}
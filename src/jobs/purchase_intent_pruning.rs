@@ -0,0 +1,27 @@
+use crate::config::ConfigError;
+use crate::profiles::prune_purchase_intents;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Delete expired purchase intents on startup and then on its schedule
+///
+/// Scheduled by `PURCHASE_INTENT_PRUNE_SCHEDULE`, or every
+/// `PURCHASE_INTENT_PRUNE_INTERVAL_SECS` (default 3600).
+pub fn register_purchase_intent_pruning_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env(
+        "PURCHASE_INTENT_PRUNE_SCHEDULE",
+        "PURCHASE_INTENT_PRUNE_INTERVAL_SECS",
+        3600,
+    )?;
+
+    scheduler.register(
+        "purchase_intent_pruning",
+        schedule,
+        move |app_state| async move {
+            let mut conn = app_state.get_db_connection()?;
+            let pruned = prune_purchase_intents(&mut conn, app_state.clock.now_naive())?;
+            Ok((pruned > 0).then(|| format!("Pruned {} expired purchase intents", pruned)))
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use crate::config::ConfigError;
+use crate::queues::check_queues;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Export queue depths and ages on startup and then on its schedule, reporting stalled queues
+///
+/// Scheduled by `QUEUE_MONITOR_SCHEDULE`, or every `QUEUE_MONITOR_INTERVAL_SECS` (default 60).
+pub fn register_queue_monitor_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let schedule = Schedule::from_env("QUEUE_MONITOR_SCHEDULE", "QUEUE_MONITOR_INTERVAL_SECS", 60)?;
+
+    scheduler.register("queue_monitor", schedule, |app_state| async move {
+        let mut conn = app_state.get_db_connection()?;
+        let stalled = check_queues(&mut conn, &app_state.metrics, app_state.clock.now_naive())?;
+        Ok((!stalled.is_empty()).then(|| {
+            let names: Vec<&str> = stalled.iter().map(|queue| queue.queue.as_str()).collect();
+            format!("Stalled queues: {}", names.join(", "))
+        }))
+    });
+
+    Ok(())
+}
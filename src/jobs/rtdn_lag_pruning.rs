@@ -0,0 +1,25 @@
+use crate::config::{env_number, ConfigError};
+use crate::rtdn_lag::prune_rtdn_lag_samples;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Delete RTDN lag samples past their retention on startup and then on its schedule
+///
+/// Samples are kept for `RTDN_LAG_RETENTION_DAYS` (default 7). Scheduled by
+/// `RTDN_LAG_PRUNE_SCHEDULE`, or every `RTDN_LAG_PRUNE_INTERVAL_SECS` (default 3600).
+pub fn register_rtdn_lag_pruning_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let retention_days: i64 = env_number("RTDN_LAG_RETENTION_DAYS", 7)?;
+    let schedule = Schedule::from_env(
+        "RTDN_LAG_PRUNE_SCHEDULE",
+        "RTDN_LAG_PRUNE_INTERVAL_SECS",
+        3600,
+    )?;
+
+    scheduler.register("rtdn_lag_pruning", schedule, move |app_state| async move {
+        let cutoff = app_state.clock.now_naive() - chrono::Duration::days(retention_days);
+        let mut conn = app_state.get_db_connection()?;
+        let pruned = prune_rtdn_lag_samples(&mut conn, cutoff)?;
+        Ok((pruned > 0).then(|| format!("Pruned {} RTDN lag samples", pruned)))
+    });
+
+    Ok(())
+}
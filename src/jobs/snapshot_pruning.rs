@@ -0,0 +1,25 @@
+use crate::config::{env_number, ConfigError};
+use crate::integrations::google_play::snapshots::prune_snapshots;
+use crate::scheduler::{Schedule, Scheduler};
+
+/// Delete subscription snapshots past their retention on startup and then on its schedule
+///
+/// Snapshots are kept for `SUBSCRIPTION_SNAPSHOT_RETENTION_DAYS` (default 90). Scheduled by
+/// `SNAPSHOT_PRUNE_SCHEDULE`, or every `SNAPSHOT_PRUNE_INTERVAL_SECS` (default 86400).
+pub fn register_snapshot_pruning_job(scheduler: &mut Scheduler) -> Result<(), ConfigError> {
+    let retention_days: i64 = env_number("SUBSCRIPTION_SNAPSHOT_RETENTION_DAYS", 90)?;
+    let schedule = Schedule::from_env(
+        "SNAPSHOT_PRUNE_SCHEDULE",
+        "SNAPSHOT_PRUNE_INTERVAL_SECS",
+        86400,
+    )?;
+
+    scheduler.register("snapshot_pruning", schedule, move |app_state| async move {
+        let cutoff = app_state.clock.now_naive() - chrono::Duration::days(retention_days);
+        let mut conn = app_state.get_db_connection()?;
+        let pruned = prune_snapshots(&mut conn, cutoff)?;
+        Ok((pruned > 0).then(|| format!("Pruned {} subscription snapshots", pruned)))
+    });
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Elects the one replica that runs scheduled jobs
+//!
+//! The leader holds a lease under `scheduler:leader` in the cache and renews it every third of
+//! `SCHEDULER_LEADER_LEASE_SECS` (default 30). Only the leader starts scheduled jobs, the
+//! others keep trying to take the lease. `GET /admin/cluster` reports who holds it.
+//!
+//! Failover:
+//! - A leader that dies or loses the cache stops renewing, and another replica takes over
+//!   within one lease. A leader that can't renew steps down at once rather than risk two
+//!   leaders.
+//! - Job runs the old leader had started keep their lease in `scheduled_jobs` until
+//!   `SCHEDULER_LEASE_SECS` runs out, so the new leader never starts the same job twice.
+//! - While the cache is unreachable no replica leads and jobs don't run. Requests are served
+//!   as usual.
+//! - The election only spans replicas when the cache is shared, i.e. `REDIS_URL` is set.
+//!   Without it every replica leads itself and the job leases alone keep replicas sharing a
+//!   database from running a job together.
+
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::config::{env_interval_secs, ConfigError};
+
+/// Cache key of the leader's lease
+pub const LEADER_KEY: &str = "scheduler:leader";
+
+const UNKNOWN: u8 = 0;
+const LEADING: u8 = 1;
+const FOLLOWING: u8 = 2;
+
+/// This replica's part in the scheduler leader election
+#[derive(Clone)]
+pub struct LeaderElection {
+    cache: Arc<dyn Cache>,
+    /// Identifies this replica in the leader lease and job leases
+    holder: String,
+    lease: Duration,
+    /// Outcome of the last renewal
+    state: Arc<AtomicU8>,
+}
+
+impl LeaderElection {
+    pub fn new(cache: Arc<dyn Cache>, holder: String, lease: Duration) -> Self {
+        Self {
+            cache,
+            holder,
+            lease,
+            state: Arc::new(AtomicU8::new(UNKNOWN)),
+        }
+    }
+
+    /// Holder named after `HOSTNAME`, leases `SCHEDULER_LEADER_LEASE_SECS` (default 30) long
+    pub fn from_env(cache: Arc<dyn Cache>) -> Result<Self, ConfigError> {
+        let lease = env_interval_secs("SCHEDULER_LEADER_LEASE_SECS", 30)?;
+        // Pod name on Kubernetes, made unique for restarts under the same name
+        let host = env::var("HOSTNAME").unwrap_or_else(|_| "replica".to_string());
+        let holder = format!("{}-{}", host, &uuid::Uuid::new_v4().to_string()[..8]);
+        Ok(Self::new(cache, holder, lease))
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    pub fn lease(&self) -> Duration {
+        self.lease
+    }
+
+    /// Whether the election spans the other replicas
+    pub fn is_cluster_wide(&self) -> bool {
+        self.cache.is_shared()
+    }
+
+    /// Take the lease if it is free or renew it if it is ours, returns whether we lead
+    pub async fn renew(&self) -> bool {
+        let renewed = match self
+            .cache
+            .extend_if_equal(LEADER_KEY, &self.holder, self.lease)
+            .await
+        {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                self.cache
+                    .set_if_absent(LEADER_KEY, &self.holder, self.lease)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let leading = match renewed {
+            Ok(leading) => leading,
+            Err(e) => {
+                eprintln!("Failed to renew the scheduler leader lease: {}", e);
+                false
+            }
+        };
+
+        let state = if leading { LEADING } else { FOLLOWING };
+        let previous = self.state.swap(state, Ordering::SeqCst);
+        if previous != state {
+            if leading {
+                println!("{} is now the scheduler leader", self.holder);
+            } else if previous == LEADING {
+                println!("{} stepped down as scheduler leader", self.holder);
+            }
+        }
+        leading
+    }
+
+    /// Whether we led at the last renewal, renewing first if there was none yet
+    pub async fn is_leader(&self) -> bool {
+        match self.state.load(Ordering::SeqCst) {
+            UNKNOWN => self.renew().await,
+            state => state == LEADING,
+        }
+    }
+
+    /// Holder of the lease right now, `None` while no replica leads
+    pub async fn current_leader(&self) -> Option<String> {
+        match self.cache.get(LEADER_KEY).await {
+            Ok(leader) => leader,
+            Err(e) => {
+                eprintln!("Failed to read the scheduler leader: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Renew the lease every third of its length, for as long as the process runs
+    pub fn spawn_renewal(&self) {
+        let election = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(election.lease / 3);
+            loop {
+                ticker.tick().await;
+                election.renew().await;
+            }
+        });
+    }
+}
@@ -1,118 +1,312 @@
+pub mod analytics;
+pub mod api_version;
 pub mod auth;
+pub mod auth_policy;
+pub mod cache;
+pub mod cli;
+pub mod clock;
+pub mod config;
 pub mod consts;
+pub mod db;
+pub mod debug_log;
+pub mod entitlement_token;
 pub mod error;
+pub mod events;
+pub mod feature_flags;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ic_identity;
+pub mod integrations;
+pub mod jobs;
+pub mod leader;
+pub mod line_items;
+pub mod messages;
+pub mod metrics;
 pub mod model;
+pub mod notifier;
+pub mod plans;
+pub mod profiles;
+pub mod quarantine;
+pub mod queues;
+pub mod request_limits;
+pub mod risk;
+pub mod route_exposure;
 pub mod routes;
+pub mod rtdn_lag;
+pub mod scheduler;
 pub mod schema;
+pub mod seed;
+pub mod self_test;
+pub mod service_auth;
+pub mod shadow;
+pub mod smoke_test;
+pub mod subscriptions;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod token_locks;
+pub mod token_state;
 pub mod types;
+pub mod user_id;
 
-use auth::{jwt_auth_middleware, GoogleAuth};
+use analytics::{AnalyticsConsumer, AnalyticsSink};
+use api_version::{negotiate_version, ApiVersion};
+use auth_policy::enforce_auth_policy;
 use axum::{
+    extract::Request,
     http::StatusCode,
-    middleware,
+    middleware::{self, Next},
     response::{Html, IntoResponse, Json, Redirect},
     routing::{get, post},
     Router,
 };
+use cache::{cache_from_env, Cache};
+use clap::Parser;
+use cli::{Cli, Command};
+use clock::{Clock, SystemClock};
+use config::{env_number, exit_on_startup_error, ConfigError};
+use db::{connect_pool, database_url_from_env};
+use debug_log::{capture_requests, DebugLog};
+use entitlement_token::EntitlementSigner;
+use error::panic_response;
+use events::{spawn_event_dispatcher, EventBus};
+use leader::LeaderElection;
+use messages::localize_messages;
 
 use diesel::{
     prelude::*,
     r2d2::{ConnectionManager, Pool, PooledConnection},
 };
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use feature_flags::FeatureFlags;
+use integrations::google_play::{CachingGooglePlay, GooglePlayApi, SubscriptionSnapshots};
+use integrations::play_integrity::PlayIntegrityApi;
+use integrations::push_auth::PushVerifier;
+use integrations::user_info::{CachingUserInfo, UserInfoApi};
+use integrations::{IntegrationMode, Integrations};
+use jobs::access_outbox::register_access_outbox_worker;
+use jobs::acknowledgments::{register_acknowledgment_monitor_job, AckStrategies};
+use jobs::analytics_export::register_analytics_export_job;
+use jobs::catalog_sync::register_catalog_sync_job;
+use jobs::credit_ledger_check::register_credit_ledger_check_job;
+use jobs::db_maintenance::register_db_maintenance_job;
+use jobs::event_redelivery::register_event_redelivery_job;
+use jobs::expiry_reminders::register_expiry_reminder_job;
+use jobs::expiry_sweep::register_expiry_sweep_job;
+use jobs::grace_reminders::register_grace_reminder_job;
+use jobs::held_notifications::register_held_notification_job;
+use jobs::pending_purchases::{provisional_timeout_from_env, register_pending_purchase_job};
+use jobs::purchase_intent_pruning::register_purchase_intent_pruning_job;
+use jobs::queue_monitor::register_queue_monitor_job;
+use jobs::rtdn_lag_pruning::register_rtdn_lag_pruning_job;
+use jobs::snapshot_pruning::register_snapshot_pruning_job;
+use metrics::Metrics;
+use notifier::Notifier;
+use plans::CreditAllotments;
+use quarantine::QuarantinePolicy;
+use request_limits::{enforce_json_body, DEFAULT_JSON_BODY_LIMIT, RTDN_BODY_LIMIT};
+use route_exposure::{enforce_route_exposure, Listener, RouteExposure};
+use routes::access::{defer_subscription, grant_access, reverify_subscription, revoke_access};
+use routes::admin::{
+    check_db_integrity, get_cluster, get_ic_identity, get_queue_stats, get_token_diff,
+    list_debug_log, list_feature_flags, list_revenue_events, list_scheduled_jobs,
+    list_subscription_line_items, list_subscription_snapshots, list_tokens, lookup_user_plans,
+    reload_ic_identity, set_feature_flag,
+};
+use routes::cancel_intent::record_cancel_intent;
+use routes::catalog::{get_catalog, sync_catalog};
 use routes::chat_access::{check_chat_access, grant_chat_access};
-use routes::credits::{deduct_credits, increment_credits};
-use routes::purchase::verify_purchase;
+use routes::compensations::{create_compensation, get_compensation, resume_compensation};
+use routes::credits::{deduct_credits, get_credit_ledger, increment_credits};
+use routes::entitlements::{get_entitlements, get_jwks, issue_entitlement_token};
+use routes::export::{export_events, export_tokens};
+use routes::history::get_billing_history;
+use routes::introspect::introspect;
+use routes::invoices::{export_invoices, get_invoices};
+use routes::manage::get_manage_url;
+use routes::metrics::get_metrics;
+use routes::offers::get_offer_conversions;
+use routes::orders::export_orders;
+use routes::profiles::{record_intent, register_purchase_profile};
+use routes::purchase::{preview_verify_purchase, verify_purchase};
+use routes::quarantine::{
+    approve_quarantined_verification, list_quarantined_verifications,
+    reject_quarantined_verification,
+};
+use routes::refunds::{act_on_refund_request, create_refund_request, list_refund_requests};
+use routes::risk::{approve_flagged_user, list_flagged_users};
 use routes::rtdn::handle_rtdn_webhook;
+use routes::stats::{get_admin_stats, get_rtdn_lag_stats};
+use routes::teardown::teardown_user_subscriptions;
+use routes::transfer::transfer_purchase_tokens;
+use routes::user_tokens::list_user_tokens;
+use scheduler::Scheduler;
+use service_auth::ServiceAuth;
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use token_locks::TokenLocks;
+use tower_http::catch_panic::CatchPanicLayer;
 use types::{
-    AckData, AckRequest, ApiResponse, BotChatAccessStatus, ChatAccessResponse, CreditRequest,
-    EmptyData, GrantChatAccessRequest, PurchaseTokenStatus, VerifyRequest,
+    AckData, AckRequest, AcknowledgementState, AdminStatsResponse, ApiResponse,
+    ApproveUserRiskRequest, AuditAction, BillingHistoryEntry, BillingHistoryResponse,
+    BotChatAccessStatus, BotChatEntitlement, CancelIntentRequest, CancelIntentResponse,
+    CancelReason, CatalogProductResponse, CatalogResponse, CatalogSyncRequest, ChatAccessResponse,
+    ClusterResponse, CompensationBatchResponse, CompensationBatchStatus, CompensationFilter,
+    CompensationGrantResponse, CompensationGrantStatus, CompensationKind,
+    CreateCompensationRequest, CreateRefundRequest, CreditDirection, CreditLedgerEntryResponse,
+    CreditLedgerResponse, CreditReason, CreditRequest, DbIntegrityResponse, DebugLogDirection,
+    DebugLogEntry, DeferSubscriptionRequest, DeferSubscriptionResponse, EmptyData,
+    EntitlementResponse, EntitlementTokenRequest, EntitlementTokenResponse, ErrorCode,
+    ExportFormat, FeatureFlagResponse, FeatureFlagSource, GrantChatAccessRequest,
+    IcIdentityResponse, IntrospectRequest, IntrospectResponse, InvoiceResponse, JobOutcome,
+    ManageUrlResponse, ManualAccessResponse, ManualGrantRequest, ManualRevokeRequest,
+    OfferConversionResponse, OrderResponse, Plan, PurchaseEnvironment, PurchaseIntentRequest,
+    PurchaseIntentResponse, PurchaseTokenStatus, QuarantineDecisionRequest, QuarantineReason,
+    QuarantineResponse, QuarantineStatus, RefundRequestAction, RefundRequestActionRequest,
+    RefundRequestResponse, RefundRequestStatus, RegisterProfileRequest, RegisterProfileResponse,
+    RevenueEventExportRecord, RevenueTotal, ReverifyResponse, ReverifyResult, RtdnLagStats,
+    RtdnLagStatsResponse, ScheduledJobResponse, SetFeatureFlagRequest, SourceStore,
+    SubscriptionEventKind, SubscriptionLineItemResponse, SubscriptionSnapshotResponse,
+    SubscriptionState, TeardownUserRequest, TeardownUserResponse, TokenDiffResponse,
+    TokenExportRecord, TokenMismatch, TokenMismatchKind, TransferTokensRequest,
+    TransferTokensResponse, UserPlanResponse, UserPlansRequest, UserRiskResponse,
+    UserTokenResponse, VerifyPreviewOutcome, VerifyPreviewResponse, VerifyRequest,
 };
 use utoipa::OpenApi;
 
-use crate::{auth::GooglePublicKey, error::AppError, types::VerifyResponse};
+use crate::{error::AppError, ic_identity::AdminIdentity, types::VerifyResponse};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
 #[derive(Clone)]
 pub struct AppState {
-    pub google_auth: Option<Arc<GoogleAuth>>,
-    pub admin_ic_agent: Option<ic_agent::Agent>,
-    pub google_public_key: Arc<GooglePublicKey>,
+    /// Whether the services below are live or mocked
+    pub integration_mode: IntegrationMode,
+    pub google_play: Arc<dyn GooglePlayApi>,
+    pub user_info: Arc<dyn UserInfoApi>,
+    pub push_verifier: Arc<dyn PushVerifier>,
+    /// Decodes the Play Integrity tokens sent with verify
+    pub play_integrity: Arc<dyn PlayIntegrityApi>,
+    /// Rotatable identity backing the live `user_info` client, absent in mock mode
+    pub admin_identity: Option<AdminIdentity>,
     pub db_connection: Pool<ConnectionManager<SqliteConnection>>,
+    /// Runtime switches for risky billing behaviors
+    pub feature_flags: FeatureFlags,
+    /// Time source for expiry checks, swappable in tests
+    pub clock: Arc<dyn Clock>,
+    /// Outbound event delivery for user-facing notifications
+    pub notifier: Notifier,
+    /// Analytics ingestion endpoint, absent unless `ANALYTICS_INGEST_URL` is set
+    pub analytics: AnalyticsSink,
+    /// Domain events for side effects that don't need to run in the request path
+    pub events: EventBus,
+    /// Counters exported on `/metrics`
+    pub metrics: Metrics,
+    /// Redacted bodies for `/admin/debug/requests`, off unless `debug_request_log` is on
+    pub debug_log: DebugLog,
+    /// Shared secrets of internal services allowed to sign requests
+    pub service_auth: ServiceAuth,
+    /// Serializes RTDN processing per purchase token
+    pub token_locks: TokenLocks,
+    /// How long state-regressing RTDNs wait for earlier ones before being applied, zero for never
+    pub rtdn_hold_window: chrono::Duration,
+    /// Revocations plus voided purchases at which a user is flagged for review
+    pub risk_threshold: u32,
+    /// Cancellation intents a user may report per hour
+    pub cancel_intent_limit: u32,
+    /// How long a purchase intent resolves its purchase's owner
+    pub purchase_intent_ttl: chrono::Duration,
+    /// How long a provisional purchase may wait for its owner before it is reported
+    pub provisional_purchase_timeout: chrono::Duration,
+    /// Limits past which verifications are quarantined while quarantine mode is on
+    pub quarantine_policy: QuarantinePolicy,
+    /// Granted tokens this close to expiry are re-checked with Google on verify
+    pub expiry_refresh_window: chrono::Duration,
+    /// Video credits each plan grants per billing period
+    pub credit_allotments: CreditAllotments,
+    /// State shared across replicas, in Redis when `REDIS_URL` is set
+    pub cache: Arc<dyn Cache>,
+    /// Decides which replica runs scheduled jobs
+    pub leader: LeaderElection,
+    /// Whether we or the app acknowledge each package's purchases with Google
+    pub ack_strategies: AckStrategies,
+    /// Signs entitlement tokens, absent unless `ENTITLEMENT_SIGNING_KEY` is set
+    pub entitlement_signer: Option<EntitlementSigner>,
 }
 //
 impl AppState {
+    /// Build the state, exiting the process if the configuration is invalid
     pub async fn new() -> Self {
-        let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "billing.db".to_string());
-        let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-        let pool = Pool::builder()
-            .build(manager)
-            .expect("Failed to create database connection pool");
-
-        let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "billing.db".to_string());
-        if let Err(e) = run_migrations(&database_url) {
-            sentry::capture_message(
-                &format!("Failed to run migrations: {}", e),
-                sentry::Level::Error,
-            );
-            eprintln!("Failed to run migrations: {}", e);
-            std::process::exit(1);
+        match Self::try_new().await {
+            Ok(app_state) => app_state,
+            Err(e) => exit_on_startup_error(&e),
         }
+    }
 
-        // Initialize Google Auth (only for production, not for local/mock features)
-        let google_auth = if cfg!(feature = "local") {
-            None
-        } else {
-            match GoogleAuth::from_env() {
-                Ok(auth) => {
-                    println!("Google Auth initialized successfully");
-                    Some(Arc::new(auth))
-                }
-                Err(e) => {
-                    sentry::capture_message(
-                        &format!("Failed to initialize Google Auth: {}", e),
-                        sentry::Level::Error,
-                    );
-                    eprintln!("Failed to initialize Google Auth: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        };
-
-        let admin_ic_agent = if cfg!(feature = "local") {
-            None
-        } else {
-            let backend_admin_secret_key = env::var("BACKEND_ADMIN_SECRET_KEY")
-                .expect("expect backend admin canister key to be present");
-
-            let identity = match ic_agent::identity::Secp256k1Identity::from_pem(
-                stringreader::StringReader::new(backend_admin_secret_key.as_str()),
-            ) {
-                Ok(identity) => identity,
-                Err(err) => {
-                    panic!("Unable to create identity, error: {err:?}");
-                }
-            };
+    pub async fn try_new() -> Result<Self, ConfigError> {
+        Self::try_with_database(&database_url_from_env()).await
+    }
 
-            let admin_ic_agent = ic_agent::Agent::builder()
-                .with_url("https://ic0.app")
-                .with_identity(identity)
-                .build()
-                .expect("Failed to create IC agent for admin canister");
-            Some(admin_ic_agent)
-        };
+    /// Build the state on `database_url` instead of `DATABASE_URL`, e.g. `MEMORY_DATABASE_URL`
+    pub async fn try_with_database(database_url: &str) -> Result<Self, ConfigError> {
+        let pool = connect_pool(database_url)?;
 
-        let google_public_key = GooglePublicKey::new()
-            .await
-            .expect("Failed to fetch google public key");
+        let feature_flags = pool
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| FeatureFlags::load(&mut conn).map_err(|e| e.to_string()))
+            .map_err(ConfigError::Database)?;
+        let debug_log = DebugLog::from_env(feature_flags.clone())?;
+
+        let metrics = Metrics::new();
+        let integrations = Integrations::from_env(
+            debug_log.clone(),
+            SubscriptionSnapshots::new(pool.clone()),
+            metrics.clone(),
+        )
+        .await
+        .map_err(ConfigError::Integrations)?;
+        println!("Using {} integrations", integrations.mode);
+
+        let cache = cache_from_env().await?;
+        let google_play = CachingGooglePlay::wrap_from_env(
+            integrations.google_play,
+            metrics.clone(),
+            cache.clone(),
+        )?;
+        let user_info =
+            CachingUserInfo::wrap_from_env(integrations.user_info, metrics.clone(), cache.clone())?;
 
-        AppState {
-            google_auth,
-            admin_ic_agent,
-            google_public_key: Arc::new(google_public_key),
+        Ok(AppState {
+            integration_mode: integrations.mode,
+            google_play,
+            user_info,
+            push_verifier: integrations.push_verifier,
+            play_integrity: integrations.play_integrity,
+            admin_identity: integrations.admin_identity,
             db_connection: pool,
-        }
+            feature_flags,
+            clock: Arc::new(SystemClock),
+            notifier: Notifier::from_env(),
+            analytics: AnalyticsSink::from_env()?,
+            events: EventBus::new(),
+            metrics,
+            debug_log,
+            service_auth: ServiceAuth::from_env()?.with_cache(cache.clone()),
+            token_locks: TokenLocks::default(),
+            rtdn_hold_window: routes::rtdn::hold_window_from_env()?,
+            risk_threshold: risk::threshold_from_env()?,
+            cancel_intent_limit: routes::cancel_intent::limit_from_env()?,
+            purchase_intent_ttl: profiles::intent_ttl_from_env()?,
+            provisional_purchase_timeout: provisional_timeout_from_env()?,
+            quarantine_policy: QuarantinePolicy::from_env()?,
+            expiry_refresh_window: routes::purchase::refresh_window_from_env()?,
+            credit_allotments: CreditAllotments::from_env()?,
+            leader: LeaderElection::from_env(cache.clone())?,
+            cache,
+            ack_strategies: AckStrategies::from_env()?,
+            entitlement_signer: EntitlementSigner::from_env()?,
+        })
     }
 
     /// Get a database connection
@@ -129,30 +323,118 @@ impl AppState {
 #[openapi(
     paths(
         routes::purchase::verify_purchase,
+        routes::purchase::preview_verify_purchase,
         routes::credits::deduct_credits,
         routes::credits::increment_credits,
+        routes::credits::get_credit_ledger,
         routes::chat_access::grant_chat_access,
         routes::chat_access::check_chat_access,
+        routes::manage::get_manage_url,
+        routes::cancel_intent::record_cancel_intent,
+        routes::profiles::register_purchase_profile,
+        routes::profiles::record_intent,
+        routes::transfer::transfer_purchase_tokens,
+        routes::admin::get_ic_identity,
+        routes::admin::reload_ic_identity,
+        routes::refunds::create_refund_request,
+        routes::refunds::list_refund_requests,
+        routes::refunds::act_on_refund_request,
+        routes::entitlements::get_entitlements,
+        routes::entitlements::issue_entitlement_token,
+        routes::history::get_billing_history,
+        routes::user_tokens::list_user_tokens,
+        routes::invoices::get_invoices,
+        routes::orders::export_orders,
+        routes::offers::get_offer_conversions,
+        routes::invoices::export_invoices,
+        routes::export::export_tokens,
+        routes::export::export_events,
+        routes::admin::list_tokens,
+        routes::admin::get_token_diff,
+        routes::admin::list_revenue_events,
+        routes::admin::lookup_user_plans,
+        routes::stats::get_admin_stats,
+        routes::stats::get_rtdn_lag_stats,
+        routes::metrics::get_metrics,
+        routes::admin::list_feature_flags,
+        routes::admin::set_feature_flag,
+        routes::admin::list_debug_log,
+        routes::admin::list_scheduled_jobs,
+        routes::admin::get_cluster,
+        routes::admin::get_queue_stats,
+        routes::admin::check_db_integrity,
+        routes::admin::list_subscription_snapshots,
+        routes::admin::list_subscription_line_items,
+        routes::access::grant_access,
+        routes::access::revoke_access,
+        routes::access::defer_subscription,
+        routes::access::reverify_subscription,
+        routes::compensations::create_compensation,
+        routes::compensations::get_compensation,
+        routes::compensations::resume_compensation,
+        routes::catalog::get_catalog,
+        routes::catalog::sync_catalog,
+        routes::risk::list_flagged_users,
+        routes::risk::approve_flagged_user,
+        routes::quarantine::list_quarantined_verifications,
+        routes::quarantine::approve_quarantined_verification,
+        routes::quarantine::reject_quarantined_verification,
+        routes::teardown::teardown_user_subscriptions,
+        routes::introspect::introspect,
         health_check
     ),
     components(
         schemas(
-            ApiResponse<EmptyData>, EmptyData, VerifyRequest, VerifyResponse, AckRequest, AckData,
-            PurchaseTokenStatus, CreditRequest,
-            GrantChatAccessRequest, ChatAccessResponse, BotChatAccessStatus
+            ApiResponse<EmptyData>, EmptyData, ErrorCode, VerifyRequest, VerifyResponse, AckRequest, AckData,
+            PurchaseTokenStatus, PurchaseEnvironment, CreditRequest,
+            GrantChatAccessRequest, ChatAccessResponse, BotChatAccessStatus, ManageUrlResponse,
+            CancelIntentRequest, CancelIntentResponse, CancelReason,
+            RegisterProfileRequest, RegisterProfileResponse, PurchaseIntentRequest,
+            PurchaseIntentResponse,
+            TransferTokensRequest, TransferTokensResponse, IcIdentityResponse,
+            CreateRefundRequest, RefundRequestAction, RefundRequestActionRequest,
+            RefundRequestResponse, RefundRequestStatus, EntitlementResponse, Plan, SourceStore,
+            BotChatEntitlement, SubscriptionState, AcknowledgementState, ExportFormat,
+            OrderResponse, OfferConversionResponse, FeatureFlagResponse, FeatureFlagSource, SetFeatureFlagRequest,
+            VerifyPreviewOutcome, VerifyPreviewResponse, AdminStatsResponse, RevenueTotal, RtdnLagStats, RtdnLagStatsResponse,
+            DebugLogEntry, DebugLogDirection, ManualGrantRequest, ManualRevokeRequest,
+            ManualAccessResponse, AuditAction, DeferSubscriptionRequest, DeferSubscriptionResponse,
+            CatalogProductResponse, CatalogResponse, CatalogSyncRequest, UserRiskResponse,
+            ApproveUserRiskRequest, TokenExportRecord, RevenueEventExportRecord,
+            TokenDiffResponse, TokenMismatch, TokenMismatchKind,
+            QuarantineReason, QuarantineStatus, QuarantineResponse, QuarantineDecisionRequest,
+            SubscriptionSnapshotResponse, SubscriptionLineItemResponse, BillingHistoryEntry, BillingHistoryResponse,
+            UserTokenResponse, SubscriptionEventKind, TeardownUserRequest, TeardownUserResponse, IntrospectRequest,
+            IntrospectResponse, InvoiceResponse,
+            CompensationKind, CompensationBatchStatus, CompensationGrantStatus,
+            CompensationFilter, CreateCompensationRequest, CompensationGrantResponse,
+            CompensationBatchResponse, ScheduledJobResponse, JobOutcome, ClusterResponse, QueueStatsResponse, UserPlansRequest,
+            UserPlanResponse, DbIntegrityResponse, ReverifyResult, ReverifyResponse,
+            CreditDirection, CreditReason, CreditLedgerEntryResponse, CreditLedgerResponse,
+            EntitlementTokenRequest, EntitlementTokenResponse
         )
     ),
+    servers(
+        (url = "/v1", description = "API version 1"),
+        (url = "/", description = "Legacy unversioned paths, served as version 1")
+    ),
     modifiers(&SecurityAddon),
     tags(
         (name = "Subscription Verification", description = "Google Play subscription verification endpoints"),
         (name = "Credits", description = "User credit management endpoints"),
         (name = "Chat Access", description = "Bot chat access grant and check endpoints"),
+        (name = "Token Transfer", description = "Purchase token ownership transfer for account merges"),
+        (name = "Refunds", description = "Refund request intake and review"),
+        (name = "Entitlements", description = "Normalized user entitlements for other backends"),
+        (name = "Billing History", description = "Purchase history shown to users in the app"),
+        (name = "Admin", description = "Operational endpoints for the billing service"),
+        (name = "Internal", description = "Endpoints for other backends, signed with a service key"),
         (name = "Health", description = "Health check endpoints")
     ),
     info(
         title = "YRAL Billing API",
         version = "1.0.0",
-        description = "API for handling Google Play subscription billing operations and user credit management",
+        description = "API for handling Google Play subscription billing operations and user credit management. Routes are versioned under `/v1`, and the `x-api-version` response header names the version that served a request.",
         contact(
             name = "YRAL Team",
             url = "https://yral.com"
@@ -173,7 +455,16 @@ impl utoipa::Modify for SecurityAddon {
                         utoipa::openapi::security::HttpAuthScheme::Bearer,
                     ),
                 ),
-            )
+            );
+            // See `service_auth`: the signature goes with `x-yral-service` and `x-yral-timestamp`
+            components.add_security_scheme(
+                "service_hmac",
+                utoipa::openapi::security::SecurityScheme::ApiKey(
+                    utoipa::openapi::security::ApiKey::Header(
+                        utoipa::openapi::security::ApiKeyValue::new(service_auth::SIGNATURE_HEADER),
+                    ),
+                ),
+            );
         }
     }
 }
@@ -198,12 +489,30 @@ async fn swagger_ui() -> impl IntoResponse {
     Html(include_str!("../static/swagger.html"))
 }
 
+/// Support page searching tokens and events by user, the data comes from the admin JSON routes
+///
+/// The page itself holds nothing, so it skips the auth policy; every call it makes carries the
+/// operator's JWT. It is still only served where the admin routes are, see `route_exposure`.
+async fn admin_ui() -> impl IntoResponse {
+    Html(include_str!("../static/admin.html"))
+}
+
 async fn root_redirect() -> Redirect {
     Redirect::permanent("/explore")
 }
 
 pub fn run() {
-    tokio::runtime::Runtime::new().unwrap().block_on(async {
+    let cli = Cli::parse();
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the async runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    runtime.block_on(async {
         // Initialize Sentry
         let _guard = sentry::init((
             "https://d63e426c9935ab2cdaedfd53060f23e7@apm.yral.com/17",
@@ -219,46 +528,356 @@ pub fn run() {
             },
         ));
 
-        // Run database migrations on startup
-        let app_state = AppState::new().await;
-        // Create protected routes with JWT middleware
-        let protected_routes = Router::new()
-            .route("/credits/deduct", post(deduct_credits))
-            .route("/credits/increment", post(increment_credits))
-            .layer(middleware::from_fn(jwt_auth_middleware));
-
-        let app = Router::new()
-            .route("/", get(root_redirect))
-            .route("/health", get(health_check))
-            .route("/google/verify", post(verify_purchase))
-            .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
-            .route("/google/chat-access/grant", post(grant_chat_access))
-            .route("/google/chat-access/check", get(check_chat_access))
-            .route("/api-doc/openapi.json", get(openapi_spec))
-            .route("/explore", get(swagger_ui))
-            .merge(protected_routes)
-            .with_state(app_state);
-
-        let port: u16 = env::var("PORT")
-            .unwrap_or_else(|_| "3000".to_string())
-            .parse()
-            .expect("PORT must be a valid number");
-
-        let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        println!("Listening on {}", addr);
-
-        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-        axum::serve(listener, app.into_make_service())
-            .await
-            .unwrap();
+        match cli.command() {
+            Command::Serve => {
+                if let Err(e) = serve().await {
+                    exit_on_startup_error(&e);
+                }
+            }
+            Command::Migrate => {
+                if let Err(e) = run_migrations(&database_url_from_env()) {
+                    exit_on_startup_error(&ConfigError::Migrations(e.to_string()));
+                }
+            }
+            // Validate credentials and connectivity without serving, for deploy pipelines
+            Command::SelfTest => {
+                let report = self_test::run_self_test().await;
+                println!("{}", report);
+                std::process::exit(if report.passed() { 0 } else { 1 });
+            }
+            // Gate traffic cutover on a deployment answering its API end to end
+            Command::SmokeTest {
+                base_url,
+                principal,
+            } => {
+                let mut target = smoke_test::SmokeTarget::new(&base_url);
+                if let Some(principal) = principal {
+                    target.principal = principal;
+                }
+                let report = smoke_test::run_smoke_test(&target).await;
+                println!("{}", report);
+                std::process::exit(if report.passed() { 0 } else { 1 });
+            }
+            // Fill a local database with sample purchases, audit entries and catalog rows
+            Command::Seed => match seed::run_seed() {
+                Ok(report) => println!("{}", report),
+                Err(e) => {
+                    eprintln!("Seeding failed: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            command => {
+                let app_state = match AppState::try_new().await {
+                    Ok(app_state) => app_state,
+                    Err(e) => exit_on_startup_error(&e),
+                };
+                match cli::run_maintenance(&command, &app_state).await {
+                    Ok(summary) => println!("{}", summary),
+                    Err(e) => {
+                        eprintln!("{:?} failed: {}", command, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
     });
 }
 
-fn run_migrations(database_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+async fn serve() -> Result<(), ConfigError> {
+    // Run database migrations on startup
+    let app_state = AppState::try_new().await?;
+
+    // Background jobs, each run by one replica at a time
+    let mut scheduler = Scheduler::new(app_state.clone())?;
+    register_expiry_sweep_job(&mut scheduler)?;
+    register_access_outbox_worker(&mut scheduler)?;
+    register_expiry_reminder_job(&mut scheduler)?;
+    register_acknowledgment_monitor_job(&mut scheduler)?;
+    register_pending_purchase_job(&mut scheduler)?;
+    register_catalog_sync_job(&mut scheduler)?;
+    register_snapshot_pruning_job(&mut scheduler)?;
+    register_held_notification_job(&mut scheduler)?;
+    register_grace_reminder_job(&mut scheduler)?;
+    register_db_maintenance_job(&mut scheduler)?;
+    register_credit_ledger_check_job(&mut scheduler)?;
+    register_rtdn_lag_pruning_job(&mut scheduler)?;
+    register_purchase_intent_pruning_job(&mut scheduler)?;
+    register_event_redelivery_job(&mut scheduler)?;
+    register_queue_monitor_job(&mut scheduler)?;
+    if app_state.analytics.is_configured() {
+        register_analytics_export_job(&mut scheduler)?;
+    }
+    scheduler.start();
+    app_state
+        .events
+        .subscribe(Arc::new(app_state.notifier.clone()));
+    if app_state.analytics.is_configured() {
+        app_state.events.subscribe(Arc::new(AnalyticsConsumer));
+    }
+    spawn_event_dispatcher(app_state.clone());
+
+    #[cfg(feature = "grpc")]
+    grpc::spawn_grpc_server(app_state.clone())?;
+
+    let exposure = RouteExposure::from_env()?;
+    let port: u16 = env_number("PORT", 3000)?;
+
+    // Admin and internal routes move to their own port when one is configured
+    if let Some(internal_port) = exposure.internal_port {
+        let internal_listener = bind(internal_port).await?;
+        let internal_app = exposed_router(app_state.clone(), &exposure, Listener::Internal);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(
+                internal_listener,
+                internal_app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            {
+                sentry::capture_message(
+                    &format!("Internal server error: {}", e),
+                    sentry::Level::Error,
+                );
+                eprintln!("Internal server error: {}", e);
+                std::process::exit(1);
+            }
+        });
+    }
+
+    let listener = bind(port).await?;
+    let app = exposed_router(app_state, &exposure, Listener::Public);
+    if let Err(e) = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    {
+        sentry::capture_message(&format!("Server error: {}", e), sentry::Level::Error);
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
 
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+    Ok(())
+}
+
+async fn bind(port: u16) -> Result<tokio::net::TcpListener, ConfigError> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    println!("Listening on {}", addr);
+
+    tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| ConfigError::Bind {
+            addr,
+            reason: e.to_string(),
+        })
+}
+
+/// `build_router` limited to the routes `exposure` serves on `listener`
+pub fn exposed_router(app_state: AppState, exposure: &RouteExposure, listener: Listener) -> Router {
+    build_router(app_state).layer(middleware::from_fn_with_state(
+        (exposure.clone(), listener),
+        enforce_route_exposure,
+    ))
+}
+
+/// All HTTP routes of the service
+///
+/// API routes are served under each version prefix, e.g. `/v1/google/verify`, and on their
+/// legacy unprefixed paths, which stay on `ApiVersion::LEGACY` for shipped Android builds.
+pub fn build_router(app_state: AppState) -> Router {
+    // Callers are authenticated by each route's entry in `ROUTE_POLICIES`
+    let api_routes = api_routes().layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        enforce_auth_policy,
+    ));
+
+    // Only one version exists so far; a new one gets its own router for the changed routes
+    let mut versioned_routes = Router::new();
+    for version in ApiVersion::ALL {
+        versioned_routes = versioned_routes.nest(version.prefix(), api_routes.clone());
+    }
+
+    Router::new()
+        .route("/", get(root_redirect))
+        .route("/api-doc/openapi.json", get(openapi_spec))
+        .route("/explore", get(swagger_ui))
+        .route("/admin/ui", get(admin_ui))
+        // Unversioned, downstream JWT libraries look for it at the well-known path
+        .route("/.well-known/jwks.json", get(get_jwks))
+        .merge(versioned_routes)
+        .merge(api_routes)
+        .layer(middleware::from_fn(negotiate_version))
+        .layer(middleware::from_fn(localize_messages))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            capture_requests,
+        ))
+        // Outermost, so a panic anywhere still gets an `ApiResponse`
+        .layer(CatchPanicLayer::custom(panic_response))
+        .with_state(app_state)
+}
+
+/// API routes with their body limits, before `build_router` adds authentication
+pub fn api_routes() -> Router<AppState> {
+    // Bound JSON bodies per route; the RTDN webhook is internet-facing
+    let json_body = middleware::from_fn(|req: Request, next: Next| {
+        enforce_json_body(req, next, DEFAULT_JSON_BODY_LIMIT)
+    });
+    let rtdn_body = middleware::from_fn(|req: Request, next: Next| {
+        enforce_json_body(req, next, RTDN_BODY_LIMIT)
+    });
+
+    Router::new()
+        .route("/health", get(health_check))
+        .route(
+            "/google/verify",
+            post(verify_purchase).layer(json_body.clone()),
+        )
+        .route(
+            "/google/verify/preview",
+            post(preview_verify_purchase).layer(json_body.clone()),
+        )
+        .route(
+            "/google/rtdn-webhook",
+            post(handle_rtdn_webhook).layer(rtdn_body),
+        )
+        .route(
+            "/google/chat-access/grant",
+            post(grant_chat_access).layer(json_body.clone()),
+        )
+        .route(
+            "/support/refund-request",
+            post(create_refund_request).layer(json_body.clone()),
+        )
+        .route("/google/chat-access/check", get(check_chat_access))
+        .route("/google/manage-url", get(get_manage_url))
+        .route(
+            "/google/cancel-intent",
+            post(record_cancel_intent).layer(json_body.clone()),
+        )
+        .route(
+            "/google/profiles",
+            post(register_purchase_profile).layer(json_body.clone()),
+        )
+        .route(
+            "/google/purchase-intent",
+            post(record_intent).layer(json_body.clone()),
+        )
+        .route(
+            "/google/transfer",
+            post(transfer_purchase_tokens).layer(json_body.clone()),
+        )
+        .route("/entitlements/{user_id}", get(get_entitlements))
+        .route(
+            "/entitlements/token",
+            post(issue_entitlement_token).layer(json_body.clone()),
+        )
+        .route("/billing/history/{user_id}", get(get_billing_history))
+        .route("/users/{user_id}/tokens", get(list_user_tokens))
+        .route("/billing/invoices/{user_id}", get(get_invoices))
+        .route("/admin/ic-identity", get(get_ic_identity))
+        .route("/admin/ic-identity/reload", post(reload_ic_identity))
+        .route("/admin/refund-requests", get(list_refund_requests))
+        .route("/admin/risk/users", get(list_flagged_users))
+        .route("/admin/quarantine", get(list_quarantined_verifications))
+        .route(
+            "/admin/refund-requests/{id}",
+            post(act_on_refund_request).layer(json_body.clone()),
+        )
+        .route("/admin/orders/export", get(export_orders))
+        .route("/admin/offers/conversions", get(get_offer_conversions))
+        .route("/admin/invoices/export", get(export_invoices))
+        .route("/admin/export/tokens", get(export_tokens))
+        .route("/admin/export/events", get(export_events))
+        .route("/admin/tokens", get(list_tokens))
+        .route("/admin/tokens/{id}/diff", get(get_token_diff))
+        .route("/admin/events", get(list_revenue_events))
+        .route(
+            "/admin/users/plans",
+            post(lookup_user_plans).layer(json_body.clone()),
+        )
+        .route("/admin/stats", get(get_admin_stats))
+        .route("/admin/stats/rtdn-lag", get(get_rtdn_lag_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/admin/feature-flags", get(list_feature_flags))
+        .route(
+            "/admin/feature-flags/{name}",
+            post(set_feature_flag).layer(json_body.clone()),
+        )
+        .route("/admin/debug/requests", get(list_debug_log))
+        .route("/admin/jobs", get(list_scheduled_jobs))
+        .route("/admin/cluster", get(get_cluster))
+        .route("/admin/queues", get(get_queue_stats))
+        .route("/admin/db/integrity", get(check_db_integrity))
+        .route(
+            "/admin/subscriptions/{token}/snapshots",
+            get(list_subscription_snapshots),
+        )
+        .route(
+            "/admin/subscriptions/{token}/line-items",
+            get(list_subscription_line_items),
+        )
+        .route("/admin/catalog", get(get_catalog))
+        .route(
+            "/admin/catalog/sync",
+            post(sync_catalog).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/access/grant",
+            post(grant_access).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/access/revoke",
+            post(revoke_access).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/subscriptions/{token}/defer",
+            post(defer_subscription).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/subscriptions/{token}/reverify",
+            post(reverify_subscription),
+        )
+        .route(
+            "/admin/risk/users/{user_id}/approve",
+            post(approve_flagged_user).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/quarantine/{id}/approve",
+            post(approve_quarantined_verification).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/quarantine/{id}/reject",
+            post(reject_quarantined_verification).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/compensations",
+            post(create_compensation).layer(json_body.clone()),
+        )
+        .route("/admin/compensations/{id}", get(get_compensation))
+        .route(
+            "/admin/compensations/{id}/resume",
+            post(resume_compensation),
+        )
+        .route(
+            "/credits/deduct",
+            post(deduct_credits).layer(json_body.clone()),
+        )
+        .route(
+            "/credits/increment",
+            post(increment_credits).layer(json_body.clone()),
+        )
+        .route(
+            "/admin/users/{user_id}/credit-ledger",
+            get(get_credit_ledger),
+        )
+        .route(
+            "/internal/users/{user_id}/teardown",
+            post(teardown_user_subscriptions).layer(json_body.clone()),
+        )
+        .route("/internal/introspect", post(introspect).layer(json_body))
+}
 
+fn run_migrations(database_url: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut connection = SqliteConnection::establish(database_url)?;
     connection
         .run_pending_migrations(MIGRATIONS)
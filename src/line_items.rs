@@ -0,0 +1,168 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::error::{AppError, AppResult};
+use crate::model::SubscriptionLineItemRecord;
+use crate::plans::plan_for_product;
+use crate::types::{GooglePlaySubscriptionResponse, Plan, SubscriptionLineItem};
+
+/// End of a line item's current period, `None` while its payment is pending
+fn period_end(item: &SubscriptionLineItem) -> Option<NaiveDateTime> {
+    item.expiry_time
+        .as_deref()
+        .and_then(|time_str| chrono::DateTime::parse_from_rfc3339(time_str).ok())
+        .map(|dt| dt.naive_utc())
+}
+
+/// Line items whose product grants a plan from the catalog, with that plan
+///
+/// Add-ons and other products outside the catalog are stored but grant nothing.
+pub fn entitled_line_items(
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> impl Iterator<Item = (&SubscriptionLineItem, Plan)> {
+    subscription_response
+        .line_items
+        .iter()
+        .filter_map(|item| Some((item, plan_for_product(&item.product_id)?)))
+}
+
+/// Line item access is granted for, the entitled one lasting longest
+///
+/// Falls back to the first line item when none is in the catalog.
+pub fn granting_line_item(
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> Option<&SubscriptionLineItem> {
+    entitled_line_items(subscription_response)
+        .map(|(item, _)| item)
+        .max_by_key(|item| period_end(item))
+        .or_else(|| subscription_response.line_items.first())
+}
+
+/// When access ends, once every entitled line item has expired
+pub fn access_expiry(
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> Option<NaiveDateTime> {
+    granting_line_item(subscription_response).and_then(period_end)
+}
+
+/// Product access is granted for, see `granting_line_item`
+pub fn granting_product(subscription_response: &GooglePlaySubscriptionResponse) -> Option<&str> {
+    granting_line_item(subscription_response).map(|item| item.product_id.as_str())
+}
+
+/// Store every line item Google reports for a subscription we track
+///
+/// Lines are matched on product, a line Google no longer reports is removed. Each line is
+/// marked expired once its own period ended. Line items of tokens we don't track (e.g.
+/// ignored sandbox purchases) are skipped.
+pub fn record_line_items(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::purchase_tokens;
+    use crate::schema::subscription_line_items::dsl::*;
+
+    let tracked: i64 = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(purchase_token_param))
+        .count()
+        .get_result(conn)?;
+    if tracked == 0 {
+        return Ok(());
+    }
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        for item in &subscription_response.line_items {
+            let offer = item.offer_details.as_ref();
+            let ends_at = period_end(item);
+            let record = SubscriptionLineItemRecord {
+                purchase_token: purchase_token_param.to_string(),
+                product_id: item.product_id.clone(),
+                base_plan_id: offer.and_then(|offer| offer.base_plan_id.clone()),
+                offer_id: offer.and_then(|offer| offer.offer_id.clone()),
+                expiry_at: ends_at,
+                auto_renewing: item
+                    .auto_renewing_plan
+                    .as_ref()
+                    .and_then(|plan| plan.auto_renew_enabled)
+                    .or(item.auto_renewing)
+                    .unwrap_or(true),
+                expired: ends_at.is_some_and(|ends_at| ends_at < now),
+                updated_at: now,
+            };
+
+            diesel::insert_into(subscription_line_items)
+                .values(&record)
+                .on_conflict((purchase_token, product_id))
+                .do_update()
+                .set((
+                    base_plan_id.eq(&record.base_plan_id),
+                    offer_id.eq(&record.offer_id),
+                    expiry_at.eq(record.expiry_at),
+                    auto_renewing.eq(record.auto_renewing),
+                    expired.eq(record.expired),
+                    updated_at.eq(now),
+                ))
+                .execute(conn)?;
+        }
+
+        let reported: Vec<&str> = subscription_response
+            .line_items
+            .iter()
+            .map(|item| item.product_id.as_str())
+            .collect();
+        diesel::delete(
+            subscription_line_items
+                .filter(purchase_token.eq(purchase_token_param))
+                .filter(product_id.ne_all(reported)),
+        )
+        .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Mark line items whose period ended before `now` expired, returning how many were
+pub fn expire_line_items(conn: &mut SqliteConnection, now: NaiveDateTime) -> AppResult<usize> {
+    use crate::schema::subscription_line_items::dsl::*;
+
+    Ok(diesel::update(
+        subscription_line_items
+            .filter(expired.eq(false))
+            .filter(expiry_at.lt(now)),
+    )
+    .set((expired.eq(true), updated_at.eq(now)))
+    .execute(conn)?)
+}
+
+/// Stored line items of a purchase token, ordered by product
+pub fn line_items_for_token(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+) -> AppResult<Vec<SubscriptionLineItemRecord>> {
+    use crate::schema::subscription_line_items::dsl::*;
+
+    Ok(subscription_line_items
+        .filter(purchase_token.eq(purchase_token_param))
+        .order(product_id.asc())
+        .load(conn)?)
+}
+
+/// Stored product access is granted for on a purchase token, like `granting_product` but
+/// without asking Google
+///
+/// `None` for tokens whose line items were never recorded.
+pub fn stored_granting_product(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+) -> AppResult<Option<String>> {
+    let items = line_items_for_token(conn, purchase_token_param)?;
+    let granting = items
+        .iter()
+        .filter(|item| plan_for_product(&item.product_id).is_some())
+        .max_by_key(|item| item.expiry_at)
+        .or_else(|| items.first());
+
+    Ok(granting.map(|item| item.product_id.clone()))
+}
@@ -0,0 +1,168 @@
+//! Localized user-facing messages
+//!
+//! `ApiResponse` messages are written in English in the code. Translations live in per-locale
+//! catalogs under `locales/`, keyed by `ErrorCode` and with `{name}` placeholders filled from
+//! the `MessageKey` a response carries. `localize_messages` picks the locale from
+//! `Accept-Language` and swaps the message of responses that carry a `MessageKey`; codes stay
+//! the same in every locale, so clients keep branching on them.
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_LENGTH, VARY},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+
+use crate::types::ErrorCode;
+
+/// Languages messages are available in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Hi,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Hi];
+    /// Language the messages are written in, used when the client accepts none we have
+    pub const DEFAULT: Locale = Locale::En;
+
+    /// Primary language subtag, sent as `Content-Language`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Hi => "hi",
+        }
+    }
+
+    /// Accepts a language tag with or without region, e.g. `hi` or `hi-IN`
+    pub fn parse(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?;
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.as_str().eq_ignore_ascii_case(language))
+    }
+
+    /// Translations keyed by error code, `None` for the default locale
+    pub fn catalog(&self) -> Option<&'static HashMap<String, String>> {
+        match self {
+            Locale::En => None,
+            Locale::Hi => Some(&HI),
+        }
+    }
+}
+
+static HI: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    serde_json::from_str(include_str!("../locales/hi.json")).expect("locales/hi.json is valid")
+});
+
+/// Locale the client prefers most among the ones we have
+///
+/// Follows the `q` weights of `Accept-Language`, earlier entries win ties. `*` and
+/// unsupported languages fall through to the next entry, and to `Locale::DEFAULT` at the end.
+pub fn negotiate_locale(accept_language: Option<&str>) -> Locale {
+    let Some(accept_language) = accept_language else {
+        return Locale::DEFAULT;
+    };
+
+    let mut best: Option<(Locale, f32)> = None;
+    for entry in accept_language.split(',') {
+        let mut params = entry.split(';');
+        let Some(locale) = params.next().and_then(Locale::parse) else {
+            continue;
+        };
+        let weight = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if weight > 0.0 && best.is_none_or(|(_, best_weight)| weight > best_weight) {
+            best = Some((locale, weight));
+        }
+    }
+    best.map_or(Locale::DEFAULT, |(locale, _)| locale)
+}
+
+/// Response extension naming the catalog entry for the response's message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageKey {
+    pub code: ErrorCode,
+    /// Values for the entry's placeholders
+    pub args: Vec<(&'static str, String)>,
+}
+
+impl MessageKey {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    /// The message in `locale`, `None` when the catalog has no entry for the code
+    pub fn translate(&self, locale: Locale) -> Option<String> {
+        let code = serde_json::to_value(self.code).ok()?;
+        let template = locale.catalog()?.get(code.as_str()?)?;
+        Some(
+            self.args
+                .iter()
+                .fold(template.clone(), |message, (name, value)| {
+                    message.replace(&format!("{{{}}}", name), value)
+                }),
+        )
+    }
+}
+
+/// Translate the message of responses carrying a `MessageKey` into the client's locale
+///
+/// Replaces `error` on failures and `msg` on successes, and sets `Content-Language`.
+/// Responses in the default locale or without a catalog entry pass through untouched.
+pub async fn localize_messages(req: Request, next: Next) -> Response {
+    let locale = negotiate_locale(
+        req.headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok()),
+    );
+
+    let mut response = next.run(req).await;
+    let Some(key) = response.extensions().get::<MessageKey>().cloned() else {
+        return response;
+    };
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept-language"));
+    let Some(message) = key.translate(locale) else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    // Our own `ApiResponse`, small and already in memory
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let field = match json.get("success").and_then(|success| success.as_bool()) {
+        Some(true) => "msg",
+        _ => "error",
+    };
+    json[field] = serde_json::Value::String(message);
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_LANGUAGE, HeaderValue::from_static(locale.as_str()));
+    Response::from_parts(parts, Body::from(json.to_string()))
+}
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Purchase tokens marked expired by the expiry sweep
+pub const EXPIRY_SWEEP_EXPIRED_TOTAL: &str = "billing_expiry_sweep_expired_total";
+/// Access outbox entries applied on the IC, labelled by `action`
+pub const ACCESS_OUTBOX_APPLIED_TOTAL: &str = "billing_access_outbox_applied_total";
+/// Failed attempts to apply an access outbox entry, labelled by `action`
+pub const ACCESS_OUTBOX_ERRORS_TOTAL: &str = "billing_access_outbox_errors_total";
+/// Domain events every consumer handled, labelled by `event`
+pub const DOMAIN_EVENTS_DELIVERED_TOTAL: &str = "billing_domain_events_delivered_total";
+/// Failed attempts of a consumer to handle a domain event, labelled by `event` and `consumer`
+pub const DOMAIN_EVENT_ERRORS_TOTAL: &str = "billing_domain_event_errors_total";
+/// Analytics events the ingestion endpoint accepted, labelled by `event`
+pub const ANALYTICS_EVENTS_EXPORTED_TOTAL: &str = "billing_analytics_events_exported_total";
+/// Cancellation intents reported by the app, labelled by `reason`
+pub const CANCEL_INTENTS_TOTAL: &str = "billing_cancel_intents_total";
+/// Verify requests answered with a quarantine instead of a grant, labelled by `reason`
+pub const VERIFICATIONS_QUARANTINED_TOTAL: &str = "billing_verifications_quarantined_total";
+/// Operator decisions on quarantined verifications, labelled by `decision`
+pub const QUARANTINE_DECISIONS_TOTAL: &str = "billing_quarantine_decisions_total";
+/// Decisions run through both implementations of a shadow experiment, labelled by
+/// `experiment` and `outcome` (`match` or `divergence`)
+pub const SHADOW_COMPARISONS_TOTAL: &str = "billing_shadow_comparisons_total";
+/// Failed Android Publisher calls, labelled by `operation` and `kind` (`network`, `decode`,
+/// `client`, `throttled` or `upstream`)
+pub const GOOGLE_PLAY_ERRORS_TOTAL: &str = "billing_google_play_errors_total";
+/// User info canister calls, labelled by `method` and `result` (`ok`, `user_not_found`,
+/// `already_pro`, `canister_stopped`, `rejected`, `transient` or `error`)
+pub const IC_CALLS_TOTAL: &str = "billing_ic_calls_total";
+/// Acknowledgment retries by the monitor job, labelled by `result`
+pub const ACKNOWLEDGMENT_RETRIES_TOTAL: &str = "billing_acknowledgment_retries_total";
+/// Subscription lookups through the Google Play cache, labelled by `result` (`hit`, `miss` or
+/// `coalesced` for callers that waited on another caller's fetch)
+pub const GOOGLE_PLAY_CACHE_TOTAL: &str = "billing_google_play_cache_total";
+/// Plan lookups through the user plan cache, labelled by `result` (`hit` or `miss`)
+pub const USER_PLAN_CACHE_TOTAL: &str = "billing_user_plan_cache_total";
+/// Credit changes made through the API, labelled by `caller` and `action`
+pub const CREDIT_CHANGES_TOTAL: &str = "billing_credit_changes_total";
+/// Users whose IC credits differed from their ledger balance at the consistency check
+pub const CREDIT_LEDGER_MISMATCHES_TOTAL: &str = "billing_credit_ledger_mismatches_total";
+/// Purchase tokens still waiting for acknowledgment after the last monitor run
+pub const UNACKNOWLEDGED_TOKENS: &str = "billing_unacknowledged_tokens";
+/// Unacknowledged purchase tokens close to being voided by Google
+pub const ACKNOWLEDGMENT_DEADLINE_AT_RISK_TOKENS: &str =
+    "billing_acknowledgment_deadline_at_risk_tokens";
+
+/// Seconds from Google's `eventTimeMillis` to processing an RTDN, labelled by `notification_type`
+pub const RTDN_EVENT_LAG_SECONDS: &str = "billing_rtdn_event_lag_seconds";
+/// Seconds from the Pub/Sub `publishTime` to processing an RTDN, labelled by `notification_type`
+pub const RTDN_DELIVERY_LAG_SECONDS: &str = "billing_rtdn_delivery_lag_seconds";
+/// Bucket bounds of the RTDN lag histograms, from a second to a day
+pub const RTDN_LAG_BUCKETS_SECONDS: &[f64] =
+    &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 21600.0, 86400.0];
+
+/// Scheduled job runs, labelled by `job` and `outcome` (`succeeded`, `failed` or `skipped`
+/// when another replica held the job's lease)
+pub const SCHEDULED_JOB_RUNS_TOTAL: &str = "billing_scheduled_job_runs_total";
+/// How long the last run of a scheduled job took in milliseconds, labelled by `job`
+pub const SCHEDULED_JOB_DURATION_MS: &str = "billing_scheduled_job_duration_ms";
+/// Unix time of the last successful run of a scheduled job, labelled by `job`
+pub const SCHEDULED_JOB_LAST_SUCCESS_SECONDS: &str =
+    "billing_scheduled_job_last_success_timestamp_seconds";
+
+/// Items waiting in a background queue, labelled by `queue`
+pub const QUEUE_DEPTH: &str = "billing_queue_depth";
+/// Items a background queue gave up on, labelled by `queue`
+pub const QUEUE_FAILED: &str = "billing_queue_failed";
+/// Seconds the oldest due item of a queue has waited, 0 when none is due, labelled by `queue`
+pub const QUEUE_OLDEST_AGE_SECONDS: &str = "billing_queue_oldest_age_seconds";
+/// Items a background queue handled over the last hour, labelled by `queue`
+pub const QUEUE_PROCESSED_LAST_HOUR: &str = "billing_queue_processed_last_hour";
+
+type Series<T> = BTreeMap<String, BTreeMap<String, T>>;
+
+/// Observations of one histogram series, counted in the buckets it was created with
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: Vec<f64>,
+    /// Observations at or below each bound, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// In-process counters, gauges and histograms exported in the Prometheus text format on
+/// `/metrics`
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<Series<u64>>>,
+    gauges: Arc<Mutex<Series<i64>>>,
+    histograms: Arc<Mutex<Series<Histogram>>>,
+}
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_set(labels))
+            .or_default() += value;
+    }
+
+    /// Current value of a counter, zero if it was never incremented
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+        self.gauges
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(label_set(labels), value);
+    }
+
+    /// Current value of a gauge, `None` if it was never set
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<i64> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .copied()
+    }
+
+    /// Record `value` in a histogram, `buckets` are only used the first time a series is seen
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], buckets: &[f64], value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_set(labels))
+            .or_insert_with(|| Histogram::new(buckets))
+            .observe(value);
+    }
+
+    /// Observations recorded in a histogram, zero if it was never observed
+    pub fn histogram_count(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        self.histograms
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .map_or(0, |histogram| histogram.count)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_series(&mut out, "counter", &self.counters.lock().unwrap());
+        render_series(&mut out, "gauge", &self.gauges.lock().unwrap());
+        render_histograms(&mut out, &self.histograms.lock().unwrap());
+        out
+    }
+}
+
+fn render_series<T: std::fmt::Display>(out: &mut String, kind: &str, metrics: &Series<T>) {
+    for (name, series) in metrics {
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in series {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    }
+}
+
+/// `labels` with `le` added, as bucket lines carry it next to the series' own labels
+fn bucket_label_set(labels: &str, le: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{},le=\"{}\"}}", labels, le),
+        None => format!("{{le=\"{}\"}}", le),
+    }
+}
+
+fn render_histograms(out: &mut String, metrics: &Series<Histogram>) {
+    for (name, series) in metrics {
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (labels, histogram) in series {
+            let mut cumulative = 0;
+            for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    bucket_label_set(labels, &bound.to_string()),
+                    cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                bucket_label_set(labels, "+Inf"),
+                histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, labels, histogram.count);
+        }
+    }
+}
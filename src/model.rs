@@ -1,4 +1,10 @@
-use crate::types::{BotChatAccessStatus, PurchaseTokenStatus};
+use crate::types::{
+    AuditAction, BotChatAccessStatus, CancelReason, CompensationBatchStatus,
+    CompensationGrantStatus, CompensationKind, CreditDirection, CreditReason, JobOutcome,
+    OutboxAction, OutboxStatus, Plan, PurchaseEnvironment, PurchaseTokenStatus, QuarantineReason,
+    QuarantineStatus, RefundRequestStatus, SubscriptionEventKind, SubscriptionSource,
+    SubscriptionStatus,
+};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use uuid::Uuid;
@@ -46,6 +52,29 @@ pub struct PurchaseToken {
     pub status: PurchaseTokenStatus,
     pub created_at: NaiveDateTime,
     pub expiry_at: NaiveDateTime,
+    pub environment: PurchaseEnvironment,
+    pub auto_renewing: bool,
+    /// When the user was last reminded that this subscription is about to expire
+    pub notified_at: Option<NaiveDateTime>,
+    /// Android package the purchase was made in, needed to retry acknowledgment
+    pub package_name: Option<String>,
+    /// When the purchase was acknowledged with Google, unset while acknowledgment is pending
+    pub acknowledged_at: Option<NaiveDateTime>,
+    /// Event time of the last RTDN applied to this token
+    pub last_event_time: Option<NaiveDateTime>,
+    /// Start of the pause window Google reported, access is revoked from then on
+    pub paused_from: Option<NaiveDateTime>,
+    /// When Google resumes the paused subscription and access is restored
+    pub resumes_at: Option<NaiveDateTime>,
+    /// Last change to the row, bumped by a database trigger on every update
+    pub updated_at: NaiveDateTime,
+    /// Purchase token that replaced this one, bought from another Google account by the same user
+    pub replaced_by: Option<String>,
+    /// Bumped on every update, see `db::token_versions`
+    pub version: i32,
+    /// When an RTDN stored this purchase without knowing its owner, unset once it is claimed.
+    /// `user_id` is empty until then.
+    pub provisional_since: Option<NaiveDateTime>,
 }
 
 impl PurchaseToken {
@@ -54,14 +83,1055 @@ impl PurchaseToken {
         purchase_token: String,
         expiry_at: NaiveDateTime,
         status: PurchaseTokenStatus,
+        environment: PurchaseEnvironment,
     ) -> Self {
+        let now = chrono::Utc::now().naive_utc();
         Self {
             id: Uuid::new_v4().to_string(),
             user_id,
             purchase_token,
             status,
-            created_at: chrono::Utc::now().naive_utc(),
+            created_at: now,
             expiry_at,
+            environment,
+            auto_renewing: true,
+            notified_at: None,
+            package_name: None,
+            acknowledged_at: None,
+            last_event_time: None,
+            paused_from: None,
+            resumes_at: None,
+            updated_at: now,
+            replaced_by: None,
+            version: 0,
+            provisional_since: None,
+        }
+    }
+
+    /// Pending row for a purchase an RTDN reported before anyone could tell whose it is
+    pub fn provisional(
+        purchase_token: String,
+        package_name: String,
+        environment: PurchaseEnvironment,
+        now: NaiveDateTime,
+    ) -> Self {
+        let mut token = Self::new(
+            String::new(),
+            purchase_token,
+            now,
+            PurchaseTokenStatus::Pending,
+            environment,
+        );
+        token.package_name = Some(package_name);
+        token.provisional_since = Some(now);
+        token
+    }
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::feature_flags, primary_key(name))]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Last run and lease of a job run by the `scheduler`
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::scheduled_jobs, primary_key(name))]
+pub struct ScheduledJob {
+    pub name: String,
+    /// Schedule the job was last registered with, e.g. `every 3600s` or `0 3 * * *`
+    pub schedule: String,
+    /// Replica running the job, until `locked_until` has passed
+    pub locked_by: Option<String>,
+    pub locked_until: Option<NaiveDateTime>,
+    pub last_started_at: Option<NaiveDateTime>,
+    pub last_finished_at: Option<NaiveDateTime>,
+    pub last_outcome: Option<JobOutcome>,
+    pub last_error: Option<String>,
+    pub last_duration_ms: Option<i64>,
+}
+
+/// Progress of a data backfill over `purchase_tokens`, see `jobs::backfills`
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::backfills, primary_key(name))]
+pub struct BackfillProgress {
+    pub name: String,
+    /// Id of the last token of the last finished batch, the next run continues after it
+    pub last_token_id: Option<String>,
+    pub processed: i64,
+    /// Tokens whose stored data changed
+    pub updated: i64,
+    pub failed: i64,
+    pub last_error: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// Set once every token was visited, later runs do nothing unless restarted
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+impl BackfillProgress {
+    pub fn new(name: String, now: NaiveDateTime) -> Self {
+        Self {
+            name,
+            last_token_id: None,
+            processed: 0,
+            updated: 0,
+            failed: 0,
+            last_error: None,
+            started_at: now,
+            updated_at: now,
+            completed_at: None,
+        }
+    }
+}
+
+/// A Google Play order (initial purchase or renewal) kept for payout reconciliation
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::orders)]
+pub struct Order {
+    pub id: String,
+    pub order_id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub region_code: Option<String>,
+    pub recorded_at: NaiveDateTime,
+}
+
+impl Order {
+    pub fn new(
+        order_id: String,
+        purchase_token: String,
+        user_id: String,
+        product_id: String,
+        region_code: Option<String>,
+        recorded_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            order_id,
+            purchase_token,
+            user_id,
+            product_id,
+            region_code,
+            recorded_at,
+        }
+    }
+}
+
+/// Invoice for one Google Play order, shown to users who need one, e.g. in the EU
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::invoices)]
+pub struct Invoice {
+    pub id: String,
+    pub order_id: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    pub region_code: Option<String>,
+    /// ISO 4217 currency code, `None` when Play reported no price
+    pub currency_code: Option<String>,
+    /// Amount charged including tax, in millionths of the currency unit
+    pub amount_micros: Option<i64>,
+    pub issued_at: NaiveDateTime,
+}
+
+impl Invoice {
+    pub fn new(
+        order: &Order,
+        base_plan_id: Option<String>,
+        offer_id: Option<String>,
+        price: Option<(String, i64)>,
+    ) -> Self {
+        let (currency_code, amount_micros) = price.unzip();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            order_id: order.order_id.clone(),
+            user_id: order.user_id.clone(),
+            product_id: order.product_id.clone(),
+            base_plan_id,
+            offer_id,
+            region_code: order.region_code.clone(),
+            currency_code,
+            amount_micros,
+            issued_at: order.recorded_at,
+        }
+    }
+}
+
+/// Price charged for one Google Play order, kept for revenue attribution per region
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::revenue_events)]
+pub struct RevenueEvent {
+    pub id: String,
+    pub order_id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    pub region_code: Option<String>,
+    /// ISO 4217 currency code of the price
+    pub currency_code: String,
+    /// Price in millionths of the currency unit
+    pub price_micros: i64,
+    pub recorded_at: NaiveDateTime,
+}
+
+impl RevenueEvent {
+    pub fn new(
+        order: &Order,
+        base_plan_id: Option<String>,
+        offer_id: Option<String>,
+        currency_code: String,
+        price_micros: i64,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            order_id: order.order_id.clone(),
+            purchase_token: order.purchase_token.clone(),
+            user_id: order.user_id.clone(),
+            product_id: order.product_id.clone(),
+            base_plan_id,
+            offer_id,
+            region_code: order.region_code.clone(),
+            currency_code,
+            price_micros,
+            recorded_at: order.recorded_at,
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::token_transfers)]
+pub struct TokenTransfer {
+    pub id: String,
+    pub purchase_token: String,
+    pub from_user_id: String,
+    pub to_user_id: String,
+    pub transferred_at: NaiveDateTime,
+}
+
+impl TokenTransfer {
+    pub fn new(
+        purchase_token: String,
+        from_user_id: String,
+        to_user_id: String,
+        transferred_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            purchase_token,
+            from_user_id,
+            to_user_id,
+            transferred_at,
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::refund_requests)]
+pub struct RefundRequest {
+    pub id: String,
+    pub user_id: String,
+    pub purchase_token: String,
+    pub order_id: Option<String>,
+    pub reason: String,
+    pub status: RefundRequestStatus,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl RefundRequest {
+    pub fn new(
+        user_id: String,
+        purchase_token: String,
+        order_id: Option<String>,
+        reason: String,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            purchase_token,
+            order_id,
+            reason,
+            status: RefundRequestStatus::Requested,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+}
+
+/// User about to cancel in the Play Store, as reported by the app
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::cancel_intents)]
+pub struct CancelIntent {
+    pub id: String,
+    pub user_id: String,
+    pub purchase_token: String,
+    pub reason: CancelReason,
+    pub comment: Option<String>,
+    /// Whether this intent triggered the token's retention offer
+    pub retention_offered: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl CancelIntent {
+    pub fn new(
+        user_id: String,
+        purchase_token: String,
+        reason: CancelReason,
+        comment: Option<String>,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            purchase_token,
+            reason,
+            comment,
+            retention_offered: false,
+            created_at,
+        }
+    }
+}
+
+/// Verify request seen for a purchase token, successful or not
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::verify_attempts)]
+pub struct VerifyAttempt {
+    pub id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub attempted_at: NaiveDateTime,
+}
+
+impl VerifyAttempt {
+    pub fn new(purchase_token: String, user_id: String, attempted_at: NaiveDateTime) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            purchase_token,
+            user_id,
+            attempted_at,
+        }
+    }
+}
+
+/// Suspicious verification held for an operator instead of granting access
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::verification_quarantine)]
+pub struct QuarantinedVerification {
+    pub id: String,
+    pub user_id: String,
+    /// Account Google reports for the purchase, granted on approval
+    pub account_id: String,
+    pub purchase_token: String,
+    pub package_name: String,
+    pub product_id: String,
+    pub reason: QuarantineReason,
+    pub status: QuarantineStatus,
+    pub created_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decided_by: Option<String>,
+}
+
+impl QuarantinedVerification {
+    pub fn new(
+        user_id: String,
+        account_id: String,
+        purchase_token: String,
+        package_name: String,
+        product_id: String,
+        reason: QuarantineReason,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            account_id,
+            purchase_token,
+            package_name,
+            product_id,
+            reason,
+            status: QuarantineStatus::Pending,
+            created_at,
+            decided_at: None,
+            decided_by: None,
+        }
+    }
+}
+
+/// RTDN notification we received but have no handler for, kept for later inspection
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::unhandled_notifications)]
+pub struct UnhandledNotification {
+    pub id: String,
+    /// Which part of the developer notification was not handled, e.g. `subscription`
+    pub kind: String,
+    pub notification_type: Option<i32>,
+    pub purchase_token: Option<String>,
+    /// Decoded developer notification JSON
+    pub payload: String,
+    pub received_at: NaiveDateTime,
+}
+
+impl UnhandledNotification {
+    pub fn new(
+        kind: String,
+        notification_type: Option<i32>,
+        purchase_token: Option<String>,
+        payload: String,
+        received_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            notification_type,
+            purchase_token,
+            payload,
+            received_at,
+        }
+    }
+}
+
+/// Payment-declined reminders of a subscription in its grace period
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::grace_reminders, primary_key(purchase_token))]
+pub struct GraceReminder {
+    pub purchase_token: String,
+    pub user_id: String,
+    pub product_id: String,
+    /// When the grace period notification arrived, reminder offsets count from here
+    pub started_at: NaiveDateTime,
+    /// Expiry Google reports during the grace period, access ends then unless payment recovers
+    pub grace_ends_at: NaiveDateTime,
+    pub reminders_sent: i32,
+    pub last_sent_at: Option<NaiveDateTime>,
+    pub stopped_at: Option<NaiveDateTime>,
+}
+
+impl GraceReminder {
+    pub fn new(
+        purchase_token: String,
+        user_id: String,
+        product_id: String,
+        started_at: NaiveDateTime,
+        grace_ends_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            purchase_token,
+            user_id,
+            product_id,
+            started_at,
+            grace_ends_at,
+            reminders_sent: 0,
+            last_sent_at: None,
+            stopped_at: None,
+        }
+    }
+}
+
+/// State-regressing RTDN held back in case an earlier notification for the token is still in flight
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::held_notifications)]
+pub struct HeldNotification {
+    pub id: String,
+    pub purchase_token: String,
+    /// `eventTimeMillis` of the notification, held ones are applied in this order
+    pub event_time: NaiveDateTime,
+    pub notification_type: i32,
+    /// Decoded developer notification JSON
+    pub payload: String,
+    pub received_at: NaiveDateTime,
+    /// When the flush job may apply it
+    pub release_at: NaiveDateTime,
+}
+
+impl HeldNotification {
+    pub fn new(
+        purchase_token: String,
+        event_time: NaiveDateTime,
+        notification_type: i32,
+        payload: String,
+        received_at: NaiveDateTime,
+        release_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            purchase_token,
+            event_time,
+            notification_type,
+            payload,
+            received_at,
+            release_at,
+        }
+    }
+}
+
+/// How long after Google's event and Pub/Sub's publish an RTDN was processed
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::rtdn_lag_samples)]
+pub struct RtdnLagSample {
+    pub id: String,
+    /// e.g. `subscription_revoked`, see `DeveloperNotification::type_label`
+    pub notification_type: String,
+    /// Since `eventTimeMillis`, `None` if it was malformed
+    pub event_lag_ms: Option<i64>,
+    /// Since the Pub/Sub `publishTime`, `None` for held notifications applied later
+    pub delivery_lag_ms: Option<i64>,
+    pub processed_at: NaiveDateTime,
+}
+
+impl RtdnLagSample {
+    pub fn new(
+        notification_type: String,
+        event_lag_ms: Option<i64>,
+        delivery_lag_ms: Option<i64>,
+        processed_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            notification_type,
+            event_lag_ms,
+            delivery_lag_ms,
+            processed_at,
+        }
+    }
+}
+
+/// Stored domain event, delivered to the event bus consumers until all of them succeed
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::domain_events)]
+pub struct DomainEventRecord {
+    pub id: String,
+    /// `DomainEvent::name` of the payload
+    pub event_type: String,
+    /// The `DomainEvent` as JSON
+    pub payload: String,
+    /// `Done` once every consumer handled the event
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl DomainEventRecord {
+    pub fn new(event_type: String, payload: String, created_at: NaiveDateTime) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event_type,
+            payload,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+}
+
+/// Analytics event waiting to be sent, or sent already
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::analytics_events)]
+pub struct AnalyticsEventRecord {
+    pub id: String,
+    /// `AnalyticsEventType::as_str` of the payload
+    pub event_type: String,
+    pub purchase_token: String,
+    /// The `AnalyticsEvent` as JSON
+    pub payload: String,
+    /// `Done` once the ingestion endpoint accepted it
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl AnalyticsEventRecord {
+    pub fn new(
+        event_type: String,
+        purchase_token: String,
+        payload: String,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event_type,
+            purchase_token,
+            payload,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+}
+
+/// Pending change to a user's plan on the IC, applied by the outbox worker
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::access_outbox)]
+pub struct AccessOutboxEntry {
+    pub id: String,
+    pub user_id: String,
+    pub action: OutboxAction,
+    /// Product to grant, only set for grants
+    pub product_id: Option<String>,
+    /// Purchase token that caused the change, for tracing
+    pub purchase_token: Option<String>,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// Not applied before this time, unset for changes due right away
+    pub run_after: Option<NaiveDateTime>,
+}
+
+impl AccessOutboxEntry {
+    pub fn new(
+        user_id: String,
+        action: OutboxAction,
+        product_id: Option<String>,
+        purchase_token: Option<String>,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            action,
+            product_id,
+            purchase_token,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at,
+            updated_at: created_at,
+            run_after: None,
+        }
+    }
+}
+
+/// Manual access change made by an operator through the admin API
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::admin_audit_log)]
+pub struct AdminAuditEntry {
+    pub id: String,
+    /// `sub` claim of the admin JWT that made the change
+    pub operator: String,
+    pub action: AuditAction,
+    pub user_id: String,
+    pub reason: String,
+    /// Synthetic purchase token created by a grant
+    pub purchase_token: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl AdminAuditEntry {
+    pub fn new(
+        operator: String,
+        action: AuditAction,
+        user_id: String,
+        reason: String,
+        purchase_token: Option<String>,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            operator,
+            action,
+            user_id,
+            reason,
+            purchase_token,
+            created_at,
+        }
+    }
+}
+
+/// Subscription base plan configured in the Play Console, mirrored by the catalog sync
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::products)]
+pub struct Product {
+    pub id: String,
+    pub package_name: String,
+    pub product_id: String,
+    pub base_plan_id: String,
+    /// Google's base plan state, e.g. `ACTIVE`, `DRAFT` or `INACTIVE`
+    pub state: String,
+    /// ISO 8601 duration such as `P1M`
+    pub billing_period: Option<String>,
+    pub archived: bool,
+    pub synced_at: NaiveDateTime,
+}
+
+impl Product {
+    pub fn new(
+        package_name: String,
+        product_id: String,
+        base_plan_id: String,
+        state: String,
+        billing_period: Option<String>,
+        archived: bool,
+        synced_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            package_name,
+            product_id,
+            base_plan_id,
+            state,
+            billing_period,
+            archived,
+            synced_at,
+        }
+    }
+
+    /// Whether new purchases can be made on this base plan
+    pub fn is_active(&self) -> bool {
+        !self.archived && self.state == "ACTIVE"
+    }
+}
+
+/// A subscription a user holds from any source
+///
+/// Google Play and manual rows are mirrored from `purchase_tokens` by database triggers,
+/// other sources write here directly.
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::subscriptions)]
+pub struct Subscription {
+    pub id: String,
+    pub user_id: String,
+    pub source: SubscriptionSource,
+    /// Identifier of the subscription at its source
+    pub source_ref: String,
+    pub status: SubscriptionStatus,
+    pub product_id: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub auto_renewing: bool,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Subscription {
+    pub fn new(
+        user_id: String,
+        source: SubscriptionSource,
+        source_ref: String,
+        status: SubscriptionStatus,
+        started_at: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            source,
+            source_ref,
+            status,
+            product_id: None,
+            started_at,
+            expires_at,
+            auto_renewing: false,
+            updated_at: started_at,
+        }
+    }
+}
+
+/// Entitlement read model of a user, derived from their longest lasting active subscription
+///
+/// Written only by database triggers on `subscriptions`, in the same transaction as the
+/// change it reflects. The plan is stored as of that change, `plan_at` applies the expiry.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::entitlements, primary_key(user_id))]
+pub struct Entitlement {
+    pub user_id: String,
+    pub plan: Plan,
+    pub source: Option<SubscriptionSource>,
+    pub valid_from: Option<NaiveDateTime>,
+    pub valid_until: Option<NaiveDateTime>,
+    pub auto_renewing: Option<bool>,
+    /// Latest pause of the user's Google Play subscriptions, kept after it ends
+    pub paused_from: Option<NaiveDateTime>,
+    pub resumes_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Entitlement {
+    /// Plan the user holds at `now`, `Pro` lapses once `valid_until` passes
+    pub fn plan_at(&self, now: NaiveDateTime) -> Plan {
+        match self.valid_until {
+            Some(valid_until) if self.plan == Plan::Pro && valid_until > now => Plan::Pro,
+            _ => Plan::Free,
+        }
+    }
+}
+
+/// Refund and chargeback history of a user, flagged once it crosses the risk threshold
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::user_risk, primary_key(user_id))]
+pub struct UserRisk {
+    pub user_id: String,
+    /// `SUBSCRIPTION_REVOKED` notifications received for the user's subscriptions
+    pub revoked_count: i32,
+    /// Voided purchase notifications received for the user's purchases
+    pub voided_count: i32,
+    pub flagged_at: Option<NaiveDateTime>,
+    pub approved_at: Option<NaiveDateTime>,
+    /// `sub` claim of the admin JWT that cleared the flag
+    pub approved_by: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl UserRisk {
+    pub fn new(user_id: String, now: NaiveDateTime) -> Self {
+        Self {
+            user_id,
+            revoked_count: 0,
+            voided_count: 0,
+            flagged_at: None,
+            approved_at: None,
+            approved_by: None,
+            updated_at: now,
+        }
+    }
+
+    /// Flagged and not yet cleared by an operator
+    pub fn requires_approval(&self) -> bool {
+        self.flagged_at.is_some() && self.approved_at.is_none()
+    }
+}
+
+/// One line item of a subscription as Google last reported it, see `line_items`
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::subscription_line_items, primary_key(purchase_token, product_id))]
+pub struct SubscriptionLineItemRecord {
+    pub purchase_token: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    /// End of the line's current period, absent while its payment is pending
+    pub expiry_at: Option<NaiveDateTime>,
+    pub auto_renewing: bool,
+    /// Set once `expiry_at` passed, independently of the token's other line items
+    pub expired: bool,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Subscription bought through a Play offer, recorded when its purchase was granted
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::offer_redemptions, primary_key(purchase_token, offer_id))]
+pub struct OfferRedemption {
+    pub purchase_token: String,
+    pub offer_id: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    /// Comma-separated, empty for an untagged offer
+    pub offer_tags: String,
+    pub redeemed_at: NaiveDateTime,
+}
+
+/// Redacted subscriptionsv2 response as fetched from Google, gzip-compressed
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::subscription_snapshots, primary_key(purchase_token, fetched_at))]
+pub struct SubscriptionSnapshot {
+    pub purchase_token: String,
+    pub fetched_at: NaiveDateTime,
+    pub body: Vec<u8>,
+}
+
+/// Purchase, renewal or cancellation of a tracked subscription, shown in the user's billing
+/// history
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::subscription_events)]
+pub struct SubscriptionEvent {
+    pub id: String,
+    pub user_id: String,
+    pub purchase_token: String,
+    pub kind: SubscriptionEventKind,
+    /// Google Play order the event belongs to, if Google reported one
+    pub order_id: Option<String>,
+    pub product_id: String,
+    pub occurred_at: NaiveDateTime,
+}
+
+impl SubscriptionEvent {
+    pub fn new(
+        user_id: String,
+        purchase_token: String,
+        kind: SubscriptionEventKind,
+        order_id: Option<String>,
+        product_id: String,
+        occurred_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            purchase_token,
+            kind,
+            order_id,
+            product_id,
+            occurred_at,
+        }
+    }
+}
+
+/// Credit allotment added for one renewal order
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::credit_topups, primary_key(order_id))]
+pub struct CreditTopup {
+    pub order_id: String,
+    pub user_id: String,
+    pub amount: i32,
+    pub created_at: NaiveDateTime,
+}
+
+/// One change to a user's credits, the ledger explaining their balance
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::credit_ledger)]
+pub struct CreditLedgerEntry {
+    pub id: String,
+    pub user_id: String,
+    /// Always positive, `direction` says which way
+    pub amount: i32,
+    pub direction: CreditDirection,
+    pub reason: CreditReason,
+    /// Caller label, e.g. `service:video`, `user:<sub>` or `billing`
+    pub actor: String,
+    /// Caller supplied id, or the order or compensation claim the change was made for
+    pub correlation_id: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl CreditLedgerEntry {
+    pub fn new(
+        user_id: String,
+        direction: CreditDirection,
+        amount: u32,
+        reason: CreditReason,
+        actor: String,
+        correlation_id: Option<String>,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            amount: amount as i32,
+            direction,
+            reason,
+            actor,
+            correlation_id,
+            created_at,
+        }
+    }
+
+    /// Signed change to the balance
+    pub fn delta(&self) -> i64 {
+        match self.direction {
+            CreditDirection::Deduct => -(self.amount as i64),
+            CreditDirection::Increment => self.amount as i64,
+        }
+    }
+}
+
+/// Pro days or credits granted to the users affected by an incident
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::compensation_batches)]
+pub struct CompensationBatch {
+    pub id: String,
+    pub incident: String,
+    pub reason: String,
+    pub kind: CompensationKind,
+    pub amount: i32,
+    pub operator: String,
+    pub status: CompensationBatchStatus,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl CompensationBatch {
+    pub fn new(
+        incident: String,
+        reason: String,
+        kind: CompensationKind,
+        amount: i32,
+        operator: String,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            incident,
+            reason,
+            kind,
+            amount,
+            operator,
+            status: CompensationBatchStatus::Running,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+}
+
+/// Outcome of a compensation batch for one user
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::compensation_grants, primary_key(batch_id, user_id))]
+pub struct CompensationGrant {
+    pub batch_id: String,
+    pub user_id: String,
+    pub status: CompensationGrantStatus,
+    pub error: Option<String>,
+    pub purchase_token: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// User behind an obfuscated profile id, for purchases Google reports without an account id
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::purchase_profiles, primary_key(obfuscated_profile_id))]
+pub struct PurchaseProfile {
+    pub obfuscated_profile_id: String,
+    pub user_id: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Purchase the app is about to start for a user, resolves its owner until `expires_at`
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = crate::schema::purchase_intents)]
+pub struct PurchaseIntent {
+    pub id: String,
+    pub user_id: String,
+    pub package_name: String,
+    pub product_id: String,
+    /// Obfuscated account id the app sets on the purchase, when it isn't the user's principal
+    pub obfuscated_account_id: Option<String>,
+    pub obfuscated_profile_id: Option<String>,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl PurchaseIntent {
+    pub fn new(
+        user_id: String,
+        package_name: String,
+        product_id: String,
+        obfuscated_account_id: Option<String>,
+        obfuscated_profile_id: Option<String>,
+        expires_at: NaiveDateTime,
+        created_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            package_name,
+            product_id,
+            obfuscated_account_id,
+            obfuscated_profile_id,
+            expires_at,
+            created_at,
         }
     }
 }
@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::env;
+
+use crate::error::{AppError, AppResult};
+use crate::events::{DomainEvent, EventConsumer};
+use crate::types::CancelReason;
+use crate::AppState;
+
+/// Events pushed to the notification webhook for the app backend to fan out to users
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BillingEvent {
+    /// Subscription won't renew and ends soon, the app should prompt the user to re-subscribe
+    SubscriptionExpiringSoon {
+        user_id: String,
+        /// Expiry time in RFC 3339
+        expires_at: String,
+    },
+    /// Purchase is still unacknowledged and Google will void it at `deadline`
+    AcknowledgmentDeadlineApproaching {
+        user_id: String,
+        purchase_token: String,
+        /// Deadline in RFC 3339
+        deadline: String,
+    },
+    /// User turned off auto-renew while access lasts, a win-back offer can still keep them
+    CancellationIntent {
+        user_id: String,
+        purchase_token: String,
+        product_id: String,
+        /// When access ends unless renewal is turned back on, in RFC 3339
+        expires_at: String,
+    },
+    /// Renewal payment was declined, the user keeps access until `grace_ends_at` to fix it
+    PaymentDeclinedReminder {
+        user_id: String,
+        purchase_token: String,
+        product_id: String,
+        /// 1 for the first reminder of this grace period
+        reminder: u32,
+        /// Last reminder before access ends
+        final_reminder: bool,
+        /// End of the grace period in RFC 3339
+        grace_ends_at: String,
+    },
+    /// User is about to cancel in the Play Store, the app should show them a retention offer
+    RetentionOffer {
+        user_id: String,
+        purchase_token: String,
+        /// Reason the user gave for cancelling
+        reason: CancelReason,
+        /// When access ends if the user cancels, in RFC 3339
+        expires_at: String,
+    },
+}
+
+/// Delivers billing events to `NOTIFIER_WEBHOOK_URL`
+///
+/// When no URL is configured events are only logged.
+#[derive(Clone)]
+pub struct Notifier {
+    webhook_url: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn new(webhook_url: Option<String>) -> Self {
+        Self {
+            webhook_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::var("NOTIFIER_WEBHOOK_URL").ok())
+    }
+
+    pub async fn send(&self, event: &BillingEvent) -> AppResult<()> {
+        let Some(webhook_url) = &self.webhook_url else {
+            println!("Notifier not configured, dropping event: {:?}", event);
+            return Ok(());
+        };
+
+        let res = self.client.post(webhook_url).json(event).send().await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(AppError::InternalError(format!(
+                "Notifier webhook returned error status: {}",
+                res.status()
+            )))
+        }
+    }
+}
+
+/// Forwards domain events the app has to act on to the notification webhook
+#[async_trait]
+impl EventConsumer for Notifier {
+    fn name(&self) -> &'static str {
+        "notifier"
+    }
+
+    async fn handle(&self, _app_state: &AppState, event: &DomainEvent) -> AppResult<()> {
+        match event {
+            DomainEvent::RetentionOfferRequested {
+                user_id,
+                purchase_token,
+                reason,
+                expires_at,
+            } => {
+                self.send(&BillingEvent::RetentionOffer {
+                    user_id: user_id.clone(),
+                    purchase_token: purchase_token.clone(),
+                    reason: *reason,
+                    expires_at: expires_at.clone(),
+                })
+                .await
+            }
+            _ => Ok(()),
+        }
+    }
+}
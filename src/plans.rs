@@ -0,0 +1,166 @@
+use chrono::NaiveDateTime;
+
+use crate::config::{env_number, ConfigError};
+use crate::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use crate::types::Plan;
+
+/// What a plan grants, independent of how the user obtained it
+#[derive(Debug)]
+pub struct PlanDefinition {
+    pub plan: Plan,
+    /// Google Play product id that grants this plan, if it is purchasable
+    pub product_id: Option<&'static str>,
+    pub features: &'static [&'static str],
+    /// Video credits allotted per billing period, unless `CreditAllotments` overrides it
+    pub default_credit_allotment: u32,
+    /// Credits each lifecycle event adds, events without a rule add none
+    pub credit_rules: &'static [CreditRule],
+}
+
+/// Subscription lifecycle event that can add credits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreditEvent {
+    /// First grant of a new purchase
+    Purchase,
+    /// A new billing period started
+    Renewal,
+    /// A purchase replaced the subscription of another plan before its period ended
+    Upgrade,
+}
+
+/// How many credits a `CreditEvent` adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreditAmount {
+    /// The plan's whole allotment
+    Allotment,
+    /// Allotment gained over the replaced plan, for the share of the credit period left
+    ProratedDelta,
+}
+
+/// Credits a plan adds on one lifecycle event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditRule {
+    pub event: CreditEvent,
+    pub amount: CreditAmount,
+}
+
+/// Allotments refresh monthly, whatever the billing period of the product
+pub const CREDIT_PERIOD_DAYS: i64 = 30;
+
+/// Plan a purchase replaced and how much of its credit period was left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Upgrade {
+    pub from: Plan,
+    /// Between 0 and 1
+    pub remaining: f64,
+}
+
+impl Upgrade {
+    /// Upgrade from `from` at `now`, to a purchase whose period ends at `expiry`
+    pub fn new(from: Plan, expiry: NaiveDateTime, now: NaiveDateTime) -> Self {
+        let period = chrono::Duration::days(CREDIT_PERIOD_DAYS).num_seconds() as f64;
+        Self {
+            from,
+            remaining: ((expiry - now).num_seconds() as f64 / period).clamp(0.0, 1.0),
+        }
+    }
+}
+
+pub static PLAN_CATALOG: &[PlanDefinition] = &[
+    PlanDefinition {
+        plan: Plan::Free,
+        product_id: None,
+        features: &[],
+        default_credit_allotment: 0,
+        credit_rules: &[],
+    },
+    PlanDefinition {
+        plan: Plan::Pro,
+        product_id: Some(YRAL_PRO_PLAN_PRODUCT_ID),
+        features: &["video_generation"],
+        default_credit_allotment: 30,
+        credit_rules: &[
+            CreditRule {
+                event: CreditEvent::Purchase,
+                amount: CreditAmount::Allotment,
+            },
+            CreditRule {
+                event: CreditEvent::Renewal,
+                amount: CreditAmount::Allotment,
+            },
+            CreditRule {
+                event: CreditEvent::Upgrade,
+                amount: CreditAmount::ProratedDelta,
+            },
+        ],
+    },
+];
+
+pub fn plan_definition(plan: Plan) -> &'static PlanDefinition {
+    PLAN_CATALOG
+        .iter()
+        .find(|definition| definition.plan == plan)
+        .expect("every plan has a catalog entry")
+}
+
+/// Plan granted by a Google Play product, `None` for products outside the catalog
+pub fn plan_for_product(product_id: &str) -> Option<Plan> {
+    PLAN_CATALOG
+        .iter()
+        .find(|definition| definition.product_id == Some(product_id))
+        .map(|definition| definition.plan)
+}
+
+/// Video credits allotted per billing period, as configured for this deployment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditAllotments {
+    pub pro: u32,
+}
+
+impl Default for CreditAllotments {
+    fn default() -> Self {
+        Self {
+            pro: plan_definition(Plan::Pro).default_credit_allotment,
+        }
+    }
+}
+
+impl CreditAllotments {
+    /// Pro allotment from `PRO_CREDIT_ALLOTMENT`, the catalog default when unset
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Self::default();
+        Ok(Self {
+            pro: env_number("PRO_CREDIT_ALLOTMENT", defaults.pro)?,
+        })
+    }
+
+    pub fn for_plan(&self, plan: Plan) -> u32 {
+        match plan {
+            Plan::Free => plan_definition(Plan::Free).default_credit_allotment,
+            Plan::Pro => self.pro,
+        }
+    }
+
+    /// Credits `plan` adds on `event` following its catalog rules
+    ///
+    /// `upgrade` describes the replaced plan for `ProratedDelta`, without it nothing is added.
+    /// Moving to a plan with a smaller allotment never takes credits away.
+    pub fn credits_for(&self, plan: Plan, event: CreditEvent, upgrade: Option<&Upgrade>) -> u32 {
+        let rule = plan_definition(plan)
+            .credit_rules
+            .iter()
+            .find(|rule| rule.event == event);
+
+        match (rule.map(|rule| rule.amount), upgrade) {
+            (None, _) => 0,
+            (Some(CreditAmount::Allotment), _) => self.for_plan(plan),
+            (Some(CreditAmount::ProratedDelta), None) => 0,
+            (Some(CreditAmount::ProratedDelta), Some(upgrade)) => {
+                let delta = self
+                    .for_plan(plan)
+                    .saturating_sub(self.for_plan(upgrade.from));
+                (delta as f64 * upgrade.remaining).round() as u32
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Owners of purchases Google reports without the user's principal
+//!
+//! Purchases made from a secondary profile of a family or multi-profile account can carry
+//! `obfuscatedExternalProfileId` without `obfuscatedExternalAccountId`. The app registers the
+//! profile id it is about to set on such a purchase with `POST /google/profiles`. Before
+//! launching any billing flow it may also announce the purchase with
+//! `POST /google/purchase-intent`, naming the obfuscated ids it sets, which tells us the owner
+//! for a short while even when RTDN gets here before verify. `resolve_account_id` consults
+//! both when Google reports no principal, registered profiles first.
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::{AppError, AppResult};
+use crate::model::{PurchaseIntent, PurchaseProfile};
+use crate::user_id::{canonical_user_id, normalize_user_id};
+
+/// Longest obfuscated account or profile id Google accepts
+pub const MAX_PROFILE_ID_CHARS: usize = 64;
+
+/// How long a purchase intent resolves its purchase's owner, from `PURCHASE_INTENT_TTL_SECS`
+/// (default 30 minutes)
+pub fn intent_ttl_from_env() -> Result<chrono::Duration, ConfigError> {
+    let secs: i64 = env_number("PURCHASE_INTENT_TTL_SECS", 30 * 60)?;
+    Ok(chrono::Duration::seconds(secs))
+}
+
+fn check_obfuscated_id(kind: &str, obfuscated_id: &str) -> AppResult<()> {
+    if obfuscated_id.is_empty() || obfuscated_id.chars().count() > MAX_PROFILE_ID_CHARS {
+        return Err(AppError::BadRequest(format!(
+            "{} id must be 1 to {} characters",
+            kind, MAX_PROFILE_ID_CHARS
+        )));
+    }
+    Ok(())
+}
+
+/// User registered for `profile_id`, if any
+fn profile_owner(conn: &mut SqliteConnection, profile_id: &str) -> AppResult<Option<String>> {
+    use crate::schema::purchase_profiles::dsl::*;
+
+    Ok(purchase_profiles
+        .find(profile_id)
+        .select(user_id)
+        .first(conn)
+        .optional()?)
+}
+
+/// Register `user_id_param` as the user behind `profile_id`
+///
+/// Registering the same pair again is a no-op, a profile id is never moved to another user.
+pub fn register_profile(
+    conn: &mut SqliteConnection,
+    profile_id: &str,
+    user_id_param: &str,
+    now: NaiveDateTime,
+) -> AppResult<PurchaseProfile> {
+    use crate::schema::purchase_profiles::dsl::*;
+
+    check_obfuscated_id("Profile", profile_id)?;
+
+    diesel::insert_or_ignore_into(purchase_profiles)
+        .values(&PurchaseProfile {
+            obfuscated_profile_id: profile_id.to_string(),
+            user_id: user_id_param.to_string(),
+            created_at: now,
+        })
+        .execute(conn)?;
+
+    let profile: PurchaseProfile = purchase_profiles.find(profile_id).first(conn)?;
+    if profile.user_id != user_id_param {
+        return Err(AppError::ProfileAlreadyRegistered(profile_id.to_string()));
+    }
+    Ok(profile)
+}
+
+/// Store `intent`, refusing ids its user doesn't own
+///
+/// An account id that is a principal must be the intent's user, and an opaque one must not be
+/// named by another user's unexpired intent. A profile id must be registered to the intent's
+/// user. So an intent can't claim someone else's purchases.
+pub fn record_purchase_intent(
+    conn: &mut SqliteConnection,
+    intent: &PurchaseIntent,
+) -> AppResult<()> {
+    use crate::schema::purchase_intents;
+
+    if intent.obfuscated_account_id.is_none() && intent.obfuscated_profile_id.is_none() {
+        return Err(AppError::BadRequest(
+            "An obfuscated account id or profile id is required".to_string(),
+        ));
+    }
+    if let Some(account_id) = intent.obfuscated_account_id.as_deref() {
+        check_obfuscated_id("Account", account_id)?;
+        match canonical_user_id(account_id) {
+            Ok(principal) if principal != intent.user_id => return Err(AppError::AccountMismatch),
+            Ok(_) => {}
+            Err(_) => {
+                if account_claimed_by_other(conn, account_id, &intent.user_id, intent.created_at)? {
+                    return Err(AppError::NotOwner);
+                }
+            }
+        }
+    }
+    if let Some(profile_id) = intent.obfuscated_profile_id.as_deref() {
+        check_obfuscated_id("Profile", profile_id)?;
+        match profile_owner(conn, profile_id)? {
+            Some(owner) if owner == intent.user_id => {}
+            Some(_) => return Err(AppError::ProfileAlreadyRegistered(profile_id.to_string())),
+            None => return Err(AppError::NotOwner),
+        }
+    }
+
+    diesel::insert_into(purchase_intents::table)
+        .values(intent)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Whether another user than `user` has an unexpired intent naming the opaque `account_id`
+fn account_claimed_by_other(
+    conn: &mut SqliteConnection,
+    account_id: &str,
+    user: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::purchase_intents::dsl::*;
+
+    Ok(diesel::select(diesel::dsl::exists(
+        purchase_intents
+            .filter(obfuscated_account_id.eq(account_id))
+            .filter(user_id.ne(user))
+            .filter(expires_at.gt(now)),
+    ))
+    .get_result(conn)?)
+}
+
+/// User of the latest unexpired intent for `product` naming one of the reported ids
+fn intended_owner(
+    conn: &mut SqliteConnection,
+    account_id: Option<&str>,
+    profile_id: Option<&str>,
+    product: &str,
+    now: NaiveDateTime,
+) -> AppResult<Option<String>> {
+    use crate::schema::purchase_intents::dsl::*;
+
+    let query = purchase_intents
+        .filter(product_id.eq(product))
+        .filter(expires_at.gt(now))
+        .into_boxed();
+    let query = match (account_id, profile_id) {
+        (Some(account), Some(profile)) => query.filter(
+            obfuscated_account_id
+                .eq(account)
+                .or(obfuscated_profile_id.eq(profile)),
+        ),
+        (Some(account), None) => query.filter(obfuscated_account_id.eq(account)),
+        (None, Some(profile)) => query.filter(obfuscated_profile_id.eq(profile)),
+        (None, None) => return Ok(None),
+    };
+
+    Ok(query
+        .order(created_at.desc())
+        .select(user_id)
+        .first(conn)
+        .optional()?)
+}
+
+/// Delete intents that expired before `now`, returning how many
+pub fn prune_purchase_intents(conn: &mut SqliteConnection, now: NaiveDateTime) -> AppResult<usize> {
+    use crate::schema::purchase_intents::dsl::*;
+
+    Ok(diesel::delete(purchase_intents.filter(expires_at.le(now))).execute(conn)?)
+}
+
+/// User a purchase of `product` belongs to, from the identifiers Google reports for it
+///
+/// An obfuscated account id that is a principal wins. Otherwise the user registered for the
+/// profile id, then the user of an unexpired purchase intent naming the reported ids, then the
+/// account id as it is. Fails with `ExternalAccountIdentifiersMissing` when none
+/// leads to a user.
+pub fn resolve_account_id(
+    conn: &mut SqliteConnection,
+    account_id: Option<&str>,
+    profile_id: Option<&str>,
+    product: &str,
+    now: NaiveDateTime,
+) -> AppResult<String> {
+    if let Some(principal) = account_id.and_then(|id| canonical_user_id(id).ok()) {
+        return Ok(principal);
+    }
+    let registered = match profile_id {
+        Some(profile_id) => profile_owner(conn, profile_id)?,
+        None => None,
+    };
+    if let Some(owner) = registered {
+        return Ok(owner);
+    }
+    if let Some(owner) = intended_owner(conn, account_id, profile_id, product, now)? {
+        return Ok(owner);
+    }
+
+    account_id
+        .map(normalize_user_id)
+        .ok_or(AppError::ExternalAccountIdentifiersMissing)
+}
@@ -0,0 +1,197 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::{AppError, AppResult};
+use crate::model::{QuarantinedVerification, VerifyAttempt};
+use crate::types::{
+    GooglePlaySubscriptionResponse, QuarantineReason, QuarantineStatus, VerifyRequest,
+};
+
+/// Limits past which a verification is held for an operator instead of granted
+#[derive(Debug, Clone, Copy)]
+pub struct QuarantinePolicy {
+    /// Distinct users that may verify the same purchase token
+    pub max_users_per_token: u32,
+    /// Longest time between a purchase's start and its first grant
+    pub max_verify_delay: chrono::Duration,
+    /// Verifications a user may send per hour
+    pub max_attempts_per_hour: u32,
+}
+
+impl QuarantinePolicy {
+    /// Limits from `QUARANTINE_MAX_USERS_PER_TOKEN` (default 2),
+    /// `QUARANTINE_MAX_VERIFY_DELAY_SECS` (default 3 days) and
+    /// `QUARANTINE_MAX_ATTEMPTS_PER_HOUR` (default 20)
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let delay_secs: i64 = env_number("QUARANTINE_MAX_VERIFY_DELAY_SECS", 3 * 24 * 60 * 60)?;
+        Ok(Self {
+            max_users_per_token: env_number("QUARANTINE_MAX_USERS_PER_TOKEN", 2)?,
+            max_verify_delay: chrono::Duration::seconds(delay_secs),
+            max_attempts_per_hour: env_number("QUARANTINE_MAX_ATTEMPTS_PER_HOUR", 20)?,
+        })
+    }
+}
+
+/// Remember that `user` tried to verify `token`, whatever the outcome
+pub fn record_verify_attempt(
+    conn: &mut SqliteConnection,
+    token: &str,
+    user: &str,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::verify_attempts;
+
+    diesel::insert_into(verify_attempts::table)
+        .values(&VerifyAttempt::new(
+            token.to_string(),
+            user.to_string(),
+            now,
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The first heuristic a verification about to grant access trips, if any
+fn assess_verification(
+    conn: &mut SqliteConnection,
+    policy: &QuarantinePolicy,
+    payload: &VerifyRequest,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<Option<QuarantineReason>> {
+    use crate::schema::verify_attempts::dsl::*;
+
+    let users: i64 = verify_attempts
+        .filter(purchase_token.eq(&payload.purchase_token))
+        .select(diesel::dsl::count_distinct(user_id))
+        .first(conn)?;
+    if users > policy.max_users_per_token as i64 {
+        return Ok(Some(QuarantineReason::SharedToken));
+    }
+
+    let recent: i64 = verify_attempts
+        .filter(user_id.eq(&payload.user_id))
+        .filter(attempted_at.gt(now - chrono::Duration::hours(1)))
+        .count()
+        .get_result(conn)?;
+    if recent > policy.max_attempts_per_hour as i64 {
+        return Ok(Some(QuarantineReason::HighVelocity));
+    }
+
+    let started_at = subscription_response
+        .start_time
+        .as_deref()
+        .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.naive_utc());
+    if started_at.is_some_and(|started_at| now - started_at > policy.max_verify_delay) {
+        return Ok(Some(QuarantineReason::LateVerification));
+    }
+
+    Ok(None)
+}
+
+/// Most recent quarantine entry of a purchase token
+pub fn latest_quarantine(
+    conn: &mut SqliteConnection,
+    token: &str,
+) -> AppResult<Option<QuarantinedVerification>> {
+    use crate::schema::verification_quarantine::dsl::*;
+
+    Ok(verification_quarantine
+        .filter(purchase_token.eq(token))
+        .order(created_at.desc())
+        .first(conn)
+        .optional()?)
+}
+
+/// Decide whether a verification about to grant access waits for an operator instead
+///
+/// A token an operator already decided on keeps that decision, approved tokens are granted
+/// and rejected ones fail. Otherwise a pending entry is stored once a heuristic trips, and
+/// later verifications of the token by any user wait for the same entry.
+pub fn hold_for_review(
+    conn: &mut SqliteConnection,
+    policy: &QuarantinePolicy,
+    payload: &VerifyRequest,
+    account: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> AppResult<Option<QuarantineReason>> {
+    use crate::schema::verification_quarantine;
+
+    if let Some(entry) = latest_quarantine(conn, &payload.purchase_token)? {
+        return match entry.status {
+            QuarantineStatus::Pending => Ok(Some(entry.reason)),
+            QuarantineStatus::Approved => Ok(None),
+            QuarantineStatus::Rejected => Err(AppError::VerificationRejected),
+        };
+    }
+
+    let Some(reason) = assess_verification(conn, policy, payload, subscription_response, now)?
+    else {
+        return Ok(None);
+    };
+
+    let entry = QuarantinedVerification::new(
+        payload.user_id.clone(),
+        account.to_string(),
+        payload.purchase_token.clone(),
+        payload.package_name.clone(),
+        payload.product_id.clone(),
+        reason,
+        now,
+    );
+    diesel::insert_into(verification_quarantine::table)
+        .values(&entry)
+        .execute(conn)?;
+    println!(
+        "Quarantined verification of purchase token {} by {}: {}",
+        payload.purchase_token,
+        payload.user_id,
+        reason.as_str()
+    );
+
+    Ok(Some(reason))
+}
+
+/// Quarantine entries with `state`, the oldest first so operators work through them in order
+pub fn quarantined_verifications(
+    conn: &mut SqliteConnection,
+    state: QuarantineStatus,
+) -> AppResult<Vec<QuarantinedVerification>> {
+    use crate::schema::verification_quarantine::dsl::*;
+
+    Ok(verification_quarantine
+        .filter(status.eq(state))
+        .order(created_at.asc())
+        .load(conn)?)
+}
+
+/// Approve or reject a pending entry, returning `None` when it isn't pending
+pub fn decide_quarantine(
+    conn: &mut SqliteConnection,
+    entry_id: &str,
+    decision: QuarantineStatus,
+    operator: &str,
+    now: NaiveDateTime,
+) -> AppResult<Option<QuarantinedVerification>> {
+    use crate::schema::verification_quarantine::dsl::*;
+
+    let updated = diesel::update(
+        verification_quarantine
+            .filter(id.eq(entry_id))
+            .filter(status.eq(QuarantineStatus::Pending)),
+    )
+    .set((
+        status.eq(decision),
+        decided_at.eq(Some(now)),
+        decided_by.eq(Some(operator)),
+    ))
+    .execute(conn)?;
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(verification_quarantine.find(entry_id).first(conn)?))
+}
@@ -0,0 +1,125 @@
+//! Depth and age of the tables work waits in before a background worker handles it
+//!
+//! Each worker describes its table through `QueueStats`. The queue monitor job exports every
+//! queue's figures as gauges labelled by `queue`, and `GET /admin/queues` reports them on
+//! demand. A queue whose oldest due item waited past its `max_age` counts as stalled and is
+//! reported to Sentry on every check until it drains.
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::error::AppResult;
+use crate::jobs::access_outbox::AccessOutboxQueue;
+use crate::jobs::analytics_export::AnalyticsEventQueue;
+use crate::jobs::event_redelivery::DomainEventQueue;
+use crate::jobs::grace_reminders::GraceReminderQueue;
+use crate::jobs::held_notifications::HeldNotificationQueue;
+use crate::jobs::pending_purchases::ProvisionalPurchaseQueue;
+use crate::metrics::{
+    Metrics, QUEUE_DEPTH, QUEUE_FAILED, QUEUE_OLDEST_AGE_SECONDS, QUEUE_PROCESSED_LAST_HOUR,
+};
+use crate::types::QueueStatsResponse;
+
+/// Figures of one queue at a point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueSnapshot {
+    /// Items waiting to be handled, due or not
+    pub pending: i64,
+    /// Items given up on, `None` for queues that never give up
+    pub failed: Option<i64>,
+    /// When the longest waiting item became due, `None` while nothing is due
+    pub oldest_due_at: Option<NaiveDateTime>,
+    /// Items handled over the last hour, `None` for queues that don't keep handled items
+    pub processed_last_hour: Option<i64>,
+}
+
+/// A table a background worker drains
+pub trait QueueStats: Send + Sync {
+    /// `queue` label of the gauges
+    fn name(&self) -> &'static str;
+
+    /// How long an item may stay due before the queue counts as stalled
+    fn max_age(&self) -> chrono::Duration;
+
+    fn snapshot(&self, conn: &mut SqliteConnection, now: NaiveDateTime)
+        -> AppResult<QueueSnapshot>;
+}
+
+/// Every monitored queue
+pub fn queues() -> Vec<Box<dyn QueueStats>> {
+    vec![
+        Box::new(AccessOutboxQueue),
+        Box::new(DomainEventQueue),
+        Box::new(HeldNotificationQueue),
+        Box::new(GraceReminderQueue::from_env()),
+        Box::new(AnalyticsEventQueue),
+        Box::new(ProvisionalPurchaseQueue::from_env()),
+    ]
+}
+
+/// Current figures of every queue
+pub fn queue_stats(
+    conn: &mut SqliteConnection,
+    now: NaiveDateTime,
+) -> AppResult<Vec<QueueStatsResponse>> {
+    queues()
+        .iter()
+        .map(|queue| {
+            let snapshot = queue.snapshot(conn, now)?;
+            let age = snapshot
+                .oldest_due_at
+                .map(|due_at| (now - due_at).max(chrono::Duration::zero()));
+            Ok(QueueStatsResponse {
+                queue: queue.name().to_string(),
+                pending: snapshot.pending,
+                failed: snapshot.failed,
+                oldest_due_at: snapshot
+                    .oldest_due_at
+                    .map(|due_at| due_at.and_utc().to_rfc3339()),
+                oldest_age_secs: age.map(|age| age.num_seconds()),
+                processed_last_hour: snapshot.processed_last_hour,
+                max_age_secs: queue.max_age().num_seconds(),
+                stalled: age.is_some_and(|age| age > queue.max_age()),
+            })
+        })
+        .collect()
+}
+
+/// Export every queue's figures to `metrics` and report stalled queues, returning the stalled
+/// ones
+pub fn check_queues(
+    conn: &mut SqliteConnection,
+    metrics: &Metrics,
+    now: NaiveDateTime,
+) -> AppResult<Vec<QueueStatsResponse>> {
+    let stats = queue_stats(conn, now)?;
+    for queue in &stats {
+        let labels = [("queue", queue.queue.as_str())];
+        metrics.set_gauge(QUEUE_DEPTH, &labels, queue.pending);
+        metrics.set_gauge(
+            QUEUE_OLDEST_AGE_SECONDS,
+            &labels,
+            queue.oldest_age_secs.unwrap_or(0),
+        );
+        if let Some(failed) = queue.failed {
+            metrics.set_gauge(QUEUE_FAILED, &labels, failed);
+        }
+        if let Some(processed) = queue.processed_last_hour {
+            metrics.set_gauge(QUEUE_PROCESSED_LAST_HOUR, &labels, processed);
+        }
+
+        if queue.stalled {
+            sentry::capture_message(
+                &format!(
+                    "Queue {} is stalled: {} pending, oldest due {}s ago",
+                    queue.queue,
+                    queue.pending,
+                    queue.oldest_age_secs.unwrap_or(0)
+                ),
+                sentry::Level::Warning,
+            );
+        }
+    }
+
+    Ok(stats.into_iter().filter(|queue| queue.stalled).collect())
+}
@@ -0,0 +1,57 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::error::AppError;
+
+/// Body limit for client-facing JSON endpoints such as verify and grant
+pub const DEFAULT_JSON_BODY_LIMIT: usize = 64 * 1024;
+
+/// Body limit for the Pub/Sub RTDN webhook
+pub const RTDN_BODY_LIMIT: usize = 256 * 1024;
+
+fn is_json_content_type(req: &Request) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+        .unwrap_or(false)
+}
+
+/// Reject requests that are not `application/json` or whose body exceeds `limit` bytes
+///
+/// Use with `middleware::from_fn(move |req, next| enforce_json_body(req, next, limit))`.
+pub async fn enforce_json_body(
+    req: Request,
+    next: Next,
+    limit: usize,
+) -> Result<Response, AppError> {
+    if !is_json_content_type(&req) {
+        return Err(AppError::UnsupportedMediaType);
+    }
+
+    // Fail fast on an honest Content-Length before reading anything
+    let declared_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared_length.is_some_and(|length| length > limit) {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+
+    // Buffer with a cap so chunked bodies without Content-Length are bounded too
+    let (parts, body) = req.into_parts();
+    let bytes = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|_| AppError::PayloadTooLarge(limit))?;
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
@@ -0,0 +1,112 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::{AppError, AppResult};
+use crate::model::UserRisk;
+
+/// Refund abuse signals counted per user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskEvent {
+    /// `SUBSCRIPTION_REVOKED`, sent when a subscription is refunded and access ends at once
+    Revoked,
+    /// Voided purchase, sent for refunds and chargebacks
+    Voided,
+}
+
+/// Revocations plus voided purchases at which a user is flagged, from `RISK_FLAG_THRESHOLD`
+pub fn threshold_from_env() -> Result<u32, ConfigError> {
+    env_number("RISK_FLAG_THRESHOLD", 3)
+}
+
+/// Count `event` against `user` and flag them once their total reaches `threshold`
+///
+/// A user an operator already approved is flagged again by the next event past the
+/// threshold, so approval covers only the history it was given for.
+pub fn record_risk_event(
+    conn: &mut SqliteConnection,
+    user: &str,
+    event: RiskEvent,
+    threshold: u32,
+    now: NaiveDateTime,
+) -> AppResult<UserRisk> {
+    use crate::schema::user_risk::dsl::*;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let mut risk = user_risk
+            .filter(user_id.eq(user))
+            .first::<UserRisk>(conn)
+            .optional()?
+            .unwrap_or_else(|| UserRisk::new(user.to_string(), now));
+
+        match event {
+            RiskEvent::Revoked => risk.revoked_count += 1,
+            RiskEvent::Voided => risk.voided_count += 1,
+        }
+        risk.updated_at = now;
+
+        let total = (risk.revoked_count + risk.voided_count) as u32;
+        if total >= threshold && (risk.flagged_at.is_none() || risk.approved_at.is_some()) {
+            risk.flagged_at = Some(now);
+            risk.approved_at = None;
+            risk.approved_by = None;
+            println!(
+                "Flagged user {} for review after {} revocations and {} voided purchases",
+                user, risk.revoked_count, risk.voided_count
+            );
+        }
+
+        diesel::replace_into(user_risk)
+            .values(&risk)
+            .execute(conn)?;
+        Ok(risk)
+    })
+}
+
+/// Whether `user` is flagged and waiting for an operator to approve them
+pub fn requires_approval(conn: &mut SqliteConnection, user: &str) -> AppResult<bool> {
+    use crate::schema::user_risk::dsl::*;
+
+    let risk: Option<UserRisk> = user_risk.filter(user_id.eq(user)).first(conn).optional()?;
+
+    Ok(risk.is_some_and(|risk| risk.requires_approval()))
+}
+
+/// Flagged users, the most recently flagged first
+pub fn flagged_users(conn: &mut SqliteConnection) -> AppResult<Vec<UserRisk>> {
+    use crate::schema::user_risk::dsl::*;
+
+    Ok(user_risk
+        .filter(flagged_at.is_not_null())
+        .order(flagged_at.desc())
+        .load(conn)?)
+}
+
+/// Clear the flag on `user` so their verifications grant access again
+///
+/// Returns `None` for users that were never flagged.
+pub fn approve_user(
+    conn: &mut SqliteConnection,
+    user: &str,
+    operator: &str,
+    now: NaiveDateTime,
+) -> AppResult<Option<UserRisk>> {
+    use crate::schema::user_risk::dsl::*;
+
+    let updated = diesel::update(
+        user_risk
+            .filter(user_id.eq(user))
+            .filter(flagged_at.is_not_null()),
+    )
+    .set((
+        approved_at.eq(Some(now)),
+        approved_by.eq(Some(operator)),
+        updated_at.eq(now),
+    ))
+    .execute(conn)?;
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(user_risk.filter(user_id.eq(user)).first(conn)?))
+}
@@ -0,0 +1,211 @@
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::api_version::ApiVersion;
+use crate::config::ConfigError;
+
+/// Path prefixes of the admin and internal route groups, without a version prefix
+pub const INTERNAL_ROUTE_PREFIXES: &[&str] = &["/admin", "/internal"];
+
+/// Whether `path` belongs to the admin or internal route groups, with or without a version prefix
+pub fn is_internal_route(path: &str) -> bool {
+    let path = match ApiVersion::from_path(path) {
+        Some(version) => &path[version.prefix().len()..],
+        None => path,
+    };
+    INTERNAL_ROUTE_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// An address range in CIDR notation, a bare address is a range of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            // IPv4 peers on a dual-stack socket show up mapped into IPv6
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("`{}` is not an IP address", address))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_len)
+                .ok_or_else(|| format!("`{}` has an invalid prefix length", value))?,
+            None => max_len,
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// Addresses allowed to call admin and internal routes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpAllowlist {
+    ranges: Vec<IpRange>,
+}
+
+impl IpAllowlist {
+    /// Comma separated addresses and CIDR ranges, e.g. `10.0.0.0/8,127.0.0.1`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let ranges = value
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<IpRange>, String>>()?;
+        if ranges.is_empty() {
+            return Err("no addresses given".to_string());
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+}
+
+/// Listener a router is served on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listener {
+    /// `PORT`, reachable from the internet
+    Public,
+    /// `INTERNAL_PORT`, only for admin and internal routes
+    Internal,
+}
+
+/// Where the admin and internal route groups may be reached from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteExposure {
+    /// Port of a second listener serving the admin and internal routes exclusively
+    pub internal_port: Option<u16>,
+    /// Peers allowed to call admin and internal routes, on whichever listener serves them
+    pub allowlist: Option<IpAllowlist>,
+}
+
+impl RouteExposure {
+    /// `INTERNAL_PORT` and `INTERNAL_ALLOWLIST`, both unset serves everything on `PORT`
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let internal_port = env::var("INTERNAL_PORT")
+            .ok()
+            .map(|value| {
+                value.parse().map_err(|_| ConfigError::InvalidNumber {
+                    name: "INTERNAL_PORT",
+                    value,
+                })
+            })
+            .transpose()?;
+        let allowlist = match env::var("INTERNAL_ALLOWLIST") {
+            Ok(value) => Some(IpAllowlist::parse(&value).map_err(ConfigError::InternalAllowlist)?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            internal_port,
+            allowlist,
+        })
+    }
+
+    /// Whether a request for `path` from `peer` may be served on `listener`
+    ///
+    /// Refused requests get a 404 when the route isn't served on the listener at all, so it
+    /// looks like any unknown path, and a 403 when the peer isn't on the allowlist.
+    pub fn check(
+        &self,
+        listener: Listener,
+        path: &str,
+        peer: Option<IpAddr>,
+    ) -> Result<(), StatusCode> {
+        let internal = is_internal_route(path);
+        match listener {
+            Listener::Public if internal && self.internal_port.is_some() => {
+                return Err(StatusCode::NOT_FOUND);
+            }
+            // Health checks of the internal listener itself stay reachable
+            Listener::Internal if !internal && path != "/health" => {
+                return Err(StatusCode::NOT_FOUND);
+            }
+            _ => {}
+        }
+
+        if let Some(allowlist) = &self.allowlist {
+            let allowed = peer.is_some_and(|peer| allowlist.contains(peer));
+            if internal && !allowed {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serve on `listener` only the routes `RouteExposure` allows there
+///
+/// The peer address comes from `ConnectInfo`, so the router must be served with
+/// `into_make_service_with_connect_info::<SocketAddr>()`. Use with
+/// `middleware::from_fn_with_state((exposure, listener), ...)` on the whole router.
+pub async fn enforce_route_exposure(
+    State((exposure, listener)): State<(RouteExposure, Listener)>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Err(status) = exposure.check(listener, req.uri().path(), peer) {
+        if status == StatusCode::FORBIDDEN {
+            eprintln!(
+                "Refused {} from {:?}, not on INTERNAL_ALLOWLIST",
+                req.uri().path(),
+                peer
+            );
+        }
+        return Err(status);
+    }
+    Ok(next.run(req).await)
+}
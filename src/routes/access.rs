@@ -0,0 +1,489 @@
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+
+use crate::auth::Claims;
+use crate::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use crate::db::token_versions::{ensure_unchanged, reserve_token};
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::pending_purchases::{reverify_purchase_token, ReverifyOutcome};
+use crate::line_items::{access_expiry, granting_product};
+use crate::model::{AdminAuditEntry, PurchaseToken};
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{
+    ApiResponse, AuditAction, DeferSubscriptionRequest, DeferSubscriptionResponse, EmptyData,
+    ManualAccessResponse, ManualGrantRequest, ManualRevokeRequest, PurchaseEnvironment,
+    PurchaseTokenStatus, ReverifyResponse, ReverifyResult,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+/// Prefix of the synthetic purchase tokens created by manual grants
+pub const MANUAL_TOKEN_PREFIX: &str = "manual-";
+
+/// Longest extension support can give in one deferral
+pub const MAX_DEFERRAL_DAYS: u32 = 365;
+
+fn validate_reason(reason: &str) -> AppResult<()> {
+    if reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "A reason is required for manual access changes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn operator(claims: &Claims) -> String {
+    // `require_admin_scope` guarantees `sub` is present
+    claims.sub.clone().unwrap_or_default()
+}
+
+/// Move a user to Pro on the IC and record who did it and why
+///
+/// With `record_purchase`, a synthetic `manual` purchase lasting `duration_days` is stored so
+/// entitlements report Pro and the expiry sweep revokes it when it ends.
+pub async fn grant_manual_access(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    operator: &str,
+    request: &ManualGrantRequest,
+    now: NaiveDateTime,
+) -> AppResult<ManualAccessResponse> {
+    use crate::schema::{admin_audit_log, purchase_tokens};
+
+    validate_reason(&request.reason)?;
+    if request.duration_days == 0 {
+        return Err(AppError::BadRequest(
+            "duration_days must be greater than zero".to_string(),
+        ));
+    }
+
+    user_info
+        .grant_pro_plan(YRAL_PRO_PLAN_PRODUCT_ID, &request.user_id)
+        .await?;
+
+    let purchase = request.record_purchase.then(|| {
+        let mut token = PurchaseToken::new(
+            request.user_id.clone(),
+            format!("{}{}", MANUAL_TOKEN_PREFIX, uuid::Uuid::new_v4()),
+            now + chrono::Duration::days(request.duration_days as i64),
+            PurchaseTokenStatus::AccessGranted,
+            PurchaseEnvironment::Manual,
+        );
+        token.created_at = now;
+        token.auto_renewing = false;
+        // Nothing to acknowledge with Google
+        token.acknowledged_at = Some(now);
+        token
+    });
+
+    let entry = AdminAuditEntry::new(
+        operator.to_string(),
+        AuditAction::GrantPro,
+        request.user_id.clone(),
+        request.reason.clone(),
+        purchase.as_ref().map(|token| token.purchase_token.clone()),
+        now,
+    );
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        if let Some(token) = &purchase {
+            diesel::insert_into(purchase_tokens::table)
+                .values(token)
+                .execute(conn)?;
+        }
+        diesel::insert_into(admin_audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    println!(
+        "Manual Pro grant for user {} by {}: {}",
+        request.user_id, operator, request.reason
+    );
+
+    Ok(ManualAccessResponse {
+        audit_id: entry.id,
+        user_id: entry.user_id,
+        action: entry.action,
+        operator: entry.operator,
+        purchase_token: purchase.as_ref().map(|token| token.purchase_token.clone()),
+        expiry_at: purchase.map(|token| token.expiry_at.and_utc().to_rfc3339()),
+        expired_purchases: 0,
+    })
+}
+
+/// Move a user back to Free on the IC and record who did it and why
+///
+/// With `expire_purchases`, the user's active purchases are marked expired so entitlements
+/// stop reporting Pro. A later renewal from Google grants access again.
+pub async fn revoke_manual_access(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    operator: &str,
+    request: &ManualRevokeRequest,
+    now: NaiveDateTime,
+) -> AppResult<ManualAccessResponse> {
+    use crate::schema::{admin_audit_log, purchase_tokens};
+
+    validate_reason(&request.reason)?;
+    // Only purchases that still grant access are expired
+    let expired_status = TokenStateMachine::next(
+        PurchaseTokenStatus::AccessGranted,
+        TransitionReason::OperatorRevoked,
+    )?;
+
+    // Taken before the IC call, so a purchase that changes meanwhile fails the revoke
+    let mut granted: Vec<PurchaseToken> = Vec::new();
+    if request.expire_purchases {
+        let loaded: Vec<PurchaseToken> = purchase_tokens::table
+            .filter(purchase_tokens::user_id.eq(&request.user_id))
+            .filter(purchase_tokens::status.eq(PurchaseTokenStatus::AccessGranted))
+            .load(conn)?;
+        for token in &loaded {
+            granted.push(reserve_token(conn, token)?);
+        }
+    }
+
+    user_info.revoke_pro_plan(&request.user_id).await?;
+
+    let entry = AdminAuditEntry::new(
+        operator.to_string(),
+        AuditAction::RevokePro,
+        request.user_id.clone(),
+        request.reason.clone(),
+        None,
+        now,
+    );
+
+    let expired = conn.transaction::<_, AppError, _>(|conn| {
+        for token in &granted {
+            let updated = diesel::update(
+                purchase_tokens::table
+                    .filter(purchase_tokens::id.eq(&token.id))
+                    .filter(purchase_tokens::version.eq(token.version)),
+            )
+            .set(purchase_tokens::status.eq(expired_status))
+            .execute(conn)?;
+            ensure_unchanged(updated, token)?;
+        }
+        diesel::insert_into(admin_audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+        Ok(granted.len())
+    })?;
+
+    println!(
+        "Manual Pro revoke for user {} by {}: {}",
+        request.user_id, operator, request.reason
+    );
+
+    Ok(ManualAccessResponse {
+        audit_id: entry.id,
+        user_id: entry.user_id,
+        action: entry.action,
+        operator: entry.operator,
+        purchase_token: None,
+        expiry_at: None,
+        expired_purchases: expired,
+    })
+}
+
+/// Push back a subscription's renewal by `days` through Google and mirror the new expiry
+///
+/// Google's current expiry is fetched first since the defer call must quote it.
+pub async fn defer_subscription_expiry(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    operator: &str,
+    purchase_token_param: &str,
+    request: &DeferSubscriptionRequest,
+    now: NaiveDateTime,
+) -> AppResult<DeferSubscriptionResponse> {
+    use crate::schema::{admin_audit_log, purchase_tokens};
+
+    validate_reason(&request.reason)?;
+    if request.days == 0 || request.days > MAX_DEFERRAL_DAYS {
+        return Err(AppError::BadRequest(format!(
+            "days must be between 1 and {}",
+            MAX_DEFERRAL_DAYS
+        )));
+    }
+
+    let token: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(purchase_token_param))
+        .first(conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest("Unknown purchase token".to_string()))?;
+
+    if token.environment == PurchaseEnvironment::Manual {
+        return Err(AppError::BadRequest(
+            "Manual grants are not Google subscriptions, grant access again instead".to_string(),
+        ));
+    }
+    if token.status != PurchaseTokenStatus::AccessGranted {
+        return Err(AppError::BadRequest(
+            "Only active subscriptions can be deferred".to_string(),
+        ));
+    }
+    let package_name = token.package_name.clone().ok_or_else(|| {
+        AppError::BadRequest("Purchase token has no package name recorded".to_string())
+    })?;
+
+    let subscription_response = google_play
+        .fetch_subscription(&package_name, purchase_token_param)
+        .await?;
+    let subscription_id = granting_product(&subscription_response)
+        .ok_or(AppError::SubscriptionInvalidLineItems)?
+        .to_string();
+    let expected_expiry = access_expiry(&subscription_response)
+        .ok_or(AppError::SubscriptionInvalidLineItems)?
+        .and_utc();
+    let desired_expiry = expected_expiry + chrono::Duration::days(request.days as i64);
+
+    let new_expiry = google_play
+        .defer_subscription(
+            &package_name,
+            &subscription_id,
+            purchase_token_param,
+            expected_expiry,
+            desired_expiry,
+        )
+        .await?;
+
+    let entry = AdminAuditEntry::new(
+        operator.to_string(),
+        AuditAction::DeferSubscription,
+        token.user_id.clone(),
+        format!("+{} days: {}", request.days, request.reason),
+        Some(token.purchase_token.clone()),
+        now,
+    );
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        // A new expiry deserves a fresh reminder
+        let updated = diesel::update(
+            purchase_tokens::table
+                .filter(purchase_tokens::id.eq(&token.id))
+                .filter(purchase_tokens::version.eq(token.version)),
+        )
+        .set((
+            purchase_tokens::expiry_at.eq(new_expiry.naive_utc()),
+            purchase_tokens::notified_at.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)?;
+        ensure_unchanged(updated, &token)?;
+        diesel::insert_into(admin_audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    println!(
+        "Deferred subscription of user {} by {} days to {} by {}: {}",
+        token.user_id, request.days, new_expiry, operator, request.reason
+    );
+
+    Ok(DeferSubscriptionResponse {
+        audit_id: entry.id,
+        purchase_token: token.purchase_token,
+        user_id: token.user_id,
+        operator: entry.operator,
+        previous_expiry_at: expected_expiry.to_rfc3339(),
+        expiry_at: new_expiry.to_rfc3339(),
+    })
+}
+
+/// Manually grant Pro access to a user
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/access/grant",
+    request_body = ManualGrantRequest,
+    responses(
+        (status = 200, description = "Access granted", body = ApiResponse<ManualAccessResponse>),
+        (status = 400, description = "Missing reason or invalid duration", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn grant_access(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<ManualGrantRequest>,
+) -> Result<Json<ApiResponse<ManualAccessResponse>>, AppError> {
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    let mut conn = app_state.get_db_connection()?;
+
+    let response = grant_manual_access(
+        &mut conn,
+        app_state.user_info.as_ref(),
+        &operator(&claims),
+        &payload,
+        app_state.clock.now_naive(),
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Manually revoke Pro access from a user
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/access/revoke",
+    request_body = ManualRevokeRequest,
+    responses(
+        (status = 200, description = "Access revoked", body = ApiResponse<ManualAccessResponse>),
+        (status = 400, description = "Missing reason", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn revoke_access(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<ManualRevokeRequest>,
+) -> Result<Json<ApiResponse<ManualAccessResponse>>, AppError> {
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    let mut conn = app_state.get_db_connection()?;
+
+    let response = revoke_manual_access(
+        &mut conn,
+        app_state.user_info.as_ref(),
+        &operator(&claims),
+        &payload,
+        app_state.clock.now_naive(),
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Extend a subscription by a number of days, e.g. to compensate for an outage
+///
+/// Google moves the next renewal date, so the user is not charged for the extra days.
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/subscriptions/{token}/defer",
+    params(
+        ("token" = String, Path, description = "Purchase token of the subscription"),
+    ),
+    request_body = DeferSubscriptionRequest,
+    responses(
+        (status = 200, description = "Subscription deferred", body = ApiResponse<DeferSubscriptionResponse>),
+        (status = 400, description = "Unknown or inactive token, invalid days or Google rejected the deferral", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn defer_subscription(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(token): Path<String>,
+    Json(payload): Json<DeferSubscriptionRequest>,
+) -> Result<Json<ApiResponse<DeferSubscriptionResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let response = defer_subscription_expiry(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &operator(&claims),
+        &token,
+        &payload,
+        app_state.clock.now_naive(),
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Ask Google about a stored subscription again and bring the stored row in line
+///
+/// The same as `yral-billing reverify`, for support staff without shell access. An active
+/// subscription gets access granted again, an ended one its expiry corrected.
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim.
+#[utoipa::path(
+    post,
+    path = "/admin/subscriptions/{token}/reverify",
+    params(
+        ("token" = String, Path, description = "Purchase token of the subscription"),
+    ),
+    responses(
+        (status = 200, description = "Subscription reverified", body = ApiResponse<ReverifyResponse>),
+        (status = 400, description = "Unknown purchase token or no package name recorded", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn reverify_subscription(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(token): Path<String>,
+) -> Result<Json<ApiResponse<ReverifyResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let outcome = reverify_purchase_token(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.user_info.as_ref(),
+        app_state.clock.as_ref(),
+        &token,
+    )
+    .await?;
+    println!(
+        "Reverified purchase token {} for {}: {:?}",
+        token,
+        operator(&claims),
+        outcome
+    );
+
+    let (result, expiry_at) = match outcome {
+        ReverifyOutcome::Granted => (ReverifyResult::Granted, None),
+        ReverifyOutcome::StillPending => (ReverifyResult::StillPending, None),
+        ReverifyOutcome::Ended { expiry_at } => (
+            ReverifyResult::Ended,
+            Some(expiry_at.and_utc().to_rfc3339()),
+        ),
+    };
+
+    Ok(Json(ApiResponse::success(ReverifyResponse {
+        purchase_token: token,
+        result,
+        expiry_at,
+    })))
+}
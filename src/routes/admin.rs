@@ -0,0 +1,783 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    db::{
+        database_size_bytes, integrity_problems,
+        pagination::{before_cursor, decode_cursor, into_page, page_limit},
+    },
+    debug_log::redact_personal_data,
+    error::AppError,
+    feature_flags::FeatureFlags,
+    ic_identity::AdminIdentity,
+    integrations::google_play::snapshots::{snapshot_body, snapshots_for_token},
+    line_items::{access_expiry, line_items_for_token},
+    model::{PurchaseToken, RevenueEvent, ScheduledJob},
+    plans::plan_for_product,
+    queues::queue_stats,
+    routes::export::{event_cursor, event_record, token_cursor, token_record},
+    types::{
+        AcknowledgementState, ApiResponse, ClusterResponse, DbIntegrityResponse, DebugLogEntry,
+        EmptyData, FeatureFlagResponse, GooglePlaySubscriptionResponse, IcIdentityResponse,
+        PaginatedResponse, PurchaseTokenStatus, QueueStatsResponse, RevenueEventExportRecord,
+        ScheduledJobResponse, SetFeatureFlagRequest, SubscriptionLineItemResponse,
+        SubscriptionSnapshotResponse, SubscriptionState, TokenDiffResponse, TokenExportRecord,
+        TokenMismatch, TokenMismatchKind, UserPlanResponse, UserPlansRequest,
+    },
+    user_id::normalize_user_id,
+    AppState,
+};
+
+/// Items per page of an admin listing unless `limit` says otherwise
+pub const DEFAULT_LIST_LIMIT: i64 = 50;
+/// Most items one page of an admin listing may carry
+pub const MAX_LIST_LIMIT: i64 = 200;
+/// Most users one plan lookup may ask for
+pub const MAX_PLAN_LOOKUP_USERS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct AdminListQuery {
+    /// Only items of this user
+    pub user_id: Option<String>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn identity_response(identity: &AdminIdentity) -> IcIdentityResponse {
+    IcIdentityResponse {
+        principal: identity.principal().to_text(),
+        source: identity.source().describe(),
+        loaded_at: identity.loaded_at().to_rfc3339(),
+    }
+}
+
+/// Report the admin IC identity currently in use
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/ic-identity",
+    responses(
+        (status = 200, description = "Current admin identity", body = ApiResponse<IcIdentityResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Admin identity not configured", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_ic_identity(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<IcIdentityResponse>>, AppError> {
+    let identity = app_state
+        .admin_identity
+        .as_ref()
+        .ok_or(AppError::AdminIcAgentMissing)?;
+
+    Ok(Json(ApiResponse::success(identity_response(identity))))
+}
+
+/// Reload the admin IC identity from its key source immediately
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/ic-identity/reload",
+    responses(
+        (status = 200, description = "Identity reloaded", body = ApiResponse<IcIdentityResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Reload failed or identity not configured", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn reload_ic_identity(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<IcIdentityResponse>>, AppError> {
+    let identity = app_state
+        .admin_identity
+        .as_ref()
+        .ok_or(AppError::AdminIcAgentMissing)?;
+
+    let rotated = identity.reload().map_err(AppError::InternalError)?;
+    let msg = if rotated {
+        "Admin identity rotated".to_string()
+    } else {
+        "Admin identity unchanged".to_string()
+    };
+
+    Ok(Json(ApiResponse::success_with_msg(
+        identity_response(identity),
+        msg,
+    )))
+}
+
+fn flag_response(flags: &FeatureFlags, name: &str) -> FeatureFlagResponse {
+    let (enabled, source) = flags.resolve(name);
+    FeatureFlagResponse {
+        name: name.to_string(),
+        enabled,
+        source,
+    }
+}
+
+/// List feature flags with their effective values
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/feature-flags",
+    responses(
+        (status = 200, description = "Feature flags", body = ApiResponse<Vec<FeatureFlagResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject")
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_feature_flags(
+    State(app_state): State<AppState>,
+) -> Json<ApiResponse<Vec<FeatureFlagResponse>>> {
+    let flags = &app_state.feature_flags;
+    let responses = flags
+        .names()
+        .iter()
+        .map(|name| flag_response(flags, name))
+        .collect();
+
+    Json(ApiResponse::success(responses))
+}
+
+/// Turn a feature flag on or off at runtime
+///
+/// Flags pinned by an environment variable keep their env value until it is removed.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/feature-flags/{name}",
+    params(
+        ("name" = String, Path, description = "Flag name, e.g. `strict_account_match` or `package.com.example`"),
+    ),
+    request_body = SetFeatureFlagRequest,
+    responses(
+        (status = 200, description = "Flag updated", body = ApiResponse<FeatureFlagResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn set_feature_flag(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<SetFeatureFlagRequest>,
+) -> Result<Json<ApiResponse<FeatureFlagResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+    let flags = &app_state.feature_flags;
+
+    flags.set(
+        &mut conn,
+        &name,
+        payload.enabled,
+        app_state.clock.now_naive(),
+    )?;
+    println!("Feature flag {} set to {}", name, payload.enabled);
+
+    Ok(Json(ApiResponse::success(flag_response(flags, &name))))
+}
+
+/// Recent request and Google response bodies with tokens and personal data redacted
+///
+/// Empty unless the `debug_request_log` flag is on. Newest entries come first.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/debug/requests",
+    responses(
+        (status = 200, description = "Captured bodies, newest first", body = ApiResponse<Vec<DebugLogEntry>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject")
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_debug_log(
+    State(app_state): State<AppState>,
+) -> Json<ApiResponse<Vec<DebugLogEntry>>> {
+    Json(ApiResponse::success(app_state.debug_log.entries()))
+}
+
+/// Google's stored subscriptionsv2 responses for a purchase token, oldest first
+///
+/// Kept for `SUBSCRIPTION_SNAPSHOT_RETENTION_DAYS` so verification changes can be replayed
+/// against real responses.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/subscriptions/{token}/snapshots",
+    params(
+        ("token" = String, Path, description = "Purchase token"),
+    ),
+    responses(
+        (status = 200, description = "Stored responses, oldest first", body = ApiResponse<Vec<SubscriptionSnapshotResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_subscription_snapshots(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<ApiResponse<Vec<SubscriptionSnapshotResponse>>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let snapshots = snapshots_for_token(&mut conn, &token)?
+        .iter()
+        .map(|snapshot| {
+            Ok(SubscriptionSnapshotResponse {
+                fetched_at: snapshot.fetched_at.and_utc().to_rfc3339(),
+                body: snapshot_body(snapshot)?,
+            })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    Ok(Json(ApiResponse::success(snapshots)))
+}
+
+/// Every line item Google last reported for a purchase token, add-ons included
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/subscriptions/{token}/line-items",
+    params(
+        ("token" = String, Path, description = "Purchase token"),
+    ),
+    responses(
+        (status = 200, description = "Stored line items, by product", body = ApiResponse<Vec<SubscriptionLineItemResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_subscription_line_items(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<ApiResponse<Vec<SubscriptionLineItemResponse>>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let line_items = line_items_for_token(&mut conn, &token)?
+        .into_iter()
+        .map(|item| SubscriptionLineItemResponse {
+            plan: plan_for_product(&item.product_id),
+            product_id: item.product_id,
+            base_plan_id: item.base_plan_id,
+            offer_id: item.offer_id,
+            expiry_at: item.expiry_at.map(|time| time.and_utc().to_rfc3339()),
+            auto_renewing: item.auto_renewing,
+            expired: item.expired,
+            updated_at: item.updated_at.and_utc().to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(line_items)))
+}
+
+/// Scheduled background jobs with their schedule and last run, by name
+///
+/// Jobs show up once a replica has started with them registered. A job is `running` while a
+/// replica holds its lease.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    responses(
+        (status = 200, description = "Scheduled jobs", body = ApiResponse<Vec<ScheduledJobResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_scheduled_jobs(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<ScheduledJobResponse>>>, AppError> {
+    use crate::schema::scheduled_jobs::dsl::*;
+
+    let mut conn = app_state.get_db_connection()?;
+    let now = app_state.clock.now_naive();
+    let jobs: Vec<ScheduledJob> = scheduled_jobs.order(name.asc()).load(&mut conn)?;
+
+    let rfc3339 =
+        |time: Option<chrono::NaiveDateTime>| time.map(|time| time.and_utc().to_rfc3339());
+    let jobs = jobs
+        .into_iter()
+        .map(|job| {
+            // A lease that ran out belongs to a replica that died mid-run
+            let running = job.locked_until.is_some_and(|until| until > now);
+            ScheduledJobResponse {
+                name: job.name,
+                schedule: job.schedule,
+                running,
+                locked_by: job.locked_by.filter(|_| running),
+                last_started_at: rfc3339(job.last_started_at),
+                last_finished_at: rfc3339(job.last_finished_at),
+                last_outcome: job.last_outcome,
+                last_error: job.last_error,
+                last_duration_ms: job.last_duration_ms,
+            }
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(jobs)))
+}
+
+/// Which replica is the scheduler leader, as seen by the one answering
+///
+/// `leader` is absent while no replica holds the lease, e.g. right after the leader died or
+/// while the cache is unreachable. Without `REDIS_URL` the election isn't `cluster_wide` and
+/// every replica reports itself.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/cluster",
+    responses(
+        (status = 200, description = "Scheduler leader", body = ApiResponse<ClusterResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_cluster(State(app_state): State<AppState>) -> Json<ApiResponse<ClusterResponse>> {
+    let election = &app_state.leader;
+    let leader = election.current_leader().await;
+    Json(ApiResponse::success(ClusterResponse {
+        instance: election.holder().to_string(),
+        is_leader: leader.as_deref() == Some(election.holder()),
+        leader,
+        cluster_wide: election.is_cluster_wide(),
+        leader_lease_secs: election.lease().as_secs(),
+    }))
+}
+
+/// Depth, oldest due item and throughput of each background queue
+///
+/// A queue is `stalled` once its oldest due item waited longer than `max_age_secs`. The same
+/// figures are exported on `/metrics` by the queue monitor job.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/queues",
+    responses(
+        (status = 200, description = "Queue figures", body = ApiResponse<Vec<QueueStatsResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_queue_stats(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<QueueStatsResponse>>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+    let stats = queue_stats(&mut conn, app_state.clock.now_naive())?;
+    Ok(Json(ApiResponse::success(stats)))
+}
+
+/// List purchase tokens, most recently changed first
+///
+/// Pages are `limit` tokens long (50 by default, at most 200); pass `next_cursor` back as
+/// `cursor` for the next one.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/tokens",
+    params(
+        ("user_id" = Option<String>, Query, description = "Only tokens of this user"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Most tokens to return, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of purchase tokens", body = ApiResponse<PaginatedResponse<TokenExportRecord>>),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_tokens(
+    State(app_state): State<AppState>,
+    Query(params): Query<AdminListQuery>,
+) -> Result<Json<ApiResponse<PaginatedResponse<TokenExportRecord>>>, AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let before = decode_cursor(params.cursor.as_deref(), "token")?;
+    let page_size = page_limit(params.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let mut total = purchase_tokens.count().into_boxed();
+    // One extra row tells whether another page follows
+    let mut query = purchase_tokens
+        .order((updated_at.desc(), id.desc()))
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(user) = params.user_id.as_deref().map(normalize_user_id) {
+        total = total.filter(user_id.eq(user.clone()));
+        query = query.filter(user_id.eq(user));
+    }
+    if let Some(before) = &before {
+        query = query.filter(before_cursor(updated_at, id, before));
+    }
+    let total: i64 = total.get_result(&mut conn)?;
+    let rows: Vec<PurchaseToken> = query.load(&mut conn)?;
+    let (rows, next_cursor) = into_page(rows, page_size, token_cursor);
+
+    Ok(Json(ApiResponse::success(PaginatedResponse {
+        items: rows.into_iter().map(token_record).collect(),
+        next_cursor,
+        total_estimate: Some(total),
+    })))
+}
+
+/// Stored expiry may trail Google by this much before it counts as drift
+pub const EXPIRY_DRIFT_TOLERANCE_SECS: i64 = 60;
+
+/// Status a stored token should have given Google's view, `None` when any status could be right
+fn expected_status(
+    subscription_response: &GooglePlaySubscriptionResponse,
+    expiry: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> Option<PurchaseTokenStatus> {
+    match subscription_response.subscription_state {
+        SubscriptionState::Pending => Some(PurchaseTokenStatus::Pending),
+        SubscriptionState::Active | SubscriptionState::InGracePeriod => {
+            Some(PurchaseTokenStatus::AccessGranted)
+        }
+        // Access stays until the paid period ends
+        SubscriptionState::Canceled => Some(match expiry {
+            Some(expiry) if expiry > now => PurchaseTokenStatus::AccessGranted,
+            _ => PurchaseTokenStatus::Expired,
+        }),
+        SubscriptionState::OnHold
+        | SubscriptionState::Expired
+        | SubscriptionState::PendingPurchaseCanceled => Some(PurchaseTokenStatus::Expired),
+        // Access follows the pause window rather than the status
+        SubscriptionState::Paused | SubscriptionState::Unspecified | SubscriptionState::Unknown => {
+            None
+        }
+    }
+}
+
+/// Where a stored token disagrees with what Google reports for it at `now`
+pub fn token_mismatches(
+    token: &PurchaseToken,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: NaiveDateTime,
+) -> Vec<TokenMismatch> {
+    let mut mismatches = Vec::new();
+    let expiry = access_expiry(subscription_response);
+
+    match expiry {
+        Some(expiry)
+            if (expiry - token.expiry_at).num_seconds().abs() <= EXPIRY_DRIFT_TOLERANCE_SECS => {}
+        _ => mismatches.push(TokenMismatch {
+            kind: TokenMismatchKind::ExpiryDrift,
+            stored: token.expiry_at.and_utc().to_rfc3339(),
+            google: expiry
+                .map(|expiry| expiry.and_utc().to_rfc3339())
+                .unwrap_or_else(|| "none".to_string()),
+        }),
+    }
+
+    if let Some(expected) = expected_status(subscription_response, expiry, now) {
+        if expected != token.status {
+            mismatches.push(TokenMismatch {
+                kind: TokenMismatchKind::StateDivergence,
+                stored: token.status.as_str().to_string(),
+                google: format!(
+                    "{} ({:?})",
+                    expected.as_str(),
+                    subscription_response.subscription_state
+                ),
+            });
+        }
+    }
+
+    let acknowledged =
+        subscription_response.acknowledgement_state == AcknowledgementState::Acknowledged;
+    if acknowledged != token.acknowledged_at.is_some() {
+        mismatches.push(TokenMismatch {
+            kind: TokenMismatchKind::AcknowledgmentMismatch,
+            stored: token
+                .acknowledged_at
+                .map(|time| format!("acknowledged at {}", time.and_utc().to_rfc3339()))
+                .unwrap_or_else(|| "not acknowledged".to_string()),
+            google: format!("{:?}", subscription_response.acknowledgement_state),
+        });
+    }
+
+    if subscription_response.auto_renewing() != token.auto_renewing {
+        mismatches.push(TokenMismatch {
+            kind: TokenMismatchKind::AutoRenewingMismatch,
+            stored: token.auto_renewing.to_string(),
+            google: subscription_response.auto_renewing().to_string(),
+        });
+    }
+
+    mismatches
+}
+
+/// Compare a stored purchase token with what Google reports for it right now
+///
+/// `id` is the token's `id` from `/admin/tokens` or the purchase token itself. The fetch
+/// skips the subscription cache, and the stored row is left as is; use
+/// `/admin/subscriptions/{token}/reverify` to bring it in line.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/tokens/{id}/diff",
+    params(
+        ("id" = String, Path, description = "Token id or purchase token"),
+    ),
+    responses(
+        (status = 200, description = "Stored token, Google's response and their mismatches", body = ApiResponse<TokenDiffResponse>),
+        (status = 400, description = "Unknown token or no package name recorded", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>),
+        (status = 502, description = "Google Play is unreachable or refused the fetch", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_token_diff(
+    State(app_state): State<AppState>,
+    Path(token_id): Path<String>,
+) -> Result<Json<ApiResponse<TokenDiffResponse>>, AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let token: PurchaseToken = purchase_tokens
+        .filter(id.eq(&token_id).or(purchase_token.eq(&token_id)))
+        .first(&mut app_state.get_db_connection()?)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown purchase token {}", token_id)))?;
+    let package = token.package_name.clone().ok_or_else(|| {
+        AppError::BadRequest("Purchase token has no package name to fetch with".to_string())
+    })?;
+
+    app_state
+        .google_play
+        .invalidate_subscription(&token.purchase_token)
+        .await;
+    let subscription_response = app_state
+        .google_play
+        .fetch_subscription(&package, &token.purchase_token)
+        .await?;
+
+    let mismatches = token_mismatches(&token, &subscription_response, app_state.clock.now_naive());
+    let mut google = serde_json::to_value(&subscription_response)
+        .map_err(|e| AppError::InternalError(e.to_string()))?;
+    redact_personal_data(&mut google);
+
+    Ok(Json(ApiResponse::success(TokenDiffResponse {
+        stored: token_record(token),
+        google,
+        mismatches,
+    })))
+}
+
+/// List revenue events, most recently recorded first
+///
+/// Pages like `/admin/tokens`.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/events",
+    params(
+        ("user_id" = Option<String>, Query, description = "Only events of this user"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Most events to return, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of revenue events", body = ApiResponse<PaginatedResponse<RevenueEventExportRecord>>),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_revenue_events(
+    State(app_state): State<AppState>,
+    Query(params): Query<AdminListQuery>,
+) -> Result<Json<ApiResponse<PaginatedResponse<RevenueEventExportRecord>>>, AppError> {
+    use crate::schema::revenue_events::dsl::*;
+
+    let before = decode_cursor(params.cursor.as_deref(), "event")?;
+    let page_size = page_limit(params.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let mut total = revenue_events.count().into_boxed();
+    // One extra row tells whether another page follows
+    let mut query = revenue_events
+        .order((recorded_at.desc(), id.desc()))
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(user) = params.user_id.as_deref().map(normalize_user_id) {
+        total = total.filter(user_id.eq(user.clone()));
+        query = query.filter(user_id.eq(user));
+    }
+    if let Some(before) = &before {
+        query = query.filter(before_cursor(recorded_at, id, before));
+    }
+    let total: i64 = total.get_result(&mut conn)?;
+    let rows: Vec<RevenueEvent> = query.load(&mut conn)?;
+    let (rows, next_cursor) = into_page(rows, page_size, event_cursor);
+
+    Ok(Json(ApiResponse::success(PaginatedResponse {
+        items: rows.into_iter().map(event_record).collect(),
+        next_cursor,
+        total_estimate: Some(total),
+    })))
+}
+
+/// Current plans of several users on the user info canister, in the order asked for
+///
+/// Plans are cached for `USER_PLAN_CACHE_TTL_SECS`, only users missing from the cache are
+/// looked up on the canister.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/users/plans",
+    request_body = UserPlansRequest,
+    responses(
+        (status = 200, description = "Plan of each user", body = ApiResponse<Vec<UserPlanResponse>>),
+        (status = 400, description = "Too many users", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn lookup_user_plans(
+    State(app_state): State<AppState>,
+    Json(payload): Json<UserPlansRequest>,
+) -> Result<Json<ApiResponse<Vec<UserPlanResponse>>>, AppError> {
+    if payload.user_ids.len() > MAX_PLAN_LOOKUP_USERS {
+        return Err(AppError::BadRequest(format!(
+            "At most {} users per lookup",
+            MAX_PLAN_LOOKUP_USERS
+        )));
+    }
+
+    let user_ids: Vec<String> = payload
+        .user_ids
+        .iter()
+        .map(|id| normalize_user_id(id))
+        .collect();
+    let plans = app_state.user_info.get_plans(&user_ids).await?;
+    let responses = user_ids
+        .into_iter()
+        .filter_map(|user_id| {
+            let plan = *plans.get(&user_id)?;
+            Some(UserPlanResponse { user_id, plan })
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(responses)))
+}
+
+/// Run SQLite's integrity check on the database
+///
+/// Reads every page, so it takes a while on a large database.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/db/integrity",
+    responses(
+        (status = 200, description = "Integrity check result", body = ApiResponse<DbIntegrityResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn check_db_integrity(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<DbIntegrityResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+    let problems = integrity_problems(&mut conn)?;
+    let size_bytes = database_size_bytes(&mut conn)?;
+    if !problems.is_empty() {
+        eprintln!("Database integrity check failed: {:?}", problems);
+    }
+
+    Ok(Json(ApiResponse::success(DbIntegrityResponse {
+        ok: problems.is_empty(),
+        problems,
+        size_bytes,
+    })))
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use axum::{extract::State, Json};
+use diesel::prelude::*;
+
+use crate::config::{env_number, ConfigError};
+use crate::error::{AppError, AppResult};
+use crate::events::{record_event, DomainEvent};
+use crate::metrics::CANCEL_INTENTS_TOTAL;
+use crate::model::{CancelIntent, PurchaseToken};
+use crate::types::{
+    ApiResponse, CancelIntentRequest, CancelIntentResponse, EmptyData, PurchaseTokenStatus,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+/// Longest comment stored with an intent, in characters
+pub const MAX_CANCEL_COMMENT_CHARS: usize = 1000;
+
+const RATE_LIMIT_WINDOW_SECS: i64 = 3600;
+
+/// Cancellation intents a user may report per hour, from `CANCEL_INTENT_HOURLY_LIMIT`
+pub fn limit_from_env() -> Result<u32, ConfigError> {
+    env_number("CANCEL_INTENT_HOURLY_LIMIT", 5)
+}
+
+/// Count a report of `user` against their hourly limit
+///
+/// Counted in the shared cache so the limit holds across replicas.
+async fn check_rate_limit(app_state: &AppState, user: &str) -> AppResult<()> {
+    let now = app_state.clock.now_naive().and_utc().timestamp();
+    let window = now.div_euclid(RATE_LIMIT_WINDOW_SECS);
+    let count = app_state
+        .cache
+        .increment(
+            &format!("cancel_intent:{}:{}", user, window),
+            Duration::from_secs(RATE_LIMIT_WINDOW_SECS as u64),
+        )
+        .await?;
+
+    if count > app_state.cancel_intent_limit as u64 {
+        return Err(AppError::RateLimited {
+            retry_after_secs: (RATE_LIMIT_WINDOW_SECS - now.rem_euclid(RATE_LIMIT_WINDOW_SECS))
+                as u64,
+        });
+    }
+    Ok(())
+}
+
+/// Whether the user still has renewing access a retention offer could keep
+fn offer_eligible(token: &PurchaseToken, now: chrono::NaiveDateTime) -> bool {
+    token.status == PurchaseTokenStatus::AccessGranted
+        && token.auto_renewing
+        && token.expiry_at > now
+}
+
+/// Record that the user is about to cancel their subscription in the Play Store
+///
+/// The first report for a subscription that still renews triggers a retention offer, later
+/// ones are only recorded.
+///
+/// Requires JWT authentication in Authorization header
+#[utoipa::path(
+    post,
+    path = "/google/cancel-intent",
+    request_body = CancelIntentRequest,
+    responses(
+        (status = 200, description = "Cancellation intent recorded", body = ApiResponse<CancelIntentResponse>),
+        (status = 400, description = "Unknown purchase token, token owned by another user or comment too long", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 429, description = "Too many intents reported by this user, retry after the Retry-After header", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Subscription Verification",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn record_cancel_intent(
+    State(app_state): State<AppState>,
+    Json(mut payload): Json<CancelIntentRequest>,
+) -> Result<Json<ApiResponse<CancelIntentResponse>>, AppError> {
+    use crate::schema::{cancel_intents, purchase_tokens};
+
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    if payload
+        .comment
+        .as_deref()
+        .is_some_and(|comment| comment.chars().count() > MAX_CANCEL_COMMENT_CHARS)
+    {
+        return Err(AppError::BadRequest(format!(
+            "Comment must be at most {} characters",
+            MAX_CANCEL_COMMENT_CHARS
+        )));
+    }
+    check_rate_limit(&app_state, &payload.user_id).await?;
+
+    let mut conn = app_state.get_db_connection()?;
+    let now = app_state.clock.now_naive();
+
+    let token: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&payload.purchase_token))
+        .first(&mut conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest("Unknown purchase token".to_string()))?;
+
+    if token.user_id != payload.user_id {
+        return Err(AppError::TokenAlreadyUsed);
+    }
+
+    // The intent and its offer event are stored together, so an offer is neither lost nor doubled
+    let (intent, offer_event) = conn.transaction::<_, AppError, _>(|conn| {
+        let mut intent = CancelIntent::new(
+            payload.user_id.clone(),
+            payload.purchase_token.clone(),
+            payload.reason,
+            payload.comment.clone(),
+            now,
+        );
+
+        let already_offered: i64 = cancel_intents::table
+            .filter(cancel_intents::purchase_token.eq(&payload.purchase_token))
+            .filter(cancel_intents::retention_offered.eq(true))
+            .count()
+            .get_result(conn)?;
+        let offer_event = if already_offered == 0 && offer_eligible(&token, now) {
+            intent.retention_offered = true;
+            let event = DomainEvent::RetentionOfferRequested {
+                user_id: payload.user_id.clone(),
+                purchase_token: payload.purchase_token.clone(),
+                reason: payload.reason,
+                expires_at: token.expiry_at.and_utc().to_rfc3339(),
+            };
+            Some(record_event(conn, &event, now)?)
+        } else {
+            None
+        };
+
+        diesel::insert_into(cancel_intents::table)
+            .values(&intent)
+            .execute(conn)?;
+
+        Ok((intent, offer_event))
+    })?;
+
+    if let Some(event_id) = offer_event {
+        app_state.events.dispatch(event_id);
+    }
+    app_state.metrics.inc_counter(
+        CANCEL_INTENTS_TOTAL,
+        &[("reason", payload.reason.as_str())],
+        1,
+    );
+
+    Ok(Json(ApiResponse::success(CancelIntentResponse {
+        id: intent.id,
+        retention_offer: intent.retention_offered,
+        created_at: intent.created_at.and_utc().to_rfc3339(),
+    })))
+}
@@ -0,0 +1,99 @@
+use axum::extract::State;
+use axum::Json;
+use diesel::prelude::*;
+
+use crate::error::AppError;
+use crate::jobs::catalog_sync::{missing_plan_products, sync_product_catalog};
+use crate::model::Product;
+use crate::types::{
+    ApiResponse, CatalogProductResponse, CatalogResponse, CatalogSyncRequest, EmptyData,
+};
+use crate::AppState;
+
+fn catalog_response(products: &[Product]) -> CatalogResponse {
+    CatalogResponse {
+        products: products
+            .iter()
+            .map(|product| CatalogProductResponse {
+                package_name: product.package_name.clone(),
+                product_id: product.product_id.clone(),
+                base_plan_id: product.base_plan_id.clone(),
+                state: product.state.clone(),
+                billing_period: product.billing_period.clone(),
+                archived: product.archived,
+                synced_at: product.synced_at.and_utc().to_rfc3339(),
+            })
+            .collect(),
+        missing_plan_products: missing_plan_products(products),
+    }
+}
+
+/// Subscription products and base plans last synced from the Play Console
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/catalog",
+    responses(
+        (status = 200, description = "Stored catalog", body = ApiResponse<CatalogResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_catalog(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<CatalogResponse>>, AppError> {
+    use crate::schema::products;
+
+    let mut conn = app_state.get_db_connection()?;
+    let stored: Vec<Product> = products::table
+        .order((
+            products::package_name.asc(),
+            products::product_id.asc(),
+            products::base_plan_id.asc(),
+        ))
+        .load(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(catalog_response(&stored))))
+}
+
+/// Pull the subscription catalog of a package from Google Play now
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/catalog/sync",
+    request_body = CatalogSyncRequest,
+    responses(
+        (status = 200, description = "Catalog synced", body = ApiResponse<CatalogResponse>),
+        (status = 400, description = "Google Play rejected the request", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn sync_catalog(
+    State(app_state): State<AppState>,
+    Json(payload): Json<CatalogSyncRequest>,
+) -> Result<Json<ApiResponse<CatalogResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let products = sync_product_catalog(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &payload.package_name,
+        app_state.clock.as_ref(),
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(catalog_response(&products))))
+}
@@ -1,12 +1,12 @@
 use crate::error::{AppError, AppResult};
+use crate::feature_flags::package_flag;
 use crate::model::BotChatAccess;
-use crate::routes::goole_play_billing_helpers::{
-    consume_google_play_product, fetch_google_play_product_details,
-};
+use crate::profiles::resolve_account_id;
 use crate::types::{
     google_play_consumption_state, google_play_product_purchase_state, ApiResponse,
     BotChatAccessStatus, ChatAccessResponse, EmptyData, GrantChatAccessRequest,
 };
+use crate::user_id::canonical_user_id;
 use crate::AppState;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
@@ -36,6 +36,13 @@ pub async fn grant_chat_access(
     State(app_state): State<AppState>,
     Json(payload): Json<GrantChatAccessRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    if !app_state
+        .feature_flags
+        .is_enabled(&package_flag(&payload.package_name))
+    {
+        return Err(AppError::PackageDisabled(payload.package_name.clone()));
+    }
+
     let mut conn = app_state.get_db_connection()?;
 
     process_grant_chat_access(&mut conn, &app_state, &payload).await?;
@@ -61,12 +68,10 @@ async fn process_grant_chat_access(
     match existing {
         // ── No row yet: validate purchase, insert as ConsumePending, then consume ──
         None => {
-            let product_response = fetch_google_play_product_details(
-                &payload.package_name,
-                &payload.purchase_token,
-                app_state.google_auth.as_ref(),
-            )
-            .await?;
+            let product_response = app_state
+                .google_play
+                .fetch_product(&payload.package_name, &payload.purchase_token)
+                .await?;
 
             let line_item = product_response
                 .product_line_item
@@ -92,11 +97,15 @@ async fn process_grant_chat_access(
                 ));
             }
 
-            let user_id_str = product_response
-                .obfuscated_external_account_id
-                .ok_or(AppError::ExternalAccountIdentifiersMissing)?;
+            let user_id_str = resolve_account_id(
+                conn,
+                product_response.obfuscated_external_account_id.as_deref(),
+                product_response.obfuscated_external_profile_id.as_deref(),
+                &payload.product_id,
+                app_state.clock.now_naive(),
+            )?;
 
-            let access_expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::hours(24);
+            let access_expires_at = app_state.clock.now_naive() + chrono::Duration::hours(24);
 
             let new_grant = BotChatAccess::new(
                 payload.purchase_token.clone(),
@@ -109,15 +118,16 @@ async fn process_grant_chat_access(
                 .values(&new_grant)
                 .execute(conn)?;
 
-            consume_google_play_product(
-                &payload.package_name,
-                &payload.product_id,
-                &payload.purchase_token,
-                app_state.google_auth.as_ref(),
-            )
-            .await?;
+            app_state
+                .google_play
+                .consume_product(
+                    &payload.package_name,
+                    &payload.product_id,
+                    &payload.purchase_token,
+                )
+                .await?;
 
-            let now = chrono::Utc::now().naive_utc();
+            let now = app_state.clock.now_naive();
             diesel::update(bot_chat_access.filter(id.eq(&new_grant.id)))
                 .set((status.eq(BotChatAccessStatus::Active), updated_at.eq(now)))
                 .execute(conn)?;
@@ -132,12 +142,10 @@ async fn process_grant_chat_access(
         Some(grant) => match grant.status {
             // Consume was attempted before but not confirmed — resume from where we left off
             BotChatAccessStatus::ConsumePending => {
-                let product_response = fetch_google_play_product_details(
-                    &payload.package_name,
-                    &payload.purchase_token,
-                    app_state.google_auth.as_ref(),
-                )
-                .await?;
+                let product_response = app_state
+                    .google_play
+                    .fetch_product(&payload.package_name, &payload.purchase_token)
+                    .await?;
 
                 let line_item = product_response
                     .product_line_item
@@ -162,13 +170,14 @@ async fn process_grant_chat_access(
 
                     // Not yet consumed — retry
                     Some(google_play_consumption_state::NOT_CONSUMED) | None => {
-                        consume_google_play_product(
-                            &payload.package_name,
-                            &payload.product_id,
-                            &payload.purchase_token,
-                            app_state.google_auth.as_ref(),
-                        )
-                        .await?;
+                        app_state
+                            .google_play
+                            .consume_product(
+                                &payload.package_name,
+                                &payload.product_id,
+                                &payload.purchase_token,
+                            )
+                            .await?;
                     }
 
                     Some(state) => {
@@ -178,7 +187,7 @@ async fn process_grant_chat_access(
                     }
                 }
 
-                let now = chrono::Utc::now().naive_utc();
+                let now = app_state.clock.now_naive();
                 diesel::update(bot_chat_access.filter(id.eq(&grant.id)))
                     .set((status.eq(BotChatAccessStatus::Active), updated_at.eq(now)))
                     .execute(conn)?;
@@ -187,9 +196,7 @@ async fn process_grant_chat_access(
             }
 
             // Access is live and within the window — idempotent success
-            BotChatAccessStatus::Active if grant.expires_at > chrono::Utc::now().naive_utc() => {
-                Ok(())
-            }
+            BotChatAccessStatus::Active if grant.expires_at > app_state.clock.now_naive() => Ok(()),
 
             // Access window has passed — token is spent, new purchase required
             BotChatAccessStatus::Active => Err(AppError::TokenExpired),
@@ -222,12 +229,13 @@ pub async fn check_chat_access(
 ) -> Result<impl IntoResponse, AppError> {
     use crate::schema::bot_chat_access::dsl::*;
 
+    let user = canonical_user_id(&params.user_id)?;
     let mut conn = app_state.get_db_connection()?;
 
-    let now = chrono::Utc::now().naive_utc();
+    let now = app_state.clock.now_naive();
 
     let grant: Option<BotChatAccess> = bot_chat_access
-        .filter(user_id.eq(&params.user_id))
+        .filter(user_id.eq(&user))
         .filter(bot_id.eq(&params.bot_id))
         .filter(status.eq(BotChatAccessStatus::Active))
         .filter(expires_at.gt(now))
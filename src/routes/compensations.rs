@@ -0,0 +1,474 @@
+use std::collections::BTreeSet;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::auth::Claims;
+use crate::error::{AppError, AppResult};
+use crate::model::{CompensationBatch, CompensationGrant};
+use crate::routes::access::{grant_manual_access, operator};
+use crate::routes::credits::top_up_credits;
+use crate::types::{
+    ApiResponse, CompensationBatchResponse, CompensationBatchStatus, CompensationFilter,
+    CompensationGrantResponse, CompensationGrantStatus, CompensationKind,
+    CreateCompensationRequest, CreditReason, EmptyData, ManualGrantRequest, PurchaseTokenStatus,
+};
+use crate::user_id::normalize_user_id;
+use crate::AppState;
+
+/// Most users a single batch may compensate
+pub const MAX_COMPENSATION_USERS: usize = 100_000;
+
+/// Grant rows inserted per statement, well below SQLite's bound parameter limit
+const INSERT_CHUNK_SIZE: usize = 500;
+
+/// A running batch that made no progress for this long stopped midway and may be resumed
+pub const STALE_RUN_AFTER: chrono::Duration = chrono::Duration::minutes(5);
+
+/// Users holding a paid or manual purchase at some point between `active_from` and
+/// `active_until`
+fn affected_users(
+    conn: &mut SqliteConnection,
+    filter: &CompensationFilter,
+) -> AppResult<Vec<String>> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    if filter.active_until < filter.active_from {
+        return Err(AppError::BadRequest(
+            "active_until must not be before active_from".to_string(),
+        ));
+    }
+
+    let users = purchase_tokens
+        .filter(status.ne(PurchaseTokenStatus::Pending))
+        .filter(created_at.le(filter.active_until.naive_utc()))
+        .filter(expiry_at.ge(filter.active_from.naive_utc()))
+        .select(user_id)
+        .distinct()
+        .load(conn)?;
+    Ok(users)
+}
+
+/// Record a compensation batch with a `pending` result for every affected user
+///
+/// Users listed in the request and users matched by its filter are merged. Nothing is granted
+/// yet, `run_compensation_batch` does that.
+pub fn create_compensation_batch(
+    conn: &mut SqliteConnection,
+    operator: &str,
+    request: &CreateCompensationRequest,
+    now: NaiveDateTime,
+) -> AppResult<CompensationBatch> {
+    use crate::schema::{compensation_batches, compensation_grants};
+
+    if request.incident.trim().is_empty() || request.reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "An incident and a reason are required for compensations".to_string(),
+        ));
+    }
+    if request.amount == 0 {
+        return Err(AppError::BadRequest(
+            "amount must be greater than zero".to_string(),
+        ));
+    }
+
+    let mut users: BTreeSet<String> = request
+        .user_ids
+        .iter()
+        .map(|user| user.trim())
+        .filter(|user| !user.is_empty())
+        .map(normalize_user_id)
+        .collect();
+    if let Some(filter) = &request.filter {
+        users.extend(affected_users(conn, filter)?);
+    }
+
+    if users.is_empty() {
+        return Err(AppError::BadRequest("No users to compensate".to_string()));
+    }
+    if users.len() > MAX_COMPENSATION_USERS {
+        return Err(AppError::BadRequest(format!(
+            "A batch may compensate at most {} users, got {}",
+            MAX_COMPENSATION_USERS,
+            users.len()
+        )));
+    }
+
+    let batch = CompensationBatch::new(
+        request.incident.clone(),
+        request.reason.clone(),
+        request.kind,
+        request.amount as i32,
+        operator.to_string(),
+        now,
+    );
+    let grants: Vec<CompensationGrant> = users
+        .into_iter()
+        .map(|user| CompensationGrant {
+            batch_id: batch.id.clone(),
+            user_id: user,
+            status: CompensationGrantStatus::Pending,
+            error: None,
+            purchase_token: None,
+            updated_at: now,
+        })
+        .collect();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        diesel::insert_into(compensation_batches::table)
+            .values(&batch)
+            .execute(conn)?;
+        for chunk in grants.chunks(INSERT_CHUNK_SIZE) {
+            diesel::insert_into(compensation_grants::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        Ok(())
+    })?;
+
+    println!(
+        "Compensation batch {} for incident {} by {}: {} users",
+        batch.id,
+        batch.incident,
+        operator,
+        grants.len()
+    );
+
+    Ok(batch)
+}
+
+fn find_batch(conn: &mut SqliteConnection, batch_id: &str) -> AppResult<CompensationBatch> {
+    use crate::schema::compensation_batches;
+
+    compensation_batches::table
+        .find(batch_id)
+        .first(conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown compensation batch {}", batch_id)))
+}
+
+/// Claim a batch that stopped midway or finished with failures so it can run again
+///
+/// The claim is a single conditional update, so two operators resuming at once can't both
+/// run the batch.
+pub fn resume_compensation_batch(
+    conn: &mut SqliteConnection,
+    batch_id: &str,
+    now: NaiveDateTime,
+) -> AppResult<CompensationBatch> {
+    use crate::schema::compensation_batches::dsl::*;
+
+    let batch = find_batch(conn, batch_id)?;
+    let claimed = diesel::update(
+        compensation_batches.filter(id.eq(batch_id)).filter(
+            status.eq(CompensationBatchStatus::Failed).or(status
+                .eq(CompensationBatchStatus::Running)
+                .and(updated_at.le(now - STALE_RUN_AFTER))),
+        ),
+    )
+    .set((
+        status.eq(CompensationBatchStatus::Running),
+        updated_at.eq(now),
+    ))
+    .execute(conn)?;
+
+    if claimed == 0 {
+        return Err(AppError::BadRequest(match batch.status {
+            CompensationBatchStatus::Completed => {
+                format!("Compensation batch {} is already completed", batch_id)
+            }
+            _ => format!("Compensation batch {} is still running", batch_id),
+        }));
+    }
+
+    find_batch(conn, batch_id)
+}
+
+/// Grant one user their compensation, returning the manual purchase recording Pro days
+///
+/// Credits are claimed in `credit_topups` under a key for the batch and user, so resuming a
+/// batch never credits a user twice.
+async fn compensate_user(
+    state: &AppState,
+    batch: &CompensationBatch,
+    user: &str,
+) -> AppResult<Option<String>> {
+    match batch.kind {
+        CompensationKind::ProDays => {
+            let request = ManualGrantRequest {
+                user_id: user.to_string(),
+                reason: format!("{} (incident {})", batch.reason, batch.incident),
+                duration_days: batch.amount as u32,
+                record_purchase: true,
+            };
+            let response = grant_manual_access(
+                &mut state.get_db_connection()?,
+                state.user_info.as_ref(),
+                &batch.operator,
+                &request,
+                state.clock.now_naive(),
+            )
+            .await?;
+            Ok(response.purchase_token)
+        }
+        CompensationKind::Credits => {
+            let claim = format!("compensation:{}:{}", batch.id, user);
+            top_up_credits(
+                state,
+                &mut state.get_db_connection()?,
+                user,
+                &claim,
+                batch.amount as u32,
+                CreditReason::Compensation,
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Compensate every user of a batch not granted yet, recording each result as it goes
+///
+/// Users that fail are retried on the next resume. Each result updates the batch too, so a
+/// run that dies midway shows up as a `running` batch with no recent progress.
+pub async fn run_compensation_batch(
+    state: &AppState,
+    batch_id: &str,
+) -> AppResult<CompensationBatch> {
+    use crate::schema::{compensation_batches, compensation_grants};
+
+    let mut conn = state.get_db_connection()?;
+    let batch = find_batch(&mut conn, batch_id)?;
+
+    let users: Vec<String> = compensation_grants::table
+        .filter(compensation_grants::batch_id.eq(batch_id))
+        .filter(compensation_grants::status.ne(CompensationGrantStatus::Granted))
+        .order(compensation_grants::user_id.asc())
+        .select(compensation_grants::user_id)
+        .load(&mut conn)?;
+
+    let mut failed = 0;
+    for user in &users {
+        let (grant_status, grant_error, grant_token) =
+            match compensate_user(state, &batch, user).await {
+                Ok(token) => (CompensationGrantStatus::Granted, None, token),
+                Err(e) => {
+                    eprintln!(
+                        "Compensation batch {} failed for user {}: {}",
+                        batch_id, user, e
+                    );
+                    failed += 1;
+                    (CompensationGrantStatus::Failed, Some(e.to_string()), None)
+                }
+            };
+
+        let now = state.clock.now_naive();
+        conn.transaction::<_, AppError, _>(|conn| {
+            diesel::update(
+                compensation_grants::table
+                    .filter(compensation_grants::batch_id.eq(batch_id))
+                    .filter(compensation_grants::user_id.eq(user)),
+            )
+            .set((
+                compensation_grants::status.eq(grant_status),
+                compensation_grants::error.eq(grant_error),
+                compensation_grants::purchase_token.eq(grant_token),
+                compensation_grants::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+            diesel::update(compensation_batches::table.find(batch_id))
+                .set(compensation_batches::updated_at.eq(now))
+                .execute(conn)?;
+            Ok(())
+        })?;
+    }
+
+    let final_status = if failed == 0 {
+        CompensationBatchStatus::Completed
+    } else {
+        CompensationBatchStatus::Failed
+    };
+    diesel::update(compensation_batches::table.find(batch_id))
+        .set((
+            compensation_batches::status.eq(final_status),
+            compensation_batches::updated_at.eq(state.clock.now_naive()),
+        ))
+        .execute(&mut conn)?;
+
+    println!(
+        "Compensation batch {} finished: {} of {} users failed",
+        batch_id,
+        failed,
+        users.len()
+    );
+
+    find_batch(&mut conn, batch_id)
+}
+
+/// Summary of a batch, with every user's result when `with_results` is set
+pub fn batch_response(
+    conn: &mut SqliteConnection,
+    batch: &CompensationBatch,
+    with_results: bool,
+) -> AppResult<CompensationBatchResponse> {
+    use crate::schema::compensation_grants;
+
+    let grants: Vec<CompensationGrant> = compensation_grants::table
+        .filter(compensation_grants::batch_id.eq(&batch.id))
+        .order(compensation_grants::user_id.asc())
+        .load(conn)?;
+    let count = |wanted: CompensationGrantStatus| {
+        grants.iter().filter(|grant| grant.status == wanted).count()
+    };
+
+    Ok(CompensationBatchResponse {
+        id: batch.id.clone(),
+        incident: batch.incident.clone(),
+        reason: batch.reason.clone(),
+        kind: batch.kind,
+        amount: batch.amount as u32,
+        operator: batch.operator.clone(),
+        status: batch.status,
+        total_users: grants.len(),
+        granted: count(CompensationGrantStatus::Granted),
+        failed: count(CompensationGrantStatus::Failed),
+        pending: count(CompensationGrantStatus::Pending),
+        results: with_results.then(|| {
+            grants
+                .iter()
+                .map(|grant| CompensationGrantResponse {
+                    user_id: grant.user_id.clone(),
+                    status: grant.status,
+                    error: grant.error.clone(),
+                    purchase_token: grant.purchase_token.clone(),
+                    updated_at: grant.updated_at.and_utc().to_rfc3339(),
+                })
+                .collect()
+        }),
+        created_at: batch.created_at.and_utc().to_rfc3339(),
+        updated_at: batch.updated_at.and_utc().to_rfc3339(),
+    })
+}
+
+/// Run a batch in the background, it may take far longer than an HTTP request
+fn spawn_batch_run(state: AppState, batch_id: String) {
+    tokio::spawn(async move {
+        if let Err(e) = run_compensation_batch(&state, &batch_id).await {
+            eprintln!("Compensation batch {} stopped: {}", batch_id, e);
+        }
+    });
+}
+
+/// Compensate users affected by an incident with days of Pro or credits
+///
+/// The batch runs in the background; poll `GET /admin/compensations/{id}` for its progress.
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator.
+#[utoipa::path(
+    post,
+    path = "/admin/compensations",
+    request_body = CreateCompensationRequest,
+    responses(
+        (status = 202, description = "Batch recorded and started", body = ApiResponse<CompensationBatchResponse>),
+        (status = 400, description = "Missing incident or reason, invalid amount or filter, or no users", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_compensation(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateCompensationRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<CompensationBatchResponse>>), AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let batch = create_compensation_batch(
+        &mut conn,
+        &operator(&claims),
+        &payload,
+        app_state.clock.now_naive(),
+    )?;
+    let response = batch_response(&mut conn, &batch, false)?;
+    spawn_batch_run(app_state, batch.id);
+
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(response))))
+}
+
+/// Look up a compensation batch with every user's result
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/compensations/{id}",
+    params(
+        ("id" = String, Path, description = "Compensation batch id"),
+    ),
+    responses(
+        (status = 200, description = "Compensation batch", body = ApiResponse<CompensationBatchResponse>),
+        (status = 400, description = "Unknown compensation batch", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_compensation(
+    State(app_state): State<AppState>,
+    Path(batch_id): Path<String>,
+) -> Result<Json<ApiResponse<CompensationBatchResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let batch = find_batch(&mut conn, &batch_id)?;
+    let response = batch_response(&mut conn, &batch, true)?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Retry the users a compensation batch has not granted yet
+///
+/// Allowed once the batch finished with failures, or when a run stopped making progress,
+/// e.g. because the service restarted.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/compensations/{id}/resume",
+    params(
+        ("id" = String, Path, description = "Compensation batch id"),
+    ),
+    responses(
+        (status = 202, description = "Batch resumed", body = ApiResponse<CompensationBatchResponse>),
+        (status = 400, description = "Unknown, completed or still running batch", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn resume_compensation(
+    State(app_state): State<AppState>,
+    Path(batch_id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<CompensationBatchResponse>>), AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let batch = resume_compensation_batch(&mut conn, &batch_id, app_state.clock.now_naive())?;
+    let response = batch_response(&mut conn, &batch, false)?;
+    spawn_batch_run(app_state, batch.id);
+
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(response))))
+}
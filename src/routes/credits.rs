@@ -1,16 +1,300 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use diesel::dsl::sum;
+use diesel::prelude::*;
 use ic_agent::export::Principal;
-use yral_canisters_client::{ic::USER_INFO_SERVICE_ID, user_info_service::UserInfoService};
+use serde::Deserialize;
 
 use crate::{
-    error::AppError,
-    types::{ApiResponse, CreditRequest, EmptyData},
+    db::pagination::{before_cursor, decode_cursor, into_page, page_limit, Cursor},
+    error::{AppError, AppResult},
+    line_items::{access_expiry, granting_product},
+    metrics::CREDIT_CHANGES_TOTAL,
+    model::{CreditLedgerEntry, CreditTopup},
+    plans::{plan_for_product, CreditEvent, Upgrade},
+    routes::admin::{DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT},
+    service_auth::Caller,
+    types::{
+        ApiResponse, CreditDirection, CreditLedgerEntryResponse, CreditLedgerResponse,
+        CreditReason, CreditRequest, EmptyData, GooglePlaySubscriptionResponse, PaginatedResponse,
+        Plan,
+    },
+    user_id::normalize_user_id,
     AppState,
 };
 
+/// Caller recorded for credit changes billing makes on its own, e.g. renewal top-ups
+pub const BILLING_SERVICE: &str = "billing";
+
+/// Direction of a credit change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreditChange {
+    Deduct,
+    Increment,
+}
+
+impl CreditChange {
+    fn action(&self) -> &'static str {
+        match self {
+            CreditChange::Deduct => "deducted",
+            CreditChange::Increment => "incremented",
+        }
+    }
+
+    fn direction(&self) -> CreditDirection {
+        match self {
+            CreditChange::Deduct => CreditDirection::Deduct,
+            CreditChange::Increment => CreditDirection::Increment,
+        }
+    }
+
+    /// Reason recorded for a change made through the credits API
+    pub fn api_reason(&self) -> CreditReason {
+        match self {
+            CreditChange::Deduct => CreditReason::Spent,
+            CreditChange::Increment => CreditReason::Added,
+        }
+    }
+}
+
+/// Signed sum of a user's ledger entries, the credits billing accounts for
+pub fn ledger_balance(conn: &mut SqliteConnection, user: &str) -> AppResult<i64> {
+    use crate::schema::credit_ledger::dsl::*;
+
+    let totals: Vec<(CreditDirection, Option<i64>)> = credit_ledger
+        .filter(user_id.eq(user))
+        .group_by(direction)
+        .select((direction, sum(amount)))
+        .load(conn)?;
+    Ok(totals
+        .into_iter()
+        .map(|(entry_direction, total)| match entry_direction {
+            CreditDirection::Deduct => -total.unwrap_or(0),
+            CreditDirection::Increment => total.unwrap_or(0),
+        })
+        .sum())
+}
+
+/// Record a credit change already made on the IC
+///
+/// The change can't be undone at this point, so a failed write is logged rather than
+/// returned; the consistency check balances the ledger again later.
+fn record_ledger_entry(conn: &mut SqliteConnection, entry: &CreditLedgerEntry) {
+    use crate::schema::credit_ledger;
+
+    let result = diesel::insert_into(credit_ledger::table)
+        .values(entry)
+        .execute(conn);
+    if let Err(e) = result {
+        eprintln!(
+            "Failed to record {:?} of {} credits for {} in the ledger: {}",
+            entry.direction, entry.amount, entry.user_id, e
+        );
+    }
+}
+
+/// Log who changed a user's credits, JWT users and internal services alike
+fn audit_credit_change(state: &AppState, caller: &Caller, action: &str, payload: &CreditRequest) {
+    let caller = caller.label();
+    state.metrics.inc_counter(
+        CREDIT_CHANGES_TOTAL,
+        &[("caller", &caller), ("action", action)],
+        1,
+    );
+    println!(
+        "{} {} {} credits for {}",
+        caller, action, payload.amount, payload.user_principal
+    );
+}
+
+/// Change a user's credits on the IC and audit who did it, shared by the HTTP and gRPC APIs
+///
+/// Every change is also written to the credit ledger with `reason`, through `conn` so it
+/// commits with a transaction the caller has open there.
+pub async fn apply_credit_change(
+    state: &AppState,
+    conn: &mut SqliteConnection,
+    caller: &Caller,
+    change: CreditChange,
+    reason: CreditReason,
+    payload: &CreditRequest,
+) -> AppResult<()> {
+    // Parse user principal
+    let user_principal = Principal::from_text(&payload.user_principal)
+        .map_err(|e| AppError::BadRequest(format!("Invalid user principal: {}", e)))?;
+
+    match change {
+        CreditChange::Deduct => {
+            state
+                .user_info
+                .deduct_credits(user_principal, payload.amount)
+                .await?
+        }
+        CreditChange::Increment => {
+            state
+                .user_info
+                .increment_credits(user_principal, payload.amount)
+                .await?
+        }
+    }
+    audit_credit_change(state, caller, change.action(), payload);
+    record_ledger_entry(
+        conn,
+        &CreditLedgerEntry::new(
+            payload.user_principal.clone(),
+            change.direction(),
+            payload.amount,
+            reason,
+            caller.label(),
+            payload.correlation_id.clone(),
+            state.clock.now_naive(),
+        ),
+    );
+
+    Ok(())
+}
+
+/// Add the allotment of a renewed billing period to a user's credits, once per Google order
+///
+/// Pub/Sub redelivers notifications, so the order is claimed before crediting and released
+/// again if the IC call fails, leaving the redelivery to retry. Returns whether credits were
+/// added by this call.
+pub async fn top_up_renewal_credits(
+    state: &AppState,
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    order_id: &str,
+    amount: u32,
+) -> AppResult<bool> {
+    top_up_credits(
+        state,
+        conn,
+        user_id,
+        order_id,
+        amount,
+        CreditReason::RenewalTopup,
+    )
+    .await
+}
+
+/// Add the credits a purchase replacing another plan's subscription brings, once per order
+///
+/// See `upgrade_credits`. Returns whether credits were added by this call.
+pub async fn top_up_upgrade_credits(
+    state: &AppState,
+    conn: &mut SqliteConnection,
+    package_name: &str,
+    user_id: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> AppResult<bool> {
+    let Some(order_id) = subscription_response.latest_order_id.as_deref() else {
+        return Ok(false);
+    };
+    let amount = upgrade_credits(state, package_name, subscription_response).await?;
+
+    top_up_credits(
+        state,
+        conn,
+        user_id,
+        order_id,
+        amount,
+        CreditReason::UpgradeTopup,
+    )
+    .await
+}
+
+/// Credits a purchase replacing another plan's subscription brings
+///
+/// The replaced plan is looked up with Google through `linkedPurchaseToken`, products outside
+/// the catalog count as Free. Purchases replacing nothing bring none.
+pub async fn upgrade_credits(
+    state: &AppState,
+    package_name: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> AppResult<u32> {
+    let (Some(linked_token), Some(expiry)) = (
+        subscription_response.linked_purchase_token.as_deref(),
+        access_expiry(subscription_response),
+    ) else {
+        return Ok(0);
+    };
+    let Some(plan) = granting_product(subscription_response).and_then(plan_for_product) else {
+        return Ok(0);
+    };
+
+    let replaced = state
+        .google_play
+        .fetch_subscription(package_name, linked_token)
+        .await?;
+    let from = granting_product(&replaced)
+        .and_then(plan_for_product)
+        .unwrap_or(Plan::Free);
+    let upgrade = Upgrade::new(from, expiry, state.clock.now_naive());
+    Ok(state
+        .credit_allotments
+        .credits_for(plan, CreditEvent::Upgrade, Some(&upgrade)))
+}
+
+/// Add credits once per `order_id`, any unique claim such as a Google order or compensation
+///
+/// See `top_up_renewal_credits`; `reason` is what the ledger records.
+pub async fn top_up_credits(
+    state: &AppState,
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    order_id: &str,
+    amount: u32,
+    reason: CreditReason,
+) -> AppResult<bool> {
+    use crate::schema::credit_topups;
+
+    if amount == 0 {
+        return Ok(false);
+    }
+
+    let topup = CreditTopup {
+        order_id: order_id.to_string(),
+        user_id: user_id.to_string(),
+        amount: amount as i32,
+        created_at: state.clock.now_naive(),
+    };
+    let claimed = diesel::insert_or_ignore_into(credit_topups::table)
+        .values(&topup)
+        .execute(conn)?;
+    if claimed == 0 {
+        println!("Credits for order {} were already added", order_id);
+        return Ok(false);
+    }
+
+    let payload = CreditRequest {
+        user_principal: user_id.to_string(),
+        amount,
+        correlation_id: Some(order_id.to_string()),
+    };
+    let caller = Caller::Service(BILLING_SERVICE.to_string());
+    if let Err(e) = apply_credit_change(
+        state,
+        conn,
+        &caller,
+        CreditChange::Increment,
+        reason,
+        &payload,
+    )
+    .await
+    {
+        diesel::delete(credit_topups::table.filter(credit_topups::order_id.eq(order_id)))
+            .execute(conn)?;
+        return Err(e);
+    }
+
+    Ok(true)
+}
+
 /// Deduct credits from a user's account
 ///
-/// Requires JWT authentication in Authorization header
+/// Requires JWT authentication in Authorization header, or an HMAC-signed internal service
 #[utoipa::path(
     post,
     path = "/credits/deduct",
@@ -18,7 +302,8 @@ use crate::{
     responses(
         (status = 200, description = "Credits deducted successfully", body = ApiResponse<EmptyData>),
         (status = 400, description = "Invalid request", body = ApiResponse<EmptyData>),
-        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token or service signature"),
+        (status = 429, description = "Internal service exceeded its rate limit"),
         (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
     ),
     tag = "Credits",
@@ -28,44 +313,28 @@ use crate::{
 )]
 pub async fn deduct_credits(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Json(payload): Json<CreditRequest>,
 ) -> Result<Json<ApiResponse<()>>, AppError> {
-    // Get IC agent
-    let admin_ic_agent = state
-        .admin_ic_agent
-        .as_ref()
-        .ok_or(AppError::AdminIcAgentMissing)?;
-
-    // Parse user principal
-    let user_principal = Principal::from_text(&payload.user_principal)
-        .map_err(|e| AppError::BadRequest(format!("Invalid user principal: {}", e)))?;
+    apply_credit_change(
+        &state,
+        &mut state.get_db_connection()?,
+        &caller,
+        CreditChange::Deduct,
+        CreditChange::Deduct.api_reason(),
+        &payload,
+    )
+    .await?;
 
-    // Create user info service client
-    let user_info_client = UserInfoService(USER_INFO_SERVICE_ID, admin_ic_agent);
-
-    // Call canister to deduct credits
-    let result = user_info_client
-        .remove_pro_plan_free_video_credits(user_principal, payload.amount)
-        .await
-        .map_err(|e| AppError::NetworkError(format!("Failed to deduct credits: {}", e)))?;
-
-    // Check canister result
-    match result {
-        yral_canisters_client::user_info_service::Result_::Ok => {
-            Ok(Json(ApiResponse::ok_with_msg(format!(
-                "Successfully deducted {} credits from user",
-                payload.amount
-            ))))
-        }
-        yral_canisters_client::user_info_service::Result_::Err(e) => Err(AppError::BadRequest(
-            format!("Canister returned error: {}", e),
-        )),
-    }
+    Ok(Json(ApiResponse::ok_with_msg(format!(
+        "Successfully deducted {} credits from user",
+        payload.amount
+    ))))
 }
 
 /// Increment credits to a user's account
 ///
-/// Requires JWT authentication in Authorization header
+/// Requires JWT authentication in Authorization header, or an HMAC-signed internal service
 #[utoipa::path(
     post,
     path = "/credits/increment",
@@ -73,7 +342,8 @@ pub async fn deduct_credits(
     responses(
         (status = 200, description = "Credits incremented successfully", body = ApiResponse<EmptyData>),
         (status = 400, description = "Invalid request", body = ApiResponse<EmptyData>),
-        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token or service signature"),
+        (status = 429, description = "Internal service exceeded its rate limit"),
         (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
     ),
     tag = "Credits",
@@ -83,37 +353,108 @@ pub async fn deduct_credits(
 )]
 pub async fn increment_credits(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     Json(payload): Json<CreditRequest>,
 ) -> Result<Json<ApiResponse<()>>, AppError> {
-    // Get IC agent
-    let admin_ic_agent = state
-        .admin_ic_agent
-        .as_ref()
-        .ok_or(AppError::AdminIcAgentMissing)?;
+    apply_credit_change(
+        &state,
+        &mut state.get_db_connection()?,
+        &caller,
+        CreditChange::Increment,
+        CreditChange::Increment.api_reason(),
+        &payload,
+    )
+    .await?;
 
-    // Parse user principal
-    let user_principal = Principal::from_text(&payload.user_principal)
-        .map_err(|e| AppError::BadRequest(format!("Invalid user principal: {}", e)))?;
+    Ok(Json(ApiResponse::ok_with_msg(format!(
+        "Successfully added {} credits to user",
+        payload.amount
+    ))))
+}
 
-    // Create user info service client
-    let user_info_client = UserInfoService(USER_INFO_SERVICE_ID, admin_ic_agent);
-
-    // Call canister to add credits
-    let result = user_info_client
-        .add_pro_plan_free_video_credits(user_principal, payload.amount)
-        .await
-        .map_err(|e| AppError::NetworkError(format!("Failed to increment credits: {}", e)))?;
-
-    // Check canister result
-    match result {
-        yral_canisters_client::user_info_service::Result_::Ok => {
-            Ok(Json(ApiResponse::ok_with_msg(format!(
-                "Successfully added {} credits to user",
-                payload.amount
-            ))))
-        }
-        yral_canisters_client::user_info_service::Result_::Err(e) => Err(AppError::BadRequest(
-            format!("Canister returned error: {}", e),
-        )),
+#[derive(Deserialize)]
+pub struct CreditLedgerQuery {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn ledger_entry_response(entry: CreditLedgerEntry) -> CreditLedgerEntryResponse {
+    CreditLedgerEntryResponse {
+        id: entry.id,
+        amount: entry.amount,
+        direction: entry.direction,
+        reason: entry.reason,
+        actor: entry.actor,
+        correlation_id: entry.correlation_id,
+        created_at: entry.created_at.and_utc().to_rfc3339(),
+    }
+}
+
+/// List the changes to a user's credits, newest first, with the balance they add up to
+///
+/// Pages are `limit` entries long (50 by default, at most 200); pass `next_cursor` back as
+/// `cursor` for the next one.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user_id}/credit-ledger",
+    params(
+        ("user_id" = String, Path, description = "User principal"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Most entries to return, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "Ledger balance and a page of entries", body = ApiResponse<CreditLedgerResponse>),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Credits",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_credit_ledger(
+    State(state): State<AppState>,
+    Path(user): Path<String>,
+    Query(query): Query<CreditLedgerQuery>,
+) -> Result<Json<ApiResponse<CreditLedgerResponse>>, AppError> {
+    use crate::schema::credit_ledger::dsl::*;
+
+    let user = normalize_user_id(&user);
+    let limit = page_limit(query.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+    let cursor = decode_cursor(query.cursor.as_deref(), "credit ledger")?;
+    let mut conn = state.get_db_connection()?;
+
+    let mut entries = credit_ledger
+        .filter(user_id.eq(&user))
+        .order((created_at.desc(), id.desc()))
+        .limit(limit + 1)
+        .into_boxed();
+    if let Some(cursor) = &cursor {
+        entries = entries.filter(before_cursor(created_at, id, cursor));
     }
+    let entries: Vec<CreditLedgerEntry> = entries.load(&mut conn)?;
+    let (entries, next_cursor) = into_page(entries, limit, |entry| Cursor {
+        at: entry.created_at,
+        id: entry.id.clone(),
+    });
+    let total_estimate = credit_ledger
+        .filter(user_id.eq(&user))
+        .count()
+        .get_result(&mut conn)?;
+    let balance = ledger_balance(&mut conn, &user)?;
+
+    Ok(Json(ApiResponse::success(CreditLedgerResponse {
+        user_id: user,
+        balance,
+        entries: PaginatedResponse {
+            items: entries.into_iter().map(ledger_entry_response).collect(),
+            next_cursor,
+            total_estimate: Some(total_estimate),
+        },
+    })))
 }
@@ -0,0 +1,192 @@
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::auth::Claims;
+use crate::entitlement_token::JwkSet;
+use crate::error::{AppError, AppResult};
+use crate::model::{BotChatAccess, Entitlement};
+use crate::plans::{plan_definition, CreditAllotments};
+use crate::routes::user_tokens::ensure_owner;
+use crate::subscriptions::active_subscription;
+use crate::types::{
+    ApiResponse, BotChatAccessStatus, BotChatEntitlement, EmptyData, EntitlementResponse,
+    EntitlementTokenRequest, EntitlementTokenResponse, Plan, SourceStore,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+/// Get the normalized entitlement document for a user
+///
+/// Served from the entitlement read model, kept current with subscriptions of every source,
+/// and the plan catalog, so callers don't need to query the IC canister. A `free` plan with an
+/// earlier `pro` response means access was downgraded. A paused Google Play subscription
+/// reports its pause window.
+///
+/// Requires JWT authentication in Authorization header
+#[utoipa::path(
+    get,
+    path = "/entitlements/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "User ID to get entitlements for"),
+    ),
+    responses(
+        (status = 200, description = "Entitlement document", body = ApiResponse<EntitlementResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Entitlements",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_entitlements(
+    State(app_state): State<AppState>,
+    Path(user_id_param): Path<String>,
+) -> Result<Json<ApiResponse<EntitlementResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let entitlements = load_entitlements(
+        &mut conn,
+        canonical_user_id(&user_id_param)?,
+        &app_state.credit_allotments,
+        app_state.clock.now_naive(),
+    )?;
+
+    Ok(Json(ApiResponse::success(entitlements)))
+}
+
+/// Issue a short-lived signed entitlement token for a user
+///
+/// Other services check the token offline with the keys at `/.well-known/jwks.json` instead
+/// of calling billing or the IC on every request. It states the plan as stored now, so fetch
+/// it after a successful verify, and expires with the plan at the latest.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/entitlements/token",
+    request_body = EntitlementTokenRequest,
+    responses(
+        (status = 200, description = "Signed entitlement token", body = ApiResponse<EntitlementTokenResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 503, description = "No signing key is configured", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Entitlements",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn issue_entitlement_token(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<EntitlementTokenRequest>,
+) -> Result<Json<ApiResponse<EntitlementTokenResponse>>, AppError> {
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    ensure_owner(&claims, &payload.user_id)?;
+    let signer = app_state
+        .entitlement_signer
+        .as_ref()
+        .ok_or(AppError::EntitlementTokensDisabled)?;
+    let mut conn = app_state.get_db_connection()?;
+    let now = app_state.clock.now_naive();
+
+    let subscription = active_subscription(&mut conn, &payload.user_id, now)?;
+    let plan = if subscription.is_some() {
+        Plan::Pro
+    } else {
+        Plan::Free
+    };
+    let (token, token_claims) = signer.sign(
+        &payload.user_id,
+        plan,
+        subscription.map(|sub| sub.expires_at),
+        now,
+    )?;
+
+    Ok(Json(ApiResponse::success(EntitlementTokenResponse {
+        token,
+        plan,
+        expires_at: chrono::DateTime::from_timestamp(token_claims.exp, 0)
+            .unwrap_or_default()
+            .to_rfc3339(),
+    })))
+}
+
+/// Public keys entitlement tokens are signed with, empty when none is configured
+pub async fn get_jwks(State(app_state): State<AppState>) -> Json<JwkSet> {
+    Json(
+        app_state
+            .entitlement_signer
+            .as_ref()
+            .map(|signer| signer.jwks())
+            .unwrap_or(JwkSet { keys: vec![] }),
+    )
+}
+
+/// Entitlement document of a user at `now`, shared by the HTTP and gRPC APIs
+///
+/// Reads the user's row of the `entitlements` read model by primary key instead of joining
+/// their tokens and subscriptions. Users without a row never subscribed and get the free plan.
+pub fn load_entitlements(
+    conn: &mut SqliteConnection,
+    user_id_param: String,
+    credit_allotments: &CreditAllotments,
+    now: NaiveDateTime,
+) -> AppResult<EntitlementResponse> {
+    use crate::schema::{bot_chat_access, entitlements};
+
+    let entitlement: Option<Entitlement> = entitlements::table
+        .find(&user_id_param)
+        .first(conn)
+        .optional()?;
+    let plan = entitlement
+        .as_ref()
+        .map_or(Plan::Free, |entitlement| entitlement.plan_at(now));
+    let subscription = entitlement.as_ref().filter(|_| plan == Plan::Pro);
+
+    // Shown while a pause is upcoming or in effect, access is back once it ends
+    let (paused_from, resumes_at) = entitlement
+        .as_ref()
+        .filter(|entitlement| entitlement.resumes_at.is_some_and(|resumes| resumes > now))
+        .map(|entitlement| (entitlement.paused_from, entitlement.resumes_at))
+        .unwrap_or_default();
+
+    let chat_grants: Vec<BotChatAccess> = bot_chat_access::table
+        .filter(bot_chat_access::user_id.eq(&user_id_param))
+        .filter(bot_chat_access::status.eq(BotChatAccessStatus::Active))
+        .filter(bot_chat_access::expires_at.gt(now))
+        .order(bot_chat_access::expires_at.desc())
+        .load(conn)?;
+
+    let definition = plan_definition(plan);
+
+    Ok(EntitlementResponse {
+        user_id: user_id_param,
+        plan,
+        features: definition.features.iter().map(|f| f.to_string()).collect(),
+        credit_allotment: credit_allotments.for_plan(plan),
+        valid_from: subscription
+            .and_then(|sub| sub.valid_from)
+            .map(|time| time.and_utc().to_rfc3339()),
+        valid_until: subscription
+            .and_then(|sub| sub.valid_until)
+            .map(|time| time.and_utc().to_rfc3339()),
+        auto_renewing: subscription.and_then(|sub| sub.auto_renewing),
+        source_store: subscription
+            .and_then(|sub| sub.source)
+            .map(SourceStore::from),
+        paused_from: paused_from.map(|time| time.and_utc().to_rfc3339()),
+        resumes_at: resumes_at.map(|time| time.and_utc().to_rfc3339()),
+        bot_chat_access: chat_grants
+            .into_iter()
+            .map(|grant| BotChatEntitlement {
+                bot_id: grant.bot_id,
+                expires_at: grant.expires_at.and_utc().to_rfc3339(),
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,304 @@
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde::{Deserialize, Serialize};
+
+use crate::db::pagination::{after_cursor, decode_cursor, page_limit, Cursor};
+use crate::error::{AppError, AppResult};
+use crate::model::{PurchaseToken, RevenueEvent};
+use crate::types::{
+    ApiResponse, EmptyData, ExportLine, RevenueEventExportRecord, TokenExportRecord,
+};
+use crate::AppState;
+
+/// Rows read from the database per chunk of the response body
+pub const EXPORT_BATCH_SIZE: i64 = 500;
+/// Records per response unless `limit` says otherwise
+pub const DEFAULT_EXPORT_LIMIT: i64 = 10_000;
+/// Most records one response may carry, larger tables are paged with `cursor`
+pub const MAX_EXPORT_LIMIT: i64 = 100_000;
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Only records changed at or after this time (RFC 3339)
+    pub updated_since: Option<DateTime<Utc>>,
+    /// `cursor` of the last record received, the export continues after it
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Which records a batch may return
+#[derive(Debug, Clone)]
+pub struct ExportWindow {
+    pub since: Option<NaiveDateTime>,
+    pub after: Option<Cursor>,
+}
+
+impl ExportWindow {
+    fn from_query(query: &ExportQuery) -> AppResult<(Self, i64)> {
+        let after = decode_cursor(query.cursor.as_deref(), "export")?;
+        let limit = page_limit(query.limit, DEFAULT_EXPORT_LIMIT, MAX_EXPORT_LIMIT)?;
+
+        Ok((
+            Self {
+                since: query.updated_since.map(|since| since.naive_utc()),
+                after,
+            },
+            limit,
+        ))
+    }
+}
+
+/// NDJSON lines of the next batch, with the cursor of its last record
+type Batch = (Bytes, Option<Cursor>, usize);
+type LoadBatch = fn(&mut SqliteConnection, &ExportWindow, i64) -> AppResult<Batch>;
+
+fn to_ndjson<T: Serialize>(rows: impl IntoIterator<Item = (Cursor, T)>) -> AppResult<Batch> {
+    let mut body = Vec::new();
+    let mut last = None;
+    let mut count = 0;
+    for (cursor, record) in rows {
+        let line = ExportLine {
+            cursor: cursor.encode(),
+            record,
+        };
+        serde_json::to_writer(&mut body, &line)
+            .map_err(|e| AppError::InternalError(e.to_string()))?;
+        body.push(b'\n');
+        last = Some(cursor);
+        count += 1;
+    }
+    Ok((Bytes::from(body), last, count))
+}
+
+fn rfc3339(time: NaiveDateTime) -> String {
+    time.and_utc().to_rfc3339()
+}
+
+/// Position of a purchase token in listings ordered by its last change
+pub(crate) fn token_cursor(token: &PurchaseToken) -> Cursor {
+    Cursor {
+        at: token.updated_at,
+        id: token.id.clone(),
+    }
+}
+
+pub(crate) fn token_record(token: PurchaseToken) -> TokenExportRecord {
+    TokenExportRecord {
+        id: token.id,
+        user_id: token.user_id,
+        purchase_token: token.purchase_token,
+        status: token.status,
+        environment: token.environment,
+        package_name: token.package_name,
+        auto_renewing: token.auto_renewing,
+        created_at: rfc3339(token.created_at),
+        expiry_at: rfc3339(token.expiry_at),
+        acknowledged_at: token.acknowledged_at.map(rfc3339),
+        paused_from: token.paused_from.map(rfc3339),
+        resumes_at: token.resumes_at.map(rfc3339),
+        updated_at: rfc3339(token.updated_at),
+        replaced_by: token.replaced_by,
+    }
+}
+
+/// Position of a revenue event in listings ordered by when it was recorded
+pub(crate) fn event_cursor(event: &RevenueEvent) -> Cursor {
+    Cursor {
+        at: event.recorded_at,
+        id: event.id.clone(),
+    }
+}
+
+pub(crate) fn event_record(event: RevenueEvent) -> RevenueEventExportRecord {
+    RevenueEventExportRecord {
+        id: event.id,
+        order_id: event.order_id,
+        purchase_token: event.purchase_token,
+        user_id: event.user_id,
+        product_id: event.product_id,
+        base_plan_id: event.base_plan_id,
+        offer_id: event.offer_id,
+        region_code: event.region_code,
+        currency_code: event.currency_code,
+        price_micros: event.price_micros,
+        recorded_at: rfc3339(event.recorded_at),
+    }
+}
+
+/// Purchase tokens in `window` ordered by `updated_at`, at most `size` of them
+fn load_token_batch(
+    conn: &mut SqliteConnection,
+    window: &ExportWindow,
+    size: i64,
+) -> AppResult<Batch> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let mut query = purchase_tokens
+        .order((updated_at.asc(), id.asc()))
+        .limit(size)
+        .into_boxed();
+    if let Some(since) = window.since {
+        query = query.filter(updated_at.ge(since));
+    }
+    if let Some(after) = &window.after {
+        query = query.filter(after_cursor(updated_at, id, after));
+    }
+    let rows: Vec<PurchaseToken> = query.load(conn)?;
+
+    to_ndjson(
+        rows.into_iter()
+            .map(|token| (token_cursor(&token), token_record(token))),
+    )
+}
+
+/// Revenue events in `window` ordered by `recorded_at`, at most `size` of them
+///
+/// Events are never changed once recorded, so their record time doubles as change time.
+fn load_event_batch(
+    conn: &mut SqliteConnection,
+    window: &ExportWindow,
+    size: i64,
+) -> AppResult<Batch> {
+    use crate::schema::revenue_events::dsl::*;
+
+    let mut query = revenue_events
+        .order((recorded_at.asc(), id.asc()))
+        .limit(size)
+        .into_boxed();
+    if let Some(since) = window.since {
+        query = query.filter(recorded_at.ge(since));
+    }
+    if let Some(after) = &window.after {
+        query = query.filter(after_cursor(recorded_at, id, after));
+    }
+    let rows: Vec<RevenueEvent> = query.load(conn)?;
+
+    to_ndjson(
+        rows.into_iter()
+            .map(|event| (event_cursor(&event), event_record(event))),
+    )
+}
+
+/// Stream up to `limit` records as NDJSON, reading them in batches
+///
+/// The first batch is read before responding so a broken query still gets a proper error.
+/// A failure further in aborts the body; the client resumes from the last cursor it got.
+fn ndjson_response(
+    pool: Pool<ConnectionManager<SqliteConnection>>,
+    window: ExportWindow,
+    limit: i64,
+    load: LoadBatch,
+) -> AppResult<Response> {
+    let size = limit.min(EXPORT_BATCH_SIZE);
+    let first = load(
+        &mut pool.get().map_err(|_| AppError::DatabaseConnection)?,
+        &window,
+        size,
+    )?;
+
+    // Each step sends the batch in hand and reads the one after it
+    let stream = futures_util::stream::unfold(Some((first, size, window, limit)), move |state| {
+        let pool = pool.clone();
+        async move {
+            let ((body, last, count), requested, mut window, limit) = state?;
+            if count == 0 {
+                return None;
+            }
+
+            let remaining = limit - count as i64;
+            // A short batch means nothing is left to read
+            if remaining == 0 || (count as i64) < requested {
+                return Some((Ok(body), None));
+            }
+
+            let size = remaining.min(EXPORT_BATCH_SIZE);
+            window.after = last;
+            let next = pool
+                .get()
+                .map_err(|_| AppError::DatabaseConnection)
+                .and_then(|mut conn| load(&mut conn, &window, size));
+            match next {
+                Ok(batch) => Some((Ok(body), Some((batch, size, window, remaining)))),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// Stream purchase tokens as NDJSON for the data warehouse
+///
+/// Tokens come ordered by their last change. Each line carries a `cursor`; passing the last
+/// one back continues the export, and a response with fewer than `limit` lines is the end.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/export/tokens",
+    params(
+        ("updated_since" = Option<String>, Query, description = "Only tokens changed at or after this time (RFC 3339)"),
+        ("cursor" = Option<String>, Query, description = "`cursor` of the last line received"),
+        ("limit" = Option<i64>, Query, description = "Most lines to return, 10000 by default and at most 100000"),
+    ),
+    responses(
+        (status = 200, description = "One `{cursor, record}` object per line", body = TokenExportRecord, content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_tokens(
+    State(app_state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (window, limit) = ExportWindow::from_query(&params)?;
+    ndjson_response(app_state.db_connection, window, limit, load_token_batch)
+}
+
+/// Stream revenue events as NDJSON for the data warehouse
+///
+/// Events come ordered by when they were recorded and page like `/admin/export/tokens`.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/export/events",
+    params(
+        ("updated_since" = Option<String>, Query, description = "Only events recorded at or after this time (RFC 3339)"),
+        ("cursor" = Option<String>, Query, description = "`cursor` of the last line received"),
+        ("limit" = Option<i64>, Query, description = "Most lines to return, 10000 by default and at most 100000"),
+    ),
+    responses(
+        (status = 200, description = "One `{cursor, record}` object per line", body = RevenueEventExportRecord, content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_events(
+    State(app_state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (window, limit) = ExportWindow::from_query(&params)?;
+    ndjson_response(app_state.db_connection, window, limit, load_event_batch)
+}
@@ -0,0 +1,175 @@
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::Claims;
+use crate::db::pagination::{before_cursor, decode_cursor, into_page, page_limit, Cursor};
+use crate::error::{AppError, AppResult};
+use crate::model::{Order, SubscriptionEvent};
+use crate::plans::plan_for_product;
+use crate::routes::user_tokens::ensure_owner;
+use crate::types::{
+    ApiResponse, BillingHistoryEntry, BillingHistoryResponse, EmptyData,
+    GooglePlaySubscriptionResponse, SubscriptionEventKind,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+/// Entries per page unless `limit` says otherwise
+pub const DEFAULT_HISTORY_LIMIT: i64 = 20;
+/// Most entries one page may carry
+pub const MAX_HISTORY_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct BillingHistoryQuery {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Add a newly recorded order to its owner's history
+///
+/// The first order of a purchase token is its purchase, every later one a renewal.
+pub fn record_order_event(conn: &mut SqliteConnection, order: &Order) -> AppResult<()> {
+    use crate::schema::{orders, subscription_events};
+
+    let earlier: i64 = orders::table
+        .filter(orders::purchase_token.eq(&order.purchase_token))
+        .filter(orders::id.ne(&order.id))
+        .count()
+        .get_result(conn)?;
+    let kind = if earlier == 0 {
+        SubscriptionEventKind::Purchase
+    } else {
+        SubscriptionEventKind::Renewal
+    };
+
+    let event = SubscriptionEvent::new(
+        order.user_id.clone(),
+        order.purchase_token.clone(),
+        kind,
+        Some(order.order_id.clone()),
+        order.product_id.clone(),
+        order.recorded_at,
+    );
+    diesel::insert_into(subscription_events::table)
+        .values(&event)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Add a cancellation to the history of the subscription's owner
+///
+/// Cancellations of tokens we don't track are skipped, like their orders.
+pub fn record_cancellation(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    occurred_at: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::{purchase_tokens, subscription_events};
+
+    let line_item = subscription_response
+        .line_items
+        .first()
+        .ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+    let owner: Option<String> = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(purchase_token_param))
+        .select(purchase_tokens::user_id)
+        .first(conn)
+        .optional()?;
+    let Some(owner) = owner else {
+        return Ok(());
+    };
+
+    let event = SubscriptionEvent::new(
+        owner,
+        purchase_token_param.to_string(),
+        SubscriptionEventKind::Cancellation,
+        subscription_response.latest_order_id.clone(),
+        line_item.product_id.clone(),
+        occurred_at,
+    );
+    diesel::insert_into(subscription_events::table)
+        .values(&event)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Get a user's purchases, renewals and cancellations, newest first
+///
+/// Pages are `limit` entries long (20 by default, at most 100); pass `next_cursor` back as
+/// `cursor` for the next one. Purchase tokens are never included.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/billing/history/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "User principal, must be the caller's"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Most entries to return, 20 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "A page of billing history", body = ApiResponse<BillingHistoryResponse>),
+        (status = 400, description = "Invalid cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Billing History",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_billing_history(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id_param): Path<String>,
+    Query(params): Query<BillingHistoryQuery>,
+) -> Result<Json<ApiResponse<BillingHistoryResponse>>, AppError> {
+    use crate::schema::subscription_events::dsl::*;
+
+    let user_id_param = canonical_user_id(&user_id_param)?;
+    ensure_owner(&claims, &user_id_param)?;
+    let before = decode_cursor(params.cursor.as_deref(), "history")?;
+    let page_size = page_limit(params.limit, DEFAULT_HISTORY_LIMIT, MAX_HISTORY_LIMIT)?;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    // One extra row tells whether another page follows
+    let mut query = subscription_events
+        .filter(user_id.eq(&user_id_param))
+        .order((occurred_at.desc(), id.desc()))
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(before) = &before {
+        query = query.filter(before_cursor(occurred_at, id, before));
+    }
+    let rows: Vec<SubscriptionEvent> = query.load(&mut conn)?;
+    let (rows, next_cursor) = into_page(rows, page_size, |event| Cursor {
+        at: event.occurred_at,
+        id: event.id.clone(),
+    });
+
+    let events = rows
+        .into_iter()
+        .map(|event| BillingHistoryEntry {
+            kind: event.kind,
+            order_id: event.order_id,
+            plan: plan_for_product(&event.product_id),
+            product_id: event.product_id,
+            occurred_at: event.occurred_at.and_utc().to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(BillingHistoryResponse {
+        events,
+        next_cursor,
+    })))
+}
@@ -0,0 +1,104 @@
+use axum::extract::State;
+use axum::Json;
+use diesel::prelude::*;
+
+use crate::clock::Clock;
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::acknowledgments::AckStrategies;
+use crate::jobs::pending_purchases::reverify_purchase_token;
+use crate::model::PurchaseToken;
+use crate::types::{
+    ApiResponse, EmptyData, IntrospectRequest, IntrospectResponse, Plan, PurchaseTokenStatus,
+};
+use crate::AppState;
+
+/// What billing knows about a purchase token, refreshed from Google first on request
+pub async fn introspect_purchase_token(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    clock: &dyn Clock,
+    request: &IntrospectRequest,
+) -> AppResult<IntrospectResponse> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    if request.refresh {
+        reverify_purchase_token(
+            conn,
+            google_play,
+            ack_strategies,
+            user_info,
+            clock,
+            &request.purchase_token,
+        )
+        .await?;
+    }
+
+    let token: PurchaseToken = purchase_tokens
+        .filter(purchase_token.eq(&request.purchase_token))
+        .first(conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest("Unknown purchase token".to_string()))?;
+
+    let now = clock.now_naive();
+    let plan = if token.status == PurchaseTokenStatus::AccessGranted && token.expiry_at > now {
+        Plan::Pro
+    } else {
+        Plan::Free
+    };
+
+    Ok(IntrospectResponse {
+        user_id: token.user_id,
+        plan,
+        status: token.status,
+        expiry_at: token.expiry_at.and_utc().to_rfc3339(),
+        auto_renewing: token.auto_renewing,
+        environment: token.environment,
+        refreshed: request.refresh,
+    })
+}
+
+/// Look up the user, plan, status and expiry behind a purchase token
+///
+/// For backends handed a purchase token by the app that need billing to vouch for it. The
+/// stored row is returned as is unless `refresh` is set, which asks Google first and stores
+/// what it reports, like an operator reverify.
+///
+/// Requires an HMAC-signed request from an internal service
+#[utoipa::path(
+    post,
+    path = "/internal/introspect",
+    request_body = IntrospectRequest,
+    responses(
+        (status = 200, description = "Stored state of the purchase token", body = ApiResponse<IntrospectResponse>),
+        (status = 400, description = "Unknown purchase token", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Missing or invalid service signature"),
+        (status = 429, description = "Internal service exceeded its rate limit"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Internal",
+    security(
+        ("service_hmac" = [])
+    )
+)]
+pub async fn introspect(
+    State(app_state): State<AppState>,
+    Json(payload): Json<IntrospectRequest>,
+) -> Result<Json<ApiResponse<IntrospectResponse>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let response = introspect_purchase_token(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.user_info.as_ref(),
+        app_state.clock.as_ref(),
+        &payload,
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
@@ -0,0 +1,171 @@
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::Claims;
+use crate::error::AppError;
+use crate::model::Invoice;
+use crate::routes::orders::{csv_field, day_range};
+use crate::routes::user_tokens::ensure_owner;
+use crate::types::{ApiResponse, EmptyData, ExportFormat, InvoiceResponse};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct ExportInvoicesQuery {
+    /// First day to include (UTC)
+    pub from: NaiveDate,
+    /// Last day to include (UTC)
+    pub to: NaiveDate,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+fn to_response(invoice: Invoice) -> InvoiceResponse {
+    InvoiceResponse {
+        invoice_id: invoice.id,
+        order_id: invoice.order_id,
+        user_id: invoice.user_id,
+        product_id: invoice.product_id,
+        base_plan_id: invoice.base_plan_id,
+        offer_id: invoice.offer_id,
+        region_code: invoice.region_code,
+        currency_code: invoice.currency_code,
+        amount_micros: invoice.amount_micros,
+        issued_at: invoice.issued_at.and_utc().to_rfc3339(),
+    }
+}
+
+fn to_csv(invoices: &[InvoiceResponse]) -> String {
+    let mut csv = String::from(
+        "invoice_id,order_id,user_id,product_id,base_plan_id,offer_id,region_code,currency_code,amount_micros,issued_at\n",
+    );
+    for invoice in invoices {
+        let amount_micros = invoice
+            .amount_micros
+            .map(|amount| amount.to_string())
+            .unwrap_or_default();
+        let fields = [
+            invoice.invoice_id.as_str(),
+            invoice.order_id.as_str(),
+            invoice.user_id.as_str(),
+            invoice.product_id.as_str(),
+            invoice.base_plan_id.as_deref().unwrap_or(""),
+            invoice.offer_id.as_deref().unwrap_or(""),
+            invoice.region_code.as_deref().unwrap_or(""),
+            invoice.currency_code.as_deref().unwrap_or(""),
+            amount_micros.as_str(),
+            invoice.issued_at.as_str(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Get a user's invoices, one per purchase and renewal, newest first
+///
+/// Amounts are absent for orders Play reported no price for.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/billing/invoices/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "User principal, must be the caller's"),
+    ),
+    responses(
+        (status = 200, description = "The user's invoices", body = ApiResponse<Vec<InvoiceResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Billing History",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_invoices(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id_param): Path<String>,
+) -> Result<Json<ApiResponse<Vec<InvoiceResponse>>>, AppError> {
+    use crate::schema::invoices::dsl::*;
+
+    let user_id_param = canonical_user_id(&user_id_param)?;
+    ensure_owner(&claims, &user_id_param)?;
+    let mut conn = app_state.get_db_connection()?;
+    let records: Vec<Invoice> = invoices
+        .filter(user_id.eq(&user_id_param))
+        .order((issued_at.desc(), id.desc()))
+        .load(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(
+        records.into_iter().map(to_response).collect(),
+    )))
+}
+
+/// Export invoices issued in a date range for accounting
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/invoices/export",
+    params(
+        ("from" = String, Query, description = "First day to include, YYYY-MM-DD (UTC)"),
+        ("to" = String, Query, description = "Last day to include, YYYY-MM-DD (UTC)"),
+        ("format" = Option<ExportFormat>, Query, description = "`json` (default) or `csv`"),
+    ),
+    responses(
+        (status = 200, description = "Invoices issued in the date range", body = ApiResponse<Vec<InvoiceResponse>>),
+        (status = 400, description = "Invalid date range", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_invoices(
+    State(app_state): State<AppState>,
+    Query(params): Query<ExportInvoicesQuery>,
+) -> Result<Response, AppError> {
+    use crate::schema::invoices::dsl::*;
+
+    let (start, end) = day_range(params.from, params.to)?;
+
+    let mut conn = app_state.get_db_connection()?;
+    let records: Vec<Invoice> = invoices
+        .filter(issued_at.ge(start))
+        .filter(issued_at.lt(end))
+        .order((issued_at.asc(), id.asc()))
+        .load(&mut conn)?;
+    let records: Vec<InvoiceResponse> = records.into_iter().map(to_response).collect();
+
+    let response = match params.format {
+        ExportFormat::Json => Json(ApiResponse::success(records)).into_response(),
+        ExportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"invoices_{}_{}.csv\"",
+                        params.from, params.to
+                    ),
+                ),
+            ],
+            to_csv(&records),
+        )
+            .into_response(),
+    };
+
+    Ok(response)
+}
@@ -0,0 +1,83 @@
+use axum::extract::Query;
+use axum::Json;
+use serde::Deserialize;
+
+use crate::error::{AppError, AppResult};
+use crate::types::{ApiResponse, EmptyData, ManageUrlResponse};
+
+/// Play Store page listing every subscription of the signed-in Google account
+pub const PLAY_SUBSCRIPTIONS_URL: &str = "https://play.google.com/store/account/subscriptions";
+
+/// Play Store page where the user changes their payment methods
+pub const PLAY_PAYMENT_METHODS_URL: &str = "https://play.google.com/store/paymentmethods";
+
+#[derive(Deserialize)]
+pub struct ManageUrlQuery {
+    pub product_id: Option<String>,
+    pub package_name: Option<String>,
+}
+
+/// Package names and product ids only use these characters, so they go into URLs unescaped
+fn is_play_identifier(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Deep links into the Play Store's subscription management for `product_id` of `package_name`
+///
+/// Without a product the links open the list of all the user's subscriptions.
+pub fn manage_urls(
+    product_id: Option<&str>,
+    package_name: Option<&str>,
+) -> AppResult<ManageUrlResponse> {
+    let manage_url = match (product_id, package_name) {
+        (Some(product_id), Some(package_name)) => {
+            for (field, value) in [("product_id", product_id), ("package_name", package_name)] {
+                if !is_play_identifier(value) {
+                    return Err(AppError::BadRequest(format!("Invalid {}", field)));
+                }
+            }
+            format!(
+                "{}?sku={}&package={}",
+                PLAY_SUBSCRIPTIONS_URL, product_id, package_name
+            )
+        }
+        (None, None) => PLAY_SUBSCRIPTIONS_URL.to_string(),
+        _ => {
+            return Err(AppError::BadRequest(
+                "product_id and package_name must be given together".to_string(),
+            ))
+        }
+    };
+
+    Ok(ManageUrlResponse {
+        // Google sends users who need to fix a declined payment to the subscription's own page
+        update_payment_url: manage_url.clone(),
+        manage_url,
+        all_subscriptions_url: PLAY_SUBSCRIPTIONS_URL.to_string(),
+        payment_methods_url: PLAY_PAYMENT_METHODS_URL.to_string(),
+    })
+}
+
+/// Play Store links for managing a subscription, so the app doesn't hard-code their format
+#[utoipa::path(
+    get,
+    path = "/google/manage-url",
+    params(
+        ("product_id" = Option<String>, Query, description = "Subscription product, links to its own page when given with package_name"),
+        ("package_name" = Option<String>, Query, description = "Android package the product belongs to"),
+    ),
+    responses(
+        (status = 200, description = "Management links", body = ApiResponse<ManageUrlResponse>),
+        (status = 400, description = "Only one of product_id and package_name given, or not a valid identifier", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Subscription Verification"
+)]
+pub async fn get_manage_url(
+    Query(params): Query<ManageUrlQuery>,
+) -> Result<Json<ApiResponse<ManageUrlResponse>>, AppError> {
+    let urls = manage_urls(params.product_id.as_deref(), params.package_name.as_deref())?;
+    Ok(Json(ApiResponse::success(urls)))
+}
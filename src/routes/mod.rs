@@ -1,7 +1,27 @@
+pub mod access;
+pub mod admin;
+pub mod cancel_intent;
+pub mod catalog;
 pub mod chat_access;
-pub mod goole_play_billing_helpers;
+pub mod compensations;
+pub mod entitlements;
+pub mod export;
+pub mod history;
+pub mod introspect;
+pub mod invoices;
+pub mod manage;
+pub mod metrics;
+pub mod offers;
+pub mod orders;
+pub mod profiles;
 pub mod purchase;
 pub mod purchase_token_helpers;
+pub mod quarantine;
+pub mod refunds;
+pub mod risk;
 pub mod rtdn;
-pub mod utils;
+pub mod stats;
+pub mod teardown;
+pub mod transfer;
+pub mod user_tokens;
 pub mod credits;
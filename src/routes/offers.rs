@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashSet};
+
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::error::{AppError, AppResult};
+use crate::model::OfferRedemption;
+use crate::routes::orders::day_range;
+use crate::types::{
+    ApiResponse, EmptyData, GooglePlaySubscriptionResponse, OfferConversionResponse,
+};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct OfferConversionsQuery {
+    /// First day to include (UTC)
+    pub from: NaiveDate,
+    /// Last day to include (UTC)
+    pub to: NaiveDate,
+    /// Only offers carrying this tag, e.g. `winback`
+    pub tag: Option<String>,
+}
+
+/// Record which offers the line items of a granted subscription were bought through
+///
+/// Each offer is recorded once per purchase token, at its first grant. Line items bought at
+/// the base plan's regular price carry no offer and are skipped.
+pub fn record_offer_redemptions(
+    conn: &mut SqliteConnection,
+    purchase_token: &str,
+    user_id: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    redeemed_at: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::offer_redemptions;
+
+    for item in &subscription_response.line_items {
+        let Some(offer) = &item.offer_details else {
+            continue;
+        };
+        let Some(offer_id) = &offer.offer_id else {
+            continue;
+        };
+
+        let redemption = OfferRedemption {
+            purchase_token: purchase_token.to_string(),
+            offer_id: offer_id.clone(),
+            user_id: user_id.to_string(),
+            product_id: item.product_id.clone(),
+            base_plan_id: offer.base_plan_id.clone(),
+            offer_tags: offer.offer_tags.join(","),
+            redeemed_at,
+        };
+        diesel::insert_or_ignore_into(offer_redemptions::table)
+            .values(&redemption)
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Product, base plan and offer, offer ids are only unique within their base plan
+type OfferKey<'a> = (&'a str, Option<&'a str>, &'a str);
+
+fn tags(redemption: &OfferRedemption) -> Vec<String> {
+    redemption
+        .offer_tags
+        .split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Conversions per offer among `redemptions`, which must be ordered by redemption time
+fn summarize_conversions(redemptions: &[OfferRedemption]) -> Vec<OfferConversionResponse> {
+    let mut summaries: BTreeMap<OfferKey, (OfferConversionResponse, HashSet<&str>)> =
+        BTreeMap::new();
+
+    for redemption in redemptions {
+        let key = (
+            redemption.product_id.as_str(),
+            redemption.base_plan_id.as_deref(),
+            redemption.offer_id.as_str(),
+        );
+        let redeemed_at = redemption.redeemed_at.and_utc().to_rfc3339();
+        let (summary, users) = summaries.entry(key).or_insert_with(|| {
+            (
+                OfferConversionResponse {
+                    product_id: redemption.product_id.clone(),
+                    base_plan_id: redemption.base_plan_id.clone(),
+                    offer_id: redemption.offer_id.clone(),
+                    offer_tags: Vec::new(),
+                    conversions: 0,
+                    users: 0,
+                    first_redeemed_at: redeemed_at.clone(),
+                    last_redeemed_at: redeemed_at.clone(),
+                },
+                HashSet::new(),
+            )
+        });
+
+        summary.offer_tags = tags(redemption);
+        summary.conversions += 1;
+        summary.last_redeemed_at = redeemed_at;
+        users.insert(redemption.user_id.as_str());
+        summary.users = users.len() as u64;
+    }
+
+    summaries
+        .into_values()
+        .map(|(summary, _)| summary)
+        .collect()
+}
+
+/// Summarize purchases granted through each Play offer, e.g. to compare win-back offers
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/offers/conversions",
+    params(
+        ("from" = String, Query, description = "First day to include, YYYY-MM-DD (UTC)"),
+        ("to" = String, Query, description = "Last day to include, YYYY-MM-DD (UTC)"),
+        ("tag" = Option<String>, Query, description = "Only offers carrying this tag"),
+    ),
+    responses(
+        (status = 200, description = "Conversions per offer redeemed in the date range", body = ApiResponse<Vec<OfferConversionResponse>>),
+        (status = 400, description = "Invalid date range", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_offer_conversions(
+    State(app_state): State<AppState>,
+    Query(params): Query<OfferConversionsQuery>,
+) -> Result<Json<ApiResponse<Vec<OfferConversionResponse>>>, AppError> {
+    use crate::schema::offer_redemptions::dsl::*;
+
+    let (start, end) = day_range(params.from, params.to)?;
+
+    let mut conn = app_state.get_db_connection()?;
+    let mut redemptions: Vec<OfferRedemption> = offer_redemptions
+        .filter(redeemed_at.ge(start))
+        .filter(redeemed_at.lt(end))
+        .order(redeemed_at.asc())
+        .load(&mut conn)?;
+    if let Some(tag) = &params.tag {
+        redemptions.retain(|redemption| tags(redemption).contains(tag));
+    }
+
+    Ok(Json(ApiResponse::success(summarize_conversions(
+        &redemptions,
+    ))))
+}
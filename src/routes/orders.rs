@@ -0,0 +1,223 @@
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::error::{AppError, AppResult};
+use crate::model::{Invoice, Order, RevenueEvent};
+use crate::routes::history::record_order_event;
+use crate::types::{
+    ApiResponse, EmptyData, ExportFormat, GooglePlaySubscriptionResponse, OrderResponse,
+};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct ExportOrdersQuery {
+    /// First day to include (UTC)
+    pub from: NaiveDate,
+    /// Last day to include (UTC)
+    pub to: NaiveDate,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Record the latest order of a subscription we track, along with the price it was charged at
+///
+/// Every renewal gets a new order id, so each one is stored once. Orders for tokens we don't
+/// track (e.g. ignored sandbox purchases) are skipped.
+pub fn record_order(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    recorded_at: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::{invoices, orders, purchase_tokens, revenue_events};
+
+    let Some(order_id) = subscription_response.latest_order_id.clone() else {
+        return Ok(());
+    };
+    let line_item = subscription_response
+        .line_items
+        .first()
+        .ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+    let owner: Option<String> = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(purchase_token_param))
+        .select(purchase_tokens::user_id)
+        .first(conn)
+        .optional()?;
+    let Some(owner) = owner else {
+        return Ok(());
+    };
+
+    let order = Order::new(
+        order_id,
+        purchase_token_param.to_string(),
+        owner,
+        line_item.product_id.clone(),
+        subscription_response.region_code.clone(),
+        recorded_at,
+    );
+
+    let inserted = diesel::insert_or_ignore_into(orders::table)
+        .values(&order)
+        .execute(conn)?;
+    if inserted > 0 {
+        record_order_event(conn, &order)?;
+    }
+
+    // Prepaid plans and older API responses carry no price, the order is still worth keeping
+    let price = line_item
+        .auto_renewing_plan
+        .as_ref()
+        .and_then(|plan| plan.recurring_price.as_ref())
+        .and_then(|money| Some((money.currency_code.clone(), money.micros()?)));
+    let offer = line_item.offer_details.as_ref();
+
+    let invoice = Invoice::new(
+        &order,
+        offer.and_then(|offer| offer.base_plan_id.clone()),
+        offer.and_then(|offer| offer.offer_id.clone()),
+        price.clone(),
+    );
+    diesel::insert_or_ignore_into(invoices::table)
+        .values(&invoice)
+        .execute(conn)?;
+
+    if let Some((currency_code, price_micros)) = price {
+        let event = RevenueEvent::new(
+            &order,
+            offer.and_then(|offer| offer.base_plan_id.clone()),
+            offer.and_then(|offer| offer.offer_id.clone()),
+            currency_code,
+            price_micros,
+        );
+
+        diesel::insert_or_ignore_into(revenue_events::table)
+            .values(&event)
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+fn to_response(order: &Order) -> OrderResponse {
+    OrderResponse {
+        order_id: order.order_id.clone(),
+        purchase_token: order.purchase_token.clone(),
+        user_id: order.user_id.clone(),
+        product_id: order.product_id.clone(),
+        region_code: order.region_code.clone(),
+        recorded_at: order.recorded_at.and_utc().to_rfc3339(),
+    }
+}
+
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(orders: &[OrderResponse]) -> String {
+    let mut csv =
+        String::from("order_id,purchase_token,user_id,product_id,region_code,recorded_at\n");
+    for order in orders {
+        let fields = [
+            order.order_id.as_str(),
+            order.purchase_token.as_str(),
+            order.user_id.as_str(),
+            order.product_id.as_str(),
+            order.region_code.as_deref().unwrap_or(""),
+            order.recorded_at.as_str(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Start of `from` and of the day after `to`, for queries covering whole UTC days
+pub(crate) fn day_range(
+    from: NaiveDate,
+    to: NaiveDate,
+) -> AppResult<(NaiveDateTime, NaiveDateTime)> {
+    if to < from {
+        return Err(AppError::BadRequest(
+            "`to` must not be before `from`".to_string(),
+        ));
+    }
+
+    let start = from.and_hms_opt(0, 0, 0).unwrap();
+    let end = to
+        .succ_opt()
+        .ok_or_else(|| AppError::BadRequest("`to` is out of range".to_string()))?
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    Ok((start, end))
+}
+
+/// Export recorded Google Play orders for reconciliation against Play payouts
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/orders/export",
+    params(
+        ("from" = String, Query, description = "First day to include, YYYY-MM-DD (UTC)"),
+        ("to" = String, Query, description = "Last day to include, YYYY-MM-DD (UTC)"),
+        ("format" = Option<ExportFormat>, Query, description = "`json` (default) or `csv`"),
+    ),
+    responses(
+        (status = 200, description = "Orders recorded in the date range", body = ApiResponse<Vec<OrderResponse>>),
+        (status = 400, description = "Invalid date range", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn export_orders(
+    State(app_state): State<AppState>,
+    Query(params): Query<ExportOrdersQuery>,
+) -> Result<Response, AppError> {
+    use crate::schema::orders::dsl::*;
+
+    let (start, end) = day_range(params.from, params.to)?;
+
+    let mut conn = app_state.get_db_connection()?;
+    let records: Vec<Order> = orders
+        .filter(recorded_at.ge(start))
+        .filter(recorded_at.lt(end))
+        .order(recorded_at.asc())
+        .load(&mut conn)?;
+    let records: Vec<OrderResponse> = records.iter().map(to_response).collect();
+
+    let response = match params.format {
+        ExportFormat::Json => Json(ApiResponse::success(records)).into_response(),
+        ExportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"orders_{}_{}.csv\"",
+                        params.from, params.to
+                    ),
+                ),
+            ],
+            to_csv(&records),
+        )
+            .into_response(),
+    };
+
+    Ok(response)
+}
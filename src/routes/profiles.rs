@@ -0,0 +1,111 @@
+use axum::extract::State;
+use axum::{Extension, Json};
+
+use crate::auth::Claims;
+use crate::error::AppError;
+use crate::model::PurchaseIntent;
+use crate::profiles::{record_purchase_intent, register_profile};
+use crate::routes::user_tokens::ensure_owner;
+use crate::types::{
+    ApiResponse, EmptyData, PurchaseIntentRequest, PurchaseIntentResponse, RegisterProfileRequest,
+    RegisterProfileResponse,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+/// Register the user behind an obfuscated profile id before purchasing with it
+///
+/// Purchases from family or multi-profile accounts may reach us with only the profile id, they
+/// are credited to the user registered here. Registering the same pair again succeeds.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/google/profiles",
+    request_body = RegisterProfileRequest,
+    responses(
+        (status = 200, description = "Profile id registered to the user", body = ApiResponse<RegisterProfileResponse>),
+        (status = 400, description = "Invalid user id or profile id", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 409, description = "Profile id already registered to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Subscription Verification",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn register_purchase_profile(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<RegisterProfileRequest>,
+) -> Result<Json<ApiResponse<RegisterProfileResponse>>, AppError> {
+    let user_id = canonical_user_id(&payload.user_id)?;
+    ensure_owner(&claims, &user_id)?;
+
+    let profile = register_profile(
+        &mut app_state.get_db_connection()?,
+        payload.obfuscated_profile_id.trim(),
+        &user_id,
+        app_state.clock.now_naive(),
+    )?;
+
+    Ok(Json(ApiResponse::success(RegisterProfileResponse {
+        obfuscated_profile_id: profile.obfuscated_profile_id,
+        user_id: profile.user_id,
+        created_at: profile.created_at.and_utc().to_rfc3339(),
+    })))
+}
+
+/// Announce a purchase the app is about to start, before launching the billing flow
+///
+/// For `PURCHASE_INTENT_TTL_SECS` (30 minutes by default) verify and RTDN credit a purchase
+/// of the product carrying one of the given obfuscated ids to the user, also when its account
+/// id isn't the user's principal or RTDN gets here before verify. A profile id must be
+/// registered to the user with `POST /google/profiles` first.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/google/purchase-intent",
+    request_body = PurchaseIntentRequest,
+    responses(
+        (status = 200, description = "Purchase intent recorded", body = ApiResponse<PurchaseIntentResponse>),
+        (status = 400, description = "Invalid user id, no obfuscated id, or an account id naming another user", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user, the profile id isn't registered to the user, or another user announced the account id", body = ApiResponse<EmptyData>),
+        (status = 409, description = "Profile id registered to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Subscription Verification",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn record_intent(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<PurchaseIntentRequest>,
+) -> Result<Json<ApiResponse<PurchaseIntentResponse>>, AppError> {
+    let user_id = canonical_user_id(&payload.user_id)?;
+    ensure_owner(&claims, &user_id)?;
+
+    let now = app_state.clock.now_naive();
+    let trimmed = |id: Option<String>| id.map(|id| id.trim().to_string());
+    let intent = PurchaseIntent::new(
+        user_id,
+        payload.package_name,
+        payload.product_id,
+        trimmed(payload.obfuscated_account_id),
+        trimmed(payload.obfuscated_profile_id),
+        now + app_state.purchase_intent_ttl,
+        now,
+    );
+    record_purchase_intent(&mut app_state.get_db_connection()?, &intent)?;
+
+    Ok(Json(ApiResponse::success(PurchaseIntentResponse {
+        id: intent.id,
+        expires_at: intent.expires_at.and_utc().to_rfc3339(),
+    })))
+}
@@ -1,148 +1,700 @@
-use crate::auth::GoogleAuth;
+use crate::auth::Claims;
+use crate::clock::Clock;
+use crate::config::{env_number, ConfigError};
+use crate::db::token_versions::{current_token, ensure_unchanged};
 use crate::error::{AppError, AppResult};
-use crate::model::PurchaseToken;
-use crate::routes::goole_play_billing_helpers::{
-    acknowledge_google_play, fetch_google_play_purchase_details,
+use crate::events::DomainEvent;
+use crate::feature_flags::{
+    package_flag, FeatureFlags, CANDIDATE_VERIFICATION, HONOR_SANDBOX_PURCHASES,
+    PLAY_INTEGRITY_CHECK, PLAY_INTEGRITY_REQUIRED, QUARANTINE_SUSPICIOUS_VERIFICATIONS,
+    QUEUE_GRANTS_ON_IC_OUTAGE, RISK_MANUAL_APPROVAL, SHADOW_VERIFICATION, STRICT_ACCOUNT_MATCH,
+};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::play_integrity::PlayIntegrityApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::access_outbox::{enqueue_access_change, has_pending_access_change};
+use crate::jobs::acknowledgments::{acknowledge_purchase, AckStrategies};
+use crate::jobs::expiry_sweep::expire_tokens;
+use crate::jobs::pending_purchases::{
+    claim_provisional_token, complete_pending_purchase, PendingPurchaseOutcome,
 };
-use crate::routes::purchase_token_helpers::verify_subcription_response_for_active_status;
+use crate::line_items::access_expiry;
+use crate::messages::MessageKey;
+use crate::metrics::{Metrics, VERIFICATIONS_QUARANTINED_TOTAL};
+use crate::model::PurchaseToken;
+use crate::profiles::resolve_account_id;
+use crate::quarantine::{hold_for_review, record_verify_attempt, QuarantinePolicy};
+use crate::risk::requires_approval;
+use crate::routes::credits::top_up_upgrade_credits;
+use crate::routes::purchase_token_helpers::{admit_new_purchase, NewPurchaseAdmission};
+use crate::routes::user_tokens::ensure_owner;
+use crate::shadow::{ShadowCompare, ShadowExperiment};
+use crate::token_state::{TokenStateMachine, TransitionReason};
 use crate::types::{
-    ApiResponse, EmptyData, GooglePlaySubscriptionResponse, PurchaseTokenStatus, VerifyRequest,
+    ApiResponse, EmptyData, ErrorCode, GooglePlaySubscriptionResponse, OutboxAction,
+    PurchaseEnvironment, PurchaseTokenStatus, QuarantineReason, VerifyPreviewOutcome,
+    VerifyPreviewResponse, VerifyRequest,
 };
+use crate::user_id::canonical_user_id;
 
 use crate::AppState;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::Json;
+use axum::{Extension, Json};
 use diesel::prelude::*;
-use std::sync::Arc;
 use utoipa;
 
 fn verify_purchase_token_validity_for_subscription_active(
     payload: &VerifyRequest,
     subscription_response: &GooglePlaySubscriptionResponse,
-) -> AppResult<()> {
+) -> AppResult<NewPurchaseAdmission> {
     subscription_response
         .line_items
         .iter()
         .find(|item| item.product_id == payload.product_id)
         .ok_or(AppError::SubscriptionInvalidLineItems)?;
 
-    verify_subcription_response_for_active_status(subscription_response)
+    admit_new_purchase(subscription_response.subscription_state)
 }
 
-#[cfg(feature = "local")]
-async fn grant_user_access(
-    _product_id: &str,
-    _admin_ic_agent: Option<&ic_agent::Agent>,
-    user_id: &str,
-) -> AppResult<()> {
-    // Mock implementation for local development
-    println!("MOCK: Granting access to user {}", user_id);
-    Ok(())
+/// When access bought with the verified product ends
+///
+/// The product must be one of the subscription's line items, access lasts until the last of
+/// its entitled line items expires.
+fn line_item_expiry(
+    subscription_response: &GooglePlaySubscriptionResponse,
+    product: &str,
+) -> AppResult<chrono::NaiveDateTime> {
+    if !subscription_response
+        .line_items
+        .iter()
+        .any(|item| item.product_id == product)
+    {
+        return Err(AppError::SubscriptionInvalidLineItems);
+    }
+    access_expiry(subscription_response).ok_or(AppError::SubscriptionInvalidLineItems)
 }
-/// Grant user access to your services after successful purchase acknowledgment
+
+/// How close to its stored expiry a granted token is re-checked with Google on verify, from
+/// `EXPIRY_REFRESH_WINDOW_SECS` (default 48 hours)
+pub fn refresh_window_from_env() -> Result<chrono::Duration, ConfigError> {
+    let secs: i64 = env_number("EXPIRY_REFRESH_WINDOW_SECS", 48 * 60 * 60)?;
+    Ok(chrono::Duration::seconds(secs))
+}
+
+/// Verify checks being changed, so a change can run in shadow before it answers requests
 ///
-#[cfg(not(feature = "local"))]
-async fn grant_user_access(
-    product_id: &str,
-    admin_ic_agent: Option<&ic_agent::Agent>,
-    user_id: &str,
+/// A change in progress gets a field here, set by `candidate` while `current` keeps the
+/// behavior verify has today.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerificationRules {
+    /// Refuse purchases whose Google account isn't the caller
+    pub strict_account_match: bool,
+}
+
+impl VerificationRules {
+    /// Rules verify answers with, strict account matching only while its flag is on
+    pub fn current(flags: &FeatureFlags) -> Self {
+        Self {
+            strict_account_match: flags.is_enabled(STRICT_ACCOUNT_MATCH),
+        }
+    }
+
+    /// Rules being rolled out through `VERIFICATION_SHADOW`
+    pub fn candidate(_flags: &FeatureFlags) -> Self {
+        Self {
+            strict_account_match: true,
+        }
+    }
+}
+
+/// Verification rules rolled out behind `shadow_verification` and `candidate_verification`
+pub const VERIFICATION_SHADOW: ShadowExperiment = ShadowExperiment {
+    name: "verification",
+    shadow_flag: SHADOW_VERIFICATION,
+    candidate_flag: CANDIDATE_VERIFICATION,
+};
+
+/// What verifying a purchase token would do, decided without side effects
+enum PurchaseEvaluation {
+    /// The caller already holds an active grant for this token
+    AlreadyGranted(PurchaseToken),
+    /// The caller's grant is about to expire, with the expiry Google currently reports
+    Renewal {
+        token: PurchaseToken,
+        expiry_at: chrono::NaiveDateTime,
+        auto_renewing: bool,
+    },
+    /// Google reports an active subscription that should be granted
+    Grant {
+        subscription_response: GooglePlaySubscriptionResponse,
+        account_id: String,
+        expiry_at: chrono::NaiveDateTime,
+        environment: PurchaseEnvironment,
+        /// The caller's `Pending` row left by an earlier verify or a pending payment
+        pending: Option<PurchaseToken>,
+    },
+    /// Payment is still pending, the purchase is stored and granted once it completes
+    Defer {
+        account_id: String,
+        environment: PurchaseEnvironment,
+        pending: Option<PurchaseToken>,
+    },
+}
+
+impl ShadowCompare for PurchaseEvaluation {
+    fn shadow_key(&self) -> String {
+        match self {
+            PurchaseEvaluation::AlreadyGranted(token) => {
+                format!("already granted to {}", token.user_id)
+            }
+            PurchaseEvaluation::Renewal {
+                expiry_at,
+                auto_renewing,
+                ..
+            } => format!(
+                "renewal until {} (auto renewing {})",
+                expiry_at, auto_renewing
+            ),
+            PurchaseEvaluation::Grant {
+                account_id,
+                expiry_at,
+                environment,
+                ..
+            } => format!(
+                "grant to {} until {} ({:?})",
+                account_id, expiry_at, environment
+            ),
+            PurchaseEvaluation::Defer {
+                account_id,
+                environment,
+                ..
+            } => format!("defer for {} ({:?})", account_id, environment),
+        }
+    }
+}
+
+/// Check the Play Integrity token sent with a verify, while `play_integrity_check` is on
+///
+/// Requests without a token pass unless `play_integrity_required` is on too, so the check
+/// can be rolled out before every app version sends one.
+async fn check_device_integrity(
+    play_integrity: &dyn PlayIntegrityApi,
+    flags: &FeatureFlags,
+    clock: &dyn Clock,
+    payload: &VerifyRequest,
 ) -> AppResult<()> {
-    use crate::routes::utils::grant_yral_pro_plan_access;
+    if !flags.is_enabled(PLAY_INTEGRITY_CHECK) {
+        return Ok(());
+    }
 
-    let Some(admin_ic_agent) = admin_ic_agent else {
-        return Err(AppError::InternalError(
-            "Admin IC agent not available".to_string(),
-        ));
+    let integrity_token = match payload.integrity_token.as_deref() {
+        Some(integrity_token) => integrity_token,
+        None if flags.is_enabled(PLAY_INTEGRITY_REQUIRED) => {
+            return Err(AppError::IntegrityCheckFailed(
+                "integrity_token is required".to_string(),
+            ))
+        }
+        None => return Ok(()),
     };
 
-    grant_yral_pro_plan_access(product_id, admin_ic_agent, user_id).await?;
-
-    Ok(())
+    play_integrity
+        .decode_integrity_token(&payload.package_name, integrity_token)
+        .await?
+        .check(&payload.package_name, clock.now_naive())
 }
 
-async fn process_purchase_token(
+/// Run every check `verify` performs: package flag, device integrity, ownership, Google
+/// state, sandbox and account match. Nothing is acknowledged, granted or written.
+///
+/// Grants expiring within `refresh_window` are looked up again, the stored expiry predates
+/// any renewal Google charged since.
+#[allow(clippy::too_many_arguments)]
+async fn evaluate_purchase_token(
     conn: &mut SqliteConnection,
-    auth: Option<&Arc<GoogleAuth>>,
-    admin_ic_agent: Option<&ic_agent::Agent>,
+    google_play: &dyn GooglePlayApi,
+    play_integrity: &dyn PlayIntegrityApi,
+    flags: &FeatureFlags,
+    clock: &dyn Clock,
+    refresh_window: chrono::Duration,
+    rules: VerificationRules,
     payload: &VerifyRequest,
-) -> AppResult<()> {
+) -> AppResult<PurchaseEvaluation> {
     use crate::schema::purchase_tokens::dsl::*;
 
+    if !flags.is_enabled(&package_flag(&payload.package_name)) {
+        return Err(AppError::PackageDisabled(payload.package_name.clone()));
+    }
+
+    check_device_integrity(play_integrity, flags, clock, payload).await?;
+
     let existing_token: Option<PurchaseToken> = purchase_tokens
         .filter(purchase_token.eq(&payload.purchase_token))
         .first(conn)
         .optional()?;
 
+    let now = clock.now_naive();
     match existing_token {
-        Some(token) if token.user_id != payload.user_id => {
-            return Err(AppError::TokenAlreadyUsed);
+        // Provisional rows have no owner yet, they are checked against the resolved one below
+        Some(token) if token.provisional_since.is_none() && token.user_id != payload.user_id => {
+            Err(AppError::TokenAlreadyUsed)
         }
         Some(token)
             if token.status == PurchaseTokenStatus::AccessGranted
-                && token.expiry_at > chrono::Utc::now().naive_utc() =>
+                && token.expiry_at > now + refresh_window =>
         {
-            Ok(())
+            Ok(PurchaseEvaluation::AlreadyGranted(token))
         }
-        _ => {
-            let gooogle_subscription_response = fetch_google_play_purchase_details(
-                &payload.package_name,
-                &payload.purchase_token,
-                auth,
-            )
-            .await?;
+        Some(token)
+            if token.status == PurchaseTokenStatus::AccessGranted && token.expiry_at > now =>
+        {
+            let subscription_response = google_play
+                .fetch_subscription(&payload.package_name, &payload.purchase_token)
+                .await?;
+
+            Ok(PurchaseEvaluation::Renewal {
+                expiry_at: line_item_expiry(&subscription_response, &payload.product_id)?,
+                auto_renewing: subscription_response.auto_renewing(),
+                token,
+            })
+        }
+        existing => {
+            let provisional = existing
+                .as_ref()
+                .is_some_and(|token| token.provisional_since.is_some());
+            let pending = existing.filter(|token| token.status == PurchaseTokenStatus::Pending);
 
-            verify_purchase_token_validity_for_subscription_active(
+            let gooogle_subscription_response = google_play
+                .fetch_subscription(&payload.package_name, &payload.purchase_token)
+                .await?;
+
+            let admission = verify_purchase_token_validity_for_subscription_active(
                 payload,
                 &gooogle_subscription_response,
             )?;
 
-            acknowledge_google_play(
-                &payload.package_name,
-                &payload.purchase_token,
-                &gooogle_subscription_response,
-                auth,
-            )
-            .await?;
+            let purchase_environment = gooogle_subscription_response.environment();
+            if purchase_environment == PurchaseEnvironment::Sandbox
+                && !flags.is_enabled(HONOR_SANDBOX_PURCHASES)
+            {
+                return Err(AppError::SandboxPurchaseNotHonored);
+            }
 
-            grant_user_access(
+            let identifiers = gooogle_subscription_response
+                .external_account_identifiers
+                .as_ref();
+            let account_id = resolve_account_id(
+                conn,
+                identifiers.and_then(|ids| ids.obfuscated_external_account_id.as_deref()),
+                identifiers.and_then(|ids| ids.obfuscated_external_profile_id.as_deref()),
                 &payload.product_id,
-                admin_ic_agent,
-                gooogle_subscription_response
-                    .external_account_identifiers
-                    .ok_or(AppError::ExternalAccountIdentifiersMissing)?
-                    .obfuscated_external_account_id
-                    .ok_or(AppError::ExternalAccountIdentifiersMissing)?
-                    .as_str(),
-            )
-            .await?;
+                now,
+            )?;
 
-            let expiry = gooogle_subscription_response
-                .line_items
-                .iter()
-                .find(|item| item.product_id == payload.product_id)
-                .map(|item| item.expiry_time.clone())
-                .ok_or(AppError::SubscriptionInvalidLineItems)?;
-
-            let expiry_native = expiry
-                .and_then(|time_str| chrono::DateTime::parse_from_rfc3339(&time_str).ok())
-                .map(|dt| dt.naive_utc())
-                .ok_or(AppError::SubscriptionInvalidLineItems)?;
-
-            let new_token = PurchaseToken::new(
-                payload.user_id.clone(),
-                payload.purchase_token.clone(),
-                expiry_native,
-                PurchaseTokenStatus::AccessGranted,
-            );
+            // Only the user a provisional row resolves to may claim it
+            if provisional && account_id != payload.user_id {
+                return Err(AppError::TokenAlreadyUsed);
+            }
+
+            // Access is granted to the account Google reports, which may differ from the caller
+            if rules.strict_account_match && account_id != payload.user_id {
+                return Err(AppError::AccountMismatch);
+            }
+
+            // Users with repeated refunds or chargebacks wait for an operator
+            if flags.is_enabled(RISK_MANUAL_APPROVAL) && requires_approval(conn, &account_id)? {
+                return Err(AppError::ManualApprovalRequired);
+            }
+
+            // Pending payments are accepted and granted once Google reports them active
+            if admission == NewPurchaseAdmission::AwaitPayment {
+                return Ok(PurchaseEvaluation::Defer {
+                    account_id,
+                    environment: purchase_environment,
+                    pending,
+                });
+            }
+
+            let expiry_native =
+                line_item_expiry(&gooogle_subscription_response, &payload.product_id)?;
+
+            Ok(PurchaseEvaluation::Grant {
+                subscription_response: gooogle_subscription_response,
+                account_id,
+                expiry_at: expiry_native,
+                environment: purchase_environment,
+                pending,
+            })
+        }
+    }
+}
+
+/// `evaluate_purchase_token` under the current rules, with the candidate rules run next to
+/// them while `VERIFICATION_SHADOW` is on
+///
+/// Both paths look the purchase up with Google, the second lookup is answered by the Google
+/// Play cache when it is enabled.
+#[allow(clippy::too_many_arguments)]
+async fn evaluate_shadowed(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    play_integrity: &dyn PlayIntegrityApi,
+    flags: &FeatureFlags,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+    refresh_window: chrono::Duration,
+    payload: &VerifyRequest,
+) -> AppResult<PurchaseEvaluation> {
+    let current = evaluate_purchase_token(
+        conn,
+        google_play,
+        play_integrity,
+        flags,
+        clock,
+        refresh_window,
+        VerificationRules::current(flags),
+        payload,
+    )
+    .await;
+    let Some(authoritative) = VERIFICATION_SHADOW.authoritative(flags) else {
+        return current;
+    };
+
+    let candidate = evaluate_purchase_token(
+        conn,
+        google_play,
+        play_integrity,
+        flags,
+        clock,
+        refresh_window,
+        VerificationRules::candidate(flags),
+        payload,
+    )
+    .await;
+    VERIFICATION_SHADOW.settle(
+        metrics,
+        &payload.purchase_token,
+        authoritative,
+        current,
+        candidate,
+    )
+}
+
+/// Result of a successful verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Granted,
+    /// Payment is pending, access is granted by RTDN or reconciliation once it completes
+    Pending,
+    /// Payment completed while the IC is unreachable, the grant waits in the access outbox
+    GrantQueued,
+    /// The verification looked suspicious, access waits for an operator's approval
+    Quarantined(QuarantineReason),
+}
+
+/// What `process_purchase_token` did with a purchase
+#[derive(Debug, Clone)]
+pub struct VerifyResult {
+    pub outcome: VerifyOutcome,
+    /// The subscription Google reported, when this call granted it rather than an earlier one
+    pub new_grant: Option<GooglePlaySubscriptionResponse>,
+}
+
+impl From<VerifyOutcome> for VerifyResult {
+    fn from(outcome: VerifyOutcome) -> Self {
+        Self {
+            outcome,
+            new_grant: None,
+        }
+    }
+}
+
+/// Store the expiry Google reported for a grant close to its stored expiry
+///
+/// A renewed subscription keeps access until its new expiry. One Google no longer extends is
+/// expired right away, revoking Pro unless the user holds another subscription.
+fn refresh_granted_token(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+    renewed_expiry: chrono::NaiveDateTime,
+    renewing: bool,
+    now: chrono::NaiveDateTime,
+) -> AppResult<VerifyOutcome> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set((expiry_at.eq(renewed_expiry), auto_renewing.eq(renewing)))
+        .execute(conn)?;
+        ensure_unchanged(updated, token)?;
+
+        if renewed_expiry <= now {
+            // The update above moved the version on, expire the row as it is now
+            let refreshed: PurchaseToken = purchase_tokens.find(&token.id).first(conn)?;
+            expire_tokens(conn, &[refreshed], now)?;
+        }
+        Ok(())
+    })?;
+
+    if renewed_expiry <= now {
+        return Err(AppError::SubscriptionExpired);
+    }
+    if renewed_expiry != token.expiry_at {
+        println!(
+            "Refreshed expiry of purchase token {} from {} to {}",
+            token.purchase_token, token.expiry_at, renewed_expiry
+        );
+    }
+
+    Ok(VerifyOutcome::Granted)
+}
+
+/// Store the purchase as `Pending`, before anything is granted
+///
+/// `expiry` is the placeholder `now` while payment is pending, the real expiry is only known
+/// once it completes. A row left `Pending` by a failure is picked up again by the next verify
+/// or the pending purchase job.
+fn store_pending_purchase(
+    conn: &mut SqliteConnection,
+    payload: &VerifyRequest,
+    expiry: chrono::NaiveDateTime,
+    purchase_environment: PurchaseEnvironment,
+) -> AppResult<PurchaseToken> {
+    use crate::schema::purchase_tokens::dsl::*;
 
-            diesel::replace_into(purchase_tokens)
-                .values(&new_token)
-                .execute(conn)?;
+    let Some(token) = current_token(conn, &payload.purchase_token)? else {
+        let mut pending_token = PurchaseToken::new(
+            payload.user_id.clone(),
+            payload.purchase_token.clone(),
+            expiry,
+            PurchaseTokenStatus::Pending,
+            purchase_environment,
+        );
+        pending_token.package_name = Some(payload.package_name.clone());
+        diesel::insert_into(purchase_tokens)
+            .values(&pending_token)
+            .execute(conn)?;
+        return Ok(pending_token);
+    };
+
+    // A token stored before is updated in place, which must be a change its status allows. Its
+    // id and version stay, as do the RTDN, acknowledgment, pause and replacement state.
+    let next_status = TokenStateMachine::next(token.status, TransitionReason::Verified)?;
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set((
+        user_id.eq(&payload.user_id),
+        expiry_at.eq(expiry),
+        status.eq(next_status),
+        environment.eq(purchase_environment),
+        package_name.eq(Some(&payload.package_name)),
+        notified_at.eq(None::<chrono::NaiveDateTime>),
+        provisional_since.eq(None::<chrono::NaiveDateTime>),
+    ))
+    .execute(conn)?;
+    ensure_unchanged(updated, &token)?;
+
+    Ok(purchase_tokens.find(&token.id).first(conn)?)
+}
+
+/// Accept a purchase Google confirmed while the IC is unreachable
+///
+/// The token stays `Pending`, the purchase is acknowledged so Google doesn't void it and the
+/// grant is queued in the access outbox. Once the IC recovers the pending purchase job moves
+/// the token to `AccessGranted` and records the order.
+#[allow(clippy::too_many_arguments)]
+async fn queue_grant_during_outage(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    token: &PurchaseToken,
+    account_id: &str,
+    payload: &VerifyRequest,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: chrono::NaiveDateTime,
+) -> AppResult<()> {
+    if let Err(e) = acknowledge_purchase(
+        conn,
+        google_play,
+        ack_strategies,
+        &payload.package_name,
+        &token.purchase_token,
+        subscription_response,
+        now,
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to acknowledge purchase token {}, leaving it for retry: {}",
+            token.purchase_token, e
+        );
+    }
 
-            Ok(())
+    // Verifying again before the IC recovers must not queue a second grant
+    if !has_pending_access_change(conn, &token.purchase_token, OutboxAction::GrantPro)? {
+        enqueue_access_change(
+            conn,
+            account_id,
+            OutboxAction::GrantPro,
+            Some(&payload.product_id),
+            Some(&token.purchase_token),
+            now,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Verify a purchase token with Google and grant access, shared by the HTTP and gRPC APIs
+///
+/// The token is stored `Pending` first and only moves to `AccessGranted` once Pro is granted
+/// on the IC, so a failure in between leaves a row the next attempt resumes from. While
+/// `queue_grants_on_ic_outage` is on, a grant the IC fails is queued instead of failing.
+/// While `quarantine_suspicious_verifications` is on, a grant tripping one of `quarantine`'s
+/// limits waits for an operator instead. While `shadow_verification` or
+/// `candidate_verification` is on, the checks also run under `VerificationRules::candidate`.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_purchase_token(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    play_integrity: &dyn PlayIntegrityApi,
+    user_info: &dyn UserInfoApi,
+    flags: &FeatureFlags,
+    clock: &dyn Clock,
+    metrics: &Metrics,
+    refresh_window: chrono::Duration,
+    quarantine: &QuarantinePolicy,
+    payload: &VerifyRequest,
+) -> AppResult<VerifyResult> {
+    // Attempts that fail count too, other users' rejected attempts are what sharing looks like
+    let quarantining = flags.is_enabled(QUARANTINE_SUSPICIOUS_VERIFICATIONS);
+    if quarantining {
+        record_verify_attempt(
+            conn,
+            &payload.purchase_token,
+            &payload.user_id,
+            clock.now_naive(),
+        )?;
+    }
+
+    let evaluation = evaluate_shadowed(
+        conn,
+        google_play,
+        play_integrity,
+        flags,
+        clock,
+        metrics,
+        refresh_window,
+        payload,
+    )
+    .await?;
+
+    let now = clock.now_naive();
+    let (subscription_response, account_id, token) = match evaluation {
+        PurchaseEvaluation::AlreadyGranted(_) => return Ok(VerifyOutcome::Granted.into()),
+        PurchaseEvaluation::Renewal {
+            token,
+            expiry_at,
+            auto_renewing,
+        } => {
+            return refresh_granted_token(conn, &token, expiry_at, auto_renewing, now)
+                .map(VerifyResult::from)
+        }
+        PurchaseEvaluation::Defer {
+            account_id,
+            environment,
+            pending,
+        } => {
+            match pending {
+                Some(token) => {
+                    claim_provisional_token(conn, &token, &account_id)?;
+                }
+                None => {
+                    store_pending_purchase(conn, payload, now, environment)?;
+                }
+            }
+            return Ok(VerifyOutcome::Pending.into());
+        }
+        PurchaseEvaluation::Grant {
+            subscription_response,
+            account_id,
+            expiry_at: grant_expiry,
+            environment,
+            pending,
+        } => {
+            if quarantining {
+                if let Some(reason) = hold_for_review(
+                    conn,
+                    quarantine,
+                    payload,
+                    &account_id,
+                    &subscription_response,
+                    now,
+                )? {
+                    return Ok(VerifyOutcome::Quarantined(reason).into());
+                }
+            }
+
+            // Resume a row an earlier attempt left behind instead of starting over
+            let token = match pending {
+                Some(token) => claim_provisional_token(conn, &token, &account_id)?,
+                None => store_pending_purchase(conn, payload, grant_expiry, environment)?,
+            };
+            (subscription_response, account_id, token)
+        }
+    };
+
+    let outcome = match complete_pending_purchase(
+        conn,
+        google_play,
+        ack_strategies,
+        user_info,
+        &token,
+        &account_id,
+        &payload.package_name,
+        &subscription_response,
+        now,
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
+        Err(e) if e.is_ic_failure() && flags.is_enabled(QUEUE_GRANTS_ON_IC_OUTAGE) => {
+            eprintln!(
+                "IC unreachable while granting purchase token {}, queueing the grant: {}",
+                token.purchase_token, e
+            );
+            queue_grant_during_outage(
+                conn,
+                google_play,
+                ack_strategies,
+                &token,
+                &account_id,
+                payload,
+                &subscription_response,
+                now,
+            )
+            .await?;
+            return Ok(VerifyOutcome::GrantQueued.into());
         }
+        Err(e) => return Err(e),
+    };
+
+    match outcome {
+        PendingPurchaseOutcome::Granted => Ok(VerifyResult {
+            outcome: VerifyOutcome::Granted,
+            new_grant: Some(subscription_response),
+        }),
+        PendingPurchaseOutcome::StillPending => Ok(VerifyOutcome::Pending.into()),
+        PendingPurchaseOutcome::Canceled => Err(AppError::SubscriptionCanceled),
     }
 }
 
@@ -152,29 +704,233 @@ async fn process_purchase_token(
     request_body = VerifyRequest,
     responses(
         (status = 200, description = "Subscription verification successful", body = ApiResponse<EmptyData>),
+        (status = 202, description = "Payment is pending, completed while the IC is unreachable (code `GRANT_QUEUED`) or the verification is quarantined for review (code `VERIFICATION_QUARANTINED`), access is granted once it completes", body = ApiResponse<EmptyData>),
         (status = 400, description = "Bad request - subscription canceled, expired, or invalid", body = ApiResponse<EmptyData>),
+        (status = 403, description = "Account is flagged for refund abuse and needs manual approval, the device failed the Play Integrity check, or an operator rejected the quarantined verification", body = ApiResponse<EmptyData>),
+        (status = 503, description = "Google Play is throttling, retry after the Retry-After header", body = ApiResponse<EmptyData>),
         (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
     ),
     tag = "Subscription Verification"
 )]
 pub async fn verify_purchase(
     State(app_state): State<AppState>,
-    Json(payload): Json<VerifyRequest>,
+    Json(mut payload): Json<VerifyRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    payload.user_id = canonical_user_id(&payload.user_id)?;
     let mut conn = app_state
         .get_db_connection()
         .map_err(|_| AppError::DatabaseConnection)?;
 
-    process_purchase_token(
+    let VerifyResult { outcome, new_grant } = process_purchase_token(
         &mut conn,
-        app_state.google_auth.as_ref(),
-        app_state.admin_ic_agent.as_ref(),
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.play_integrity.as_ref(),
+        app_state.user_info.as_ref(),
+        &app_state.feature_flags,
+        app_state.clock.as_ref(),
+        &app_state.metrics,
+        app_state.expiry_refresh_window,
+        &app_state.quarantine_policy,
         &payload,
     )
     .await?;
+    if let VerifyOutcome::Quarantined(reason) = outcome {
+        app_state.metrics.inc_counter(
+            VERIFICATIONS_QUARANTINED_TOTAL,
+            &[("reason", reason.as_str())],
+            1,
+        );
+    } else {
+        publish_purchase_verified(&app_state, &mut conn, &payload);
+    }
+    if let Some(subscription_response) = &new_grant {
+        top_up_verified_upgrade(&app_state, &mut conn, &payload, subscription_response).await;
+    }
 
-    Ok((
-        StatusCode::OK,
-        Json(ApiResponse::<EmptyData>::success(EmptyData {})),
-    ))
+    match outcome {
+        VerifyOutcome::Granted => Ok((
+            StatusCode::OK,
+            Json(ApiResponse::<EmptyData>::success(EmptyData {})),
+        )
+            .into_response()),
+        VerifyOutcome::Pending => Ok((
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::<EmptyData>::success_with_msg(
+                EmptyData {},
+                "Payment is pending, access will be granted once it completes".to_string(),
+            )),
+        )
+            .into_response()),
+        VerifyOutcome::GrantQueued => Ok((
+            StatusCode::ACCEPTED,
+            Extension(MessageKey::new(ErrorCode::GrantQueued)),
+            Json(ApiResponse::<EmptyData>::success_with_code(
+                EmptyData {},
+                ErrorCode::GrantQueued,
+                "Payment confirmed, access will be granted shortly".to_string(),
+            )),
+        )
+            .into_response()),
+        VerifyOutcome::Quarantined(_) => Ok((
+            StatusCode::ACCEPTED,
+            Extension(MessageKey::new(ErrorCode::VerificationQuarantined)),
+            Json(ApiResponse::<EmptyData>::success_with_code(
+                EmptyData {},
+                ErrorCode::VerificationQuarantined,
+                "Purchase is under review, access will be granted once it is approved".to_string(),
+            )),
+        )
+            .into_response()),
+    }
+}
+
+/// Add the upgrade credits of a purchase this verify granted, RTDN retries them if this fails
+async fn top_up_verified_upgrade(
+    app_state: &AppState,
+    conn: &mut SqliteConnection,
+    payload: &VerifyRequest,
+    subscription_response: &GooglePlaySubscriptionResponse,
+) {
+    if let Err(e) = top_up_upgrade_credits(
+        app_state,
+        conn,
+        &payload.package_name,
+        &payload.user_id,
+        subscription_response,
+    )
+    .await
+    {
+        eprintln!(
+            "Failed to add upgrade credits for purchase token {}: {}",
+            payload.purchase_token, e
+        );
+    }
+}
+
+/// Tell event consumers verify accepted `payload`
+///
+/// Called for granted, pending and queued grants alike. The token is already stored by then,
+/// a failure to publish is logged instead of failing the request.
+pub fn publish_purchase_verified(
+    app_state: &AppState,
+    conn: &mut SqliteConnection,
+    payload: &VerifyRequest,
+) {
+    let event = DomainEvent::PurchaseVerified {
+        user_id: payload.user_id.clone(),
+        purchase_token: payload.purchase_token.clone(),
+        product_id: payload.product_id.clone(),
+    };
+    if let Err(e) = app_state
+        .events
+        .publish(conn, &event, app_state.clock.now_naive())
+    {
+        eprintln!(
+            "Failed to publish verification of purchase token {}: {}",
+            payload.purchase_token, e
+        );
+    }
+}
+
+/// Preview what verifying a purchase token would do
+///
+/// Runs the same checks as `/google/verify` and returns the same errors, but skips the
+/// Google acknowledgment, the IC grant and all database writes.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/google/verify/preview",
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, description = "Verification would succeed", body = ApiResponse<VerifyPreviewResponse>),
+        (status = 400, description = "Bad request - verification would fail with this error", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Subscription Verification",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn preview_verify_purchase(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<VerifyRequest>,
+) -> Result<Json<ApiResponse<VerifyPreviewResponse>>, AppError> {
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    ensure_owner(&claims, &payload.user_id)?;
+    let mut conn = app_state.get_db_connection()?;
+
+    let evaluation = evaluate_shadowed(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        app_state.play_integrity.as_ref(),
+        &app_state.feature_flags,
+        app_state.clock.as_ref(),
+        &app_state.metrics,
+        app_state.expiry_refresh_window,
+        &payload,
+    )
+    .await?;
+
+    let mut preview = match evaluation {
+        PurchaseEvaluation::AlreadyGranted(token) => VerifyPreviewResponse {
+            outcome: VerifyPreviewOutcome::AlreadyGranted,
+            grant_to_user_id: Some(token.user_id),
+            expiry_at: Some(token.expiry_at.and_utc().to_rfc3339()),
+            environment: token.environment,
+            would_acknowledge: false,
+        },
+        // Verify would expire the grant instead
+        PurchaseEvaluation::Renewal { expiry_at, .. }
+            if expiry_at <= app_state.clock.now_naive() =>
+        {
+            return Err(AppError::SubscriptionExpired)
+        }
+        PurchaseEvaluation::Renewal {
+            token, expiry_at, ..
+        } => VerifyPreviewResponse {
+            outcome: VerifyPreviewOutcome::AlreadyGranted,
+            grant_to_user_id: Some(token.user_id),
+            expiry_at: Some(expiry_at.and_utc().to_rfc3339()),
+            environment: token.environment,
+            would_acknowledge: false,
+        },
+        PurchaseEvaluation::Grant {
+            subscription_response,
+            account_id,
+            expiry_at,
+            environment,
+            ..
+        } => VerifyPreviewResponse {
+            outcome: VerifyPreviewOutcome::WouldGrant,
+            grant_to_user_id: Some(account_id),
+            expiry_at: Some(expiry_at.and_utc().to_rfc3339()),
+            environment,
+            would_acknowledge: app_state
+                .ack_strategies
+                .for_package(&payload.package_name)
+                .should_acknowledge(subscription_response.acknowledgement_state),
+        },
+        PurchaseEvaluation::Defer {
+            account_id,
+            environment,
+            ..
+        } => VerifyPreviewResponse {
+            outcome: VerifyPreviewOutcome::WouldDefer,
+            grant_to_user_id: Some(account_id),
+            expiry_at: None,
+            environment,
+            would_acknowledge: false,
+        },
+    };
+    // A purchase made from another account must not reveal who that is
+    preview.grant_to_user_id = preview
+        .grant_to_user_id
+        .filter(|grant_to| ensure_owner(&claims, grant_to).is_ok());
+
+    Ok(Json(ApiResponse::success(preview)))
 }
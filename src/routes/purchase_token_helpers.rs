@@ -1,26 +1,41 @@
 use crate::{
     error::{AppError, AppResult},
-    types::{google_play_subscription_state, GooglePlaySubscriptionResponse},
+    types::{GooglePlaySubscriptionResponse, SubscriptionState},
 };
 
 pub fn verify_subcription_response_for_active_status(
     subscription_response: &GooglePlaySubscriptionResponse,
 ) -> AppResult<()> {
-    match subscription_response.subscription_state.as_str() {
-        google_play_subscription_state::SUBSCRIPTION_STATE_ACTIVE => Ok(()),
-        google_play_subscription_state::SUBSCRIPTION_STATE_CANCELED => {
-            Err(AppError::SubscriptionCanceled)
-        }
-        google_play_subscription_state::SUBSCRIPTION_STATE_IN_GRACE_PERIOD => Ok(()),
-        google_play_subscription_state::SUBSCRIPTION_STATE_ON_HOLD => {
-            Err(AppError::SubscriptionOnHold)
-        }
-        google_play_subscription_state::SUBSCRIPTION_STATE_PAUSED => {
-            Err(AppError::SubscriptionPaused)
-        }
-        google_play_subscription_state::SUBSCRIPTION_STATE_EXPIRED => {
-            Err(AppError::SubscriptionExpired)
-        }
-        _ => Err(AppError::SubscriptionInvalidState),
+    verify_subscription_state_is_active(subscription_response.subscription_state)
+}
+
+pub fn verify_subscription_state_is_active(state: SubscriptionState) -> AppResult<()> {
+    match state {
+        SubscriptionState::Active | SubscriptionState::InGracePeriod => Ok(()),
+        SubscriptionState::Canceled => Err(AppError::SubscriptionCanceled),
+        SubscriptionState::OnHold => Err(AppError::SubscriptionOnHold),
+        SubscriptionState::Paused => Err(AppError::SubscriptionPaused),
+        SubscriptionState::Expired => Err(AppError::SubscriptionExpired),
+        SubscriptionState::Unspecified
+        | SubscriptionState::Pending
+        | SubscriptionState::PendingPurchaseCanceled
+        | SubscriptionState::Unknown => Err(AppError::SubscriptionInvalidState),
+    }
+}
+
+/// How a purchase seen for the first time is taken in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewPurchaseAdmission {
+    /// Active or in its grace period, access is granted now
+    Grant,
+    /// Payment is pending, access is granted once it completes
+    AwaitPayment,
+}
+
+/// Whether a new purchase in `state` is taken in, the one decision verify and RTDN share
+pub fn admit_new_purchase(state: SubscriptionState) -> AppResult<NewPurchaseAdmission> {
+    if state == SubscriptionState::Pending {
+        return Ok(NewPurchaseAdmission::AwaitPayment);
     }
+    verify_subscription_state_is_active(state).map(|()| NewPurchaseAdmission::Grant)
 }
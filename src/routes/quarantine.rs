@@ -0,0 +1,260 @@
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::Claims;
+use crate::error::{AppError, AppResult};
+use crate::metrics::QUARANTINE_DECISIONS_TOTAL;
+use crate::model::{AdminAuditEntry, QuarantinedVerification};
+use crate::quarantine::{decide_quarantine, quarantined_verifications};
+use crate::routes::access::operator;
+use crate::routes::purchase::{process_purchase_token, publish_purchase_verified};
+use crate::types::{
+    ApiResponse, AuditAction, EmptyData, QuarantineDecisionRequest, QuarantineResponse,
+    QuarantineStatus, VerifyRequest,
+};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct QuarantineQuery {
+    /// Entries with this status, pending by default
+    pub status: Option<QuarantineStatus>,
+}
+
+fn quarantine_response(entry: QuarantinedVerification) -> QuarantineResponse {
+    QuarantineResponse {
+        id: entry.id,
+        user_id: entry.user_id,
+        account_id: entry.account_id,
+        purchase_token: entry.purchase_token,
+        package_name: entry.package_name,
+        product_id: entry.product_id,
+        reason: entry.reason,
+        status: entry.status,
+        created_at: entry.created_at.and_utc().to_rfc3339(),
+        decided_at: entry.decided_at.map(|time| time.and_utc().to_rfc3339()),
+        decided_by: entry.decided_by,
+    }
+}
+
+/// Record the operator's decision on a pending entry together with its audit entry
+fn decide(
+    app_state: &AppState,
+    claims: &Claims,
+    entry_id: &str,
+    decision: QuarantineStatus,
+    reason: &str,
+) -> AppResult<QuarantinedVerification> {
+    use crate::schema::admin_audit_log;
+
+    if reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "A reason is required to decide on a quarantined verification".to_string(),
+        ));
+    }
+
+    let operator = operator(claims);
+    let now = app_state.clock.now_naive();
+    let mut conn = app_state.get_db_connection()?;
+
+    let action = if decision == QuarantineStatus::Approved {
+        AuditAction::ApproveQuarantine
+    } else {
+        AuditAction::RejectQuarantine
+    };
+
+    let entry = conn.transaction::<_, AppError, _>(|conn| {
+        let entry =
+            decide_quarantine(conn, entry_id, decision, &operator, now)?.ok_or_else(|| {
+                AppError::BadRequest(format!("No pending quarantined verification {}", entry_id))
+            })?;
+        diesel::insert_into(admin_audit_log::table)
+            .values(&AdminAuditEntry::new(
+                operator.clone(),
+                action,
+                entry.purchase_token.clone(),
+                reason.to_string(),
+                None,
+                now,
+            ))
+            .execute(conn)?;
+        Ok(entry)
+    })?;
+
+    app_state.metrics.inc_counter(
+        QUARANTINE_DECISIONS_TOTAL,
+        &[("decision", decision.as_str())],
+        1,
+    );
+    println!(
+        "Quarantined verification {} of purchase token {} {} by {}: {}",
+        entry.id,
+        entry.purchase_token,
+        decision.as_str(),
+        operator,
+        reason
+    );
+
+    Ok(entry)
+}
+
+/// List verifications held in quarantine
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/quarantine",
+    params(
+        ("status" = Option<QuarantineStatus>, Query, description = "Entries with this status, pending by default"),
+    ),
+    responses(
+        (status = 200, description = "Quarantined verifications, oldest first", body = ApiResponse<Vec<QuarantineResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_quarantined_verifications(
+    State(app_state): State<AppState>,
+    Query(params): Query<QuarantineQuery>,
+) -> Result<Json<ApiResponse<Vec<QuarantineResponse>>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let entries = quarantined_verifications(
+        &mut conn,
+        params.status.unwrap_or(QuarantineStatus::Pending),
+    )?
+    .into_iter()
+    .map(quarantine_response)
+    .collect();
+
+    Ok(Json(ApiResponse::success(entries)))
+}
+
+/// Approve a quarantined verification and grant its purchase
+///
+/// The purchase is verified again with Google right away. Should that fail, e.g. because
+/// Play Integrity tokens are required, access is granted on the user's next verify instead.
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/quarantine/{id}/approve",
+    params(
+        ("id" = String, Path, description = "Quarantine entry to approve"),
+    ),
+    request_body = QuarantineDecisionRequest,
+    responses(
+        (status = 200, description = "Verification approved", body = ApiResponse<QuarantineResponse>),
+        (status = 400, description = "Missing reason or entry is not pending", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn approve_quarantined_verification(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(payload): Json<QuarantineDecisionRequest>,
+) -> Result<Json<ApiResponse<QuarantineResponse>>, AppError> {
+    let entry = decide(
+        &app_state,
+        &claims,
+        &id,
+        QuarantineStatus::Approved,
+        &payload.reason,
+    )?;
+
+    let verify_request = VerifyRequest {
+        user_id: entry.user_id.clone(),
+        package_name: entry.package_name.clone(),
+        product_id: entry.product_id.clone(),
+        purchase_token: entry.purchase_token.clone(),
+        integrity_token: None,
+    };
+    let mut conn = app_state.get_db_connection()?;
+    let granted = process_purchase_token(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.play_integrity.as_ref(),
+        app_state.user_info.as_ref(),
+        &app_state.feature_flags,
+        app_state.clock.as_ref(),
+        &app_state.metrics,
+        app_state.expiry_refresh_window,
+        &app_state.quarantine_policy,
+        &verify_request,
+    )
+    .await;
+
+    match granted {
+        Ok(_) => {
+            publish_purchase_verified(&app_state, &mut conn, &verify_request);
+            Ok(Json(ApiResponse::success(quarantine_response(entry))))
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to grant approved purchase token {}, left for the next verify: {}",
+                entry.purchase_token, e
+            );
+            Ok(Json(ApiResponse::success_with_msg(
+                quarantine_response(entry),
+                format!(
+                    "Approved, access is granted on the user's next verify: {}",
+                    e
+                ),
+            )))
+        }
+    }
+}
+
+/// Reject a quarantined verification, later verifications of the purchase fail
+///
+/// Requires a JWT with the `billing:admin` scope and a `sub` claim, which is recorded as the
+/// operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/quarantine/{id}/reject",
+    params(
+        ("id" = String, Path, description = "Quarantine entry to reject"),
+    ),
+    request_body = QuarantineDecisionRequest,
+    responses(
+        (status = 200, description = "Verification rejected", body = ApiResponse<QuarantineResponse>),
+        (status = 400, description = "Missing reason or entry is not pending", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn reject_quarantined_verification(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    Json(payload): Json<QuarantineDecisionRequest>,
+) -> Result<Json<ApiResponse<QuarantineResponse>>, AppError> {
+    let entry = decide(
+        &app_state,
+        &claims,
+        &id,
+        QuarantineStatus::Rejected,
+        &payload.reason,
+    )?;
+
+    Ok(Json(ApiResponse::success(quarantine_response(entry))))
+}
@@ -0,0 +1,276 @@
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::db::token_versions::{current_token, ensure_unchanged, reserve_token};
+use crate::error::{AppError, AppResult};
+use crate::model::{PurchaseToken, RefundRequest};
+use crate::subscriptions::revoke_pro_for_subscription;
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{
+    ApiResponse, CreateRefundRequest, EmptyData, RefundRequestAction, RefundRequestActionRequest,
+    RefundRequestResponse, RefundRequestStatus, SubscriptionSource,
+};
+use crate::user_id::canonical_user_id;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct ListRefundRequestsQuery {
+    pub status: Option<RefundRequestStatus>,
+}
+
+fn to_response(request: &RefundRequest) -> RefundRequestResponse {
+    RefundRequestResponse {
+        id: request.id.clone(),
+        user_id: request.user_id.clone(),
+        order_id: request.order_id.clone(),
+        reason: request.reason.clone(),
+        status: request.status,
+        created_at: request.created_at.and_utc().to_rfc3339(),
+        updated_at: request.updated_at.and_utc().to_rfc3339(),
+    }
+}
+
+/// Submit a refund request for a subscription
+#[utoipa::path(
+    post,
+    path = "/support/refund-request",
+    request_body = CreateRefundRequest,
+    responses(
+        (status = 200, description = "Refund request recorded", body = ApiResponse<RefundRequestResponse>),
+        (status = 400, description = "Unknown purchase token or token owned by another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Refunds"
+)]
+pub async fn create_refund_request(
+    State(app_state): State<AppState>,
+    Json(mut payload): Json<CreateRefundRequest>,
+) -> Result<Json<ApiResponse<RefundRequestResponse>>, AppError> {
+    use crate::schema::{purchase_tokens, refund_requests};
+
+    payload.user_id = canonical_user_id(&payload.user_id)?;
+    let mut conn = app_state.get_db_connection()?;
+
+    let token: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&payload.purchase_token))
+        .first(&mut conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest("Unknown purchase token".to_string()))?;
+
+    if token.user_id != payload.user_id {
+        return Err(AppError::TokenAlreadyUsed);
+    }
+
+    // Resubmitting while a request is still open returns the existing one
+    let open_request: Option<RefundRequest> = refund_requests::table
+        .filter(refund_requests::purchase_token.eq(&payload.purchase_token))
+        .filter(
+            refund_requests::status
+                .eq(RefundRequestStatus::Requested)
+                .or(refund_requests::status.eq(RefundRequestStatus::Approved)),
+        )
+        .first(&mut conn)
+        .optional()?;
+
+    if let Some(existing) = open_request {
+        return Ok(Json(ApiResponse::success(to_response(&existing))));
+    }
+
+    // Link the request to the Play order so support can find it in the Play Console
+    let subscription_response = app_state
+        .google_play
+        .fetch_subscription(&payload.package_name, &payload.purchase_token)
+        .await?;
+
+    let request = RefundRequest::new(
+        payload.user_id.clone(),
+        payload.purchase_token.clone(),
+        subscription_response.latest_order_id,
+        payload.reason.clone(),
+        app_state.clock.now_naive(),
+    );
+
+    diesel::insert_into(refund_requests::table)
+        .values(&request)
+        .execute(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(to_response(&request))))
+}
+
+/// List refund requests, optionally filtered by status
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/refund-requests",
+    params(
+        ("status" = Option<RefundRequestStatus>, Query, description = "Only return requests in this status"),
+    ),
+    responses(
+        (status = 200, description = "Refund requests", body = ApiResponse<Vec<RefundRequestResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Refunds",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_refund_requests(
+    State(app_state): State<AppState>,
+    Query(params): Query<ListRefundRequestsQuery>,
+) -> Result<Json<ApiResponse<Vec<RefundRequestResponse>>>, AppError> {
+    use crate::schema::refund_requests::dsl::*;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let mut query = refund_requests.order(created_at.asc()).into_boxed();
+    if let Some(filter_status) = params.status {
+        query = query.filter(status.eq(filter_status));
+    }
+    let requests: Vec<RefundRequest> = query.load(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(
+        requests.iter().map(to_response).collect(),
+    )))
+}
+
+/// Approve or reject a refund request
+///
+/// Approving revokes Pro access and expires the purchase token. A request stuck in
+/// `Approved` (revocation failed) can be approved again to retry.
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/admin/refund-requests/{id}",
+    params(
+        ("id" = String, Path, description = "Refund request ID"),
+    ),
+    request_body = RefundRequestActionRequest,
+    responses(
+        (status = 200, description = "Refund request updated", body = ApiResponse<RefundRequestResponse>),
+        (status = 400, description = "Unknown or already resolved refund request", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Refunds",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn act_on_refund_request(
+    State(app_state): State<AppState>,
+    Path(request_id): Path<String>,
+    Json(payload): Json<RefundRequestActionRequest>,
+) -> Result<Json<ApiResponse<RefundRequestResponse>>, AppError> {
+    use crate::schema::refund_requests::dsl::*;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let request: RefundRequest = refund_requests
+        .filter(id.eq(&request_id))
+        .first(&mut conn)
+        .optional()?
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown refund request {}", request_id)))?;
+
+    let next_status = match (request.status, payload.action) {
+        (RefundRequestStatus::Requested, RefundRequestAction::Reject) => {
+            RefundRequestStatus::Rejected
+        }
+        (
+            RefundRequestStatus::Requested | RefundRequestStatus::Approved,
+            RefundRequestAction::Approve,
+        ) => {
+            set_refund_status(
+                &mut conn,
+                &app_state,
+                &request.id,
+                RefundRequestStatus::Approved,
+            )?;
+            revoke_refunded_access(&mut conn, &app_state, &request).await?;
+            RefundRequestStatus::Revoked
+        }
+        (current, _) => {
+            return Err(AppError::BadRequest(format!(
+                "Refund request is {:?} and cannot be changed",
+                current
+            )));
+        }
+    };
+
+    set_refund_status(&mut conn, &app_state, &request.id, next_status)?;
+
+    let updated: RefundRequest = refund_requests
+        .filter(id.eq(&request.id))
+        .first(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(to_response(&updated))))
+}
+
+fn set_refund_status(
+    conn: &mut SqliteConnection,
+    app_state: &AppState,
+    request_id: &str,
+    new_status: RefundRequestStatus,
+) -> AppResult<()> {
+    use crate::schema::refund_requests::dsl::*;
+
+    diesel::update(refund_requests.filter(id.eq(request_id)))
+        .set((
+            status.eq(new_status),
+            updated_at.eq(app_state.clock.now_naive()),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+async fn revoke_refunded_access(
+    conn: &mut SqliteConnection,
+    app_state: &AppState,
+    request: &RefundRequest,
+) -> AppResult<()> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let stored = current_token(conn, &request.purchase_token)?;
+    let next_status = match &stored {
+        Some(token) => TokenStateMachine::next(token.status, TransitionReason::Refunded)?,
+        None => TokenStateMachine::transition(TransitionReason::Refunded).to,
+    };
+    let source = stored
+        .as_ref()
+        .map(|token| SubscriptionSource::for_environment(token.environment))
+        .unwrap_or(SubscriptionSource::GooglePlay);
+    let reserved = stored
+        .as_ref()
+        .map(|token| reserve_token(conn, token))
+        .transpose()?;
+
+    revoke_pro_for_subscription(
+        conn,
+        app_state.user_info.as_ref(),
+        &request.user_id,
+        source,
+        &request.purchase_token,
+        app_state.clock.now_naive(),
+    )
+    .await?;
+
+    if let Some(token) = reserved {
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set(status.eq(next_status))
+        .execute(conn)?;
+        ensure_unchanged(updated, &token)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use diesel::prelude::*;
+
+use crate::auth::Claims;
+use crate::error::AppError;
+use crate::model::{AdminAuditEntry, UserRisk};
+use crate::risk::{approve_user, flagged_users};
+use crate::types::{ApiResponse, ApproveUserRiskRequest, AuditAction, EmptyData, UserRiskResponse};
+use crate::user_id::normalize_user_id;
+use crate::AppState;
+
+fn risk_response(risk: UserRisk) -> UserRiskResponse {
+    UserRiskResponse {
+        requires_approval: risk.requires_approval(),
+        user_id: risk.user_id,
+        revoked_count: risk.revoked_count,
+        voided_count: risk.voided_count,
+        flagged_at: risk.flagged_at.map(|time| time.and_utc().to_rfc3339()),
+        approved_at: risk.approved_at.map(|time| time.and_utc().to_rfc3339()),
+        approved_by: risk.approved_by,
+    }
+}
+
+/// List users flagged for repeated revocations or voided purchases
+///
+/// Approved users stay listed with their approval. Requires a JWT with the
+/// `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/risk/users",
+    responses(
+        (status = 200, description = "Flagged users, most recently flagged first", body = ApiResponse<Vec<UserRiskResponse>>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_flagged_users(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<UserRiskResponse>>>, AppError> {
+    let mut conn = app_state.get_db_connection()?;
+
+    let users = flagged_users(&mut conn)?
+        .into_iter()
+        .map(risk_response)
+        .collect();
+
+    Ok(Json(ApiResponse::success(users)))
+}
+
+/// Approve a flagged user so their verifications grant access again
+///
+/// Only matters while the `risk_manual_approval` flag is on. Requires a JWT with the
+/// `billing:admin` scope and a `sub` claim, which is recorded as the operator in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/risk/users/{user_id}/approve",
+    params(
+        ("user_id" = String, Path, description = "User to approve"),
+    ),
+    request_body = ApproveUserRiskRequest,
+    responses(
+        (status = 200, description = "User approved", body = ApiResponse<UserRiskResponse>),
+        (status = 400, description = "Missing reason or user is not flagged", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn approve_flagged_user(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<ApproveUserRiskRequest>,
+) -> Result<Json<ApiResponse<UserRiskResponse>>, AppError> {
+    use crate::schema::admin_audit_log;
+
+    let user_id = normalize_user_id(&user_id);
+    if payload.reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "A reason is required to approve a flagged user".to_string(),
+        ));
+    }
+
+    // `require_admin_scope` guarantees `sub` is present
+    let operator = claims.sub.clone().unwrap_or_default();
+    let now = app_state.clock.now_naive();
+    let mut conn = app_state.get_db_connection()?;
+
+    let entry = AdminAuditEntry::new(
+        operator.clone(),
+        AuditAction::ApproveRisk,
+        user_id.clone(),
+        payload.reason.clone(),
+        None,
+        now,
+    );
+
+    let risk = conn.transaction::<_, AppError, _>(|conn| {
+        let risk = approve_user(conn, &user_id, &operator, now)?
+            .ok_or_else(|| AppError::BadRequest(format!("User {} is not flagged", user_id)))?;
+        diesel::insert_into(admin_audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+        Ok(risk)
+    })?;
+
+    println!(
+        "Flagged user {} approved by {}: {}",
+        user_id, operator, payload.reason
+    );
+
+    Ok(Json(ApiResponse::success(risk_response(risk))))
+}
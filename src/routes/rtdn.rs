@@ -1,51 +1,52 @@
-use std::sync::Arc;
-
-use crate::auth::{GoogleAuth, GooglePublicKey};
+use crate::config::{env_number, ConfigError};
+use crate::db::token_versions::{current_token, ensure_unchanged, reserve_token};
+use crate::db::transactions::{begin_immediate, finish};
 use crate::error::AppError;
-use crate::model::PurchaseToken;
-use crate::routes::goole_play_billing_helpers::{
-    acknowledge_google_play, fetch_google_play_purchase_details,
+use crate::events::{record_event, DomainEvent};
+use crate::feature_flags::{package_flag, HONOR_SANDBOX_PURCHASES, RTDN_VOIDED_PURCHASES};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::integrations::user_info::UserInfoApi;
+use crate::jobs::access_outbox::{cancel_scheduled_access_changes, schedule_access_change};
+use crate::jobs::acknowledgments::{acknowledge_purchase, AckStrategies};
+use crate::jobs::grace_reminders::{start_grace_reminders, stop_grace_reminders};
+use crate::jobs::pending_purchases::{
+    claim_provisional_token, complete_pending_purchase, record_provisional_purchase,
+};
+use crate::line_items::{access_expiry, granting_product, record_line_items};
+use crate::model::{HeldNotification, PurchaseToken, UnhandledNotification};
+use crate::notifier::BillingEvent;
+use crate::plans::{plan_for_product, CreditEvent};
+use crate::profiles::resolve_account_id;
+use crate::risk::{record_risk_event, RiskEvent};
+use crate::routes::credits::{top_up_credits, upgrade_credits};
+use crate::routes::history::record_cancellation;
+use crate::routes::orders::record_order;
+use crate::routes::purchase_token_helpers::{admit_new_purchase, NewPurchaseAdmission};
+use crate::rtdn_lag::record_rtdn_lag;
+use crate::subscriptions::{
+    grant_pro_for_subscription, granted_by_other_token, renew_pro_for_subscription,
+    replace_duplicate_tokens, revoke_pro_for_subscription,
 };
-use crate::routes::purchase_token_helpers::verify_subcription_response_for_active_status;
-use crate::routes::utils::{grant_yral_pro_plan_access, revoke_yral_pro_plan_access};
+use crate::token_state::{TokenStateMachine, TransitionReason};
 use crate::types::{
-    one_time_product_notification_type, subscription_notification_type, DeveloperNotification,
-    GooglePlaySubscriptionResponse, OneTimeProductNotification, PubSubMessage, PurchaseTokenStatus,
+    CreditReason, DeveloperNotification, GooglePlaySubscriptionResponse,
+    OneTimeProductNotification, OneTimeProductNotificationType, OutboxAction, PubSubMessage,
+    PurchaseEnvironment, PurchaseTokenStatus, SubscriptionNotificationType, SubscriptionSource,
+    VoidedProductType, VoidedPurchaseNotification,
 };
-use axum::http::HeaderMap;
 use axum::{http::StatusCode, response::IntoResponse, Json};
 use base64::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::{prelude::*, RunQueryDsl};
-use reqwest::header::AUTHORIZATION;
 use serde_json;
 
-pub async fn verify_rtdn_webhook(
-    header_value: Option<&axum::http::HeaderValue>,
-    google_public_key: Arc<GooglePublicKey>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let auth_header = header_value.ok_or("Missing Authorization header")?;
-    let auth_token = auth_header.to_str()?.trim_start_matches("Bearer ").trim();
-
-    google_public_key.validate_token(auth_token).await?;
-
-    Ok(())
-}
-
+/// Pub/Sub push endpoint for Play notifications, authenticated by `enforce_auth_policy`
 pub async fn handle_rtdn_webhook(
-    header_map: HeaderMap,
     axum::extract::State(app_state): axum::extract::State<crate::AppState>,
     Json(payload): Json<PubSubMessage>,
 ) -> impl IntoResponse {
     println!("Received RTDN webhook: {:?}", payload);
 
-    let auth_header = header_map.get(AUTHORIZATION).take();
-
-    if let Err(e) = verify_rtdn_webhook(auth_header, app_state.google_public_key.clone()).await {
-        eprintln!("Authentication failed: {}", e);
-        return (StatusCode::UNAUTHORIZED, "Unauthorized");
-    }
-
     // Decode the base64 message data
     let decoded_data = match BASE64_STANDARD.decode(&payload.message.data) {
         Ok(data) => data,
@@ -73,15 +74,30 @@ pub async fn handle_rtdn_webhook(
     };
 
     // Process the notification
-    match process_notification(&notification, &app_state).await {
-        Ok(_) => {
+    match process_notification(&notification, &notification_json, &app_state, true).await {
+        Ok(applied) => {
             println!(
                 "Successfully processed notification for package: {}",
                 notification.package_name
             );
+            // Held notifications are measured once the flush job applies them
+            if applied {
+                record_lag(&app_state, &notification, payload.message.publish_time());
+            }
             // HTTP 200 acknowledges the message to Pub/Sub - Google requires simple success response
             (StatusCode::OK, "OK")
         }
+        // Redelivery can't make the change valid, so the message is acknowledged
+        Err(e)
+            if matches!(
+                e.downcast_ref::<AppError>(),
+                Some(AppError::InvalidTransition { .. })
+            ) =>
+        {
+            eprintln!("Ignoring notification: {}", e);
+            record_lag(&app_state, &notification, payload.message.publish_time());
+            (StatusCode::OK, "OK")
+        }
         Err(e) => {
             eprintln!("Failed to process notification: {}", e);
             // HTTP 500 causes Pub/Sub to retry delivery
@@ -91,25 +107,169 @@ pub async fn handle_rtdn_webhook(
     }
 }
 
-async fn process_notification(
+/// Record the notification's processing lag, a failure only costs the sample
+fn record_lag(
+    app_state: &crate::AppState,
+    notification: &DeveloperNotification,
+    publish_time: Option<chrono::NaiveDateTime>,
+) {
+    let result = app_state.get_db_connection().and_then(|mut conn| {
+        record_rtdn_lag(
+            &mut conn,
+            &app_state.metrics,
+            &notification.type_label(),
+            notification.event_time(),
+            publish_time,
+            app_state.clock.now_naive(),
+        )
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to record RTDN lag: {}", e);
+    }
+}
+
+/// How long notifications that take access away are held before being applied, from
+/// `RTDN_HOLD_WINDOW_SECS` (default 0, applied right away)
+pub fn hold_window_from_env() -> Result<chrono::Duration, ConfigError> {
+    let secs: i64 = env_number("RTDN_HOLD_WINDOW_SECS", 0)?;
+    Ok(chrono::Duration::seconds(secs))
+}
+
+/// Subscription notifications that move a token backwards, e.g. to expired
+///
+/// Pub/Sub can deliver one of these before the renewal that preceded it, which would then be
+/// discarded as stale along with its order and credits, so they are held for a while first.
+fn is_state_regressing(notification_type: SubscriptionNotificationType) -> bool {
+    matches!(
+        notification_type,
+        SubscriptionNotificationType::Canceled
+            | SubscriptionNotificationType::OnHold
+            | SubscriptionNotificationType::Paused
+            | SubscriptionNotificationType::Revoked
+            | SubscriptionNotificationType::Expired
+            | SubscriptionNotificationType::PendingPurchaseCanceled
+    )
+}
+
+/// Apply a notification the flush job released from `held_notifications`
+///
+/// Skipped like any other if a newer notification for the token was applied meanwhile. Its
+/// lag is recorded without a delivery lag, the Pub/Sub publish time isn't kept while held.
+pub async fn apply_held_notification(
     notification: &DeveloperNotification,
+    raw_notification: &str,
     app_state: &crate::AppState,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    process_notification(notification, raw_notification, app_state, false).await?;
+    record_lag(app_state, notification, None);
+    Ok(())
+}
+
+/// Apply a notification, or hold it when `may_hold` and it regresses the token's state
+///
+/// Returns whether it was applied, `false` when it was held.
+async fn process_notification(
+    notification: &DeveloperNotification,
+    raw_notification: &str,
+    app_state: &crate::AppState,
+    may_hold: bool,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     println!(
         "Processing notification for package: {}",
         notification.package_name
     );
     println!("Event time: {}", notification.event_time_millis);
 
+    // Acknowledge without acting so Pub/Sub doesn't redeliver while the package is switched off
+    if !app_state
+        .feature_flags
+        .is_enabled(&package_flag(&notification.package_name))
+    {
+        println!(
+            "Billing is disabled for package {}, ignoring notification",
+            notification.package_name
+        );
+        return Ok(true);
+    }
+
+    // Pub/Sub is unordered, so a token's notifications are applied one at a time in event order
+    let token_to_order = notification.subscription_purchase_token();
+    let event_time = notification.event_time();
+    let _token_guard = match token_to_order {
+        Some(token) => Some(app_state.token_locks.lock(token).await),
+        None => None,
+    };
+    // Subscription notifications are checked again in the transaction that applies them
+    if let (Some(token), Some(event_time)) = (token_to_order, event_time) {
+        if is_stale_notification(&mut app_state.get_db_connection()?, token, event_time)? {
+            println!(
+                "Discarding notification for token {} that is not newer than the last applied one",
+                token
+            );
+            return Ok(true);
+        }
+    }
+
+    // Held until an earlier notification still in flight has had a chance to arrive
+    if may_hold && app_state.rtdn_hold_window > chrono::Duration::zero() {
+        if let (Some(sub_notification), Some(event_time)) =
+            (&notification.subscription_notification, event_time)
+        {
+            if is_state_regressing(sub_notification.notification_type) {
+                let now = app_state.clock.now_naive();
+                hold_notification(
+                    &mut app_state.get_db_connection()?,
+                    HeldNotification::new(
+                        sub_notification.purchase_token.clone(),
+                        event_time,
+                        sub_notification.notification_type.into(),
+                        raw_notification.to_string(),
+                        now,
+                        now + app_state.rtdn_hold_window,
+                    ),
+                )?;
+                println!(
+                    "Holding {:?} notification for token {} until {}",
+                    sub_notification.notification_type,
+                    sub_notification.purchase_token,
+                    now + app_state.rtdn_hold_window
+                );
+                return Ok(false);
+            }
+        }
+    }
+
     // Handle subscription notifications
     if let Some(sub_notification) = &notification.subscription_notification {
-        handle_subscription_notification(sub_notification, app_state, &notification.package_name)
-            .await?;
+        handle_subscription_notification(
+            sub_notification,
+            app_state,
+            &notification.package_name,
+            raw_notification,
+            event_time,
+        )
+        .await?;
     }
 
     // Handle one-time product notifications
     if let Some(otp_notification) = &notification.one_time_product_notification {
-        handle_one_time_product_notification(otp_notification, app_state).await?;
+        handle_one_time_product_notification(otp_notification, app_state, raw_notification).await?;
+    }
+
+    // Handle voided purchase notifications (refunds, chargebacks)
+    if let Some(voided_notification) = &notification.voided_purchase_notification {
+        if app_state.feature_flags.is_enabled(RTDN_VOIDED_PURCHASES) {
+            handle_voided_purchase_notification(voided_notification, app_state, raw_notification)
+                .await?;
+        } else {
+            record_unhandled_notification(
+                app_state,
+                "voided_purchase",
+                None,
+                Some(&voided_notification.purchase_token),
+                raw_notification,
+            )?;
+        }
     }
 
     // Handle test notifications
@@ -117,17 +277,121 @@ async fn process_notification(
         handle_test_notification(test_notification).await?;
     }
 
+    // Notification kinds added by Google after this handler was written
+    if notification.subscription_notification.is_none()
+        && notification.one_time_product_notification.is_none()
+        && notification.voided_purchase_notification.is_none()
+        && notification.test_notification.is_none()
+    {
+        println!("Unrecognized developer notification, storing for inspection");
+        record_unhandled_notification(app_state, "unrecognized", None, None, raw_notification)?;
+    }
+
+    // Subscription notifications record theirs along with the change they make
+    if let (Some(token), Some(event_time), None) = (
+        token_to_order,
+        event_time,
+        &notification.subscription_notification,
+    ) {
+        record_event_time(&mut app_state.get_db_connection()?, token, event_time)?;
+    }
+
+    Ok(true)
+}
+
+/// Whether a notification at `event_time` was already applied or superseded for the token
+///
+/// Redeliveries carry the same event time, so they are discarded too.
+fn is_stale_notification(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    event_time: chrono::NaiveDateTime,
+) -> Result<bool, AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let last_applied: Option<chrono::NaiveDateTime> = purchase_tokens
+        .filter(purchase_token.eq(purchase_token_param))
+        .select(last_event_time)
+        .first::<Option<chrono::NaiveDateTime>>(conn)
+        .optional()?
+        .flatten();
+
+    Ok(last_applied.is_some_and(|last| event_time <= last))
+}
+
+/// Remember the event time of an applied notification, never moving it backwards
+fn record_event_time(
+    conn: &mut SqliteConnection,
+    purchase_token_param: &str,
+    event_time: chrono::NaiveDateTime,
+) -> Result<(), AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let Some(token) = current_token(conn, purchase_token_param)? else {
+        return Ok(());
+    };
+    if token.last_event_time.is_some_and(|last| last >= event_time) {
+        return Ok(());
+    }
+
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set(last_event_time.eq(event_time))
+    .execute(conn)?;
+    ensure_unchanged(updated, &token)
+}
+
+/// Store a notification for the flush job, redeliveries of one already held are ignored
+fn hold_notification(conn: &mut SqliteConnection, held: HeldNotification) -> Result<(), AppError> {
+    use crate::schema::held_notifications;
+
+    diesel::insert_or_ignore_into(held_notifications::table)
+        .values(&held)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Store a notification we can't act on so it isn't lost once Pub/Sub acknowledges it
+fn record_unhandled_notification(
+    app_state: &crate::AppState,
+    kind: &str,
+    notification_type: Option<i32>,
+    purchase_token: Option<&str>,
+    raw_notification: &str,
+) -> Result<(), AppError> {
+    use crate::schema::unhandled_notifications;
+
+    let unhandled = UnhandledNotification::new(
+        kind.to_string(),
+        notification_type,
+        purchase_token.map(str::to_string),
+        raw_notification.to_string(),
+        app_state.clock.now_naive(),
+    );
+
+    diesel::insert_into(unhandled_notifications::table)
+        .values(&unhandled)
+        .execute(&mut app_state.get_db_connection()?)?;
+
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_new_subscription_purchase(
     conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
-    auth: Option<&Arc<GoogleAuth>>,
-    admin_ic_agent: &ic_agent::Agent,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    honor_sandbox_purchases: bool,
     package_name: &str,
     user_id_str: &str,
     purchase_token_param: &str,
     subscription_response: &GooglePlaySubscriptionResponse,
+    now: chrono::NaiveDateTime,
 ) -> Result<(), AppError> {
     use crate::schema::purchase_tokens::dsl::*;
 
@@ -137,72 +401,152 @@ pub async fn handle_new_subscription_purchase(
         .first(conn)
         .optional()?;
 
-    let expiry = subscription_response
-        .line_items
-        .iter()
-        .find(|item| item.product_id == subscription_response.line_items[0].product_id)
-        .map(|item| item.expiry_time.clone())
-        .ok_or(AppError::SubscriptionInvalidLineItems)?;
-
-    let product_id = &subscription_response.line_items[0].product_id;
+    let expiry = access_expiry(subscription_response);
+    let product_id =
+        granting_product(subscription_response).ok_or(AppError::SubscriptionInvalidLineItems)?;
 
     match existing_token {
+        // Payment of a deferred purchase completed
+        Some(token) if token.status == PurchaseTokenStatus::Pending => {
+            let token = claim_provisional_token(conn, &token, user_id_str)?;
+            complete_pending_purchase(
+                conn,
+                google_play,
+                ack_strategies,
+                user_info,
+                &token,
+                user_id_str,
+                package_name,
+                subscription_response,
+                now,
+            )
+            .await?;
+
+            Ok(())
+        }
         Some(token) => {
             // Update existing token with new expiry and status
-            let expiry_native = expiry
-                .and_then(|time_str| chrono::DateTime::parse_from_rfc3339(&time_str).ok())
-                .map(|dt| dt.naive_utc())
-                .ok_or(AppError::SubscriptionInvalidLineItems)?;
+            let next_status = TokenStateMachine::next(token.status, TransitionReason::Refreshed)?;
+            let expiry_native = expiry.ok_or(AppError::SubscriptionInvalidLineItems)?;
 
-            diesel::update(purchase_tokens.filter(id.eq(&token.id)))
-                .set((
-                    expiry_at.eq(expiry_native),
-                    status.eq(PurchaseTokenStatus::AccessGranted),
-                ))
-                .execute(conn)?;
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set((
+                expiry_at.eq(expiry_native),
+                status.eq(next_status),
+                auto_renewing.eq(subscription_response.auto_renewing()),
+                notified_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
 
             Ok(())
         }
         None => {
-            verify_subcription_response_for_active_status(subscription_response)?;
-            acknowledge_google_play(
-                package_name,
+            let payment_pending = admit_new_purchase(subscription_response.subscription_state)?
+                == NewPurchaseAdmission::AwaitPayment;
+
+            let purchase_environment = subscription_response.environment();
+            if purchase_environment == PurchaseEnvironment::Sandbox && !honor_sandbox_purchases {
+                // Returning an error here would make Pub/Sub redeliver forever
+                println!(
+                    "Ignoring sandbox purchase for user {} as sandbox purchases are not honored",
+                    user_id_str
+                );
+                return Ok(());
+            }
+
+            // Access is granted once the payment completes
+            if payment_pending {
+                let mut pending_token = PurchaseToken::new(
+                    user_id_str.to_string(),
+                    purchase_token_param.to_string(),
+                    now,
+                    PurchaseTokenStatus::Pending,
+                    purchase_environment,
+                );
+                pending_token.package_name = Some(package_name.to_string());
+
+                diesel::insert_into(purchase_tokens)
+                    .values(&pending_token)
+                    .execute(conn)?;
+
+                return Ok(());
+            }
+
+            grant_pro_for_subscription(
+                conn,
+                user_info,
+                product_id,
+                user_id_str,
+                SubscriptionSource::for_environment(purchase_environment),
                 purchase_token_param,
-                subscription_response,
-                auth,
+                now,
             )
             .await?;
-            grant_yral_pro_plan_access(product_id, admin_ic_agent, user_id_str).await?;
 
             // Insert new purchase token into database
-            let expiry_native = expiry
-                .and_then(|time_str| chrono::DateTime::parse_from_rfc3339(&time_str).ok())
-                .map(|dt| dt.naive_utc())
-                .ok_or(AppError::SubscriptionInvalidLineItems)?;
+            let expiry_native = expiry.ok_or(AppError::SubscriptionInvalidLineItems)?;
 
-            let new_token = PurchaseToken::new(
+            let mut new_token = PurchaseToken::new(
                 user_id_str.to_string(),
                 purchase_token_param.to_string(),
                 expiry_native,
                 PurchaseTokenStatus::AccessGranted,
+                purchase_environment,
             );
+            new_token.auto_renewing = subscription_response.auto_renewing();
+            new_token.package_name = Some(package_name.to_string());
 
             diesel::insert_into(purchase_tokens)
                 .values(&new_token)
                 .execute(conn)?;
+            replace_duplicate_tokens(conn, user_id_str)?;
+
+            // Access is already granted, a failed acknowledgment is retried by the monitor job
+            if let Err(e) = acknowledge_purchase(
+                conn,
+                google_play,
+                ack_strategies,
+                package_name,
+                purchase_token_param,
+                subscription_response,
+                now,
+            )
+            .await
+            {
+                eprintln!(
+                    "Failed to acknowledge purchase token {}, leaving it for retry: {}",
+                    purchase_token_param, e
+                );
+            }
 
             Ok(())
         }
     }
 }
 
+/// Restore or extend access for a renewed or recovered subscription
+///
+/// With `keep_credits` the user's credits are left for the renewal top-up instead of being
+/// reset to the allotment. Returns whether an existing grant was renewed, false for a pending
+/// purchase completed here with its first period's credits.
+#[allow(clippy::too_many_arguments)]
 async fn handle_subscription_renewal(
     conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
-    admin_ic_agent: &ic_agent::Agent,
+    google_play: &dyn GooglePlayApi,
+    ack_strategies: &AckStrategies,
+    user_info: &dyn UserInfoApi,
+    package_name: &str,
     user_id_param: &str,
     purchase_token_param: &str,
     subscription_response: &GooglePlaySubscriptionResponse,
-) -> Result<(), AppError> {
+    keep_credits: bool,
+    now: chrono::NaiveDateTime,
+) -> Result<bool, AppError> {
     use crate::schema::purchase_tokens::dsl::*;
 
     // Check if this purchase token already exists
@@ -211,38 +555,90 @@ async fn handle_subscription_renewal(
         .first(conn)
         .optional()?;
 
-    let expiry = subscription_response
-        .line_items
-        .iter()
-        .find(|item| item.product_id == subscription_response.line_items[0].product_id)
-        .map(|item| item.expiry_time.clone())
-        .ok_or(AppError::SubscriptionInvalidLineItems)?;
-
-    let product_id = &subscription_response
-        .line_items
-        .get(0)
-        .map(|item| &item.product_id)
-        .ok_or(AppError::SubscriptionInvalidLineItems)?;
+    let expiry = access_expiry(subscription_response);
+    let product_id =
+        granting_product(subscription_response).ok_or(AppError::SubscriptionInvalidLineItems)?;
 
     match existing_token {
+        Some(token) if token.status == PurchaseTokenStatus::Pending => {
+            let token = claim_provisional_token(conn, &token, user_id_param)?;
+            complete_pending_purchase(
+                conn,
+                google_play,
+                ack_strategies,
+                user_info,
+                &token,
+                user_id_param,
+                package_name,
+                subscription_response,
+                now,
+            )
+            .await?;
+
+            Ok(false)
+        }
         Some(token) => {
             // Update existing token with new expiry and status
+            let next_status = TokenStateMachine::next(token.status, TransitionReason::Renewed)?;
 
-            let expiry_native = expiry
-                .and_then(|time_str| chrono::DateTime::parse_from_rfc3339(&time_str).ok())
-                .map(|dt| dt.naive_utc())
-                .ok_or(AppError::SubscriptionInvalidLineItems)?;
+            let expiry_native = expiry.ok_or(AppError::SubscriptionInvalidLineItems)?;
+            let token = reserve_token(conn, &token)?;
 
-            grant_yral_pro_plan_access(product_id, admin_ic_agent, user_id_param).await?;
+            // A token replaced from another Google account may still renew, Pro is already granted
+            if granted_by_other_token(conn, user_id_param, purchase_token_param, now)? {
+                println!(
+                    "User {} already has Pro through another purchase token, not granting for {}",
+                    user_id_param, purchase_token_param
+                );
+            } else if keep_credits {
+                renew_pro_for_subscription(
+                    conn,
+                    user_info,
+                    product_id,
+                    user_id_param,
+                    SubscriptionSource::for_environment(token.environment),
+                    purchase_token_param,
+                    now,
+                )
+                .await?;
+            } else {
+                grant_pro_for_subscription(
+                    conn,
+                    user_info,
+                    product_id,
+                    user_id_param,
+                    SubscriptionSource::for_environment(token.environment),
+                    purchase_token_param,
+                    now,
+                )
+                .await?;
+            }
 
-            diesel::update(purchase_tokens.filter(id.eq(&token.id)))
-                .set((
-                    expiry_at.eq(expiry_native),
-                    status.eq(PurchaseTokenStatus::AccessGranted),
-                ))
-                .execute(conn)?;
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set((
+                expiry_at.eq(expiry_native),
+                status.eq(next_status),
+                auto_renewing.eq(subscription_response.auto_renewing()),
+                notified_at.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
+            replace_duplicate_tokens(conn, user_id_param)?;
+            record_event(
+                conn,
+                &DomainEvent::SubscriptionRenewed {
+                    user_id: user_id_param.to_string(),
+                    purchase_token: purchase_token_param.to_string(),
+                    product_id: product_id.to_string(),
+                },
+                now,
+            )?;
 
-            Ok(())
+            Ok(true)
         }
         None => Err(AppError::SubscriptionInvalidLineItems),
     }
@@ -250,10 +646,12 @@ async fn handle_subscription_renewal(
 
 async fn handle_revoking_user_access(
     conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
-    admin_ic_agent: &ic_agent::Agent,
+    user_info: &dyn UserInfoApi,
     user_id_str: &str,
     purchase_token_param: &str,
     _subscription_response: &GooglePlaySubscriptionResponse,
+    reason: TransitionReason,
+    now: chrono::NaiveDateTime,
 ) -> Result<(), AppError> {
     use crate::schema::purchase_tokens::dsl::*;
 
@@ -266,12 +664,30 @@ async fn handle_revoking_user_access(
     match existing_token {
         Some(token) => {
             // Update existing token with new expiry and status
+            let next_status = TokenStateMachine::next(token.status, reason)?;
+            let token = reserve_token(conn, &token)?;
+
+            revoke_pro_for_subscription(
+                conn,
+                user_info,
+                user_id_str,
+                SubscriptionSource::for_environment(token.environment),
+                purchase_token_param,
+                now,
+            )
+            .await?;
 
-            revoke_yral_pro_plan_access(admin_ic_agent, user_id_str).await?;
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set((status.eq(next_status),))
+            .execute(conn)?;
+            ensure_unchanged(updated, &token)?;
 
-            diesel::update(purchase_tokens.filter(id.eq(&token.id)))
-                .set((status.eq(PurchaseTokenStatus::Expired),))
-                .execute(conn)?;
+            // A restore scheduled by a pause must not bring access back
+            cancel_scheduled_access_changes(conn, purchase_token_param)?;
 
             Ok(())
         }
@@ -281,149 +697,626 @@ async fn handle_revoking_user_access(
     }
 }
 
+/// Keep a purchase whose owner can't be resolved yet instead of failing its notification
+///
+/// A new purchase is stored as a provisional row, granted once a purchase intent or a profile
+/// registration names its owner: by the pending purchase job, or by a verify from that owner.
+/// Verify resolves the owner from Google's ids like this handler, and refuses other callers
+/// with `TokenAlreadyUsed`. Later notifications for a provisional row are acknowledged, the job
+/// applies Google's state as it is by then. Any other notification fails as before.
+fn hold_unowned_purchase(
+    conn: &mut SqliteConnection,
+    app_state: &crate::AppState,
+    notification_type: SubscriptionNotificationType,
+    package_name: &str,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let existing: Option<PurchaseToken> = crate::schema::purchase_tokens::table
+        .filter(crate::schema::purchase_tokens::purchase_token.eq(purchase_token_param))
+        .first(conn)
+        .optional()?;
+
+    match existing {
+        Some(token) if token.provisional_since.is_some() => Ok(()),
+        None if notification_type == SubscriptionNotificationType::Purchased => {
+            let environment = subscription_response.environment();
+            if environment == PurchaseEnvironment::Sandbox
+                && !app_state.feature_flags.is_enabled(HONOR_SANDBOX_PURCHASES)
+            {
+                return Ok(());
+            }
+            println!(
+                "Owner of purchase token {} unknown, storing it provisionally",
+                purchase_token_param
+            );
+            record_provisional_purchase(
+                conn,
+                package_name,
+                purchase_token_param,
+                environment,
+                app_state.clock.now_naive(),
+            )?;
+            Ok(())
+        }
+        _ => Err(AppError::ExternalAccountIdentifiersMissing.into()),
+    }
+}
+
 async fn handle_subscription_notification(
     notification: &crate::types::SubscriptionNotification,
     app_state: &crate::AppState,
     package_name: &str,
+    raw_notification: &str,
+    event_time: Option<chrono::NaiveDateTime>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let notification_type = notification.notification_type;
     let purchase_token = &notification.purchase_token;
     let subscription_id = &notification.subscription_id;
 
     println!(
-        "Subscription notification - Type: {}, Token: {}, ID: {}",
+        "Subscription notification - Type: {:?}, Token: {}, ID: {}",
         notification_type, purchase_token, subscription_id
     );
 
-    // Get user ID from purchase details using obfuscatedAccountId set by client
-    let google_play_subscription_response = fetch_google_play_purchase_details(
+    if let SubscriptionNotificationType::Unknown(unknown_type) = notification_type {
+        println!(
+            "Unknown subscription notification type: {}, storing for inspection",
+            unknown_type
+        );
+        record_unhandled_notification(
+            app_state,
+            "subscription",
+            Some(unknown_type),
+            Some(purchase_token),
+            raw_notification,
+        )?;
+        if let Some(event_time) = event_time {
+            record_event_time(
+                &mut app_state.get_db_connection()?,
+                purchase_token,
+                event_time,
+            )?;
+        }
+        return Ok(());
+    }
+
+    // The notification means the subscription changed, cached details are outdated
+    app_state
+        .google_play
+        .invalidate_subscription(purchase_token)
+        .await;
+
+    // Get user ID from purchase details using the obfuscated ids set by the client
+    let google_play_subscription_response = app_state
+        .google_play
+        .fetch_subscription(package_name, purchase_token)
+        .await?;
+
+    // Looked up with Google too, which must not wait on the transaction below
+    let upgrade_amount = if notification_type == SubscriptionNotificationType::Purchased {
+        upgrade_credits(app_state, package_name, &google_play_subscription_response).await?
+    } else {
+        0
+    };
+
+    // The change and the event time commit together. A notification failing part way leaves
+    // nothing behind, so its redelivery is neither discarded as stale nor refused a transition.
+    let mut conn = app_state.get_db_connection()?;
+    begin_immediate(&mut conn)?;
+    let applied = apply_subscription_notification(
+        &mut conn,
+        notification,
+        app_state,
         package_name,
-        &purchase_token,
-        app_state.google_auth.as_ref(),
+        &google_play_subscription_response,
+        upgrade_amount,
+        event_time,
     )
-    .await?;
+    .await;
+    let intent = finish(&mut conn, applied)?;
 
-    let user_id = google_play_subscription_response
+    // Best effort, failing the notification would not bring the event back on redelivery
+    if let Some(event) = intent {
+        if let Err(e) = app_state.notifier.send(&event).await {
+            sentry::capture_message(
+                &format!(
+                    "Failed to send cancellation intent for purchase token {}: {}",
+                    purchase_token, e
+                ),
+                sentry::Level::Warning,
+            );
+            eprintln!(
+                "Failed to send cancellation intent for purchase token {}: {}",
+                purchase_token, e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply a subscription notification inside the transaction open on `conn`
+///
+/// Returns the cancellation intent to send once the transaction committed.
+#[allow(clippy::too_many_arguments)]
+async fn apply_subscription_notification(
+    conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    notification: &crate::types::SubscriptionNotification,
+    app_state: &crate::AppState,
+    package_name: &str,
+    google_play_subscription_response: &GooglePlaySubscriptionResponse,
+    upgrade_amount: u32,
+    event_time: Option<chrono::NaiveDateTime>,
+) -> Result<Option<BillingEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    let notification_type = notification.notification_type;
+    let purchase_token = &notification.purchase_token;
+    let subscription_id = &notification.subscription_id;
+
+    // Checked before the Google lookup too, but another instance may have applied a newer one
+    if let Some(event_time) = event_time {
+        if is_stale_notification(conn, purchase_token, event_time)? {
+            println!(
+                "Discarding notification for token {} that is not newer than the last applied one",
+                purchase_token
+            );
+            return Ok(None);
+        }
+    }
+
+    let identifiers = google_play_subscription_response
         .external_account_identifiers
-        .clone()
-        .ok_or(AppError::ExternalAccountIdentifiersMissing)?
-        .obfuscated_external_account_id
-        .ok_or(AppError::ExternalAccountIdentifiersMissing)?;
+        .as_ref();
+    let user_id = match resolve_account_id(
+        conn,
+        identifiers.and_then(|ids| ids.obfuscated_external_account_id.as_deref()),
+        identifiers.and_then(|ids| ids.obfuscated_external_profile_id.as_deref()),
+        subscription_id,
+        app_state.clock.now_naive(),
+    ) {
+        Ok(user_id) => user_id,
+        Err(AppError::ExternalAccountIdentifiersMissing) => {
+            hold_unowned_purchase(
+                conn,
+                app_state,
+                notification_type,
+                package_name,
+                purchase_token,
+                google_play_subscription_response,
+            )?;
+            if let Some(event_time) = event_time {
+                record_event_time(conn, purchase_token, event_time)?;
+            }
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     println!("Processing subscription notification for user: {}", user_id);
 
     handle_linked_purchase_token(
-        &mut app_state.get_db_connection()?,
+        conn,
         google_play_subscription_response
             .linked_purchase_token
             .clone(),
     )?;
 
+    // Added last: once the IC added them, a rollback would release the order for a second top-up
+    let mut credits_due: Option<(u32, CreditReason)> = None;
+    let mut intent = None;
     match notification_type {
-        subscription_notification_type::SUBSCRIPTION_PURCHASED => {
+        SubscriptionNotificationType::Purchased => {
             handle_new_subscription_purchase(
-                &mut app_state
-                    .get_db_connection()
-                    .map_err(|_| AppError::DatabaseConnection)?,
-                app_state.google_auth.as_ref(),
-                app_state
-                    .admin_ic_agent
-                    .as_ref()
-                    .ok_or(AppError::AdminIcAgentMissing)?,
+                conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                app_state.feature_flags.is_enabled(HONOR_SANDBOX_PURCHASES),
                 package_name,
                 &user_id,
                 purchase_token,
-                &google_play_subscription_response,
+                google_play_subscription_response,
+                app_state.clock.now_naive(),
             )
             .await?;
+
+            // A purchase replacing another plan's subscription brings the allotment it gains
+            let granted: Option<PurchaseToken> = crate::schema::purchase_tokens::table
+                .filter(crate::schema::purchase_tokens::purchase_token.eq(purchase_token))
+                .filter(
+                    crate::schema::purchase_tokens::status.eq(PurchaseTokenStatus::AccessGranted),
+                )
+                .first(conn)
+                .optional()?;
+            if granted.is_some() {
+                credits_due = Some((upgrade_amount, CreditReason::UpgradeTopup));
+            }
         }
-        subscription_notification_type::SUBSCRIPTION_RENEWED => {
-            handle_subscription_renewal(
-                &mut app_state
-                    .get_db_connection()
-                    .map_err(|_| AppError::DatabaseConnection)?,
-                app_state
-                    .admin_ic_agent
-                    .as_ref()
-                    .ok_or(AppError::AdminIcAgentMissing)?,
+        SubscriptionNotificationType::Renewed => {
+            let renewed = handle_subscription_renewal(
+                conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                package_name,
                 &user_id,
                 purchase_token,
-                &google_play_subscription_response,
+                google_play_subscription_response,
+                true,
+                app_state.clock.now_naive(),
             )
             .await?;
+
+            // The renewed period comes with a fresh credit allotment, added once per order
+            let plan =
+                granting_product(google_play_subscription_response).and_then(plan_for_product);
+            if let (true, Some(plan)) = (renewed, plan) {
+                credits_due = Some((
+                    app_state
+                        .credit_allotments
+                        .credits_for(plan, CreditEvent::Renewal, None),
+                    CreditReason::RenewalTopup,
+                ));
+            }
         }
-        subscription_notification_type::SUBSCRIPTION_CANCELED => {
+        SubscriptionNotificationType::Canceled => {
             println!("Subscription canceled for user: {}", user_id);
-            // we don't need to anything as we will expire the subscriptino on expiry
+            let stored: Option<PurchaseToken> = crate::schema::purchase_tokens::table
+                .filter(crate::schema::purchase_tokens::purchase_token.eq(purchase_token))
+                .first(conn)
+                .optional()?;
+            // access stays until expiry, we only record that it won't renew
+            update_auto_renewing(
+                conn,
+                purchase_token,
+                google_play_subscription_response.auto_renewing(),
+            )?;
+            record_cancellation(
+                conn,
+                purchase_token,
+                google_play_subscription_response,
+                app_state.clock.now_naive(),
+            )?;
+
+            intent = stored.and_then(|token| {
+                cancellation_intent(
+                    &token,
+                    google_play_subscription_response,
+                    app_state.clock.now_naive(),
+                )
+            });
         }
 
-        subscription_notification_type::SUBSCRIPTION_RECOVERED => {
+        SubscriptionNotificationType::Recovered => {
             // in case of recovered we need to grant access again and update the expiry the token was expired
             handle_subscription_renewal(
-                &mut app_state
-                    .get_db_connection()
-                    .map_err(|_| AppError::DatabaseConnection)?,
-                app_state
-                    .admin_ic_agent
-                    .as_ref()
-                    .ok_or(AppError::AdminIcAgentMissing)?,
+                conn,
+                app_state.google_play.as_ref(),
+                &app_state.ack_strategies,
+                app_state.user_info.as_ref(),
+                package_name,
                 &user_id,
                 purchase_token,
-                &google_play_subscription_response,
+                google_play_subscription_response,
+                false,
+                app_state.clock.now_naive(),
             )
             .await?;
         }
-        subscription_notification_type::SUBSCRIPTION_IN_GRACE_PERIOD => {
+        SubscriptionNotificationType::InGracePeriod => {
             println!("Subscription in grace period for user: {}", user_id);
-            //Rignt now we are doing nothing about it
+            // Access stays until the grace period ends, the user is reminded to fix payment
+            start_grace_reminders(
+                conn,
+                purchase_token,
+                &user_id,
+                google_play_subscription_response,
+                app_state.clock.now_naive(),
+            )?;
         }
-        subscription_notification_type::SUBSCRIPTION_RESTARTED => {
+        SubscriptionNotificationType::Restarted => {
             println!("Subscription restarted for user: {}", user_id);
-            // it is automatically handled in renewal flow
+            // access is handled in renewal flow, we only record that it will renew again
+            update_auto_renewing(
+                conn,
+                purchase_token,
+                google_play_subscription_response.auto_renewing(),
+            )?;
         }
-        subscription_notification_type::SUBSCRIPTION_PRICE_CHANGE_CONFIRMED => {
+        SubscriptionNotificationType::PriceChangeConfirmed => {
             println!("Subscription price change confirmed for user: {}", user_id);
             // right now we are not doing anything about it
         }
-        subscription_notification_type::SUBSCRIPTION_DEFERRED => {
+        SubscriptionNotificationType::Deferred => {
             println!("Subscription deferred for user: {}", user_id);
             // not doing anything about it right now
         }
-        subscription_notification_type::SUBSCRIPTION_PAUSED => {
-            // we are not supporting subscription pause right now
-            println!("Subscription paused for user: {}", user_id);
-        }
-        subscription_notification_type::SUBSCRIPTION_PAUSE_SCHEDULE_CHANGED => {
-            println!("Subscription pause schedule changed for user: {}", user_id);
-            // we are not supporting subscription pause right now
+        // Both carry the pause window, in case the schedule change was missed
+        SubscriptionNotificationType::Paused
+        | SubscriptionNotificationType::PauseScheduleChanged => {
+            println!(
+                "Subscription pause {:?} for user: {}",
+                notification_type, user_id
+            );
+            apply_pause_schedule(
+                conn,
+                &user_id,
+                purchase_token,
+                google_play_subscription_response,
+                app_state.clock.now_naive(),
+            )?;
         }
-        subscription_notification_type::SUBSCRIPTION_REVOKED
-        | subscription_notification_type::SUBSCRIPTION_EXPIRED
-        | subscription_notification_type::SUBSCRIPTION_ON_HOLD => {
+        SubscriptionNotificationType::Revoked
+        | SubscriptionNotificationType::Expired
+        | SubscriptionNotificationType::OnHold => {
             handle_revoking_user_access(
-                &mut app_state
-                    .get_db_connection()
-                    .map_err(|_| AppError::DatabaseConnection)?,
-                app_state
-                    .admin_ic_agent
-                    .as_ref()
-                    .ok_or(AppError::AdminIcAgentMissing)?,
+                conn,
+                app_state.user_info.as_ref(),
                 &user_id,
                 purchase_token,
-                &google_play_subscription_response,
+                google_play_subscription_response,
+                if notification_type == SubscriptionNotificationType::Revoked {
+                    TransitionReason::Revoked
+                } else {
+                    TransitionReason::Lapsed
+                },
+                app_state.clock.now_naive(),
             )
             .await?;
             println!("Subscription revoked for user: {}", user_id);
+
+            // Unlike expiry or a failed renewal, a revocation comes with a refund
+            if notification_type == SubscriptionNotificationType::Revoked {
+                record_risk_event(
+                    conn,
+                    &user_id,
+                    RiskEvent::Revoked,
+                    app_state.risk_threshold,
+                    app_state.clock.now_naive(),
+                )?;
+            }
+        }
+        SubscriptionNotificationType::ItemsChanged => {
+            // An added or removed line item can move when access ends
+            if let Some(expiry) = access_expiry(google_play_subscription_response) {
+                update_access_expiry(conn, purchase_token, expiry)?;
+            }
         }
-        _ => {
+        SubscriptionNotificationType::PriceChangeUpdated
+        | SubscriptionNotificationType::PendingPurchaseCanceled => {
             println!(
-                "Unknown subscription notification type: {} for user: {}",
+                "Subscription notification {:?} for user: {}, no action needed",
                 notification_type, user_id
             );
         }
+        // Recorded before fetching purchase details
+        SubscriptionNotificationType::Unknown(_) => {}
     }
 
-    Ok(())
+    // Payment went through or no longer can, reminders to fix it would be wrong
+    if matches!(
+        notification_type,
+        SubscriptionNotificationType::Renewed
+            | SubscriptionNotificationType::Recovered
+            | SubscriptionNotificationType::Canceled
+            | SubscriptionNotificationType::OnHold
+            | SubscriptionNotificationType::Revoked
+            | SubscriptionNotificationType::Expired
+    ) {
+        stop_grace_reminders(conn, purchase_token, app_state.clock.now_naive())?;
+    }
+
+    // Add-ons may be added, removed or renewed with any notification
+    record_line_items(
+        conn,
+        purchase_token,
+        google_play_subscription_response,
+        app_state.clock.now_naive(),
+    )?;
+
+    // Each purchase and renewal carries a new order id for finance reconciliation
+    if matches!(
+        notification_type,
+        SubscriptionNotificationType::Purchased
+            | SubscriptionNotificationType::Renewed
+            | SubscriptionNotificationType::Recovered
+    ) {
+        record_order(
+            conn,
+            purchase_token,
+            google_play_subscription_response,
+            app_state.clock.now_naive(),
+        )?;
+    }
+
+    // Payment went through or no longer can, reminders to fix it would be wrong
+    if matches!(
+        notification_type,
+        SubscriptionNotificationType::Renewed
+            | SubscriptionNotificationType::Recovered
+            | SubscriptionNotificationType::Canceled
+            | SubscriptionNotificationType::OnHold
+            | SubscriptionNotificationType::Revoked
+            | SubscriptionNotificationType::Expired
+    ) {
+        stop_grace_reminders(conn, purchase_token, app_state.clock.now_naive())?;
+    }
+
+    // Add-ons may be added, removed or renewed with any notification
+    record_line_items(
+        conn,
+        purchase_token,
+        google_play_subscription_response,
+        app_state.clock.now_naive(),
+    )?;
+
+    // Each purchase and renewal carries a new order id for finance reconciliation
+    if matches!(
+        notification_type,
+        SubscriptionNotificationType::Purchased
+            | SubscriptionNotificationType::Renewed
+            | SubscriptionNotificationType::Recovered
+    ) {
+        record_order(
+            conn,
+            purchase_token,
+            google_play_subscription_response,
+            app_state.clock.now_naive(),
+        )?;
+    }
+
+    if let (Some((amount, reason)), Some(order_id)) = (
+        credits_due,
+        google_play_subscription_response.latest_order_id.as_deref(),
+    ) {
+        top_up_credits(app_state, conn, &user_id, order_id, amount, reason).await?;
+    }
+
+    if let Some(event_time) = event_time {
+        record_event_time(conn, purchase_token, event_time)?;
+    }
+
+    Ok(intent)
+}
+
+/// Win-back event for a cancellation, if it turned off renewal of a token that still has access
+///
+/// `token` is the stored token from before the cancellation was applied, so a redelivered or
+/// repeated cancellation doesn't produce a second event.
+pub fn cancellation_intent(
+    token: &PurchaseToken,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: chrono::NaiveDateTime,
+) -> Option<BillingEvent> {
+    let turned_off = token.auto_renewing && !subscription_response.auto_renewing();
+    if !turned_off || token.status != PurchaseTokenStatus::AccessGranted || token.expiry_at <= now {
+        return None;
+    }
+
+    Some(BillingEvent::CancellationIntent {
+        user_id: token.user_id.clone(),
+        purchase_token: token.purchase_token.clone(),
+        product_id: granting_product(subscription_response)
+            .unwrap_or_default()
+            .to_string(),
+        expires_at: token.expiry_at.and_utc().to_rfc3339(),
+    })
+}
+
+/// Move the expiry of a token that grants access, e.g. after its line items changed
+fn update_access_expiry(
+    database_conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    purchase_token_param: &str,
+    expiry: chrono::NaiveDateTime,
+) -> Result<(), AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let Some(token) = current_token(database_conn, purchase_token_param)?
+        .filter(|token| token.status == PurchaseTokenStatus::AccessGranted)
+    else {
+        return Ok(());
+    };
+
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set(expiry_at.eq(expiry))
+    .execute(database_conn)?;
+    ensure_unchanged(updated, &token)
+}
+
+fn update_auto_renewing(
+    database_conn: &mut PooledConnection<ConnectionManager<SqliteConnection>>,
+    purchase_token_param: &str,
+    auto_renews: bool,
+) -> Result<(), AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let Some(token) = current_token(database_conn, purchase_token_param)? else {
+        return Ok(());
+    };
+
+    // A fresh expiry reminder is due whenever the subscription stops renewing again
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set((
+        auto_renewing.eq(auto_renews),
+        notified_at.eq(None::<chrono::NaiveDateTime>),
+    ))
+    .execute(database_conn)?;
+    ensure_unchanged(updated, &token)
+}
+
+/// Store the pause window Google reports and schedule access to follow it
+///
+/// Pro is revoked through the access outbox when the pause starts and granted again when it
+/// ends. A removed pause clears the window and drops the changes scheduled for it.
+fn apply_pause_schedule(
+    conn: &mut SqliteConnection,
+    user_id_str: &str,
+    purchase_token_param: &str,
+    subscription_response: &GooglePlaySubscriptionResponse,
+    now: chrono::NaiveDateTime,
+) -> Result<(), AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let token: PurchaseToken = purchase_tokens
+        .filter(purchase_token.eq(purchase_token_param))
+        .first(conn)
+        .optional()?
+        .ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+    let window = subscription_response.pause_window();
+    if window == token.paused_from.zip(token.resumes_at) {
+        return Ok(());
+    }
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set((
+            paused_from.eq(window.map(|(start, _)| start)),
+            resumes_at.eq(window.map(|(_, end)| end)),
+        ))
+        .execute(conn)?;
+        ensure_unchanged(updated, &token)?;
+
+        cancel_scheduled_access_changes(conn, purchase_token_param)?;
+
+        // A window that already ended needs no change
+        if let Some((start, end)) = window.filter(|(_, end)| *end > now) {
+            let product_id = granting_product(subscription_response)
+                .ok_or(AppError::SubscriptionInvalidLineItems)?;
+            schedule_access_change(
+                conn,
+                user_id_str,
+                OutboxAction::RevokePro,
+                None,
+                purchase_token_param,
+                start,
+                now,
+            )?;
+            schedule_access_change(
+                conn,
+                user_id_str,
+                OutboxAction::GrantPro,
+                Some(product_id),
+                purchase_token_param,
+                end,
+                now,
+            )?;
+        }
+
+        Ok(())
+    })
 }
 
 fn handle_linked_purchase_token(
@@ -432,11 +1325,21 @@ fn handle_linked_purchase_token(
 ) -> Result<(), AppError> {
     use crate::schema::purchase_tokens::dsl::*;
 
-    if let Some(token) = linked_purchase_token {
-        diesel::update(purchase_tokens.filter(purchase_token.eq(&token)))
-            .set(status.eq(PurchaseTokenStatus::Expired))
-            .execute(database_conn)
-            .map_err(|_| AppError::DatabaseConnection)?;
+    if let Some(linked) = linked_purchase_token {
+        let Some(token) = current_token(database_conn, &linked)? else {
+            return Ok(());
+        };
+        let next_status = TokenStateMachine::next(token.status, TransitionReason::Superseded)?;
+
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set(status.eq(next_status))
+        .execute(database_conn)
+        .map_err(|_| AppError::DatabaseConnection)?;
+        ensure_unchanged(updated, &token)?;
     }
 
     Ok(())
@@ -445,53 +1348,186 @@ fn handle_linked_purchase_token(
 async fn handle_one_time_product_notification(
     notification: &OneTimeProductNotification,
     app_state: &crate::AppState,
+    raw_notification: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let notification_type = notification.notification_type;
     let purchase_token_value = &notification.purchase_token;
 
     println!(
-        "One-time product notification - Type: {}, SKU: {}, Token: {}",
+        "One-time product notification - Type: {:?}, SKU: {}, Token: {}",
         notification_type, notification.sku, purchase_token_value
     );
 
     match notification_type {
-        one_time_product_notification_type::ONE_TIME_PRODUCT_PURCHASED => {
+        OneTimeProductNotificationType::Purchased => {
             // Grant is initiated by the client calling /google/chat-access/grant.
             // Nothing to do here as we need bot_id from the client to create the grant.
             println!("One-time product purchased, waiting for client to call grant endpoint");
         }
-        one_time_product_notification_type::ONE_TIME_PRODUCT_CANCELED => {
-            use crate::schema::bot_chat_access::dsl;
-            use crate::types::BotChatAccessStatus;
+        OneTimeProductNotificationType::Canceled => {
+            cancel_bot_chat_access(app_state, purchase_token_value)?;
+        }
+        OneTimeProductNotificationType::Unknown(unknown_type) => {
+            println!(
+                "Unknown one-time product notification type: {}, storing for inspection",
+                unknown_type
+            );
+            record_unhandled_notification(
+                app_state,
+                "one_time_product",
+                Some(unknown_type),
+                Some(purchase_token_value),
+                raw_notification,
+            )?;
+        }
+    }
 
-            let mut conn = app_state.get_db_connection()?;
-            let now = chrono::Utc::now().naive_utc();
+    Ok(())
+}
 
-            diesel::update(
-                dsl::bot_chat_access.filter(dsl::purchase_token.eq(purchase_token_value)),
-            )
-            .set((
-                dsl::status.eq(BotChatAccessStatus::Canceled),
-                dsl::updated_at.eq(now),
-            ))
-            .execute(&mut conn)?;
+async fn handle_voided_purchase_notification(
+    notification: &VoidedPurchaseNotification,
+    app_state: &crate::AppState,
+    raw_notification: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let purchase_token_value = &notification.purchase_token;
 
-            println!(
-                "Canceled bot chat access for purchase token: {}",
-                purchase_token_value
-            );
+    println!(
+        "Voided purchase notification - Product type: {:?}, Order: {}, Token: {}",
+        notification.product_type, notification.order_id, purchase_token_value
+    );
+
+    app_state
+        .google_play
+        .invalidate_subscription(purchase_token_value)
+        .await;
+
+    match notification.product_type {
+        VoidedProductType::Subscription => {
+            revoke_voided_subscription(app_state, purchase_token_value).await?;
+        }
+        VoidedProductType::OneTime => {
+            cancel_bot_chat_access(app_state, purchase_token_value)?;
         }
-        _ => {
+        VoidedProductType::Unknown(unknown_type) => {
             println!(
-                "Unknown one-time product notification type: {}",
-                notification_type
+                "Unknown voided product type: {}, storing for inspection",
+                unknown_type
             );
+            record_unhandled_notification(
+                app_state,
+                "voided_purchase",
+                Some(unknown_type),
+                Some(purchase_token_value),
+                raw_notification,
+            )?;
         }
     }
 
     Ok(())
 }
 
+/// Revoke access for a refunded or charged back subscription
+///
+/// The purchase is void so Google no longer reports the user; the owner comes from our records.
+async fn revoke_voided_subscription(
+    app_state: &crate::AppState,
+    purchase_token_param: &str,
+) -> Result<(), AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let existing_token: Option<PurchaseToken> = purchase_tokens
+        .filter(purchase_token.eq(purchase_token_param))
+        .first(&mut conn)
+        .optional()?;
+
+    let Some(token) = existing_token else {
+        // Never verified with us, so there is no access to revoke
+        println!(
+            "Voided subscription token {} is unknown, nothing to revoke",
+            purchase_token_param
+        );
+        return Ok(());
+    };
+
+    let next_status = TokenStateMachine::next(token.status, TransitionReason::Voided)?;
+    let token = reserve_token(&mut conn, &token)?;
+    if token.status == PurchaseTokenStatus::AccessGranted {
+        revoke_pro_for_subscription(
+            &mut conn,
+            app_state.user_info.as_ref(),
+            &token.user_id,
+            SubscriptionSource::for_environment(token.environment),
+            &token.purchase_token,
+            app_state.clock.now_naive(),
+        )
+        .await?;
+    }
+
+    let updated = diesel::update(
+        purchase_tokens
+            .filter(id.eq(&token.id))
+            .filter(version.eq(token.version)),
+    )
+    .set(status.eq(next_status))
+    .execute(&mut conn)?;
+    ensure_unchanged(updated, &token)?;
+    record_risk_event(
+        &mut conn,
+        &token.user_id,
+        RiskEvent::Voided,
+        app_state.risk_threshold,
+        app_state.clock.now_naive(),
+    )?;
+
+    println!("Revoked voided subscription for user: {}", token.user_id);
+
+    Ok(())
+}
+
+fn cancel_bot_chat_access(
+    app_state: &crate::AppState,
+    purchase_token_value: &str,
+) -> Result<(), AppError> {
+    use crate::schema::bot_chat_access::dsl;
+    use crate::types::BotChatAccessStatus;
+
+    let mut conn = app_state.get_db_connection()?;
+    let now = app_state.clock.now_naive();
+
+    let owner: Option<String> = dsl::bot_chat_access
+        .filter(dsl::purchase_token.eq(purchase_token_value))
+        .select(dsl::user_id)
+        .first(&mut conn)
+        .optional()?;
+
+    diesel::update(dsl::bot_chat_access.filter(dsl::purchase_token.eq(purchase_token_value)))
+        .set((
+            dsl::status.eq(BotChatAccessStatus::Canceled),
+            dsl::updated_at.eq(now),
+        ))
+        .execute(&mut conn)?;
+
+    if let Some(owner) = owner {
+        record_risk_event(
+            &mut conn,
+            &owner,
+            RiskEvent::Voided,
+            app_state.risk_threshold,
+            now,
+        )?;
+    }
+
+    println!(
+        "Canceled bot chat access for purchase token: {}",
+        purchase_token_value
+    );
+
+    Ok(())
+}
+
 async fn handle_test_notification(
     notification: &crate::types::TestNotification,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::model::RevenueEvent;
+use crate::routes::orders::day_range;
+use crate::rtdn_lag::rtdn_lag_stats;
+use crate::types::{
+    AdminStatsResponse, ApiResponse, EmptyData, RevenueTotal, RtdnLagStatsResponse,
+};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// First day to include (UTC)
+    pub from: NaiveDate,
+    /// Last day to include (UTC)
+    pub to: NaiveDate,
+}
+
+/// Sum revenue events per day, region and currency
+fn revenue_totals(events: &[RevenueEvent]) -> Vec<RevenueTotal> {
+    let mut buckets: BTreeMap<(NaiveDate, Option<String>, String), (i64, i64)> = BTreeMap::new();
+    for event in events {
+        let bucket = buckets
+            .entry((
+                event.recorded_at.date(),
+                event.region_code.clone(),
+                event.currency_code.clone(),
+            ))
+            .or_default();
+        bucket.0 += 1;
+        bucket.1 += event.price_micros;
+    }
+
+    buckets
+        .into_iter()
+        .map(
+            |((day, region_code, currency_code), (orders, total_micros))| RevenueTotal {
+                day: day.to_string(),
+                region_code,
+                currency_code,
+                orders,
+                total_micros,
+            },
+        )
+        .collect()
+}
+
+/// Billing stats for a date range, currently revenue per day and region
+///
+/// Requires a JWT with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    params(
+        ("from" = String, Query, description = "First day to include, YYYY-MM-DD (UTC)"),
+        ("to" = String, Query, description = "Last day to include, YYYY-MM-DD (UTC)"),
+    ),
+    responses(
+        (status = 200, description = "Stats for the date range", body = ApiResponse<AdminStatsResponse>),
+        (status = 400, description = "Invalid date range", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_admin_stats(
+    State(app_state): State<AppState>,
+    Query(params): Query<StatsQuery>,
+) -> Result<Json<ApiResponse<AdminStatsResponse>>, AppError> {
+    use crate::schema::revenue_events::dsl::*;
+
+    let (start, end) = day_range(params.from, params.to)?;
+
+    let mut conn = app_state.get_db_connection()?;
+    let events: Vec<RevenueEvent> = revenue_events
+        .filter(recorded_at.ge(start))
+        .filter(recorded_at.lt(end))
+        .load(&mut conn)?;
+
+    Ok(Json(ApiResponse::success(AdminStatsResponse {
+        from: params.from.to_string(),
+        to: params.to.to_string(),
+        revenue: revenue_totals(&events),
+    })))
+}
+
+/// Hours of RTDN processing covered by `/admin/stats/rtdn-lag`
+pub const RTDN_LAG_WINDOW_HOURS: i64 = 24;
+
+/// p50 and p95 RTDN processing lag per notification type over the last 24 hours
+///
+/// Shows whether access changes trail the Google events behind them. Requires a JWT with
+/// the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/admin/stats/rtdn-lag",
+    responses(
+        (status = 200, description = "Lag percentiles per notification type", body = ApiResponse<RtdnLagStatsResponse>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "JWT lacks the admin scope or a subject"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Admin",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_rtdn_lag_stats(
+    State(app_state): State<AppState>,
+) -> Result<Json<ApiResponse<RtdnLagStatsResponse>>, AppError> {
+    let since = app_state.clock.now_naive() - chrono::Duration::hours(RTDN_LAG_WINDOW_HOURS);
+    let mut conn = app_state.get_db_connection()?;
+
+    Ok(Json(ApiResponse::success(RtdnLagStatsResponse {
+        since: since.and_utc().to_rfc3339(),
+        types: rtdn_lag_stats(&mut conn, since)?,
+    })))
+}
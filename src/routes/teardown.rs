@@ -0,0 +1,220 @@
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::token_versions::{ensure_unchanged, reserve_token};
+use crate::error::{AppError, AppResult};
+use crate::integrations::google_play::GooglePlayApi;
+use crate::jobs::access_outbox::enqueue_access_change;
+use crate::model::{AdminAuditEntry, PurchaseToken};
+use crate::service_auth::Caller;
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{
+    ApiResponse, AuditAction, EmptyData, OutboxAction, PurchaseEnvironment, PurchaseTokenStatus,
+    TeardownUserRequest, TeardownUserResponse,
+};
+use crate::user_id::normalize_user_id;
+use crate::AppState;
+
+/// Turn off auto-renew of one Google subscription
+///
+/// Google's cancel call needs the subscription id, which only the purchase details carry.
+async fn cancel_renewal(google_play: &dyn GooglePlayApi, token: &PurchaseToken) -> AppResult<()> {
+    let package_name = token.package_name.as_deref().ok_or_else(|| {
+        AppError::InternalError("Package name unknown, cannot cancel".to_string())
+    })?;
+
+    let subscription_response = google_play
+        .fetch_subscription(package_name, &token.purchase_token)
+        .await?;
+    let subscription_id = subscription_response
+        .line_items
+        .first()
+        .map(|item| item.product_id.as_str())
+        .ok_or(AppError::SubscriptionInvalidLineItems)?;
+
+    google_play
+        .cancel_subscription(package_name, subscription_id, &token.purchase_token)
+        .await
+}
+
+/// Tear down everything that keeps a user subscribed
+///
+/// Auto-renewing Google subscriptions are canceled where Google accepts it, the user's live
+/// purchases are marked expired and Pro is revoked through the access outbox. A cancellation
+/// that fails is logged and counted, the rest of the teardown still happens.
+pub async fn teardown_user(
+    conn: &mut SqliteConnection,
+    google_play: &dyn GooglePlayApi,
+    operator: &str,
+    user_id_param: &str,
+    request: &TeardownUserRequest,
+    now: NaiveDateTime,
+) -> AppResult<TeardownUserResponse> {
+    use crate::schema::{admin_audit_log, purchase_tokens};
+
+    if request.reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "A reason is required to tear down a user".to_string(),
+        ));
+    }
+
+    let loaded: Vec<PurchaseToken> = purchase_tokens::table
+        .filter(purchase_tokens::user_id.eq(user_id_param))
+        .filter(purchase_tokens::status.eq_any([
+            PurchaseTokenStatus::AccessGranted,
+            PurchaseTokenStatus::Pending,
+        ]))
+        .load(conn)?;
+    for token in &loaded {
+        TokenStateMachine::next(token.status, TransitionReason::TornDown)?;
+    }
+    // Taken before Google is called, a purchase changing meanwhile fails the teardown
+    let mut live = Vec::with_capacity(loaded.len());
+    for token in &loaded {
+        live.push(reserve_token(conn, token)?);
+    }
+    let expired_status = TokenStateMachine::transition(TransitionReason::TornDown).to;
+
+    let mut canceled: Vec<&PurchaseToken> = Vec::new();
+    let mut failed = 0;
+    // Manual grants have nothing to cancel with Google
+    for token in live
+        .iter()
+        .filter(|token| token.auto_renewing && token.environment != PurchaseEnvironment::Manual)
+    {
+        match cancel_renewal(google_play, token).await {
+            Ok(()) => canceled.push(token),
+            Err(e) => {
+                failed += 1;
+                eprintln!(
+                    "Failed to cancel renewal of purchase token {} during teardown of user {}: {}",
+                    token.purchase_token, user_id_param, e
+                );
+            }
+        }
+    }
+
+    let entry = AdminAuditEntry::new(
+        operator.to_string(),
+        AuditAction::TeardownUser,
+        user_id_param.to_string(),
+        request.reason.clone(),
+        None,
+        now,
+    );
+    let cancellations: Vec<AdminAuditEntry> = canceled
+        .iter()
+        .map(|token| {
+            AdminAuditEntry::new(
+                operator.to_string(),
+                AuditAction::CancelSubscription,
+                user_id_param.to_string(),
+                request.reason.clone(),
+                Some(token.purchase_token.clone()),
+                now,
+            )
+        })
+        .collect();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        for token in &live {
+            let renewal_canceled = canceled.iter().any(|other| other.id == token.id);
+            let updated = diesel::update(
+                purchase_tokens::table
+                    .filter(purchase_tokens::id.eq(&token.id))
+                    .filter(purchase_tokens::version.eq(token.version)),
+            )
+            .set((
+                purchase_tokens::status.eq(expired_status),
+                purchase_tokens::auto_renewing.eq(token.auto_renewing && !renewal_canceled),
+            ))
+            .execute(conn)?;
+            ensure_unchanged(updated, token)?;
+        }
+
+        // Pro may also come from a grant without a stored purchase, so it is always revoked
+        enqueue_access_change(
+            conn,
+            user_id_param,
+            OutboxAction::RevokePro,
+            None,
+            None,
+            now,
+        )?;
+
+        diesel::insert_into(admin_audit_log::table)
+            .values(&cancellations)
+            .execute(conn)?;
+        diesel::insert_into(admin_audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    println!(
+        "Tore down subscriptions of user {} for {}: {} canceled, {} failed, {} expired",
+        user_id_param,
+        operator,
+        canceled.len(),
+        failed,
+        live.len()
+    );
+
+    Ok(TeardownUserResponse {
+        audit_id: entry.id,
+        user_id: entry.user_id,
+        operator: entry.operator,
+        canceled_subscriptions: canceled.len(),
+        failed_cancellations: failed,
+        expired_purchases: live.len(),
+    })
+}
+
+/// Tear down a user's subscription state, e.g. when their account is deleted
+///
+/// Cancels Google auto-renew where possible, expires the user's purchases, revokes Pro on the
+/// IC and records the teardown in the audit log.
+///
+/// Requires an HMAC-signed request from an internal service
+#[utoipa::path(
+    post,
+    path = "/internal/users/{user_id}/teardown",
+    params(
+        ("user_id" = String, Path, description = "User whose subscriptions are torn down"),
+    ),
+    request_body = TeardownUserRequest,
+    responses(
+        (status = 200, description = "Subscription state torn down", body = ApiResponse<TeardownUserResponse>),
+        (status = 400, description = "Missing reason", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Missing or invalid service signature"),
+        (status = 429, description = "Internal service exceeded its rate limit"),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Internal",
+    security(
+        ("service_hmac" = [])
+    )
+)]
+pub async fn teardown_user_subscriptions(
+    State(app_state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<TeardownUserRequest>,
+) -> Result<Json<ApiResponse<TeardownUserResponse>>, AppError> {
+    let user_id = normalize_user_id(&user_id);
+    let mut conn = app_state.get_db_connection()?;
+
+    let response = teardown_user(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &caller.label(),
+        &user_id,
+        &payload,
+        app_state.clock.now_naive(),
+    )
+    .await?;
+
+    Ok(Json(ApiResponse::success(response)))
+}
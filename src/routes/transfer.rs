@@ -0,0 +1,159 @@
+use axum::{extract::State, Extension, Json};
+use diesel::prelude::*;
+
+use crate::{
+    auth::Claims,
+    consts::YRAL_PRO_PLAN_PRODUCT_ID,
+    db::token_versions::{ensure_unchanged, reserve_token},
+    error::AppError,
+    line_items::stored_granting_product,
+    model::{PurchaseToken, TokenTransfer},
+    routes::user_tokens::ensure_owner,
+    subscriptions::{
+        grant_pro_for_subscription, replace_duplicate_tokens, revoke_pro_for_subscription,
+    },
+    types::{
+        ApiResponse, EmptyData, PurchaseTokenStatus, SubscriptionSource, TransferTokensRequest,
+        TransferTokensResponse,
+    },
+    user_id::canonical_user_id,
+    AppState,
+};
+
+/// Move all purchase tokens from one user to another after an account merge
+///
+/// Requires a JWT whose `sub` is the source user, or one with the `billing:admin` scope
+#[utoipa::path(
+    post,
+    path = "/google/transfer",
+    request_body = TransferTokensRequest,
+    responses(
+        (status = 200, description = "Purchase tokens transferred successfully", body = ApiResponse<TransferTokensResponse>),
+        (status = 400, description = "Invalid request or no tokens to transfer", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "Caller is not the source user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Token Transfer",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn transfer_purchase_tokens(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut payload): Json<TransferTokensRequest>,
+) -> Result<Json<ApiResponse<TransferTokensResponse>>, AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+    use crate::schema::token_transfers;
+
+    payload.from_user_id = canonical_user_id(&payload.from_user_id)?;
+    payload.to_user_id = canonical_user_id(&payload.to_user_id)?;
+    ensure_owner(&claims, &payload.from_user_id)?;
+    if payload.from_user_id == payload.to_user_id {
+        return Err(AppError::BadRequest(
+            "Source and destination user must differ".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.get_db_connection()?;
+    let now = app_state.clock.now_naive();
+
+    let tokens: Vec<PurchaseToken> = purchase_tokens
+        .filter(user_id.eq(&payload.from_user_id))
+        .load(&mut conn)?;
+
+    if tokens.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "No purchase tokens found for user {}",
+            payload.from_user_id
+        )));
+    }
+
+    // Duplicates are replaced as they appear, so at most one token grants access
+    let granting = tokens
+        .iter()
+        .filter(|token| token.status == PurchaseTokenStatus::AccessGranted && token.expiry_at > now)
+        .max_by_key(|token| token.expiry_at);
+    let pro_access_moved = granting.is_some();
+
+    // Taken before access moves on the IC, a token changing meanwhile fails the transfer
+    let mut reserved = Vec::with_capacity(tokens.len());
+    for token in &tokens {
+        reserved.push(reserve_token(&mut conn, token)?);
+    }
+
+    // Move access on the IC before the tokens, so a failed DB write can simply be retried.
+    // Either user may keep or already have Pro through another subscription.
+    if let Some(token) = granting {
+        // Tokens recorded before line items were stored are all Pro purchases
+        let product_id = stored_granting_product(&mut conn, &token.purchase_token)?
+            .unwrap_or_else(|| YRAL_PRO_PLAN_PRODUCT_ID.to_string());
+        let source = SubscriptionSource::for_environment(token.environment);
+        revoke_pro_for_subscription(
+            &mut conn,
+            app_state.user_info.as_ref(),
+            &payload.from_user_id,
+            source,
+            &token.purchase_token,
+            now,
+        )
+        .await?;
+        grant_pro_for_subscription(
+            &mut conn,
+            app_state.user_info.as_ref(),
+            &product_id,
+            &payload.to_user_id,
+            source,
+            &token.purchase_token,
+            now,
+        )
+        .await?;
+    }
+
+    let transfers: Vec<TokenTransfer> = tokens
+        .iter()
+        .map(|token| {
+            TokenTransfer::new(
+                token.purchase_token.clone(),
+                payload.from_user_id.clone(),
+                payload.to_user_id.clone(),
+                now,
+            )
+        })
+        .collect();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        for token in &reserved {
+            let updated = diesel::update(
+                purchase_tokens
+                    .filter(id.eq(&token.id))
+                    .filter(version.eq(token.version)),
+            )
+            .set(user_id.eq(&payload.to_user_id))
+            .execute(conn)?;
+            ensure_unchanged(updated, token)?;
+        }
+
+        diesel::insert_into(token_transfers::table)
+            .values(&transfers)
+            .execute(conn)?;
+
+        // The destination user may now hold two tokens granting access
+        replace_duplicate_tokens(conn, &payload.to_user_id)?;
+
+        Ok(())
+    })?;
+
+    println!(
+        "Transferred {} purchase tokens from user {} to user {}",
+        tokens.len(),
+        payload.from_user_id,
+        payload.to_user_id
+    );
+
+    Ok(Json(ApiResponse::success(TransferTokensResponse {
+        transferred: tokens.len(),
+        pro_access_moved,
+    })))
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+use crate::auth::{Claims, ADMIN_SCOPE};
+use crate::db::pagination::{before_cursor, decode_cursor, into_page, page_limit, Cursor};
+use crate::error::{AppError, AppResult};
+use crate::model::{PurchaseToken, SubscriptionEvent};
+use crate::plans::plan_for_product;
+use crate::types::{
+    ApiResponse, BillingHistoryEntry, EmptyData, PaginatedResponse, PurchaseTokenStatus,
+    UserTokenResponse,
+};
+use crate::user_id::{canonical_user_id, normalize_user_id};
+use crate::AppState;
+
+/// Purchases per page unless `limit` says otherwise
+pub const DEFAULT_USER_TOKENS_LIMIT: i64 = 20;
+/// Most purchases one page may carry
+pub const MAX_USER_TOKENS_LIMIT: i64 = 100;
+/// History entries returned with each purchase
+pub const USER_TOKEN_HISTORY_LIMIT: usize = 5;
+
+#[derive(Deserialize)]
+pub struct UserTokensQuery {
+    /// Only purchases with this status
+    pub status: Option<PurchaseTokenStatus>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Refuse callers other than `user` itself, operators with the admin scope may look anyone up
+pub(crate) fn ensure_owner(claims: &Claims, user: &str) -> AppResult<()> {
+    if claims.has_scope(ADMIN_SCOPE) {
+        return Ok(());
+    }
+    match claims.sub.as_deref().map(normalize_user_id) {
+        Some(caller) if caller == user => Ok(()),
+        _ => Err(AppError::NotOwner),
+    }
+}
+
+/// Latest history entries of each of `tokens`, newest first
+fn token_histories(
+    conn: &mut SqliteConnection,
+    tokens: &[PurchaseToken],
+) -> AppResult<HashMap<String, Vec<SubscriptionEvent>>> {
+    use crate::schema::subscription_events::dsl::*;
+
+    let events: Vec<SubscriptionEvent> = subscription_events
+        .filter(purchase_token.eq_any(tokens.iter().map(|token| &token.purchase_token)))
+        .order((occurred_at.desc(), id.desc()))
+        .load(conn)?;
+
+    let mut histories: HashMap<String, Vec<SubscriptionEvent>> = HashMap::new();
+    for event in events {
+        let history = histories.entry(event.purchase_token.clone()).or_default();
+        if history.len() < USER_TOKEN_HISTORY_LIMIT {
+            history.push(event);
+        }
+    }
+    Ok(histories)
+}
+
+fn user_token_response(token: PurchaseToken, history: Vec<SubscriptionEvent>) -> UserTokenResponse {
+    let product_id = history.first().map(|event| event.product_id.clone());
+    UserTokenResponse {
+        id: token.id,
+        status: token.status,
+        plan: product_id.as_deref().and_then(plan_for_product),
+        product_id,
+        expiry_at: token.expiry_at.and_utc().to_rfc3339(),
+        auto_renewing: token.auto_renewing,
+        environment: token.environment,
+        created_at: token.created_at.and_utc().to_rfc3339(),
+        history: history
+            .into_iter()
+            .map(|event| BillingHistoryEntry {
+                kind: event.kind,
+                order_id: event.order_id,
+                plan: plan_for_product(&event.product_id),
+                product_id: event.product_id,
+                occurred_at: event.occurred_at.and_utc().to_rfc3339(),
+            })
+            .collect(),
+    }
+}
+
+/// List a user's purchases, newest first, with their latest history
+///
+/// Pages are `limit` purchases long (20 by default, at most 100); pass `next_cursor` back as
+/// `cursor` for the next one. Each purchase carries its last 5 history entries. Purchase
+/// tokens are never included.
+///
+/// Requires a JWT whose `sub` is the user, or one with the `billing:admin` scope
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/tokens",
+    params(
+        ("user_id" = String, Path, description = "User principal, must be the caller's"),
+        ("status" = Option<PurchaseTokenStatus>, Query, description = "Only purchases with this status"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Most purchases to return, 20 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "A page of the user's purchases", body = ApiResponse<PaginatedResponse<UserTokenResponse>>),
+        (status = 400, description = "Invalid user id, cursor or limit", body = ApiResponse<EmptyData>),
+        (status = 401, description = "Unauthorized - Invalid or missing JWT token"),
+        (status = 403, description = "The JWT belongs to another user", body = ApiResponse<EmptyData>),
+        (status = 500, description = "Internal server error", body = ApiResponse<EmptyData>)
+    ),
+    tag = "Billing History",
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_user_tokens(
+    State(app_state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(user_id_param): Path<String>,
+    Query(params): Query<UserTokensQuery>,
+) -> Result<Json<ApiResponse<PaginatedResponse<UserTokenResponse>>>, AppError> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let user_id_param = canonical_user_id(&user_id_param)?;
+    ensure_owner(&claims, &user_id_param)?;
+    let before = decode_cursor(params.cursor.as_deref(), "token")?;
+    let page_size = page_limit(
+        params.limit,
+        DEFAULT_USER_TOKENS_LIMIT,
+        MAX_USER_TOKENS_LIMIT,
+    )?;
+
+    let mut conn = app_state.get_db_connection()?;
+
+    let mut total = purchase_tokens
+        .filter(user_id.eq(&user_id_param))
+        .count()
+        .into_boxed();
+    // One extra row tells whether another page follows
+    let mut query = purchase_tokens
+        .filter(user_id.eq(&user_id_param))
+        .order((created_at.desc(), id.desc()))
+        .limit(page_size + 1)
+        .into_boxed();
+    if let Some(wanted) = params.status {
+        total = total.filter(status.eq(wanted));
+        query = query.filter(status.eq(wanted));
+    }
+    if let Some(before) = &before {
+        query = query.filter(before_cursor(created_at, id, before));
+    }
+    let total: i64 = total.get_result(&mut conn)?;
+    let rows: Vec<PurchaseToken> = query.load(&mut conn)?;
+    let (rows, next_cursor) = into_page(rows, page_size, |token| Cursor {
+        at: token.created_at,
+        id: token.id.clone(),
+    });
+
+    let mut histories = token_histories(&mut conn, &rows)?;
+    let items = rows
+        .into_iter()
+        .map(|token| {
+            let history = histories.remove(&token.purchase_token).unwrap_or_default();
+            user_token_response(token, history)
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(PaginatedResponse {
+        items,
+        next_cursor,
+        total_estimate: Some(total),
+    })))
+}
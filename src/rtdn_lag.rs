@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::error::AppResult;
+use crate::metrics::{
+    Metrics, RTDN_DELIVERY_LAG_SECONDS, RTDN_EVENT_LAG_SECONDS, RTDN_LAG_BUCKETS_SECONDS,
+};
+use crate::model::RtdnLagSample;
+use crate::types::RtdnLagStats;
+
+/// Milliseconds from `from` to `now`, clock skew between Google and us never makes it negative
+fn lag_ms(from: NaiveDateTime, now: NaiveDateTime) -> i64 {
+    (now - from).num_milliseconds().max(0)
+}
+
+/// Record how long after Google's event and the Pub/Sub publish a notification was processed
+///
+/// The lag is observed in the RTDN lag histograms and stored for `rtdn_lag_stats`.
+pub fn record_rtdn_lag(
+    conn: &mut SqliteConnection,
+    metrics: &Metrics,
+    notification_type: &str,
+    event_time: Option<NaiveDateTime>,
+    publish_time: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> AppResult<()> {
+    use crate::schema::rtdn_lag_samples;
+
+    let event_lag_ms = event_time.map(|time| lag_ms(time, now));
+    let delivery_lag_ms = publish_time.map(|time| lag_ms(time, now));
+
+    let labels = [("notification_type", notification_type)];
+    if let Some(lag) = event_lag_ms {
+        metrics.observe(
+            RTDN_EVENT_LAG_SECONDS,
+            &labels,
+            RTDN_LAG_BUCKETS_SECONDS,
+            lag as f64 / 1000.0,
+        );
+    }
+    if let Some(lag) = delivery_lag_ms {
+        metrics.observe(
+            RTDN_DELIVERY_LAG_SECONDS,
+            &labels,
+            RTDN_LAG_BUCKETS_SECONDS,
+            lag as f64 / 1000.0,
+        );
+    }
+
+    diesel::insert_into(rtdn_lag_samples::table)
+        .values(&RtdnLagSample::new(
+            notification_type.to_string(),
+            event_lag_ms,
+            delivery_lag_ms,
+            now,
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Nearest-rank percentile `p` (0 to 100) of ascending `sorted`
+fn percentile(sorted: &[i64], p: f64) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// p50 and p95 lag per notification type of the notifications processed since `since`
+pub fn rtdn_lag_stats(
+    conn: &mut SqliteConnection,
+    since: NaiveDateTime,
+) -> AppResult<Vec<RtdnLagStats>> {
+    use crate::schema::rtdn_lag_samples::dsl::*;
+
+    let samples: Vec<(String, Option<i64>, Option<i64>)> = rtdn_lag_samples
+        .filter(processed_at.ge(since))
+        .select((notification_type, event_lag_ms, delivery_lag_ms))
+        .load(conn)?;
+
+    let mut by_type: BTreeMap<String, (i64, Vec<i64>, Vec<i64>)> = BTreeMap::new();
+    for (kind, event_lag, delivery_lag) in samples {
+        let entry = by_type.entry(kind).or_default();
+        entry.0 += 1;
+        entry.1.extend(event_lag);
+        entry.2.extend(delivery_lag);
+    }
+
+    Ok(by_type
+        .into_iter()
+        .map(|(kind, (count, mut event_lags, mut delivery_lags))| {
+            event_lags.sort_unstable();
+            delivery_lags.sort_unstable();
+            RtdnLagStats {
+                notification_type: kind,
+                samples: count,
+                event_lag_p50_ms: percentile(&event_lags, 50.0),
+                event_lag_p95_ms: percentile(&event_lags, 95.0),
+                delivery_lag_p50_ms: percentile(&delivery_lags, 50.0),
+                delivery_lag_p95_ms: percentile(&delivery_lags, 95.0),
+            }
+        })
+        .collect())
+}
+
+/// Delete lag samples processed before `cutoff`, returning how many were deleted
+pub fn prune_rtdn_lag_samples(
+    conn: &mut SqliteConnection,
+    cutoff: NaiveDateTime,
+) -> AppResult<usize> {
+    use crate::schema::rtdn_lag_samples::dsl::*;
+
+    Ok(diesel::delete(rtdn_lag_samples.filter(processed_at.lt(cutoff))).execute(conn)?)
+}
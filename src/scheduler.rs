@@ -0,0 +1,534 @@
+//! Background jobs run on an interval or a cron schedule, by one replica at a time
+//!
+//! A job's schedule is read from `<JOB>_SCHEDULE`, a number of seconds or a five-field cron
+//! expression evaluated in UTC, and falls back to its `<JOB>_INTERVAL_SECS`. Scheduled runs
+//! only start on the elected leader, see [`crate::leader`]. Before each run the replica also
+//! takes the job's lease in `scheduled_jobs`, like an advisory lock, so replicas sharing the
+//! database never run a job at the same time, even across a leader change. The row also
+//! keeps the last run for `GET /admin/jobs`.
+//!
+//! ```ignore
+//! let mut scheduler = Scheduler::new(app_state)?;
+//! scheduler.register(
+//!     "snapshot_pruning",
+//!     Schedule::from_env("SNAPSHOT_PRUNE_SCHEDULE", "SNAPSHOT_PRUNE_INTERVAL_SECS", 86400)?,
+//!     |app_state| async move { Ok(None) },
+//! );
+//! scheduler.start();
+//! ```
+
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use diesel::prelude::*;
+
+use crate::config::{env_interval_secs, ConfigError};
+use crate::error::AppResult;
+use crate::metrics::{
+    SCHEDULED_JOB_DURATION_MS, SCHEDULED_JOB_LAST_SUCCESS_SECONDS, SCHEDULED_JOB_RUNS_TOTAL,
+};
+use crate::types::JobOutcome;
+use crate::AppState;
+
+/// How far ahead a cron expression is searched for its next run, covers Feb 29th
+const CRON_SEARCH_DAYS: i64 = 366 * 5;
+
+/// Five-field cron expression: minute, hour, day of month, month and day of week
+///
+/// Fields take `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n`, and comma separated lists
+/// of those. Day of week runs from 0 (Sunday) to 7 (Sunday again). Like cron, when both the
+/// day of month and the day of week are restricted a day matching either runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    /// One bit per allowed value
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+fn parse_cron_field(field: &str, name: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid {} field `{}`", name, field);
+    let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(number(step)?)),
+            None => (part, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // `5/15` runs from 5 to the end of the range
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if first < min || last > max || first > last || step == Some(0) {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+fn has_bit(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let &[minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+            return Err(format!(
+                "expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+                fields.len()
+            ));
+        };
+
+        let mut days_of_week = parse_cron_field(day_of_week, "day of week", 0, 7)?;
+        if has_bit(days_of_week, 7) {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+        let schedule = Self {
+            expression: fields.join(" "),
+            minutes: parse_cron_field(minute, "minute", 0, 59)?,
+            hours: parse_cron_field(hour, "hour", 0, 23)?,
+            days_of_month: parse_cron_field(day_of_month, "day of month", 1, 31)?,
+            months: parse_cron_field(month, "month", 1, 12)?,
+            days_of_week,
+            days_of_month_restricted: !day_of_month.starts_with('*'),
+            days_of_week_restricted: !day_of_week.starts_with('*'),
+        };
+
+        // e.g. `0 0 31 2 *`
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .expect("valid date");
+        if schedule.next_after(epoch).is_none() {
+            return Err(format!("`{}` never runs", schedule.expression));
+        }
+
+        Ok(schedule)
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day_of_month = has_bit(self.days_of_month, date.day());
+        let day_of_week = has_bit(self.days_of_week, date.weekday().num_days_from_sunday());
+        if self.days_of_month_restricted && self.days_of_week_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+
+    /// First matching minute strictly after `after`
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = after + chrono::Duration::days(CRON_SEARCH_DAYS);
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+
+        while time <= limit {
+            let date = time.date();
+            if !has_bit(self.months, date.month()) {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !has_bit(self.hours, time.hour()) {
+                time = time.with_minute(0)? + chrono::Duration::hours(1);
+            } else if !has_bit(self.minutes, time.minute()) {
+                time += chrono::Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// When a scheduled job runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// At startup and then every interval
+    Every(Duration),
+    /// At every minute the expression matches, in UTC
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// A number of seconds, or a cron expression
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        match value.parse::<u64>() {
+            Ok(0) => Err("the interval must be greater than zero".to_string()),
+            Ok(secs) => Ok(Schedule::Every(Duration::from_secs(secs))),
+            Err(_) => CronSchedule::parse(value).map(Schedule::Cron),
+        }
+    }
+
+    /// Read `schedule_var`, or every `interval_var` seconds when it is unset
+    pub fn from_env(
+        schedule_var: &'static str,
+        interval_var: &'static str,
+        default_secs: u64,
+    ) -> Result<Self, ConfigError> {
+        match env::var(schedule_var) {
+            Ok(value) => Schedule::parse(&value).map_err(|reason| ConfigError::InvalidSchedule {
+                name: schedule_var,
+                reason,
+            }),
+            Err(_) => env_interval_secs(interval_var, default_secs).map(Schedule::Every),
+        }
+    }
+
+    /// When the job runs next after a run at `after`
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Schedule::Every(interval) => Some(after + chrono::Duration::from_std(*interval).ok()?),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(cron) => write!(f, "{}", cron),
+        }
+    }
+}
+
+/// Store `job` with its current schedule, keeping its last run
+pub fn record_job(
+    conn: &mut SqliteConnection,
+    job: &str,
+    job_schedule: &Schedule,
+) -> AppResult<()> {
+    use crate::schema::scheduled_jobs::dsl::*;
+
+    diesel::insert_into(scheduled_jobs)
+        .values((name.eq(job), schedule.eq(job_schedule.to_string())))
+        .on_conflict(name)
+        .do_update()
+        .set(schedule.eq(job_schedule.to_string()))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Take the lease on `job` for `holder` until `now + lease`
+///
+/// Returns false while another holder's lease hasn't run out. A holder that dies mid-run
+/// keeps the job until its lease does.
+pub fn try_lock_job(
+    conn: &mut SqliteConnection,
+    job: &str,
+    job_schedule: &Schedule,
+    holder: &str,
+    now: NaiveDateTime,
+    lease: chrono::Duration,
+) -> AppResult<bool> {
+    use crate::schema::scheduled_jobs::dsl::*;
+
+    diesel::insert_or_ignore_into(scheduled_jobs)
+        .values((name.eq(job), schedule.eq(job_schedule.to_string())))
+        .execute(conn)?;
+    // A single statement, so two replicas can't both see the job unlocked
+    let locked = diesel::update(
+        scheduled_jobs
+            .filter(name.eq(job))
+            .filter(locked_until.is_null().or(locked_until.le(now))),
+    )
+    .set((
+        locked_by.eq(holder),
+        locked_until.eq(now + lease),
+        last_started_at.eq(now),
+    ))
+    .execute(conn)?;
+
+    Ok(locked == 1)
+}
+
+/// Record how `holder`'s run of `job` went and give up its lease
+///
+/// Nothing is recorded if the lease ran out and another holder took the job meanwhile.
+pub fn finish_job(
+    conn: &mut SqliteConnection,
+    job: &str,
+    holder: &str,
+    now: NaiveDateTime,
+    outcome: JobOutcome,
+    error: Option<&str>,
+    duration_ms: i64,
+) -> AppResult<()> {
+    use crate::schema::scheduled_jobs::dsl::*;
+
+    diesel::update(
+        scheduled_jobs
+            .filter(name.eq(job))
+            .filter(locked_by.eq(holder)),
+    )
+    .set((
+        locked_by.eq(None::<String>),
+        locked_until.eq(None::<NaiveDateTime>),
+        last_finished_at.eq(now),
+        last_outcome.eq(outcome),
+        last_error.eq(error),
+        last_duration_ms.eq(duration_ms),
+    ))
+    .execute(conn)?;
+    Ok(())
+}
+
+type JobFuture = Pin<Box<dyn Future<Output = AppResult<Option<String>>> + Send>>;
+
+/// One run of a job, resolving to a summary worth logging if there is one
+type JobFn = Arc<dyn Fn(AppState) -> JobFuture + Send + Sync>;
+
+struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+/// Background jobs of this replica, started together once registered
+pub struct Scheduler {
+    app_state: AppState,
+    /// Identifies this replica in job leases
+    holder: String,
+    lease: chrono::Duration,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Configured with `SCHEDULER_LEASE_SECS` (default 3600), how long a replica may run a job
+    /// before the others assume it died; keep it above the longest run
+    pub fn new(app_state: AppState) -> Result<Self, ConfigError> {
+        let lease = env_interval_secs("SCHEDULER_LEASE_SECS", 3600)?;
+
+        Ok(Self {
+            holder: app_state.leader.holder().to_string(),
+            app_state,
+            lease: chrono::Duration::seconds(lease.as_secs() as i64),
+            jobs: Vec::new(),
+        })
+    }
+
+    /// Holder name this replica takes job leases under
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Registered jobs and their schedules
+    pub fn jobs(&self) -> Vec<(&'static str, Schedule)> {
+        self.jobs
+            .iter()
+            .map(|job| (job.name, job.schedule.clone()))
+            .collect()
+    }
+
+    /// Add a job; `run` returns a summary to log, or `None` when there is nothing to report
+    pub fn register<F, Fut>(&mut self, name: &'static str, schedule: Schedule, run: F)
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AppResult<Option<String>>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name,
+            schedule,
+            run: Arc::new(move |app_state| Box::pin(run(app_state))),
+        });
+    }
+
+    /// Run the job registered as `name` once now, unless another replica is running it
+    ///
+    /// Runs on followers too. Returns `None` when it was skipped or no job has that name.
+    pub async fn run_now(&self, name: &str) -> Option<JobOutcome> {
+        let job = self.jobs.iter().find(|job| job.name == name)?;
+        run_job(&self.app_state, &self.holder, self.lease, job).await
+    }
+
+    /// Record every job's schedule, join the leader election and run each job in its own task
+    pub fn start(self) {
+        if let Err(e) = self.app_state.get_db_connection().and_then(|mut conn| {
+            self.jobs
+                .iter()
+                .try_for_each(|job| record_job(&mut conn, job.name, &job.schedule))
+        }) {
+            eprintln!("Failed to record scheduled jobs: {}", e);
+        }
+        self.app_state.leader.spawn_renewal();
+
+        for job in self.jobs {
+            let app_state = self.app_state.clone();
+            let holder = self.holder.clone();
+            let lease = self.lease;
+            println!("Scheduled job {} runs {}", job.name, job.schedule);
+
+            tokio::spawn(async move {
+                match &job.schedule {
+                    Schedule::Every(interval) => {
+                        // The first tick completes immediately, so the first run is at startup
+                        let mut ticker = tokio::time::interval(*interval);
+                        loop {
+                            ticker.tick().await;
+                            run_job_if_leader(&app_state, &holder, lease, &job).await;
+                        }
+                    }
+                    Schedule::Cron(cron) => loop {
+                        let now = app_state.clock.now_naive();
+                        let Some(next) = cron.next_after(now) else {
+                            eprintln!("Scheduled job {} has no next run", job.name);
+                            return;
+                        };
+                        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+                        run_job_if_leader(&app_state, &holder, lease, &job).await;
+                    },
+                }
+            });
+        }
+    }
+}
+
+fn report_failure(job: &str, error: &str) {
+    sentry::capture_message(
+        &format!("Scheduled job {} failed: {}", job, error),
+        sentry::Level::Error,
+    );
+    eprintln!("Scheduled job {} failed: {}", job, error);
+}
+
+/// Scheduled run of `job`, skipped unless this replica is the leader
+async fn run_job_if_leader(
+    app_state: &AppState,
+    holder: &str,
+    lease: chrono::Duration,
+    job: &Job,
+) -> Option<JobOutcome> {
+    if !app_state.leader.is_leader().await {
+        app_state.metrics.inc_counter(
+            SCHEDULED_JOB_RUNS_TOTAL,
+            &[("job", job.name), ("outcome", "skipped")],
+            1,
+        );
+        return None;
+    }
+    run_job(app_state, holder, lease, job).await
+}
+
+/// Take the job's lease, run it and record the outcome; `None` when another replica has it
+async fn run_job(
+    app_state: &AppState,
+    holder: &str,
+    lease: chrono::Duration,
+    job: &Job,
+) -> Option<JobOutcome> {
+    let metrics = &app_state.metrics;
+    let started_at = app_state.clock.now_naive();
+    let locked = app_state.get_db_connection().and_then(|mut conn| {
+        try_lock_job(
+            &mut conn,
+            job.name,
+            &job.schedule,
+            holder,
+            started_at,
+            lease,
+        )
+    });
+    match locked {
+        Ok(true) => {}
+        Ok(false) => {
+            metrics.inc_counter(
+                SCHEDULED_JOB_RUNS_TOTAL,
+                &[("job", job.name), ("outcome", "skipped")],
+                1,
+            );
+            return None;
+        }
+        Err(e) => {
+            report_failure(job.name, &e.to_string());
+            metrics.inc_counter(
+                SCHEDULED_JOB_RUNS_TOTAL,
+                &[("job", job.name), ("outcome", JobOutcome::Failed.as_str())],
+                1,
+            );
+            return Some(JobOutcome::Failed);
+        }
+    }
+
+    let timer = Instant::now();
+    let result = (job.run)(app_state.clone()).await;
+    let duration_ms = timer.elapsed().as_millis() as i64;
+
+    let error = match result {
+        Ok(Some(summary)) => {
+            println!("{}", summary);
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            report_failure(job.name, &e.to_string());
+            Some(e.to_string())
+        }
+    };
+    let outcome = match error {
+        None => JobOutcome::Succeeded,
+        Some(_) => JobOutcome::Failed,
+    };
+
+    let finished_at = app_state.clock.now_naive();
+    metrics.inc_counter(
+        SCHEDULED_JOB_RUNS_TOTAL,
+        &[("job", job.name), ("outcome", outcome.as_str())],
+        1,
+    );
+    metrics.set_gauge(SCHEDULED_JOB_DURATION_MS, &[("job", job.name)], duration_ms);
+    if outcome == JobOutcome::Succeeded {
+        metrics.set_gauge(
+            SCHEDULED_JOB_LAST_SUCCESS_SECONDS,
+            &[("job", job.name)],
+            finished_at.and_utc().timestamp(),
+        );
+    }
+
+    if let Err(e) = app_state.get_db_connection().and_then(|mut conn| {
+        finish_job(
+            &mut conn,
+            job.name,
+            holder,
+            finished_at,
+            outcome,
+            error.as_deref(),
+            duration_ms,
+        )
+    }) {
+        eprintln!(
+            "Failed to record the run of scheduled job {}: {}",
+            job.name, e
+        );
+    }
+
+    Some(outcome)
+}
@@ -1,5 +1,61 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    admin_audit_log (id) {
+        id -> Text,
+        operator -> Text,
+        action -> Text,
+        user_id -> Text,
+        reason -> Text,
+        purchase_token -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    access_outbox (id) {
+        id -> Text,
+        user_id -> Text,
+        action -> Text,
+        product_id -> Nullable<Text>,
+        purchase_token -> Nullable<Text>,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        run_after -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    analytics_events (id) {
+        id -> Text,
+        event_type -> Text,
+        purchase_token -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    backfills (name) {
+        name -> Text,
+        last_token_id -> Nullable<Text>,
+        processed -> BigInt,
+        updated -> BigInt,
+        failed -> BigInt,
+        last_error -> Nullable<Text>,
+        started_at -> Timestamp,
+        updated_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     bot_chat_access (id) {
         id -> Text,
@@ -13,6 +69,198 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    cancel_intents (id) {
+        id -> Text,
+        user_id -> Text,
+        purchase_token -> Text,
+        reason -> Text,
+        comment -> Nullable<Text>,
+        retention_offered -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    compensation_batches (id) {
+        id -> Text,
+        incident -> Text,
+        reason -> Text,
+        kind -> Text,
+        amount -> Integer,
+        operator -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    compensation_grants (batch_id, user_id) {
+        batch_id -> Text,
+        user_id -> Text,
+        status -> Text,
+        error -> Nullable<Text>,
+        purchase_token -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    credit_ledger (id) {
+        id -> Text,
+        user_id -> Text,
+        amount -> Integer,
+        direction -> Text,
+        reason -> Text,
+        actor -> Text,
+        correlation_id -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    credit_topups (order_id) {
+        order_id -> Text,
+        user_id -> Text,
+        amount -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    domain_events (id) {
+        id -> Text,
+        event_type -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    entitlements (user_id) {
+        user_id -> Text,
+        plan -> Text,
+        source -> Nullable<Text>,
+        valid_from -> Nullable<Timestamp>,
+        valid_until -> Nullable<Timestamp>,
+        auto_renewing -> Nullable<Bool>,
+        paused_from -> Nullable<Timestamp>,
+        resumes_at -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    feature_flags (name) {
+        name -> Text,
+        enabled -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    grace_reminders (purchase_token) {
+        purchase_token -> Text,
+        user_id -> Text,
+        product_id -> Text,
+        started_at -> Timestamp,
+        grace_ends_at -> Timestamp,
+        reminders_sent -> Integer,
+        last_sent_at -> Nullable<Timestamp>,
+        stopped_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    held_notifications (id) {
+        id -> Text,
+        purchase_token -> Text,
+        event_time -> Timestamp,
+        notification_type -> Integer,
+        payload -> Text,
+        received_at -> Timestamp,
+        release_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    invoices (id) {
+        id -> Text,
+        order_id -> Text,
+        user_id -> Text,
+        product_id -> Text,
+        base_plan_id -> Nullable<Text>,
+        offer_id -> Nullable<Text>,
+        region_code -> Nullable<Text>,
+        currency_code -> Nullable<Text>,
+        amount_micros -> Nullable<BigInt>,
+        issued_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    offer_redemptions (purchase_token, offer_id) {
+        purchase_token -> Text,
+        offer_id -> Text,
+        user_id -> Text,
+        product_id -> Text,
+        base_plan_id -> Nullable<Text>,
+        offer_tags -> Text,
+        redeemed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    orders (id) {
+        id -> Text,
+        order_id -> Text,
+        purchase_token -> Text,
+        user_id -> Text,
+        product_id -> Text,
+        region_code -> Nullable<Text>,
+        recorded_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    products (id) {
+        id -> Text,
+        package_name -> Text,
+        product_id -> Text,
+        base_plan_id -> Text,
+        state -> Text,
+        billing_period -> Nullable<Text>,
+        archived -> Bool,
+        synced_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    purchase_intents (id) {
+        id -> Text,
+        user_id -> Text,
+        package_name -> Text,
+        product_id -> Text,
+        obfuscated_account_id -> Nullable<Text>,
+        obfuscated_profile_id -> Nullable<Text>,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    purchase_profiles (obfuscated_profile_id) {
+        obfuscated_profile_id -> Text,
+        user_id -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     purchase_tokens (id) {
         id -> Text,
@@ -21,7 +269,214 @@ diesel::table! {
         status -> Text,
         created_at -> Timestamp,
         expiry_at -> Timestamp,
+        environment -> Text,
+        auto_renewing -> Bool,
+        notified_at -> Nullable<Timestamp>,
+        package_name -> Nullable<Text>,
+        acknowledged_at -> Nullable<Timestamp>,
+        last_event_time -> Nullable<Timestamp>,
+        paused_from -> Nullable<Timestamp>,
+        resumes_at -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+        replaced_by -> Nullable<Text>,
+        version -> Integer,
+        provisional_since -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    refund_requests (id) {
+        id -> Text,
+        user_id -> Text,
+        purchase_token -> Text,
+        order_id -> Nullable<Text>,
+        reason -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    revenue_events (id) {
+        id -> Text,
+        order_id -> Text,
+        purchase_token -> Text,
+        user_id -> Text,
+        product_id -> Text,
+        base_plan_id -> Nullable<Text>,
+        offer_id -> Nullable<Text>,
+        region_code -> Nullable<Text>,
+        currency_code -> Text,
+        price_micros -> BigInt,
+        recorded_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rtdn_lag_samples (id) {
+        id -> Text,
+        notification_type -> Text,
+        event_lag_ms -> Nullable<BigInt>,
+        delivery_lag_ms -> Nullable<BigInt>,
+        processed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    scheduled_jobs (name) {
+        name -> Text,
+        schedule -> Text,
+        locked_by -> Nullable<Text>,
+        locked_until -> Nullable<Timestamp>,
+        last_started_at -> Nullable<Timestamp>,
+        last_finished_at -> Nullable<Timestamp>,
+        last_outcome -> Nullable<Text>,
+        last_error -> Nullable<Text>,
+        last_duration_ms -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    subscription_line_items (purchase_token, product_id) {
+        purchase_token -> Text,
+        product_id -> Text,
+        base_plan_id -> Nullable<Text>,
+        offer_id -> Nullable<Text>,
+        expiry_at -> Nullable<Timestamp>,
+        auto_renewing -> Bool,
+        expired -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    subscription_snapshots (purchase_token, fetched_at) {
+        purchase_token -> Text,
+        fetched_at -> Timestamp,
+        body -> Binary,
+    }
+}
+
+diesel::table! {
+    subscription_events (id) {
+        id -> Text,
+        user_id -> Text,
+        purchase_token -> Text,
+        kind -> Text,
+        order_id -> Nullable<Text>,
+        product_id -> Text,
+        occurred_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    subscriptions (id) {
+        id -> Text,
+        user_id -> Text,
+        source -> Text,
+        source_ref -> Text,
+        status -> Text,
+        product_id -> Nullable<Text>,
+        started_at -> Timestamp,
+        expires_at -> Timestamp,
+        auto_renewing -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    token_transfers (id) {
+        id -> Text,
+        purchase_token -> Text,
+        from_user_id -> Text,
+        to_user_id -> Text,
+        transferred_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    unhandled_notifications (id) {
+        id -> Text,
+        kind -> Text,
+        notification_type -> Nullable<Integer>,
+        purchase_token -> Nullable<Text>,
+        payload -> Text,
+        received_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_risk (user_id) {
+        user_id -> Text,
+        revoked_count -> Integer,
+        voided_count -> Integer,
+        flagged_at -> Nullable<Timestamp>,
+        approved_at -> Nullable<Timestamp>,
+        approved_by -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    verification_quarantine (id) {
+        id -> Text,
+        user_id -> Text,
+        account_id -> Text,
+        purchase_token -> Text,
+        package_name -> Text,
+        product_id -> Text,
+        reason -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        decided_at -> Nullable<Timestamp>,
+        decided_by -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    verify_attempts (id) {
+        id -> Text,
+        purchase_token -> Text,
+        user_id -> Text,
+        attempted_at -> Timestamp,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(bot_chat_access, purchase_tokens,);
+diesel::allow_tables_to_appear_in_same_query!(
+    access_outbox,
+    admin_audit_log,
+    analytics_events,
+    backfills,
+    bot_chat_access,
+    cancel_intents,
+    compensation_batches,
+    compensation_grants,
+    credit_ledger,
+    credit_topups,
+    domain_events,
+    entitlements,
+    feature_flags,
+    grace_reminders,
+    held_notifications,
+    invoices,
+    offer_redemptions,
+    orders,
+    products,
+    purchase_intents,
+    purchase_profiles,
+    purchase_tokens,
+    refund_requests,
+    revenue_events,
+    rtdn_lag_samples,
+    scheduled_jobs,
+    subscription_line_items,
+    subscription_snapshots,
+    subscription_events,
+    subscriptions,
+    token_transfers,
+    unhandled_notifications,
+    user_risk,
+    verification_quarantine,
+    verify_attempts,
+);
@@ -0,0 +1,281 @@
+use std::env;
+use std::fmt;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+
+use crate::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use crate::db::database_url_from_env;
+use crate::error::{AppError, AppResult};
+use crate::model::{AdminAuditEntry, Order, Product, PurchaseToken, RevenueEvent};
+use crate::types::{AuditAction, PurchaseEnvironment, PurchaseTokenStatus};
+use crate::MIGRATIONS;
+
+/// Prefix of every purchase token, user id and order id written by the seed
+pub const SEED_PREFIX: &str = "seed-";
+
+/// Package the seeded catalog is stored under, kept apart from the synced package
+pub const SEED_PACKAGE_NAME: &str = "com.yral.android.seed";
+
+/// Operator recorded on seeded audit entries
+pub const SEED_OPERATOR: &str = "seed";
+
+/// Rows written by `seed_database`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SeedReport {
+    pub purchase_tokens: usize,
+    pub orders: usize,
+    pub revenue_events: usize,
+    pub audit_entries: usize,
+    pub products: usize,
+}
+
+impl fmt::Display for SeedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Seeded {} purchase tokens, {} orders, {} revenue events, {} audit entries and {} products",
+            self.purchase_tokens,
+            self.orders,
+            self.revenue_events,
+            self.audit_entries,
+            self.products
+        )
+    }
+}
+
+/// Seeding writes fake users into the database, never allow it against production
+pub fn allowed() -> Result<(), String> {
+    let production = env::var("APP_ENV")
+        .map(|app_env| app_env == "production")
+        .unwrap_or(false);
+    if production {
+        return Err("Refusing to seed the database when APP_ENV=production".to_string());
+    }
+    Ok(())
+}
+
+/// Migrate the database in `DATABASE_URL` and seed it, for the `seed` command
+pub fn run_seed() -> Result<SeedReport, String> {
+    allowed()?;
+
+    let database_url = database_url_from_env();
+    let mut conn = SqliteConnection::establish(&database_url)
+        .map_err(|e| format!("Failed to open {}: {}", database_url, e))?;
+    conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|e| format!("Migration error: {}", e))?;
+
+    seed_database(&mut conn, Utc::now().naive_utc()).map_err(|e| e.to_string())
+}
+
+fn seed_token(
+    name: &str,
+    status: PurchaseTokenStatus,
+    environment: PurchaseEnvironment,
+    created_at: NaiveDateTime,
+    expiry_at: NaiveDateTime,
+) -> PurchaseToken {
+    let mut token = PurchaseToken::new(
+        format!("{}user-{}", SEED_PREFIX, name),
+        format!("{}token-{}", SEED_PREFIX, name),
+        expiry_at,
+        status,
+        environment,
+    );
+    token.created_at = created_at;
+    token.package_name = Some(SEED_PACKAGE_NAME.to_string());
+    token
+}
+
+/// Replace previously seeded rows with a representative local dataset
+///
+/// Writes a purchase token in every status (plus one close to expiry, one sandbox and one manual
+/// grant), orders and revenue for the paid ones, audit entries and a catalog with an active and
+/// a draft base plan. Rows are recognised by `SEED_PREFIX` and `SEED_PACKAGE_NAME`, so running it
+/// again leaves the same dataset and other rows untouched.
+pub fn seed_database(conn: &mut SqliteConnection, now: NaiveDateTime) -> AppResult<SeedReport> {
+    use crate::schema::{admin_audit_log, orders, products, purchase_tokens, revenue_events};
+
+    let seed_pattern = format!("{}%", SEED_PREFIX);
+
+    let mut active = seed_token(
+        "active",
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+        now - Duration::days(5),
+        now + Duration::days(25),
+    );
+    active.acknowledged_at = Some(active.created_at);
+
+    let pending = seed_token(
+        "pending",
+        PurchaseTokenStatus::Pending,
+        PurchaseEnvironment::Production,
+        now - Duration::hours(1),
+        now + Duration::days(30),
+    );
+
+    let mut expiring = seed_token(
+        "expiring",
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+        now - Duration::days(28),
+        now + Duration::days(2),
+    );
+    expiring.auto_renewing = false;
+    expiring.acknowledged_at = Some(expiring.created_at);
+
+    let mut expired = seed_token(
+        "expired",
+        PurchaseTokenStatus::Expired,
+        PurchaseEnvironment::Production,
+        now - Duration::days(40),
+        now - Duration::days(10),
+    );
+    expired.auto_renewing = false;
+    expired.acknowledged_at = Some(expired.created_at);
+
+    let mut sandbox = seed_token(
+        "sandbox",
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Sandbox,
+        now - Duration::days(1),
+        now + Duration::days(29),
+    );
+    sandbox.acknowledged_at = Some(sandbox.created_at);
+
+    let mut manual = seed_token(
+        "manual",
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Manual,
+        now - Duration::days(3),
+        now + Duration::days(27),
+    );
+    manual.auto_renewing = false;
+    manual.acknowledged_at = Some(manual.created_at);
+    manual.package_name = None;
+
+    let tokens = vec![active, pending, expiring, expired, sandbox, manual];
+
+    let order_rows: Vec<Order> = tokens
+        .iter()
+        .filter(|token| token.environment == PurchaseEnvironment::Production)
+        .filter(|token| token.status != PurchaseTokenStatus::Pending)
+        .enumerate()
+        .map(|(i, token)| {
+            Order::new(
+                format!("{}GPA.0000-0000-0000-{:05}", SEED_PREFIX, i),
+                token.purchase_token.clone(),
+                token.user_id.clone(),
+                YRAL_PRO_PLAN_PRODUCT_ID.to_string(),
+                Some(if i % 2 == 0 { "US" } else { "IN" }.to_string()),
+                token.created_at,
+            )
+        })
+        .collect();
+
+    let revenue_rows: Vec<RevenueEvent> = order_rows
+        .iter()
+        .map(|order| {
+            let (currency_code, price_micros) = match order.region_code.as_deref() {
+                Some("IN") => ("INR", 199_000_000),
+                _ => ("USD", 4_990_000),
+            };
+            RevenueEvent::new(
+                order,
+                Some("monthly".to_string()),
+                None,
+                currency_code.to_string(),
+                price_micros,
+            )
+        })
+        .collect();
+
+    let audit_rows = vec![
+        AdminAuditEntry::new(
+            SEED_OPERATOR.to_string(),
+            AuditAction::GrantPro,
+            format!("{}user-manual", SEED_PREFIX),
+            "Seeded manual grant".to_string(),
+            Some(format!("{}token-manual", SEED_PREFIX)),
+            now - Duration::days(3),
+        ),
+        AdminAuditEntry::new(
+            SEED_OPERATOR.to_string(),
+            AuditAction::RevokePro,
+            format!("{}user-expired", SEED_PREFIX),
+            "Seeded manual revoke".to_string(),
+            None,
+            now - Duration::days(10),
+        ),
+        AdminAuditEntry::new(
+            SEED_OPERATOR.to_string(),
+            AuditAction::DeferSubscription,
+            format!("{}user-active", SEED_PREFIX),
+            "Seeded deferral".to_string(),
+            Some(format!("{}token-active", SEED_PREFIX)),
+            now - Duration::days(1),
+        ),
+    ];
+
+    let product_rows = vec![
+        Product::new(
+            SEED_PACKAGE_NAME.to_string(),
+            YRAL_PRO_PLAN_PRODUCT_ID.to_string(),
+            "monthly".to_string(),
+            "ACTIVE".to_string(),
+            Some("P1M".to_string()),
+            false,
+            now,
+        ),
+        Product::new(
+            SEED_PACKAGE_NAME.to_string(),
+            YRAL_PRO_PLAN_PRODUCT_ID.to_string(),
+            "yearly".to_string(),
+            "DRAFT".to_string(),
+            Some("P1Y".to_string()),
+            false,
+            now,
+        ),
+    ];
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        diesel::delete(
+            purchase_tokens::table.filter(purchase_tokens::purchase_token.like(&seed_pattern)),
+        )
+        .execute(conn)?;
+        diesel::delete(orders::table.filter(orders::order_id.like(&seed_pattern))).execute(conn)?;
+        diesel::delete(revenue_events::table.filter(revenue_events::order_id.like(&seed_pattern)))
+            .execute(conn)?;
+        diesel::delete(admin_audit_log::table.filter(admin_audit_log::operator.eq(SEED_OPERATOR)))
+            .execute(conn)?;
+        diesel::delete(products::table.filter(products::package_name.eq(SEED_PACKAGE_NAME)))
+            .execute(conn)?;
+
+        diesel::insert_into(purchase_tokens::table)
+            .values(&tokens)
+            .execute(conn)?;
+        diesel::insert_into(orders::table)
+            .values(&order_rows)
+            .execute(conn)?;
+        diesel::insert_into(revenue_events::table)
+            .values(&revenue_rows)
+            .execute(conn)?;
+        diesel::insert_into(admin_audit_log::table)
+            .values(&audit_rows)
+            .execute(conn)?;
+        diesel::insert_into(products::table)
+            .values(&product_rows)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    Ok(SeedReport {
+        purchase_tokens: tokens.len(),
+        orders: order_rows.len(),
+        revenue_events: revenue_rows.len(),
+        audit_entries: audit_rows.len(),
+        products: product_rows.len(),
+    })
+}
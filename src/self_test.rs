@@ -0,0 +1,239 @@
+use std::env;
+use std::fmt;
+
+use diesel::migration::MigrationSource;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use diesel_migrations::MigrationHarness;
+
+use crate::auth::{GoogleAuth, GooglePublicKey};
+use crate::db::database_url_from_env;
+use crate::ic_identity::{AdminIdentity, KeySource};
+use crate::integrations::user_info::IcConfig;
+use crate::integrations::IntegrationMode;
+use crate::MIGRATIONS;
+
+/// Outcome of a single self-test check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed(String),
+    Failed(String),
+    /// Check does not apply to this build or configuration
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+/// Diagnostic report printed by `--self-test`
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    fn record(&mut self, name: &'static str, outcome: CheckOutcome) {
+        self.checks.push(CheckResult { name, outcome });
+    }
+
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Self-test report:")?;
+        for check in &self.checks {
+            let (label, detail) = match &check.outcome {
+                CheckOutcome::Passed(detail) => ("PASS", detail),
+                CheckOutcome::Failed(detail) => ("FAIL", detail),
+                CheckOutcome::Skipped(detail) => ("SKIP", detail),
+            };
+            writeln!(f, "  [{}] {}: {}", label, check.name, detail)?;
+        }
+        write!(
+            f,
+            "Self-test {}",
+            if self.passed() { "passed" } else { "failed" }
+        )
+    }
+}
+
+/// Whether the process was started with `--self-test` or `SELF_TEST=true`
+pub fn requested() -> bool {
+    env::args().any(|arg| arg == "--self-test")
+        || env::var("SELF_TEST").map(|v| v == "true").unwrap_or(false)
+}
+
+/// Exercise every external dependency the service needs to serve requests
+pub async fn run_self_test() -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    let database_url = database_url_from_env();
+    report.record("database migrations", check_migrations(&database_url));
+
+    match IntegrationMode::from_env() {
+        Ok(IntegrationMode::Live) => {
+            report.record("integration mode", CheckOutcome::Passed("live".to_string()));
+            check_google(&mut report).await;
+            report.record("ic user info canister", check_ic().await);
+        }
+        Ok(IntegrationMode::Mock) => {
+            report.record("integration mode", CheckOutcome::Passed("mock".to_string()));
+            for name in [
+                "google access token",
+                "android publisher api",
+                "google public key",
+                "ic user info canister",
+            ] {
+                report.record(name, CheckOutcome::Skipped("mock integrations".to_string()));
+            }
+        }
+        Err(e) => report.record("integration mode", CheckOutcome::Failed(e)),
+    }
+
+    report
+}
+
+/// Verify the database is reachable and its schema is not ahead of this binary
+///
+/// Pending migrations are not a failure since they are applied on startup.
+pub fn check_migrations(database_url: &str) -> CheckOutcome {
+    let mut conn = match SqliteConnection::establish(database_url) {
+        Ok(conn) => conn,
+        Err(e) => return CheckOutcome::Failed(format!("Failed to open {}: {}", database_url, e)),
+    };
+
+    let known: Vec<String> = match MigrationSource::<Sqlite>::migrations(&MIGRATIONS) {
+        Ok(migrations) => migrations
+            .iter()
+            .map(|m| m.name().version().to_string())
+            .collect(),
+        Err(e) => {
+            return CheckOutcome::Failed(format!("Failed to read embedded migrations: {}", e))
+        }
+    };
+
+    let applied = match conn.applied_migrations() {
+        Ok(applied) => applied,
+        Err(e) => return CheckOutcome::Failed(format!("Failed to read applied migrations: {}", e)),
+    };
+
+    let unknown: Vec<String> = applied
+        .iter()
+        .map(|version| version.to_string())
+        .filter(|version| !known.contains(version))
+        .collect();
+    if !unknown.is_empty() {
+        return CheckOutcome::Failed(format!(
+            "Database has migrations unknown to this build: {}",
+            unknown.join(", ")
+        ));
+    }
+
+    let pending = known.len() - applied.len().min(known.len());
+    CheckOutcome::Passed(format!(
+        "{} applied, {} pending (applied on startup)",
+        applied.len(),
+        pending
+    ))
+}
+
+async fn check_google(report: &mut SelfTestReport) {
+    let access_token = match GoogleAuth::from_env().await {
+        Ok(auth) => match auth.get_token_for_default_scopes().await {
+            Ok(token) => {
+                report.record(
+                    "google access token",
+                    CheckOutcome::Passed("acquired".to_string()),
+                );
+                Some(token)
+            }
+            Err(e) => {
+                report.record("google access token", CheckOutcome::Failed(e.to_string()));
+                None
+            }
+        },
+        Err(e) => {
+            report.record("google access token", CheckOutcome::Failed(e.to_string()));
+            None
+        }
+    };
+
+    let publisher_outcome = match (access_token, env::var("GOOGLE_PLAY_PACKAGE_NAME")) {
+        (None, _) => CheckOutcome::Skipped("no access token".to_string()),
+        (Some(_), Err(_)) => {
+            CheckOutcome::Skipped("GOOGLE_PLAY_PACKAGE_NAME is not set".to_string())
+        }
+        (Some(token), Ok(package_name)) => check_android_publisher(&token, &package_name).await,
+    };
+    report.record("android publisher api", publisher_outcome);
+
+    let public_key_outcome = match GooglePublicKey::new().await {
+        Ok(_) => CheckOutcome::Passed("fetched".to_string()),
+        Err(e) => CheckOutcome::Failed(e.to_string()),
+    };
+    report.record("google public key", public_key_outcome);
+}
+
+/// List the app's subscriptions, a read-only call that needs the same permissions as verification
+async fn check_android_publisher(access_token: &str, package_name: &str) -> CheckOutcome {
+    let url = format!(
+        "https://androidpublisher.googleapis.com/androidpublisher/v3/applications/{}/subscriptions",
+        package_name
+    );
+
+    let res = match reqwest::Client::new()
+        .get(&url)
+        .bearer_auth(access_token)
+        .send()
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => return CheckOutcome::Failed(format!("Request failed: {}", e)),
+    };
+
+    let status = res.status();
+    if status.is_success() {
+        CheckOutcome::Passed(format!("listed subscriptions for {}", package_name))
+    } else {
+        let body = res.text().await.unwrap_or_default();
+        CheckOutcome::Failed(format!("{}: {}", status, body))
+    }
+}
+
+/// Load the admin identity and read the user info canister's module hash through the agent
+async fn check_ic() -> CheckOutcome {
+    let identity = match KeySource::from_env().and_then(AdminIdentity::load) {
+        Ok(identity) => identity,
+        Err(e) => return CheckOutcome::Failed(format!("Admin identity: {}", e)),
+    };
+    let principal = identity.principal();
+
+    let config = match IcConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => return CheckOutcome::Failed(e),
+    };
+    let agent = match config.agent(identity).await {
+        Ok(agent) => agent,
+        Err(e) => return CheckOutcome::Failed(e),
+    };
+
+    match agent
+        .read_state_canister_info(config.user_info_canister_id, "module_hash")
+        .await
+    {
+        Ok(_) => CheckOutcome::Passed(format!(
+            "{} on {} reachable as {}",
+            config.user_info_canister_id, config.url, principal
+        )),
+        Err(e) => CheckOutcome::Failed(format!("Failed to query user info canister: {}", e)),
+    }
+}
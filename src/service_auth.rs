@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::auth::{claims_from_headers, Claims};
+use crate::cache::{Cache, MemoryCache};
+use crate::config::{env_number, ConfigError};
+use crate::error::AppError;
+use crate::request_limits::DEFAULT_JSON_BODY_LIMIT;
+use crate::AppState;
+
+/// Name of the calling service, a key in `SERVICE_AUTH_KEYS`
+pub const SERVICE_HEADER: &str = "x-yral-service";
+/// Unix seconds at which the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-yral-timestamp";
+/// Hex HMAC-SHA256 of the string built by `signing_payload`
+pub const SIGNATURE_HEADER: &str = "x-yral-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Shared secret of one internal caller
+#[derive(Debug, Clone)]
+pub struct ServiceKey {
+    pub secret: String,
+    /// Requests allowed per minute, unlimited when unset
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Who made an authenticated request, available to handlers as an extension
+#[derive(Debug, Clone)]
+pub enum Caller {
+    /// End user or operator authenticated with a JWT
+    User(Claims),
+    /// Internal backend authenticated with an HMAC signature
+    Service(String),
+}
+
+impl Caller {
+    /// Stable label for logs and metrics, e.g. `service:video` or `user:<sub>`
+    pub fn label(&self) -> String {
+        match self {
+            Caller::User(claims) => {
+                format!("user:{}", claims.sub.as_deref().unwrap_or("anonymous"))
+            }
+            Caller::Service(name) => format!("service:{}", name),
+        }
+    }
+}
+
+/// String that is signed: `<timestamp>.<METHOD>.<path>.<hex sha256 of body>`
+pub fn signing_payload(timestamp: i64, method: &str, path: &str, body: &[u8]) -> String {
+    format!(
+        "{}.{}.{}.{}",
+        timestamp,
+        method.to_ascii_uppercase(),
+        path,
+        hex::encode(Sha256::digest(body))
+    )
+}
+
+/// Signature a caller holding `secret` sends in `SIGNATURE_HEADER`
+pub fn sign(secret: &str, timestamp: i64, method: &str, path: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(signing_payload(timestamp, method, path, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Verifies HMAC-signed requests from internal services
+///
+/// Signatures older or newer than the replay window are refused, and a signature is accepted
+/// only once within the window. Accepted signatures and per-minute request counts live in the
+/// cache, so replicas sharing one enforce both together.
+#[derive(Clone)]
+pub struct ServiceAuth {
+    keys: Arc<HashMap<String, ServiceKey>>,
+    replay_window_secs: i64,
+    cache: Arc<dyn Cache>,
+}
+
+impl ServiceAuth {
+    pub fn new(keys: HashMap<String, ServiceKey>, replay_window_secs: i64) -> Self {
+        Self {
+            keys: Arc::new(keys),
+            replay_window_secs,
+            cache: Arc::new(MemoryCache::default()),
+        }
+    }
+
+    /// Keep accepted signatures and request counts in `cache` instead of this process
+    pub fn with_cache(mut self, cache: Arc<dyn Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Callers from `SERVICE_AUTH_KEYS` and the window from `SERVICE_AUTH_REPLAY_WINDOW_SECS`
+    /// (default 300)
+    ///
+    /// `SERVICE_AUTH_KEYS` is a comma-separated list of `name:secret` or
+    /// `name:secret:requests_per_minute`. Unset means no service may sign requests.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let replay_window_secs = env_number("SERVICE_AUTH_REPLAY_WINDOW_SECS", 300)?;
+        let keys = match env::var("SERVICE_AUTH_KEYS") {
+            Ok(value) => parse_service_keys(&value)?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(Self::new(keys, replay_window_secs))
+    }
+
+    /// Check a signed request from `caller`, `now` being unix seconds
+    ///
+    /// Refuses with 503 when the cache can't be reached, rather than letting replays through.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn verify(
+        &self,
+        caller: &str,
+        timestamp: i64,
+        signature: &str,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        now: i64,
+    ) -> Result<(), StatusCode> {
+        let key = self.keys.get(caller).ok_or(StatusCode::UNAUTHORIZED)?;
+
+        if (now - timestamp).abs() > self.replay_window_secs {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        let signature_bytes = hex::decode(signature).map_err(|_| StatusCode::UNAUTHORIZED)?;
+        let mut mac = HmacSha256::new_from_slice(key.secret.as_bytes())
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+        mac.update(signing_payload(timestamp, method, path, body).as_bytes());
+        mac.verify_slice(&signature_bytes)
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+        // Past twice the window the timestamp check refuses the signature on its own
+        let seen_for = Duration::from_secs(2 * self.replay_window_secs.max(1) as u64);
+        let first_use = self
+            .cache
+            .set_if_absent(
+                &format!("service_auth:signature:{}", signature.to_ascii_lowercase()),
+                &timestamp.to_string(),
+                seen_for,
+            )
+            .await
+            .map_err(cache_unavailable)?;
+        if !first_use {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        if let Some(limit) = key.requests_per_minute {
+            let minute = now.div_euclid(60);
+            let count = self
+                .cache
+                .increment(
+                    &format!("service_auth:usage:{}:{}", caller, minute),
+                    Duration::from_secs(60),
+                )
+                .await
+                .map_err(cache_unavailable)?;
+            if count > limit as u64 {
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn cache_unavailable(e: AppError) -> StatusCode {
+    eprintln!("Service auth cache unavailable: {}", e);
+    StatusCode::SERVICE_UNAVAILABLE
+}
+
+fn parse_service_keys(value: &str) -> Result<HashMap<String, ServiceKey>, ConfigError> {
+    let mut keys = HashMap::new();
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let mut parts = entry.splitn(3, ':');
+        let (Some(name), Some(secret)) = (parts.next(), parts.next()) else {
+            return Err(ConfigError::ServiceAuthKeys(format!(
+                "`{}` is not `name:secret`",
+                entry
+            )));
+        };
+        if name.is_empty() || secret.is_empty() {
+            return Err(ConfigError::ServiceAuthKeys(format!(
+                "`{}` has an empty name or secret",
+                entry
+            )));
+        }
+        let requests_per_minute = match parts.next() {
+            Some(limit) => Some(limit.parse().map_err(|_| {
+                ConfigError::ServiceAuthKeys(format!("`{}` has an invalid rate limit", entry))
+            })?),
+            None => None,
+        };
+        keys.insert(
+            name.to_string(),
+            ServiceKey {
+                secret: secret.to_string(),
+                requests_per_minute,
+            },
+        );
+    }
+    Ok(keys)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Accept either an HMAC-signed internal service or a user JWT
+///
+/// Requests carrying `SERVICE_HEADER` must be signed, anything else goes through the same JWT
+/// check as `jwt_auth_middleware`. The `Caller` is added to the request extensions, plus the
+/// `Claims` for JWT callers. Use with `middleware::from_fn_with_state(app_state, ...)`.
+pub async fn service_or_jwt_auth(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let req = authenticate_caller(&app_state, req, true).await?;
+    Ok(next.run(req).await)
+}
+
+/// Accept only HMAC-signed internal services, for routes users must never call
+///
+/// Adds the `Caller` to the request extensions like `service_or_jwt_auth`. Use with
+/// `middleware::from_fn_with_state(app_state, ...)`.
+pub async fn require_service_auth(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let req = authenticate_caller(&app_state, req, false).await?;
+    Ok(next.run(req).await)
+}
+
+/// Check a signed service request, or a user JWT when `allow_jwt`, and hand back the request
+/// with its `Caller`
+pub(crate) async fn authenticate_caller(
+    app_state: &AppState,
+    mut req: Request,
+    allow_jwt: bool,
+) -> Result<Request, StatusCode> {
+    let Some(service) = header(req.headers(), SERVICE_HEADER).map(str::to_string) else {
+        if !allow_jwt {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let claims = claims_from_headers(req.headers())?;
+        req.extensions_mut().insert(Caller::User(claims.clone()));
+        req.extensions_mut().insert(claims);
+        return Ok(req);
+    };
+
+    verify_service_request(app_state, service, req).await
+}
+
+/// Check the signature of a request from `service` and hand back the request to run
+async fn verify_service_request(
+    app_state: &AppState,
+    service: String,
+    req: Request,
+) -> Result<Request, StatusCode> {
+    let timestamp: i64 = header(req.headers(), TIMESTAMP_HEADER)
+        .and_then(|value| value.parse().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let signature = header(req.headers(), SIGNATURE_HEADER)
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_string();
+
+    let (mut parts, body) = req.into_parts();
+    let bytes = axum::body::to_bytes(body, DEFAULT_JSON_BODY_LIMIT)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+
+    // Callers sign the path they requested, before a version prefix is stripped by nesting
+    let path = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| parts.uri.path().to_string());
+
+    app_state
+        .service_auth
+        .verify(
+            &service,
+            timestamp,
+            &signature,
+            parts.method.as_str(),
+            &path,
+            &bytes,
+            app_state.clock.now().timestamp(),
+        )
+        .await?;
+
+    parts.extensions.insert(Caller::Service(service));
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
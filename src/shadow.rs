@@ -0,0 +1,98 @@
+use crate::error::AppResult;
+use crate::feature_flags::FeatureFlags;
+use crate::metrics::{Metrics, SHADOW_COMPARISONS_TOTAL};
+
+/// Implementation of a shadowed decision whose result callers get
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowPath {
+    Current,
+    Candidate,
+}
+
+impl ShadowPath {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShadowPath::Current => "current",
+            ShadowPath::Candidate => "candidate",
+        }
+    }
+}
+
+/// What the two implementations of a decision are compared on
+///
+/// Keys leave out whatever both paths are expected to differ in, e.g. rows they loaded.
+pub trait ShadowCompare {
+    fn shadow_key(&self) -> String;
+}
+
+impl<T: ShadowCompare> ShadowCompare for AppResult<T> {
+    fn shadow_key(&self) -> String {
+        match self {
+            Ok(value) => value.shadow_key(),
+            Err(e) => format!("error {:?}", e.code()),
+        }
+    }
+}
+
+/// A decision moving to a new implementation, rolled out through two feature flags
+///
+/// With `shadow_flag` on both implementations run and the current one answers. With
+/// `candidate_flag` on the candidate answers and the current one keeps running next to it,
+/// so switching back is a flag flip. Every comparison is counted in `SHADOW_COMPARISONS_TOTAL`
+/// and divergences are logged.
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowExperiment {
+    /// `experiment` label of the comparisons
+    pub name: &'static str,
+    pub shadow_flag: &'static str,
+    pub candidate_flag: &'static str,
+}
+
+impl ShadowExperiment {
+    /// Path whose result callers get, `None` while the candidate isn't run at all
+    pub fn authoritative(&self, flags: &FeatureFlags) -> Option<ShadowPath> {
+        if flags.is_enabled(self.candidate_flag) {
+            Some(ShadowPath::Candidate)
+        } else if flags.is_enabled(self.shadow_flag) {
+            Some(ShadowPath::Current)
+        } else {
+            None
+        }
+    }
+
+    /// Compare the results both paths reached for `subject` and return the authoritative one
+    pub fn settle<T: ShadowCompare>(
+        &self,
+        metrics: &Metrics,
+        subject: &str,
+        authoritative: ShadowPath,
+        current: T,
+        candidate: T,
+    ) -> T {
+        let current_key = current.shadow_key();
+        let candidate_key = candidate.shadow_key();
+        let outcome = if current_key == candidate_key {
+            "match"
+        } else {
+            println!(
+                "Shadow {} diverged for {}: current {}, candidate {} ({} answered)",
+                self.name,
+                subject,
+                current_key,
+                candidate_key,
+                authoritative.as_str()
+            );
+            "divergence"
+        };
+        metrics.inc_counter(
+            SHADOW_COMPARISONS_TOTAL,
+            &[("experiment", self.name), ("outcome", outcome)],
+            1,
+        );
+
+        match authoritative {
+            ShadowPath::Current => current,
+            ShadowPath::Candidate => candidate,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use base64::prelude::*;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use crate::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use crate::self_test::{CheckOutcome, CheckResult};
+
+/// Package name sent with the smoke test's verification and test notification
+pub const SMOKE_TEST_PACKAGE_NAME: &str = "com.yral.android";
+
+/// Where and as whom `smoke-test` calls the service
+#[derive(Debug, Clone)]
+pub struct SmokeTarget {
+    /// Base URL of the deployment, e.g. `https://billing-staging.yral.com`
+    pub base_url: String,
+    /// User JWT for the credits round-trip, which is skipped without one
+    pub token: Option<String>,
+    /// OIDC token sent as Pub/Sub would, needed when the deployment checks push auth
+    pub push_token: Option<String>,
+    /// Principal whose credits are added and taken back
+    pub principal: String,
+}
+
+impl SmokeTarget {
+    /// Target at `base_url`, tokens from `SMOKE_TEST_JWT` and `SMOKE_TEST_PUSH_TOKEN`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: std::env::var("SMOKE_TEST_JWT").ok(),
+            push_token: std::env::var("SMOKE_TEST_PUSH_TOKEN").ok(),
+            principal: MOCK_SUBSCRIPTION_ACCOUNT_ID.to_string(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+/// Pass/fail report printed by `smoke-test`
+#[derive(Debug, Default)]
+pub struct SmokeTestReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl SmokeTestReport {
+    fn record(&mut self, name: &'static str, outcome: CheckOutcome) {
+        self.checks.push(CheckResult { name, outcome });
+    }
+
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for SmokeTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Smoke test report:")?;
+        for check in &self.checks {
+            let (label, detail) = match &check.outcome {
+                CheckOutcome::Passed(detail) => ("PASS", detail),
+                CheckOutcome::Failed(detail) => ("FAIL", detail),
+                CheckOutcome::Skipped(detail) => ("SKIP", detail),
+            };
+            writeln!(f, "  [{}] {}: {}", label, check.name, detail)?;
+        }
+        write!(
+            f,
+            "Smoke test {}",
+            if self.passed() { "passed" } else { "failed" }
+        )
+    }
+}
+
+/// Exercise a running deployment end to end through its public API
+///
+/// The verification uses the mock Google Play account, so the deployment must run with
+/// mock integrations for it to pass.
+pub async fn run_smoke_test(target: &SmokeTarget) -> SmokeTestReport {
+    let client = Client::new();
+    let mut report = SmokeTestReport::default();
+
+    report.record("health", check_health(&client, target).await);
+    report.record("openapi spec", check_openapi(&client, target).await);
+    report.record(
+        "mock verification",
+        check_verification(&client, target).await,
+    );
+    report.record("rtdn test notification", check_rtdn(&client, target).await);
+
+    match &target.token {
+        Some(token) => report.record(
+            "credits round-trip",
+            check_credits(&client, target, token).await,
+        ),
+        None => report.record(
+            "credits round-trip",
+            CheckOutcome::Skipped("SMOKE_TEST_JWT is not set".to_string()),
+        ),
+    }
+
+    report
+}
+
+/// Send `request`, passing when the status is a success and `expect` accepts the body
+async fn expect_success(
+    request: RequestBuilder,
+    expect: impl FnOnce(&str) -> Result<String, String>,
+) -> CheckOutcome {
+    let res = match request.send().await {
+        Ok(res) => res,
+        Err(e) => return CheckOutcome::Failed(format!("Request failed: {}", e)),
+    };
+
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return CheckOutcome::Failed(format!("{}: {}", status, body));
+    }
+    match expect(&body) {
+        Ok(detail) => CheckOutcome::Passed(detail),
+        Err(detail) => CheckOutcome::Failed(detail),
+    }
+}
+
+/// `success` of an `ApiResponse` body
+fn api_success(body: &str) -> Result<Value, String> {
+    let value: Value =
+        serde_json::from_str(body).map_err(|e| format!("Response is not JSON: {}", e))?;
+    if value["success"] == true {
+        Ok(value)
+    } else {
+        Err(format!("Request was not successful: {}", body))
+    }
+}
+
+async fn check_health(client: &Client, target: &SmokeTarget) -> CheckOutcome {
+    expect_success(client.get(target.url("/health")), |body| {
+        let value: Value =
+            serde_json::from_str(body).map_err(|e| format!("Response is not JSON: {}", e))?;
+        match value["status"].as_str() {
+            Some("ok") => Ok("ok".to_string()),
+            _ => Err(format!("Unexpected health: {}", body)),
+        }
+    })
+    .await
+}
+
+async fn check_openapi(client: &Client, target: &SmokeTarget) -> CheckOutcome {
+    expect_success(client.get(target.url("/api-doc/openapi.json")), |body| {
+        let value: Value =
+            serde_json::from_str(body).map_err(|e| format!("Spec is not JSON: {}", e))?;
+        match value["paths"].as_object() {
+            Some(paths) if paths.contains_key("/google/verify") => {
+                Ok(format!("{} paths", paths.len()))
+            }
+            _ => Err("Spec does not document /google/verify".to_string()),
+        }
+    })
+    .await
+}
+
+/// Verify a fresh token for the mock Google Play account
+async fn check_verification(client: &Client, target: &SmokeTarget) -> CheckOutcome {
+    let purchase_token = format!("smoke-test-{}", uuid::Uuid::new_v4());
+    let request = client.post(target.url("/google/verify")).json(&json!({
+        "user_id": MOCK_SUBSCRIPTION_ACCOUNT_ID,
+        "package_name": SMOKE_TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "purchase_token": purchase_token,
+    }));
+
+    expect_success(request, |body| {
+        api_success(body)?;
+        Ok(format!("verified {}", purchase_token))
+    })
+    .await
+}
+
+/// Pub/Sub push envelope carrying a Play Console test notification
+pub fn test_notification_envelope(now: chrono::DateTime<chrono::Utc>) -> Value {
+    let notification = json!({
+        "version": "1.0",
+        "packageName": SMOKE_TEST_PACKAGE_NAME,
+        "eventTimeMillis": now.timestamp_millis().to_string(),
+        "testNotification": { "version": "1.0" },
+    });
+    json!({
+        "message": {
+            "data": BASE64_STANDARD.encode(notification.to_string()),
+            "messageId": format!("smoke-test-{}", uuid::Uuid::new_v4()),
+            "publishTime": now.to_rfc3339(),
+        },
+        "subscription": "projects/yral/subscriptions/smoke-test",
+    })
+}
+
+async fn check_rtdn(client: &Client, target: &SmokeTarget) -> CheckOutcome {
+    let mut request = client
+        .post(target.url("/google/rtdn-webhook"))
+        .json(&test_notification_envelope(chrono::Utc::now()));
+    if let Some(push_token) = &target.push_token {
+        request = request.bearer_auth(push_token);
+    }
+
+    expect_success(request, |_| Ok("accepted".to_string())).await
+}
+
+/// Add one credit to the test principal and take it back
+async fn check_credits(client: &Client, target: &SmokeTarget, token: &str) -> CheckOutcome {
+    for path in ["/credits/increment", "/credits/deduct"] {
+        let request = client
+            .post(target.url(path))
+            .bearer_auth(token)
+            .json(&json!({
+                "user_principal": target.principal,
+                "amount": 1,
+                "correlation_id": "smoke-test",
+            }));
+        let outcome = expect_success(request, |body| api_success(body).map(|_| String::new()));
+        if let CheckOutcome::Failed(detail) = outcome.await {
+            return CheckOutcome::Failed(format!("{}: {}", path, detail));
+        }
+    }
+
+    CheckOutcome::Passed(format!(
+        "added and deducted 1 credit for {}",
+        target.principal
+    ))
+}
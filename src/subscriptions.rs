@@ -0,0 +1,230 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::token_versions::ensure_unchanged;
+use crate::error::AppResult;
+use crate::integrations::user_info::UserInfoApi;
+use crate::model::{PurchaseToken, Subscription};
+use crate::token_state::{TokenStateMachine, TransitionReason};
+use crate::types::{PurchaseTokenStatus, SubscriptionSource, SubscriptionStatus};
+
+/// Active subscription of `user` from any source, the one lasting longest first
+pub fn active_subscription(
+    conn: &mut SqliteConnection,
+    user: &str,
+    now: NaiveDateTime,
+) -> AppResult<Option<Subscription>> {
+    use crate::schema::subscriptions::dsl::*;
+
+    Ok(subscriptions
+        .filter(user_id.eq(user))
+        .filter(status.eq(SubscriptionStatus::Active))
+        .filter(expires_at.gt(now))
+        .order(expires_at.desc())
+        .first(conn)
+        .optional()?)
+}
+
+/// Whether `user` holds an active subscription other than `source`/`reference`
+///
+/// Such a subscription already grants Pro, so this one must neither grant it again nor
+/// revoke it.
+pub fn covered_by_other_subscription(
+    conn: &mut SqliteConnection,
+    user: &str,
+    source_param: SubscriptionSource,
+    reference: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::subscriptions::dsl::*;
+
+    let others: i64 = subscriptions
+        .filter(user_id.eq(user))
+        .filter(status.eq(SubscriptionStatus::Active))
+        .filter(expires_at.gt(now))
+        .filter(source.ne(source_param).or(source_ref.ne(reference)))
+        .count()
+        .get_result(conn)?;
+
+    Ok(others > 0)
+}
+
+/// Grant Pro on the IC for a subscription unless another one of the user's already does
+///
+/// Returns whether the canister was called. Prevents double credits for users subscribed
+/// through more than one source.
+#[allow(clippy::too_many_arguments)]
+pub async fn grant_pro_for_subscription(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    product_id: &str,
+    user: &str,
+    source: SubscriptionSource,
+    reference: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    if covered_by_other_subscription(conn, user, source, reference, now)? {
+        println!(
+            "User {} already has Pro through another subscription, not granting for {}",
+            user, reference
+        );
+        return Ok(false);
+    }
+
+    user_info.grant_pro_plan(product_id, user).await?;
+    Ok(true)
+}
+
+/// Keep Pro on the IC for a renewed subscription, like `grant_pro_for_subscription` but leaving
+/// the user's credits for the renewal top-up
+///
+/// Returns whether the canister was called.
+#[allow(clippy::too_many_arguments)]
+pub async fn renew_pro_for_subscription(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    product_id: &str,
+    user: &str,
+    source: SubscriptionSource,
+    reference: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    if covered_by_other_subscription(conn, user, source, reference, now)? {
+        println!(
+            "User {} already has Pro through another subscription, not renewing for {}",
+            user, reference
+        );
+        return Ok(false);
+    }
+
+    user_info.renew_pro_plan(product_id, user).await?;
+    Ok(true)
+}
+
+/// Revoke Pro on the IC for an ended subscription unless another one still grants it
+///
+/// Returns whether the canister was called.
+pub async fn revoke_pro_for_subscription(
+    conn: &mut SqliteConnection,
+    user_info: &dyn UserInfoApi,
+    user: &str,
+    source: SubscriptionSource,
+    reference: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    if covered_by_other_subscription(conn, user, source, reference, now)? {
+        println!(
+            "User {} keeps Pro through another subscription, not revoking for {}",
+            user, reference
+        );
+        return Ok(false);
+    }
+
+    user_info.revoke_pro_plan(user).await?;
+    Ok(true)
+}
+
+/// Store or update a subscription from a source that doesn't go through `purchase_tokens`
+///
+/// Rows are matched on `source` and `source_ref`, the stored id is kept on update.
+pub fn upsert_subscription(
+    conn: &mut SqliteConnection,
+    subscription: &Subscription,
+) -> AppResult<()> {
+    use crate::schema::subscriptions::dsl::*;
+
+    diesel::insert_into(subscriptions)
+        .values(subscription)
+        .on_conflict((source, source_ref))
+        .do_update()
+        .set((
+            user_id.eq(&subscription.user_id),
+            status.eq(subscription.status),
+            product_id.eq(&subscription.product_id),
+            expires_at.eq(subscription.expires_at),
+            auto_renewing.eq(subscription.auto_renewing),
+            updated_at.eq(subscription.updated_at),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Whether another of `user`'s purchase tokens than `reference` grants Pro until after `now`
+///
+/// A renewal of a token already replaced must not grant Pro a second time.
+pub fn granted_by_other_token(
+    conn: &mut SqliteConnection,
+    user: &str,
+    reference: &str,
+    now: NaiveDateTime,
+) -> AppResult<bool> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let others: i64 = purchase_tokens
+        .filter(user_id.eq(user))
+        .filter(purchase_token.ne(reference))
+        .filter(status.eq(PurchaseTokenStatus::AccessGranted))
+        .filter(expiry_at.gt(now))
+        .count()
+        .get_result(conn)?;
+
+    Ok(others > 0)
+}
+
+/// Expire all but the longest lasting of `user`'s purchase tokens granting access
+///
+/// A user who buys again from another Google account ends up with two tokens, each granting
+/// Pro again on renewal. The expired ones link to the token kept through `replaced_by`, and
+/// their purchase tokens are returned. The kept token may have been replaced before and renewed
+/// since, so its own link is cleared. Access on the IC is left alone, the kept token still
+/// grants it.
+pub fn replace_duplicate_tokens(conn: &mut SqliteConnection, user: &str) -> AppResult<Vec<String>> {
+    use crate::schema::purchase_tokens::dsl::*;
+
+    let granted: Vec<PurchaseToken> = purchase_tokens
+        .filter(user_id.eq(user))
+        .filter(status.eq(PurchaseTokenStatus::AccessGranted))
+        .order((expiry_at.desc(), created_at.desc(), id.desc()))
+        .load(conn)?;
+    let Some((kept, others)) = granted.split_first() else {
+        return Ok(Vec::new());
+    };
+    if others.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if kept.replaced_by.is_some() {
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&kept.id))
+                .filter(version.eq(kept.version)),
+        )
+        .set(replaced_by.eq(None::<String>))
+        .execute(conn)?;
+        ensure_unchanged(updated, kept)?;
+    }
+
+    let mut replaced = Vec::new();
+    for token in others {
+        let next_status = TokenStateMachine::next(token.status, TransitionReason::Replaced)?;
+        let updated = diesel::update(
+            purchase_tokens
+                .filter(id.eq(&token.id))
+                .filter(version.eq(token.version)),
+        )
+        .set((
+            status.eq(next_status),
+            replaced_by.eq(Some(&kept.purchase_token)),
+        ))
+        .execute(conn)?;
+        ensure_unchanged(updated, token)?;
+        println!(
+            "Purchase token {} of user {} replaced by {}",
+            token.purchase_token, user, kept.purchase_token
+        );
+        replaced.push(token.purchase_token.clone());
+    }
+
+    Ok(replaced)
+}
@@ -0,0 +1,540 @@
+//! Builders for tests, compiled for the crate's own tests and with the `test-support` feature
+//!
+//! ```ignore
+//! let app_state = memory_state().await;
+//! let mut conn = app_state.get_db_connection().unwrap();
+//! let token = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::Request;
+use axum::{Extension, Router};
+use base64::prelude::*;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use ic_agent::export::Principal;
+
+use crate::auth::Claims;
+use crate::db::MEMORY_DATABASE_URL;
+use crate::error::AppResult;
+use crate::integrations::google_play::{GooglePlayApi, MockGooglePlay};
+use crate::integrations::push_auth::MOCK_PUSH_TOKEN;
+use crate::model::PurchaseToken;
+use crate::types::{
+    AcknowledgementState, ExternalAccountIdentifiers, GooglePlayProductPurchaseV2,
+    GooglePlaySubscriptionResponse, MonetizationSubscription, OneTimeProductNotificationType,
+    PurchaseEnvironment, PurchaseTokenStatus, SubscriptionNotificationType, SubscriptionState,
+    TestPurchase, VerifyRequest, VoidedProductType,
+};
+use crate::AppState;
+
+/// Package name the builders use unless told otherwise
+pub const TEST_PACKAGE_NAME: &str = "com.example";
+
+/// User id for `name`, the text of a principal made of its bytes so the API accepts it
+///
+/// The same name always gives the same id. Names are at most 29 bytes.
+pub fn test_user(name: &str) -> String {
+    Principal::from_slice(name.as_bytes()).to_text()
+}
+
+/// User id no other test uses
+pub fn new_test_user() -> String {
+    Principal::from_slice(uuid::Uuid::new_v4().as_bytes()).to_text()
+}
+
+/// Migrated database file that `DATABASE_URL` points at while the guard lives, for tests
+/// building their state through `AppState::new`
+///
+/// The previous `DATABASE_URL` is restored and the file removed on drop. Tests holding one must
+/// not run in parallel, which `RUST_TEST_THREADS=1` ensures.
+pub struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    pub fn new() -> Self {
+        let test_db = format!("./test_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        crate::run_migrations(&test_db).expect("test database migrations");
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    /// New connection to the guarded database
+    pub fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Default for TestDbGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// State with mocked integrations on its own in-memory database, no env juggling needed
+pub async fn memory_state() -> AppState {
+    AppState::try_with_database(MEMORY_DATABASE_URL)
+        .await
+        .expect("test state")
+}
+
+/// Every API route on `app_state`, without authentication
+///
+/// Handlers that read the caller's claims need them layered on with `as_user`.
+pub fn test_app(app_state: AppState) -> Router {
+    crate::api_routes().with_state(app_state)
+}
+
+/// `app` with every request carrying the JWT claims of `caller`
+pub fn as_user(app: Router, caller: &str) -> Router {
+    app.layer(Extension(Claims {
+        aud: "billing".to_string(),
+        exp: 0,
+        sub: Some(caller.to_string()),
+        scope: None,
+    }))
+}
+
+/// `PurchaseToken` row, granted for the next 30 days unless changed
+pub struct PurchaseTokenBuilder {
+    token: PurchaseToken,
+}
+
+impl PurchaseTokenBuilder {
+    pub fn new(user_id: &str) -> Self {
+        let mut token = PurchaseToken::new(
+            user_id.to_string(),
+            format!("token_{}", uuid::Uuid::new_v4()),
+            Utc::now().naive_utc() + chrono::Duration::days(30),
+            PurchaseTokenStatus::AccessGranted,
+            PurchaseEnvironment::Production,
+        );
+        token.package_name = Some(TEST_PACKAGE_NAME.to_string());
+        Self { token }
+    }
+
+    pub fn purchase_token(mut self, purchase_token: &str) -> Self {
+        self.token.purchase_token = purchase_token.to_string();
+        self
+    }
+
+    pub fn status(mut self, status: PurchaseTokenStatus) -> Self {
+        self.token.status = status;
+        self
+    }
+
+    pub fn expiry_at(mut self, expiry_at: NaiveDateTime) -> Self {
+        self.token.expiry_at = expiry_at;
+        self
+    }
+
+    pub fn created_at(mut self, created_at: NaiveDateTime) -> Self {
+        self.token.created_at = created_at;
+        self
+    }
+
+    pub fn environment(mut self, environment: PurchaseEnvironment) -> Self {
+        self.token.environment = environment;
+        self
+    }
+
+    pub fn package_name(mut self, package_name: Option<&str>) -> Self {
+        self.token.package_name = package_name.map(str::to_string);
+        self
+    }
+
+    pub fn auto_renewing(mut self, auto_renewing: bool) -> Self {
+        self.token.auto_renewing = auto_renewing;
+        self
+    }
+
+    pub fn replaced_by(mut self, purchase_token: &str) -> Self {
+        self.token.replaced_by = Some(purchase_token.to_string());
+        self
+    }
+
+    pub fn updated_at(mut self, updated_at: NaiveDateTime) -> Self {
+        self.token.updated_at = updated_at;
+        self
+    }
+
+    pub fn build(self) -> PurchaseToken {
+        self.token
+    }
+
+    /// Insert the row and return it
+    pub fn insert(self, conn: &mut SqliteConnection) -> PurchaseToken {
+        use crate::schema::purchase_tokens;
+
+        diesel::insert_into(purchase_tokens::table)
+            .values(&self.token)
+            .execute(conn)
+            .expect("insert purchase token");
+        self.token
+    }
+}
+
+/// `VerifyRequest` for a new purchase token of `TEST_PACKAGE_NAME`
+pub struct VerifyRequestBuilder {
+    payload: VerifyRequest,
+}
+
+impl VerifyRequestBuilder {
+    pub fn new(user_id: &str) -> Self {
+        Self {
+            payload: VerifyRequest {
+                user_id: user_id.to_string(),
+                package_name: TEST_PACKAGE_NAME.to_string(),
+                product_id: "mock-product-id".to_string(),
+                purchase_token: format!("token_{}", uuid::Uuid::new_v4()),
+                integrity_token: None,
+            },
+        }
+    }
+
+    pub fn purchase_token(mut self, purchase_token: &str) -> Self {
+        self.payload.purchase_token = purchase_token.to_string();
+        self
+    }
+
+    pub fn package_name(mut self, package_name: &str) -> Self {
+        self.payload.package_name = package_name.to_string();
+        self
+    }
+
+    pub fn integrity_token(mut self, integrity_token: Option<&str>) -> Self {
+        self.payload.integrity_token = integrity_token.map(str::to_string);
+        self
+    }
+
+    pub fn build(self) -> VerifyRequest {
+        self.payload
+    }
+
+    /// `POST /google/verify` carrying the request
+    pub fn request(&self) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/google/verify")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&self.payload).unwrap()))
+            .unwrap()
+    }
+}
+
+/// Google subscription as `MockGooglePlay` reports it, but expiring in 30 days
+pub struct SubscriptionResponseBuilder {
+    response: GooglePlaySubscriptionResponse,
+}
+
+impl Default for SubscriptionResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubscriptionResponseBuilder {
+    pub fn new() -> Self {
+        Self {
+            response: MockGooglePlay::subscription(),
+        }
+        .expiry_at(Utc::now().naive_utc() + chrono::Duration::days(30))
+    }
+
+    pub fn state(mut self, state: SubscriptionState) -> Self {
+        self.response.subscription_state = state;
+        self
+    }
+
+    pub fn expiry_at(mut self, expiry_at: NaiveDateTime) -> Self {
+        self.response.line_items[0].expiry_time = Some(expiry_at.and_utc().to_rfc3339());
+        self
+    }
+
+    pub fn product_id(mut self, product_id: &str) -> Self {
+        self.response.line_items[0].product_id = product_id.to_string();
+        self
+    }
+
+    pub fn auto_renewing(mut self, auto_renewing: bool) -> Self {
+        self.response.line_items[0].auto_renewing = Some(auto_renewing);
+        self
+    }
+
+    /// Another line item like the first, e.g. an add-on, with its own product and expiry
+    pub fn add_line_item(mut self, product_id: &str, expiry_at: NaiveDateTime) -> Self {
+        let mut item = self.response.line_items[0].clone();
+        item.product_id = product_id.to_string();
+        item.expiry_time = Some(expiry_at.and_utc().to_rfc3339());
+        self.response.line_items.push(item);
+        self
+    }
+
+    /// Bought through `offer_id` of the mock base plan, tagged with `offer_tags`
+    pub fn offer(mut self, offer_id: &str, offer_tags: &[&str]) -> Self {
+        if let Some(offer) = self.response.line_items[0].offer_details.as_mut() {
+            offer.offer_id = Some(offer_id.to_string());
+            offer.offer_tags = offer_tags.iter().map(|tag| tag.to_string()).collect();
+        }
+        self
+    }
+
+    pub fn order_id(mut self, order_id: &str) -> Self {
+        self.response.latest_order_id = Some(order_id.to_string());
+        self
+    }
+
+    /// Obfuscated account id of the purchase, none like purchases made outside the app
+    pub fn account_id(mut self, account_id: Option<&str>) -> Self {
+        self.response.external_account_identifiers =
+            account_id.map(|account_id| ExternalAccountIdentifiers {
+                external_account_id: None,
+                obfuscated_external_account_id: Some(account_id.to_string()),
+                obfuscated_external_profile_id: None,
+            });
+        self
+    }
+
+    pub fn acknowledged(mut self) -> Self {
+        self.response.acknowledgement_state = AcknowledgementState::Acknowledged;
+        self
+    }
+
+    /// Made by a license tester
+    pub fn sandbox(mut self) -> Self {
+        self.response.test_purchase = Some(TestPurchase {});
+        self
+    }
+
+    pub fn build(self) -> GooglePlaySubscriptionResponse {
+        self.response
+    }
+}
+
+/// Google Play reporting the same subscription for every token, other calls as `MockGooglePlay`
+pub struct FixedGooglePlay {
+    response: Mutex<GooglePlaySubscriptionResponse>,
+}
+
+impl FixedGooglePlay {
+    pub fn new(response: GooglePlaySubscriptionResponse) -> Arc<Self> {
+        Arc::new(Self {
+            response: Mutex::new(response),
+        })
+    }
+
+    /// Report `response` from now on, e.g. after a renewal
+    pub fn set(&self, response: GooglePlaySubscriptionResponse) {
+        *self.response.lock().unwrap() = response;
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for FixedGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        _package_name: &str,
+        _purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        Ok(self.response.lock().unwrap().clone())
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: DateTime<Utc>,
+        desired_expiry: DateTime<Utc>,
+    ) -> AppResult<DateTime<Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+/// Pub/Sub push envelope of a Real-time developer notification
+pub struct RtdnBuilder {
+    package_name: String,
+    event_time: DateTime<Utc>,
+    /// Notification kind key and body, e.g. `subscriptionNotification`
+    kind: &'static str,
+    body: serde_json::Value,
+}
+
+impl RtdnBuilder {
+    fn new(kind: &'static str, body: serde_json::Value) -> Self {
+        Self {
+            package_name: TEST_PACKAGE_NAME.to_string(),
+            event_time: Utc::now(),
+            kind,
+            body,
+        }
+    }
+
+    pub fn subscription(
+        notification_type: SubscriptionNotificationType,
+        purchase_token: &str,
+    ) -> Self {
+        Self::new(
+            "subscriptionNotification",
+            serde_json::json!({
+                "version": "1.0",
+                "notificationType": notification_type,
+                "purchaseToken": purchase_token,
+                "subscriptionId": "mock-product-id",
+            }),
+        )
+    }
+
+    pub fn one_time_product(
+        notification_type: OneTimeProductNotificationType,
+        purchase_token: &str,
+    ) -> Self {
+        Self::new(
+            "oneTimeProductNotification",
+            serde_json::json!({
+                "version": "1.0",
+                "notificationType": notification_type,
+                "purchaseToken": purchase_token,
+                "sku": "mock-product-id",
+            }),
+        )
+    }
+
+    pub fn voided(product_type: VoidedProductType, purchase_token: &str) -> Self {
+        Self::new(
+            "voidedPurchaseNotification",
+            serde_json::json!({
+                "purchaseToken": purchase_token,
+                "orderId": "GPA.0000-0000-0000-00000",
+                "productType": product_type,
+                "refundType": 1,
+            }),
+        )
+    }
+
+    /// The notification the Play Console sends from "Send test notification"
+    pub fn test() -> Self {
+        Self::new("testNotification", serde_json::json!({ "version": "1.0" }))
+    }
+
+    pub fn package_name(mut self, package_name: &str) -> Self {
+        self.package_name = package_name.to_string();
+        self
+    }
+
+    pub fn event_time(mut self, event_time: DateTime<Utc>) -> Self {
+        self.event_time = event_time;
+        self
+    }
+
+    /// Body Pub/Sub posts to the webhook
+    pub fn envelope(&self) -> serde_json::Value {
+        let mut notification = serde_json::json!({
+            "version": "1.0",
+            "packageName": self.package_name,
+            "eventTimeMillis": self.event_time.timestamp_millis().to_string(),
+        });
+        notification[self.kind] = self.body.clone();
+
+        serde_json::json!({
+            "message": {
+                "data": BASE64_STANDARD.encode(notification.to_string()),
+                "messageId": uuid::Uuid::new_v4().to_string(),
+                "publishTime": Utc::now().to_rfc3339(),
+            }
+        })
+    }
+
+    /// `POST /google/rtdn-webhook` carrying the envelope, with the push token mock
+    /// integrations accept
+    pub fn request(&self) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/google/rtdn-webhook")
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", MOCK_PUSH_TOKEN))
+            .body(Body::from(self.envelope().to_string()))
+            .unwrap()
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Per purchase token locks serializing the processing of its notifications
+///
+/// SQLite has no row locks and a write transaction can't be held across the Google Play and
+/// IC calls a handler makes, so changes to one token are serialized in process instead.
+#[derive(Clone, Default)]
+pub struct TokenLocks {
+    locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl TokenLocks {
+    /// Wait until no other task holds `purchase_token`, released when the guard is dropped
+    pub async fn lock(&self, purchase_token: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Entries only referenced by the map aren't held or awaited by anyone
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(purchase_token.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
@@ -0,0 +1,187 @@
+use crate::error::{AppError, AppResult};
+use crate::types::PurchaseTokenStatus::{self, AccessGranted, Expired, Pending};
+
+/// Why a stored purchase token changes status
+///
+/// Every reason leads to exactly one status; the machine decides which statuses it may
+/// start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionReason {
+    /// Verify stored the purchase before granting, Google reported it active or payment pending
+    Verified,
+    /// Google reported the purchase paid and Pro was granted for it
+    PaymentCompleted,
+    /// Google reported a new paid period, after a renewal or a recovered payment
+    Renewed,
+    /// A purchase notification arrived for a token that already grants access
+    Refreshed,
+    /// The paid period ran out, or Google expired the subscription or put it on hold
+    Lapsed,
+    /// A pending payment was canceled or declined
+    PaymentAbandoned,
+    /// Google revoked the subscription, refunding it
+    Revoked,
+    /// Google voided the purchase
+    Voided,
+    /// An operator approved a refund for the purchase
+    Refunded,
+    /// An upgrade or downgrade replaced the token with a linked one
+    Superseded,
+    /// The user bought again from another Google account and the other token lasts longer
+    Replaced,
+    /// An operator revoked the user's Pro access
+    OperatorRevoked,
+    /// The user's subscriptions were torn down
+    TornDown,
+}
+
+impl TransitionReason {
+    pub const ALL: [TransitionReason; 13] = [
+        TransitionReason::Verified,
+        TransitionReason::PaymentCompleted,
+        TransitionReason::Renewed,
+        TransitionReason::Refreshed,
+        TransitionReason::Lapsed,
+        TransitionReason::PaymentAbandoned,
+        TransitionReason::Revoked,
+        TransitionReason::Voided,
+        TransitionReason::Refunded,
+        TransitionReason::Superseded,
+        TransitionReason::Replaced,
+        TransitionReason::OperatorRevoked,
+        TransitionReason::TornDown,
+    ];
+
+    /// Whether Google confirmed a payment or an active purchase for this change
+    pub fn confirms_payment(&self) -> bool {
+        matches!(
+            self,
+            TransitionReason::Verified
+                | TransitionReason::PaymentCompleted
+                | TransitionReason::Renewed
+        )
+    }
+}
+
+/// One allowed change of a purchase token's status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub reason: TransitionReason,
+    pub from: &'static [PurchaseTokenStatus],
+    pub to: PurchaseTokenStatus,
+}
+
+/// Every status change a purchase token may go through
+///
+/// An expired token only comes back through a payment Google confirms; a purchase
+/// notification alone doesn't revive it. Ending a subscription again is a no-op, so
+/// redelivered notifications don't fail.
+const TRANSITIONS: [Transition; 13] = [
+    Transition {
+        reason: TransitionReason::Verified,
+        from: &[Pending, AccessGranted, Expired],
+        to: Pending,
+    },
+    Transition {
+        reason: TransitionReason::PaymentCompleted,
+        from: &[Pending, AccessGranted, Expired],
+        to: AccessGranted,
+    },
+    Transition {
+        reason: TransitionReason::Renewed,
+        from: &[AccessGranted, Expired],
+        to: AccessGranted,
+    },
+    Transition {
+        reason: TransitionReason::Refreshed,
+        from: &[AccessGranted],
+        to: AccessGranted,
+    },
+    Transition {
+        reason: TransitionReason::Lapsed,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::PaymentAbandoned,
+        from: &[Pending, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::Revoked,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::Voided,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::Refunded,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::Superseded,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::Replaced,
+        from: &[AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::OperatorRevoked,
+        from: &[AccessGranted, Expired],
+        to: Expired,
+    },
+    Transition {
+        reason: TransitionReason::TornDown,
+        from: &[Pending, AccessGranted, Expired],
+        to: Expired,
+    },
+];
+
+/// Guard for purchase token status changes, used by every writer of `purchase_tokens.status`
+pub struct TokenStateMachine;
+
+impl TokenStateMachine {
+    /// The transition `reason` stands for
+    pub fn transition(reason: TransitionReason) -> &'static Transition {
+        TRANSITIONS
+            .iter()
+            .find(|transition| transition.reason == reason)
+            .expect("every transition reason is listed in TRANSITIONS")
+    }
+
+    /// Statuses a token may have for `reason` to apply, for writers updating many rows at once
+    pub fn sources(reason: TransitionReason) -> &'static [PurchaseTokenStatus] {
+        Self::transition(reason).from
+    }
+
+    /// Whether a token in `from` may change status for `reason`
+    pub fn allows(from: PurchaseTokenStatus, reason: TransitionReason) -> bool {
+        Self::sources(reason).contains(&from)
+    }
+
+    /// Status a token in `from` moves to for `reason`
+    ///
+    /// Checked before any side effect, so a refused change leaves nothing half done.
+    pub fn next(
+        from: PurchaseTokenStatus,
+        reason: TransitionReason,
+    ) -> AppResult<PurchaseTokenStatus> {
+        let transition = Self::transition(reason);
+        if !transition.from.contains(&from) {
+            return Err(AppError::InvalidTransition {
+                from,
+                to: transition.to,
+                reason,
+            });
+        }
+
+        Ok(transition.to)
+    }
+}
@@ -15,10 +15,96 @@ pub struct ApiResponse<T: ToSchema> {
     pub msg: Option<String>,
     /// Optional error message (present when success is false)
     pub error: Option<String>,
+    /// Stable machine-readable code, present when success is false or an accepted request
+    /// needs one to tell its outcome apart
+    pub code: Option<ErrorCode>,
     /// Response data (present when success is true)
     pub data: Option<T>,
 }
 
+/// One page of a list endpoint, pages are followed with `next_cursor`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PaginatedResponse<T: ToSchema> {
+    pub items: Vec<T>,
+    /// Pass as `cursor` to get the next page, absent on the last page
+    pub next_cursor: Option<String>,
+    /// Rows in the whole listing when the page was read, it may change while paging
+    pub total_estimate: Option<i64>,
+}
+
+/// Stable error codes clients can branch on instead of parsing error messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    DatabaseConnection,
+    DatabaseOperation,
+    GooglePlayApi,
+    GooglePlayVerification,
+    AuthServiceUnavailable,
+    AdminIcAgentMissing,
+    AccessTokenFailed,
+    TokenAlreadyUsed,
+    TokenExpired,
+    SubscriptionCanceled,
+    SubscriptionExpired,
+    SubscriptionOnHold,
+    SubscriptionPaused,
+    SubscriptionInvalidLineItems,
+    SubscriptionInvalidState,
+    SubscriptionNoState,
+    GooglePlayResponseParse,
+    GooglePlayConnection,
+    AcknowledgmentFailed,
+    ServiceAccessFailed,
+    NetworkError,
+    InternalError,
+    BadRequest,
+    ExternalAccountIdentifiersMissing,
+    SandboxPurchaseNotHonored,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    AccountMismatch,
+    PackageDisabled,
+    /// Google Play is rate limiting or out of quota, retry after the `Retry-After` header
+    GooglePlayThrottled,
+    /// Google Play doesn't know the purchase token, retrying won't help
+    GooglePlayTokenNotFound,
+    /// The service account lacks permission for the package
+    GooglePlayPermissionDenied,
+    /// Google Play failed on its side, retrying later may succeed
+    GooglePlayUnavailable,
+    /// The user is flagged for refund abuse and waits for an operator's approval
+    ManualApprovalRequired,
+    /// The request's Play Integrity verdict is missing or doesn't vouch for the app and device
+    IntegrityCheckFailed,
+    /// The purchase's current status doesn't allow the requested change
+    InvalidTransition,
+    /// No signing key is configured, so entitlement tokens can't be issued
+    EntitlementTokensDisabled,
+    /// Google confirmed the payment while the IC is unreachable, Pro is granted once it recovers
+    GrantQueued,
+    /// The caller sent too many requests, retry after the `Retry-After` header
+    RateLimited,
+    /// The verification looked suspicious and waits for an operator's approval
+    VerificationQuarantined,
+    /// An operator rejected the quarantined verification of this purchase
+    VerificationRejected,
+    /// The user info canister has no such user
+    UserNotFound,
+    /// The user is already on the Pro plan
+    AlreadyPro,
+    /// The user info canister is stopped for an upgrade, retrying later may succeed
+    CanisterStopped,
+    /// The user info canister refused the call, retrying won't help
+    CanisterRejected,
+    /// The JWT belongs to another user than the one whose data was requested
+    NotOwner,
+    /// Another request changed the purchase meanwhile, retrying sees its change
+    TokenConflict,
+    /// The obfuscated profile id already belongs to another user
+    ProfileAlreadyRegistered,
+}
+
 /// Empty data type for API responses without payload
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct EmptyData;
@@ -30,6 +116,7 @@ impl<T: utoipa::ToSchema> ApiResponse<T> {
             success: true,
             msg: None,
             error: None,
+            code: None,
             data: Some(data),
         }
     }
@@ -40,6 +127,18 @@ impl<T: utoipa::ToSchema> ApiResponse<T> {
             success: true,
             msg: Some(msg),
             error: None,
+            code: None,
+            data: Some(data),
+        }
+    }
+
+    /// Create a successful response whose outcome clients branch on by `code`
+    pub fn success_with_code(data: T, code: ErrorCode, msg: String) -> Self {
+        Self {
+            success: true,
+            msg: Some(msg),
+            error: None,
+            code: Some(code),
             data: Some(data),
         }
     }
@@ -50,6 +149,18 @@ impl<T: utoipa::ToSchema> ApiResponse<T> {
             success: false,
             msg: None,
             error: Some(error),
+            code: None,
+            data: None,
+        }
+    }
+
+    /// Create an error response with a machine-readable error code
+    pub fn error_with_code(code: ErrorCode, error: String) -> Self {
+        Self {
+            success: false,
+            msg: None,
+            error: Some(error),
+            code: Some(code),
             data: None,
         }
     }
@@ -60,6 +171,7 @@ impl<T: utoipa::ToSchema> ApiResponse<T> {
             success: false,
             msg: Some(msg),
             error: Some(error),
+            code: None,
             data: None,
         }
     }
@@ -72,6 +184,7 @@ impl ApiResponse<()> {
             success: true,
             msg: None,
             error: None,
+            code: None,
             data: Some(()),
         }
     }
@@ -82,6 +195,7 @@ impl ApiResponse<()> {
             success: true,
             msg: Some(msg),
             error: None,
+            code: None,
             data: Some(()),
         }
     }
@@ -100,15 +214,19 @@ pub enum PurchaseTokenStatus {
     Expired,
 }
 
+impl PurchaseTokenStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PurchaseTokenStatus::Pending => "pending",
+            PurchaseTokenStatus::AccessGranted => "access_granted",
+            PurchaseTokenStatus::Expired => "expired",
+        }
+    }
+}
+
 impl ToSql<Text, Sqlite> for PurchaseTokenStatus {
     fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
-        match *self {
-            PurchaseTokenStatus::AccessGranted => {
-                <&str as ToSql<Text, Sqlite>>::to_sql(&"access_granted", out)
-            }
-            PurchaseTokenStatus::Expired => <&str as ToSql<Text, Sqlite>>::to_sql(&"expired", out),
-            PurchaseTokenStatus::Pending => <&str as ToSql<Text, Sqlite>>::to_sql(&"pending", out),
-        }
+        <&str as ToSql<Text, Sqlite>>::to_sql(&self.as_str(), out)
     }
 }
 
@@ -126,7 +244,47 @@ impl FromSql<Text, Sqlite> for PurchaseTokenStatus {
     }
 }
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum PurchaseEnvironment {
+    /// Real purchase made through Google Play
+    Production,
+    /// Test purchase made by a license tester
+    Sandbox,
+    /// Synthetic record of access granted by an operator, not backed by a Google purchase
+    Manual,
+}
+
+impl ToSql<Text, Sqlite> for PurchaseEnvironment {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            PurchaseEnvironment::Production => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"production", out)
+            }
+            PurchaseEnvironment::Sandbox => <&str as ToSql<Text, Sqlite>>::to_sql(&"sandbox", out),
+            PurchaseEnvironment::Manual => <&str as ToSql<Text, Sqlite>>::to_sql(&"manual", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for PurchaseEnvironment {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let env_str = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match env_str.as_str() {
+            "production" => Ok(PurchaseEnvironment::Production),
+            "sandbox" => Ok(PurchaseEnvironment::Sandbox),
+            "manual" => Ok(PurchaseEnvironment::Manual),
+            _ => Err("Invalid purchase environment".into()),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct VerifyRequest {
     /// Unique identifier for the user
     pub user_id: String,
@@ -136,13 +294,42 @@ pub struct VerifyRequest {
     pub product_id: String,
     /// Subscription purchase token from Google Play
     pub purchase_token: String,
+    /// Play Integrity token requested by the app for this purchase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity_token: Option<String>,
 }
 
 /// Empty response for verification endpoints
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct VerifyResponse {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyPreviewOutcome {
+    /// Verify would be a no-op, the caller already holds an active grant for this token
+    AlreadyGranted,
+    /// Verify would acknowledge the purchase and grant access
+    WouldGrant,
+    /// Payment is pending, verify would store the purchase and grant access once it completes
+    WouldDefer,
+}
+
+/// What `/google/verify` would do for a request, without doing it
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyPreviewResponse {
+    pub outcome: VerifyPreviewOutcome,
+    /// Account that gets Pro access, Google's obfuscated account id for new grants. Absent when
+    /// it is another user's.
+    pub grant_to_user_id: Option<String>,
+    /// When access would lapse unless renewed (RFC 3339), unknown while payment is pending
+    pub expiry_at: Option<String>,
+    pub environment: PurchaseEnvironment,
+    /// Whether verify would acknowledge the purchase with Google
+    pub would_acknowledge: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct AckRequest {
     /// Android package name
     pub package_name: String,
@@ -185,13 +372,55 @@ pub struct DeveloperNotification {
     pub one_time_product_notification: Option<OneTimeProductNotification>,
     #[serde(rename = "testNotification")]
     pub test_notification: Option<TestNotification>,
+    #[serde(rename = "voidedPurchaseNotification")]
+    pub voided_purchase_notification: Option<VoidedPurchaseNotification>,
+}
+
+impl DeveloperNotification {
+    /// When Google emitted the notification, `None` if `eventTimeMillis` is malformed
+    pub fn event_time(&self) -> Option<chrono::NaiveDateTime> {
+        self.event_time_millis
+            .parse::<i64>()
+            .ok()
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|time| time.naive_utc())
+    }
+
+    /// Kind and type of the notification for metrics, e.g. `subscription_revoked`
+    pub fn type_label(&self) -> String {
+        if let Some(notification) = &self.subscription_notification {
+            format!("subscription_{}", notification.notification_type.as_str())
+        } else if let Some(notification) = &self.one_time_product_notification {
+            format!(
+                "one_time_product_{}",
+                notification.notification_type.as_str()
+            )
+        } else if self.voided_purchase_notification.is_some() {
+            "voided_purchase".to_string()
+        } else if self.test_notification.is_some() {
+            "test".to_string()
+        } else {
+            "unrecognized".to_string()
+        }
+    }
+
+    /// Stored purchase token the notification changes, if any
+    pub fn subscription_purchase_token(&self) -> Option<&str> {
+        self.subscription_notification
+            .as_ref()
+            .map(|notification| notification.purchase_token.as_str())
+            .or(self
+                .voided_purchase_notification
+                .as_ref()
+                .map(|notification| notification.purchase_token.as_str()))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SubscriptionNotification {
     pub version: String,
     #[serde(rename = "notificationType")]
-    pub notification_type: i32,
+    pub notification_type: SubscriptionNotificationType,
     #[serde(rename = "purchaseToken")]
     pub purchase_token: String,
     #[serde(rename = "subscriptionId")]
@@ -202,12 +431,26 @@ pub struct SubscriptionNotification {
 pub struct OneTimeProductNotification {
     pub version: String,
     #[serde(rename = "notificationType")]
-    pub notification_type: i32,
+    pub notification_type: OneTimeProductNotificationType,
     #[serde(rename = "purchaseToken")]
     pub purchase_token: String,
     pub sku: String,
 }
 
+/// Sent when a purchase is refunded, charged back or otherwise voided
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VoidedPurchaseNotification {
+    #[serde(rename = "purchaseToken")]
+    pub purchase_token: String,
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    #[serde(rename = "productType")]
+    pub product_type: VoidedProductType,
+    /// 1 for a full refund, 2 for a partial quantity refund
+    #[serde(rename = "refundType")]
+    pub refund_type: Option<i32>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TestNotification {
     pub version: String,
@@ -227,31 +470,182 @@ pub struct PubSubData {
     pub publish_time: String,
 }
 
-// Notification types for subscriptions
-pub mod subscription_notification_type {
-    pub const SUBSCRIPTION_RECOVERED: i32 = 1;
-    pub const SUBSCRIPTION_RENEWED: i32 = 2;
-    pub const SUBSCRIPTION_CANCELED: i32 = 3;
-    pub const SUBSCRIPTION_PURCHASED: i32 = 4;
-    pub const SUBSCRIPTION_ON_HOLD: i32 = 5;
-    pub const SUBSCRIPTION_IN_GRACE_PERIOD: i32 = 6;
-    pub const SUBSCRIPTION_RESTARTED: i32 = 7;
-    pub const SUBSCRIPTION_PRICE_CHANGE_CONFIRMED: i32 = 8;
-    pub const SUBSCRIPTION_DEFERRED: i32 = 9;
-    pub const SUBSCRIPTION_PAUSED: i32 = 10;
-    pub const SUBSCRIPTION_PAUSE_SCHEDULE_CHANGED: i32 = 11;
-    pub const SUBSCRIPTION_REVOKED: i32 = 12;
-    pub const SUBSCRIPTION_EXPIRED: i32 = 13;
+impl PubSubData {
+    /// When Pub/Sub published the message, `None` if `publishTime` is malformed
+    pub fn publish_time(&self) -> Option<chrono::NaiveDateTime> {
+        chrono::DateTime::parse_from_rfc3339(&self.publish_time)
+            .ok()
+            .map(|time| time.naive_utc())
+    }
+}
+
+/// Subscription notification types sent by Google Play
+///
+/// Types added by Google after this list was written deserialize as `Unknown` instead of failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "i32", into = "i32")]
+pub enum SubscriptionNotificationType {
+    Recovered,
+    Renewed,
+    Canceled,
+    Purchased,
+    OnHold,
+    InGracePeriod,
+    Restarted,
+    PriceChangeConfirmed,
+    Deferred,
+    Paused,
+    PauseScheduleChanged,
+    Revoked,
+    Expired,
+    ItemsChanged,
+    PriceChangeUpdated,
+    PendingPurchaseCanceled,
+    Unknown(i32),
+}
+
+impl SubscriptionNotificationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionNotificationType::Recovered => "recovered",
+            SubscriptionNotificationType::Renewed => "renewed",
+            SubscriptionNotificationType::Canceled => "canceled",
+            SubscriptionNotificationType::Purchased => "purchased",
+            SubscriptionNotificationType::OnHold => "on_hold",
+            SubscriptionNotificationType::InGracePeriod => "in_grace_period",
+            SubscriptionNotificationType::Restarted => "restarted",
+            SubscriptionNotificationType::PriceChangeConfirmed => "price_change_confirmed",
+            SubscriptionNotificationType::Deferred => "deferred",
+            SubscriptionNotificationType::Paused => "paused",
+            SubscriptionNotificationType::PauseScheduleChanged => "pause_schedule_changed",
+            SubscriptionNotificationType::Revoked => "revoked",
+            SubscriptionNotificationType::Expired => "expired",
+            SubscriptionNotificationType::ItemsChanged => "items_changed",
+            SubscriptionNotificationType::PriceChangeUpdated => "price_change_updated",
+            SubscriptionNotificationType::PendingPurchaseCanceled => "pending_purchase_canceled",
+            SubscriptionNotificationType::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl From<i32> for SubscriptionNotificationType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Recovered,
+            2 => Self::Renewed,
+            3 => Self::Canceled,
+            4 => Self::Purchased,
+            5 => Self::OnHold,
+            6 => Self::InGracePeriod,
+            7 => Self::Restarted,
+            8 => Self::PriceChangeConfirmed,
+            9 => Self::Deferred,
+            10 => Self::Paused,
+            11 => Self::PauseScheduleChanged,
+            12 => Self::Revoked,
+            13 => Self::Expired,
+            17 => Self::ItemsChanged,
+            19 => Self::PriceChangeUpdated,
+            20 => Self::PendingPurchaseCanceled,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<SubscriptionNotificationType> for i32 {
+    fn from(value: SubscriptionNotificationType) -> Self {
+        match value {
+            SubscriptionNotificationType::Recovered => 1,
+            SubscriptionNotificationType::Renewed => 2,
+            SubscriptionNotificationType::Canceled => 3,
+            SubscriptionNotificationType::Purchased => 4,
+            SubscriptionNotificationType::OnHold => 5,
+            SubscriptionNotificationType::InGracePeriod => 6,
+            SubscriptionNotificationType::Restarted => 7,
+            SubscriptionNotificationType::PriceChangeConfirmed => 8,
+            SubscriptionNotificationType::Deferred => 9,
+            SubscriptionNotificationType::Paused => 10,
+            SubscriptionNotificationType::PauseScheduleChanged => 11,
+            SubscriptionNotificationType::Revoked => 12,
+            SubscriptionNotificationType::Expired => 13,
+            SubscriptionNotificationType::ItemsChanged => 17,
+            SubscriptionNotificationType::PriceChangeUpdated => 19,
+            SubscriptionNotificationType::PendingPurchaseCanceled => 20,
+            SubscriptionNotificationType::Unknown(other) => other,
+        }
+    }
+}
+
+/// One-time product notification types sent by Google Play
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "i32", into = "i32")]
+pub enum OneTimeProductNotificationType {
+    Purchased,
+    Canceled,
+    Unknown(i32),
+}
+
+impl OneTimeProductNotificationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OneTimeProductNotificationType::Purchased => "purchased",
+            OneTimeProductNotificationType::Canceled => "canceled",
+            OneTimeProductNotificationType::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl From<i32> for OneTimeProductNotificationType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Purchased,
+            2 => Self::Canceled,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<OneTimeProductNotificationType> for i32 {
+    fn from(value: OneTimeProductNotificationType) -> Self {
+        match value {
+            OneTimeProductNotificationType::Purchased => 1,
+            OneTimeProductNotificationType::Canceled => 2,
+            OneTimeProductNotificationType::Unknown(other) => other,
+        }
+    }
+}
+
+/// Kind of product a voided purchase was for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "i32", into = "i32")]
+pub enum VoidedProductType {
+    Subscription,
+    OneTime,
+    Unknown(i32),
+}
+
+impl From<i32> for VoidedProductType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Subscription,
+            2 => Self::OneTime,
+            other => Self::Unknown(other),
+        }
+    }
 }
 
-// Notification types for one-time products
-pub mod one_time_product_notification_type {
-    pub const ONE_TIME_PRODUCT_PURCHASED: i32 = 1;
-    pub const ONE_TIME_PRODUCT_CANCELED: i32 = 2;
+impl From<VoidedProductType> for i32 {
+    fn from(value: VoidedProductType) -> Self {
+        match value {
+            VoidedProductType::Subscription => 1,
+            VoidedProductType::OneTime => 2,
+            VoidedProductType::Unknown(other) => other,
+        }
+    }
 }
 
 // Google Play Subscriptions v2 API response types
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct GooglePlaySubscriptionResponse {
     pub kind: String,
     #[serde(rename = "startTime")]
@@ -259,11 +653,11 @@ pub struct GooglePlaySubscriptionResponse {
     #[serde(rename = "regionCode")]
     pub region_code: Option<String>,
     #[serde(rename = "subscriptionState")]
-    pub subscription_state: String,
+    pub subscription_state: SubscriptionState,
     #[serde(rename = "latestOrderId")]
     pub latest_order_id: Option<String>,
     #[serde(rename = "acknowledgementState")]
-    pub acknowledgement_state: String,
+    pub acknowledgement_state: AcknowledgementState,
     #[serde(rename = "lineItems")]
     pub line_items: Vec<SubscriptionLineItem>,
     #[serde(rename = "linkedPurchaseToken")]
@@ -272,9 +666,74 @@ pub struct GooglePlaySubscriptionResponse {
     pub external_account_identifiers: Option<ExternalAccountIdentifiers>,
     #[serde(rename = "subscribeWithGoogleInfo")]
     pub subscribe_with_google_info: Option<SubscribeWithGoogleInfo>,
+    /// Present only when the subscription was bought by a license tester
+    #[serde(rename = "testPurchase")]
+    pub test_purchase: Option<TestPurchase>,
+    /// Present while a pause is scheduled or in effect
+    #[serde(rename = "pausedStateContext")]
+    pub paused_state_context: Option<PausedStateContext>,
 }
 
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
+impl GooglePlaySubscriptionResponse {
+    /// Environment the purchase was made in, derived from Google's test purchase marker
+    pub fn environment(&self) -> PurchaseEnvironment {
+        if self.test_purchase.is_some() {
+            PurchaseEnvironment::Sandbox
+        } else {
+            PurchaseEnvironment::Production
+        }
+    }
+
+    /// Whether the subscription will renew, based on the first line item
+    ///
+    /// Google reports it as `autoRenewingPlan.autoRenewEnabled`, `autoRenewing` on the line
+    /// item is only read when that is missing.
+    pub fn auto_renewing(&self) -> bool {
+        self.line_items
+            .first()
+            .and_then(|item| {
+                item.auto_renewing_plan
+                    .as_ref()
+                    .and_then(|plan| plan.auto_renew_enabled)
+                    .or(item.auto_renewing)
+            })
+            .unwrap_or(true)
+    }
+
+    /// Window the subscription is paused for, `None` when no pause is scheduled
+    ///
+    /// The pause starts when the current period, the first line item, ends.
+    pub fn pause_window(&self) -> Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+        let parse = |time: &str| {
+            chrono::DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|time| time.naive_utc())
+        };
+
+        let resumes_at = parse(
+            self.paused_state_context
+                .as_ref()?
+                .auto_resume_time
+                .as_deref()?,
+        )?;
+        let paused_from = parse(self.line_items.first()?.expiry_time.as_deref()?)?;
+        Some((paused_from, resumes_at))
+    }
+}
+
+/// Pause details Google attaches to a paused or pause-scheduled subscription
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct PausedStateContext {
+    /// When the subscription resumes automatically (RFC 3339)
+    #[serde(rename = "autoResumeTime")]
+    pub auto_resume_time: Option<String>,
+}
+
+/// Marker object Google attaches to license tester purchases (has no fields)
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct TestPurchase {}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct SubscriptionLineItem {
     #[serde(rename = "productId")]
     pub product_id: String,
@@ -284,6 +743,54 @@ pub struct SubscriptionLineItem {
     pub auto_renewing: Option<bool>,
     #[serde(rename = "priceChangeState")]
     pub price_change_state: Option<String>,
+    #[serde(rename = "offerDetails")]
+    pub offer_details: Option<OfferDetails>,
+    #[serde(rename = "autoRenewingPlan")]
+    pub auto_renewing_plan: Option<AutoRenewingPlan>,
+}
+
+/// Base plan and offer a subscription line item was bought through
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct OfferDetails {
+    #[serde(rename = "basePlanId")]
+    pub base_plan_id: Option<String>,
+    #[serde(rename = "offerId")]
+    pub offer_id: Option<String>,
+    /// Tags set on the offer in the Play Console, e.g. to mark win-back offers
+    #[serde(rename = "offerTags", default)]
+    pub offer_tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct AutoRenewingPlan {
+    /// Whether the user still has auto-renew turned on
+    #[serde(rename = "autoRenewEnabled")]
+    pub auto_renew_enabled: Option<bool>,
+    /// Price charged for the current billing period in the buyer's currency
+    #[serde(rename = "recurringPrice")]
+    pub recurring_price: Option<Money>,
+}
+
+/// Google's `Money` type, an amount split into whole units and nanos
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct Money {
+    #[serde(rename = "currencyCode")]
+    pub currency_code: String,
+    /// Whole units of the amount, an int64 encoded as a string
+    pub units: Option<String>,
+    pub nanos: Option<i32>,
+}
+
+impl Money {
+    /// Amount in millionths of the currency unit, `None` if `units` is not a number
+    pub fn micros(&self) -> Option<i64> {
+        let units: i64 = match &self.units {
+            Some(units) => units.parse().ok()?,
+            None => 0,
+        };
+        let nanos = i64::from(self.nanos.unwrap_or(0));
+        units.checked_mul(1_000_000)?.checked_add(nanos / 1_000)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
@@ -296,7 +803,7 @@ pub struct ExternalAccountIdentifiers {
     pub obfuscated_external_profile_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct SubscribeWithGoogleInfo {
     #[serde(rename = "profileId")]
     pub profile_id: Option<String>,
@@ -310,23 +817,44 @@ pub struct SubscribeWithGoogleInfo {
     pub family_name: Option<String>,
 }
 
-// Google Play subscription states
-pub mod google_play_subscription_state {
-    pub const SUBSCRIPTION_STATE_UNSPECIFIED: &str = "SUBSCRIPTION_STATE_UNSPECIFIED";
-    pub const SUBSCRIPTION_STATE_PENDING: &str = "SUBSCRIPTION_STATE_PENDING";
-    pub const SUBSCRIPTION_STATE_ACTIVE: &str = "SUBSCRIPTION_STATE_ACTIVE";
-    pub const SUBSCRIPTION_STATE_PAUSED: &str = "SUBSCRIPTION_STATE_PAUSED";
-    pub const SUBSCRIPTION_STATE_IN_GRACE_PERIOD: &str = "SUBSCRIPTION_STATE_IN_GRACE_PERIOD";
-    pub const SUBSCRIPTION_STATE_ON_HOLD: &str = "SUBSCRIPTION_STATE_ON_HOLD";
-    pub const SUBSCRIPTION_STATE_CANCELED: &str = "SUBSCRIPTION_STATE_CANCELED";
-    pub const SUBSCRIPTION_STATE_EXPIRED: &str = "SUBSCRIPTION_STATE_EXPIRED";
+/// Google Play subscription state
+///
+/// States Google adds later deserialize as `Unknown` and are treated as invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub enum SubscriptionState {
+    #[serde(rename = "SUBSCRIPTION_STATE_UNSPECIFIED")]
+    Unspecified,
+    #[serde(rename = "SUBSCRIPTION_STATE_PENDING")]
+    Pending,
+    #[serde(rename = "SUBSCRIPTION_STATE_ACTIVE")]
+    Active,
+    #[serde(rename = "SUBSCRIPTION_STATE_PAUSED")]
+    Paused,
+    #[serde(rename = "SUBSCRIPTION_STATE_IN_GRACE_PERIOD")]
+    InGracePeriod,
+    #[serde(rename = "SUBSCRIPTION_STATE_ON_HOLD")]
+    OnHold,
+    #[serde(rename = "SUBSCRIPTION_STATE_CANCELED")]
+    Canceled,
+    #[serde(rename = "SUBSCRIPTION_STATE_EXPIRED")]
+    Expired,
+    #[serde(rename = "SUBSCRIPTION_STATE_PENDING_PURCHASE_CANCELED")]
+    PendingPurchaseCanceled,
+    #[serde(other)]
+    Unknown,
 }
 
-// Google Play acknowledgement states
-pub mod google_play_acknowledgement_state {
-    pub const ACKNOWLEDGEMENT_STATE_UNSPECIFIED: &str = "ACKNOWLEDGEMENT_STATE_UNSPECIFIED";
-    pub const ACKNOWLEDGEMENT_STATE_PENDING: &str = "ACKNOWLEDGEMENT_STATE_PENDING";
-    pub const ACKNOWLEDGEMENT_STATE_ACKNOWLEDGED: &str = "ACKNOWLEDGEMENT_STATE_ACKNOWLEDGED";
+/// Google Play acknowledgement state of a purchase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub enum AcknowledgementState {
+    #[serde(rename = "ACKNOWLEDGEMENT_STATE_UNSPECIFIED")]
+    Unspecified,
+    #[serde(rename = "ACKNOWLEDGEMENT_STATE_PENDING")]
+    Pending,
+    #[serde(rename = "ACKNOWLEDGEMENT_STATE_ACKNOWLEDGED")]
+    Acknowledged,
+    #[serde(other)]
+    Unknown,
 }
 
 // Bot chat access status
@@ -430,10 +958,107 @@ pub struct GooglePlayProductPurchaseV2 {
     #[serde(rename = "purchaseCompletionTime")]
     pub purchase_completion_time: Option<String>,
     #[serde(rename = "acknowledgementState")]
-    pub acknowledgement_state: Option<String>,
+    pub acknowledgement_state: Option<AcknowledgementState>,
+}
+
+// Google Play voided purchases API response types
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct VoidedPurchase {
+    #[serde(rename = "purchaseToken")]
+    pub purchase_token: String,
+    #[serde(rename = "orderId")]
+    pub order_id: Option<String>,
+    /// When the purchase was made, milliseconds since the epoch
+    #[serde(rename = "purchaseTimeMillis")]
+    pub purchase_time_millis: Option<String>,
+    /// When the purchase was voided, milliseconds since the epoch
+    #[serde(rename = "voidedTimeMillis")]
+    pub voided_time_millis: Option<String>,
+    /// 0 user, 1 developer, 2 Google
+    #[serde(rename = "voidedSource")]
+    pub voided_source: Option<i32>,
+    /// 0 other, 1 remorse, 2 not received, 3 defective, 4 accidental purchase, 5 fraud,
+    /// 6 friendly fraud, 7 chargeback
+    #[serde(rename = "voidedReason")]
+    pub voided_reason: Option<i32>,
+    /// 1 full refund, 2 quantity-based partial refund
+    #[serde(rename = "refundType")]
+    pub refund_type: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct TokenPagination {
+    #[serde(rename = "nextPageToken")]
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct VoidedPurchasesResponse {
+    #[serde(rename = "voidedPurchases", default)]
+    pub voided_purchases: Vec<VoidedPurchase>,
+    #[serde(rename = "tokenPagination")]
+    pub token_pagination: Option<TokenPagination>,
+}
+
+impl VoidedPurchasesResponse {
+    /// Token for the next page, absent on the last page
+    pub fn next_page_token(&self) -> Option<&str> {
+        self.token_pagination
+            .as_ref()
+            .and_then(|pagination| pagination.next_page_token.as_deref())
+    }
+}
+
+// Google Play monetization API types
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct BillingPeriodType {
+    /// ISO 8601 duration such as `P1M`
+    #[serde(rename = "billingPeriodDuration")]
+    pub billing_period_duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct BasePlan {
+    #[serde(rename = "basePlanId")]
+    pub base_plan_id: String,
+    /// `DRAFT`, `ACTIVE` or `INACTIVE`
+    pub state: Option<String>,
+    #[serde(rename = "autoRenewingBasePlanType")]
+    pub auto_renewing_base_plan_type: Option<BillingPeriodType>,
+    #[serde(rename = "prepaidBasePlanType")]
+    pub prepaid_base_plan_type: Option<BillingPeriodType>,
+}
+
+impl BasePlan {
+    pub fn billing_period(&self) -> Option<String> {
+        self.auto_renewing_base_plan_type
+            .as_ref()
+            .or(self.prepaid_base_plan_type.as_ref())
+            .and_then(|plan_type| plan_type.billing_period_duration.clone())
+    }
+}
+
+/// Subscription product as configured in the Play Console
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct MonetizationSubscription {
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    #[serde(rename = "basePlans", default)]
+    pub base_plans: Vec<BasePlan>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ListSubscriptionsResponse {
+    #[serde(default)]
+    pub subscriptions: Vec<MonetizationSubscription>,
+    #[serde(rename = "nextPageToken")]
+    pub next_page_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct GrantChatAccessRequest {
     /// Android package name
     pub package_name: String,
@@ -451,11 +1076,1683 @@ pub struct ChatAccessResponse {
     pub expires_at: Option<String>,
 }
 
+/// Play Store deep links for managing a subscription
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ManageUrlResponse {
+    /// The subscription's own page, or the list of subscriptions when no product was given
+    pub manage_url: String,
+    /// Where to send a user whose payment was declined, e.g. in grace period or on hold
+    pub update_payment_url: String,
+    /// List of all the user's subscriptions, where they can also cancel
+    pub all_subscriptions_url: String,
+    /// The user's payment methods
+    pub payment_methods_url: String,
+}
+
 // Credit management types
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct CreditRequest {
     /// Principal ID of the user
     pub user_principal: String,
     /// Amount to deduct or increment
     pub amount: u32,
+    /// Caller's id for the change, e.g. the video it paid for, kept in the credit ledger
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+}
+
+/// Which way a credit change went
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditDirection {
+    Deduct,
+    Increment,
+}
+
+impl ToSql<Text, Sqlite> for CreditDirection {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            CreditDirection::Deduct => <&str as ToSql<Text, Sqlite>>::to_sql(&"deduct", out),
+            CreditDirection::Increment => <&str as ToSql<Text, Sqlite>>::to_sql(&"increment", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for CreditDirection {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "deduct" => Ok(CreditDirection::Deduct),
+            "increment" => Ok(CreditDirection::Increment),
+            _ => Err("Invalid credit direction".into()),
+        }
+    }
+}
+
+/// Why a user's credits changed
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditReason {
+    /// Deducted through the credits API, e.g. for a generated video
+    Spent,
+    /// Added through the credits API, e.g. refunding a failed generation
+    Added,
+    /// Allotment of a renewed billing period
+    RenewalTopup,
+    /// Allotment gained by a purchase replacing another plan's subscription
+    UpgradeTopup,
+    /// Granted by an incident compensation batch
+    Compensation,
+    /// Difference the consistency check found on the IC, e.g. a plan change resetting credits
+    Balancing,
+}
+
+impl ToSql<Text, Sqlite> for CreditReason {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            CreditReason::Spent => <&str as ToSql<Text, Sqlite>>::to_sql(&"spent", out),
+            CreditReason::Added => <&str as ToSql<Text, Sqlite>>::to_sql(&"added", out),
+            CreditReason::RenewalTopup => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"renewal_topup", out)
+            }
+            CreditReason::UpgradeTopup => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"upgrade_topup", out)
+            }
+            CreditReason::Compensation => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"compensation", out)
+            }
+            CreditReason::Balancing => <&str as ToSql<Text, Sqlite>>::to_sql(&"balancing", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for CreditReason {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "spent" => Ok(CreditReason::Spent),
+            "added" => Ok(CreditReason::Added),
+            "renewal_topup" => Ok(CreditReason::RenewalTopup),
+            "upgrade_topup" => Ok(CreditReason::UpgradeTopup),
+            "compensation" => Ok(CreditReason::Compensation),
+            "balancing" => Ok(CreditReason::Balancing),
+            _ => Err("Invalid credit reason".into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreditLedgerEntryResponse {
+    pub id: String,
+    /// Always positive, `direction` says which way
+    pub amount: i32,
+    pub direction: CreditDirection,
+    pub reason: CreditReason,
+    /// Who made the change, e.g. `service:video`, `user:<sub>` or `billing`
+    pub actor: String,
+    pub correlation_id: Option<String>,
+    /// RFC 3339
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreditLedgerResponse {
+    pub user_id: String,
+    /// Sum of every entry, what the IC should report as the user's credits
+    pub balance: i64,
+    /// Entries, newest first
+    pub entries: PaginatedResponse<CreditLedgerEntryResponse>,
+}
+
+// Account merge types
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TransferTokensRequest {
+    /// User ID that currently owns the purchase tokens
+    pub from_user_id: String,
+    /// User ID the purchase tokens should be moved to
+    pub to_user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TransferTokensResponse {
+    /// Number of purchase tokens moved to the new user
+    pub transferred: usize,
+    /// Whether Pro access was moved along with the tokens
+    pub pro_access_moved: bool,
+}
+
+// Admin types
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IcIdentityResponse {
+    /// Principal of the identity currently signing IC calls
+    pub principal: String,
+    /// Where the key was loaded from
+    pub source: String,
+    /// When the current key was loaded (RFC 3339)
+    pub loaded_at: String,
+}
+
+// Refund request types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum RefundRequestStatus {
+    /// Submitted by the user, awaiting review
+    Requested,
+    /// Approved by support, access revocation pending
+    Approved,
+    /// Approved and access has been revoked
+    Revoked,
+    /// Declined by support
+    Rejected,
+}
+
+impl ToSql<Text, Sqlite> for RefundRequestStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            RefundRequestStatus::Requested => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"requested", out)
+            }
+            RefundRequestStatus::Approved => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"approved", out)
+            }
+            RefundRequestStatus::Revoked => <&str as ToSql<Text, Sqlite>>::to_sql(&"revoked", out),
+            RefundRequestStatus::Rejected => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"rejected", out)
+            }
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for RefundRequestStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "requested" => Ok(RefundRequestStatus::Requested),
+            "approved" => Ok(RefundRequestStatus::Approved),
+            "revoked" => Ok(RefundRequestStatus::Revoked),
+            "rejected" => Ok(RefundRequestStatus::Rejected),
+            _ => Err("Invalid refund request status".into()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateRefundRequest {
+    /// Unique identifier for the user
+    pub user_id: String,
+    /// Android package name
+    pub package_name: String,
+    /// Subscription purchase token from Google Play
+    pub purchase_token: String,
+    /// Reason given by the user
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundRequestAction {
+    /// Approve the refund and revoke access
+    Approve,
+    /// Decline the refund
+    Reject,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RefundRequestActionRequest {
+    pub action: RefundRequestAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RefundRequestResponse {
+    pub id: String,
+    pub user_id: String,
+    /// Google Play order id the refund applies to
+    pub order_id: Option<String>,
+    pub reason: String,
+    pub status: RefundRequestStatus,
+    /// Creation time (RFC 3339)
+    pub created_at: String,
+    /// Last status change (RFC 3339)
+    pub updated_at: String,
+}
+
+// Cancellation intent types
+/// Why the user wants to cancel, the answers of Google's cancellation survey
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    NotUsingEnough,
+    TechnicalIssues,
+    TooExpensive,
+    FoundAlternative,
+    Other,
+}
+
+impl CancelReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelReason::NotUsingEnough => "not_using_enough",
+            CancelReason::TechnicalIssues => "technical_issues",
+            CancelReason::TooExpensive => "too_expensive",
+            CancelReason::FoundAlternative => "found_alternative",
+            CancelReason::Other => "other",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for CancelReason {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <&str as ToSql<Text, Sqlite>>::to_sql(&self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for CancelReason {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "not_using_enough" => Ok(CancelReason::NotUsingEnough),
+            "technical_issues" => Ok(CancelReason::TechnicalIssues),
+            "too_expensive" => Ok(CancelReason::TooExpensive),
+            "found_alternative" => Ok(CancelReason::FoundAlternative),
+            "other" => Ok(CancelReason::Other),
+            _ => Err("Invalid cancel reason".into()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CancelIntentRequest {
+    /// Unique identifier for the user
+    pub user_id: String,
+    /// Subscription purchase token the user is about to cancel
+    pub purchase_token: String,
+    pub reason: CancelReason,
+    /// Free text the user added, at most 1000 characters
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CancelIntentResponse {
+    pub id: String,
+    /// Whether a retention offer is on its way to the user
+    pub retention_offer: bool,
+    /// Recording time (RFC 3339)
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterProfileRequest {
+    /// Unique identifier for the user
+    pub user_id: String,
+    /// Obfuscated profile id the app sets on the purchase, at most 64 characters
+    pub obfuscated_profile_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegisterProfileResponse {
+    pub obfuscated_profile_id: String,
+    pub user_id: String,
+    /// First registration time (RFC 3339)
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PurchaseIntentRequest {
+    /// Unique identifier for the user
+    pub user_id: String,
+    /// Package name of the Android app
+    pub package_name: String,
+    /// Product ID of the subscription or one-time product about to be bought
+    pub product_id: String,
+    /// Obfuscated account id the app sets on the purchase, the user's principal or an opaque id
+    pub obfuscated_account_id: Option<String>,
+    /// Obfuscated profile id the app sets on the purchase
+    pub obfuscated_profile_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PurchaseIntentResponse {
+    pub id: String,
+    /// Until when the intent resolves the purchase's owner (RFC 3339)
+    pub expires_at: String,
+}
+
+// Feature flag types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureFlagSource {
+    /// Built-in default
+    Default,
+    /// Set at runtime through the admin endpoint
+    Database,
+    /// Pinned by an environment variable, runtime changes have no effect
+    Env,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FeatureFlagResponse {
+    pub name: String,
+    pub enabled: bool,
+    pub source: FeatureFlagSource,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SetFeatureFlagRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserPlansRequest {
+    /// User principals, at most 100
+    pub user_ids: Vec<String>,
+}
+
+/// Plan the user info canister has for a user
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct UserPlanResponse {
+    pub user_id: String,
+    pub plan: Plan,
+}
+
+// Order reconciliation types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OrderResponse {
+    /// Google Play order id, e.g. `GPA.1234-5678-9012-34567..0` for the first renewal
+    pub order_id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub product_id: String,
+    /// ISO 3166-1 alpha-2 billing region reported by Google
+    pub region_code: Option<String>,
+    /// When the order was recorded (RFC 3339)
+    pub recorded_at: String,
+}
+
+/// Purchases granted through one Play offer
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OfferConversionResponse {
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: String,
+    /// Tags of the offer when it was last redeemed, e.g. `winback`
+    pub offer_tags: Vec<String>,
+    /// Purchase tokens granted through the offer
+    pub conversions: u64,
+    /// Distinct users among them
+    pub users: u64,
+    /// First redemption in the range (RFC 3339)
+    pub first_redeemed_at: String,
+    /// Last redemption in the range (RFC 3339)
+    pub last_redeemed_at: String,
+}
+
+/// Invoice for one Google Play order
+///
+/// Play charges prices including tax and doesn't report the tax portion, so invoices state the
+/// amount charged only.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InvoiceResponse {
+    pub invoice_id: String,
+    /// Google Play order id the invoice is for
+    pub order_id: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    /// ISO 3166-1 alpha-2 billing region reported by Google
+    pub region_code: Option<String>,
+    /// ISO 4217 currency code, absent when Play reported no price for the order
+    pub currency_code: Option<String>,
+    /// Amount charged including tax in millionths of the currency unit, absent with `currency_code`
+    pub amount_micros: Option<i64>,
+    /// When the order was charged (RFC 3339)
+    pub issued_at: String,
+}
+
+// Warehouse export types
+/// One NDJSON line of a warehouse export
+///
+/// Passing `cursor` back resumes the export after this record.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportLine<T> {
+    pub cursor: String,
+    pub record: T,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenExportRecord {
+    pub id: String,
+    pub user_id: String,
+    pub purchase_token: String,
+    pub status: PurchaseTokenStatus,
+    pub environment: PurchaseEnvironment,
+    pub package_name: Option<String>,
+    pub auto_renewing: bool,
+    /// RFC 3339
+    pub created_at: String,
+    /// RFC 3339
+    pub expiry_at: String,
+    /// RFC 3339, unset while acknowledgment is pending
+    pub acknowledged_at: Option<String>,
+    /// Start of the pause window (RFC 3339)
+    pub paused_from: Option<String>,
+    /// End of the pause window (RFC 3339)
+    pub resumes_at: Option<String>,
+    /// Last change to the token (RFC 3339), what `updated_since` filters on
+    pub updated_at: String,
+    /// Purchase token that replaced this one, bought from another Google account
+    pub replaced_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevenueEventExportRecord {
+    pub id: String,
+    pub order_id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    /// ISO 3166-1 alpha-2 billing region reported by Google
+    pub region_code: Option<String>,
+    /// ISO 4217 currency code of the price
+    pub currency_code: String,
+    /// Price in millionths of the currency unit
+    pub price_micros: i64,
+    /// When the order was recorded (RFC 3339), what `updated_since` filters on
+    pub recorded_at: String,
+}
+
+/// Where a debug log body came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugLogDirection {
+    /// Request body sent to this service
+    Inbound,
+    /// Response body returned by the Google Play Developer API
+    GoogleResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DebugLogEntry {
+    pub id: u64,
+    /// When the body was captured (RFC 3339)
+    pub recorded_at: String,
+    pub direction: DebugLogDirection,
+    pub method: String,
+    /// Request path, or the Google API method for Google responses
+    pub target: String,
+    /// Response status code
+    pub status: Option<u16>,
+    /// Body with purchase tokens, emails and names redacted
+    #[schema(value_type = Object)]
+    pub body: serde_json::Value,
+}
+
+/// What a stored purchase token disagrees with Google about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenMismatchKind {
+    /// Stored expiry differs from when Google ends access
+    ExpiryDrift,
+    /// Stored status doesn't follow from Google's subscription state
+    StateDivergence,
+    /// Stored and Google acknowledgment differ
+    AcknowledgmentMismatch,
+    /// Stored and Google auto-renewal differ
+    AutoRenewingMismatch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenMismatch {
+    pub kind: TokenMismatchKind,
+    /// Stored value
+    pub stored: String,
+    /// Value Google reports, or the stored value it implies
+    pub google: String,
+}
+
+/// Stored purchase token next to a live subscriptionsv2 fetch
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenDiffResponse {
+    pub stored: TokenExportRecord,
+    /// Response body fetched from Google just now, emails and names redacted
+    #[schema(value_type = Object)]
+    pub google: serde_json::Value,
+    /// Empty when the stored token agrees with Google
+    pub mismatches: Vec<TokenMismatch>,
+}
+
+/// Stored subscriptionsv2 response of a purchase token
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SubscriptionSnapshotResponse {
+    /// When the response was fetched from Google (RFC 3339)
+    pub fetched_at: String,
+    /// Response body with emails and names redacted
+    #[schema(value_type = Object)]
+    pub body: serde_json::Value,
+}
+
+/// Stored line item of a subscription, one per product the subscription covers
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SubscriptionLineItemResponse {
+    pub product_id: String,
+    pub base_plan_id: Option<String>,
+    pub offer_id: Option<String>,
+    /// Plan the line grants, absent for add-ons outside the catalog
+    pub plan: Option<Plan>,
+    /// End of the line's current period (RFC 3339), absent while its payment is pending
+    pub expiry_at: Option<String>,
+    pub auto_renewing: bool,
+    /// Whether the line's period ended, other lines of the subscription may still grant access
+    pub expired: bool,
+    /// When Google last reported the line (RFC 3339)
+    pub updated_at: String,
+}
+
+// Billing history types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionEventKind {
+    /// First order of a subscription
+    Purchase,
+    /// Later order of the same subscription, including recoveries from account hold
+    Renewal,
+    /// The user turned off auto-renew, access lasts until expiry
+    Cancellation,
+}
+
+impl ToSql<Text, Sqlite> for SubscriptionEventKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            SubscriptionEventKind::Purchase => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"purchase", out)
+            }
+            SubscriptionEventKind::Renewal => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"renewal", out)
+            }
+            SubscriptionEventKind::Cancellation => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"cancellation", out)
+            }
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for SubscriptionEventKind {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "purchase" => Ok(SubscriptionEventKind::Purchase),
+            "renewal" => Ok(SubscriptionEventKind::Renewal),
+            "cancellation" => Ok(SubscriptionEventKind::Cancellation),
+            _ => Err("Invalid subscription event kind".into()),
+        }
+    }
+}
+
+/// One entry of a user's billing history
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BillingHistoryEntry {
+    pub kind: SubscriptionEventKind,
+    /// Google Play order id, e.g. `GPA.1234-5678-9012-34567..0` for the first renewal
+    pub order_id: Option<String>,
+    pub product_id: String,
+    /// Plan the product grants, absent for products outside the plan catalog
+    pub plan: Option<Plan>,
+    /// When the event happened (RFC 3339)
+    pub occurred_at: String,
+}
+
+/// A page of billing history, newest first
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BillingHistoryResponse {
+    pub events: Vec<BillingHistoryEntry>,
+    /// Pass as `cursor` to get the next page, absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A purchase as its owner sees it on the app's "Manage subscription" screen
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserTokenResponse {
+    /// Stable id of the purchase, the purchase token itself is never included
+    pub id: String,
+    pub status: PurchaseTokenStatus,
+    /// Product of the latest order or cancellation, absent until Google reported one
+    pub product_id: Option<String>,
+    /// Plan the product grants, absent for products outside the plan catalog
+    pub plan: Option<Plan>,
+    /// When access ends unless the subscription renews (RFC 3339)
+    pub expiry_at: String,
+    pub auto_renewing: bool,
+    pub environment: PurchaseEnvironment,
+    /// When the purchase was first verified (RFC 3339)
+    pub created_at: String,
+    /// Latest purchases, renewals and cancellations of this purchase, newest first
+    pub history: Vec<BillingHistoryEntry>,
+}
+
+/// Revenue of one day in one region and currency
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevenueTotal {
+    /// UTC day, YYYY-MM-DD
+    pub day: String,
+    /// ISO 3166-1 alpha-2 billing region reported by Google
+    pub region_code: Option<String>,
+    /// ISO 4217 currency code, amounts in different currencies are never summed together
+    pub currency_code: String,
+    /// Number of orders (purchases and renewals) in the bucket
+    pub orders: i64,
+    /// Sum of order prices in millionths of the currency unit
+    pub total_micros: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AdminStatsResponse {
+    /// First day included (UTC)
+    pub from: String,
+    /// Last day included (UTC)
+    pub to: String,
+    /// Revenue totals ordered by day, region and currency
+    pub revenue: Vec<RevenueTotal>,
+}
+
+/// RTDN processing lag of one notification type, in milliseconds
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RtdnLagStats {
+    /// e.g. `subscription_revoked`
+    pub notification_type: String,
+    pub samples: i64,
+    /// From Google's event to processing
+    pub event_lag_p50_ms: Option<i64>,
+    pub event_lag_p95_ms: Option<i64>,
+    /// From the Pub/Sub publish to processing
+    pub delivery_lag_p50_ms: Option<i64>,
+    pub delivery_lag_p95_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RtdnLagStatsResponse {
+    /// Start of the window the percentiles cover (UTC)
+    pub since: String,
+    /// One entry per notification type seen in the window, ordered by type
+    pub types: Vec<RtdnLagStats>,
+}
+
+// Entitlement types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum Plan {
+    Free,
+    Pro,
+}
+
+impl ToSql<Text, Sqlite> for Plan {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            Plan::Free => <&str as ToSql<Text, Sqlite>>::to_sql(&"free", out),
+            Plan::Pro => <&str as ToSql<Text, Sqlite>>::to_sql(&"pro", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for Plan {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "free" => Ok(Plan::Free),
+            "pro" => Ok(Plan::Pro),
+            _ => Err("Invalid plan".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStore {
+    GooglePlay,
+    AppStore,
+    /// Web checkout billed through Stripe
+    Stripe,
+    /// Redeemed promotional access
+    Promo,
+    /// Granted by an operator through the admin API
+    Manual,
+}
+
+impl From<SubscriptionSource> for SourceStore {
+    fn from(source: SubscriptionSource) -> Self {
+        match source {
+            SubscriptionSource::GooglePlay => SourceStore::GooglePlay,
+            SubscriptionSource::AppStore => SourceStore::AppStore,
+            SubscriptionSource::Stripe => SourceStore::Stripe,
+            SubscriptionSource::Promo => SourceStore::Promo,
+            SubscriptionSource::Manual => SourceStore::Manual,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BotChatEntitlement {
+    pub bot_id: String,
+    /// End of the chat access window (RFC 3339)
+    pub expires_at: String,
+}
+
+/// Normalized view of what a user is entitled to, for other backends to poll and cache
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EntitlementResponse {
+    pub user_id: String,
+    pub plan: Plan,
+    pub features: Vec<String>,
+    /// Video credits allotted per billing period by the plan
+    pub credit_allotment: u32,
+    /// When the subscription was first verified (RFC 3339), absent on the free plan
+    pub valid_from: Option<String>,
+    /// When access lapses unless renewed (RFC 3339), absent on the free plan
+    pub valid_until: Option<String>,
+    /// Whether the subscription renews at `valid_until`
+    pub auto_renewing: Option<bool>,
+    /// Store the plan was purchased through, absent on the free plan
+    pub source_store: Option<SourceStore>,
+    /// Start of a scheduled or current pause of the Google Play subscription (RFC 3339)
+    pub paused_from: Option<String>,
+    /// When the paused subscription resumes and access returns (RFC 3339)
+    pub resumes_at: Option<String>,
+    /// Active per-bot chat access windows
+    pub bot_chat_access: Vec<BotChatEntitlement>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EntitlementTokenRequest {
+    pub user_id: String,
+}
+
+/// Signed entitlement for other services to check offline against `/.well-known/jwks.json`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EntitlementTokenResponse {
+    /// EdDSA JWT with the user as `sub` and the `plan`, `features` and `plan_expires_at` claims
+    pub token: String,
+    pub plan: Plan,
+    /// When the token expires (RFC 3339), fetch a new one before then
+    pub expires_at: String,
+}
+
+// Access outbox types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum OutboxAction {
+    /// Move the user to the Pro plan on the IC
+    GrantPro,
+    /// Move the user back to the Free plan on the IC
+    RevokePro,
+}
+
+impl ToSql<Text, Sqlite> for OutboxAction {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            OutboxAction::GrantPro => <&str as ToSql<Text, Sqlite>>::to_sql(&"grant_pro", out),
+            OutboxAction::RevokePro => <&str as ToSql<Text, Sqlite>>::to_sql(&"revoke_pro", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for OutboxAction {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "grant_pro" => Ok(OutboxAction::GrantPro),
+            "revoke_pro" => Ok(OutboxAction::RevokePro),
+            _ => Err("Invalid outbox action".into()),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum OutboxStatus {
+    /// Waiting to be applied, retried until it succeeds or runs out of attempts
+    Pending,
+    /// Applied on the IC
+    Done,
+    /// Gave up after too many attempts, needs manual attention
+    Failed,
+}
+
+impl ToSql<Text, Sqlite> for OutboxStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            OutboxStatus::Pending => <&str as ToSql<Text, Sqlite>>::to_sql(&"pending", out),
+            OutboxStatus::Done => <&str as ToSql<Text, Sqlite>>::to_sql(&"done", out),
+            OutboxStatus::Failed => <&str as ToSql<Text, Sqlite>>::to_sql(&"failed", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for OutboxStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "pending" => Ok(OutboxStatus::Pending),
+            "done" => Ok(OutboxStatus::Done),
+            "failed" => Ok(OutboxStatus::Failed),
+            _ => Err("Invalid outbox status".into()),
+        }
+    }
+}
+
+// Admin audit types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum AuditAction {
+    /// Operator moved the user to the Pro plan
+    GrantPro,
+    /// Operator moved the user back to the Free plan
+    RevokePro,
+    /// Operator pushed back a subscription's renewal date through Google
+    DeferSubscription,
+    /// Operator cleared a user flagged for repeated refunds or chargebacks
+    ApproveRisk,
+    /// Internal service turned off auto-renew of a subscription through Google
+    CancelSubscription,
+    /// Internal service tore down a user's subscription state, e.g. on account deletion
+    TeardownUser,
+    /// Operator approved a quarantined verification and granted its purchase
+    ApproveQuarantine,
+    /// Operator rejected a quarantined verification
+    RejectQuarantine,
+}
+
+impl ToSql<Text, Sqlite> for AuditAction {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            AuditAction::GrantPro => <&str as ToSql<Text, Sqlite>>::to_sql(&"grant_pro", out),
+            AuditAction::RevokePro => <&str as ToSql<Text, Sqlite>>::to_sql(&"revoke_pro", out),
+            AuditAction::DeferSubscription => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"defer_subscription", out)
+            }
+            AuditAction::ApproveRisk => <&str as ToSql<Text, Sqlite>>::to_sql(&"approve_risk", out),
+            AuditAction::CancelSubscription => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"cancel_subscription", out)
+            }
+            AuditAction::TeardownUser => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"teardown_user", out)
+            }
+            AuditAction::ApproveQuarantine => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"approve_quarantine", out)
+            }
+            AuditAction::RejectQuarantine => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"reject_quarantine", out)
+            }
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for AuditAction {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "grant_pro" => Ok(AuditAction::GrantPro),
+            "revoke_pro" => Ok(AuditAction::RevokePro),
+            "defer_subscription" => Ok(AuditAction::DeferSubscription),
+            "approve_risk" => Ok(AuditAction::ApproveRisk),
+            "cancel_subscription" => Ok(AuditAction::CancelSubscription),
+            "teardown_user" => Ok(AuditAction::TeardownUser),
+            "approve_quarantine" => Ok(AuditAction::ApproveQuarantine),
+            "reject_quarantine" => Ok(AuditAction::RejectQuarantine),
+            _ => Err("Invalid audit action".into()),
+        }
+    }
+}
+
+// Source-agnostic subscription types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionSource {
+    /// Google Play subscription, `source_ref` is the purchase token
+    GooglePlay,
+    /// Apple App Store subscription, `source_ref` is the original transaction id
+    AppStore,
+    /// Web subscription billed through Stripe, `source_ref` is the Stripe subscription id
+    Stripe,
+    /// Promotional access, `source_ref` is the redeemed code
+    Promo,
+    /// Granted by an operator through the admin API, `source_ref` is the synthetic token
+    Manual,
+}
+
+impl SubscriptionSource {
+    /// Source of a row in `purchase_tokens`
+    pub fn for_environment(environment: PurchaseEnvironment) -> Self {
+        match environment {
+            PurchaseEnvironment::Manual => SubscriptionSource::Manual,
+            PurchaseEnvironment::Production | PurchaseEnvironment::Sandbox => {
+                SubscriptionSource::GooglePlay
+            }
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for SubscriptionSource {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            SubscriptionSource::GooglePlay => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"google_play", out)
+            }
+            SubscriptionSource::AppStore => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"app_store", out)
+            }
+            SubscriptionSource::Stripe => <&str as ToSql<Text, Sqlite>>::to_sql(&"stripe", out),
+            SubscriptionSource::Promo => <&str as ToSql<Text, Sqlite>>::to_sql(&"promo", out),
+            SubscriptionSource::Manual => <&str as ToSql<Text, Sqlite>>::to_sql(&"manual", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for SubscriptionSource {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "google_play" => Ok(SubscriptionSource::GooglePlay),
+            "app_store" => Ok(SubscriptionSource::AppStore),
+            "stripe" => Ok(SubscriptionSource::Stripe),
+            "promo" => Ok(SubscriptionSource::Promo),
+            "manual" => Ok(SubscriptionSource::Manual),
+            _ => Err("Invalid subscription source".into()),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Payment has not completed, no access yet
+    Pending,
+    /// Grants Pro until `expires_at`
+    Active,
+    /// Ended, canceled, refunded or revoked
+    Expired,
+}
+
+impl ToSql<Text, Sqlite> for SubscriptionStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            SubscriptionStatus::Pending => <&str as ToSql<Text, Sqlite>>::to_sql(&"pending", out),
+            SubscriptionStatus::Active => <&str as ToSql<Text, Sqlite>>::to_sql(&"active", out),
+            SubscriptionStatus::Expired => <&str as ToSql<Text, Sqlite>>::to_sql(&"expired", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for SubscriptionStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "pending" => Ok(SubscriptionStatus::Pending),
+            "active" => Ok(SubscriptionStatus::Active),
+            "expired" => Ok(SubscriptionStatus::Expired),
+            _ => Err("Invalid subscription status".into()),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ManualGrantRequest {
+    pub user_id: String,
+    /// Why access is being granted, kept in the audit log
+    pub reason: String,
+    /// How long the synthetic purchase record keeps the user on Pro
+    #[serde(default = "default_manual_grant_days")]
+    pub duration_days: u32,
+    /// Store a synthetic `manual` purchase so status endpoints and sweeps agree with the IC
+    #[serde(default = "default_true")]
+    pub record_purchase: bool,
+}
+
+fn default_manual_grant_days() -> u32 {
+    30
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ManualRevokeRequest {
+    pub user_id: String,
+    /// Why access is being revoked, kept in the audit log
+    pub reason: String,
+    /// Mark the user's active purchases expired so status endpoints stop reporting Pro
+    #[serde(default = "default_true")]
+    pub expire_purchases: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ManualAccessResponse {
+    /// Id of the audit log entry recording the change
+    pub audit_id: String,
+    pub user_id: String,
+    pub action: AuditAction,
+    /// Operator taken from the admin JWT `sub` claim
+    pub operator: String,
+    /// Synthetic purchase token, set when a grant recorded a purchase
+    pub purchase_token: Option<String>,
+    /// End of the manual grant (RFC 3339), set when a grant recorded a purchase
+    pub expiry_at: Option<String>,
+    /// Purchases marked expired by a revoke
+    pub expired_purchases: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeferSubscriptionRequest {
+    /// Days to add to the current expiry
+    pub days: u32,
+    /// Why the subscription is being extended, kept in the audit log
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeferSubscriptionResponse {
+    /// Id of the audit log entry recording the change
+    pub audit_id: String,
+    pub purchase_token: String,
+    pub user_id: String,
+    /// Operator taken from the admin JWT `sub` claim
+    pub operator: String,
+    /// Expiry Google reported before the deferral (RFC 3339)
+    pub previous_expiry_at: String,
+    /// New expiry confirmed by Google (RFC 3339)
+    pub expiry_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TeardownUserRequest {
+    /// Why the user's subscriptions are torn down, kept in the audit log
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TeardownUserResponse {
+    /// Id of the audit log entry recording the teardown
+    pub audit_id: String,
+    pub user_id: String,
+    /// Service that requested the teardown, e.g. `service:accounts`
+    pub operator: String,
+    /// Google subscriptions whose auto-renew was turned off
+    pub canceled_subscriptions: usize,
+    /// Google subscriptions that could not be canceled and may renew once more
+    pub failed_cancellations: usize,
+    /// Purchases marked expired
+    pub expired_purchases: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IntrospectRequest {
+    pub purchase_token: String,
+    /// Ask Google for the token's current state and store it before answering
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IntrospectResponse {
+    /// User the purchase belongs to
+    pub user_id: String,
+    /// Plan the purchase gives right now, `free` once it no longer grants access
+    pub plan: Plan,
+    pub status: PurchaseTokenStatus,
+    /// When access lapses unless renewed (RFC 3339)
+    pub expiry_at: String,
+    pub auto_renewing: bool,
+    pub environment: PurchaseEnvironment,
+    /// Whether the answer was refreshed from Google rather than read from the stored row
+    pub refreshed: bool,
+}
+
+// Refund abuse review types
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserRiskResponse {
+    pub user_id: String,
+    /// Subscriptions Google revoked with a refund
+    pub revoked_count: i32,
+    /// Purchases voided by a refund or chargeback
+    pub voided_count: i32,
+    /// When the user crossed the risk threshold (RFC 3339)
+    pub flagged_at: Option<String>,
+    /// When an operator cleared the flag (RFC 3339)
+    pub approved_at: Option<String>,
+    /// Operator taken from the admin JWT `sub` claim
+    pub approved_by: Option<String>,
+    /// Whether verifications wait for approval while manual approval is on
+    pub requires_approval: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ApproveUserRiskRequest {
+    /// Why the user is trusted again, kept in the audit log
+    pub reason: String,
+}
+
+// Verification quarantine types
+/// Why a verification was held for an operator
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineReason {
+    /// The purchase token was verified by more distinct users than allowed
+    SharedToken,
+    /// The purchase started long before it was first verified
+    LateVerification,
+    /// The user sent more verifications in the last hour than allowed
+    HighVelocity,
+}
+
+impl QuarantineReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineReason::SharedToken => "shared_token",
+            QuarantineReason::LateVerification => "late_verification",
+            QuarantineReason::HighVelocity => "high_velocity",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for QuarantineReason {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <&str as ToSql<Text, Sqlite>>::to_sql(&self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for QuarantineReason {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "shared_token" => Ok(QuarantineReason::SharedToken),
+            "late_verification" => Ok(QuarantineReason::LateVerification),
+            "high_velocity" => Ok(QuarantineReason::HighVelocity),
+            _ => Err("Invalid quarantine reason".into()),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineStatus {
+    /// Waiting for an operator, verify answers `VERIFICATION_QUARANTINED`
+    Pending,
+    /// Granted by an operator, verify grants the purchase again
+    Approved,
+    /// Rejected by an operator, verify answers `VERIFICATION_REJECTED`
+    Rejected,
+}
+
+impl QuarantineStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineStatus::Pending => "pending",
+            QuarantineStatus::Approved => "approved",
+            QuarantineStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for QuarantineStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <&str as ToSql<Text, Sqlite>>::to_sql(&self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for QuarantineStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "pending" => Ok(QuarantineStatus::Pending),
+            "approved" => Ok(QuarantineStatus::Approved),
+            "rejected" => Ok(QuarantineStatus::Rejected),
+            _ => Err("Invalid quarantine status".into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct QuarantineResponse {
+    pub id: String,
+    /// User who sent the verification
+    pub user_id: String,
+    /// Account Google reports for the purchase, which is granted on approval
+    pub account_id: String,
+    pub purchase_token: String,
+    pub package_name: String,
+    pub product_id: String,
+    pub reason: QuarantineReason,
+    pub status: QuarantineStatus,
+    /// When the verification was quarantined (RFC 3339)
+    pub created_at: String,
+    /// When an operator approved or rejected it (RFC 3339)
+    pub decided_at: Option<String>,
+    /// Operator taken from the admin JWT `sub` claim
+    pub decided_by: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QuarantineDecisionRequest {
+    /// Why the verification is approved or rejected, kept in the audit log
+    pub reason: String,
+}
+
+// Product catalog types
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogProductResponse {
+    pub package_name: String,
+    pub product_id: String,
+    pub base_plan_id: String,
+    /// Google's base plan state, e.g. `ACTIVE`, `DRAFT` or `INACTIVE`
+    pub state: String,
+    /// ISO 8601 duration such as `P1M`
+    pub billing_period: Option<String>,
+    pub archived: bool,
+    /// When the entry was last refreshed from Google (RFC 3339)
+    pub synced_at: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogSyncRequest {
+    /// Android package to pull the catalog for
+    pub package_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatalogResponse {
+    pub products: Vec<CatalogProductResponse>,
+    /// Plan product ids with no active base plan in the Console
+    pub missing_plan_products: Vec<String>,
+}
+
+// Incident compensation types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CompensationKind {
+    /// Days of Pro, recorded as a manual purchase per user
+    ProDays,
+    /// Video credits added on the IC
+    Credits,
+}
+
+impl ToSql<Text, Sqlite> for CompensationKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            CompensationKind::ProDays => <&str as ToSql<Text, Sqlite>>::to_sql(&"pro_days", out),
+            CompensationKind::Credits => <&str as ToSql<Text, Sqlite>>::to_sql(&"credits", out),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for CompensationKind {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "pro_days" => Ok(CompensationKind::ProDays),
+            "credits" => Ok(CompensationKind::Credits),
+            _ => Err("Invalid compensation kind".into()),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CompensationBatchStatus {
+    /// Users are being compensated, or the run stopped midway and waits to be resumed
+    Running,
+    /// Every user was compensated
+    Completed,
+    /// The run finished with users that could not be compensated
+    Failed,
+}
+
+impl ToSql<Text, Sqlite> for CompensationBatchStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            CompensationBatchStatus::Running => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"running", out)
+            }
+            CompensationBatchStatus::Completed => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"completed", out)
+            }
+            CompensationBatchStatus::Failed => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"failed", out)
+            }
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for CompensationBatchStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "running" => Ok(CompensationBatchStatus::Running),
+            "completed" => Ok(CompensationBatchStatus::Completed),
+            "failed" => Ok(CompensationBatchStatus::Failed),
+            _ => Err("Invalid compensation batch status".into()),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum CompensationGrantStatus {
+    /// Not compensated yet
+    Pending,
+    Granted,
+    /// The last attempt failed, retried when the batch is resumed
+    Failed,
+}
+
+impl ToSql<Text, Sqlite> for CompensationGrantStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            CompensationGrantStatus::Pending => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"pending", out)
+            }
+            CompensationGrantStatus::Granted => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"granted", out)
+            }
+            CompensationGrantStatus::Failed => {
+                <&str as ToSql<Text, Sqlite>>::to_sql(&"failed", out)
+            }
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for CompensationGrantStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "pending" => Ok(CompensationGrantStatus::Pending),
+            "granted" => Ok(CompensationGrantStatus::Granted),
+            "failed" => Ok(CompensationGrantStatus::Failed),
+            _ => Err("Invalid compensation grant status".into()),
+        }
+    }
+}
+
+/// Users whose subscription was active at some point during an incident
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CompensationFilter {
+    /// Start of the incident (RFC 3339)
+    #[schema(value_type = String)]
+    pub active_from: chrono::DateTime<chrono::Utc>,
+    /// End of the incident (RFC 3339)
+    #[schema(value_type = String)]
+    pub active_until: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateCompensationRequest {
+    /// Incident the compensation is for, e.g. the postmortem id
+    pub incident: String,
+    /// Why users are compensated, kept in the audit log
+    pub reason: String,
+    pub kind: CompensationKind,
+    /// Days of Pro or number of credits each user receives
+    pub amount: u32,
+    /// Users to compensate
+    #[serde(default)]
+    pub user_ids: Vec<String>,
+    /// Also compensate every user matching this filter
+    pub filter: Option<CompensationFilter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompensationGrantResponse {
+    pub user_id: String,
+    pub status: CompensationGrantStatus,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    /// Manual purchase recording granted Pro days
+    pub purchase_token: Option<String>,
+    /// When the user was last attempted (RFC 3339)
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CompensationBatchResponse {
+    pub id: String,
+    pub incident: String,
+    pub reason: String,
+    pub kind: CompensationKind,
+    pub amount: u32,
+    /// Operator taken from the admin JWT `sub` claim
+    pub operator: String,
+    pub status: CompensationBatchStatus,
+    pub total_users: usize,
+    pub granted: usize,
+    pub failed: usize,
+    pub pending: usize,
+    /// Per-user results, only returned when looking up a single batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<CompensationGrantResponse>>,
+    /// RFC 3339
+    pub created_at: String,
+    /// RFC 3339
+    pub updated_at: String,
+}
+
+// Scheduled job types
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+}
+
+impl JobOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Succeeded => "succeeded",
+            JobOutcome::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for JobOutcome {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <&str as ToSql<Text, Sqlite>>::to_sql(&self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for JobOutcome {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> deserialize::Result<Self> {
+        let s = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        match s.as_str() {
+            "succeeded" => Ok(JobOutcome::Succeeded),
+            "failed" => Ok(JobOutcome::Failed),
+            _ => Err("Invalid job outcome".into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct QueueStatsResponse {
+    /// e.g. `access_outbox`, the `queue` label of the queue gauges
+    pub queue: String,
+    /// Items waiting to be handled, due or not
+    pub pending: i64,
+    /// Items given up on, absent for queues that retry forever
+    pub failed: Option<i64>,
+    /// When the longest waiting item became due (RFC 3339), absent while nothing is due
+    pub oldest_due_at: Option<String>,
+    pub oldest_age_secs: Option<i64>,
+    /// Items handled over the last hour, absent for queues that delete handled items
+    pub processed_last_hour: Option<i64>,
+    /// Age of the oldest due item past which the queue is stalled
+    pub max_age_secs: i64,
+    pub stalled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClusterResponse {
+    /// Replica that answered
+    pub instance: String,
+    /// Replica holding the scheduler leader lease, absent while none does
+    pub leader: Option<String>,
+    /// Whether the answering replica is the leader
+    pub is_leader: bool,
+    /// Whether replicas share the election, false without `REDIS_URL`
+    pub cluster_wide: bool,
+    pub leader_lease_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScheduledJobResponse {
+    pub name: String,
+    /// e.g. `every 3600s` or a cron expression like `0 3 * * *` (UTC)
+    pub schedule: String,
+    /// Whether a replica holds the job's lease right now
+    pub running: bool,
+    /// Replica holding the lease
+    pub locked_by: Option<String>,
+    /// RFC 3339
+    pub last_started_at: Option<String>,
+    /// RFC 3339
+    pub last_finished_at: Option<String>,
+    pub last_outcome: Option<JobOutcome>,
+    /// Why the last run failed
+    pub last_error: Option<String>,
+    pub last_duration_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DbIntegrityResponse {
+    /// Whether `PRAGMA integrity_check` found nothing wrong
+    pub ok: bool,
+    /// What the check found, empty when `ok`
+    pub problems: Vec<String>,
+    /// Size of the database file, free pages included
+    pub size_bytes: i64,
+}
+
+/// What reverifying a purchase against Google found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReverifyResult {
+    /// Active, access was granted and the stored row refreshed
+    Granted,
+    /// Payment has not completed yet
+    StillPending,
+    /// No longer renews, the stored expiry was corrected to Google's
+    Ended,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReverifyResponse {
+    pub purchase_token: String,
+    pub result: ReverifyResult,
+    /// When the subscription ended (RFC 3339), set for `ended`
+    pub expiry_at: Option<String>,
 }
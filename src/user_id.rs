@@ -0,0 +1,22 @@
+use ic_agent::export::Principal;
+
+use crate::error::{AppError, AppResult};
+
+/// Canonical text of the principal a client sent as a user id
+///
+/// Clients send principals with stray whitespace or in upper case, which would otherwise be
+/// stored as a different user. Ids that aren't principals are rejected.
+pub fn canonical_user_id(user_id: &str) -> AppResult<String> {
+    Principal::from_text(user_id.trim().to_ascii_lowercase())
+        .map(|principal| principal.to_text())
+        .map_err(|_| AppError::BadRequest(format!("Invalid user id: {}", user_id.trim())))
+}
+
+/// `canonical_user_id` where rejecting isn't an option, ids that aren't principals are kept
+///
+/// For Google's `obfuscatedExternalAccountId`, which the app sets to the user's principal at
+/// purchase but which can't be refused once Google charged for it, and for lookups that
+/// should still find rows whose ids were never principals.
+pub fn normalize_user_id(user_id: &str) -> String {
+    canonical_user_id(user_id).unwrap_or_else(|_| user_id.to_string())
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::clock::TestClock;
+use yral_billing::integrations::google_play::MockGooglePlay;
+use yral_billing::jobs::acknowledgments::{
+    check_pending_acknowledgments, parse_package_strategies, AckStrategies, AckStrategy,
+    AcknowledgmentReport,
+};
+use yral_billing::jobs::JOB_BATCH_SIZE;
+use yral_billing::metrics::{
+    Metrics, ACKNOWLEDGMENT_DEADLINE_AT_RISK_TOKENS, ACKNOWLEDGMENT_RETRIES_TOTAL,
+    UNACKNOWLEDGED_TOKENS,
+};
+use yral_billing::model::PurchaseToken;
+use yral_billing::notifier::Notifier;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_acknowledgments_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+fn insert_unacknowledged_token(
+    conn: &mut SqliteConnection,
+    package_name: Option<&str>,
+    created_at: chrono::NaiveDateTime,
+) -> PurchaseToken {
+    let mut token = PurchaseToken::new(
+        "user_pending".to_string(),
+        format!("token_{}", uuid::Uuid::new_v4()),
+        created_at + chrono::Duration::days(30),
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    token.created_at = created_at;
+    token.package_name = package_name.map(str::to_string);
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(conn)
+        .unwrap();
+    token
+}
+
+fn acknowledged_at(
+    conn: &mut SqliteConnection,
+    token: &PurchaseToken,
+) -> Option<chrono::NaiveDateTime> {
+    purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .select(purchase_tokens::acknowledged_at)
+        .first(conn)
+        .unwrap()
+}
+
+// Pending acknowledgments are retried and recorded once Google accepts them
+#[tokio::test]
+async fn test_pending_acknowledgment_is_retried() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    let token = insert_unacknowledged_token(
+        &mut conn,
+        Some("com.yral.android"),
+        (now - chrono::Duration::hours(1)).naive_utc(),
+    );
+
+    let report = check_pending_acknowledgments(
+        &mut conn,
+        &MockGooglePlay,
+        &AckStrategies::default(),
+        &Notifier::new(None),
+        &clock,
+        &metrics,
+        chrono::Duration::hours(24),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        report,
+        AcknowledgmentReport {
+            acknowledged: 1,
+            pending: 0,
+            at_risk: 0,
+        }
+    );
+    assert_eq!(acknowledged_at(&mut conn, &token), Some(now.naive_utc()));
+    assert_eq!(
+        metrics.counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "ok")]),
+        1
+    );
+    assert_eq!(metrics.gauge(UNACKNOWLEDGED_TOKENS, &[]), Some(0));
+
+    // Acknowledged tokens are not retried again
+    let report = check_pending_acknowledgments(
+        &mut conn,
+        &MockGooglePlay,
+        &AckStrategies::default(),
+        &Notifier::new(None),
+        &clock,
+        &metrics,
+        chrono::Duration::hours(24),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report, AcknowledgmentReport::default());
+}
+
+// Tokens that keep failing are only flagged once they are close to Google's deadline
+#[tokio::test]
+async fn test_failing_acknowledgment_near_deadline_is_at_risk() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    // Without a package name the acknowledgment can't be retried
+    let near_deadline = insert_unacknowledged_token(
+        &mut conn,
+        None,
+        (now - chrono::Duration::hours(60)).naive_utc(),
+    );
+    let recent = insert_unacknowledged_token(
+        &mut conn,
+        None,
+        (now - chrono::Duration::hours(1)).naive_utc(),
+    );
+
+    let report = check_pending_acknowledgments(
+        &mut conn,
+        &MockGooglePlay,
+        &AckStrategies::default(),
+        &Notifier::new(None),
+        &clock,
+        &metrics,
+        chrono::Duration::hours(24),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        report,
+        AcknowledgmentReport {
+            acknowledged: 0,
+            pending: 2,
+            at_risk: 1,
+        }
+    );
+    assert_eq!(acknowledged_at(&mut conn, &near_deadline), None);
+    assert_eq!(acknowledged_at(&mut conn, &recent), None);
+    assert_eq!(
+        metrics.counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "error")]),
+        2
+    );
+    assert_eq!(metrics.gauge(UNACKNOWLEDGED_TOKENS, &[]), Some(2));
+    assert_eq!(
+        metrics.gauge(ACKNOWLEDGMENT_DEADLINE_AT_RISK_TOKENS, &[]),
+        Some(1)
+    );
+}
+
+// Failing tokens stay unacknowledged, yet every token past the first batch is still visited once
+#[tokio::test]
+async fn test_failing_acknowledgments_span_batches() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    let total = JOB_BATCH_SIZE as usize + 20;
+    for i in 0..total {
+        insert_unacknowledged_token(
+            &mut conn,
+            None,
+            (now - chrono::Duration::seconds(i as i64)).naive_utc(),
+        );
+    }
+
+    let report = check_pending_acknowledgments(
+        &mut conn,
+        &MockGooglePlay,
+        &AckStrategies::default(),
+        &Notifier::new(None),
+        &clock,
+        &metrics,
+        chrono::Duration::hours(24),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.pending, total);
+    assert_eq!(
+        metrics.counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "error")]),
+        total as u64
+    );
+}
+
+// Purchases left to the app stay pending without counting as failed retries
+#[tokio::test]
+async fn test_never_strategy_leaves_acknowledgment_to_app() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+    let ack_strategies = AckStrategies::new(
+        AckStrategy::OnlyIfPending,
+        HashMap::from([("com.yral.android".to_string(), AckStrategy::Never)]),
+    );
+
+    let token = insert_unacknowledged_token(
+        &mut conn,
+        Some("com.yral.android"),
+        (now - chrono::Duration::hours(1)).naive_utc(),
+    );
+
+    let report = check_pending_acknowledgments(
+        &mut conn,
+        &MockGooglePlay,
+        &ack_strategies,
+        &Notifier::new(None),
+        &clock,
+        &metrics,
+        chrono::Duration::hours(24),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        report,
+        AcknowledgmentReport {
+            acknowledged: 0,
+            pending: 1,
+            at_risk: 0,
+        }
+    );
+    assert_eq!(acknowledged_at(&mut conn, &token), None);
+    assert_eq!(
+        metrics.counter(ACKNOWLEDGMENT_RETRIES_TOTAL, &[("result", "error")]),
+        0
+    );
+}
+
+#[test]
+fn test_parse_package_strategies() {
+    let packages =
+        parse_package_strategies("com.yral.android=never, com.yral.lite=always,").unwrap();
+    let ack_strategies = AckStrategies::new(AckStrategy::OnlyIfPending, packages);
+    assert_eq!(
+        ack_strategies.for_package("com.yral.android"),
+        AckStrategy::Never
+    );
+    assert_eq!(
+        ack_strategies.for_package("com.yral.lite"),
+        AckStrategy::Always
+    );
+    assert_eq!(
+        ack_strategies.for_package("com.other"),
+        AckStrategy::OnlyIfPending
+    );
+
+    for invalid in ["com.yral.android", "com.yral.android=sometimes"] {
+        assert!(parse_package_strategies(invalid).is_err(), "{}", invalid);
+    }
+}
@@ -0,0 +1,333 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::routing::post;
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::auth::{require_admin_scope, Claims, ADMIN_SCOPE};
+use yral_billing::integrations::google_play::MockGooglePlay;
+use yral_billing::integrations::user_info::MockUserInfo;
+use yral_billing::model::{AdminAuditEntry, PurchaseToken};
+use yral_billing::routes::access::{
+    defer_subscription_expiry, grant_manual_access, revoke_manual_access,
+};
+use yral_billing::schema::{admin_audit_log, purchase_tokens};
+use yral_billing::types::{
+    AuditAction, DeferSubscriptionRequest, ManualGrantRequest, ManualRevokeRequest,
+    PurchaseEnvironment, PurchaseTokenStatus,
+};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_admin_access_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+fn grant_request(user_id: &str, record_purchase: bool) -> ManualGrantRequest {
+    ManualGrantRequest {
+        user_id: user_id.to_string(),
+        reason: "Compensation for outage".to_string(),
+        duration_days: 7,
+        record_purchase,
+    }
+}
+
+fn audit_log(conn: &mut SqliteConnection) -> Vec<AdminAuditEntry> {
+    admin_audit_log::table
+        .order(admin_audit_log::created_at.asc())
+        .load(conn)
+        .unwrap()
+}
+
+// A grant records the operator and reason and stores a synthetic purchase lasting the duration
+#[tokio::test]
+async fn test_grant_records_audit_and_manual_purchase() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let response = grant_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &grant_request("user_1", true),
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.action, AuditAction::GrantPro);
+    assert_eq!(response.operator, "ops@yral.com");
+
+    let token: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(response.purchase_token.unwrap()))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(token.user_id, "user_1");
+    assert_eq!(token.environment, PurchaseEnvironment::Manual);
+    assert_eq!(token.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(token.expiry_at, now + chrono::Duration::days(7));
+    assert!(!token.auto_renewing);
+
+    let entries = audit_log(&mut conn);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, response.audit_id);
+    assert_eq!(entries[0].operator, "ops@yral.com");
+    assert_eq!(entries[0].reason, "Compensation for outage");
+    assert_eq!(entries[0].purchase_token, Some(token.purchase_token));
+}
+
+// Without `record_purchase` only the audit entry is written
+#[tokio::test]
+async fn test_grant_without_purchase_record() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+
+    let response = grant_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &grant_request("user_1", false),
+        chrono::Utc::now().naive_utc(),
+    )
+    .await
+    .unwrap();
+    assert!(response.purchase_token.is_none());
+
+    let tokens: i64 = purchase_tokens::table
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(tokens, 0);
+    assert_eq!(audit_log(&mut conn).len(), 1);
+}
+
+// Revoking expires the user's active purchases so entitlements stop reporting Pro
+#[tokio::test]
+async fn test_revoke_expires_active_purchases() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    grant_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &grant_request("user_1", true),
+        now,
+    )
+    .await
+    .unwrap();
+
+    let response = revoke_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &ManualRevokeRequest {
+            user_id: "user_1".to_string(),
+            reason: "Granted to the wrong account".to_string(),
+            expire_purchases: true,
+        },
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.expired_purchases, 1);
+
+    let active: i64 = purchase_tokens::table
+        .filter(purchase_tokens::status.eq(PurchaseTokenStatus::AccessGranted))
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(active, 0);
+
+    let entries = audit_log(&mut conn);
+    assert_eq!(entries.len(), 2);
+    assert!(entries
+        .iter()
+        .any(|entry| entry.action == AuditAction::RevokePro));
+}
+
+#[tokio::test]
+async fn test_reason_is_required() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+
+    let mut request = grant_request("user_1", true);
+    request.reason = "  ".to_string();
+    let result = grant_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &request,
+        chrono::Utc::now().naive_utc(),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(audit_log(&mut conn).is_empty());
+}
+
+// Deferral extends Google's expiry, mirrors it locally and is audited
+#[tokio::test]
+async fn test_defer_subscription_extends_expiry() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut token = PurchaseToken::new(
+        "user_1".to_string(),
+        format!("token_{}", uuid::Uuid::new_v4()),
+        now,
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    token.package_name = Some("com.example".to_string());
+    token.notified_at = Some(now);
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(&mut conn)
+        .unwrap();
+
+    let request = DeferSubscriptionRequest {
+        days: 7,
+        reason: "Outage on 2026-03-10".to_string(),
+    };
+    let response = defer_subscription_expiry(
+        &mut conn,
+        &MockGooglePlay,
+        "ops@yral.com",
+        &token.purchase_token,
+        &request,
+        now,
+    )
+    .await
+    .unwrap();
+
+    // The mock reports an expiry of 2024-01-01
+    let expected = chrono::DateTime::parse_from_rfc3339("2024-01-08T00:00:00Z")
+        .unwrap()
+        .naive_utc();
+    let stored: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.expiry_at, expected);
+    assert!(stored.notified_at.is_none());
+    assert_eq!(response.expiry_at, expected.and_utc().to_rfc3339());
+
+    let entries = audit_log(&mut conn);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].action, AuditAction::DeferSubscription);
+    assert_eq!(entries[0].user_id, "user_1");
+    assert_eq!(
+        entries[0].purchase_token.as_deref(),
+        Some(token.purchase_token.as_str())
+    );
+}
+
+// Manual grants and unknown tokens can't be deferred through Google
+#[tokio::test]
+async fn test_defer_rejects_manual_and_unknown_tokens() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let granted = grant_manual_access(
+        &mut conn,
+        &MockUserInfo,
+        "ops@yral.com",
+        &grant_request("user_1", true),
+        now,
+    )
+    .await
+    .unwrap();
+
+    let request = DeferSubscriptionRequest {
+        days: 7,
+        reason: "Outage".to_string(),
+    };
+    for purchase_token in [granted.purchase_token.unwrap(), "unknown".to_string()] {
+        let result = defer_subscription_expiry(
+            &mut conn,
+            &MockGooglePlay,
+            "ops@yral.com",
+            &purchase_token,
+            &request,
+            now,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+    assert_eq!(audit_log(&mut conn).len(), 1);
+}
+
+async fn scope_check_status(claims: Claims) -> StatusCode {
+    let app = Router::new()
+        .route("/admin/access/grant", post(|| async { StatusCode::OK }))
+        .layer(middleware::from_fn(require_admin_scope))
+        .layer(middleware::from_fn(
+            move |mut req: axum::extract::Request, next: Next| {
+                req.extensions_mut().insert(claims.clone());
+                next.run(req)
+            },
+        ));
+    let req = Request::builder()
+        .method("POST")
+        .uri("/admin/access/grant")
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(req).await.unwrap().status()
+}
+
+fn claims(sub: Option<&str>, scope: Option<&str>) -> Claims {
+    Claims {
+        aud: "billing".to_string(),
+        exp: 0,
+        sub: sub.map(str::to_string),
+        scope: scope.map(str::to_string),
+    }
+}
+
+// Access changes need the admin scope and a subject to record as the operator
+#[tokio::test]
+async fn test_admin_scope_required() {
+    let scopes = format!("openid {}", ADMIN_SCOPE);
+
+    assert_eq!(
+        scope_check_status(claims(Some("ops@yral.com"), Some(&scopes))).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        scope_check_status(claims(Some("ops@yral.com"), Some("openid"))).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        scope_check_status(claims(Some("ops@yral.com"), None)).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        scope_check_status(claims(None, Some(ADMIN_SCOPE))).await,
+        StatusCode::FORBIDDEN
+    );
+}
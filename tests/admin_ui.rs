@@ -0,0 +1,91 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::{Extension, Router};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::auth::{Claims, ADMIN_SCOPE};
+use yral_billing::exposed_router;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::route_exposure::{Listener, RouteExposure};
+use yral_billing::routes::access::reverify_subscription;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+
+async fn get(app: Router, uri: &str) -> (StatusCode, String) {
+    let res = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body_bytes.to_vec()).unwrap())
+}
+
+// The page needs no JWT itself, but is only served where the admin routes are
+#[tokio::test]
+async fn test_page_served_with_admin_routes() {
+    let app_state = memory_state().await;
+
+    let app = exposed_router(
+        app_state.clone(),
+        &RouteExposure::default(),
+        Listener::Public,
+    );
+    let (status, body) = get(app, "/admin/ui").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("/admin/tokens"));
+
+    let exposure = RouteExposure {
+        internal_port: Some(3001),
+        allowlist: None,
+    };
+    let public = exposed_router(app_state.clone(), &exposure, Listener::Public);
+    assert_eq!(get(public, "/admin/ui").await.0, StatusCode::NOT_FOUND);
+    let internal = exposed_router(app_state, &exposure, Listener::Internal);
+    assert_eq!(get(internal, "/admin/ui").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_reverify_action() {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(SubscriptionResponseBuilder::new().build());
+    PurchaseTokenBuilder::new(MOCK_SUBSCRIPTION_ACCOUNT_ID)
+        .purchase_token("token_1")
+        .insert(&mut app_state.get_db_connection().unwrap());
+    let claims = Claims {
+        aud: "billing".to_string(),
+        exp: 0,
+        sub: Some("ops@yral.com".to_string()),
+        scope: Some(ADMIN_SCOPE.to_string()),
+    };
+    let app = Router::new()
+        .route(
+            "/admin/subscriptions/{token}/reverify",
+            post(reverify_subscription),
+        )
+        .layer(Extension(claims))
+        .with_state(app_state);
+
+    let reverify = |token: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/admin/subscriptions/{}/reverify", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let res = app.clone().oneshot(reverify("token_1")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["result"], "granted");
+    assert_eq!(response["data"]["expiry_at"], serde_json::Value::Null);
+
+    let res = app.oneshot(reverify("unknown")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use yral_billing::analytics::{
+    AnalyticsConsumer, AnalyticsEvent, AnalyticsEventType, AnalyticsSink, TRIAL_OFFER_TAG,
+};
+use yral_billing::events::{DomainEvent, EventConsumer};
+use yral_billing::jobs::analytics_export::export_analytics_events;
+use yral_billing::model::AnalyticsEventRecord;
+use yral_billing::schema::analytics_events;
+use yral_billing::test_support::{
+    memory_state, new_test_user, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{OutboxStatus, PurchaseTokenStatus};
+use yral_billing::AppState;
+
+fn stored(app_state: &AppState) -> Vec<AnalyticsEventRecord> {
+    analytics_events::table
+        .order(analytics_events::event_type.asc())
+        .load(&mut app_state.get_db_connection().unwrap())
+        .unwrap()
+}
+
+/// Ingestion endpoint answering with `status` and keeping what it was sent
+#[derive(Clone, Default)]
+struct FakeIngest {
+    status: Arc<Mutex<Option<StatusCode>>>,
+    received: Arc<Mutex<Vec<(Option<String>, serde_json::Value)>>>,
+}
+
+async fn ingest(
+    State(fake): State<FakeIngest>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> StatusCode {
+    let authorization = headers
+        .get("authorization")
+        .map(|value| value.to_str().unwrap().to_string());
+    fake.received.lock().unwrap().push((authorization, body));
+    fake.status.lock().unwrap().unwrap_or(StatusCode::OK)
+}
+
+async fn start_ingest() -> (FakeIngest, String) {
+    let fake = FakeIngest::default();
+    let app = Router::new()
+        .route("/ingest", post(ingest))
+        .with_state(fake.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (fake, format!("http://{}/ingest", addr))
+}
+
+// A trial bought by a user whose earlier subscription lapsed starts a trial and wins them
+// back, its first renewal converts it, and repeated events don't count twice
+#[tokio::test]
+async fn test_events_derived_once_per_token() {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .offer("free-week", &[TRIAL_OFFER_TAG])
+            .build(),
+    );
+    let user = new_test_user();
+    let mut conn = app_state.get_db_connection().unwrap();
+    PurchaseTokenBuilder::new(&user)
+        .status(PurchaseTokenStatus::Expired)
+        .expiry_at(Utc::now().naive_utc() - Duration::days(60))
+        .insert(&mut conn);
+    let token = PurchaseTokenBuilder::new(&user).insert(&mut conn);
+
+    let granted = DomainEvent::AccessGranted {
+        user_id: user.clone(),
+        purchase_token: token.purchase_token.clone(),
+        product_id: "yral_pro_plan".to_string(),
+    };
+    let renewed = DomainEvent::SubscriptionRenewed {
+        user_id: user.clone(),
+        purchase_token: token.purchase_token.clone(),
+        product_id: "yral_pro_plan".to_string(),
+    };
+    for event in [&granted, &granted, &renewed, &renewed] {
+        AnalyticsConsumer.handle(&app_state, event).await.unwrap();
+    }
+    AnalyticsConsumer
+        .handle(
+            &app_state,
+            &DomainEvent::SubscriptionExpired {
+                user_id: user.clone(),
+                purchase_token: token.purchase_token.clone(),
+            },
+        )
+        .await
+        .unwrap();
+
+    let types: Vec<String> = stored(&app_state)
+        .into_iter()
+        .map(|record| record.event_type)
+        .collect();
+    assert_eq!(
+        types,
+        ["churn", "trial_conversion", "trial_start", "winback"]
+    );
+}
+
+// A refused batch stays pending and goes out on the next run, with the API key
+#[tokio::test]
+async fn test_export_retries_refused_batch() {
+    let (fake, url) = start_ingest().await;
+    let mut app_state = memory_state().await;
+    app_state.analytics = AnalyticsSink::new(Some(url), Some("secret".to_string())).unwrap();
+    let user = new_test_user();
+    let token =
+        PurchaseTokenBuilder::new(&user).insert(&mut app_state.get_db_connection().unwrap());
+    AnalyticsConsumer
+        .handle(
+            &app_state,
+            &DomainEvent::PurchaseVerified {
+                user_id: user.clone(),
+                purchase_token: token.purchase_token.clone(),
+                product_id: "yral_pro_plan".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+    *fake.status.lock().unwrap() = Some(StatusCode::SERVICE_UNAVAILABLE);
+    assert!(export_analytics_events(&app_state, 100).await.is_err());
+    let record = stored(&app_state).remove(0);
+    assert_eq!(record.status, OutboxStatus::Pending);
+    assert_eq!(record.attempts, 1);
+
+    *fake.status.lock().unwrap() = None;
+    assert_eq!(export_analytics_events(&app_state, 100).await.unwrap(), 1);
+    assert_eq!(stored(&app_state)[0].status, OutboxStatus::Done);
+    assert_eq!(export_analytics_events(&app_state, 100).await.unwrap(), 0);
+
+    let received = fake.received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    let (authorization, body) = &received[1];
+    assert_eq!(authorization.as_deref(), Some("Bearer secret"));
+    let sent = &body["events"][0];
+    assert_eq!(sent["id"], serde_json::json!(record.id));
+    let event: AnalyticsEvent = serde_json::from_value(sent.clone()).unwrap();
+    assert_eq!(event.event, AnalyticsEventType::Purchase);
+    assert_eq!(event.user_id, user);
+}
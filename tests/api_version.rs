@@ -0,0 +1,124 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::api_version::{ApiVersion, API_VERSION_HEADER};
+use yral_billing::{build_router, AppState};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_api_version_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+#[test]
+fn test_versions_are_parsed() {
+    assert_eq!(ApiVersion::parse("1"), Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::parse("v1"), Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::parse("2"), None);
+
+    assert_eq!(
+        ApiVersion::from_path("/v1/google/verify"),
+        Some(ApiVersion::V1)
+    );
+    assert_eq!(ApiVersion::from_path("/v1"), Some(ApiVersion::V1));
+    assert_eq!(ApiVersion::from_path("/v10/health"), None);
+    assert_eq!(ApiVersion::from_path("/google/verify"), None);
+}
+
+// Routes answer on both their versioned and legacy paths, and report the version used
+#[tokio::test]
+async fn test_versioned_and_legacy_paths() {
+    let _db_guard = TestDbGuard::new();
+    let app = build_router(AppState::new().await);
+
+    for path in ["/health", "/v1/health"] {
+        let res = app
+            .clone()
+            .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK, "{}", path);
+        assert_eq!(res.headers()[API_VERSION_HEADER], "1");
+    }
+
+    // Protected routes stay protected under the prefix
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/v1/entitlements/user_1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // A version the service doesn't serve is refused
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/health")
+                .header(API_VERSION_HEADER, "2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+// The OpenAPI document points clients at the versioned prefix
+#[tokio::test]
+async fn test_openapi_lists_version_servers() {
+    let _db_guard = TestDbGuard::new();
+    let app = build_router(AppState::new().await);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api-doc/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let doc: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(doc["servers"][0]["url"], "/v1");
+    assert_eq!(doc["info"]["version"], "1.0.0");
+}
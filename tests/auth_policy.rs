@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::routing::get;
+use axum::{middleware, Router};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::auth::{Claims, ADMIN_SCOPE};
+use yral_billing::auth_policy::{
+    authorize_claims, enforce_auth_policy, policy_for, AuthPolicy, ROUTE_POLICIES,
+};
+use yral_billing::integrations::push_auth::PushVerifier;
+use yral_billing::test_support::{memory_state, RtdnBuilder};
+use yral_billing::{build_router, AppState};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_auth_policy_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Refuses every push, like Google's verifier does for a request without a token
+struct RejectingPushVerifier;
+
+#[async_trait]
+impl PushVerifier for RejectingPushVerifier {
+    async fn verify(&self, _header_value: Option<&HeaderValue>) -> Result<(), String> {
+        Err("missing token".to_string())
+    }
+}
+
+async fn request_status(app: &Router, method: &str, path: &str) -> StatusCode {
+    let req = Request::builder()
+        .method(method)
+        .uri(path)
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(req).await.unwrap().status()
+}
+
+/// Concrete path for a template, e.g. `/entitlements/{user_id}` to `/entitlements/user_id`
+fn concrete_path(template: &str) -> String {
+    template.replace(['{', '}'], "")
+}
+
+// Every route in the OpenAPI document is listed in the policy table
+#[tokio::test]
+async fn test_documented_routes_have_policy() {
+    let _db_guard = TestDbGuard::new();
+    let app = build_router(AppState::new().await);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api-doc/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let doc: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(!paths.is_empty());
+    for path in paths.keys() {
+        assert!(policy_for(path).is_some(), "{} has no auth policy", path);
+        assert!(policy_for(&format!("/v1{}", path)).is_some(), "/v1{}", path);
+    }
+}
+
+// Every listed route is served, and refuses anonymous callers unless it is public
+#[tokio::test]
+async fn test_policies_enforced_on_every_route() {
+    let _db_guard = TestDbGuard::new();
+    let mut app_state = AppState::new().await;
+    app_state.push_verifier = Arc::new(RejectingPushVerifier);
+    let app = build_router(app_state);
+
+    for (template, policy) in ROUTE_POLICIES {
+        for prefix in ["", "/v1"] {
+            let path = format!("{}{}", prefix, concrete_path(template));
+            // GET and POST, so the method router's 405 fallback is covered too
+            for method in ["GET", "POST"] {
+                let status = request_status(&app, method, &path).await;
+                match policy {
+                    AuthPolicy::Public => {
+                        assert_ne!(status, StatusCode::NOT_FOUND, "{} {}", method, path);
+                        assert_ne!(status, StatusCode::UNAUTHORIZED, "{} {}", method, path);
+                        assert_ne!(status, StatusCode::FORBIDDEN, "{} {}", method, path);
+                    }
+                    _ => assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, path),
+                }
+            }
+        }
+    }
+}
+
+// A route added without a policy is refused rather than left open
+#[tokio::test]
+async fn test_route_without_policy_refused() {
+    let _db_guard = TestDbGuard::new();
+    let app_state = AppState::new().await;
+    let app = Router::new()
+        .route("/health", get(|| async { StatusCode::OK }))
+        .route("/unlisted", get(|| async { StatusCode::OK }))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            enforce_auth_policy,
+        ))
+        .with_state(app_state);
+
+    assert_eq!(request_status(&app, "GET", "/health").await, StatusCode::OK);
+    assert_eq!(
+        request_status(&app, "GET", "/unlisted").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        request_status(&app, "GET", "/missing").await,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[test]
+fn test_policy_lookup_ignores_version_prefix() {
+    assert_eq!(policy_for("/health"), Some(AuthPolicy::Public));
+    assert_eq!(
+        policy_for("/v1/admin/access/grant"),
+        Some(AuthPolicy::AdminJwt)
+    );
+    assert_eq!(
+        policy_for("/v1/internal/users/{user_id}/teardown"),
+        Some(AuthPolicy::Service)
+    );
+    assert_eq!(policy_for("/v10/health"), None);
+    assert_eq!(policy_for("/unlisted"), None);
+}
+
+fn user_claims(sub: Option<&str>, scope: Option<&str>) -> Claims {
+    Claims {
+        aud: "billing".to_string(),
+        exp: 0,
+        sub: sub.map(str::to_string),
+        scope: scope.map(str::to_string),
+    }
+}
+
+// Every admin route refuses user JWTs without the admin scope
+#[test]
+fn test_admin_routes_need_admin_scope() {
+    let admin_routes: Vec<&str> = ROUTE_POLICIES
+        .iter()
+        .map(|(route, _)| *route)
+        .filter(|route| route.starts_with("/admin/"))
+        .collect();
+    for route in [
+        "/admin/refund-requests/{id}",
+        "/admin/feature-flags/{name}",
+        "/admin/catalog/sync",
+        "/admin/ic-identity/reload",
+        "/admin/export/tokens",
+        "/admin/export/events",
+        "/admin/tokens",
+        "/admin/users/plans",
+        "/admin/users/{user_id}/credit-ledger",
+        "/admin/debug/requests",
+    ] {
+        assert!(admin_routes.contains(&route), "{}", route);
+    }
+
+    let user = user_claims(Some("user_1"), Some("openid"));
+    let admin = user_claims(
+        Some("ops@yral.com"),
+        Some(&format!("openid {}", ADMIN_SCOPE)),
+    );
+    for route in admin_routes {
+        let policy = policy_for(route).unwrap();
+        assert_eq!(policy, AuthPolicy::AdminJwt, "{}", route);
+        assert_eq!(
+            authorize_claims(policy, &user),
+            Err(StatusCode::FORBIDDEN),
+            "{}",
+            route
+        );
+        assert_eq!(
+            authorize_claims(policy, &user_claims(None, Some(ADMIN_SCOPE))),
+            Err(StatusCode::FORBIDDEN),
+            "{}",
+            route
+        );
+        assert_eq!(authorize_claims(policy, &admin), Ok(()), "{}", route);
+    }
+}
+
+// Metrics expose purchase and credit volumes, user JWTs can't read them
+#[test]
+fn test_metrics_need_admin_scope() {
+    let policy = policy_for("/metrics").unwrap();
+    assert_eq!(policy, AuthPolicy::AdminJwt);
+    assert_eq!(
+        authorize_claims(policy, &user_claims(Some("user_1"), Some("openid"))),
+        Err(StatusCode::FORBIDDEN)
+    );
+    assert_eq!(
+        authorize_claims(
+            policy,
+            &user_claims(Some("ops@yral.com"), Some(ADMIN_SCOPE))
+        ),
+        Ok(())
+    );
+}
+
+// Mock integrations keep push auth on, only with a fixed token instead of Google's
+#[tokio::test]
+async fn test_mock_push_verifier_needs_token() {
+    let app = build_router(memory_state().await);
+
+    let anonymous = Request::builder()
+        .method("POST")
+        .uri("/google/rtdn-webhook")
+        .header("content-type", "application/json")
+        .body(Body::from(RtdnBuilder::test().envelope().to_string()))
+        .unwrap();
+    let res = app.clone().oneshot(anonymous).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = app.oneshot(RtdnBuilder::test().request()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
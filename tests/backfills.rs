@@ -0,0 +1,118 @@
+use diesel::prelude::*;
+use yral_billing::jobs::backfills::{run_backfill, Backfill, BackfillReport};
+use yral_billing::model::{BackfillProgress, PurchaseToken};
+use yral_billing::schema::{backfills, purchase_tokens};
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::PurchaseEnvironment;
+use yral_billing::AppState;
+
+/// Run the environment backfill one token per batch
+async fn run(
+    app_state: &AppState,
+    google_play: &FixedGooglePlay,
+    max_batches: Option<usize>,
+    restart: bool,
+) -> BackfillReport {
+    let mut conn = app_state.get_db_connection().unwrap();
+    run_backfill(
+        &mut conn,
+        google_play,
+        app_state.clock.as_ref(),
+        Backfill::Environment,
+        1,
+        max_batches,
+        restart,
+    )
+    .await
+    .unwrap()
+}
+
+#[test]
+fn test_backfill_names() {
+    for backfill in Backfill::ALL {
+        assert_eq!(backfill.name().parse::<Backfill>(), Ok(backfill));
+    }
+    let err = "expiry".parse::<Backfill>().unwrap_err();
+    assert!(err.contains("environment, orders, linked_tokens, line_items"));
+}
+
+// Rows stored as production before `environment` existed are corrected batch by batch, and a
+// run that stopped early continues after the last batch it finished
+#[tokio::test]
+async fn test_environment_backfill_resumes() {
+    let app_state = memory_state().await;
+    let google_play = FixedGooglePlay::new(SubscriptionResponseBuilder::new().sandbox().build());
+    let mut conn = app_state.get_db_connection().unwrap();
+    for user in ["user_1", "user_2", "user_3"] {
+        PurchaseTokenBuilder::new(user).insert(&mut conn);
+    }
+    // Tokens stored before the package name was kept can't be looked up
+    PurchaseTokenBuilder::new("user_4")
+        .package_name(None)
+        .insert(&mut conn);
+
+    let report = run(&app_state, &google_play, Some(1), false).await;
+    assert_eq!((report.processed, report.updated), (1, 1));
+    assert!(!report.completed);
+
+    let report = run(&app_state, &google_play, None, false).await;
+    assert_eq!((report.processed, report.updated, report.failed), (2, 2, 0));
+    assert!(report.completed);
+
+    let sandbox: i64 = purchase_tokens::table
+        .filter(purchase_tokens::environment.eq(PurchaseEnvironment::Sandbox))
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(sandbox, 3);
+
+    let progress: BackfillProgress = backfills::table
+        .find("environment")
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!((progress.processed, progress.updated), (3, 3));
+    assert!(progress.completed_at.is_some());
+
+    // Done, until restarted
+    let report = run(&app_state, &google_play, None, false).await;
+    assert_eq!(report.processed, 0);
+    assert!(report.completed);
+
+    let report = run(&app_state, &google_play, None, true).await;
+    assert_eq!((report.processed, report.updated), (3, 0));
+    assert!(report.completed);
+}
+
+#[tokio::test]
+async fn test_linked_tokens_backfill() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let old = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+    let mut response = SubscriptionResponseBuilder::new().build();
+    response.linked_purchase_token = Some(old.purchase_token.clone());
+    let google_play = FixedGooglePlay::new(response);
+    let new = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+
+    let report = run_backfill(
+        &mut conn,
+        google_play.as_ref(),
+        app_state.clock.as_ref(),
+        Backfill::LinkedTokens,
+        10,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    // Google reports the same link for both tokens here, the old one isn't its own replacement
+    assert_eq!((report.processed, report.updated), (2, 1));
+    assert!(report.completed);
+
+    let old: PurchaseToken = purchase_tokens::table
+        .find(&old.id)
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(old.replaced_by, Some(new.purchase_token));
+}
@@ -0,0 +1,178 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::model::SubscriptionEvent;
+use yral_billing::schema::subscription_events;
+use yral_billing::test_support::{as_user, test_app, test_user, VerifyRequestBuilder};
+use yral_billing::types::SubscriptionEventKind;
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this order for every subscription
+const MOCK_ORDER_ID: &str = "GPA.0000-0000-0000-00000";
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_billing_history_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn get_history(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// A verified purchase shows up with its order id, but without the purchase token
+#[tokio::test]
+async fn test_verified_purchase_is_listed() {
+    let _db_guard = TestDbGuard::new();
+    let app = as_user(test_app(AppState::new().await), &test_user("user_1"));
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1")).request();
+    assert_eq!(
+        app.clone().oneshot(req).await.unwrap().status(),
+        StatusCode::OK
+    );
+
+    let uri = format!("/billing/history/{}", test_user("user_1"));
+    let (status, response) = get_history(app, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let events = response["data"]["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["kind"], "purchase");
+    assert_eq!(events[0]["order_id"], MOCK_ORDER_ID);
+    assert_eq!(events[0]["product_id"], "mock-product-id");
+    assert!(events[0].get("purchase_token").is_none());
+    assert!(response["data"]["next_cursor"].is_null());
+}
+
+// Pages run newest first and the last one carries no cursor
+#[tokio::test]
+async fn test_history_is_paged() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let start = chrono::Utc::now().naive_utc() - chrono::Duration::days(120);
+    let kinds = [
+        SubscriptionEventKind::Purchase,
+        SubscriptionEventKind::Renewal,
+        SubscriptionEventKind::Renewal,
+        SubscriptionEventKind::Cancellation,
+        SubscriptionEventKind::Purchase,
+    ];
+    for (i, kind) in kinds.into_iter().enumerate() {
+        let event = SubscriptionEvent::new(
+            test_user("user_1"),
+            "token_1".to_string(),
+            kind,
+            Some(format!("GPA.0000-0000-0000-00000..{}", i)),
+            "mock-product-id".to_string(),
+            start + chrono::Duration::days(30 * i as i64),
+        );
+        diesel::insert_into(subscription_events::table)
+            .values(&event)
+            .execute(&mut conn)
+            .unwrap();
+    }
+    let app = as_user(test_app(AppState::new().await), &test_user("user_1"));
+
+    let history_uri = format!("/billing/history/{}", test_user("user_1"));
+    let mut seen = Vec::new();
+    let mut uri = format!("{}?limit=2", history_uri);
+    loop {
+        let (status, response) = get_history(app.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        seen.extend(
+            response["data"]["events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|event| event["order_id"].as_str().unwrap().to_string()),
+        );
+        match response["data"]["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("{}?limit=2&cursor={}", history_uri, cursor),
+            None => break,
+        }
+    }
+    let expected: Vec<String> = (0..5)
+        .rev()
+        .map(|i| format!("GPA.0000-0000-0000-00000..{}", i))
+        .collect();
+    assert_eq!(seen, expected);
+
+    let (status, _) = get_history(app.clone(), &format!("{}?cursor=bogus", history_uri)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Ids that aren't principals are refused rather than matching nobody
+    let (status, _) = get_history(app, "/billing/history/user_1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// Users only see their own history, another user's is refused
+#[tokio::test]
+async fn test_other_users_history_refused() {
+    let db_guard = TestDbGuard::new();
+    let event = SubscriptionEvent::new(
+        test_user("user_1"),
+        "token_1".to_string(),
+        SubscriptionEventKind::Purchase,
+        Some(MOCK_ORDER_ID.to_string()),
+        "mock-product-id".to_string(),
+        chrono::Utc::now().naive_utc(),
+    );
+    diesel::insert_into(subscription_events::table)
+        .values(&event)
+        .execute(&mut db_guard.conn())
+        .unwrap();
+    let app = as_user(test_app(AppState::new().await), &test_user("user_2"));
+
+    let uri = format!("/billing/history/{}", test_user("user_1"));
+    let (status, response) = get_history(app.clone(), &uri).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(response["code"], "NOT_OWNER");
+
+    let uri = format!("/billing/history/{}", test_user("user_2"));
+    let (status, response) = get_history(app, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(response["data"]["events"].as_array().unwrap().is_empty());
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use yral_billing::cache::{Cache, MemoryCache};
+
+#[tokio::test]
+async fn test_memory_cache_entries_expire() {
+    let cache = MemoryCache::default();
+    let ttl = Duration::from_millis(100);
+
+    cache.set("key", "first", ttl).await.unwrap();
+    assert_eq!(cache.get("key").await.unwrap().as_deref(), Some("first"));
+    assert!(!cache.set_if_absent("key", "second", ttl).await.unwrap());
+    assert_eq!(cache.get("key").await.unwrap().as_deref(), Some("first"));
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(cache.get("key").await.unwrap(), None);
+    assert!(cache.set_if_absent("key", "second", ttl).await.unwrap());
+
+    cache.delete("key").await.unwrap();
+    assert_eq!(cache.get("key").await.unwrap(), None);
+}
+
+// Counters keep the TTL of their first increment and start over once it passes
+#[tokio::test]
+async fn test_memory_cache_counters() {
+    let cache = MemoryCache::default();
+    let ttl = Duration::from_millis(100);
+
+    assert_eq!(cache.increment("count", ttl).await.unwrap(), 1);
+    assert_eq!(cache.increment("count", ttl).await.unwrap(), 2);
+    assert_eq!(cache.increment("other", ttl).await.unwrap(), 1);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(cache.increment("count", ttl).await.unwrap(), 1);
+}
@@ -0,0 +1,186 @@
+use axum::body::Body;
+use axum::http::{header::RETRY_AFTER, Request, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::events::DomainEvent;
+use yral_billing::model::{CancelIntent, DomainEventRecord};
+use yral_billing::routes::cancel_intent::record_cancel_intent;
+use yral_billing::schema::{cancel_intents, domain_events};
+use yral_billing::test_support::{memory_state, new_test_user, PurchaseTokenBuilder};
+use yral_billing::types::CancelReason;
+use yral_billing::AppState;
+
+fn app(app_state: AppState) -> Router {
+    Router::new()
+        .route("/google/cancel-intent", post(record_cancel_intent))
+        .with_state(app_state)
+}
+
+async fn post_intent(
+    app_state: &AppState,
+    payload: serde_json::Value,
+) -> (StatusCode, Option<String>, serde_json::Value) {
+    let res = app(app_state.clone())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/google/cancel-intent")
+                .header("content-type", "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let retry_after = res
+        .headers()
+        .get(RETRY_AFTER)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (
+        status,
+        retry_after,
+        serde_json::from_slice(&body_bytes).unwrap(),
+    )
+}
+
+fn intent(user: &str, token: &str, reason: &str) -> serde_json::Value {
+    serde_json::json!({
+        "user_id": user,
+        "purchase_token": token,
+        "reason": reason,
+        "comment": "Not watching enough",
+    })
+}
+
+// The first intent for a renewing subscription triggers one retention offer, later ones don't
+#[tokio::test]
+async fn test_first_intent_triggers_retention_offer() {
+    let app_state = memory_state().await;
+    let user = new_test_user();
+    let token =
+        PurchaseTokenBuilder::new(&user).insert(&mut app_state.get_db_connection().unwrap());
+
+    let (status, _, body) = post_intent(
+        &app_state,
+        intent(&user, &token.purchase_token, "too_expensive"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["retention_offer"], true);
+
+    let (status, _, body) = post_intent(
+        &app_state,
+        intent(&user, &token.purchase_token, "not_using_enough"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["retention_offer"], false);
+
+    let mut conn = app_state.get_db_connection().unwrap();
+    let stored: Vec<CancelIntent> = cancel_intents::table
+        .filter(cancel_intents::purchase_token.eq(&token.purchase_token))
+        .order(cancel_intents::retention_offered.desc())
+        .load(&mut conn)
+        .unwrap();
+    let reasons: Vec<(CancelReason, bool)> = stored
+        .iter()
+        .map(|intent| (intent.reason, intent.retention_offered))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            (CancelReason::TooExpensive, true),
+            (CancelReason::NotUsingEnough, false)
+        ]
+    );
+    assert_eq!(stored[0].comment.as_deref(), Some("Not watching enough"));
+
+    let events: Vec<DomainEventRecord> = domain_events::table
+        .filter(domain_events::event_type.eq("retention_offer_requested"))
+        .load(&mut conn)
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(
+        serde_json::from_str::<DomainEvent>(&events[0].payload).unwrap(),
+        DomainEvent::RetentionOfferRequested {
+            user_id: user.clone(),
+            purchase_token: token.purchase_token.clone(),
+            reason: CancelReason::TooExpensive,
+            expires_at: token.expiry_at.and_utc().to_rfc3339(),
+        }
+    );
+}
+
+// Intents are still recorded when renewal is already off, without an offer
+#[tokio::test]
+async fn test_no_offer_once_renewal_is_off() {
+    let app_state = memory_state().await;
+    let user = new_test_user();
+    let token = PurchaseTokenBuilder::new(&user)
+        .auto_renewing(false)
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    let (status, _, body) =
+        post_intent(&app_state, intent(&user, &token.purchase_token, "other")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["retention_offer"], false);
+}
+
+#[tokio::test]
+async fn test_intent_rejected_for_other_users_token() {
+    let app_state = memory_state().await;
+    let token = PurchaseTokenBuilder::new(&new_test_user())
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    let (status, _, _) = post_intent(
+        &app_state,
+        intent(&new_test_user(), &token.purchase_token, "other"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, _) = post_intent(
+        &app_state,
+        intent(&new_test_user(), "unknown_token", "other"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// Past the hourly limit a user is told when to retry, other users aren't affected
+#[tokio::test]
+async fn test_intents_rate_limited_per_user() {
+    let mut app_state = memory_state().await;
+    app_state.cancel_intent_limit = 2;
+    let user = new_test_user();
+    let token =
+        PurchaseTokenBuilder::new(&user).insert(&mut app_state.get_db_connection().unwrap());
+
+    for _ in 0..2 {
+        let (status, _, _) =
+            post_intent(&app_state, intent(&user, &token.purchase_token, "other")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, retry_after, body) =
+        post_intent(&app_state, intent(&user, &token.purchase_token, "other")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["code"], "RATE_LIMITED");
+    let retry_after: u64 = retry_after.unwrap().parse().unwrap();
+    assert!((1..=3600).contains(&retry_after));
+
+    let other = new_test_user();
+    let other_token =
+        PurchaseTokenBuilder::new(&other).insert(&mut app_state.get_db_connection().unwrap());
+    let (status, _, _) = post_intent(
+        &app_state,
+        intent(&other, &other_token.purchase_token, "other"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,86 @@
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use chrono::{Duration, Utc};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::plans::CreditAllotments;
+use yral_billing::routes::entitlements::load_entitlements;
+use yral_billing::routes::rtdn::{cancellation_intent, handle_rtdn_webhook};
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{PurchaseTokenStatus, SubscriptionNotificationType};
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+#[test]
+fn test_cancellation_intent_only_when_renewal_turned_off() {
+    let now = Utc::now().naive_utc();
+    let token = PurchaseTokenBuilder::new("user_1").build();
+    let canceled = SubscriptionResponseBuilder::new()
+        .product_id("yral_pro_monthly")
+        .auto_renewing(false)
+        .build();
+
+    let event = cancellation_intent(&token, &canceled, now).unwrap();
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        serde_json::json!({
+            "event": "cancellation_intent",
+            "user_id": "user_1",
+            "purchase_token": token.purchase_token,
+            "product_id": "yral_pro_monthly",
+            "expires_at": token.expiry_at.and_utc().to_rfc3339(),
+        })
+    );
+
+    // Still renewing, already turned off before, or no access left to win back
+    let renewing = SubscriptionResponseBuilder::new()
+        .auto_renewing(true)
+        .build();
+    assert!(cancellation_intent(&token, &renewing, now).is_none());
+    let already_off = PurchaseTokenBuilder::new("user_1")
+        .auto_renewing(false)
+        .build();
+    assert!(cancellation_intent(&already_off, &canceled, now).is_none());
+    let lapsed = PurchaseTokenBuilder::new("user_1")
+        .status(PurchaseTokenStatus::Expired)
+        .expiry_at(now - Duration::days(1))
+        .build();
+    assert!(cancellation_intent(&lapsed, &canceled, now).is_none());
+}
+
+// A cancellation keeps access but shows on the entitlements that renewal is off
+#[tokio::test]
+async fn test_canceled_notification_surfaces_renewal_off() {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .auto_renewing(false)
+            .build(),
+    );
+    let token =
+        PurchaseTokenBuilder::new(MOCK_USER_ID).insert(&mut app_state.get_db_connection().unwrap());
+
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state.clone());
+    let rtdn = RtdnBuilder::subscription(
+        SubscriptionNotificationType::Canceled,
+        &token.purchase_token,
+    );
+    let res = app.oneshot(rtdn.request()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let entitlements = load_entitlements(
+        &mut app_state.get_db_connection().unwrap(),
+        MOCK_USER_ID.to_string(),
+        &CreditAllotments::default(),
+        Utc::now().naive_utc(),
+    )
+    .unwrap();
+    assert_eq!(entitlements.auto_renewing, Some(false));
+    assert!(entitlements.valid_until.is_some());
+}
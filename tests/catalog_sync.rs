@@ -0,0 +1,103 @@
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::clock::TestClock;
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::integrations::google_play::MockGooglePlay;
+use yral_billing::jobs::catalog_sync::{missing_plan_products, sync_product_catalog};
+use yral_billing::model::Product;
+use yral_billing::schema::products;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_catalog_sync_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+fn stored(conn: &mut SqliteConnection) -> Vec<Product> {
+    products::table.load(conn).unwrap()
+}
+
+// Each base plan is stored and a re-sync replaces the package's catalog
+#[tokio::test]
+async fn test_sync_stores_base_plans() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let clock = TestClock::new(chrono::Utc::now());
+
+    let synced = sync_product_catalog(&mut conn, &MockGooglePlay, "com.example", &clock)
+        .await
+        .unwrap();
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].product_id, "mock-product-id");
+    assert_eq!(synced[0].base_plan_id, "mock-base-plan");
+    assert_eq!(synced[0].billing_period.as_deref(), Some("P1M"));
+    assert!(synced[0].is_active());
+
+    sync_product_catalog(&mut conn, &MockGooglePlay, "com.example", &clock)
+        .await
+        .unwrap();
+    sync_product_catalog(&mut conn, &MockGooglePlay, "com.other", &clock)
+        .await
+        .unwrap();
+    let rows = stored(&mut conn);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows.iter()
+            .filter(|product| product.package_name == "com.example")
+            .count(),
+        1
+    );
+}
+
+// Plans whose product has no active base plan in the Console are reported
+#[test]
+fn test_missing_plan_products() {
+    let now = chrono::Utc::now().naive_utc();
+    let product = |product_id: &str, state: &str, archived: bool| {
+        Product::new(
+            "com.example".to_string(),
+            product_id.to_string(),
+            "monthly".to_string(),
+            state.to_string(),
+            Some("P1M".to_string()),
+            archived,
+            now,
+        )
+    };
+
+    assert_eq!(
+        missing_plan_products(&[product("mock-product-id", "ACTIVE", false)]),
+        vec![YRAL_PRO_PLAN_PRODUCT_ID.to_string()]
+    );
+    assert_eq!(
+        missing_plan_products(&[product(YRAL_PRO_PLAN_PRODUCT_ID, "INACTIVE", false)]),
+        vec![YRAL_PRO_PLAN_PRODUCT_ID.to_string()]
+    );
+    assert_eq!(
+        missing_plan_products(&[product(YRAL_PRO_PLAN_PRODUCT_ID, "ACTIVE", true)]),
+        vec![YRAL_PRO_PLAN_PRODUCT_ID.to_string()]
+    );
+    assert!(
+        missing_plan_products(&[product(YRAL_PRO_PLAN_PRODUCT_ID, "ACTIVE", false)]).is_empty()
+    );
+}
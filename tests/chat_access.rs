@@ -3,28 +3,17 @@ use axum::http::{Request, StatusCode};
 use axum::Router;
 use diesel::prelude::*;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::sync::Arc;
 use tower::ServiceExt; // for `oneshot`
 use uuid;
-use yral_billing::routes::chat_access::{check_chat_access, grant_chat_access};
+use yral_billing::clock::TestClock;
+use yral_billing::integrations::google_play::MOCK_PRODUCT_ACCOUNT_ID;
+use yral_billing::test_support::{new_test_user, test_app};
 use yral_billing::types::{BotChatAccessStatus, GrantChatAccessRequest};
 use yral_billing::AppState;
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
-async fn create_test_app() -> Router {
-    let app_state = AppState::new().await;
-    Router::new()
-        .route(
-            "/google/chat-access/grant",
-            axum::routing::post(grant_chat_access),
-        )
-        .route(
-            "/google/chat-access/check",
-            axum::routing::get(check_chat_access),
-        )
-        .with_state(app_state)
-}
-
 struct TestDbGuard {
     db_path: String,
     original_database_url: Option<String>,
@@ -95,7 +84,7 @@ async fn get_check(app: Router, user_id: &str, bot_id: &str) -> axum::response::
 #[tokio::test]
 async fn test_grant_chat_access_success() {
     let _db_guard = TestDbGuard::new();
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
 
     let token = format!("token_{}", uuid::Uuid::new_v4());
     let res = post_grant(app, &grant_request(&token, "bot_abc")).await;
@@ -111,12 +100,12 @@ async fn test_grant_chat_access_idempotent() {
     let payload = grant_request(&token, "bot_abc");
 
     // First grant
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &payload).await;
     assert_eq!(res.status(), StatusCode::OK);
 
     // Second grant — same token, same bot
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &payload).await;
     assert_eq!(res.status(), StatusCode::OK);
 }
@@ -128,12 +117,12 @@ async fn test_grant_chat_access_different_bot_rejected() {
     let token = format!("token_{}", uuid::Uuid::new_v4());
 
     // Grant for bot_abc
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &grant_request(&token, "bot_abc")).await;
     assert_eq!(res.status(), StatusCode::OK);
 
     // Attempt to use same token for bot_xyz
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &grant_request(&token, "bot_xyz")).await;
     assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
@@ -145,18 +134,18 @@ async fn test_grant_chat_access_different_bot_rejected() {
 }
 
 // Check returns has_access=true after a successful grant
-// Note: the mock returns obfuscated_external_account_id = "mock-user-id"
+// Note: the mock returns obfuscated_external_account_id = MOCK_PRODUCT_ACCOUNT_ID
 #[tokio::test]
 async fn test_check_chat_access_active() {
     let _db_guard = TestDbGuard::new();
     let token = format!("token_{}", uuid::Uuid::new_v4());
 
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &grant_request(&token, "bot_abc")).await;
     assert_eq!(res.status(), StatusCode::OK);
 
-    let app = create_test_app().await;
-    let res = get_check(app, "mock-user-id", "bot_abc").await;
+    let app = test_app(AppState::new().await);
+    let res = get_check(app, MOCK_PRODUCT_ACCOUNT_ID, "bot_abc").await;
     assert_eq!(res.status(), StatusCode::OK);
 
     let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
@@ -171,9 +160,9 @@ async fn test_check_chat_access_active() {
 #[tokio::test]
 async fn test_check_chat_access_no_grant() {
     let _db_guard = TestDbGuard::new();
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
 
-    let res = get_check(app, "unknown-user", "bot_abc").await;
+    let res = get_check(app, &new_test_user(), "bot_abc").await;
     assert_eq!(res.status(), StatusCode::OK);
 
     let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
@@ -193,7 +182,7 @@ async fn test_check_chat_access_canceled() {
     let token = format!("token_{}", uuid::Uuid::new_v4());
 
     // Grant access
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
     let res = post_grant(app, &grant_request(&token, "bot_abc")).await;
     assert_eq!(res.status(), StatusCode::OK);
 
@@ -209,8 +198,8 @@ async fn test_check_chat_access_canceled() {
         .unwrap();
 
     // Check should now return false
-    let app = create_test_app().await;
-    let res = get_check(app, "mock-user-id", "bot_abc").await;
+    let app = test_app(AppState::new().await);
+    let res = get_check(app, MOCK_PRODUCT_ACCOUNT_ID, "bot_abc").await;
     assert_eq!(res.status(), StatusCode::OK);
 
     let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
@@ -234,7 +223,7 @@ async fn test_check_chat_access_expired() {
     let expired_at = (chrono::Utc::now() - chrono::Duration::hours(1)).naive_utc();
     let mut grant = BotChatAccess::new(
         token.clone(),
-        "mock-user-id".to_string(),
+        MOCK_PRODUCT_ACCOUNT_ID.to_string(),
         "bot_abc".to_string(),
         expired_at,
     );
@@ -246,13 +235,47 @@ async fn test_check_chat_access_expired() {
         .execute(&mut conn)
         .unwrap();
 
-    let app = create_test_app().await;
-    let res = get_check(app, "mock-user-id", "bot_abc").await;
+    let app = test_app(AppState::new().await);
+    let res = get_check(app, MOCK_PRODUCT_ACCOUNT_ID, "bot_abc").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["has_access"], false);
+}
+
+// Access lapses once the test clock moves past the 24 hour window
+#[tokio::test]
+async fn test_check_chat_access_lapses_after_window() {
+    let _db_guard = TestDbGuard::new();
+    let clock = Arc::new(TestClock::new(chrono::Utc::now()));
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+    let mut app_state = AppState::new().await;
+    app_state.clock = clock.clone();
+    let app = test_app(app_state);
+
+    let res = post_grant(app.clone(), &grant_request(&token, "bot_abc")).await;
     assert_eq!(res.status(), StatusCode::OK);
 
+    clock.advance(chrono::Duration::hours(23));
+    let res = get_check(app.clone(), MOCK_PRODUCT_ACCOUNT_ID, "bot_abc").await;
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["has_access"], true);
+
+    clock.advance(chrono::Duration::hours(2));
+    let res = get_check(app.clone(), MOCK_PRODUCT_ACCOUNT_ID, "bot_abc").await;
     let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
         .await
         .unwrap();
     let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
     assert_eq!(response["data"]["has_access"], false);
+
+    // Re-granting with the spent token is rejected as expired
+    let res = post_grant(app, &grant_request(&token, "bot_abc")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 }
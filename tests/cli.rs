@@ -0,0 +1,141 @@
+use clap::Parser;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::cli::{run_maintenance, Cli, Command};
+use yral_billing::jobs::backfills::Backfill;
+use yral_billing::model::PurchaseToken;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_cli_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+#[test]
+fn test_subcommands_are_parsed() {
+    let cli = Cli::try_parse_from(["yral-billing"]).unwrap();
+    assert_eq!(cli.command(), Command::Serve);
+
+    let cli = Cli::try_parse_from(["yral-billing", "reverify", "token-1"]).unwrap();
+    assert_eq!(
+        cli.command(),
+        Command::Reverify {
+            token: "token-1".to_string()
+        }
+    );
+
+    let cli = Cli::try_parse_from(["yral-billing", "expire-sweep"]).unwrap();
+    assert_eq!(cli.command(), Command::ExpireSweep);
+
+    let cli = Cli::try_parse_from(["yral-billing", "import", "--file", "purchases.csv"]).unwrap();
+    assert_eq!(
+        cli.command(),
+        Command::Import {
+            file: "purchases.csv".into()
+        }
+    );
+
+    let cli =
+        Cli::try_parse_from(["yral-billing", "backfill", "orders", "--max-batches", "2"]).unwrap();
+    assert_eq!(
+        cli.command(),
+        Command::Backfill {
+            name: Backfill::Orders,
+            max_batches: Some(2),
+            restart: false,
+        }
+    );
+
+    // Flags used before the subcommands existed still work
+    let cli = Cli::try_parse_from(["yral-billing", "--seed"]).unwrap();
+    assert_eq!(cli.command(), Command::Seed);
+
+    let cli = Cli::try_parse_from([
+        "yral-billing",
+        "smoke-test",
+        "https://billing-staging.yral.com",
+        "--principal",
+        "aaaaa-aa",
+    ])
+    .unwrap();
+    assert_eq!(
+        cli.command(),
+        Command::SmokeTest {
+            base_url: "https://billing-staging.yral.com".to_string(),
+            principal: Some("aaaaa-aa".to_string()),
+        }
+    );
+
+    assert!(Cli::try_parse_from(["yral-billing", "reverify"]).is_err());
+    assert!(Cli::try_parse_from(["yral-billing", "smoke-test"]).is_err());
+    assert!(Cli::try_parse_from(["yral-billing", "import"]).is_err());
+    assert!(Cli::try_parse_from(["yral-billing", "backfill", "expiry"]).is_err());
+}
+
+// `expire-sweep` expires lapsed tokens once and reports how many
+#[tokio::test]
+async fn test_expire_sweep_command() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let app_state = AppState::new().await;
+
+    let lapsed = PurchaseToken::new(
+        "user_1".to_string(),
+        format!("token_{}", uuid::Uuid::new_v4()),
+        chrono::Utc::now().naive_utc() - chrono::Duration::days(1),
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&lapsed)
+        .execute(&mut conn)
+        .unwrap();
+
+    let summary = run_maintenance(&Command::ExpireSweep, &app_state)
+        .await
+        .unwrap();
+    assert_eq!(summary, "Expired 1 purchase tokens");
+
+    let stored: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&lapsed.id))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::Expired);
+
+    assert!(run_maintenance(&Command::Serve, &app_state).await.is_err());
+}
@@ -0,0 +1,298 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use uuid;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::model::{CompensationGrant, PurchaseToken};
+use yral_billing::routes::compensations::{
+    create_compensation_batch, resume_compensation_batch, run_compensation_batch, STALE_RUN_AFTER,
+};
+use yral_billing::schema::{compensation_batches, compensation_grants, purchase_tokens};
+use yral_billing::test_support::PurchaseTokenBuilder;
+use yral_billing::types::{
+    CompensationBatchStatus, CompensationFilter, CompensationGrantStatus, CompensationKind,
+    CreateCompensationRequest, Plan, PurchaseEnvironment, PurchaseTokenStatus,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const OPERATOR: &str = "ops@yral.com";
+const FIRST_PRINCIPAL: &str = "2vxsx-fae";
+const SECOND_PRINCIPAL: &str = "aaaaa-aa";
+
+/// Mock user info that counts credited amounts and can be taken offline
+#[derive(Clone, Default)]
+struct CountingUserInfo {
+    credited: Arc<AtomicU32>,
+    offline: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl UserInfoApi for CountingUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        MockUserInfo.grant_pro_plan(product_id, user_id).await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, _user_principal: Principal, amount: u32) -> AppResult<()> {
+        if self.offline.load(Ordering::SeqCst) {
+            return Err(AppError::ServiceAccessFailed("unreachable".to_string()));
+        }
+        self.credited.fetch_add(amount, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_compensations_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn test_state(user_info: &CountingUserInfo) -> AppState {
+    let mut app_state = AppState::new().await;
+    app_state.user_info = Arc::new(user_info.clone());
+    app_state
+}
+
+fn request(kind: CompensationKind, user_ids: &[&str]) -> CreateCompensationRequest {
+    CreateCompensationRequest {
+        incident: "INC-42".to_string(),
+        reason: "Video generation outage".to_string(),
+        kind,
+        amount: 5,
+        user_ids: user_ids.iter().map(|user| user.to_string()).collect(),
+        filter: None,
+    }
+}
+
+fn grants(conn: &mut SqliteConnection, batch_id: &str) -> Vec<CompensationGrant> {
+    compensation_grants::table
+        .filter(compensation_grants::batch_id.eq(batch_id))
+        .order(compensation_grants::user_id.asc())
+        .load(conn)
+        .unwrap()
+}
+
+// Listed users and users subscribed during the incident are merged, once each
+#[tokio::test]
+async fn test_filter_selects_users_active_during_incident() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let incident_start = now - chrono::Duration::days(3);
+    let incident_end = now - chrono::Duration::days(2);
+
+    let day = chrono::Duration::days(1);
+    PurchaseTokenBuilder::new("subscriber")
+        .created_at(now - day * 20)
+        .expiry_at(now + day * 10)
+        .insert(&mut conn);
+    PurchaseTokenBuilder::new("lapsed")
+        .created_at(now - day * 40)
+        .expiry_at(now - day * 10)
+        .insert(&mut conn);
+    PurchaseTokenBuilder::new("late")
+        .created_at(now - day)
+        .expiry_at(now + day * 29)
+        .insert(&mut conn);
+    PurchaseTokenBuilder::new("unpaid")
+        .created_at(now - day * 5)
+        .expiry_at(now + day * 5)
+        .status(PurchaseTokenStatus::Pending)
+        .insert(&mut conn);
+
+    let mut payload = request(CompensationKind::ProDays, &["listed", "subscriber"]);
+    payload.filter = Some(CompensationFilter {
+        active_from: incident_start.and_utc(),
+        active_until: incident_end.and_utc(),
+    });
+    let batch = create_compensation_batch(&mut conn, OPERATOR, &payload, now).unwrap();
+
+    let users: Vec<String> = grants(&mut conn, &batch.id)
+        .into_iter()
+        .map(|grant| grant.user_id)
+        .collect();
+    assert_eq!(users, vec!["listed", "subscriber"]);
+    assert_eq!(batch.status, CompensationBatchStatus::Running);
+}
+
+#[tokio::test]
+async fn test_pro_days_recorded_as_manual_purchases() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let user_info = CountingUserInfo::default();
+    let app_state = test_state(&user_info).await;
+    let now = app_state.clock.now_naive();
+
+    let payload = request(CompensationKind::ProDays, &["alice", "bob"]);
+    let batch = create_compensation_batch(&mut conn, OPERATOR, &payload, now).unwrap();
+    let batch = run_compensation_batch(&app_state, &batch.id).await.unwrap();
+
+    assert_eq!(batch.status, CompensationBatchStatus::Completed);
+    for grant in grants(&mut conn, &batch.id) {
+        assert_eq!(grant.status, CompensationGrantStatus::Granted);
+        let token: PurchaseToken = purchase_tokens::table
+            .filter(purchase_tokens::purchase_token.eq(grant.purchase_token.unwrap()))
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(token.user_id, grant.user_id);
+        assert_eq!(token.environment, PurchaseEnvironment::Manual);
+        assert!(token.expiry_at > now + chrono::Duration::days(4));
+    }
+}
+
+// A failed run is resumed for the users left, and nobody is credited twice
+#[tokio::test]
+async fn test_resume_retries_failed_users_once() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let user_info = CountingUserInfo::default();
+    let app_state = test_state(&user_info).await;
+    let now = app_state.clock.now_naive();
+
+    let payload = request(
+        CompensationKind::Credits,
+        &[FIRST_PRINCIPAL, SECOND_PRINCIPAL],
+    );
+    let batch = create_compensation_batch(&mut conn, OPERATOR, &payload, now).unwrap();
+
+    user_info.offline.store(true, Ordering::SeqCst);
+    let batch = run_compensation_batch(&app_state, &batch.id).await.unwrap();
+    assert_eq!(batch.status, CompensationBatchStatus::Failed);
+    assert!(grants(&mut conn, &batch.id)
+        .iter()
+        .all(|grant| grant.status == CompensationGrantStatus::Failed && grant.error.is_some()));
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 0);
+
+    user_info.offline.store(false, Ordering::SeqCst);
+    let resumed = resume_compensation_batch(&mut conn, &batch.id, now).unwrap();
+    assert_eq!(resumed.status, CompensationBatchStatus::Running);
+    let batch = run_compensation_batch(&app_state, &batch.id).await.unwrap();
+    assert_eq!(batch.status, CompensationBatchStatus::Completed);
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 10);
+
+    // A run that died after crediting but before recording the result
+    diesel::update(
+        compensation_grants::table.filter(compensation_grants::user_id.eq(FIRST_PRINCIPAL)),
+    )
+    .set(compensation_grants::status.eq(CompensationGrantStatus::Pending))
+    .execute(&mut conn)
+    .unwrap();
+    run_compensation_batch(&app_state, &batch.id).await.unwrap();
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 10);
+    assert!(grants(&mut conn, &batch.id)
+        .iter()
+        .all(|grant| grant.status == CompensationGrantStatus::Granted));
+}
+
+// Running batches can only be taken over once they stopped making progress
+#[tokio::test]
+async fn test_resume_requires_stopped_batch() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let payload = request(CompensationKind::Credits, &[FIRST_PRINCIPAL]);
+    let batch = create_compensation_batch(&mut conn, OPERATOR, &payload, now).unwrap();
+
+    assert!(matches!(
+        resume_compensation_batch(&mut conn, &batch.id, now),
+        Err(AppError::BadRequest(_))
+    ));
+
+    let later = now + STALE_RUN_AFTER + chrono::Duration::seconds(1);
+    let resumed = resume_compensation_batch(&mut conn, &batch.id, later).unwrap();
+    assert_eq!(resumed.updated_at, later);
+
+    // The resumed run now holds the batch
+    assert!(resume_compensation_batch(&mut conn, &batch.id, later).is_err());
+
+    diesel::update(compensation_batches::table.find(&batch.id))
+        .set(compensation_batches::status.eq(CompensationBatchStatus::Completed))
+        .execute(&mut conn)
+        .unwrap();
+    let far_later = later + STALE_RUN_AFTER * 2;
+    assert!(resume_compensation_batch(&mut conn, &batch.id, far_later).is_err());
+    assert!(resume_compensation_batch(&mut conn, "unknown", far_later).is_err());
+}
+
+#[test]
+fn test_invalid_requests_rejected() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut missing_reason = request(CompensationKind::Credits, &[FIRST_PRINCIPAL]);
+    missing_reason.reason = " ".to_string();
+    let mut zero_amount = request(CompensationKind::Credits, &[FIRST_PRINCIPAL]);
+    zero_amount.amount = 0;
+    let no_users = request(CompensationKind::ProDays, &[" "]);
+    let mut inverted_window = request(CompensationKind::ProDays, &[]);
+    inverted_window.filter = Some(CompensationFilter {
+        active_from: chrono::Utc::now(),
+        active_until: chrono::Utc::now() - chrono::Duration::hours(1),
+    });
+
+    for payload in [missing_reason, zero_amount, no_users, inverted_window] {
+        assert!(matches!(
+            create_compensation_batch(&mut conn, OPERATOR, &payload, now),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+    let batches: i64 = compensation_batches::table
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(batches, 0);
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::{get, post};
+use axum::{Extension, Router};
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppResult;
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::jobs::credit_ledger_check::{check_credit_ledger, LedgerCheckReport};
+use yral_billing::metrics::{Metrics, CREDIT_LEDGER_MISMATCHES_TOTAL};
+use yral_billing::routes::credits::{
+    deduct_credits, get_credit_ledger, ledger_balance, top_up_renewal_credits,
+};
+use yral_billing::service_auth::Caller;
+use yral_billing::test_support::memory_state;
+use yral_billing::types::Plan;
+use yral_billing::AppState;
+
+const USER_PRINCIPAL: &str = "2vxsx-fae";
+
+/// Canister fake reporting each user's credits
+#[derive(Clone, Default)]
+struct CreditStore {
+    credits: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+#[async_trait]
+impl UserInfoApi for CreditStore {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        MockUserInfo.grant_pro_plan(product_id, user_id).await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        Ok(self
+            .credits
+            .lock()
+            .unwrap()
+            .get(user_id)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+async fn deduct(app_state: &AppState, amount: u32, correlation_id: &str) {
+    let app = Router::new()
+        .route("/credits/deduct", post(deduct_credits))
+        .layer(Extension(Caller::Service("video".to_string())))
+        .with_state(app_state.clone());
+    let body = serde_json::json!({
+        "user_principal": USER_PRINCIPAL,
+        "amount": amount,
+        "correlation_id": correlation_id,
+    });
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/credits/deduct")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+async fn ledger(app_state: &AppState, query: &str) -> serde_json::Value {
+    let app = Router::new()
+        .route(
+            "/admin/users/{user_id}/credit-ledger",
+            get(get_credit_ledger),
+        )
+        .with_state(app_state.clone());
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "/admin/users/{}/credit-ledger{}",
+                    USER_PRINCIPAL, query
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    response["data"].clone()
+}
+
+// Top-ups and API changes are recorded with who made them and what for
+#[tokio::test]
+async fn test_changes_recorded() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+
+    assert!(
+        top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.1", 30)
+            .await
+            .unwrap()
+    );
+    deduct(&app_state, 3, "video_1").await;
+
+    let first = ledger(&app_state, "?limit=1").await;
+    assert_eq!(first["balance"], 27);
+    assert_eq!(first["entries"]["total_estimate"], 2);
+    let entry = &first["entries"]["items"][0];
+    assert_eq!(entry["direction"], "deduct");
+    assert_eq!(entry["amount"], 3);
+    assert_eq!(entry["reason"], "spent");
+    assert_eq!(entry["actor"], "service:video");
+    assert_eq!(entry["correlation_id"], "video_1");
+
+    let cursor = first["entries"]["next_cursor"].as_str().unwrap();
+    let second = ledger(&app_state, &format!("?limit=1&cursor={}", cursor)).await;
+    let entry = &second["entries"]["items"][0];
+    assert_eq!(entry["direction"], "increment");
+    assert_eq!(entry["reason"], "renewal_topup");
+    assert_eq!(entry["actor"], "billing");
+    assert_eq!(entry["correlation_id"], "GPA.1");
+    assert_eq!(second["entries"]["next_cursor"], serde_json::Value::Null);
+}
+
+// A balance changed outside the ledger gets a balancing entry, once
+#[tokio::test]
+async fn test_check_balances_ledger() {
+    let store = CreditStore::default();
+    let mut app_state = memory_state().await;
+    app_state.user_info = Arc::new(store.clone());
+    let metrics = Metrics::new();
+    let mut conn = app_state.get_db_connection().unwrap();
+
+    top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.1", 30)
+        .await
+        .unwrap();
+    deduct(&app_state, 3, "video_1").await;
+    // A Pro grant reset the credits to the allotment
+    store
+        .credits
+        .lock()
+        .unwrap()
+        .insert(USER_PRINCIPAL.to_string(), 37);
+
+    let clock = app_state.clock.clone();
+    let report = check_credit_ledger(&mut conn, &store, clock.as_ref(), &metrics)
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        LedgerCheckReport {
+            checked: 1,
+            balanced: 1,
+            failed: 0,
+        }
+    );
+    assert_eq!(ledger_balance(&mut conn, USER_PRINCIPAL).unwrap(), 37);
+    assert_eq!(metrics.counter(CREDIT_LEDGER_MISMATCHES_TOTAL, &[]), 1);
+
+    let report = check_credit_ledger(&mut conn, &store, clock.as_ref(), &metrics)
+        .await
+        .unwrap();
+    assert_eq!(report.balanced, 0);
+    let latest = ledger(&app_state, "?limit=1").await;
+    assert_eq!(latest["entries"]["items"][0]["reason"], "balancing");
+    assert_eq!(latest["entries"]["items"][0]["amount"], 10);
+}
@@ -0,0 +1,357 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::routing::post;
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::model::{CreditTopup, PurchaseToken};
+use yral_billing::plans::{CreditAllotments, CreditEvent, Upgrade};
+use yral_billing::routes::credits::top_up_renewal_credits;
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::{credit_topups, domain_events, purchase_tokens};
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{Plan, SubscriptionNotificationType};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const USER_PRINCIPAL: &str = "2vxsx-fae";
+
+/// Mock user info that counts credited amounts and can be taken offline
+#[derive(Clone, Default)]
+struct CountingUserInfo {
+    credited: Arc<AtomicU32>,
+    offline: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl UserInfoApi for CountingUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        MockUserInfo.grant_pro_plan(product_id, user_id).await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, _user_principal: Principal, amount: u32) -> AppResult<()> {
+        if self.offline.load(Ordering::SeqCst) {
+            return Err(AppError::ServiceAccessFailed("unreachable".to_string()));
+        }
+        self.credited.fetch_add(amount, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Canister fake keeping the plan and balance of one user, granting Pro resets the balance
+#[derive(Clone)]
+struct BalanceUserInfo {
+    allotment: u32,
+    /// Credits left, `None` on the Free plan
+    balance: Arc<Mutex<Option<u32>>>,
+}
+
+impl BalanceUserInfo {
+    fn pro(allotment: u32, credits_left: u32) -> Self {
+        Self {
+            allotment,
+            balance: Arc::new(Mutex::new(Some(credits_left))),
+        }
+    }
+}
+
+#[async_trait]
+impl UserInfoApi for BalanceUserInfo {
+    async fn grant_pro_plan(&self, _product_id: &str, _user_id: &str) -> AppResult<()> {
+        *self.balance.lock().unwrap() = Some(self.allotment);
+        Ok(())
+    }
+
+    async fn revoke_pro_plan(&self, _user_id: &str) -> AppResult<()> {
+        *self.balance.lock().unwrap() = None;
+        Ok(())
+    }
+
+    async fn get_plan(&self, _user_id: &str) -> AppResult<Plan> {
+        Ok(match *self.balance.lock().unwrap() {
+            Some(_) => Plan::Pro,
+            None => Plan::Free,
+        })
+    }
+
+    async fn get_credits(&self, _user_id: &str) -> AppResult<u32> {
+        Ok(self.balance.lock().unwrap().unwrap_or(0))
+    }
+
+    async fn deduct_credits(&self, _user_principal: Principal, amount: u32) -> AppResult<()> {
+        if let Some(balance) = self.balance.lock().unwrap().as_mut() {
+            *balance = balance.saturating_sub(amount);
+        }
+        Ok(())
+    }
+
+    async fn increment_credits(&self, _user_principal: Principal, amount: u32) -> AppResult<()> {
+        if let Some(balance) = self.balance.lock().unwrap().as_mut() {
+            *balance += amount;
+        }
+        Ok(())
+    }
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_credit_topups_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn test_state(user_info: &CountingUserInfo) -> AppState {
+    let mut app_state = AppState::new().await;
+    app_state.user_info = Arc::new(user_info.clone());
+    app_state
+}
+
+#[test]
+fn test_allotment_follows_config() {
+    let allotments = CreditAllotments { pro: 50 };
+    assert_eq!(allotments.for_plan(Plan::Pro), 50);
+    assert_eq!(allotments.for_plan(Plan::Free), 0);
+    assert_eq!(CreditAllotments::default().for_plan(Plan::Pro), 30);
+}
+
+// Purchases and renewals add the whole allotment, upgrades the prorated difference
+#[test]
+fn test_credit_rules_per_event() {
+    let allotments = CreditAllotments { pro: 50 };
+    assert_eq!(
+        allotments.credits_for(Plan::Pro, CreditEvent::Purchase, None),
+        50
+    );
+    assert_eq!(
+        allotments.credits_for(Plan::Pro, CreditEvent::Renewal, None),
+        50
+    );
+    assert_eq!(
+        allotments.credits_for(Plan::Free, CreditEvent::Renewal, None),
+        0
+    );
+
+    let now = chrono::Utc::now().naive_utc();
+    let halfway = Upgrade::new(Plan::Free, now + chrono::Duration::days(15), now);
+    assert_eq!(
+        allotments.credits_for(Plan::Pro, CreditEvent::Upgrade, Some(&halfway)),
+        25
+    );
+    let same_plan = Upgrade::new(Plan::Pro, now + chrono::Duration::days(30), now);
+    assert_eq!(
+        allotments.credits_for(Plan::Pro, CreditEvent::Upgrade, Some(&same_plan)),
+        0
+    );
+    assert_eq!(
+        allotments.credits_for(Plan::Pro, CreditEvent::Upgrade, None),
+        0
+    );
+
+    // A yearly period still prorates against one month of credits
+    let yearly = Upgrade::new(Plan::Free, now + chrono::Duration::days(365), now);
+    assert_eq!(yearly.remaining, 1.0);
+}
+
+// A redelivered renewal of the same order adds nothing the second time
+#[tokio::test]
+async fn test_renewal_order_tops_up_once() {
+    let db_guard = TestDbGuard::new();
+    let user_info = CountingUserInfo::default();
+    let app_state = test_state(&user_info).await;
+    let mut conn = app_state.get_db_connection().unwrap();
+
+    assert!(
+        top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.1", 30)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.1", 30)
+            .await
+            .unwrap()
+    );
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 30);
+
+    // The next billing period is a new order
+    assert!(
+        top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.1..0", 30)
+            .await
+            .unwrap()
+    );
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 60);
+
+    let topups: Vec<CreditTopup> = credit_topups::table.load(&mut db_guard.conn()).unwrap();
+    assert_eq!(topups.len(), 2);
+}
+
+// A failed increment releases the order so the redelivered notification retries it
+#[tokio::test]
+async fn test_failed_top_up_is_retried() {
+    let db_guard = TestDbGuard::new();
+    let user_info = CountingUserInfo::default();
+    let app_state = test_state(&user_info).await;
+    let mut conn = app_state.get_db_connection().unwrap();
+
+    user_info.offline.store(true, Ordering::SeqCst);
+    assert!(
+        top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.2", 30)
+            .await
+            .is_err()
+    );
+    let claimed: i64 = credit_topups::table
+        .count()
+        .get_result(&mut db_guard.conn())
+        .unwrap();
+    assert_eq!(claimed, 0);
+
+    user_info.offline.store(false, Ordering::SeqCst);
+    assert!(
+        top_up_renewal_credits(&app_state, &mut conn, USER_PRINCIPAL, "GPA.2", 30)
+            .await
+            .unwrap()
+    );
+    assert_eq!(user_info.credited.load(Ordering::SeqCst), 30);
+}
+
+// A renewal adds one allotment to the credits left, its redelivery adds nothing
+#[tokio::test]
+async fn test_renewal_adds_one_allotment() {
+    let mut app_state = memory_state().await;
+    let allotment = app_state.credit_allotments.for_plan(Plan::Pro);
+    let user_info = BalanceUserInfo::pro(allotment, 4);
+    app_state.user_info = Arc::new(user_info.clone());
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .product_id(YRAL_PRO_PLAN_PRODUCT_ID)
+            .order_id("GPA.3..0")
+            .build(),
+    );
+    let token = PurchaseTokenBuilder::new(MOCK_SUBSCRIPTION_ACCOUNT_ID)
+        .insert(&mut app_state.get_db_connection().unwrap());
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state);
+
+    let renewed =
+        RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token.purchase_token);
+    for _ in 0..2 {
+        let res = app.clone().oneshot(renewed.request()).await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(
+            user_info
+                .get_credits(MOCK_SUBSCRIPTION_ACCOUNT_ID)
+                .await
+                .unwrap(),
+            4 + allotment
+        );
+    }
+}
+
+// A renewal failing at its top-up is rolled back whole, so its redelivery isn't discarded
+#[tokio::test]
+async fn test_failed_renewal_rolled_back() {
+    let user_info = CountingUserInfo::default();
+    let mut app_state = memory_state().await;
+    app_state.user_info = Arc::new(user_info.clone());
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .product_id(YRAL_PRO_PLAN_PRODUCT_ID)
+            .order_id("GPA.4..0")
+            .build(),
+    );
+    let mut conn = app_state.get_db_connection().unwrap();
+    let token = PurchaseTokenBuilder::new(MOCK_SUBSCRIPTION_ACCOUNT_ID).insert(&mut conn);
+    let before: PurchaseToken = purchase_tokens::table
+        .find(&token.id)
+        .first(&mut conn)
+        .unwrap();
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state.clone());
+    let renewed =
+        RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token.purchase_token);
+
+    user_info.offline.store(true, Ordering::SeqCst);
+    let res = app.clone().oneshot(renewed.request()).await.unwrap();
+    assert!(res.status().is_server_error());
+
+    let after: PurchaseToken = purchase_tokens::table
+        .find(&token.id)
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(after.version, before.version);
+    assert_eq!(after.expiry_at, before.expiry_at);
+    assert_eq!(after.last_event_time, None);
+    let events: i64 = domain_events::table.count().get_result(&mut conn).unwrap();
+    assert_eq!(events, 0);
+
+    user_info.offline.store(false, Ordering::SeqCst);
+    let res = app.clone().oneshot(renewed.request()).await.unwrap();
+    assert!(res.status().is_success());
+
+    let after: PurchaseToken = purchase_tokens::table
+        .find(&token.id)
+        .first(&mut conn)
+        .unwrap();
+    assert!(after.last_event_time.is_some());
+    assert_eq!(
+        user_info.credited.load(Ordering::SeqCst),
+        app_state.credit_allotments.for_plan(Plan::Pro)
+    );
+}
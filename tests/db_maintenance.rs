@@ -0,0 +1,95 @@
+use std::env;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::db::{connect_pool, integrity_problems};
+use yral_billing::jobs::db_maintenance::run_db_maintenance;
+use yral_billing::routes::admin::check_db_integrity;
+use yral_billing::test_support::{memory_state, PurchaseTokenBuilder};
+
+#[derive(QueryableByName)]
+struct JournalMode {
+    #[diesel(sql_type = Text)]
+    journal_mode: String,
+}
+
+#[derive(QueryableByName)]
+struct Pragma {
+    #[diesel(sql_type = BigInt)]
+    value: i64,
+}
+
+fn pragma(conn: &mut SqliteConnection, name: &str, column: &str) -> i64 {
+    sql_query(format!("SELECT {} AS value FROM pragma_{}()", column, name))
+        .get_result::<Pragma>(conn)
+        .unwrap()
+        .value
+}
+
+// Every pooled connection to a database file gets WAL, the busy timeout and foreign keys
+#[test]
+fn test_pool_connections_configured() {
+    let path = env::temp_dir().join(format!("billing_{}.db", uuid::Uuid::new_v4()));
+    let pool = connect_pool(path.to_str().unwrap()).unwrap();
+
+    for _ in 0..2 {
+        let mut conn = pool.get().unwrap();
+        let mode: JournalMode = sql_query("PRAGMA journal_mode")
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(mode.journal_mode, "wal");
+        assert_eq!(pragma(&mut conn, "busy_timeout", "timeout"), 5000);
+        assert_eq!(pragma(&mut conn, "foreign_keys", "foreign_keys"), 1);
+    }
+
+    drop(pool);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+// Deleted rows leave free pages behind until the database is vacuumed
+#[tokio::test]
+async fn test_maintenance_shrinks_database() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    for _ in 0..500 {
+        PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+    }
+    conn.batch_execute("DELETE FROM purchase_tokens").unwrap();
+
+    assert!(run_db_maintenance(&mut conn).unwrap() > 0);
+    assert!(integrity_problems(&mut conn).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_integrity_endpoint() {
+    let app = Router::new()
+        .route("/admin/db/integrity", get(check_db_integrity))
+        .with_state(memory_state().await);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/admin/db/integrity")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["ok"], true);
+    assert_eq!(response["data"]["problems"], serde_json::json!([]));
+    assert!(response["data"]["size_bytes"].as_i64().unwrap() > 0);
+}
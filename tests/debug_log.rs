@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use base64::prelude::*;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use serde_json::json;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::debug_log::{capture_requests, redact_body, DebugLog, REDACTED};
+use yral_billing::feature_flags::{FeatureFlags, DEBUG_REQUEST_LOG};
+use yral_billing::test_support::{test_app, test_user, VerifyRequestBuilder};
+use yral_billing::types::DebugLogDirection;
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+fn flags(enabled: bool) -> FeatureFlags {
+    FeatureFlags::new(
+        HashMap::from([(DEBUG_REQUEST_LOG.to_string(), false)]),
+        HashMap::from([(DEBUG_REQUEST_LOG.to_string(), enabled)]),
+    )
+}
+
+/// Test app capturing its requests into `debug_log`, as `build_router` does
+async fn capturing_app(debug_log: DebugLog) -> Router {
+    let mut app_state = AppState::new().await;
+    app_state.debug_log = debug_log;
+    test_app(app_state.clone()).layer(axum::middleware::from_fn_with_state(
+        app_state,
+        capture_requests,
+    ))
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_debug_log_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+// Tokens, emails and names are redacted at any depth
+#[test]
+fn test_redacts_tokens_and_personal_data() {
+    let body = json!({
+        "purchaseToken": "secret-token",
+        "lineItems": [{ "productId": "yral_pro" }],
+        "subscribeWithGoogleInfo": {
+            "emailAddress": "jane@example.com",
+            "givenName": "Jane",
+            "familyName": "Doe",
+            "profileName": "Jane Doe"
+        }
+    });
+
+    let redacted = redact_body(&serde_json::to_vec(&body).unwrap());
+    assert_eq!(redacted["purchaseToken"], REDACTED);
+    assert_eq!(redacted["lineItems"][0]["productId"], "yral_pro");
+    let info = &redacted["subscribeWithGoogleInfo"];
+    assert_eq!(info["emailAddress"], REDACTED);
+    assert_eq!(info["givenName"], REDACTED);
+    assert_eq!(info["familyName"], REDACTED);
+    assert_eq!(info["profileName"], REDACTED);
+}
+
+// The notification inside a Pub/Sub push is decoded and redacted
+#[test]
+fn test_redacts_pubsub_payload() {
+    let notification = json!({
+        "packageName": "com.example",
+        "subscriptionNotification": { "notificationType": 4, "purchaseToken": "secret-token" }
+    });
+    let body = json!({
+        "message": {
+            "data": BASE64_STANDARD.encode(serde_json::to_vec(&notification).unwrap()),
+            "messageId": "1"
+        },
+        "subscription": "projects/p/subscriptions/s"
+    });
+
+    let redacted = redact_body(&serde_json::to_vec(&body).unwrap());
+    let data = &redacted["message"]["data"];
+    assert_eq!(data["packageName"], "com.example");
+    assert_eq!(data["subscriptionNotification"]["purchaseToken"], REDACTED);
+    assert_eq!(data["subscriptionNotification"]["notificationType"], 4);
+}
+
+// Malformed bodies are kept only as their size
+#[test]
+fn test_non_json_body_is_not_stored() {
+    let redacted = redact_body(b"purchaseToken=secret-token");
+    assert_eq!(redacted, json!("<26 bytes of non-JSON body>"));
+}
+
+// The buffer keeps only the newest entries and stays empty while the flag is off
+#[test]
+fn test_ring_buffer() {
+    let disabled = DebugLog::new(flags(false), 2);
+    disabled.record(DebugLogDirection::Inbound, "POST", "/a", None, b"{}");
+    assert!(disabled.entries().is_empty());
+
+    let debug_log = DebugLog::new(flags(true), 2);
+    for target in ["/a", "/b", "/c"] {
+        debug_log.record(DebugLogDirection::Inbound, "POST", target, None, b"{}");
+    }
+    let targets: Vec<String> = debug_log
+        .entries()
+        .into_iter()
+        .map(|entry| entry.target)
+        .collect();
+    assert_eq!(targets, vec!["/c", "/b"]);
+}
+
+// Inbound bodies are captured redacted and served on the admin endpoint
+#[tokio::test]
+async fn test_captures_inbound_requests() {
+    let _db_guard = TestDbGuard::new();
+    let debug_log = DebugLog::new(flags(true), 10);
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let app = capturing_app(debug_log.clone()).await;
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let entries = debug_log.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].direction, DebugLogDirection::Inbound);
+    assert_eq!(entries[0].target, "/google/verify");
+    assert_eq!(entries[0].status, Some(200));
+    assert_eq!(entries[0].body["purchase_token"], REDACTED);
+    assert_eq!(entries[0].body["user_id"], test_user("user_1"));
+
+    let req = Request::builder()
+        .method("GET")
+        .uri("/admin/debug/requests")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert!(!body_bytes
+        .windows(token.len())
+        .any(|window| window == token.as_bytes()));
+    assert_eq!(response["data"][0]["target"], "/google/verify");
+}
+
+// Nothing is captured unless the flag is on
+#[tokio::test]
+async fn test_disabled_by_default() {
+    let _db_guard = TestDbGuard::new();
+    let debug_log = DebugLog::new(flags(false), 10);
+
+    let app = capturing_app(debug_log.clone()).await;
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1")).request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(debug_log.entries().is_empty());
+}
@@ -0,0 +1,212 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::model::{BotChatAccess, Entitlement, PurchaseToken, Subscription};
+use yral_billing::routes::entitlements::load_entitlements;
+use yral_billing::schema::entitlements;
+use yral_billing::subscriptions::upsert_subscription;
+use yral_billing::test_support::{memory_state, new_test_user, test_app, PurchaseTokenBuilder};
+use yral_billing::types::{
+    BotChatAccessStatus, Plan, PurchaseEnvironment, PurchaseTokenStatus, SubscriptionSource,
+    SubscriptionStatus,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_entitlements_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn db_path(&self) -> &str {
+        &self.db_path
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn get_entitlement_document(app: Router, user_id: &str) -> serde_json::Value {
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!("/entitlements/{}", user_id))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body_bytes).unwrap()
+}
+
+// An active subscription and chat grant show up in the entitlement document
+#[tokio::test]
+async fn test_pro_user_entitlements() {
+    use yral_billing::schema::{bot_chat_access, purchase_tokens};
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let user_id = new_test_user();
+
+    let expiry_at = (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc();
+    let token = PurchaseToken::new(
+        user_id.clone(),
+        format!("token_{}", uuid::Uuid::new_v4()),
+        expiry_at,
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(&mut conn)
+        .unwrap();
+
+    let mut grant = BotChatAccess::new(
+        format!("chat_token_{}", uuid::Uuid::new_v4()),
+        user_id.clone(),
+        "bot_1".to_string(),
+        (chrono::Utc::now() + chrono::Duration::days(1)).naive_utc(),
+    );
+    grant.status = BotChatAccessStatus::Active;
+    diesel::insert_into(bot_chat_access::table)
+        .values(&grant)
+        .execute(&mut conn)
+        .unwrap();
+
+    let app = test_app(AppState::new().await);
+    let response = get_entitlement_document(app, &user_id).await;
+    let data = &response["data"];
+
+    assert_eq!(data["plan"], "pro");
+    assert_eq!(data["credit_allotment"], 30);
+    assert_eq!(data["source_store"], "google_play");
+    assert!(data["valid_until"].is_string());
+    assert_eq!(data["bot_chat_access"][0]["bot_id"], "bot_1");
+}
+
+// Expired subscriptions fall back to the free plan
+#[tokio::test]
+async fn test_expired_subscription_is_free() {
+    use yral_billing::schema::purchase_tokens;
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let user_id = new_test_user();
+
+    let token = PurchaseToken::new(
+        user_id.clone(),
+        format!("token_{}", uuid::Uuid::new_v4()),
+        (chrono::Utc::now() - chrono::Duration::days(1)).naive_utc(),
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(&mut conn)
+        .unwrap();
+
+    let app = test_app(AppState::new().await);
+    let response = get_entitlement_document(app, &user_id).await;
+    let data = &response["data"];
+
+    assert_eq!(data["plan"], "free");
+    assert_eq!(data["credit_allotment"], 0);
+    assert!(data["valid_until"].is_null());
+    assert!(data["source_store"].is_null());
+}
+
+fn stored_entitlement(conn: &mut SqliteConnection, user_id: &str) -> Entitlement {
+    entitlements::table.find(user_id).first(conn).unwrap()
+}
+
+// The read model follows every change to the user's subscriptions in the same write
+#[tokio::test]
+async fn test_read_model_follows_subscriptions() {
+    use yral_billing::schema::purchase_tokens;
+
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let user_id = new_test_user();
+    let now = chrono::Utc::now().naive_utc();
+
+    let token = PurchaseTokenBuilder::new(&user_id).insert(&mut conn);
+    let entitlement = stored_entitlement(&mut conn, &user_id);
+    assert_eq!(entitlement.plan, Plan::Pro);
+    assert_eq!(entitlement.source, Some(SubscriptionSource::GooglePlay));
+    assert_eq!(entitlement.valid_until, Some(token.expiry_at));
+
+    // A longer subscription from another store takes over
+    let mut stripe = Subscription::new(
+        user_id.clone(),
+        SubscriptionSource::Stripe,
+        "sub_1".to_string(),
+        SubscriptionStatus::Active,
+        now,
+        token.expiry_at + chrono::Duration::days(30),
+    );
+    stripe.auto_renewing = true;
+    upsert_subscription(&mut conn, &stripe).unwrap();
+    let entitlement = stored_entitlement(&mut conn, &user_id);
+    assert_eq!(entitlement.source, Some(SubscriptionSource::Stripe));
+    assert_eq!(entitlement.auto_renewing, Some(true));
+
+    stripe.status = SubscriptionStatus::Expired;
+    upsert_subscription(&mut conn, &stripe).unwrap();
+    assert_eq!(
+        stored_entitlement(&mut conn, &user_id).source,
+        Some(SubscriptionSource::GooglePlay)
+    );
+
+    // Moving the token to another user leaves the first one free
+    let other = new_test_user();
+    diesel::update(purchase_tokens::table.find(&token.id))
+        .set(purchase_tokens::user_id.eq(&other))
+        .execute(&mut conn)
+        .unwrap();
+    assert_eq!(stored_entitlement(&mut conn, &user_id).plan, Plan::Free);
+    assert_eq!(stored_entitlement(&mut conn, &other).plan, Plan::Pro);
+
+    let document =
+        load_entitlements(&mut conn, other.clone(), &app_state.credit_allotments, now).unwrap();
+    assert_eq!(document.plan, Plan::Pro);
+
+    // Past its expiry the stored grant no longer counts
+    let document = load_entitlements(
+        &mut conn,
+        other,
+        &app_state.credit_allotments,
+        token.expiry_at + chrono::Duration::seconds(1),
+    )
+    .unwrap();
+    assert_eq!(document.plan, Plan::Free);
+    assert!(document.valid_until.is_none());
+}
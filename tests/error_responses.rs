@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use yral_billing::error::AppError;
+use yral_billing::messages::Locale;
+use yral_billing::token_state::TransitionReason;
+use yral_billing::types::PurchaseTokenStatus;
+
+/// Status and code clients see for each error, part of the API contract
+///
+/// No wildcard arm, so a new variant doesn't compile until its response is decided here and
+/// an example of it is added to `examples`.
+fn golden(error: &AppError) -> (StatusCode, &'static str) {
+    match error {
+        AppError::DatabaseConnection => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_CONNECTION"),
+        AppError::DatabaseOperation(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_OPERATION"),
+        AppError::GooglePlayApi(_) => (StatusCode::BAD_REQUEST, "GOOGLE_PLAY_API"),
+        AppError::GooglePlayThrottled { .. } => {
+            (StatusCode::SERVICE_UNAVAILABLE, "GOOGLE_PLAY_THROTTLED")
+        }
+        AppError::GooglePlayTokenNotFound(_) => {
+            (StatusCode::BAD_REQUEST, "GOOGLE_PLAY_TOKEN_NOT_FOUND")
+        }
+        AppError::GooglePlayPermissionDenied(_) => {
+            (StatusCode::BAD_GATEWAY, "GOOGLE_PLAY_PERMISSION_DENIED")
+        }
+        AppError::GooglePlayUnavailable(_) => (StatusCode::BAD_GATEWAY, "GOOGLE_PLAY_UNAVAILABLE"),
+        AppError::GooglePlayVerification(_) => {
+            (StatusCode::BAD_REQUEST, "GOOGLE_PLAY_VERIFICATION")
+        }
+        AppError::AuthServiceUnavailable => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "AUTH_SERVICE_UNAVAILABLE",
+        ),
+        AppError::AdminIcAgentMissing => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "ADMIN_IC_AGENT_MISSING")
+        }
+        AppError::AccessTokenFailed(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "ACCESS_TOKEN_FAILED")
+        }
+        AppError::TokenAlreadyUsed => (StatusCode::BAD_REQUEST, "TOKEN_ALREADY_USED"),
+        AppError::TokenExpired => (StatusCode::BAD_REQUEST, "TOKEN_EXPIRED"),
+        AppError::SubscriptionCanceled => (StatusCode::BAD_REQUEST, "SUBSCRIPTION_CANCELED"),
+        AppError::SubscriptionExpired => (StatusCode::BAD_REQUEST, "SUBSCRIPTION_EXPIRED"),
+        AppError::SubscriptionOnHold => (StatusCode::ACCEPTED, "SUBSCRIPTION_ON_HOLD"),
+        AppError::SubscriptionPaused => (StatusCode::ACCEPTED, "SUBSCRIPTION_PAUSED"),
+        AppError::SubscriptionInvalidLineItems => {
+            (StatusCode::BAD_REQUEST, "SUBSCRIPTION_INVALID_LINE_ITEMS")
+        }
+        AppError::SubscriptionInvalidState => {
+            (StatusCode::BAD_REQUEST, "SUBSCRIPTION_INVALID_STATE")
+        }
+        AppError::SubscriptionNoState => (StatusCode::BAD_REQUEST, "SUBSCRIPTION_NO_STATE"),
+        AppError::GooglePlayResponseParse(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "GOOGLE_PLAY_RESPONSE_PARSE",
+        ),
+        AppError::GooglePlayConnection(_) => (StatusCode::BAD_GATEWAY, "GOOGLE_PLAY_CONNECTION"),
+        AppError::AcknowledgmentFailed => (StatusCode::BAD_REQUEST, "ACKNOWLEDGMENT_FAILED"),
+        AppError::ServiceAccessFailed(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "SERVICE_ACCESS_FAILED")
+        }
+        AppError::NetworkError(_) => (StatusCode::BAD_GATEWAY, "NETWORK_ERROR"),
+        AppError::UserNotFound(_) => (StatusCode::NOT_FOUND, "USER_NOT_FOUND"),
+        AppError::AlreadyPro(_) => (StatusCode::CONFLICT, "ALREADY_PRO"),
+        AppError::CanisterStopped(_) => (StatusCode::SERVICE_UNAVAILABLE, "CANISTER_STOPPED"),
+        AppError::CanisterRejected(_) => (StatusCode::BAD_GATEWAY, "CANISTER_REJECTED"),
+        AppError::InternalError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+        AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+        AppError::ExternalAccountIdentifiersMissing => (
+            StatusCode::BAD_REQUEST,
+            "EXTERNAL_ACCOUNT_IDENTIFIERS_MISSING",
+        ),
+        AppError::SandboxPurchaseNotHonored => {
+            (StatusCode::BAD_REQUEST, "SANDBOX_PURCHASE_NOT_HONORED")
+        }
+        AppError::PayloadTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, "PAYLOAD_TOO_LARGE"),
+        AppError::UnsupportedMediaType => {
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, "UNSUPPORTED_MEDIA_TYPE")
+        }
+        AppError::AccountMismatch => (StatusCode::BAD_REQUEST, "ACCOUNT_MISMATCH"),
+        AppError::PackageDisabled(_) => (StatusCode::SERVICE_UNAVAILABLE, "PACKAGE_DISABLED"),
+        AppError::ManualApprovalRequired => (StatusCode::FORBIDDEN, "MANUAL_APPROVAL_REQUIRED"),
+        AppError::VerificationRejected => (StatusCode::FORBIDDEN, "VERIFICATION_REJECTED"),
+        AppError::NotOwner => (StatusCode::FORBIDDEN, "NOT_OWNER"),
+        AppError::IntegrityCheckFailed(_) => (StatusCode::FORBIDDEN, "INTEGRITY_CHECK_FAILED"),
+        AppError::EntitlementTokensDisabled => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "ENTITLEMENT_TOKENS_DISABLED",
+        ),
+        AppError::InvalidTransition { .. } => (StatusCode::CONFLICT, "INVALID_TRANSITION"),
+        AppError::TokenConflict(_) => (StatusCode::CONFLICT, "TOKEN_CONFLICT"),
+        AppError::ProfileAlreadyRegistered(_) => {
+            (StatusCode::CONFLICT, "PROFILE_ALREADY_REGISTERED")
+        }
+        AppError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "RATE_LIMITED"),
+    }
+}
+
+/// One of every variant, with the message it is sent with
+fn examples() -> Vec<(AppError, &'static str)> {
+    vec![
+        (AppError::DatabaseConnection, "Database connection failed"),
+        (
+            AppError::DatabaseOperation("locked".to_string()),
+            "Database operation failed: locked",
+        ),
+        (
+            AppError::GooglePlayApi("boom".to_string()),
+            "Google Play API error: boom",
+        ),
+        (
+            AppError::GooglePlayThrottled {
+                message: "quota".to_string(),
+                retry_after_secs: 30,
+            },
+            "Google Play is throttling requests: quota",
+        ),
+        (
+            AppError::GooglePlayTokenNotFound("token".to_string()),
+            "Purchase token not found on Google Play: token",
+        ),
+        (
+            AppError::GooglePlayPermissionDenied("no access".to_string()),
+            "Google Play denied access: no access",
+        ),
+        (
+            AppError::GooglePlayUnavailable("down".to_string()),
+            "Google Play is unavailable: down",
+        ),
+        (
+            AppError::GooglePlayVerification("mismatch".to_string()),
+            "Google Play verification failed: mismatch",
+        ),
+        (
+            AppError::AuthServiceUnavailable,
+            "Authentication service unavailable",
+        ),
+        (AppError::AdminIcAgentMissing, "Admin IC agent is missing"),
+        (
+            AppError::AccessTokenFailed("expired key".to_string()),
+            "Failed to get access token: expired key",
+        ),
+        (
+            AppError::TokenAlreadyUsed,
+            "Purchase token already used by different user",
+        ),
+        (AppError::TokenExpired, "Purchase token has expired"),
+        (
+            AppError::SubscriptionCanceled,
+            "Subscription has been canceled",
+        ),
+        (AppError::SubscriptionExpired, "Subscription has expired"),
+        (AppError::SubscriptionOnHold, "Subscription is on hold"),
+        (
+            AppError::SubscriptionPaused,
+            "Subscription is paused by user",
+        ),
+        (
+            AppError::SubscriptionInvalidLineItems,
+            "Subscription is active but has no valid line items",
+        ),
+        (
+            AppError::SubscriptionInvalidState,
+            "Unknown or invalid subscription state",
+        ),
+        (
+            AppError::SubscriptionNoState,
+            "No subscription state found in response",
+        ),
+        (
+            AppError::GooglePlayResponseParse("eof".to_string()),
+            "Failed to parse Google Play response: eof",
+        ),
+        (
+            AppError::GooglePlayConnection("reset".to_string()),
+            "Failed to connect to Google Play API: reset",
+        ),
+        (
+            AppError::AcknowledgmentFailed,
+            "Failed to acknowledge purchase with Google Play",
+        ),
+        (
+            AppError::ServiceAccessFailed("rejected".to_string()),
+            "Failed to grant service access: rejected",
+        ),
+        (
+            AppError::NetworkError("timeout".to_string()),
+            "Network error: timeout",
+        ),
+        (
+            AppError::UserNotFound("aaaaa-aa".to_string()),
+            "User aaaaa-aa is not registered with the user info canister",
+        ),
+        (
+            AppError::AlreadyPro("aaaaa-aa".to_string()),
+            "User aaaaa-aa already has the Pro plan",
+        ),
+        (
+            AppError::CanisterStopped("IC0508".to_string()),
+            "User info canister is stopped: IC0508",
+        ),
+        (
+            AppError::CanisterRejected("trapped".to_string()),
+            "User info canister rejected the call: trapped",
+        ),
+        (
+            AppError::InternalError("oops".to_string()),
+            "Internal server error: oops",
+        ),
+        (
+            AppError::BadRequest("missing field".to_string()),
+            "Bad request: missing field",
+        ),
+        (
+            AppError::ExternalAccountIdentifiersMissing,
+            "External account identifiers are missing",
+        ),
+        (
+            AppError::SandboxPurchaseNotHonored,
+            "Sandbox purchases are not honored in this environment",
+        ),
+        (
+            AppError::PayloadTooLarge(1024),
+            "Request body exceeds the 1024 byte limit",
+        ),
+        (
+            AppError::UnsupportedMediaType,
+            "Content-Type must be application/json",
+        ),
+        (
+            AppError::AccountMismatch,
+            "Purchase belongs to a different account",
+        ),
+        (
+            AppError::PackageDisabled("com.example".to_string()),
+            "Billing is disabled for package com.example",
+        ),
+        (
+            AppError::ManualApprovalRequired,
+            "Purchases for this account need manual approval",
+        ),
+        (
+            AppError::VerificationRejected,
+            "Verification of this purchase was rejected after review",
+        ),
+        (
+            AppError::NotOwner,
+            "Callers can only access their own purchases",
+        ),
+        (
+            AppError::IntegrityCheckFailed("rooted".to_string()),
+            "Device integrity check failed: rooted",
+        ),
+        (
+            AppError::EntitlementTokensDisabled,
+            "Entitlement tokens are not configured",
+        ),
+        (
+            AppError::InvalidTransition {
+                from: PurchaseTokenStatus::Expired,
+                to: PurchaseTokenStatus::AccessGranted,
+                reason: TransitionReason::Refreshed,
+            },
+            "Purchase token cannot move from Expired to AccessGranted (Refreshed)",
+        ),
+        (
+            AppError::TokenConflict("token_1".to_string()),
+            "Purchase token token_1 was changed by another request, retry",
+        ),
+        (
+            AppError::ProfileAlreadyRegistered("profile_1".to_string()),
+            "Profile profile_1 is registered to another user",
+        ),
+        (
+            AppError::RateLimited {
+                retry_after_secs: 120,
+            },
+            "Too many requests, retry in 120 seconds",
+        ),
+    ]
+}
+
+// Every error answers with its golden status and an error body carrying its code and message
+#[tokio::test]
+async fn test_error_responses_match_golden() {
+    for (error, message) in examples() {
+        let (status, code) = golden(&error);
+        assert_eq!(error.to_string(), message);
+        assert_eq!(
+            serde_json::to_value(error.code()).unwrap(),
+            serde_json::json!(code),
+            "{}",
+            message
+        );
+
+        let retry_after = error.retry_after_secs();
+        let res = error.into_response();
+        assert_eq!(res.status(), status, "{}", message);
+        assert_eq!(
+            res.headers()
+                .get(RETRY_AFTER)
+                .map(|value| value.to_str().unwrap().to_string()),
+            retry_after.map(|secs| secs.to_string()),
+            "{}",
+            message
+        );
+
+        let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "success": false,
+                "msg": null,
+                "error": message,
+                "code": code,
+                "data": null,
+            })
+        );
+    }
+}
+
+// Only throttling and rate limits tell the client when to retry
+#[test]
+fn test_retry_after_only_when_throttled() {
+    let with_retry: Vec<String> = examples()
+        .into_iter()
+        .filter(|(error, _)| error.retry_after_secs().is_some())
+        .map(|(error, _)| golden(&error).1.to_string())
+        .collect();
+    assert_eq!(with_retry, vec!["GOOGLE_PLAY_THROTTLED", "RATE_LIMITED"]);
+}
+
+// Each variant appears once in the table and has a code of its own
+#[test]
+fn test_examples_cover_distinct_codes() {
+    let examples = examples();
+    let codes: HashSet<&str> = examples.iter().map(|(error, _)| golden(error).1).collect();
+    assert_eq!(codes.len(), examples.len());
+}
+
+// Every error has a translation in every locale, with all its placeholders filled
+#[test]
+fn test_error_messages_translated() {
+    for locale in Locale::ALL
+        .into_iter()
+        .filter(|locale| *locale != Locale::DEFAULT)
+    {
+        for (error, message) in examples() {
+            let translated = error
+                .message_key()
+                .translate(locale)
+                .unwrap_or_else(|| panic!("no {} message for: {}", locale.as_str(), message));
+            assert!(!translated.contains('{'), "{}", translated);
+        }
+    }
+}
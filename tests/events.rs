@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use diesel::prelude::*;
+use yral_billing::clock::{Clock, TestClock};
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::events::{
+    deliver_event, record_event, spawn_event_dispatcher, DomainEvent, EventConsumer,
+};
+use yral_billing::jobs::event_redelivery::redeliver_events;
+use yral_billing::metrics::DOMAIN_EVENTS_DELIVERED_TOTAL;
+use yral_billing::model::DomainEventRecord;
+use yral_billing::schema::domain_events;
+use yral_billing::test_support::memory_state;
+use yral_billing::types::OutboxStatus;
+use yral_billing::AppState;
+
+/// Consumer remembering what it handled, failing its first `failures` events
+#[derive(Default)]
+struct RecordingConsumer {
+    handled: Mutex<Vec<DomainEvent>>,
+    failures: AtomicUsize,
+}
+
+impl RecordingConsumer {
+    fn failing(failures: usize) -> Self {
+        Self {
+            failures: AtomicUsize::new(failures),
+            ..Default::default()
+        }
+    }
+
+    fn handled(&self) -> Vec<DomainEvent> {
+        self.handled.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EventConsumer for RecordingConsumer {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    async fn handle(&self, _app_state: &AppState, event: &DomainEvent) -> AppResult<()> {
+        self.handled.lock().unwrap().push(event.clone());
+        if self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok()
+        {
+            return Err(AppError::NetworkError("consumer down".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn expired_event() -> DomainEvent {
+    DomainEvent::SubscriptionExpired {
+        user_id: "user_1".to_string(),
+        purchase_token: "token_1".to_string(),
+    }
+}
+
+async fn state_with(consumer: &Arc<RecordingConsumer>) -> (AppState, Arc<TestClock>) {
+    let mut app_state = memory_state().await;
+    let clock = Arc::new(TestClock::new(chrono::Utc::now()));
+    app_state.clock = clock.clone();
+    app_state.events.subscribe(consumer.clone());
+    (app_state, clock)
+}
+
+fn stored(app_state: &AppState, event_id: &str) -> DomainEventRecord {
+    domain_events::table
+        .find(event_id)
+        .first(&mut app_state.get_db_connection().unwrap())
+        .unwrap()
+}
+
+// A published event reaches the consumers through the in-process queue
+#[tokio::test]
+async fn test_published_event_dispatched() {
+    let consumer = Arc::new(RecordingConsumer::default());
+    let (app_state, clock) = state_with(&consumer).await;
+    spawn_event_dispatcher(app_state.clone());
+
+    app_state
+        .events
+        .publish(
+            &mut app_state.get_db_connection().unwrap(),
+            &expired_event(),
+            clock.now_naive(),
+        )
+        .unwrap();
+
+    for _ in 0..100 {
+        if !consumer.handled().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(consumer.handled(), vec![expired_event()]);
+    assert_eq!(
+        app_state.metrics.counter(
+            DOMAIN_EVENTS_DELIVERED_TOTAL,
+            &[("event", "subscription_expired")]
+        ),
+        1
+    );
+}
+
+// An event a consumer failed on stays pending and the redelivery job delivers it again
+#[tokio::test]
+async fn test_failed_event_redelivered() {
+    let consumer = Arc::new(RecordingConsumer::failing(1));
+    let (app_state, clock) = state_with(&consumer).await;
+
+    let event_id = record_event(
+        &mut app_state.get_db_connection().unwrap(),
+        &expired_event(),
+        clock.now_naive(),
+    )
+    .unwrap();
+
+    assert!(!deliver_event(&app_state, &event_id).await.unwrap());
+    let record = stored(&app_state, &event_id);
+    assert_eq!(record.status, OutboxStatus::Pending);
+    assert_eq!(record.attempts, 1);
+    assert!(record.last_error.unwrap().contains("consumer down"));
+
+    // Left to the dispatcher for a while first
+    assert_eq!(redeliver_events(&app_state, 10).await.unwrap(), 0);
+
+    clock.advance(chrono::Duration::minutes(1));
+    assert_eq!(redeliver_events(&app_state, 10).await.unwrap(), 1);
+    assert_eq!(stored(&app_state, &event_id).status, OutboxStatus::Done);
+    assert_eq!(consumer.handled().len(), 2);
+
+    // Delivered events aren't delivered again
+    assert!(!deliver_event(&app_state, &event_id).await.unwrap());
+    assert_eq!(consumer.handled().len(), 2);
+}
+
+#[test]
+fn test_event_payloads() {
+    assert_eq!(
+        serde_json::to_value(DomainEvent::AccessGranted {
+            user_id: "user_1".to_string(),
+            purchase_token: "token_1".to_string(),
+            product_id: "yral_pro_plan".to_string(),
+        })
+        .unwrap(),
+        serde_json::json!({
+            "event": "access_granted",
+            "user_id": "user_1",
+            "purchase_token": "token_1",
+            "product_id": "yral_pro_plan",
+        })
+    );
+    assert_eq!(expired_event().name(), "subscription_expired");
+}
@@ -0,0 +1,253 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::error::AppResult;
+use yral_billing::integrations::google_play::{GooglePlayApi, MockGooglePlay};
+use yral_billing::model::{AccessOutboxEntry, PurchaseToken};
+use yral_billing::schema::{access_outbox, purchase_tokens};
+use yral_billing::test_support::{test_app, test_user, PurchaseTokenBuilder, VerifyRequestBuilder};
+use yral_billing::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+    OutboxAction, PurchaseTokenStatus,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_expiry_refresh_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Mock Google Play reporting `expiry` for every subscription and counting lookups
+struct RenewingGooglePlay {
+    expiry: chrono::DateTime<chrono::Utc>,
+    fetches: std::sync::atomic::AtomicUsize,
+}
+
+impl RenewingGooglePlay {
+    fn new(expiry: chrono::DateTime<chrono::Utc>) -> Arc<Self> {
+        Arc::new(Self {
+            expiry,
+            fetches: Default::default(),
+        })
+    }
+
+    fn fetches(&self) -> usize {
+        self.fetches.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for RenewingGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        self.fetches
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let mut response = MockGooglePlay
+            .fetch_subscription(package_name, purchase_token)
+            .await?;
+        response.line_items[0].expiry_time = Some(self.expiry.to_rfc3339());
+        Ok(response)
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: chrono::DateTime<chrono::Utc>,
+        desired_expiry: chrono::DateTime<chrono::Utc>,
+    ) -> AppResult<chrono::DateTime<chrono::Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+fn stored(conn: &mut SqliteConnection, token: &PurchaseToken) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .first(conn)
+        .unwrap()
+}
+
+// Grants far from expiry are answered from the database without asking Google
+#[tokio::test]
+async fn test_distant_expiry_is_not_refreshed() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now();
+    let google_play = RenewingGooglePlay::new(now + chrono::Duration::days(60));
+    let mut app_state = AppState::new().await;
+    app_state.google_play = google_play.clone();
+    let app = test_app(app_state);
+
+    let token = PurchaseTokenBuilder::new(&test_user("user_1"))
+        .expiry_at((now + chrono::Duration::days(20)).naive_utc())
+        .insert(&mut conn);
+    let req = VerifyRequestBuilder::new(&token.user_id)
+        .purchase_token(&token.purchase_token)
+        .request();
+    assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+    assert_eq!(google_play.fetches(), 0);
+    assert_eq!(stored(&mut conn, &token).expiry_at, token.expiry_at);
+}
+
+// A renewal Google charged since the last verify moves the stored expiry forward
+#[tokio::test]
+async fn test_renewal_updates_expiry() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now();
+    let renewed = now + chrono::Duration::days(30);
+    let google_play = RenewingGooglePlay::new(renewed);
+    let mut app_state = AppState::new().await;
+    app_state.google_play = google_play.clone();
+    let app = test_app(app_state);
+
+    let token = PurchaseTokenBuilder::new(&test_user("user_1"))
+        .expiry_at((now + chrono::Duration::hours(2)).naive_utc())
+        .insert(&mut conn);
+    let req = VerifyRequestBuilder::new(&token.user_id)
+        .purchase_token(&token.purchase_token)
+        .request();
+    assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+    assert_eq!(google_play.fetches(), 1);
+    let refreshed = stored(&mut conn, &token);
+    assert_eq!(refreshed.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(
+        refreshed.expiry_at.and_utc().timestamp(),
+        renewed.timestamp()
+    );
+}
+
+// A subscription Google no longer extends is expired and Pro revoked through the outbox
+#[tokio::test]
+async fn test_lapsed_subscription_is_expired() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now();
+    let google_play = RenewingGooglePlay::new(now - chrono::Duration::hours(1));
+    let mut app_state = AppState::new().await;
+    app_state.google_play = google_play;
+    let app = test_app(app_state);
+
+    let token = PurchaseTokenBuilder::new(&test_user("user_1"))
+        .expiry_at((now + chrono::Duration::hours(2)).naive_utc())
+        .insert(&mut conn);
+    let req = VerifyRequestBuilder::new(&token.user_id)
+        .purchase_token(&token.purchase_token)
+        .request();
+    assert_eq!(
+        app.oneshot(req).await.unwrap().status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    assert_eq!(
+        stored(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+    let outbox: Vec<AccessOutboxEntry> = access_outbox::table.load(&mut conn).unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].action, OutboxAction::RevokePro);
+    assert_eq!(outbox[0].user_id, test_user("user_1"));
+}
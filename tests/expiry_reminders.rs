@@ -0,0 +1,86 @@
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::clock::TestClock;
+use yral_billing::jobs::expiry_reminders::send_expiry_reminders;
+use yral_billing::model::PurchaseToken;
+use yral_billing::notifier::Notifier;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{new_test_user, PurchaseTokenBuilder};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_reminders_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+fn load(conn: &mut SqliteConnection, token: &PurchaseToken) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .first(conn)
+        .unwrap()
+}
+
+// Only non-renewing subscriptions inside the window are reminded, and only once
+#[tokio::test]
+async fn test_expiry_reminders_sent_once() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let notifier = Notifier::new(None);
+
+    let expiring = PurchaseTokenBuilder::new(&new_test_user())
+        .expiry_at((now + chrono::Duration::days(1)).naive_utc())
+        .auto_renewing(false)
+        .insert(&mut conn);
+    let renewing = PurchaseTokenBuilder::new(&new_test_user())
+        .expiry_at((now + chrono::Duration::days(1)).naive_utc())
+        .auto_renewing(true)
+        .insert(&mut conn);
+    let far_off = PurchaseTokenBuilder::new(&new_test_user())
+        .expiry_at((now + chrono::Duration::days(20)).naive_utc())
+        .auto_renewing(false)
+        .insert(&mut conn);
+
+    let sent = send_expiry_reminders(&mut conn, &notifier, &clock, chrono::Duration::days(3))
+        .await
+        .unwrap();
+    assert_eq!(sent, 1);
+    assert!(load(&mut conn, &expiring).notified_at.is_some());
+    assert!(load(&mut conn, &renewing).notified_at.is_none());
+    assert!(load(&mut conn, &far_off).notified_at.is_none());
+
+    // A second run does not notify the same token again
+    let sent = send_expiry_reminders(&mut conn, &notifier, &clock, chrono::Duration::days(3))
+        .await
+        .unwrap();
+    assert_eq!(sent, 0);
+
+    // Once the far-off token enters the window it is reminded
+    clock.advance(chrono::Duration::days(18));
+    let sent = send_expiry_reminders(&mut conn, &notifier, &clock, chrono::Duration::days(3))
+        .await
+        .unwrap();
+    assert_eq!(sent, 1);
+    assert!(load(&mut conn, &far_off).notified_at.is_some());
+}
@@ -0,0 +1,224 @@
+use async_trait::async_trait;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use uuid;
+use yral_billing::clock::TestClock;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::jobs::access_outbox::{drain_access_outbox, MAX_OUTBOX_ATTEMPTS};
+use yral_billing::jobs::expiry_sweep::sweep_expired_tokens;
+use yral_billing::jobs::JOB_BATCH_SIZE;
+use yral_billing::metrics::{Metrics, ACCESS_OUTBOX_APPLIED_TOTAL, EXPIRY_SWEEP_EXPIRED_TOTAL};
+use yral_billing::model::{AccessOutboxEntry, PurchaseToken};
+use yral_billing::schema::{access_outbox, purchase_tokens};
+use yral_billing::test_support::PurchaseTokenBuilder;
+use yral_billing::types::{OutboxAction, OutboxStatus, Plan, PurchaseTokenStatus};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_expiry_sweep_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+/// Canister client that is always unreachable
+struct UnreachableUserInfo;
+
+#[async_trait]
+impl UserInfoApi for UnreachableUserInfo {
+    async fn grant_pro_plan(&self, _product_id: &str, _user_id: &str) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn revoke_pro_plan(&self, _user_id: &str) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn get_plan(&self, _user_id: &str) -> AppResult<Plan> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn get_credits(&self, _user_id: &str) -> AppResult<u32> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn deduct_credits(&self, _user_principal: Principal, _amount: u32) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn increment_credits(&self, _user_principal: Principal, _amount: u32) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+}
+
+fn token_status(conn: &mut SqliteConnection, token: &PurchaseToken) -> PurchaseTokenStatus {
+    purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .select(purchase_tokens::status)
+        .first(conn)
+        .unwrap()
+}
+
+fn outbox(conn: &mut SqliteConnection) -> Vec<AccessOutboxEntry> {
+    access_outbox::table
+        .order(access_outbox::created_at.asc())
+        .load(conn)
+        .unwrap()
+}
+
+// Lapsed tokens are expired and revocation is queued, unless the user has another active token
+#[test]
+fn test_sweep_expires_lapsed_tokens() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    let lapsed = PurchaseTokenBuilder::new("user_lapsed")
+        .expiry_at((now - chrono::Duration::days(2)).naive_utc())
+        .insert(&mut conn);
+    let active = PurchaseTokenBuilder::new("user_active")
+        .expiry_at((now + chrono::Duration::days(2)).naive_utc())
+        .insert(&mut conn);
+    // Old token of a user who has since renewed on a new token
+    let replaced = PurchaseTokenBuilder::new("user_active")
+        .expiry_at((now - chrono::Duration::days(30)).naive_utc())
+        .insert(&mut conn);
+
+    let expired = sweep_expired_tokens(&mut conn, &clock, &metrics).unwrap();
+    assert_eq!(expired, 2);
+    assert_eq!(
+        token_status(&mut conn, &lapsed),
+        PurchaseTokenStatus::Expired
+    );
+    assert_eq!(
+        token_status(&mut conn, &replaced),
+        PurchaseTokenStatus::Expired
+    );
+    assert_eq!(
+        token_status(&mut conn, &active),
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    let entries = outbox(&mut conn);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].user_id, "user_lapsed");
+    assert_eq!(entries[0].action, OutboxAction::RevokePro);
+    assert_eq!(
+        entries[0].purchase_token.as_deref(),
+        Some(lapsed.purchase_token.as_str())
+    );
+    assert_eq!(metrics.counter(EXPIRY_SWEEP_EXPIRED_TOTAL, &[]), 2);
+
+    // Nothing is left to sweep on the next run
+    assert_eq!(
+        sweep_expired_tokens(&mut conn, &clock, &metrics).unwrap(),
+        0
+    );
+    assert_eq!(outbox(&mut conn).len(), 1);
+}
+
+// Sweeps larger than one batch expire every lapsed token in a single run
+#[test]
+fn test_sweep_spans_batches() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    let total = JOB_BATCH_SIZE as usize * 2 + 1;
+    for i in 0..total {
+        PurchaseTokenBuilder::new(&format!("user_{}", i))
+            .expiry_at((now - chrono::Duration::minutes(i as i64 + 1)).naive_utc())
+            .insert(&mut conn);
+    }
+
+    let expired = sweep_expired_tokens(&mut conn, &clock, &metrics).unwrap();
+    assert_eq!(expired, total);
+    assert_eq!(
+        metrics.counter(EXPIRY_SWEEP_EXPIRED_TOTAL, &[]),
+        total as u64
+    );
+    assert_eq!(outbox(&mut conn).len(), total);
+}
+
+// Queued revocations are applied by the outbox worker and retried while the IC is down
+#[tokio::test]
+async fn test_outbox_applies_and_retries() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now();
+    let clock = TestClock::new(now);
+    let metrics = Metrics::new();
+
+    PurchaseTokenBuilder::new("user_lapsed")
+        .expiry_at((now - chrono::Duration::days(1)).naive_utc())
+        .insert(&mut conn);
+    sweep_expired_tokens(&mut conn, &clock, &metrics).unwrap();
+
+    let applied = drain_access_outbox(&mut conn, &UnreachableUserInfo, &clock, &metrics, 10)
+        .await
+        .unwrap();
+    assert_eq!(applied, 0);
+    let entries = outbox(&mut conn);
+    let entry = &entries[0];
+    assert_eq!(entry.status, OutboxStatus::Pending);
+    assert_eq!(entry.attempts, 1);
+    assert!(entry.last_error.is_some());
+
+    let applied = drain_access_outbox(&mut conn, &MockUserInfo, &clock, &metrics, 10)
+        .await
+        .unwrap();
+    assert_eq!(applied, 1);
+    assert_eq!(outbox(&mut conn)[0].status, OutboxStatus::Done);
+    assert_eq!(
+        metrics.counter(ACCESS_OUTBOX_APPLIED_TOTAL, &[("action", "RevokePro")]),
+        1
+    );
+}
+
+// Entries give up after too many failed attempts
+#[tokio::test]
+async fn test_outbox_marks_failed_after_max_attempts() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let clock = TestClock::new(chrono::Utc::now());
+    let metrics = Metrics::new();
+
+    PurchaseTokenBuilder::new("user_lapsed")
+        .expiry_at((chrono::Utc::now() - chrono::Duration::days(1)).naive_utc())
+        .insert(&mut conn);
+    sweep_expired_tokens(&mut conn, &clock, &metrics).unwrap();
+
+    for _ in 0..MAX_OUTBOX_ATTEMPTS {
+        drain_access_outbox(&mut conn, &UnreachableUserInfo, &clock, &metrics, 10)
+            .await
+            .unwrap();
+    }
+
+    let entries = outbox(&mut conn);
+    let entry = &entries[0];
+    assert_eq!(entry.status, OutboxStatus::Failed);
+    assert_eq!(entry.attempts, MAX_OUTBOX_ATTEMPTS);
+}
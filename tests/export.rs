@@ -0,0 +1,185 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::db::pagination::Cursor;
+use yral_billing::model::PurchaseToken;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{test_app, PurchaseTokenBuilder};
+use yral_billing::types::PurchaseTokenStatus;
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_export_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn export(app: Router, uri: &str) -> (StatusCode, Vec<serde_json::Value>) {
+    let res = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    if status != StatusCode::OK {
+        return (status, Vec::new());
+    }
+    let lines = String::from_utf8(body_bytes.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    (status, lines)
+}
+
+#[test]
+fn test_cursor_round_trip() {
+    let cursor = Cursor {
+        at: chrono::Utc::now().naive_utc(),
+        id: "a|b".to_string(),
+    };
+    assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    assert_eq!(Cursor::decode("not-a-cursor"), None);
+}
+
+// Following cursors visits every token once, in change order, and picks up later changes
+#[tokio::test]
+async fn test_tokens_are_paged_by_cursor() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let start = chrono::Utc::now().naive_utc() - chrono::Duration::days(1);
+    let tokens: Vec<PurchaseToken> = (0..5)
+        .map(|i| {
+            PurchaseTokenBuilder::new("user_1")
+                .updated_at(start + chrono::Duration::minutes(i))
+                .insert(&mut conn)
+        })
+        .collect();
+    let app = test_app(AppState::new().await);
+
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let uri = match &cursor {
+            Some(cursor) => format!("/admin/export/tokens?limit=2&cursor={}", cursor),
+            None => "/admin/export/tokens?limit=2".to_string(),
+        };
+        let (status, lines) = export(app.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        seen.extend(lines.iter().map(|line| {
+            line["record"]["purchase_token"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }));
+        if let Some(line) = lines.last() {
+            cursor = line["cursor"].as_str().map(str::to_string);
+        }
+        if lines.len() < 2 {
+            break;
+        }
+    }
+    let expected: Vec<String> = tokens.iter().map(|t| t.purchase_token.clone()).collect();
+    assert_eq!(seen, expected);
+
+    // An update moves the token to the end, past the cursor the client stopped at
+    diesel::update(purchase_tokens::table.filter(purchase_tokens::id.eq(&tokens[0].id)))
+        .set(purchase_tokens::status.eq(PurchaseTokenStatus::Expired))
+        .execute(&mut conn)
+        .unwrap();
+    let (_, lines) = export(
+        app.clone(),
+        &format!("/admin/export/tokens?cursor={}", cursor.unwrap()),
+    )
+    .await;
+    assert_eq!(lines.len(), 1);
+    assert_eq!(
+        lines[0]["record"]["purchase_token"],
+        tokens[0].purchase_token
+    );
+    assert_eq!(lines[0]["record"]["status"], "Expired");
+
+    // `updated_since` skips older changes
+    let since = (start + chrono::Duration::minutes(3))
+        .and_utc()
+        .to_rfc3339();
+    let (_, lines) = export(
+        app,
+        &format!(
+            "/admin/export/tokens?updated_since={}",
+            since.replace('+', "%2B")
+        ),
+    )
+    .await;
+    assert_eq!(lines.len(), 3);
+}
+
+// Responses larger than one database batch are streamed in full
+#[tokio::test]
+async fn test_export_spans_batches() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let start = chrono::Utc::now().naive_utc() - chrono::Duration::days(1);
+    for i in 0..1200 {
+        PurchaseTokenBuilder::new("user_1")
+            .updated_at(start + chrono::Duration::seconds(i))
+            .insert(&mut conn);
+    }
+    let app = test_app(AppState::new().await);
+
+    let (_, lines) = export(app.clone(), "/admin/export/tokens").await;
+    assert_eq!(lines.len(), 1200);
+    let (_, lines) = export(app, "/admin/export/tokens?limit=700").await;
+    assert_eq!(lines.len(), 700);
+}
+
+#[tokio::test]
+async fn test_invalid_parameters_are_rejected() {
+    let _db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let (status, _) = export(app.clone(), "/admin/export/events?cursor=bogus").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = export(app.clone(), "/admin/export/events?limit=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, lines) = export(app, "/admin/export/events").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(lines.is_empty());
+}
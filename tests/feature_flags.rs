@@ -0,0 +1,186 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::test_support::{test_app, test_user, VerifyRequestBuilder};
+use yral_billing::types::SetFeatureFlagRequest;
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_feature_flags_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn send(
+    app: Router,
+    method: &str,
+    uri: &str,
+    body: Option<Vec<u8>>,
+) -> axum::response::Response {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if body.is_some() {
+        builder = builder.header("content-type", "application/json");
+    }
+    let req = builder
+        .body(body.map(Body::from).unwrap_or_else(Body::empty))
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+async fn set_flag(app: Router, name: &str, enabled: bool) -> serde_json::Value {
+    let res = send(
+        app,
+        "POST",
+        &format!("/admin/feature-flags/{}", name),
+        Some(serde_json::to_vec(&SetFeatureFlagRequest { enabled }).unwrap()),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body_bytes).unwrap()
+}
+
+// Flipping a flag is persisted and reported with its source
+#[tokio::test]
+async fn test_set_and_list_flags() {
+    let _db_guard = TestDbGuard::new();
+
+    let response = set_flag(
+        test_app(AppState::new().await),
+        "strict_account_match",
+        true,
+    )
+    .await;
+    assert_eq!(response["data"]["enabled"], true);
+    assert_eq!(response["data"]["source"], "database");
+
+    // A fresh app state reads the stored value back
+    let res = send(
+        test_app(AppState::new().await),
+        "GET",
+        "/admin/feature-flags",
+        None,
+    )
+    .await;
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let flag = response["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|flag| flag["name"] == "strict_account_match")
+        .unwrap()
+        .clone();
+    assert_eq!(flag["enabled"], true);
+}
+
+// Sandbox purchases aren't honored unless switched on, whatever `APP_ENV` says
+#[tokio::test]
+async fn test_sandbox_purchases_off_by_default() {
+    let _db_guard = TestDbGuard::new();
+
+    let res = send(
+        test_app(AppState::new().await),
+        "GET",
+        "/admin/feature-flags",
+        None,
+    )
+    .await;
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let flag = response["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|flag| flag["name"] == "honor_sandbox_purchases")
+        .unwrap()
+        .clone();
+    assert_eq!(flag["enabled"], false);
+    assert_eq!(flag["source"], "default");
+}
+
+// With strict account matching on, a purchase made by another account is rejected
+#[tokio::test]
+async fn test_strict_account_match() {
+    let _db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let req = VerifyRequestBuilder::new(&test_user("someone_else")).request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    set_flag(app.clone(), "strict_account_match", true).await;
+
+    let req = VerifyRequestBuilder::new(&test_user("someone_else")).request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "ACCOUNT_MISMATCH");
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+// A disabled package refuses verification without calling Google
+#[tokio::test]
+async fn test_package_switch() {
+    let _db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    set_flag(app.clone(), "package.com.disabled", false).await;
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .package_name("com.disabled")
+        .request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use yral_billing::cache::{Cache, MemoryCache};
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::google_play::{CachingGooglePlay, GooglePlayApi, MockGooglePlay};
+use yral_billing::metrics::{Metrics, GOOGLE_PLAY_CACHE_TOTAL};
+use yral_billing::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+};
+
+/// Mock Google Play that counts subscription fetches and answers slowly
+#[derive(Default)]
+struct CountingGooglePlay {
+    fetches: AtomicUsize,
+    failing: AtomicBool,
+}
+
+impl CountingGooglePlay {
+    fn fetches(&self) -> usize {
+        self.fetches.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for CountingGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(AppError::GooglePlayUnavailable("unavailable".to_string()));
+        }
+        MockGooglePlay
+            .fetch_subscription(package_name, purchase_token)
+            .await
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: chrono::DateTime<chrono::Utc>,
+        desired_expiry: chrono::DateTime<chrono::Utc>,
+    ) -> AppResult<chrono::DateTime<chrono::Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+fn cache_count(metrics: &Metrics, result: &str) -> u64 {
+    metrics.counter(GOOGLE_PLAY_CACHE_TOTAL, &[("result", result)])
+}
+
+// Concurrent lookups of one token share a single Google call, later ones hit the cache
+#[tokio::test]
+async fn test_concurrent_fetches_are_coalesced() {
+    let inner = Arc::new(CountingGooglePlay::default());
+    let metrics = Metrics::new();
+    let cache = Arc::new(CachingGooglePlay::new(
+        inner.clone(),
+        Duration::from_secs(60),
+        metrics.clone(),
+    ));
+
+    let lookups: Vec<_> = (0..5)
+        .map(|_| {
+            let cache = cache.clone();
+            tokio::spawn(async move { cache.fetch_subscription("com.example", "token_1").await })
+        })
+        .collect();
+    for lookup in lookups {
+        assert!(lookup.await.unwrap().is_ok());
+    }
+    assert_eq!(inner.fetches(), 1);
+    assert_eq!(cache_count(&metrics, "miss"), 1);
+    assert_eq!(cache_count(&metrics, "coalesced"), 4);
+
+    cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 1);
+    assert_eq!(cache_count(&metrics, "hit"), 1);
+
+    // Other tokens are fetched separately
+    cache
+        .fetch_subscription("com.example", "token_2")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 2);
+}
+
+// Invalidation, expiry and failures all lead to a fresh Google call
+#[tokio::test]
+async fn test_stale_or_failed_lookups_are_refetched() {
+    let inner = Arc::new(CountingGooglePlay::default());
+    let cache = CachingGooglePlay::new(inner.clone(), Duration::from_millis(200), Metrics::new());
+
+    cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    cache.invalidate_subscription("token_1").await;
+    cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 2);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 3);
+
+    inner.failing.store(true, Ordering::SeqCst);
+    cache.invalidate_subscription("token_1").await;
+    assert!(cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .is_err());
+    inner.failing.store(false, Ordering::SeqCst);
+    assert!(cache
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .is_ok());
+    assert_eq!(inner.fetches(), 5);
+}
+
+// Replicas sharing a cache reuse each other's responses and invalidations
+#[tokio::test]
+async fn test_replicas_share_cached_responses() {
+    let shared: Arc<dyn Cache> = Arc::new(MemoryCache::default());
+    let inner = Arc::new(CountingGooglePlay::default());
+    let replica = |inner: Arc<CountingGooglePlay>| {
+        CachingGooglePlay::new(inner, Duration::from_secs(60), Metrics::new())
+            .with_cache(shared.clone())
+    };
+    let (first, second) = (replica(inner.clone()), replica(inner.clone()));
+
+    first
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    second
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 1);
+
+    first.invalidate_subscription("token_1").await;
+    second
+        .fetch_subscription("com.example", "token_1")
+        .await
+        .unwrap();
+    assert_eq!(inner.fetches(), 2);
+}
@@ -0,0 +1,502 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use chrono::{TimeZone, Utc};
+use yral_billing::debug_log::DebugLog;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::feature_flags::FeatureFlags;
+use yral_billing::integrations::google_play::client::{
+    error_kind, map_google_error, Client, RevocationRefund, TokenSource, VoidedPurchasesQuery,
+    DEFAULT_RETRY_AFTER_SECS,
+};
+use yral_billing::metrics::{Metrics, GOOGLE_PLAY_ERRORS_TOTAL};
+use yral_billing::types::{AcknowledgementState, SubscriptionState};
+
+const SUBSCRIPTION_V2: &str = include_str!("fixtures/google_play/subscription_v2.json");
+const PRODUCT_PURCHASE_V2: &str = include_str!("fixtures/google_play/product_purchase_v2.json");
+const VOIDED_PURCHASES: &str = include_str!("fixtures/google_play/voided_purchases.json");
+const ERROR_NOT_FOUND: &str = include_str!("fixtures/google_play/error_not_found.json");
+
+/// Request seen by the fake Android Publisher API
+#[derive(Debug, Clone)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    authorization: Option<String>,
+    body: String,
+}
+
+/// Fake Android Publisher API that replays queued responses and records requests
+#[derive(Clone, Default)]
+struct FakePublisher {
+    responses: Arc<Mutex<VecDeque<(StatusCode, String)>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl FakePublisher {
+    fn respond(&self, status: StatusCode, body: &str) {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back((status, body.to_string()));
+    }
+
+    fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle(State(fake): State<FakePublisher>, req: Request) -> Response {
+    let (parts, body) = req.into_parts();
+    let body: Bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    fake.requests.lock().unwrap().push(RecordedRequest {
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        query: parts.uri.query().map(str::to_string),
+        authorization: parts
+            .headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+
+    let (status, body) = fake
+        .responses
+        .lock()
+        .unwrap()
+        .pop_front()
+        .unwrap_or((StatusCode::OK, "{}".to_string()));
+    (status, [("content-type", "application/json")], body).into_response()
+}
+
+/// Hands out `token-1`, `token-2`, ... so refreshes are visible
+#[derive(Clone, Default)]
+struct CountingTokens {
+    fetched: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl TokenSource for CountingTokens {
+    async fn fetch_token(&self) -> AppResult<String> {
+        let n = self.fetched.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(format!("token-{}", n))
+    }
+}
+
+async fn start_fake() -> (FakePublisher, Client, CountingTokens) {
+    let fake = FakePublisher::default();
+    let app = Router::new().fallback(handle).with_state(fake.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let tokens = CountingTokens::default();
+    let debug_log = DebugLog::new(FeatureFlags::new(HashMap::new(), HashMap::new()), 0);
+    let client = Client::new(tokens.clone(), debug_log).with_base_url(&format!("http://{}", addr));
+    (fake, client, tokens)
+}
+
+// Subscriptions are parsed from Google's v2 response and the access token is reused
+#[tokio::test]
+async fn test_get_subscription_v2() {
+    let (fake, client, tokens) = start_fake().await;
+    fake.respond(StatusCode::OK, SUBSCRIPTION_V2);
+    fake.respond(StatusCode::OK, SUBSCRIPTION_V2);
+
+    let subscription = client
+        .get_subscription_v2("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap();
+    assert_eq!(subscription.subscription_state, SubscriptionState::Active);
+    assert_eq!(
+        subscription.acknowledgement_state,
+        AcknowledgementState::Pending
+    );
+    assert_eq!(subscription.line_items[0].product_id, "yral_pro_plan");
+    client
+        .get_subscription_v2("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap();
+
+    let requests = fake.requests();
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(
+        requests[0].path,
+        "/applications/com.yral.android/purchases/subscriptionsv2/tokens/purchase-token-1"
+    );
+    assert_eq!(requests[0].authorization.as_deref(), Some("Bearer token-1"));
+    assert_eq!(requests[1].authorization.as_deref(), Some("Bearer token-1"));
+    assert_eq!(tokens.fetched.load(Ordering::SeqCst), 1);
+}
+
+// A rejected token is refreshed and the call retried once
+#[tokio::test]
+async fn test_unauthorized_refreshes_token() {
+    let (fake, client, tokens) = start_fake().await;
+    fake.respond(StatusCode::UNAUTHORIZED, "{}");
+    fake.respond(StatusCode::OK, PRODUCT_PURCHASE_V2);
+
+    let product = client
+        .get_product_purchase("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap();
+    assert_eq!(
+        product.order_id.as_deref(),
+        Some("GPA.3391-2208-4466-90001")
+    );
+
+    let requests = fake.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].authorization.as_deref(), Some("Bearer token-2"));
+    assert_eq!(tokens.fetched.load(Ordering::SeqCst), 2);
+}
+
+// Google's error message and status are surfaced in the error
+#[tokio::test]
+async fn test_error_body_is_mapped() {
+    let (fake, client, _) = start_fake().await;
+    fake.respond(StatusCode::NOT_FOUND, ERROR_NOT_FOUND);
+
+    let err = client
+        .get_subscription_v2("com.yral.android", "missing")
+        .await
+        .unwrap_err();
+    match err {
+        AppError::GooglePlayTokenNotFound(message) => {
+            assert!(message.contains("404"));
+            assert!(message.contains("NOT_FOUND"));
+            assert!(message.contains("The purchase token was not found."));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // A persistent 401 is not retried forever
+    fake.respond(StatusCode::UNAUTHORIZED, "{}");
+    fake.respond(StatusCode::UNAUTHORIZED, "{}");
+    assert!(matches!(
+        client
+            .get_subscription_v2("com.yral.android", "missing")
+            .await,
+        Err(AppError::GooglePlayPermissionDenied(_))
+    ));
+    assert_eq!(fake.requests().len(), 3);
+}
+
+// Rate limits become a 503 that tells the caller when to retry
+#[test]
+fn test_throttling_is_mapped_with_retry_after() {
+    let err = map_google_error(
+        "get_subscription_v2",
+        StatusCode::TOO_MANY_REQUESTS,
+        Some("120"),
+        br#"{"error": {"code": 429, "message": "Slow down.", "status": "RESOURCE_EXHAUSTED"}}"#,
+    );
+    assert_eq!(err.retry_after_secs(), Some(120));
+    assert!(err.to_string().contains("RESOURCE_EXHAUSTED"));
+
+    let response = err.into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get("retry-after").unwrap(), "120");
+
+    // Quota errors arrive as 403 with a reason and no Retry-After
+    let err = map_google_error(
+        "acknowledge",
+        StatusCode::FORBIDDEN,
+        None,
+        br#"{"error": {"code": 403, "message": "Quota exceeded.", "errors": [{"reason": "quotaExceeded"}]}}"#,
+    );
+    assert!(matches!(err, AppError::GooglePlayThrottled { .. }));
+    assert_eq!(err.retry_after_secs(), Some(DEFAULT_RETRY_AFTER_SECS));
+}
+
+#[test]
+fn test_error_statuses_are_mapped() {
+    let not_found = map_google_error("get_subscription_v2", StatusCode::GONE, None, b"{}");
+    assert!(matches!(not_found, AppError::GooglePlayTokenNotFound(_)));
+    assert_eq!(not_found.into_response().status(), StatusCode::BAD_REQUEST);
+
+    let denied = map_google_error(
+        "revoke",
+        StatusCode::FORBIDDEN,
+        None,
+        br#"{"error": {"code": 403, "message": "No access.", "status": "PERMISSION_DENIED"}}"#,
+    );
+    assert!(matches!(denied, AppError::GooglePlayPermissionDenied(_)));
+    assert!(denied.retry_after_secs().is_none());
+
+    let unavailable = map_google_error(
+        "get_subscription_v2",
+        StatusCode::INTERNAL_SERVER_ERROR,
+        None,
+        b"backend error",
+    );
+    assert!(matches!(unavailable, AppError::GooglePlayUnavailable(_)));
+    assert!(unavailable.to_string().contains("backend error"));
+
+    let other = map_google_error("defer", StatusCode::BAD_REQUEST, None, b"{}");
+    assert!(matches!(other, AppError::GooglePlayApi(_)));
+}
+
+// A body we can't decode is our failure, a 4xx the caller's and a 5xx Google's, each counted apart
+#[tokio::test]
+async fn test_failures_are_told_apart() {
+    let (fake, client, _) = start_fake().await;
+    let metrics = Metrics::new();
+    let client = client.with_metrics(metrics.clone());
+    let count = |kind: &str| {
+        metrics.counter(
+            GOOGLE_PLAY_ERRORS_TOTAL,
+            &[
+                ("operation", "purchases.subscriptionsv2.get"),
+                ("kind", kind),
+            ],
+        )
+    };
+
+    fake.respond(StatusCode::OK, "[]");
+    let decode = client
+        .get_subscription_v2("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap_err();
+    assert!(matches!(decode, AppError::GooglePlayResponseParse(_)));
+    assert_eq!(
+        decode.into_response().status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(count("decode"), 1);
+
+    fake.respond(
+        StatusCode::BAD_REQUEST,
+        r#"{"error": {"code": 400, "message": "Invalid token.", "errors": [{"reason": "invalid"}]}}"#,
+    );
+    let client_error = client
+        .get_subscription_v2("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap_err();
+    assert!(client_error
+        .to_string()
+        .contains("(invalid): Invalid token."));
+    assert_eq!(error_kind(&client_error), "client");
+    assert_eq!(
+        client_error.into_response().status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(count("client"), 1);
+
+    fake.respond(StatusCode::SERVICE_UNAVAILABLE, "backend error");
+    let upstream = client
+        .get_subscription_v2("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap_err();
+    assert!(matches!(upstream, AppError::GooglePlayUnavailable(_)));
+    assert_eq!(upstream.into_response().status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(count("upstream"), 1);
+    assert_eq!(count("network"), 0);
+}
+
+// Nothing listens on the port, so the call fails before reaching Google
+#[tokio::test]
+async fn test_connection_failure_is_network_error() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let metrics = Metrics::new();
+    let debug_log = DebugLog::new(FeatureFlags::new(HashMap::new(), HashMap::new()), 0);
+    let client = Client::new(CountingTokens::default(), debug_log)
+        .with_base_url(&format!("http://{}", addr))
+        .with_metrics(metrics.clone());
+
+    let err = client
+        .get_product_purchase("com.yral.android", "purchase-token-1")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::NetworkError(_)));
+    assert_eq!(
+        metrics.counter(
+            GOOGLE_PLAY_ERRORS_TOTAL,
+            &[
+                ("operation", "purchases.productsv2.get"),
+                ("kind", "network")
+            ],
+        ),
+        1
+    );
+}
+
+// Write calls hit the documented paths with the expected bodies
+#[tokio::test]
+async fn test_acknowledge_revoke_and_consume() {
+    let (fake, client, _) = start_fake().await;
+
+    client
+        .acknowledge("com.yral.android", "yral_pro_plan", "purchase-token-1")
+        .await
+        .unwrap();
+    client
+        .revoke(
+            "com.yral.android",
+            "purchase-token-1",
+            RevocationRefund::Prorated,
+        )
+        .await
+        .unwrap();
+    client
+        .consume_product("com.yral.android", "bot_chat_access", "purchase-token-2")
+        .await
+        .unwrap();
+
+    let requests = fake.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(
+        requests[0].path,
+        "/applications/com.yral.android/purchases/subscriptions/yral_pro_plan/tokens/purchase-token-1:acknowledge"
+    );
+    assert_eq!(
+        requests[1].path,
+        "/applications/com.yral.android/purchases/subscriptionsv2/tokens/purchase-token-1:revoke"
+    );
+    let revoke_body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(
+        revoke_body,
+        serde_json::json!({ "revocationContext": { "proratedRefund": {} } })
+    );
+    assert_eq!(
+        requests[2].path,
+        "/applications/com.yral.android/purchases/products/bot_chat_access/tokens/purchase-token-2:consume"
+    );
+}
+
+// Acknowledging twice is not an error, other bad requests still are
+#[tokio::test]
+async fn test_already_acknowledged_is_ok() {
+    let (fake, client, _) = start_fake().await;
+    fake.respond(
+        StatusCode::BAD_REQUEST,
+        r#"{"error":{"code":400,"message":"The subscription purchase is already acknowledged.","status":"FAILED_PRECONDITION"}}"#,
+    );
+    client
+        .acknowledge("com.yral.android", "yral_pro_plan", "purchase-token-1")
+        .await
+        .unwrap();
+
+    fake.respond(
+        StatusCode::BAD_REQUEST,
+        r#"{"error":{"code":400,"message":"Invalid package name.","status":"INVALID_ARGUMENT"}}"#,
+    );
+    assert!(matches!(
+        client
+            .acknowledge("com.yral.android", "yral_pro_plan", "purchase-token-1")
+            .await,
+        Err(AppError::GooglePlayApi(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_defer_returns_new_expiry() {
+    let (fake, client, _) = start_fake().await;
+    fake.respond(
+        StatusCode::OK,
+        r#"{"newExpiryTimeMillis": "1777629600000"}"#,
+    );
+
+    let expected = Utc.timestamp_millis_opt(1_775_037_600_000).unwrap();
+    let desired = Utc.timestamp_millis_opt(1_777_629_600_000).unwrap();
+    let new_expiry = client
+        .defer(
+            "com.yral.android",
+            "yral_pro_plan",
+            "purchase-token-1",
+            expected,
+            desired,
+        )
+        .await
+        .unwrap();
+    assert_eq!(new_expiry, desired);
+
+    let body: serde_json::Value = serde_json::from_str(&fake.requests()[0].body).unwrap();
+    assert_eq!(
+        body["deferralInfo"]["expectedExpiryTimeMillis"],
+        "1775037600000"
+    );
+    assert_eq!(
+        body["deferralInfo"]["desiredExpiryTimeMillis"],
+        "1777629600000"
+    );
+}
+
+#[tokio::test]
+async fn test_list_voided_purchases() {
+    let (fake, client, _) = start_fake().await;
+    fake.respond(StatusCode::OK, VOIDED_PURCHASES);
+
+    let page = client
+        .list_voided_purchases(
+            "com.yral.android",
+            &VoidedPurchasesQuery {
+                start_time: Some(Utc.timestamp_millis_opt(1_772_000_000_000).unwrap()),
+                page_token: Some("page-1".to_string()),
+                include_subscriptions: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(page.voided_purchases.len(), 1);
+    assert_eq!(page.voided_purchases[0].purchase_token, "voided-token-1");
+    assert_eq!(page.voided_purchases[0].voided_reason, Some(1));
+    assert_eq!(page.next_page_token(), Some("page-2"));
+
+    let requests = fake.requests();
+    assert_eq!(
+        requests[0].path,
+        "/applications/com.yral.android/purchases/voidedpurchases"
+    );
+    assert_eq!(
+        requests[0].query.as_deref(),
+        Some("startTime=1772000000000&token=page-1&type=1")
+    );
+}
+
+#[tokio::test]
+async fn test_list_subscriptions() {
+    let (fake, client, _) = start_fake().await;
+    fake.respond(
+        StatusCode::OK,
+        r#"{"subscriptions": [{"productId": "yral_pro_plan", "basePlans": [{"basePlanId": "monthly", "state": "ACTIVE", "autoRenewingBasePlanType": {"billingPeriodDuration": "P1M"}}]}], "nextPageToken": "page-2"}"#,
+    );
+
+    let page = client
+        .list_subscriptions("com.yral.android", Some("page-1"))
+        .await
+        .unwrap();
+    assert_eq!(page.subscriptions[0].product_id, "yral_pro_plan");
+    assert_eq!(
+        page.subscriptions[0].base_plans[0]
+            .billing_period()
+            .as_deref(),
+        Some("P1M")
+    );
+    assert_eq!(page.next_page_token.as_deref(), Some("page-2"));
+
+    let requests = fake.requests();
+    assert_eq!(
+        requests[0].path,
+        "/applications/com.yral.android/subscriptions"
+    );
+    assert_eq!(
+        requests[0].query.as_deref(),
+        Some("pageSize=100&pageToken=page-1")
+    );
+}
@@ -0,0 +1,177 @@
+//! Recorded (sanitized) subscriptionsv2 responses run through verify, one per shape the app
+//! runs into in the wild
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use yral_billing::clock::TestClock;
+use yral_billing::error::AppError;
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::purchase::{process_purchase_token, VerifyOutcome};
+use yral_billing::routes::purchase_token_helpers::verify_subcription_response_for_active_status;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{memory_state, FixedGooglePlay};
+use yral_billing::types::{
+    GooglePlaySubscriptionResponse, PurchaseTokenStatus, SubscriptionState, VerifyRequest,
+};
+
+const IN_GRACE_PERIOD: &str =
+    include_str!("fixtures/google_play/subscription_v2_in_grace_period.json");
+const PAUSED: &str = include_str!("fixtures/google_play/subscription_v2_paused.json");
+const DEFERRED: &str = include_str!("fixtures/google_play/subscription_v2_deferred.json");
+const PENDING: &str = include_str!("fixtures/google_play/subscription_v2_pending.json");
+const REVOKED: &str = include_str!("fixtures/google_play/subscription_v2_revoked.json");
+
+// Account the fixtures were bought with, verify requests come from the same user
+const ACCOUNT_ID: &str = "user-principal-1";
+
+/// Shortly after the fixtures were recorded
+fn recorded_at() -> DateTime<Utc> {
+    "2026-04-05T00:00:00Z".parse().unwrap()
+}
+
+fn time(rfc3339: &str) -> NaiveDateTime {
+    rfc3339.parse::<DateTime<Utc>>().unwrap().naive_utc()
+}
+
+fn parse(fixture: &str) -> GooglePlaySubscriptionResponse {
+    serde_json::from_str(fixture).unwrap()
+}
+
+/// Verify `fixture` as Google's answer, returning the outcome and the row stored for it
+async fn verify(fixture: &str) -> (Result<VerifyOutcome, AppError>, Option<PurchaseToken>) {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(parse(fixture));
+    let mut conn = app_state.get_db_connection().unwrap();
+    let payload = VerifyRequest {
+        user_id: ACCOUNT_ID.to_string(),
+        package_name: "com.yral.android".to_string(),
+        product_id: "yral_pro_plan".to_string(),
+        purchase_token: format!("token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
+    };
+
+    let outcome = process_purchase_token(
+        &mut conn,
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.play_integrity.as_ref(),
+        app_state.user_info.as_ref(),
+        &app_state.feature_flags,
+        &TestClock::new(recorded_at()),
+        &app_state.metrics,
+        app_state.expiry_refresh_window,
+        &app_state.quarantine_policy,
+        &payload,
+    )
+    .await
+    .map(|result| result.outcome);
+    let stored = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&payload.purchase_token))
+        .first(&mut conn)
+        .optional()
+        .unwrap();
+
+    (outcome, stored)
+}
+
+#[test]
+fn test_fixtures_parse() {
+    let grace = parse(IN_GRACE_PERIOD);
+    assert_eq!(grace.subscription_state, SubscriptionState::InGracePeriod);
+    assert!(grace.auto_renewing());
+    assert!(grace.test_purchase.is_none());
+
+    let paused = parse(PAUSED);
+    assert_eq!(
+        paused.pause_window(),
+        Some((time("2026-03-20T14:02:50Z"), time("2026-05-20T14:02:50Z")))
+    );
+    // `recurringPrice` is left out of paused plans
+    assert!(paused.line_items[0]
+        .auto_renewing_plan
+        .as_ref()
+        .unwrap()
+        .recurring_price
+        .is_none());
+
+    // Pending purchases have no order or expiry yet
+    let pending = parse(PENDING);
+    assert!(pending.latest_order_id.is_none());
+    assert!(pending.line_items[0].expiry_time.is_none());
+    assert!(pending.auto_renewing());
+
+    // Auto-renew is reported on the plan, not the line item
+    let revoked = parse(REVOKED);
+    assert!(revoked.line_items[0].auto_renewing.is_none());
+    assert!(!revoked.auto_renewing());
+}
+
+#[test]
+fn test_fixtures_active_status() {
+    for fixture in [IN_GRACE_PERIOD, DEFERRED] {
+        assert!(verify_subcription_response_for_active_status(&parse(fixture)).is_ok());
+    }
+    assert!(matches!(
+        verify_subcription_response_for_active_status(&parse(PAUSED)),
+        Err(AppError::SubscriptionPaused)
+    ));
+    assert!(matches!(
+        verify_subcription_response_for_active_status(&parse(PENDING)),
+        Err(AppError::SubscriptionInvalidState)
+    ));
+    assert!(matches!(
+        verify_subcription_response_for_active_status(&parse(REVOKED)),
+        Err(AppError::SubscriptionExpired)
+    ));
+}
+
+// Access lasts through the grace period, until the extended expiry Google reports
+#[tokio::test]
+async fn test_verify_in_grace_period() {
+    let (outcome, stored) = verify(IN_GRACE_PERIOD).await;
+
+    assert_eq!(outcome.unwrap(), VerifyOutcome::Granted);
+    let stored = stored.unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(stored.expiry_at, time("2026-04-11T06:41:12.455Z"));
+    assert!(stored.auto_renewing);
+    // Already acknowledged by Google, nothing left to do
+    assert!(stored.acknowledged_at.is_some());
+}
+
+// A deferred subscription is active with its expiry moved out
+#[tokio::test]
+async fn test_verify_deferred() {
+    let (outcome, stored) = verify(DEFERRED).await;
+
+    assert_eq!(outcome.unwrap(), VerifyOutcome::Granted);
+    let stored = stored.unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(stored.expiry_at, time("2026-05-28T09:14:59Z"));
+}
+
+#[tokio::test]
+async fn test_verify_pending() {
+    let (outcome, stored) = verify(PENDING).await;
+
+    assert_eq!(outcome.unwrap(), VerifyOutcome::Pending);
+    let stored = stored.unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::Pending);
+    assert_eq!(stored.expiry_at, recorded_at().naive_utc());
+}
+
+#[tokio::test]
+async fn test_verify_paused() {
+    let (outcome, stored) = verify(PAUSED).await;
+
+    assert!(matches!(outcome, Err(AppError::SubscriptionPaused)));
+    assert!(stored.is_none());
+}
+
+#[tokio::test]
+async fn test_verify_revoked_with_refund() {
+    let (outcome, stored) = verify(REVOKED).await;
+
+    assert!(matches!(outcome, Err(AppError::SubscriptionExpired)));
+    assert!(stored.is_none());
+}
@@ -0,0 +1,62 @@
+use yral_billing::error::AppError;
+use yral_billing::routes::purchase_token_helpers::verify_subscription_state_is_active;
+use yral_billing::types::{AcknowledgementState, SubscriptionLineItem, SubscriptionState};
+
+// Known states map to their variants
+#[test]
+fn test_known_states_deserialize() {
+    let state: SubscriptionState =
+        serde_json::from_str("\"SUBSCRIPTION_STATE_IN_GRACE_PERIOD\"").unwrap();
+    assert_eq!(state, SubscriptionState::InGracePeriod);
+
+    let ack: AcknowledgementState =
+        serde_json::from_str("\"ACKNOWLEDGEMENT_STATE_ACKNOWLEDGED\"").unwrap();
+    assert_eq!(ack, AcknowledgementState::Acknowledged);
+}
+
+// States Google adds later fall back to Unknown and are not treated as active
+#[test]
+fn test_unknown_state_falls_back() {
+    let state: SubscriptionState = serde_json::from_str("\"SUBSCRIPTION_STATE_NEW\"").unwrap();
+    assert_eq!(state, SubscriptionState::Unknown);
+    assert!(matches!(
+        verify_subscription_state_is_active(state),
+        Err(AppError::SubscriptionInvalidState)
+    ));
+}
+
+// Only active and grace period subscriptions grant access
+#[test]
+fn test_active_states() {
+    assert!(verify_subscription_state_is_active(SubscriptionState::Active).is_ok());
+    assert!(verify_subscription_state_is_active(SubscriptionState::InGracePeriod).is_ok());
+    assert!(matches!(
+        verify_subscription_state_is_active(SubscriptionState::OnHold),
+        Err(AppError::SubscriptionOnHold)
+    ));
+}
+
+// The recurring price is read from the line item and converted to micros
+#[test]
+fn test_line_item_price() {
+    let item: SubscriptionLineItem = serde_json::from_str(
+        r#"{
+            "productId": "yral_pro",
+            "expiryTime": "2026-04-01T00:00:00Z",
+            "offerDetails": { "basePlanId": "monthly", "offerId": "intro" },
+            "autoRenewingPlan": {
+                "autoRenewEnabled": true,
+                "recurringPrice": { "currencyCode": "INR", "units": "199", "nanos": 500000000 }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let offer = item.offer_details.unwrap();
+    assert_eq!(offer.base_plan_id.as_deref(), Some("monthly"));
+    assert_eq!(offer.offer_id.as_deref(), Some("intro"));
+
+    let price = item.auto_renewing_plan.unwrap().recurring_price.unwrap();
+    assert_eq!(price.currency_code, "INR");
+    assert_eq!(price.micros(), Some(199_500_000));
+}
@@ -0,0 +1,180 @@
+use axum::routing::post;
+use axum::Router;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::clock::TestClock;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::jobs::grace_reminders::{
+    due_grace_reminder, parse_grace_offsets, send_grace_reminders, GraceOffset,
+};
+use yral_billing::model::GraceReminder;
+use yral_billing::notifier::{BillingEvent, Notifier};
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::grace_reminders;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{SubscriptionNotificationType, SubscriptionState};
+use yral_billing::AppState;
+
+fn reminder(started_at: NaiveDateTime, grace_days: i64) -> GraceReminder {
+    GraceReminder::new(
+        "token_1".to_string(),
+        "user_1".to_string(),
+        "yral_pro".to_string(),
+        started_at,
+        started_at + chrono::Duration::days(grace_days),
+    )
+}
+
+fn reminder_number(event: Option<BillingEvent>) -> Option<(u32, bool)> {
+    match event {
+        Some(BillingEvent::PaymentDeclinedReminder {
+            reminder,
+            final_reminder,
+            ..
+        }) => Some((reminder, final_reminder)),
+        _ => None,
+    }
+}
+
+fn stored(app_state: &AppState, token: &str) -> Option<GraceReminder> {
+    grace_reminders::table
+        .find(token)
+        .first(&mut app_state.get_db_connection().unwrap())
+        .optional()
+        .unwrap()
+}
+
+async fn notify(app_state: &AppState, notification_type: SubscriptionNotificationType) {
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state.clone());
+    let rtdn = RtdnBuilder::subscription(notification_type, "token_1");
+    app.oneshot(rtdn.request()).await.unwrap();
+}
+
+#[test]
+fn test_offset_parsing() {
+    assert_eq!(
+        parse_grace_offsets("0, 3,final").unwrap(),
+        vec![
+            GraceOffset::Days(0),
+            GraceOffset::Days(3),
+            GraceOffset::FinalDay
+        ]
+    );
+    assert!(parse_grace_offsets("0,last").is_err());
+    assert!(parse_grace_offsets("-1").is_err());
+}
+
+// Day 0, day 3 and the final day of a week long grace period, each sent once
+#[test]
+fn test_reminder_cadence() {
+    let offsets = parse_grace_offsets("0,3,final").unwrap();
+    let started_at = chrono::Utc::now().naive_utc();
+    let mut grace = reminder(started_at, 7);
+    let at = |days: i64| started_at + chrono::Duration::days(days);
+
+    assert_eq!(
+        reminder_number(due_grace_reminder(&grace, &offsets, at(0))),
+        Some((1, false))
+    );
+    grace.reminders_sent = 1;
+    assert_eq!(
+        reminder_number(due_grace_reminder(&grace, &offsets, at(2))),
+        None
+    );
+    assert_eq!(
+        reminder_number(due_grace_reminder(&grace, &offsets, at(3))),
+        Some((2, false))
+    );
+    grace.reminders_sent = 2;
+    assert_eq!(
+        reminder_number(due_grace_reminder(&grace, &offsets, at(6))),
+        Some((3, true))
+    );
+    grace.reminders_sent = 3;
+    assert_eq!(
+        reminder_number(due_grace_reminder(&grace, &offsets, at(6))),
+        None
+    );
+
+    // After downtime only the latest due reminder goes out
+    let missed = reminder(started_at, 7);
+    assert_eq!(
+        reminder_number(due_grace_reminder(&missed, &offsets, at(4))),
+        Some((2, false))
+    );
+
+    // Offsets past a short grace period are dropped, the final day comes first
+    let short = reminder(started_at, 3);
+    assert_eq!(
+        reminder_number(due_grace_reminder(&short, &offsets, at(2))),
+        Some((2, true))
+    );
+
+    // Nothing once stopped or over
+    let mut stopped = reminder(started_at, 7);
+    stopped.stopped_at = Some(at(1));
+    assert!(due_grace_reminder(&stopped, &offsets, at(3)).is_none());
+    assert!(due_grace_reminder(&grace, &offsets, at(8)).is_none());
+}
+
+// A grace period notification starts the reminders, recovery stops them
+#[tokio::test]
+async fn test_reminders_follow_notifications() {
+    let now = chrono::Utc::now().naive_utc();
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .state(SubscriptionState::InGracePeriod)
+            .expiry_at(now + chrono::Duration::days(7))
+            .build(),
+    );
+    PurchaseTokenBuilder::new(MOCK_SUBSCRIPTION_ACCOUNT_ID)
+        .purchase_token("token_1")
+        .expiry_at(now + chrono::Duration::days(7))
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    notify(&app_state, SubscriptionNotificationType::InGracePeriod).await;
+    let started = stored(&app_state, "token_1").unwrap();
+    assert_eq!(started.user_id, MOCK_SUBSCRIPTION_ACCOUNT_ID);
+    assert!(started.grace_ends_at > now + chrono::Duration::days(6));
+    assert!(started.stopped_at.is_none());
+
+    let offsets = parse_grace_offsets("0,3,final").unwrap();
+    let clock = TestClock::new(chrono::Utc::now());
+    let notifier = Notifier::new(None);
+    let mut conn = app_state.get_db_connection().unwrap();
+    let sent = send_grace_reminders(&mut conn, &notifier, &clock, &offsets)
+        .await
+        .unwrap();
+    assert_eq!(sent, 1);
+    let sent = send_grace_reminders(&mut conn, &notifier, &clock, &offsets)
+        .await
+        .unwrap();
+    assert_eq!(sent, 0);
+
+    // A redelivered notification keeps the reminders already sent
+    notify(&app_state, SubscriptionNotificationType::InGracePeriod).await;
+    let redelivered = stored(&app_state, "token_1").unwrap();
+    assert_eq!(redelivered.reminders_sent, 1);
+    assert!(redelivered.last_sent_at.is_some());
+
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .expiry_at(now + chrono::Duration::days(30))
+            .build(),
+    );
+    notify(&app_state, SubscriptionNotificationType::Recovered).await;
+    assert!(stored(&app_state, "token_1").unwrap().stopped_at.is_some());
+
+    // Nothing is sent for a recovered subscription
+    clock.advance(chrono::Duration::days(3));
+    let sent = send_grace_reminders(&mut conn, &notifier, &clock, &offsets)
+        .await
+        .unwrap();
+    assert_eq!(sent, 0);
+}
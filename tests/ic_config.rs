@@ -0,0 +1,31 @@
+use ic_agent::export::Principal;
+use yral_billing::integrations::user_info::{IcConfig, IC_MAINNET_URL};
+use yral_canisters_client::ic::USER_INFO_SERVICE_ID;
+
+// Without overrides the production canister on mainnet is used
+#[test]
+fn test_defaults_to_production_canister() {
+    let config = IcConfig::new(None, None).unwrap();
+    assert_eq!(config.url, IC_MAINNET_URL);
+    assert_eq!(config.user_info_canister_id, USER_INFO_SERVICE_ID);
+    assert!(config.is_mainnet());
+}
+
+// A local replica and a staging canister can be named, a malformed canister id is refused
+#[test]
+fn test_overrides_for_local_replica() {
+    let config = IcConfig::new(
+        Some("http://127.0.0.1:4943/"),
+        Some("rrkah-fqaaa-aaaaa-aaaaq-cai"),
+    )
+    .unwrap();
+    assert_eq!(config.url, "http://127.0.0.1:4943");
+    assert_eq!(
+        config.user_info_canister_id,
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    );
+    assert!(!config.is_mainnet());
+
+    let err = IcConfig::new(None, Some("not a principal")).unwrap_err();
+    assert!(err.contains("USER_INFO_CANISTER_ID"));
+}
@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use diesel::prelude::*;
+use ic_agent::agent::RejectCode;
+use ic_agent::export::Principal;
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::user_info::{
+    decode_canister_message, decode_reject, ic_error_kind, MockUserInfo, UserInfoApi,
+};
+use yral_billing::jobs::access_outbox::{drain_access_outbox, enqueue_access_change};
+use yral_billing::model::AccessOutboxEntry;
+use yral_billing::schema::access_outbox;
+use yral_billing::test_support::{memory_state, new_test_user};
+use yral_billing::types::{OutboxAction, OutboxStatus, Plan};
+
+const USER: &str = "aaaaa-aa";
+
+// Stopped canisters and transient rejects are retried, everything else the canister refuses
+// is final
+#[test]
+fn test_rejects_decoded_with_retryability() {
+    let stopped = decode_reject(
+        "change_subscription_plan",
+        USER,
+        RejectCode::CanisterError,
+        Some("IC0508"),
+        "Canister is stopped",
+    );
+    assert!(matches!(stopped, AppError::CanisterStopped(_)));
+    assert!(stopped.is_retryable());
+    assert!(stopped.is_ic_failure());
+
+    let transient = decode_reject(
+        "get_subscription_plan",
+        USER,
+        RejectCode::SysTransient,
+        None,
+        "Subnet is overloaded",
+    );
+    assert!(matches!(transient, AppError::ServiceAccessFailed(_)));
+    assert!(transient.is_retryable());
+
+    let trapped = decode_reject(
+        "change_subscription_plan",
+        USER,
+        RejectCode::CanisterError,
+        Some("IC0503"),
+        "Canister trapped: unreachable",
+    );
+    assert!(matches!(trapped, AppError::CanisterRejected(_)));
+    assert!(!trapped.is_retryable());
+    assert_eq!(ic_error_kind(&trapped), "rejected");
+
+    let missing = decode_reject(
+        "change_subscription_plan",
+        USER,
+        RejectCode::CanisterReject,
+        None,
+        "User not found",
+    );
+    assert!(matches!(missing, AppError::UserNotFound(ref user) if user == USER));
+    assert!(!missing.is_retryable());
+}
+
+// Errors the service reports as text map to the variants callers handle
+#[test]
+fn test_canister_messages_decoded() {
+    assert!(matches!(
+        decode_canister_message(USER, "User is not registered"),
+        Some(AppError::UserNotFound(_))
+    ));
+    assert!(matches!(
+        decode_canister_message(USER, "User already has a Pro subscription"),
+        Some(AppError::AlreadyPro(_))
+    ));
+    assert!(decode_canister_message(USER, "Insufficient credits").is_none());
+}
+
+/// IC client whose grants fail with `AlreadyPro` for `pro_user` and `UserNotFound` otherwise
+struct RejectingUserInfo {
+    pro_user: String,
+}
+
+#[async_trait]
+impl UserInfoApi for RejectingUserInfo {
+    async fn grant_pro_plan(&self, _product_id: &str, user_id: &str) -> AppResult<()> {
+        if user_id == self.pro_user {
+            Err(AppError::AlreadyPro(user_id.to_string()))
+        } else {
+            Err(AppError::UserNotFound(user_id.to_string()))
+        }
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+// A grant for a user already on Pro is done, one for an unknown user fails without retries
+#[tokio::test]
+async fn test_outbox_settles_decoded_errors() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = app_state.clock.now_naive();
+    let (pro_user, unknown_user) = (new_test_user(), new_test_user());
+    for user in [&pro_user, &unknown_user] {
+        enqueue_access_change(
+            &mut conn,
+            user,
+            OutboxAction::GrantPro,
+            Some(YRAL_PRO_PLAN_PRODUCT_ID),
+            None,
+            now,
+        )
+        .unwrap();
+    }
+
+    let user_info = RejectingUserInfo {
+        pro_user: pro_user.clone(),
+    };
+    let applied = drain_access_outbox(
+        &mut conn,
+        &user_info,
+        app_state.clock.as_ref(),
+        &app_state.metrics,
+        10,
+    )
+    .await
+    .unwrap();
+    assert_eq!(applied, 1);
+
+    let mut entry = |user: &str| -> AccessOutboxEntry {
+        access_outbox::table
+            .filter(access_outbox::user_id.eq(user))
+            .first(&mut conn)
+            .unwrap()
+    };
+    assert_eq!(entry(&pro_user).status, OutboxStatus::Done);
+    let failed = entry(&unknown_user);
+    assert_eq!(failed.status, OutboxStatus::Failed);
+    assert_eq!(failed.attempts, 1);
+    assert!(failed.last_error.unwrap().contains("not registered"));
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::env;
+
+use yral_billing::debug_log::DebugLog;
+use yral_billing::feature_flags::FeatureFlags;
+use yral_billing::integrations::google_play::SubscriptionSnapshots;
+use yral_billing::integrations::{IntegrationMode, Integrations};
+use yral_billing::metrics::Metrics;
+
+struct EnvGuard;
+
+impl EnvGuard {
+    fn set(integrations: Option<&str>, app_env: Option<&str>) -> Self {
+        match integrations {
+            Some(value) => env::set_var("BILLING_INTEGRATIONS", value),
+            None => env::remove_var("BILLING_INTEGRATIONS"),
+        }
+        match app_env {
+            Some(value) => env::set_var("APP_ENV", value),
+            None => env::remove_var("APP_ENV"),
+        }
+        Self
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        env::remove_var("BILLING_INTEGRATIONS");
+        env::remove_var("APP_ENV");
+    }
+}
+
+// Test builds run with the local feature, which defaults to mock integrations
+#[test]
+fn test_local_build_defaults_to_mock() {
+    let _env = EnvGuard::set(None, None);
+
+    assert_eq!(IntegrationMode::from_env().unwrap(), IntegrationMode::Mock);
+}
+
+#[test]
+fn test_explicit_mode_is_respected() {
+    let _env = EnvGuard::set(Some("live"), None);
+    assert_eq!(IntegrationMode::from_env().unwrap(), IntegrationMode::Live);
+
+    let _env = EnvGuard::set(Some("mock"), Some("staging"));
+    assert_eq!(IntegrationMode::from_env().unwrap(), IntegrationMode::Mock);
+}
+
+#[test]
+fn test_unknown_mode_is_rejected() {
+    let _env = EnvGuard::set(Some("fake"), None);
+
+    let err = IntegrationMode::from_env().unwrap_err();
+    assert!(err.contains("BILLING_INTEGRATIONS"));
+}
+
+// A production deployment must never fall back to mock verification
+#[test]
+fn test_mock_refused_in_production() {
+    let _env = EnvGuard::set(None, Some("production"));
+    assert!(IntegrationMode::from_env().is_err());
+
+    let _env = EnvGuard::set(Some("mock"), Some("production"));
+    assert!(IntegrationMode::from_env().is_err());
+}
+
+// Live mode fails at startup instead of silently skipping work when credentials are missing
+#[tokio::test]
+async fn test_live_without_credentials_fails() {
+    let _env = EnvGuard::set(Some("live"), Some("production"));
+    env::remove_var("GOOGLE_SERVICE_ACCOUNT_JSON");
+    env::remove_var("GOOGLE_APPLICATION_CREDENTIALS");
+
+    let debug_log = DebugLog::new(FeatureFlags::new(HashMap::new(), HashMap::new()), 0);
+    let err = Integrations::from_env(debug_log, SubscriptionSnapshots::default(), Metrics::new())
+        .await
+        .err()
+        .unwrap();
+    assert!(err.contains("Google Auth"));
+}
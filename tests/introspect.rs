@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::build_router;
+use yral_billing::service_auth::{
+    sign, ServiceAuth, ServiceKey, SERVICE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::SubscriptionState;
+use yral_billing::AppState;
+
+const SECRET: &str = "chat-secret";
+const PATH: &str = "/internal/introspect";
+
+async fn signed_state() -> AppState {
+    let mut keys = HashMap::new();
+    keys.insert(
+        "chat".to_string(),
+        ServiceKey {
+            secret: SECRET.to_string(),
+            requests_per_minute: None,
+        },
+    );
+    let mut app_state = memory_state().await;
+    app_state.service_auth = ServiceAuth::new(keys, 300);
+    app_state
+}
+
+fn signed_request(body: &serde_json::Value) -> Request<Body> {
+    let body = body.to_string();
+    let now = chrono::Utc::now().timestamp();
+    Request::builder()
+        .method("POST")
+        .uri(PATH)
+        .header("content-type", "application/json")
+        .header(SERVICE_HEADER, "chat")
+        .header(TIMESTAMP_HEADER, now.to_string())
+        .header(
+            SIGNATURE_HEADER,
+            sign(SECRET, now, "POST", PATH, body.as_bytes()),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn introspect(
+    app_state: &AppState,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let res = build_router(app_state.clone())
+        .oneshot(signed_request(&body))
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// The stored row answers by default, a refresh asks Google and stores what it reports
+#[tokio::test]
+async fn test_introspect_stored_and_refreshed() {
+    let mut app_state = signed_state().await;
+    let now = chrono::Utc::now().naive_utc();
+    let lapsed_at = now - chrono::Duration::hours(1);
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .state(SubscriptionState::Expired)
+            .expiry_at(lapsed_at)
+            .build(),
+    );
+    let token =
+        PurchaseTokenBuilder::new("user_1").insert(&mut app_state.get_db_connection().unwrap());
+
+    let (status, response) = introspect(
+        &app_state,
+        serde_json::json!({ "purchase_token": token.purchase_token }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["user_id"], "user_1");
+    assert_eq!(response["data"]["plan"], "pro");
+    assert_eq!(response["data"]["status"], "AccessGranted");
+    assert_eq!(response["data"]["auto_renewing"], true);
+    assert_eq!(response["data"]["refreshed"], false);
+
+    let (status, response) = introspect(
+        &app_state,
+        serde_json::json!({ "purchase_token": token.purchase_token, "refresh": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["plan"], "free");
+    assert_eq!(response["data"]["auto_renewing"], false);
+    assert_eq!(response["data"]["refreshed"], true);
+    let expiry_at: chrono::DateTime<chrono::Utc> = response["data"]["expiry_at"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(expiry_at.naive_utc() <= now);
+}
+
+#[tokio::test]
+async fn test_introspect_unknown_token() {
+    let app_state = signed_state().await;
+
+    let (status, _) = introspect(
+        &app_state,
+        serde_json::json!({ "purchase_token": "token_unknown" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_introspect_requires_signature() {
+    let app_state = signed_state().await;
+    let request = Request::builder()
+        .method("POST")
+        .uri(PATH)
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"purchase_token": "token_1"}"#))
+        .unwrap();
+
+    let res = build_router(app_state).oneshot(request).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,154 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use chrono::Utc;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::routes::orders::record_order;
+use yral_billing::test_support::{
+    as_user, memory_state, test_app, test_user, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::AppState;
+
+/// A purchase and its renewal, the renewal reported without a price
+async fn state_with_orders() -> AppState {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let token = PurchaseTokenBuilder::new(&test_user("user_1")).insert(&mut conn);
+    let now = Utc::now().naive_utc();
+
+    let purchase = SubscriptionResponseBuilder::new().order_id("GPA.1").build();
+    record_order(&mut conn, &token.purchase_token, &purchase, now).unwrap();
+    // Recorded again, e.g. by a redelivered notification
+    record_order(&mut conn, &token.purchase_token, &purchase, now).unwrap();
+
+    let mut renewal = SubscriptionResponseBuilder::new()
+        .order_id("GPA.1..0")
+        .build();
+    renewal.line_items[0].auto_renewing_plan = None;
+    record_order(
+        &mut conn,
+        &token.purchase_token,
+        &renewal,
+        now + chrono::Duration::seconds(1),
+    )
+    .unwrap();
+
+    app_state
+}
+
+/// `GET uri` with the JWT claims of `caller`
+async fn request(app_state: &AppState, caller: &str, uri: &str) -> axum::response::Response {
+    let app = as_user(test_app(app_state.clone()), caller);
+    let req = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+// One invoice per order, newest first, without an amount where Play reported no price
+#[tokio::test]
+async fn test_invoices_per_order() {
+    let app_state = state_with_orders().await;
+
+    let res = request(
+        &app_state,
+        &test_user("user_1"),
+        &format!("/billing/invoices/{}", test_user("user_1")),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let invoices = response["data"].as_array().unwrap();
+    assert_eq!(invoices.len(), 2);
+
+    assert_eq!(invoices[0]["order_id"], "GPA.1..0");
+    assert!(invoices[0]["currency_code"].is_null());
+    assert!(invoices[0]["amount_micros"].is_null());
+
+    assert_eq!(invoices[1]["order_id"], "GPA.1");
+    assert_eq!(invoices[1]["product_id"], "mock-product-id");
+    assert_eq!(invoices[1]["base_plan_id"], "mock-base-plan");
+    assert_eq!(invoices[1]["region_code"], "US");
+    assert_eq!(invoices[1]["currency_code"], "USD");
+    assert_eq!(invoices[1]["amount_micros"], 4_990_000);
+
+    let res = request(
+        &app_state,
+        &test_user("user_2"),
+        &format!("/billing/invoices/{}", test_user("user_2")),
+    )
+    .await;
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"], serde_json::json!([]));
+}
+
+// Another user's invoices are refused
+#[tokio::test]
+async fn test_other_users_invoices_refused() {
+    let app_state = state_with_orders().await;
+
+    let res = request(
+        &app_state,
+        &test_user("user_2"),
+        &format!("/billing/invoices/{}", test_user("user_1")),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "NOT_OWNER");
+}
+
+#[tokio::test]
+async fn test_export_invoices_csv() {
+    let app_state = state_with_orders().await;
+    let today = Utc::now().date_naive();
+
+    let res = request(
+        &app_state,
+        "ops@yral.com",
+        &format!(
+            "/admin/invoices/export?from={}&to={}&format=csv",
+            today, today
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let csv = String::from_utf8(body_bytes.to_vec()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("invoice_id,order_id,user_id"));
+    let user = test_user("user_1");
+    assert!(lines[1].contains(&format!(
+        ",GPA.1,{},mock-product-id,mock-base-plan,,US,USD,4990000,",
+        user
+    )));
+    assert!(lines[2].contains(&format!(
+        ",GPA.1..0,{},mock-product-id,mock-base-plan,,US,,,",
+        user
+    )));
+
+    let res = request(
+        &app_state,
+        "ops@yral.com",
+        &format!(
+            "/admin/invoices/export?from={}&to={}",
+            today,
+            today.pred_opt().unwrap()
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::cache::{Cache, MemoryCache};
+use yral_billing::leader::LeaderElection;
+use yral_billing::routes::admin::get_cluster;
+use yral_billing::test_support::memory_state;
+
+fn replicas(lease: Duration) -> (LeaderElection, LeaderElection) {
+    let cache: Arc<dyn Cache> = Arc::new(MemoryCache::default());
+    (
+        LeaderElection::new(cache.clone(), "replica-a".to_string(), lease),
+        LeaderElection::new(cache, "replica-b".to_string(), lease),
+    )
+}
+
+// One replica leads while it keeps renewing, the other follows
+#[tokio::test]
+async fn test_single_leader_while_renewed() {
+    let (a, b) = replicas(Duration::from_secs(30));
+
+    assert!(a.is_leader().await);
+    assert!(!b.is_leader().await);
+    assert!(a.renew().await);
+    assert!(!b.renew().await);
+    assert_eq!(b.current_leader().await.as_deref(), Some("replica-a"));
+}
+
+// Once the leader stops renewing, another replica takes over after the lease runs out
+#[tokio::test]
+async fn test_follower_takes_over_expired_lease() {
+    let (a, b) = replicas(Duration::from_millis(50));
+    assert!(a.renew().await);
+    assert!(!b.renew().await);
+
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    assert!(b.renew().await);
+    assert!(!a.renew().await);
+    assert!(!a.is_leader().await);
+    assert_eq!(a.current_leader().await.as_deref(), Some("replica-b"));
+}
+
+// The cluster endpoint names the leader once this replica has taken the lease
+#[tokio::test]
+async fn test_cluster_reports_leader() {
+    let app_state = memory_state().await;
+    let app = Router::new()
+        .route("/admin/cluster", get(get_cluster))
+        .with_state(app_state.clone());
+    let cluster = || async {
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/cluster")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body_bytes).unwrap()
+    };
+
+    let body = cluster().await;
+    assert!(body["data"]["leader"].is_null());
+    assert_eq!(body["data"]["is_leader"], false);
+    assert_eq!(body["data"]["cluster_wide"], false);
+
+    assert!(app_state.leader.renew().await);
+    let body = cluster().await;
+    assert_eq!(body["data"]["leader"], app_state.leader.holder());
+    assert_eq!(body["data"]["instance"], app_state.leader.holder());
+    assert_eq!(body["data"]["is_leader"], true);
+}
@@ -0,0 +1,156 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::line_items::{
+    access_expiry, entitled_line_items, expire_line_items, granting_product, line_items_for_token,
+    record_line_items,
+};
+use yral_billing::routes::admin::list_subscription_line_items;
+use yral_billing::schema::subscription_line_items;
+use yral_billing::test_support::{memory_state, PurchaseTokenBuilder, SubscriptionResponseBuilder};
+use yral_billing::types::Plan;
+
+const ADDON_PRODUCT_ID: &str = "yral_storage_addon";
+
+/// Now, to the second, as it comes back from Google's RFC 3339 times
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc().with_nanosecond(0).unwrap()
+}
+
+// Access follows the catalog product's line, an add-on ending sooner doesn't cut it short
+#[test]
+fn test_access_follows_entitled_line() {
+    let now = now();
+    let response = SubscriptionResponseBuilder::new()
+        .product_id(ADDON_PRODUCT_ID)
+        .expiry_at(now + Duration::days(1))
+        .add_line_item(YRAL_PRO_PLAN_PRODUCT_ID, now + Duration::days(30))
+        .build();
+
+    let entitled: Vec<(&str, Plan)> = entitled_line_items(&response)
+        .map(|(item, plan)| (item.product_id.as_str(), plan))
+        .collect();
+    assert_eq!(entitled, vec![(YRAL_PRO_PLAN_PRODUCT_ID, Plan::Pro)]);
+    assert_eq!(granting_product(&response), Some(YRAL_PRO_PLAN_PRODUCT_ID));
+    assert_eq!(access_expiry(&response), Some(now + Duration::days(30)));
+
+    // Without a catalog product the first line decides, as before line items were stored
+    let unlisted = SubscriptionResponseBuilder::new()
+        .expiry_at(now + Duration::days(7))
+        .build();
+    assert_eq!(granting_product(&unlisted), Some("mock-product-id"));
+    assert_eq!(access_expiry(&unlisted), Some(now + Duration::days(7)));
+}
+
+// Every line is stored, removed lines are dropped and each line expires on its own
+#[tokio::test]
+async fn test_lines_recorded_and_expired_independently() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = now();
+    let token = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+
+    let response = SubscriptionResponseBuilder::new()
+        .product_id(ADDON_PRODUCT_ID)
+        .expiry_at(now + Duration::days(1))
+        .add_line_item(YRAL_PRO_PLAN_PRODUCT_ID, now + Duration::days(30))
+        .build();
+    record_line_items(&mut conn, &token.purchase_token, &response, now).unwrap();
+
+    let stored = line_items_for_token(&mut conn, &token.purchase_token).unwrap();
+    let products: Vec<&str> = stored.iter().map(|item| item.product_id.as_str()).collect();
+    assert_eq!(products, vec![YRAL_PRO_PLAN_PRODUCT_ID, ADDON_PRODUCT_ID]);
+    assert!(stored.iter().all(|item| !item.expired));
+
+    // Two days later only the add-on has lapsed
+    assert_eq!(
+        expire_line_items(&mut conn, now + Duration::days(2)).unwrap(),
+        1
+    );
+    let expired: Vec<(String, bool)> = subscription_line_items::table
+        .filter(subscription_line_items::purchase_token.eq(&token.purchase_token))
+        .order(subscription_line_items::product_id.asc())
+        .select((
+            subscription_line_items::product_id,
+            subscription_line_items::expired,
+        ))
+        .load(&mut conn)
+        .unwrap();
+    assert_eq!(
+        expired,
+        vec![
+            (YRAL_PRO_PLAN_PRODUCT_ID.to_string(), false),
+            (ADDON_PRODUCT_ID.to_string(), true),
+        ]
+    );
+
+    // The add-on was removed from the subscription
+    let without_addon = SubscriptionResponseBuilder::new()
+        .product_id(YRAL_PRO_PLAN_PRODUCT_ID)
+        .expiry_at(now + Duration::days(30))
+        .build();
+    record_line_items(&mut conn, &token.purchase_token, &without_addon, now).unwrap();
+    let stored = line_items_for_token(&mut conn, &token.purchase_token).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].product_id, YRAL_PRO_PLAN_PRODUCT_ID);
+
+    // Tokens we don't track keep no line items
+    record_line_items(&mut conn, "untracked_token", &response, now).unwrap();
+    assert!(line_items_for_token(&mut conn, "untracked_token")
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_admin_lists_line_items() {
+    let app_state = memory_state().await;
+    let now = now();
+    let token = {
+        let mut conn = app_state.get_db_connection().unwrap();
+        let token = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+        let response = SubscriptionResponseBuilder::new()
+            .product_id(YRAL_PRO_PLAN_PRODUCT_ID)
+            .expiry_at(now + Duration::days(30))
+            .add_line_item(ADDON_PRODUCT_ID, now - Duration::days(1))
+            .build();
+        record_line_items(&mut conn, &token.purchase_token, &response, now).unwrap();
+        token
+    };
+    let app = Router::new()
+        .route(
+            "/admin/subscriptions/{token}/line-items",
+            get(list_subscription_line_items),
+        )
+        .with_state(app_state);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "/admin/subscriptions/{}/line-items",
+                    token.purchase_token
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let items = response["data"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["product_id"], YRAL_PRO_PLAN_PRODUCT_ID);
+    assert_eq!(items[0]["plan"], "pro");
+    assert_eq!(items[0]["expired"], false);
+    assert_eq!(items[1]["product_id"], ADDON_PRODUCT_ID);
+    assert_eq!(items[1]["plan"], serde_json::Value::Null);
+    assert_eq!(items[1]["expired"], true);
+}
@@ -0,0 +1,52 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::routes::manage::{get_manage_url, manage_urls, PLAY_SUBSCRIPTIONS_URL};
+
+#[test]
+fn test_manage_urls() {
+    let urls = manage_urls(Some("yral_pro"), Some("com.yral.android")).unwrap();
+    assert_eq!(
+        urls.manage_url,
+        "https://play.google.com/store/account/subscriptions?sku=yral_pro&package=com.yral.android"
+    );
+    assert_eq!(urls.update_payment_url, urls.manage_url);
+    assert_eq!(urls.all_subscriptions_url, PLAY_SUBSCRIPTIONS_URL);
+
+    let urls = manage_urls(None, None).unwrap();
+    assert_eq!(urls.manage_url, PLAY_SUBSCRIPTIONS_URL);
+
+    assert!(manage_urls(Some("yral_pro"), None).is_err());
+    assert!(manage_urls(Some("yral_pro&sku=other"), Some("com.yral.android")).is_err());
+}
+
+#[tokio::test]
+async fn test_manage_url_route() {
+    let app = Router::new().route("/google/manage-url", get(get_manage_url));
+    let get_url = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let res = app
+        .clone()
+        .oneshot(get_url(
+            "/google/manage-url?product_id=yral_pro&package_name=com.yral.android",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(
+        response["data"]["payment_methods_url"],
+        "https://play.google.com/store/paymentmethods"
+    );
+
+    let res = app
+        .oneshot(get_url("/google/manage-url?package_name=com.yral.android"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
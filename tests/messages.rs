@@ -0,0 +1,78 @@
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::middleware;
+use axum::routing::get;
+use axum::Router;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppError;
+use yral_billing::messages::{localize_messages, negotiate_locale, Locale};
+use yral_billing::types::{ApiResponse, EmptyData, ErrorCode};
+
+async fn rate_limited() -> Result<(), AppError> {
+    Err(AppError::RateLimited {
+        retry_after_secs: 120,
+    })
+}
+
+async fn fetch(accept_language: Option<&str>) -> (Option<String>, ApiResponse<EmptyData>) {
+    let mut request = Request::builder().uri("/limited");
+    if let Some(accept_language) = accept_language {
+        request = request.header(header::ACCEPT_LANGUAGE, accept_language);
+    }
+    let res = Router::new()
+        .route("/limited", get(rate_limited))
+        .layer(middleware::from_fn(localize_messages))
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let language = res
+        .headers()
+        .get(header::CONTENT_LANGUAGE)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (language, serde_json::from_slice(&body).unwrap())
+}
+
+// The most preferred language we have wins, anything else gets English
+#[test]
+fn test_negotiate_locale() {
+    assert_eq!(negotiate_locale(None), Locale::En);
+    assert_eq!(negotiate_locale(Some("hi-IN,en;q=0.8")), Locale::Hi);
+    assert_eq!(
+        negotiate_locale(Some("fr-FR, hi;q=0.5, *;q=0.1")),
+        Locale::Hi
+    );
+    assert_eq!(negotiate_locale(Some("en;q=0.9, hi;q=0.4")), Locale::En);
+    assert_eq!(negotiate_locale(Some("hi;q=0")), Locale::En);
+    assert_eq!(negotiate_locale(Some("fr")), Locale::En);
+}
+
+// Errors are translated with their placeholders filled, the code stays the same
+#[tokio::test]
+async fn test_error_message_follows_accept_language() {
+    let (language, english) = fetch(None).await;
+    assert_eq!(language, None);
+    assert_eq!(
+        english.error.as_deref(),
+        Some("Too many requests, retry in 120 seconds")
+    );
+
+    let (language, hindi) = fetch(Some("hi-IN,hi;q=0.9,en;q=0.8")).await;
+    assert_eq!(language.as_deref(), Some("hi"));
+    assert_eq!(hindi.code, Some(ErrorCode::RateLimited));
+    let message = hindi.error.unwrap();
+    assert!(message.contains("120"), "{}", message);
+    assert!(!message.contains('{'), "{}", message);
+}
+
+// Every catalog entry is keyed by a real error code, see `error_responses` for the reverse
+#[test]
+fn test_catalog_keys_are_error_codes() {
+    for code in Locale::Hi.catalog().unwrap().keys() {
+        serde_json::from_value::<ErrorCode>(serde_json::Value::String(code.clone()))
+            .unwrap_or_else(|_| panic!("{} is not an error code", code));
+    }
+}
@@ -0,0 +1,95 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use chrono::Utc;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppError;
+use yral_billing::integrations::google_play::scenarios::MISMATCHED_ACCOUNT_ID;
+use yral_billing::integrations::google_play::{
+    GooglePlayApi, MockScenario, ScriptedGooglePlay, MOCK_SUBSCRIPTION_ACCOUNT_ID,
+};
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::purchase::verify_purchase;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{memory_state, TEST_PACKAGE_NAME};
+use yral_billing::types::SubscriptionState;
+
+// Purchases verified against the mock are stored as granted, not already expired
+#[tokio::test]
+async fn test_mock_verification_stores_future_expiry() {
+    let app_state = memory_state().await;
+    let purchase_token = format!("token_{}", uuid::Uuid::new_v4());
+    let body = serde_json::json!({
+        "user_id": MOCK_SUBSCRIPTION_ACCOUNT_ID,
+        "package_name": TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "purchase_token": purchase_token,
+    });
+    let res = Router::new()
+        .route("/google/verify", post(verify_purchase))
+        .with_state(app_state.clone())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/google/verify")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let stored: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&purchase_token))
+        .first(&mut app_state.get_db_connection().unwrap())
+        .unwrap();
+    assert!(stored.expiry_at > Utc::now().naive_utc() + chrono::Duration::days(29));
+}
+
+// Each scenario answers the way Google would in that situation
+#[tokio::test]
+async fn test_scripted_scenarios() {
+    let expiry = chrono::Duration::hours(1);
+    let fetch = |scenario| async move {
+        ScriptedGooglePlay::new(scenario, expiry)
+            .fetch_subscription(TEST_PACKAGE_NAME, "token")
+            .await
+    };
+
+    let active = fetch(MockScenario::Active).await.unwrap();
+    assert_eq!(active.subscription_state, SubscriptionState::Active);
+    let expiry_time = active.line_items[0].expiry_time.as_deref().unwrap();
+    let expiry_time = chrono::DateTime::parse_from_rfc3339(expiry_time).unwrap();
+    assert!(expiry_time > Utc::now() + chrono::Duration::minutes(59));
+
+    let expired = fetch(MockScenario::Expired).await.unwrap();
+    assert_eq!(expired.subscription_state, SubscriptionState::Expired);
+
+    let mismatched = fetch(MockScenario::AccountMismatch).await.unwrap();
+    assert_eq!(
+        mismatched
+            .external_account_identifiers
+            .unwrap()
+            .obfuscated_external_account_id
+            .as_deref(),
+        Some(MISMATCHED_ACCOUNT_ID)
+    );
+
+    assert!(matches!(
+        fetch(MockScenario::NotFound).await,
+        Err(AppError::GooglePlayTokenNotFound(_))
+    ));
+    assert!(matches!(
+        fetch(MockScenario::Throttled).await,
+        Err(AppError::GooglePlayThrottled { .. })
+    ));
+
+    assert_eq!(
+        MockScenario::parse("on_hold").unwrap(),
+        MockScenario::OnHold
+    );
+    assert!(MockScenario::parse("flaky").is_err());
+}
@@ -0,0 +1,144 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::Utc;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::model::OfferRedemption;
+use yral_billing::routes::offers::{get_offer_conversions, record_offer_redemptions};
+use yral_billing::routes::purchase::verify_purchase;
+use yral_billing::schema::offer_redemptions;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+    TEST_PACKAGE_NAME,
+};
+use yral_billing::types::VerifyRequest;
+use yral_billing::AppState;
+
+fn app(app_state: AppState) -> Router {
+    Router::new()
+        .route("/google/verify", post(verify_purchase))
+        .route("/admin/offers/conversions", get(get_offer_conversions))
+        .with_state(app_state)
+}
+
+async fn conversions(app_state: &AppState, query: &str) -> serde_json::Value {
+    let res = app(app_state.clone())
+        .oneshot(
+            Request::builder()
+                .uri(format!("/admin/offers/conversions?{}", query))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    response["data"].clone()
+}
+
+fn today_range() -> String {
+    let today = Utc::now().date_naive();
+    format!("from={}&to={}", today, today)
+}
+
+// Verifying a purchase bought through an offer records which offer converted
+#[tokio::test]
+async fn test_verify_records_offer_redemption() {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .offer("winback-50", &["winback", "half-price"])
+            .build(),
+    );
+
+    let payload = VerifyRequest {
+        user_id: MOCK_SUBSCRIPTION_ACCOUNT_ID.to_string(),
+        package_name: TEST_PACKAGE_NAME.to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: "token_1".to_string(),
+        integrity_token: None,
+    };
+    let res = app(app_state.clone())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/google/verify")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let redemptions: Vec<OfferRedemption> = offer_redemptions::table
+        .load(&mut app_state.get_db_connection().unwrap())
+        .unwrap();
+    assert_eq!(redemptions.len(), 1);
+    assert_eq!(redemptions[0].purchase_token, "token_1");
+    assert_eq!(redemptions[0].offer_id, "winback-50");
+    assert_eq!(
+        redemptions[0].base_plan_id.as_deref(),
+        Some("mock-base-plan")
+    );
+    assert_eq!(redemptions[0].offer_tags, "winback,half-price");
+}
+
+// Conversions are summarized per offer, optionally only for offers with a tag
+#[tokio::test]
+async fn test_conversions_summarized_per_offer() {
+    let app_state = memory_state().await;
+    let now = Utc::now().naive_utc();
+    {
+        let mut conn = app_state.get_db_connection().unwrap();
+        let winback = SubscriptionResponseBuilder::new()
+            .offer("winback-50", &["winback"])
+            .build();
+        let intro = SubscriptionResponseBuilder::new()
+            .offer("intro-trial", &[])
+            .build();
+        let regular = SubscriptionResponseBuilder::new().build();
+
+        for (user, response) in [
+            ("user_1", &winback),
+            ("user_2", &winback),
+            ("user_2", &winback),
+            ("user_3", &intro),
+            ("user_4", &regular),
+        ] {
+            let token = PurchaseTokenBuilder::new(user).insert(&mut conn);
+            record_offer_redemptions(&mut conn, &token.purchase_token, user, response, now)
+                .unwrap();
+            // A later grant of the same token isn't another conversion
+            record_offer_redemptions(&mut conn, &token.purchase_token, user, response, now)
+                .unwrap();
+        }
+    }
+
+    let all = conversions(&app_state, &today_range()).await;
+    let all = all.as_array().unwrap();
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[0]["offer_id"], "intro-trial");
+    assert_eq!(all[0]["conversions"], 1);
+    assert_eq!(all[0]["offer_tags"], serde_json::json!([]));
+    assert_eq!(all[1]["offer_id"], "winback-50");
+    assert_eq!(all[1]["product_id"], "mock-product-id");
+    assert_eq!(all[1]["conversions"], 3);
+    assert_eq!(all[1]["users"], 2);
+    assert_eq!(all[1]["offer_tags"], serde_json::json!(["winback"]));
+
+    let winback = conversions(&app_state, &format!("{}&tag=winback", today_range())).await;
+    let winback = winback.as_array().unwrap();
+    assert_eq!(winback.len(), 1);
+    assert_eq!(winback[0]["offer_id"], "winback-50");
+
+    let yesterday = (Utc::now() - chrono::Duration::days(1)).date_naive();
+    let none = conversions(&app_state, &format!("from={}&to={}", yesterday, yesterday)).await;
+    assert!(none.as_array().unwrap().is_empty());
+}
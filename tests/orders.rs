@@ -0,0 +1,225 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::model::{Order, RevenueEvent};
+use yral_billing::test_support::{test_app, test_user, VerifyRequestBuilder};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this order for every subscription
+const MOCK_ORDER_ID: &str = "GPA.0000-0000-0000-00000";
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_orders_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn get_export(app: Router, query: &str) -> axum::response::Response {
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!("/admin/orders/export?{}", query))
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+async fn get_stats(app: Router, query: &str) -> axum::response::Response {
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!("/admin/stats?{}", query))
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+fn today_range() -> String {
+    let today = chrono::Utc::now().date_naive();
+    format!("from={}&to={}", today, today)
+}
+
+// Verifying a purchase stores its order id and region
+#[tokio::test]
+async fn test_verify_records_order() {
+    use yral_billing::schema::orders::dsl;
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let order: Order = dsl::orders
+        .filter(dsl::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(order.order_id, MOCK_ORDER_ID);
+    assert_eq!(order.user_id, test_user("user_1"));
+    assert_eq!(order.region_code.as_deref(), Some("US"));
+}
+
+// Orders are exported as JSON by default and as CSV on request
+#[tokio::test]
+async fn test_export_orders() {
+    let _db_guard = TestDbGuard::new();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = get_export(test_app(AppState::new().await), &today_range()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"][0]["order_id"], MOCK_ORDER_ID);
+
+    let res = get_export(
+        test_app(AppState::new().await),
+        &format!("{}&format=csv", today_range()),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/csv; charset=utf-8");
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let csv = String::from_utf8(body_bytes.to_vec()).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("order_id,purchase_token,user_id,product_id,region_code,recorded_at")
+    );
+    assert!(lines.next().unwrap().starts_with(MOCK_ORDER_ID));
+}
+
+// A reversed date range is rejected
+#[tokio::test]
+async fn test_export_rejects_reversed_range() {
+    let _db_guard = TestDbGuard::new();
+
+    let res = get_export(
+        test_app(AppState::new().await),
+        "from=2026-03-10&to=2026-03-01",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+// Verifying a purchase stores the price it was charged at
+#[tokio::test]
+async fn test_verify_records_revenue_event() {
+    use yral_billing::schema::revenue_events::dsl;
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let event: RevenueEvent = dsl::revenue_events
+        .filter(dsl::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(event.order_id, MOCK_ORDER_ID);
+    assert_eq!(event.region_code.as_deref(), Some("US"));
+    assert_eq!(event.currency_code, "USD");
+    assert_eq!(event.price_micros, 4_990_000);
+    assert_eq!(event.base_plan_id.as_deref(), Some("mock-base-plan"));
+}
+
+// Revenue is summed per day, region and currency
+#[tokio::test]
+async fn test_admin_stats_revenue_totals() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let today = chrono::Utc::now().naive_utc();
+
+    for (order_id, region, currency, micros) in [
+        ("GPA.1", "IN", "INR", 199_000_000),
+        ("GPA.2", "IN", "INR", 199_000_000),
+        ("GPA.3", "US", "USD", 4_990_000),
+    ] {
+        let order = Order::new(
+            order_id.to_string(),
+            format!("token_{}", order_id),
+            "user_1".to_string(),
+            "yral_pro".to_string(),
+            Some(region.to_string()),
+            today,
+        );
+        let event = RevenueEvent::new(&order, None, None, currency.to_string(), micros);
+        diesel::insert_into(yral_billing::schema::revenue_events::table)
+            .values(&event)
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let res = get_stats(test_app(AppState::new().await), &today_range()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let revenue = response["data"]["revenue"].as_array().unwrap();
+    assert_eq!(revenue.len(), 2);
+    assert_eq!(revenue[0]["region_code"], "IN");
+    assert_eq!(revenue[0]["currency_code"], "INR");
+    assert_eq!(revenue[0]["orders"], 2);
+    assert_eq!(revenue[0]["total_micros"], 398_000_000);
+    assert_eq!(revenue[1]["region_code"], "US");
+    assert_eq!(revenue[1]["total_micros"], 4_990_000);
+
+    let res = get_stats(
+        test_app(AppState::new().await),
+        "from=2026-03-10&to=2026-03-01",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
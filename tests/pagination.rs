@@ -0,0 +1,102 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::db::pagination::{decode_cursor, into_page, page_limit, Cursor};
+use yral_billing::routes::admin::list_tokens;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{memory_state, PurchaseTokenBuilder};
+
+async fn get_json(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let res = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+#[test]
+fn test_page_parameters() {
+    assert_eq!(page_limit(None, 50, 200).unwrap(), 50);
+    assert_eq!(page_limit(Some(200), 50, 200).unwrap(), 200);
+    assert!(page_limit(Some(0), 50, 200).is_err());
+    assert!(page_limit(Some(201), 50, 200).is_err());
+
+    assert_eq!(decode_cursor(None, "token").unwrap(), None);
+    assert!(decode_cursor(Some("not-a-cursor"), "token").is_err());
+}
+
+// The extra row is dropped and the last row kept points to the next page
+#[test]
+fn test_rows_cut_to_page() {
+    let now = chrono::Utc::now().naive_utc();
+    let cursor_of = |id: &i32| Cursor {
+        at: now,
+        id: id.to_string(),
+    };
+
+    let (rows, next_cursor) = into_page(vec![1, 2, 3], 2, cursor_of);
+    assert_eq!(rows, vec![1, 2]);
+    assert_eq!(Cursor::decode(&next_cursor.unwrap()), Some(cursor_of(&2)));
+
+    let (rows, next_cursor) = into_page(vec![1, 2], 2, cursor_of);
+    assert_eq!(rows, vec![1, 2]);
+    assert_eq!(next_cursor, None);
+}
+
+// Following `next_cursor` lists every token once, most recently changed first
+#[tokio::test]
+async fn test_tokens_listed_page_by_page() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let start = chrono::Utc::now().naive_utc() - chrono::Duration::days(1);
+    let mut tokens: Vec<String> = (0..5)
+        .map(|i| {
+            let mut token = PurchaseTokenBuilder::new("user_1").build();
+            token.updated_at = start + chrono::Duration::minutes(i);
+            diesel::insert_into(purchase_tokens::table)
+                .values(&token)
+                .execute(&mut conn)
+                .unwrap();
+            token.id
+        })
+        .collect();
+    PurchaseTokenBuilder::new("user_2").insert(&mut conn);
+    tokens.reverse();
+
+    let app = Router::new()
+        .route("/admin/tokens", get(list_tokens))
+        .with_state(app_state.clone());
+
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let uri = match &cursor {
+            Some(cursor) => format!("/admin/tokens?user_id=user_1&limit=2&cursor={}", cursor),
+            None => "/admin/tokens?user_id=user_1&limit=2".to_string(),
+        };
+        let (status, body) = get_json(app.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let page = &body["data"];
+        assert_eq!(page["total_estimate"], 5);
+        for item in page["items"].as_array().unwrap() {
+            seen.push(item["id"].as_str().unwrap().to_string());
+        }
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(seen, tokens);
+
+    let (status, _) = get_json(app.clone(), "/admin/tokens?limit=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get_json(app, "/admin/tokens?cursor=not-a-cursor").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use base64::prelude::*;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::clock::TestClock;
+use yral_billing::error::AppResult;
+use yral_billing::integrations::google_play::{
+    GooglePlayApi, MockGooglePlay, MOCK_SUBSCRIPTION_ACCOUNT_ID,
+};
+use yral_billing::integrations::user_info::MockUserInfo;
+use yral_billing::jobs::access_outbox::drain_access_outbox;
+use yral_billing::metrics::Metrics;
+use yral_billing::model::{AccessOutboxEntry, PurchaseToken};
+use yral_billing::schema::{access_outbox, purchase_tokens};
+use yral_billing::test_support::{test_app, PurchaseTokenBuilder};
+use yral_billing::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+    OutboxAction, OutboxStatus, PausedStateContext, SubscriptionNotificationType,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_pause_schedule_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Mock Google Play reporting a pause from `paused_from` until `resumes_at`
+struct PausingGooglePlay {
+    paused_from: chrono::DateTime<chrono::Utc>,
+    resumes_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[async_trait]
+impl GooglePlayApi for PausingGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        let mut response = MockGooglePlay
+            .fetch_subscription(package_name, purchase_token)
+            .await?;
+        response.line_items[0].expiry_time = Some(self.paused_from.to_rfc3339());
+        response.paused_state_context = self.resumes_at.map(|resumes_at| PausedStateContext {
+            auto_resume_time: Some(resumes_at.to_rfc3339()),
+        });
+        Ok(response)
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: chrono::DateTime<chrono::Utc>,
+        desired_expiry: chrono::DateTime<chrono::Utc>,
+    ) -> AppResult<chrono::DateTime<chrono::Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+async fn post_pause_schedule_changed(app: Router, purchase_token: &str) {
+    let notification = serde_json::json!({
+        "version": "1.0",
+        "packageName": "com.example",
+        "eventTimeMillis": chrono::Utc::now().timestamp_millis().to_string(),
+        "subscriptionNotification": {
+            "version": "1.0",
+            "notificationType": SubscriptionNotificationType::PauseScheduleChanged,
+            "purchaseToken": purchase_token,
+            "subscriptionId": "mock-product-id",
+        }
+    });
+    let payload = serde_json::json!({
+        "message": {
+            "data": BASE64_STANDARD.encode(notification.to_string()),
+            "messageId": uuid::Uuid::new_v4().to_string(),
+            "publishTime": chrono::Utc::now().to_rfc3339(),
+        }
+    });
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/rtdn-webhook")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn load_token(conn: &mut SqliteConnection, token: &str) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(token))
+        .first(conn)
+        .unwrap()
+}
+
+fn outbox(conn: &mut SqliteConnection) -> Vec<AccessOutboxEntry> {
+    access_outbox::table
+        .order(access_outbox::run_after.asc())
+        .load(conn)
+        .unwrap()
+}
+
+// A scheduled pause is stored, shown in the entitlements and applied exactly in its window
+#[tokio::test]
+async fn test_pause_window_is_scheduled() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let paused_from = chrono::Utc::now() + chrono::Duration::days(3);
+    let resumes_at = paused_from + chrono::Duration::days(30);
+    let token = PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .expiry_at(paused_from.naive_utc())
+        .insert(&mut conn);
+
+    let mut app_state = AppState::new().await;
+    app_state.google_play = Arc::new(PausingGooglePlay {
+        paused_from,
+        resumes_at: Some(resumes_at),
+    });
+    let app = test_app(app_state);
+    post_pause_schedule_changed(app.clone(), &token.purchase_token).await;
+
+    let stored = load_token(&mut conn, &token.purchase_token);
+    assert_eq!(
+        stored.paused_from.map(|time| time.and_utc().timestamp()),
+        Some(paused_from.timestamp())
+    );
+    assert_eq!(
+        stored.resumes_at.map(|time| time.and_utc().timestamp()),
+        Some(resumes_at.timestamp())
+    );
+
+    let entries = outbox(&mut conn);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].action, OutboxAction::RevokePro);
+    assert_eq!(entries[1].action, OutboxAction::GrantPro);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/entitlements/{}", MOCK_USER_ID))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert!(response["data"]["paused_from"].is_string());
+    assert!(response["data"]["resumes_at"].is_string());
+
+    // Nothing happens before the pause, the revoke at its start and the restore at its end
+    let clock = TestClock::new(chrono::Utc::now());
+    let metrics = Metrics::new();
+    for (at, applied) in [
+        (paused_from - chrono::Duration::minutes(1), 0),
+        (paused_from + chrono::Duration::minutes(1), 1),
+        (resumes_at - chrono::Duration::minutes(1), 0),
+        (resumes_at + chrono::Duration::minutes(1), 1),
+    ] {
+        clock.set(at);
+        assert_eq!(
+            drain_access_outbox(&mut conn, &MockUserInfo, &clock, &metrics, 10)
+                .await
+                .unwrap(),
+            applied
+        );
+    }
+    assert!(outbox(&mut conn)
+        .iter()
+        .all(|entry| entry.status == OutboxStatus::Done));
+}
+
+// A pause the user called off clears the window and its scheduled changes
+#[tokio::test]
+async fn test_removed_pause_is_cleared() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let paused_from = chrono::Utc::now() + chrono::Duration::days(3);
+    let token = PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .expiry_at(paused_from.naive_utc())
+        .insert(&mut conn);
+
+    let mut app_state = AppState::new().await;
+    app_state.google_play = Arc::new(PausingGooglePlay {
+        paused_from,
+        resumes_at: Some(paused_from + chrono::Duration::days(30)),
+    });
+    post_pause_schedule_changed(test_app(app_state.clone()), &token.purchase_token).await;
+    assert_eq!(outbox(&mut conn).len(), 2);
+
+    app_state.google_play = Arc::new(PausingGooglePlay {
+        paused_from,
+        resumes_at: None,
+    });
+    let app = test_app(app_state);
+    post_pause_schedule_changed(app, &token.purchase_token).await;
+
+    let stored = load_token(&mut conn, &token.purchase_token);
+    assert!(stored.paused_from.is_none());
+    assert!(stored.resumes_at.is_none());
+    assert!(outbox(&mut conn).is_empty());
+}
@@ -0,0 +1,544 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::feature_flags::QUEUE_GRANTS_ON_IC_OUTAGE;
+use yral_billing::integrations::google_play::{
+    GooglePlayApi, MockGooglePlay, MOCK_SUBSCRIPTION_ACCOUNT_ID,
+};
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::jobs::access_outbox::drain_access_outbox;
+use yral_billing::jobs::acknowledgments::AckStrategies;
+use yral_billing::jobs::pending_purchases::{
+    reconcile_pending_purchases, reverify_purchase_token, ReverifyOutcome,
+};
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::rtdn::handle_new_subscription_purchase;
+use yral_billing::schema::{access_outbox, purchase_tokens};
+use yral_billing::test_support::{test_app, test_user, VerifyRequestBuilder};
+use yral_billing::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription,
+    OutboxAction, Plan, PurchaseTokenStatus, SubscriptionState,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Mock Google Play whose subscription state can be changed during a test
+#[derive(Clone)]
+struct SwitchableGooglePlay {
+    state: Arc<Mutex<SubscriptionState>>,
+}
+
+impl SwitchableGooglePlay {
+    fn new(state: SubscriptionState) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn set_state(&self, state: SubscriptionState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+#[async_trait]
+impl GooglePlayApi for SwitchableGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        let mut response = MockGooglePlay
+            .fetch_subscription(package_name, purchase_token)
+            .await?;
+        response.subscription_state = *self.state.lock().unwrap();
+        // Expiry in the future so the completed grant is active
+        response.line_items[0].expiry_time =
+            Some((chrono::Utc::now() + chrono::Duration::days(30)).to_rfc3339());
+        Ok(response)
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: chrono::DateTime<chrono::Utc>,
+        desired_expiry: chrono::DateTime<chrono::Utc>,
+    ) -> AppResult<chrono::DateTime<chrono::Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+/// IC client whose Pro grants fail until `recover` is called
+#[derive(Clone, Default)]
+struct FlakyUserInfo {
+    recovered: Arc<AtomicBool>,
+}
+
+impl FlakyUserInfo {
+    fn recover(&self) {
+        self.recovered.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl UserInfoApi for FlakyUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        if !self.recovered.load(Ordering::SeqCst) {
+            return Err(AppError::ServiceAccessFailed("unreachable".to_string()));
+        }
+        MockUserInfo.grant_pro_plan(product_id, user_id).await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_pending_purchases_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn app_state_with(google_play: &SwitchableGooglePlay) -> AppState {
+    let mut app_state = AppState::new().await;
+    app_state.google_play = Arc::new(google_play.clone());
+    app_state
+}
+
+fn load_token(conn: &mut SqliteConnection, token: &str) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(token))
+        .first(conn)
+        .unwrap()
+}
+
+// A pending payment is stored without access and granted by reconciliation once it completes
+#[tokio::test]
+async fn test_pending_purchase_granted_by_reconciliation() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Pending);
+    let app_state = app_state_with(&google_play).await;
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state.clone()).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let stored = load_token(&mut conn, &token);
+    assert_eq!(stored.status, PurchaseTokenStatus::Pending);
+    assert_eq!(stored.package_name.as_deref(), Some("com.example"));
+
+    // Still pending, nothing changes
+    let granted = reconcile_pending_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        app_state.provisional_purchase_timeout,
+    )
+    .await
+    .unwrap();
+    assert_eq!(granted, 0);
+    assert_eq!(
+        load_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Pending
+    );
+
+    google_play.set_state(SubscriptionState::Active);
+    let granted = reconcile_pending_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        app_state.provisional_purchase_timeout,
+    )
+    .await
+    .unwrap();
+    assert_eq!(granted, 1);
+
+    let completed = load_token(&mut conn, &token);
+    assert_eq!(completed.status, PurchaseTokenStatus::AccessGranted);
+    assert!(completed.expiry_at > chrono::Utc::now().naive_utc());
+    assert!(completed.acknowledged_at.is_some());
+}
+
+// The RTDN purchase notification completes a pending purchase
+#[tokio::test]
+async fn test_pending_purchase_granted_by_rtdn() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Pending);
+    let app_state = app_state_with(&google_play).await;
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state.clone()).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    google_play.set_state(SubscriptionState::Active);
+    let subscription_response = google_play
+        .fetch_subscription("com.example", &token)
+        .await
+        .unwrap();
+    handle_new_subscription_purchase(
+        &mut app_state.get_db_connection().unwrap(),
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        true,
+        "com.example",
+        MOCK_SUBSCRIPTION_ACCOUNT_ID,
+        &token,
+        &subscription_response,
+        chrono::Utc::now().naive_utc(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        load_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
+
+// A pending payment that gets canceled never grants access
+#[tokio::test]
+async fn test_canceled_pending_purchase_expires() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Pending);
+    let app_state = app_state_with(&google_play).await;
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    test_app(app_state.clone()).oneshot(req).await.unwrap();
+
+    google_play.set_state(SubscriptionState::PendingPurchaseCanceled);
+    let granted = reconcile_pending_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        app_state.provisional_purchase_timeout,
+    )
+    .await
+    .unwrap();
+    assert_eq!(granted, 0);
+    assert_eq!(
+        load_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+}
+
+// Re-verifying one token follows Google through pending, active and canceled
+#[tokio::test]
+async fn test_reverify_purchase_token() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Pending);
+    let app_state = app_state_with(&google_play).await;
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    test_app(app_state.clone()).oneshot(req).await.unwrap();
+
+    let outcome = reverify_purchase_token(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        &token,
+    )
+    .await
+    .unwrap();
+    assert_eq!(outcome, ReverifyOutcome::StillPending);
+
+    google_play.set_state(SubscriptionState::Active);
+    let outcome = reverify_purchase_token(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        &token,
+    )
+    .await
+    .unwrap();
+    assert_eq!(outcome, ReverifyOutcome::Granted);
+    assert_eq!(
+        load_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    // Canceled keeps access until the paid period ends
+    google_play.set_state(SubscriptionState::Canceled);
+    let outcome = reverify_purchase_token(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        &token,
+    )
+    .await
+    .unwrap();
+    let stored = load_token(&mut conn, &token);
+    assert_eq!(
+        outcome,
+        ReverifyOutcome::Ended {
+            expiry_at: stored.expiry_at
+        }
+    );
+    assert_eq!(stored.status, PurchaseTokenStatus::AccessGranted);
+    assert!(!stored.auto_renewing);
+    assert!(stored.expiry_at > chrono::Utc::now().naive_utc());
+
+    assert!(reverify_purchase_token(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &MockUserInfo,
+        app_state.clock.as_ref(),
+        "unknown-token",
+    )
+    .await
+    .is_err());
+}
+
+// A verify that fails after storing the token leaves it `Pending`, the retry resumes that row
+#[tokio::test]
+async fn test_failed_grant_is_resumed_by_next_verify() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Active);
+    let user_info = FlakyUserInfo::default();
+    let mut app_state = app_state_with(&google_play).await;
+    app_state.user_info = Arc::new(user_info.clone());
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state.clone()).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let stuck = load_token(&mut conn, &token);
+    assert_eq!(stuck.status, PurchaseTokenStatus::Pending);
+    assert!(stuck.expiry_at > chrono::Utc::now().naive_utc());
+    assert!(stuck.acknowledged_at.is_none());
+
+    user_info.recover();
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let completed = load_token(&mut conn, &token);
+    assert_eq!(completed.id, stuck.id);
+    assert_eq!(completed.status, PurchaseTokenStatus::AccessGranted);
+    assert!(completed.acknowledged_at.is_some());
+}
+
+// While the IC is down a confirmed payment is accepted, acknowledged and granted through the outbox
+#[tokio::test]
+async fn test_grant_queued_during_ic_outage() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google_play = SwitchableGooglePlay::new(SubscriptionState::Active);
+    let user_info = FlakyUserInfo::default();
+    let mut app_state = app_state_with(&google_play).await;
+    app_state.user_info = Arc::new(user_info.clone());
+    app_state
+        .feature_flags
+        .set(
+            &mut conn,
+            QUEUE_GRANTS_ON_IC_OUTAGE,
+            true,
+            chrono::Utc::now().naive_utc(),
+        )
+        .unwrap();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state.clone()).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "GRANT_QUEUED");
+
+    let queued = load_token(&mut conn, &token);
+    assert_eq!(queued.status, PurchaseTokenStatus::Pending);
+    assert!(queued.acknowledged_at.is_some());
+
+    // Verifying again doesn't queue a second grant
+    let req = VerifyRequestBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .request();
+    let res = test_app(app_state.clone()).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let grants: i64 = access_outbox::table
+        .filter(access_outbox::purchase_token.eq(&token))
+        .filter(access_outbox::action.eq(OutboxAction::GrantPro))
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(grants, 1);
+
+    user_info.recover();
+    let applied = drain_access_outbox(
+        &mut conn,
+        &user_info,
+        app_state.clock.as_ref(),
+        &app_state.metrics,
+        100,
+    )
+    .await
+    .unwrap();
+    assert_eq!(applied, 1);
+
+    reconcile_pending_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &user_info,
+        app_state.clock.as_ref(),
+        app_state.provisional_purchase_timeout,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        load_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
@@ -0,0 +1,208 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::feature_flags::{PLAY_INTEGRITY_CHECK, PLAY_INTEGRITY_REQUIRED};
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::integrations::play_integrity::{IntegrityVerdict, PlayIntegrityApi};
+use yral_billing::test_support::{test_app, VerifyRequestBuilder};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+const PACKAGE_NAME: &str = "com.example";
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_play_integrity_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Returns the trusted verdict for tokens named `trusted`, a rooted device otherwise
+#[derive(Default)]
+struct FakePlayIntegrity {
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl PlayIntegrityApi for FakePlayIntegrity {
+    async fn decode_integrity_token(
+        &self,
+        package_name: &str,
+        integrity_token: &str,
+    ) -> AppResult<IntegrityVerdict> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let mut verdict = IntegrityVerdict::trusted(package_name, chrono::Utc::now().naive_utc());
+        if integrity_token != "trusted" {
+            verdict.device_integrity.device_recognition_verdict.clear();
+        }
+        Ok(verdict)
+    }
+}
+
+async fn test_state(play_integrity: &Arc<FakePlayIntegrity>) -> AppState {
+    let mut app_state = AppState::new().await;
+    app_state.play_integrity = play_integrity.clone();
+    app_state
+}
+
+async fn error_code(res: axum::response::Response) -> serde_json::Value {
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    response["code"].clone()
+}
+
+// The check is off by default, tokens aren't even decoded
+#[tokio::test]
+async fn test_integrity_ignored_while_flag_off() {
+    let _db_guard = TestDbGuard::new();
+    let play_integrity = Arc::new(FakePlayIntegrity::default());
+    let app = test_app(test_state(&play_integrity).await);
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .integrity_token(Some("rooted"))
+        .request();
+    let res = app.oneshot(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(play_integrity.calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_untrusted_device_rejected() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let play_integrity = Arc::new(FakePlayIntegrity::default());
+    let app_state = test_state(&play_integrity).await;
+    app_state
+        .feature_flags
+        .set(
+            &mut conn,
+            PLAY_INTEGRITY_CHECK,
+            true,
+            chrono::Utc::now().naive_utc(),
+        )
+        .unwrap();
+    let app = test_app(app_state);
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .integrity_token(Some("rooted"))
+        .request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "INTEGRITY_CHECK_FAILED");
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .integrity_token(Some("trusted"))
+        .request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(play_integrity.calls.load(Ordering::SeqCst), 2);
+}
+
+// Older app versions without a token keep working until the token is required
+#[tokio::test]
+async fn test_missing_token_rejected_only_when_required() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let play_integrity = Arc::new(FakePlayIntegrity::default());
+    let app_state = test_state(&play_integrity).await;
+    app_state
+        .feature_flags
+        .set(&mut conn, PLAY_INTEGRITY_CHECK, true, now)
+        .unwrap();
+    let app = test_app(app_state.clone());
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    app_state
+        .feature_flags
+        .set(&mut conn, PLAY_INTEGRITY_REQUIRED, true, now)
+        .unwrap();
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "INTEGRITY_CHECK_FAILED");
+    assert_eq!(play_integrity.calls.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_verdict_checks() {
+    let now = chrono::Utc::now().naive_utc();
+
+    assert!(IntegrityVerdict::trusted(PACKAGE_NAME, now)
+        .check(PACKAGE_NAME, now)
+        .is_ok());
+
+    // Token requested by another app
+    assert!(matches!(
+        IntegrityVerdict::trusted("com.other", now).check(PACKAGE_NAME, now),
+        Err(AppError::IntegrityCheckFailed(_))
+    ));
+
+    // Replayed token
+    let stale = IntegrityVerdict::trusted(PACKAGE_NAME, now - chrono::Duration::hours(1));
+    assert!(matches!(
+        stale.check(PACKAGE_NAME, now),
+        Err(AppError::IntegrityCheckFailed(_))
+    ));
+
+    // Sideloaded build
+    let mut sideloaded = IntegrityVerdict::trusted(PACKAGE_NAME, now);
+    sideloaded.app_integrity.app_recognition_verdict = Some("UNRECOGNIZED_VERSION".to_string());
+    assert!(matches!(
+        sideloaded.check(PACKAGE_NAME, now),
+        Err(AppError::IntegrityCheckFailed(_))
+    ));
+
+    // Basic integrity alone isn't enough
+    let mut emulator = IntegrityVerdict::trusted(PACKAGE_NAME, now);
+    emulator.device_integrity.device_recognition_verdict =
+        vec!["MEETS_BASIC_INTEGRITY".to_string()];
+    assert!(matches!(
+        emulator.check(PACKAGE_NAME, now),
+        Err(AppError::IntegrityCheckFailed(_))
+    ));
+}
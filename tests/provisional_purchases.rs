@@ -0,0 +1,197 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::jobs::pending_purchases::reconcile_pending_purchases;
+use yral_billing::model::PurchaseToken;
+use yral_billing::profiles::register_profile;
+use yral_billing::queues::check_queues;
+use yral_billing::schema::{purchase_tokens, subscriptions};
+use yral_billing::test_support::{
+    as_user, memory_state, new_test_user, test_app, FixedGooglePlay, RtdnBuilder,
+    SubscriptionResponseBuilder, TEST_PACKAGE_NAME,
+};
+use yral_billing::types::{
+    ExternalAccountIdentifiers, GooglePlaySubscriptionResponse, PurchaseTokenStatus,
+    SubscriptionNotificationType, SubscriptionState,
+};
+use yral_billing::AppState;
+
+/// Purchase in `state` that Google reports with only the obfuscated profile id `profile_1`
+fn profile_only_response(state: SubscriptionState) -> GooglePlaySubscriptionResponse {
+    let mut response = SubscriptionResponseBuilder::new()
+        .state(state)
+        .account_id(None)
+        .build();
+    response.external_account_identifiers = Some(ExternalAccountIdentifiers {
+        external_account_id: None,
+        obfuscated_external_account_id: None,
+        obfuscated_external_profile_id: Some("profile_1".to_string()),
+    });
+    response
+}
+
+/// State where Google reports purchases with only the obfuscated profile id `profile_1`
+async fn profile_only_state() -> AppState {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(profile_only_response(SubscriptionState::Active));
+    app_state
+}
+
+/// Owner of the `subscriptions` row mirroring `token_1`, if there is one
+fn mirrored_owner(app_state: &AppState) -> Option<String> {
+    subscriptions::table
+        .filter(subscriptions::source_ref.eq("token_1"))
+        .select(subscriptions::user_id)
+        .first(&mut app_state.get_db_connection().unwrap())
+        .optional()
+        .unwrap()
+}
+
+async fn deliver_purchase(app_state: &AppState) {
+    let res = test_app(app_state.clone())
+        .oneshot(
+            RtdnBuilder::subscription(SubscriptionNotificationType::Purchased, "token_1").request(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn stored(app_state: &AppState) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq("token_1"))
+        .first(&mut app_state.get_db_connection().unwrap())
+        .unwrap()
+}
+
+async fn reconcile(app_state: &AppState) -> usize {
+    reconcile_pending_purchases(
+        &mut app_state.get_db_connection().unwrap(),
+        app_state.google_play.as_ref(),
+        &app_state.ack_strategies,
+        app_state.user_info.as_ref(),
+        app_state.clock.as_ref(),
+        app_state.provisional_purchase_timeout,
+    )
+    .await
+    .unwrap()
+}
+
+async fn verify(app_state: &AppState, user: &str) -> (StatusCode, serde_json::Value) {
+    let body = serde_json::json!({
+        "user_id": user,
+        "package_name": TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "purchase_token": "token_1",
+    });
+    let res = as_user(test_app(app_state.clone()), user)
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/google/verify")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// A purchase whose owner RTDN can't tell waits provisionally, and is granted once it can
+#[tokio::test]
+async fn test_provisional_purchase_granted_once_owner_known() {
+    let app_state = profile_only_state().await;
+    let user = new_test_user();
+
+    deliver_purchase(&app_state).await;
+    let provisional = stored(&app_state);
+    assert_eq!(provisional.status, PurchaseTokenStatus::Pending);
+    assert!(provisional.provisional_since.is_some());
+    assert_eq!(provisional.user_id, "");
+    assert_eq!(mirrored_owner(&app_state), None);
+
+    // A redelivery is acknowledged without a second row
+    deliver_purchase(&app_state).await;
+    assert_eq!(reconcile(&app_state).await, 0);
+    assert!(stored(&app_state).provisional_since.is_some());
+
+    let now = Utc::now().naive_utc();
+    let mut conn = app_state.get_db_connection().unwrap();
+    let stalled = check_queues(&mut conn, &app_state.metrics, now + Duration::hours(7)).unwrap();
+    assert!(stalled
+        .iter()
+        .any(|queue| queue.queue == "provisional_purchases" && queue.pending == 1));
+
+    register_profile(&mut conn, "profile_1", &user, now).unwrap();
+    assert_eq!(reconcile(&app_state).await, 1);
+    let granted = stored(&app_state);
+    assert_eq!(granted.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(granted.user_id, user);
+    assert!(granted.provisional_since.is_none());
+    assert_eq!(mirrored_owner(&app_state), Some(user.clone()));
+    let stalled = check_queues(&mut conn, &app_state.metrics, now + Duration::hours(7)).unwrap();
+    assert!(stalled
+        .iter()
+        .all(|queue| queue.queue != "provisional_purchases"));
+}
+
+// A verify for a provisional purchase claims it for the account Google's ids resolve to, and
+// only that account may claim it
+#[tokio::test]
+async fn test_verify_claims_provisional_purchase() {
+    let app_state = profile_only_state().await;
+    let user = new_test_user();
+    deliver_purchase(&app_state).await;
+    let (status, body) = verify(&app_state, &user).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "EXTERNAL_ACCOUNT_IDENTIFIERS_MISSING");
+    register_profile(
+        &mut app_state.get_db_connection().unwrap(),
+        "profile_1",
+        &user,
+        Utc::now().naive_utc(),
+    )
+    .unwrap();
+
+    let (status, body) = verify(&app_state, &new_test_user()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "TOKEN_ALREADY_USED");
+    assert!(stored(&app_state).provisional_since.is_some());
+
+    assert_eq!(verify(&app_state, &user).await.0, StatusCode::OK);
+    let claimed = stored(&app_state);
+    assert_eq!(claimed.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(claimed.user_id, user);
+    assert!(claimed.provisional_since.is_none());
+}
+
+// A provisional purchase whose subscription ends before anyone claims it is expired and leaves
+// the queue
+#[tokio::test]
+async fn test_unclaimed_provisional_purchase_expires() {
+    let mut app_state = memory_state().await;
+    let google_play = FixedGooglePlay::new(profile_only_response(SubscriptionState::Active));
+    app_state.google_play = google_play.clone();
+    deliver_purchase(&app_state).await;
+
+    google_play.set(profile_only_response(SubscriptionState::Expired));
+    assert_eq!(reconcile(&app_state).await, 0);
+    let expired = stored(&app_state);
+    assert_eq!(expired.status, PurchaseTokenStatus::Expired);
+    assert!(expired.provisional_since.is_some());
+    assert_eq!(mirrored_owner(&app_state), None);
+
+    let now = Utc::now().naive_utc();
+    let mut conn = app_state.get_db_connection().unwrap();
+    let stalled = check_queues(&mut conn, &app_state.metrics, now + Duration::hours(7)).unwrap();
+    assert!(stalled
+        .iter()
+        .all(|queue| queue.queue != "provisional_purchases"));
+}
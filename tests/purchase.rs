@@ -3,18 +3,12 @@ use axum::http::{Request, StatusCode};
 use axum::Router;
 use tower::ServiceExt; // for `oneshot`
 use uuid;
-use yral_billing::routes::purchase::verify_purchase;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::request_limits::DEFAULT_JSON_BODY_LIMIT;
+use yral_billing::test_support::{as_user, new_test_user, test_app, test_user};
 use yral_billing::types::VerifyRequest;
 use yral_billing::AppState;
 
-// Helper function to create a test router with mock state
-async fn create_test_app() -> Router {
-    let app_state = AppState::new().await;
-    Router::new()
-        .route("/verify", axum::routing::post(verify_purchase))
-        .with_state(app_state)
-}
-
 // Helper struct to ensure test database cleanup
 struct TestDbGuard {
     db_path: String,
@@ -66,17 +60,18 @@ async fn test_verify_purchase_route() {
     // Set up test database with automatic cleanup
     let _db_guard = TestDbGuard::new();
 
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
 
     let payload = VerifyRequest {
-        user_id: format!("test_user_{}", uuid::Uuid::new_v4()),
+        user_id: new_test_user(),
         package_name: "com.example".to_string(),
         product_id: "mock-product-id".to_string(),
         purchase_token: format!("test_token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
     };
     let req = Request::builder()
         .method("POST")
-        .uri("/verify")
+        .uri("/google/verify")
         .header("content-type", "application/json")
         .body(Body::from(serde_json::to_vec(&payload).unwrap()))
         .unwrap();
@@ -92,12 +87,12 @@ async fn test_verify_purchase_route() {
 async fn test_purchase_token_reuse_prevention() {
     use yral_billing::model::PurchaseToken;
     use yral_billing::schema::purchase_tokens;
-    use yral_billing::types::PurchaseTokenStatus;
+    use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
 
     // Set up test database with automatic cleanup
     let db_guard = TestDbGuard::new();
 
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
 
     // Use unique token per test to avoid conflicts
     let shared_token = format!("shared_token_{}", uuid::Uuid::new_v4());
@@ -107,10 +102,11 @@ async fn test_purchase_token_reuse_prevention() {
     let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
     let expiry_at = (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc();
     let new_token = PurchaseToken::new(
-        "user_1".to_string(),
+        test_user("user_1"),
         shared_token.clone(),
         expiry_at,
         PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
     );
     let _ = diesel::insert_into(purchase_tokens::table)
         .values(&new_token)
@@ -118,15 +114,16 @@ async fn test_purchase_token_reuse_prevention() {
 
     // Now test: Second user attempts to use the same purchase token
     let payload_user2 = VerifyRequest {
-        user_id: "user_2".to_string(),
+        user_id: test_user("user_2"),
         package_name: "com.example".to_string(),
         product_id: "test_product".to_string(),
         purchase_token: shared_token.clone(),
+        integrity_token: None,
     };
 
     let req2 = Request::builder()
         .method("POST")
-        .uri("/verify")
+        .uri("/google/verify")
         .header("content-type", "application/json")
         .body(Body::from(serde_json::to_vec(&payload_user2).unwrap()))
         .unwrap();
@@ -141,6 +138,10 @@ async fn test_purchase_token_reuse_prevention() {
         .unwrap();
     let body_str = String::from_utf8(body_bytes.to_vec()).unwrap();
     assert!(body_str.contains("Purchase token already used by different user"));
+
+    // Clients branch on the stable error code rather than the message
+    let response: serde_json::Value = serde_json::from_str(&body_str).unwrap();
+    assert_eq!(response["code"], "TOKEN_ALREADY_USED");
     // Database cleanup handled automatically by TestDbGuard
 }
 
@@ -148,16 +149,16 @@ async fn test_purchase_token_reuse_prevention() {
 async fn test_same_user_same_token_allowed() {
     use yral_billing::model::PurchaseToken;
     use yral_billing::schema::purchase_tokens;
-    use yral_billing::types::PurchaseTokenStatus;
+    use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
 
     // Set up test database with automatic cleanup
     let db_guard = TestDbGuard::new();
 
-    let app = create_test_app().await;
+    let app = test_app(AppState::new().await);
 
     // Use unique token per test to avoid conflicts
     let token = format!("user_token_{}", uuid::Uuid::new_v4());
-    let user_id = format!("user_{}", uuid::Uuid::new_v4());
+    let user_id = new_test_user();
 
     // Manually insert a token for the user to simulate a previous successful verification
     use diesel::prelude::*;
@@ -168,6 +169,7 @@ async fn test_same_user_same_token_allowed() {
         token.clone(),
         expiry_at,
         PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
     );
     let _ = diesel::insert_into(purchase_tokens::table)
         .values(&new_token)
@@ -179,11 +181,12 @@ async fn test_same_user_same_token_allowed() {
         package_name: "com.example".to_string(),
         product_id: "test_product".to_string(),
         purchase_token: token.clone(),
+        integrity_token: None,
     };
 
     let req = Request::builder()
         .method("POST")
-        .uri("/verify")
+        .uri("/google/verify")
         .header("content-type", "application/json")
         .body(Body::from(serde_json::to_vec(&payload).unwrap()))
         .unwrap();
@@ -192,3 +195,308 @@ async fn test_same_user_same_token_allowed() {
     let res = app.oneshot(req).await.unwrap();
     assert_eq!(res.status(), StatusCode::OK);
 }
+
+// The user id is compared as a principal, whatever casing or whitespace the client sent
+#[tokio::test]
+async fn test_verify_canonicalizes_user_id() {
+    use diesel::prelude::*;
+    use yral_billing::model::PurchaseToken;
+    use yral_billing::schema::purchase_tokens;
+    use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+
+    let db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let user_id = test_user("user_1");
+    let token = format!("user_token_{}", uuid::Uuid::new_v4());
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    diesel::insert_into(purchase_tokens::table)
+        .values(&PurchaseToken::new(
+            user_id.clone(),
+            token.clone(),
+            (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc(),
+            PurchaseTokenStatus::AccessGranted,
+            PurchaseEnvironment::Production,
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+    let post = |user_id: String| {
+        let payload = VerifyRequest {
+            user_id,
+            package_name: "com.example".to_string(),
+            product_id: "test_product".to_string(),
+            purchase_token: token.clone(),
+            integrity_token: None,
+        };
+        Request::builder()
+            .method("POST")
+            .uri("/google/verify")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+            .unwrap()
+    };
+
+    let res = app
+        .clone()
+        .oneshot(post(format!(" {}\n", user_id.to_uppercase())))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app.oneshot(post("user_1".to_string())).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "BAD_REQUEST");
+}
+
+#[tokio::test]
+async fn test_verify_rejects_non_json_content_type() {
+    let _db_guard = TestDbGuard::new();
+
+    let app = test_app(AppState::new().await);
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify")
+        .header("content-type", "text/plain")
+        .body(Body::from("hello"))
+        .unwrap();
+
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "UNSUPPORTED_MEDIA_TYPE");
+}
+
+#[tokio::test]
+async fn test_verify_rejects_oversized_body() {
+    let _db_guard = TestDbGuard::new();
+
+    let app = test_app(AppState::new().await);
+
+    let payload = serde_json::json!({
+        "user_id": "x".repeat(DEFAULT_JSON_BODY_LIMIT + 1),
+        "package_name": "com.example",
+        "product_id": "mock-product-id",
+        "purchase_token": "token",
+    });
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "PAYLOAD_TOO_LARGE");
+}
+
+#[tokio::test]
+async fn test_verify_rejects_unknown_fields() {
+    let _db_guard = TestDbGuard::new();
+
+    let app = test_app(AppState::new().await);
+
+    let payload = serde_json::json!({
+        "user_id": "user",
+        "package_name": "com.example",
+        "product_id": "mock-product-id",
+        "purchase_token": "token",
+        "unexpected": true,
+    });
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+/// Preview `payload` with the JWT claims of `caller`
+async fn post_preview(
+    app: Router,
+    caller: &str,
+    payload: &VerifyRequest,
+) -> (StatusCode, serde_json::Value) {
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify/preview")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(payload).unwrap()))
+        .unwrap();
+
+    let res = as_user(app, caller).oneshot(req).await.unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// Preview reports the grant verify would make without acknowledging or storing anything
+#[tokio::test]
+async fn test_preview_does_not_write() {
+    use diesel::prelude::*;
+    use yral_billing::schema::purchase_tokens;
+
+    let db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let payload = VerifyRequest {
+        user_id: MOCK_SUBSCRIPTION_ACCOUNT_ID.to_string(),
+        package_name: "com.example".to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: format!("test_token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
+    };
+
+    let (status, response) = post_preview(app, &payload.user_id, &payload).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["outcome"], "would_grant");
+    assert_eq!(
+        response["data"]["grant_to_user_id"],
+        MOCK_SUBSCRIPTION_ACCOUNT_ID
+    );
+    assert_eq!(response["data"]["would_acknowledge"], true);
+
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let stored: i64 = purchase_tokens::table
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(stored, 0);
+}
+
+// Preview of a token the caller already holds reports the existing grant
+#[tokio::test]
+async fn test_preview_already_granted() {
+    use diesel::prelude::*;
+    use yral_billing::model::PurchaseToken;
+    use yral_billing::schema::purchase_tokens;
+    use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+
+    let db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let payload = VerifyRequest {
+        user_id: test_user("user_1"),
+        package_name: "com.example".to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: format!("test_token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
+    };
+
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let token = PurchaseToken::new(
+        payload.user_id.clone(),
+        payload.purchase_token.clone(),
+        (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc(),
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&token)
+        .execute(&mut conn)
+        .unwrap();
+
+    let (status, response) = post_preview(app, &payload.user_id, &payload).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["outcome"], "already_granted");
+    assert_eq!(response["data"]["grant_to_user_id"], test_user("user_1"));
+    assert_eq!(response["data"]["would_acknowledge"], false);
+}
+
+// Callers preview only their own purchases and don't learn which other account one grants to
+#[tokio::test]
+async fn test_preview_hides_other_accounts() {
+    let _db_guard = TestDbGuard::new();
+    let user = new_test_user();
+    let payload = VerifyRequest {
+        user_id: user.clone(),
+        package_name: "com.example".to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: format!("test_token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
+    };
+
+    let (status, response) =
+        post_preview(test_app(AppState::new().await), &new_test_user(), &payload).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(response["code"], "NOT_OWNER");
+
+    // The mock purchase belongs to another Google account
+    let (status, response) = post_preview(test_app(AppState::new().await), &user, &payload).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["outcome"], "would_grant");
+    assert!(response["data"]["grant_to_user_id"].is_null());
+}
+
+// Verifying a lapsed token again updates its row, keeping its id and the RTDN order it saw
+#[tokio::test]
+async fn test_verify_keeps_stored_token_row() {
+    use diesel::prelude::*;
+    use yral_billing::model::PurchaseToken;
+    use yral_billing::schema::purchase_tokens;
+    use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+
+    let db_guard = TestDbGuard::new();
+    let app = test_app(AppState::new().await);
+
+    let payload = VerifyRequest {
+        user_id: MOCK_SUBSCRIPTION_ACCOUNT_ID.to_string(),
+        package_name: "com.example".to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: format!("test_token_{}", uuid::Uuid::new_v4()),
+        integrity_token: None,
+    };
+
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let now = chrono::Utc::now().naive_utc();
+    let mut lapsed = PurchaseToken::new(
+        payload.user_id.clone(),
+        payload.purchase_token.clone(),
+        now - chrono::Duration::days(1),
+        PurchaseTokenStatus::Expired,
+        PurchaseEnvironment::Production,
+    );
+    lapsed.last_event_time = Some(now - chrono::Duration::hours(1));
+    diesel::insert_into(purchase_tokens::table)
+        .values(&lapsed)
+        .execute(&mut conn)
+        .unwrap();
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let stored: Vec<PurchaseToken> = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&payload.purchase_token))
+        .load(&mut conn)
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, lapsed.id);
+    assert_eq!(stored[0].status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(stored[0].last_event_time, lapsed.last_event_time);
+}
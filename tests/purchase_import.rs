@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use uuid;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::google_play::{GooglePlayApi, MockGooglePlay};
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::jobs::acknowledgments::AckStrategies;
+use yral_billing::jobs::purchase_import::{import_purchases, parse_import_file, ImportReport};
+use yral_billing::model::PurchaseToken;
+use yral_billing::schema::{orders, purchase_tokens};
+use yral_billing::types::{
+    GooglePlayProductPurchaseV2, GooglePlaySubscriptionResponse, MonetizationSubscription, Plan,
+    PurchaseTokenStatus, SubscriptionState,
+};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const HEADER: &str = "order_id,purchase_token,user_id,package_name,expiry_time,voided_time";
+
+/// Mock Google Play reporting a fixed state per token, active for unknown ones
+#[derive(Default)]
+struct ExportedGooglePlay {
+    states: HashMap<String, SubscriptionState>,
+    fetches: AtomicUsize,
+}
+
+#[async_trait]
+impl GooglePlayApi for ExportedGooglePlay {
+    async fn fetch_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlaySubscriptionResponse> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let mut response = MockGooglePlay
+            .fetch_subscription(package_name, purchase_token)
+            .await?;
+        response.subscription_state = self
+            .states
+            .get(purchase_token)
+            .copied()
+            .unwrap_or(SubscriptionState::Active);
+        response.latest_order_id = Some(format!("GPA.{}", purchase_token));
+        response.line_items[0].expiry_time =
+            Some((chrono::Utc::now() + chrono::Duration::days(30)).to_rfc3339());
+        Ok(response)
+    }
+
+    async fn acknowledge_subscription(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+        subscription_response: &GooglePlaySubscriptionResponse,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .acknowledge_subscription(package_name, purchase_token, subscription_response)
+            .await
+    }
+
+    async fn fetch_product(
+        &self,
+        package_name: &str,
+        purchase_token: &str,
+    ) -> AppResult<GooglePlayProductPurchaseV2> {
+        MockGooglePlay
+            .fetch_product(package_name, purchase_token)
+            .await
+    }
+
+    async fn consume_product(
+        &self,
+        package_name: &str,
+        product_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .consume_product(package_name, product_id, purchase_token)
+            .await
+    }
+
+    async fn defer_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+        expected_expiry: chrono::DateTime<chrono::Utc>,
+        desired_expiry: chrono::DateTime<chrono::Utc>,
+    ) -> AppResult<chrono::DateTime<chrono::Utc>> {
+        MockGooglePlay
+            .defer_subscription(
+                package_name,
+                subscription_id,
+                purchase_token,
+                expected_expiry,
+                desired_expiry,
+            )
+            .await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        package_name: &str,
+        subscription_id: &str,
+        purchase_token: &str,
+    ) -> AppResult<()> {
+        MockGooglePlay
+            .cancel_subscription(package_name, subscription_id, purchase_token)
+            .await
+    }
+
+    async fn list_subscription_products(
+        &self,
+        package_name: &str,
+    ) -> AppResult<Vec<MonetizationSubscription>> {
+        MockGooglePlay
+            .list_subscription_products(package_name)
+            .await
+    }
+}
+
+/// IC client whose Pro grants fail while it is offline
+#[derive(Default)]
+struct OfflineUserInfo {
+    offline: AtomicBool,
+}
+
+#[async_trait]
+impl UserInfoApi for OfflineUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        if self.offline.load(Ordering::SeqCst) {
+            return Err(AppError::ServiceAccessFailed("unreachable".to_string()));
+        }
+        MockUserInfo.grant_pro_plan(product_id, user_id).await
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        MockUserInfo.revoke_pro_plan(user_id).await
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_purchase_import_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+fn stored(conn: &mut SqliteConnection, token: &str) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(token))
+        .first(conn)
+        .unwrap()
+}
+
+// Each kind of exported purchase ends up stored with the right status, once
+#[tokio::test]
+async fn test_import_is_rerunnable() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let google_play = ExportedGooglePlay {
+        states: HashMap::from([("canceled".to_string(), SubscriptionState::Expired)]),
+        ..Default::default()
+    };
+    let user_info = OfflineUserInfo::default();
+
+    let file = format!(
+        "{}\n\
+         GPA.1,active,user_1,com.yral.android,,\n\
+         GPA.2,canceled,user_2,com.yral.android,,\n\
+         GPA.3,refunded,user_3,com.yral.android,2030-01-01T00:00:00Z,2024-02-01T10:00:00Z\n\
+         GPA.4,lapsed,user_4,com.yral.android,2024-03-01 00:00:00,\n\
+         GPA.5,no-user,,com.yral.android,,\n",
+        HEADER
+    );
+
+    let report = import_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &user_info,
+        &file,
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        report,
+        ImportReport {
+            granted: 1,
+            expired: 2,
+            voided: 1,
+            failed: vec![(6, "`user_id` is empty".to_string())],
+            ..Default::default()
+        }
+    );
+    // Purchases the export already shows as ended aren't looked up
+    assert_eq!(google_play.fetches.load(Ordering::SeqCst), 2);
+
+    let active = stored(&mut conn, "active");
+    assert_eq!(active.status, PurchaseTokenStatus::AccessGranted);
+    assert!(active.expiry_at > now);
+    assert_eq!(active.package_name.as_deref(), Some("com.yral.android"));
+    let recorded: i64 = orders::table
+        .filter(orders::purchase_token.eq("active"))
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(recorded, 1);
+
+    for token in ["canceled", "refunded", "lapsed"] {
+        let token = stored(&mut conn, token);
+        assert_eq!(token.status, PurchaseTokenStatus::Expired);
+        assert!(token.expiry_at <= now);
+    }
+    assert_eq!(
+        stored(&mut conn, "refunded").expiry_at,
+        chrono::NaiveDate::from_ymd_opt(2024, 2, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap()
+    );
+
+    let rerun = import_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &user_info,
+        &file,
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(rerun.already_imported, 4);
+    assert_eq!(rerun.granted + rerun.expired + rerun.voided, 0);
+    assert_eq!(google_play.fetches.load(Ordering::SeqCst), 2);
+}
+
+// A grant that failed leaves the token pending, and the next run completes it
+#[tokio::test]
+async fn test_failed_grant_completed_on_rerun() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let google_play = ExportedGooglePlay::default();
+    let user_info = OfflineUserInfo::default();
+    let file = format!("{}\nGPA.1,active,user_1,com.yral.android,,\n", HEADER);
+
+    user_info.offline.store(true, Ordering::SeqCst);
+    let report = import_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &user_info,
+        &file,
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, 2);
+    assert_eq!(
+        stored(&mut conn, "active").status,
+        PurchaseTokenStatus::Pending
+    );
+
+    user_info.offline.store(false, Ordering::SeqCst);
+    let report = import_purchases(
+        &mut conn,
+        &google_play,
+        &AckStrategies::default(),
+        &user_info,
+        &file,
+        now,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.granted, 1);
+    assert!(report.failed.is_empty());
+    assert_eq!(
+        stored(&mut conn, "active").status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
+
+#[test]
+fn test_import_file_parsing() {
+    // Columns in any order, quoted fields and CRLF line endings
+    let file = "User_Id,Package_Name,Purchase_Token,Note\r\n\
+                user_1,com.yral.android,token_1,\"refund, \"\"goodwill\"\"\"\r\n\
+                \r\n\
+                user_2,com.yral.android,token_2,\"two\nlines\"\r\n\
+                user_3,com.yral.android,token_3,\r\n";
+    let (rows, invalid) = parse_import_file(file).unwrap();
+    assert!(invalid.is_empty());
+    let tokens: Vec<(usize, &str)> = rows
+        .iter()
+        .map(|row| (row.line, row.purchase_token.as_str()))
+        .collect();
+    assert_eq!(tokens, vec![(2, "token_1"), (4, "token_2"), (6, "token_3")]);
+
+    let (rows, invalid) = parse_import_file(&format!(
+        "{}\nGPA.1,token_1,user_1,com.yral.android,next week,\n",
+        HEADER
+    ))
+    .unwrap();
+    assert!(rows.is_empty());
+    assert_eq!(
+        invalid,
+        vec![(2, "`next week` is not a timestamp".to_string())]
+    );
+
+    for file in [
+        "",
+        "purchase_token,user_id\ntoken_1,user_1\n",
+        "purchase_token\n\"open",
+    ] {
+        assert!(matches!(
+            parse_import_file(file),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
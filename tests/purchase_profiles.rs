@@ -0,0 +1,211 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::model::PurchaseToken;
+use yral_billing::profiles::resolve_account_id;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{
+    as_user, memory_state, new_test_user, test_app, FixedGooglePlay, SubscriptionResponseBuilder,
+    TEST_PACKAGE_NAME,
+};
+use yral_billing::types::ExternalAccountIdentifiers;
+use yral_billing::AppState;
+
+async fn send(
+    app_state: &AppState,
+    caller: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let res = as_user(test_app(app_state.clone()), caller)
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+async fn register(app_state: &AppState, caller: &str, user: &str, profile: &str) -> StatusCode {
+    let body = serde_json::json!({ "user_id": user, "obfuscated_profile_id": profile });
+    send(app_state, caller, "/google/profiles", body).await.0
+}
+
+fn stored_owner(app_state: &AppState) -> String {
+    let token: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq("token_1"))
+        .first(&mut app_state.get_db_connection().unwrap())
+        .unwrap();
+    token.user_id
+}
+
+async fn announce(
+    app_state: &AppState,
+    user: &str,
+    account: Option<&str>,
+    profile: Option<&str>,
+) -> (StatusCode, serde_json::Value) {
+    let body = serde_json::json!({
+        "user_id": user,
+        "package_name": TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "obfuscated_account_id": account,
+        "obfuscated_profile_id": profile,
+    });
+    send(app_state, user, "/google/purchase-intent", body).await
+}
+
+async fn verify(app_state: &AppState, user: &str) -> (StatusCode, serde_json::Value) {
+    let body = serde_json::json!({
+        "user_id": user,
+        "package_name": TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "purchase_token": "token_1",
+    });
+    send(app_state, user, "/google/verify", body).await
+}
+
+/// State where Google reports the purchase with these obfuscated ids
+async fn identified_state(account: Option<&str>, profile: Option<&str>) -> AppState {
+    let mut app_state = memory_state().await;
+    let mut response = SubscriptionResponseBuilder::new().account_id(None).build();
+    response.external_account_identifiers = Some(ExternalAccountIdentifiers {
+        external_account_id: None,
+        obfuscated_external_account_id: account.map(str::to_string),
+        obfuscated_external_profile_id: profile.map(str::to_string),
+    });
+    app_state.google_play = FixedGooglePlay::new(response);
+    app_state
+}
+
+// A purchase carrying only a registered profile id is granted to the user behind it
+#[tokio::test]
+async fn test_registered_profile_resolves_purchase() {
+    let app_state = identified_state(None, Some("profile_1")).await;
+    let user = new_test_user();
+
+    let (status, body) = verify(&app_state, &user).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "EXTERNAL_ACCOUNT_IDENTIFIERS_MISSING");
+
+    assert_eq!(
+        register(&app_state, &user, &user, "profile_1").await,
+        StatusCode::OK
+    );
+    let (status, _) = verify(&app_state, &user).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(stored_owner(&app_state), user);
+}
+
+// A profile id stays with the user who registered it first
+#[tokio::test]
+async fn test_profile_registration_is_owned() {
+    let app_state = memory_state().await;
+    let user = new_test_user();
+    let other = new_test_user();
+
+    assert_eq!(
+        register(&app_state, &other, &user, "profile_1").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        register(&app_state, &user, &user, "profile_1").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        register(&app_state, &user, &user, "profile_1").await,
+        StatusCode::OK
+    );
+
+    let body = serde_json::json!({ "user_id": other, "obfuscated_profile_id": "profile_1" });
+    let (status, body) = send(&app_state, &other, "/google/profiles", body).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "PROFILE_ALREADY_REGISTERED");
+}
+
+// A purchase whose account id isn't a principal goes to the user who announced it
+#[tokio::test]
+async fn test_purchase_intent_resolves_opaque_account_id() {
+    let app_state = identified_state(Some("opaque_1"), None).await;
+    let user = new_test_user();
+
+    let (status, body) = announce(&app_state, &user, Some("opaque_1"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["data"]["expires_at"].is_string());
+
+    let (status, _) = verify(&app_state, &user).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored_owner(&app_state), user);
+}
+
+// Intents resolve only until they expire, and only name ids their user owns
+#[tokio::test]
+async fn test_purchase_intent_limits() {
+    let app_state = memory_state().await;
+    let user = new_test_user();
+    let other = new_test_user();
+    let now = chrono::Utc::now().naive_utc();
+
+    let (status, _) = announce(&app_state, &user, None, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = announce(&app_state, &user, Some(&other), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "ACCOUNT_MISMATCH");
+    assert_eq!(
+        register(&app_state, &other, &other, "profile_1").await,
+        StatusCode::OK
+    );
+    let (status, _) = announce(&app_state, &user, None, Some("profile_1")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, body) = announce(&app_state, &user, None, Some("profile_2")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_OWNER");
+
+    let (status, _) = announce(&app_state, &user, Some("opaque_2"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = announce(&app_state, &other, Some("opaque_2"), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let mut conn = app_state.get_db_connection().unwrap();
+    let owner = resolve_account_id(&mut conn, Some("opaque_2"), None, "mock-product-id", now);
+    assert_eq!(owner.unwrap(), user);
+    let expired = now + app_state.purchase_intent_ttl + chrono::Duration::seconds(1);
+    let owner = resolve_account_id(
+        &mut conn,
+        Some("opaque_2"),
+        None,
+        "mock-product-id",
+        expired,
+    );
+    assert_ne!(owner.unwrap(), user);
+}
+
+// A registered profile decides the owner over any intent naming the purchase's ids
+#[tokio::test]
+async fn test_registered_profile_beats_intent() {
+    let app_state = identified_state(Some("opaque_1"), Some("profile_1")).await;
+    let user = new_test_user();
+    let other = new_test_user();
+
+    let (status, _) = announce(&app_state, &other, Some("opaque_1"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        register(&app_state, &user, &user, "profile_1").await,
+        StatusCode::OK
+    );
+
+    let (status, _) = verify(&app_state, &user).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored_owner(&app_state), user);
+}
@@ -0,0 +1,116 @@
+use axum::body::Body;
+use axum::http::Request;
+use axum::routing::post;
+use axum::Router;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::routes::purchase::verify_purchase;
+use yral_billing::routes::purchase_token_helpers::{admit_new_purchase, NewPurchaseAdmission};
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, RtdnBuilder, SubscriptionResponseBuilder, TEST_PACKAGE_NAME,
+};
+use yral_billing::types::{
+    PurchaseTokenStatus, SubscriptionNotificationType, SubscriptionState, VerifyRequest,
+};
+use yral_billing::AppState;
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+const ALL_STATES: [SubscriptionState; 10] = [
+    SubscriptionState::Unspecified,
+    SubscriptionState::Pending,
+    SubscriptionState::Active,
+    SubscriptionState::Paused,
+    SubscriptionState::InGracePeriod,
+    SubscriptionState::OnHold,
+    SubscriptionState::Canceled,
+    SubscriptionState::Expired,
+    SubscriptionState::PendingPurchaseCanceled,
+    SubscriptionState::Unknown,
+];
+
+async fn state_reporting(state: SubscriptionState) -> AppState {
+    let mut app_state = memory_state().await;
+    app_state.google_play =
+        FixedGooglePlay::new(SubscriptionResponseBuilder::new().state(state).build());
+    app_state
+}
+
+fn stored_status(app_state: &AppState, token: &str) -> Option<PurchaseTokenStatus> {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(token))
+        .select(purchase_tokens::status)
+        .first(&mut app_state.get_db_connection().unwrap())
+        .optional()
+        .unwrap()
+}
+
+/// Status a new token ends up with after the app verifies it
+async fn after_verify(state: SubscriptionState) -> Option<PurchaseTokenStatus> {
+    let app_state = state_reporting(state).await;
+    let app = Router::new()
+        .route("/google/verify", post(verify_purchase))
+        .with_state(app_state.clone());
+    let payload = VerifyRequest {
+        user_id: MOCK_USER_ID.to_string(),
+        package_name: TEST_PACKAGE_NAME.to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: "token_1".to_string(),
+        integrity_token: None,
+    };
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/verify")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+    app.oneshot(req).await.unwrap();
+
+    stored_status(&app_state, "token_1")
+}
+
+/// Status a new token ends up with after a purchase notification for it
+async fn after_rtdn(state: SubscriptionState) -> Option<PurchaseTokenStatus> {
+    let app_state = state_reporting(state).await;
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state.clone());
+    let rtdn = RtdnBuilder::subscription(SubscriptionNotificationType::Purchased, "token_1");
+    app.oneshot(rtdn.request()).await.unwrap();
+
+    stored_status(&app_state, "token_1")
+}
+
+#[test]
+fn test_new_purchase_admission() {
+    for state in ALL_STATES {
+        let expected = match state {
+            SubscriptionState::Active | SubscriptionState::InGracePeriod => {
+                Some(NewPurchaseAdmission::Grant)
+            }
+            SubscriptionState::Pending => Some(NewPurchaseAdmission::AwaitPayment),
+            _ => None,
+        };
+        assert_eq!(admit_new_purchase(state).ok(), expected, "{:?}", state);
+    }
+}
+
+// Verify and RTDN take a new purchase in the same way whatever state Google reports,
+// e.g. neither grants a canceled one
+#[tokio::test]
+async fn test_verify_and_rtdn_decide_alike() {
+    for state in ALL_STATES {
+        let expected = match admit_new_purchase(state) {
+            Ok(NewPurchaseAdmission::Grant) => Some(PurchaseTokenStatus::AccessGranted),
+            Ok(NewPurchaseAdmission::AwaitPayment) => Some(PurchaseTokenStatus::Pending),
+            Err(_) => None,
+        };
+
+        assert_eq!(after_verify(state).await, expected, "verify of {:?}", state);
+        assert_eq!(after_rtdn(state).await, expected, "RTDN of {:?}", state);
+    }
+}
@@ -0,0 +1,229 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::feature_flags::QUARANTINE_SUSPICIOUS_VERIFICATIONS;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::metrics::VERIFICATIONS_QUARANTINED_TOTAL;
+use yral_billing::model::{AdminAuditEntry, PurchaseToken};
+use yral_billing::schema::{admin_audit_log, purchase_tokens};
+use yral_billing::test_support::{
+    as_user, memory_state, new_test_user, test_app, FixedGooglePlay, SubscriptionResponseBuilder,
+    TEST_PACKAGE_NAME,
+};
+use yral_billing::types::{AuditAction, PurchaseTokenStatus};
+use yral_billing::AppState;
+
+fn app(app_state: AppState) -> Router {
+    as_user(test_app(app_state), "ops@yral.com")
+}
+
+async fn send(
+    app_state: &AppState,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    let body = match body {
+        Some(body) => {
+            request = request.header("content-type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let res = app(app_state.clone())
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+async fn verify(
+    app_state: &AppState,
+    user: &str,
+    product: &str,
+) -> (StatusCode, serde_json::Value) {
+    send(
+        app_state,
+        "POST",
+        "/google/verify",
+        Some(serde_json::json!({
+            "user_id": user,
+            "package_name": TEST_PACKAGE_NAME,
+            "product_id": product,
+            "purchase_token": "token_1",
+        })),
+    )
+    .await
+}
+
+/// State with quarantine mode on and Google reporting a purchase started at `started_at`
+async fn quarantine_state(started_at: DateTime<Utc>) -> AppState {
+    let mut app_state = memory_state().await;
+    let mut response = SubscriptionResponseBuilder::new().build();
+    response.start_time = Some(started_at.to_rfc3339());
+    app_state.google_play = FixedGooglePlay::new(response);
+    app_state
+        .feature_flags
+        .set(
+            &mut app_state.get_db_connection().unwrap(),
+            QUARANTINE_SUSPICIOUS_VERIFICATIONS,
+            true,
+            Utc::now().naive_utc(),
+        )
+        .unwrap();
+    app_state
+}
+
+fn stored_token(app_state: &AppState) -> Option<PurchaseToken> {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq("token_1"))
+        .first(&mut app_state.get_db_connection().unwrap())
+        .optional()
+        .unwrap()
+}
+
+// A token another user already tried is held until an operator approves it, which grants it
+#[tokio::test]
+async fn test_shared_token_quarantined_until_approved() {
+    let mut app_state = quarantine_state(Utc::now()).await;
+    app_state.quarantine_policy.max_users_per_token = 1;
+
+    let (status, _) = verify(&app_state, &new_test_user(), "unknown-product").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "mock-product-id").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["code"], "VERIFICATION_QUARANTINED");
+    assert!(stored_token(&app_state).is_none());
+    assert_eq!(
+        app_state.metrics.counter(
+            VERIFICATIONS_QUARANTINED_TOTAL,
+            &[("reason", "shared_token")]
+        ),
+        1
+    );
+
+    let (status, body) = send(&app_state, "GET", "/admin/quarantine", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let pending = body["data"].as_array().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["reason"], "shared_token");
+    assert_eq!(pending[0]["user_id"], MOCK_SUBSCRIPTION_ACCOUNT_ID);
+    let id = pending[0]["id"].as_str().unwrap().to_string();
+
+    let (status, body) = send(
+        &app_state,
+        "POST",
+        &format!("/admin/quarantine/{}/approve", id),
+        Some(serde_json::json!({ "reason": "Family device" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["status"], "approved");
+    assert_eq!(body["data"]["decided_by"], "ops@yral.com");
+    assert_eq!(
+        stored_token(&app_state).unwrap().status,
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    let audit: Vec<AdminAuditEntry> = admin_audit_log::table
+        .load(&mut app_state.get_db_connection().unwrap())
+        .unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].action, AuditAction::ApproveQuarantine);
+
+    // Decided entries leave the pending list and can't be decided again
+    let (_, body) = send(&app_state, "GET", "/admin/quarantine", None).await;
+    assert!(body["data"].as_array().unwrap().is_empty());
+    let (status, _) = send(
+        &app_state,
+        "POST",
+        &format!("/admin/quarantine/{}/reject", id),
+        Some(serde_json::json!({ "reason": "Changed my mind" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// A purchase first verified days after it started is held, and stays refused once rejected
+#[tokio::test]
+async fn test_late_verification_rejected() {
+    let app_state = quarantine_state(Utc::now() - chrono::Duration::days(30)).await;
+
+    let (status, body) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "mock-product-id").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["code"], "VERIFICATION_QUARANTINED");
+
+    let (_, body) = send(&app_state, "GET", "/admin/quarantine", None).await;
+    let id = body["data"][0]["id"].as_str().unwrap().to_string();
+    assert_eq!(body["data"][0]["reason"], "late_verification");
+
+    let (status, _) = send(
+        &app_state,
+        "POST",
+        &format!("/admin/quarantine/{}/reject", id),
+        Some(serde_json::json!({ "reason": " " })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &app_state,
+        "POST",
+        &format!("/admin/quarantine/{}/reject", id),
+        Some(serde_json::json!({ "reason": "Resold token" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "mock-product-id").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "VERIFICATION_REJECTED");
+    assert!(stored_token(&app_state).is_none());
+
+    let (_, body) = send(&app_state, "GET", "/admin/quarantine?status=rejected", None).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+}
+
+// A user retrying verify past the hourly limit is held
+#[tokio::test]
+async fn test_high_velocity_quarantined() {
+    let mut app_state = quarantine_state(Utc::now()).await;
+    app_state.quarantine_policy.max_attempts_per_hour = 2;
+
+    for _ in 0..2 {
+        let (status, _) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "unknown-product").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let (status, body) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "mock-product-id").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["code"], "VERIFICATION_QUARANTINED");
+    assert_eq!(
+        app_state.metrics.counter(
+            VERIFICATIONS_QUARANTINED_TOTAL,
+            &[("reason", "high_velocity")]
+        ),
+        1
+    );
+}
+
+// Without quarantine mode even a late verification is granted right away
+#[tokio::test]
+async fn test_quarantine_off_by_default() {
+    let mut app_state = memory_state().await;
+    let mut response = SubscriptionResponseBuilder::new().build();
+    response.start_time = Some((Utc::now() - chrono::Duration::days(30)).to_rfc3339());
+    app_state.google_play = FixedGooglePlay::new(response);
+
+    let (status, _) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID, "mock-product-id").await;
+    assert_eq!(status, StatusCode::OK);
+}
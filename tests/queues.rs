@@ -0,0 +1,65 @@
+use chrono::{Duration, Utc};
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::jobs::access_outbox::enqueue_access_change;
+use yral_billing::metrics::{QUEUE_DEPTH, QUEUE_OLDEST_AGE_SECONDS};
+use yral_billing::queues::{check_queues, queue_stats};
+use yral_billing::test_support::{memory_state, new_test_user};
+use yral_billing::types::OutboxAction;
+
+// Every queue is reported, idle ones with nothing due
+#[tokio::test]
+async fn test_idle_queues_reported() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+
+    let stats = queue_stats(&mut conn, Utc::now().naive_utc()).unwrap();
+    let names: Vec<&str> = stats.iter().map(|queue| queue.queue.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "access_outbox",
+            "domain_events",
+            "held_notifications",
+            "grace_reminders",
+            "analytics_events",
+            "provisional_purchases"
+        ]
+    );
+    assert!(stats
+        .iter()
+        .all(|queue| queue.oldest_due_at.is_none() && !queue.stalled));
+}
+
+// An outbox entry waiting past the queue's max age marks it stalled and shows in the gauges
+#[tokio::test]
+async fn test_old_outbox_entry_stalls_queue() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = Utc::now().naive_utc();
+    for queued_at in [now - Duration::hours(1), now] {
+        enqueue_access_change(
+            &mut conn,
+            &new_test_user(),
+            OutboxAction::GrantPro,
+            Some(YRAL_PRO_PLAN_PRODUCT_ID),
+            None,
+            queued_at,
+        )
+        .unwrap();
+    }
+
+    let stalled = check_queues(&mut conn, &app_state.metrics, now).unwrap();
+    assert_eq!(stalled.len(), 1);
+    let outbox = &stalled[0];
+    assert_eq!(outbox.queue, "access_outbox");
+    assert_eq!(outbox.pending, 2);
+    assert_eq!(outbox.failed, Some(0));
+    assert_eq!(outbox.oldest_age_secs, Some(3600));
+
+    let labels = [("queue", "access_outbox")];
+    assert_eq!(app_state.metrics.gauge(QUEUE_DEPTH, &labels), Some(2));
+    assert_eq!(
+        app_state.metrics.gauge(QUEUE_OLDEST_AGE_SECONDS, &labels),
+        Some(3600)
+    );
+}
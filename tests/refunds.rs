@@ -0,0 +1,138 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::model::PurchaseToken;
+use yral_billing::test_support::{test_app, test_user, PurchaseTokenBuilder};
+use yral_billing::types::{
+    CreateRefundRequest, PurchaseTokenStatus, RefundRequestAction, RefundRequestActionRequest,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_refunds_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn db_path(&self) -> &str {
+        &self.db_path
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn post_json(uri: &str, body: Vec<u8>) -> (StatusCode, serde_json::Value) {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+fn refund_request(user_id: &str, token: &str) -> Vec<u8> {
+    serde_json::to_vec(&CreateRefundRequest {
+        user_id: user_id.to_string(),
+        package_name: "com.example".to_string(),
+        purchase_token: token.to_string(),
+        reason: "Bought by mistake".to_string(),
+    })
+    .unwrap()
+}
+
+// Approving a request revokes access and expires the token
+#[tokio::test]
+async fn test_refund_request_approval_revokes_access() {
+    use yral_billing::schema::purchase_tokens;
+
+    let db_guard = TestDbGuard::new();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+    PurchaseTokenBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .insert(&mut SqliteConnection::establish(db_guard.db_path()).unwrap());
+
+    let (status, response) = post_json(
+        "/support/refund-request",
+        refund_request(&test_user("user_1"), &token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["status"], "Requested");
+    assert!(response["data"]["order_id"].is_string());
+    let request_id = response["data"]["id"].as_str().unwrap().to_string();
+
+    let action = serde_json::to_vec(&RefundRequestActionRequest {
+        action: RefundRequestAction::Approve,
+    })
+    .unwrap();
+    let (status, response) =
+        post_json(&format!("/admin/refund-requests/{}", request_id), action).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["data"]["status"], "Revoked");
+
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let stored: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::Expired);
+
+    // Resolved requests can't be acted on again
+    let action = serde_json::to_vec(&RefundRequestActionRequest {
+        action: RefundRequestAction::Reject,
+    })
+    .unwrap();
+    let (status, _) = post_json(&format!("/admin/refund-requests/{}", request_id), action).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// Users can't request refunds for tokens they don't own
+#[tokio::test]
+async fn test_refund_request_other_user_rejected() {
+    let db_guard = TestDbGuard::new();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+    PurchaseTokenBuilder::new(&test_user("user_1"))
+        .purchase_token(&token)
+        .insert(&mut SqliteConnection::establish(db_guard.db_path()).unwrap());
+
+    let (status, response) = post_json(
+        "/support/refund-request",
+        refund_request(&test_user("user_2"), &token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response["code"], "TOKEN_ALREADY_USED");
+}
@@ -0,0 +1,111 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::exposed_router;
+use yral_billing::route_exposure::{is_internal_route, IpAllowlist, Listener, RouteExposure};
+use yral_billing::test_support::memory_state;
+
+fn ip(value: &str) -> IpAddr {
+    value.parse().unwrap()
+}
+
+async fn status_from(app: Router, uri: &str, peer: &str) -> StatusCode {
+    let mut req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::new(ip(peer), 40000)));
+    app.oneshot(req).await.unwrap().status()
+}
+
+#[test]
+fn test_internal_routes() {
+    assert!(is_internal_route("/admin/jobs"));
+    assert!(is_internal_route("/v1/admin/jobs"));
+    assert!(is_internal_route("/internal/introspect"));
+    assert!(!is_internal_route("/administrator"));
+    assert!(!is_internal_route("/google/verify"));
+    assert!(!is_internal_route("/v1/health"));
+}
+
+#[test]
+fn test_allowlist_ranges() {
+    let allowlist = IpAllowlist::parse("10.0.0.0/8, 127.0.0.1,fd00::/8").unwrap();
+    assert!(allowlist.contains(ip("10.20.30.40")));
+    assert!(allowlist.contains(ip("127.0.0.1")));
+    assert!(allowlist.contains(ip("fd12::1")));
+    // IPv4 peer of a dual-stack socket
+    assert!(allowlist.contains(ip("::ffff:10.1.2.3")));
+    assert!(!allowlist.contains(ip("127.0.0.2")));
+    assert!(!allowlist.contains(ip("11.0.0.1")));
+    assert!(!allowlist.contains(ip("2001:db8::1")));
+
+    assert!(IpAllowlist::parse("0.0.0.0/0")
+        .unwrap()
+        .contains(ip("203.0.113.9")));
+
+    for invalid in ["", "10.0.0.0/33", "fd00::/129", "localhost", "10.0.0.0/x"] {
+        assert!(IpAllowlist::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+// With an internal port the public listener hides admin routes and the internal one serves only them
+#[test]
+fn test_routes_split_across_listeners() {
+    let exposure = RouteExposure {
+        internal_port: Some(3001),
+        allowlist: None,
+    };
+
+    assert_eq!(
+        exposure.check(Listener::Public, "/admin/jobs", None),
+        Err(StatusCode::NOT_FOUND)
+    );
+    assert_eq!(
+        exposure.check(Listener::Public, "/google/verify", None),
+        Ok(())
+    );
+    assert_eq!(
+        exposure.check(Listener::Internal, "/internal/introspect", None),
+        Ok(())
+    );
+    assert_eq!(
+        exposure.check(Listener::Internal, "/google/verify", None),
+        Err(StatusCode::NOT_FOUND)
+    );
+    assert_eq!(exposure.check(Listener::Internal, "/health", None), Ok(()));
+
+    // Everything on one port unless configured
+    let single = RouteExposure::default();
+    assert_eq!(single.check(Listener::Public, "/admin/jobs", None), Ok(()));
+}
+
+// On a single port, admin routes only answer allowlisted peers
+#[tokio::test]
+async fn test_allowlist_on_single_port() {
+    let exposure = RouteExposure {
+        internal_port: None,
+        allowlist: Some(IpAllowlist::parse("10.0.0.0/8").unwrap()),
+    };
+    let app = exposed_router(memory_state().await, &exposure, Listener::Public);
+
+    assert_eq!(
+        status_from(app.clone(), "/admin/jobs", "203.0.113.9").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status_from(app.clone(), "/v1/admin/jobs", "203.0.113.9").await,
+        StatusCode::FORBIDDEN
+    );
+    // Allowed through, then authenticated as usual
+    assert_eq!(
+        status_from(app.clone(), "/admin/jobs", "10.0.0.5").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status_from(app, "/health", "203.0.113.9").await,
+        StatusCode::OK
+    );
+}
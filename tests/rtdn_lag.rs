@@ -0,0 +1,121 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::{Duration, Utc};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::metrics::{Metrics, RTDN_DELIVERY_LAG_SECONDS, RTDN_EVENT_LAG_SECONDS};
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::routes::stats::get_rtdn_lag_stats;
+use yral_billing::rtdn_lag::{prune_rtdn_lag_samples, record_rtdn_lag, rtdn_lag_stats};
+use yral_billing::test_support::{memory_state, RtdnBuilder};
+
+// Every processed notification is observed in the histograms and shows up in the admin stats
+#[tokio::test]
+async fn test_webhook_records_lag() {
+    let app_state = memory_state().await;
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .route("/admin/stats/rtdn-lag", get(get_rtdn_lag_stats))
+        .with_state(app_state.clone());
+
+    let rtdn = RtdnBuilder::test().event_time(Utc::now() - Duration::minutes(10));
+    let res = app.clone().oneshot(rtdn.request()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let labels = [("notification_type", "test")];
+    assert_eq!(
+        app_state
+            .metrics
+            .histogram_count(RTDN_EVENT_LAG_SECONDS, &labels),
+        1
+    );
+    assert_eq!(
+        app_state
+            .metrics
+            .histogram_count(RTDN_DELIVERY_LAG_SECONDS, &labels),
+        1
+    );
+    let rendered = app_state.metrics.render();
+    assert!(rendered.contains("# TYPE billing_rtdn_event_lag_seconds histogram"));
+    assert!(rendered.contains(
+        "billing_rtdn_event_lag_seconds_bucket{notification_type=\"test\",le=\"300\"} 0"
+    ));
+    assert!(rendered.contains(
+        "billing_rtdn_event_lag_seconds_bucket{notification_type=\"test\",le=\"900\"} 1"
+    ));
+    assert!(rendered.contains("billing_rtdn_event_lag_seconds_count{notification_type=\"test\"} 1"));
+
+    let req = Request::builder()
+        .uri("/admin/stats/rtdn-lag")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let types = response["data"]["types"].as_array().unwrap();
+    assert_eq!(types.len(), 1);
+    assert_eq!(types[0]["notification_type"], "test");
+    assert_eq!(types[0]["samples"], 1);
+    let event_lag = types[0]["event_lag_p95_ms"].as_i64().unwrap();
+    assert!((600_000..660_000).contains(&event_lag), "{}", event_lag);
+}
+
+#[tokio::test]
+async fn test_lag_percentiles_per_type() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let metrics = Metrics::new();
+    let now = Utc::now().naive_utc();
+
+    for secs in 1..=20 {
+        record_rtdn_lag(
+            &mut conn,
+            &metrics,
+            "subscription_revoked",
+            Some(now - Duration::seconds(secs)),
+            None,
+            now,
+        )
+        .unwrap();
+    }
+    // Outside the window
+    record_rtdn_lag(
+        &mut conn,
+        &metrics,
+        "subscription_revoked",
+        Some(now - Duration::hours(30)),
+        None,
+        now - Duration::hours(25),
+    )
+    .unwrap();
+    // Clock skew doesn't make the lag negative
+    record_rtdn_lag(
+        &mut conn,
+        &metrics,
+        "subscription_renewed",
+        Some(now + Duration::seconds(5)),
+        Some(now - Duration::seconds(2)),
+        now,
+    )
+    .unwrap();
+
+    let stats = rtdn_lag_stats(&mut conn, now - Duration::hours(24)).unwrap();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].notification_type, "subscription_renewed");
+    assert_eq!(stats[0].event_lag_p50_ms, Some(0));
+    assert_eq!(stats[0].delivery_lag_p50_ms, Some(2000));
+    assert_eq!(stats[1].notification_type, "subscription_revoked");
+    assert_eq!(stats[1].samples, 20);
+    assert_eq!(stats[1].event_lag_p50_ms, Some(10_000));
+    assert_eq!(stats[1].event_lag_p95_ms, Some(19_000));
+    assert_eq!(stats[1].delivery_lag_p50_ms, None);
+
+    assert_eq!(
+        prune_rtdn_lag_samples(&mut conn, now - Duration::hours(24)).unwrap(),
+        1
+    );
+}
@@ -0,0 +1,304 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::model::{BotChatAccess, PurchaseToken, UnhandledNotification};
+use yral_billing::test_support::{test_app, RtdnBuilder, TestDbGuard, VerifyRequestBuilder};
+use yral_billing::types::{
+    BotChatAccessStatus, GrantChatAccessRequest, OneTimeProductNotificationType,
+    PurchaseTokenStatus, SubscriptionNotificationType, VoidedProductType,
+};
+use yral_billing::AppState;
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+// ── Request helpers ──
+
+async fn post_json(app: Router, uri: &str, body: Vec<u8>) -> axum::response::Response {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+async fn post_rtdn(payload: &serde_json::Value) -> axum::response::Response {
+    post_json(
+        test_app(AppState::new().await),
+        "/google/rtdn-webhook",
+        serde_json::to_vec(payload).unwrap(),
+    )
+    .await
+}
+
+fn load_purchase_token(conn: &mut SqliteConnection, token: &str) -> PurchaseToken {
+    use yral_billing::schema::purchase_tokens::dsl;
+
+    dsl::purchase_tokens
+        .filter(dsl::purchase_token.eq(token))
+        .first(conn)
+        .unwrap()
+}
+
+fn set_purchase_token_status(
+    conn: &mut SqliteConnection,
+    token: &str,
+    status: PurchaseTokenStatus,
+) {
+    use yral_billing::schema::purchase_tokens::dsl;
+
+    diesel::update(dsl::purchase_tokens.filter(dsl::purchase_token.eq(token)))
+        .set(dsl::status.eq(status))
+        .execute(conn)
+        .unwrap();
+}
+
+// verify → RENEWED → CANCELED → EXPIRED → re-verify, checking the stored token after each step
+#[tokio::test]
+async fn test_subscription_lifecycle() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    // Verify inserts an access-granted token
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let stored = load_purchase_token(&mut conn, &token);
+    assert_eq!(stored.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(stored.user_id, MOCK_USER_ID);
+
+    // Simulate a lapsed period so the renewal has something to restore
+    set_purchase_token_status(&mut conn, &token, PurchaseTokenStatus::Expired);
+
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    // Cancellation keeps access until the period ends
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Canceled, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    // Expiry revokes access
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Expired, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+
+    // Reconciliation through verify restores access while Google still reports it active
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
+
+// A renewal delivered after a newer expiry doesn't restore access, redeliveries are no-ops
+#[tokio::test]
+async fn test_out_of_order_notifications_discarded() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Notifications carry milliseconds
+    let expired_at =
+        chrono::DateTime::from_timestamp_millis(chrono::Utc::now().timestamp_millis()).unwrap();
+    let expired = RtdnBuilder::subscription(SubscriptionNotificationType::Expired, &token)
+        .event_time(expired_at)
+        .envelope();
+    let res = post_rtdn(&expired).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let stored = load_purchase_token(&mut conn, &token);
+    assert_eq!(stored.status, PurchaseTokenStatus::Expired);
+    assert_eq!(stored.last_event_time, Some(expired_at.naive_utc()));
+
+    // Emitted before the expiry but delivered after it
+    let renewed = RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token)
+        .event_time(expired_at - chrono::Duration::seconds(1))
+        .envelope();
+    let res = post_rtdn(&renewed).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+
+    // Redelivery of the applied expiry is acknowledged without acting again
+    set_purchase_token_status(&mut conn, &token, PurchaseTokenStatus::AccessGranted);
+    let res = post_rtdn(&expired).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
+
+// A purchase notification doesn't revive an expired token, and isn't retried
+#[tokio::test]
+async fn test_purchase_notification_for_expired_token_ignored() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    set_purchase_token_status(&mut conn, &token, PurchaseTokenStatus::Expired);
+
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Purchased, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+}
+
+// RTDN renewal for a token we have never seen is rejected so Pub/Sub retries it
+#[tokio::test]
+async fn test_renewal_for_unknown_token_fails() {
+    let _db_guard = TestDbGuard::new();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+// A refunded one-time product cancels the chat access granted with it
+#[tokio::test]
+async fn test_one_time_product_canceled() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let payload = GrantChatAccessRequest {
+        package_name: "com.example".to_string(),
+        product_id: "mock-product-id".to_string(),
+        purchase_token: token.clone(),
+        bot_id: "bot_abc".to_string(),
+    };
+    let res = post_json(
+        test_app(AppState::new().await),
+        "/google/chat-access/grant",
+        serde_json::to_vec(&payload).unwrap(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = post_rtdn(
+        &RtdnBuilder::one_time_product(OneTimeProductNotificationType::Canceled, &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    use yral_billing::schema::bot_chat_access::dsl;
+    let grant: BotChatAccess = dsl::bot_chat_access
+        .filter(dsl::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(grant.status, BotChatAccessStatus::Canceled);
+}
+
+// A refunded subscription loses access even though Google no longer reports its owner
+#[tokio::test]
+async fn test_voided_subscription_revoked() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID)
+        .purchase_token(&token)
+        .request();
+    let res = test_app(AppState::new().await).oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res =
+        post_rtdn(&RtdnBuilder::voided(VoidedProductType::Subscription, &token).envelope()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        load_purchase_token(&mut conn, &token).status,
+        PurchaseTokenStatus::Expired
+    );
+}
+
+// Notification types we don't know yet are acknowledged and stored instead of retried forever
+#[tokio::test]
+async fn test_unknown_subscription_type_stored() {
+    use yral_billing::schema::unhandled_notifications::dsl;
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let res = post_rtdn(
+        &RtdnBuilder::subscription(SubscriptionNotificationType::Unknown(99), &token).envelope(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let stored: UnhandledNotification = dsl::unhandled_notifications
+        .filter(dsl::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.kind, "subscription");
+    assert_eq!(stored.notification_type, Some(99));
+}
+
+// Malformed Pub/Sub data is rejected without touching the database
+#[tokio::test]
+async fn test_invalid_pubsub_data_rejected() {
+    let _db_guard = TestDbGuard::new();
+
+    let payload = serde_json::json!({
+        "message": {
+            "data": "not base64!",
+            "messageId": "1",
+            "publishTime": chrono::Utc::now().to_rfc3339(),
+        }
+    });
+    let res = post_rtdn(&payload).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,131 @@
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::jobs::held_notifications::release_held_notifications;
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::{held_notifications, orders, purchase_tokens};
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{PurchaseTokenStatus, SubscriptionNotificationType};
+use yral_billing::AppState;
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+async fn held_state() -> (AppState, Router, PurchaseToken) {
+    let mut app_state = memory_state().await;
+    app_state.rtdn_hold_window = Duration::seconds(60);
+    app_state.google_play = FixedGooglePlay::new(SubscriptionResponseBuilder::new().build());
+    let token =
+        PurchaseTokenBuilder::new(MOCK_USER_ID).insert(&mut app_state.get_db_connection().unwrap());
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state.clone());
+    (app_state, app, token)
+}
+
+async fn deliver(app: &Router, rtdn: RtdnBuilder) {
+    let res = app.clone().oneshot(rtdn.request()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn status(conn: &mut SqliteConnection, token: &PurchaseToken) -> PurchaseTokenStatus {
+    purchase_tokens::table
+        .find(&token.id)
+        .select(purchase_tokens::status)
+        .first(conn)
+        .unwrap()
+}
+
+fn held(conn: &mut SqliteConnection) -> i64 {
+    held_notifications::table.count().get_result(conn).unwrap()
+}
+
+// EXPIRED delivered before the RENEWED that preceded it waits, so the renewal isn't lost
+#[tokio::test]
+async fn test_expiry_held_until_earlier_renewal_applied() {
+    let (app_state, app, token) = held_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = Utc::now();
+
+    let expired = || {
+        RtdnBuilder::subscription(SubscriptionNotificationType::Expired, &token.purchase_token)
+            .event_time(now - Duration::minutes(1))
+    };
+    deliver(&app, expired()).await;
+    // A redelivery is held once
+    deliver(&app, expired()).await;
+    assert_eq!(held(&mut conn), 1);
+    assert_eq!(
+        status(&mut conn, &token),
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    deliver(
+        &app,
+        RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token.purchase_token)
+            .event_time(now - Duration::minutes(2)),
+    )
+    .await;
+    let recorded: i64 = orders::table
+        .filter(orders::purchase_token.eq(&token.purchase_token))
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(recorded, 1);
+
+    // Not released before the window has passed
+    let released = release_held_notifications(&app_state, now.naive_utc())
+        .await
+        .unwrap();
+    assert_eq!(released, 0);
+
+    let released =
+        release_held_notifications(&app_state, (now + Duration::seconds(61)).naive_utc())
+            .await
+            .unwrap();
+    assert_eq!(released, 1);
+    assert_eq!(held(&mut conn), 0);
+    assert_eq!(status(&mut conn, &token), PurchaseTokenStatus::Expired);
+}
+
+// A newer notification arriving while one is held makes the held one stale
+#[tokio::test]
+async fn test_newer_notification_supersedes_held_one() {
+    let (app_state, app, token) = held_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = Utc::now();
+
+    deliver(
+        &app,
+        RtdnBuilder::subscription(SubscriptionNotificationType::OnHold, &token.purchase_token)
+            .event_time(now - Duration::minutes(2)),
+    )
+    .await;
+    deliver(
+        &app,
+        RtdnBuilder::subscription(
+            SubscriptionNotificationType::Recovered,
+            &token.purchase_token,
+        )
+        .event_time(now - Duration::minutes(1)),
+    )
+    .await;
+
+    let released =
+        release_held_notifications(&app_state, (now + Duration::seconds(61)).naive_utc())
+            .await
+            .unwrap();
+    assert_eq!(released, 1);
+    assert_eq!(held(&mut conn), 0);
+    assert_eq!(
+        status(&mut conn, &token),
+        PurchaseTokenStatus::AccessGranted
+    );
+}
@@ -0,0 +1,238 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use chrono::{NaiveDate, NaiveDateTime};
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppError;
+use yral_billing::metrics::SCHEDULED_JOB_RUNS_TOTAL;
+use yral_billing::routes::admin::list_scheduled_jobs;
+use yral_billing::scheduler::{finish_job, try_lock_job, Schedule, Scheduler};
+use yral_billing::test_support::memory_state;
+use yral_billing::types::JobOutcome;
+
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap()
+}
+
+fn next(expression: &str, after: NaiveDateTime) -> NaiveDateTime {
+    Schedule::parse(expression)
+        .unwrap()
+        .next_after(after)
+        .unwrap()
+}
+
+#[test]
+fn test_schedule_parsing() {
+    assert_eq!(
+        Schedule::parse("300").unwrap(),
+        Schedule::Every(Duration::from_secs(300))
+    );
+    assert_eq!(Schedule::parse("300").unwrap().to_string(), "every 300s");
+    assert_eq!(
+        Schedule::parse(" 0  3 * * 1-5 ").unwrap().to_string(),
+        "0 3 * * 1-5"
+    );
+
+    for invalid in [
+        "0",
+        "* * *",
+        "61 * * * *",
+        "* 24 * * *",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "0 0 31 2 *",
+        "every hour",
+    ] {
+        assert!(Schedule::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_cron_next_run() {
+    // Friday morning
+    let friday = at(2026, 4, 10, 10, 7);
+
+    assert_eq!(next("*/15 * * * *", friday), at(2026, 4, 10, 10, 15));
+    // Strictly after, a job never runs twice in the same minute
+    assert_eq!(
+        next("*/15 * * * *", at(2026, 4, 10, 10, 15)),
+        at(2026, 4, 10, 10, 30)
+    );
+    assert_eq!(next("30 9,17 * * *", friday), at(2026, 4, 10, 17, 30));
+    // Weekdays only, over the weekend
+    assert_eq!(
+        next("0 3 * * 1-5", at(2026, 4, 10, 4, 0)),
+        at(2026, 4, 13, 3, 0)
+    );
+    // 7 is Sunday too
+    assert_eq!(next("0 0 * * 7", friday), at(2026, 4, 12, 0, 0));
+    // Day of month and day of week both restricted, either one runs
+    assert_eq!(next("0 0 1 * 0", friday), at(2026, 4, 12, 0, 0));
+    assert_eq!(
+        next("0 0 1 * 0", at(2026, 4, 26, 0, 0)),
+        at(2026, 5, 1, 0, 0)
+    );
+    // Across years, to the next leap day
+    assert_eq!(next("0 0 29 2 *", friday), at(2028, 2, 29, 0, 0));
+    assert_eq!(next("0 0 1 1 *", friday), at(2027, 1, 1, 0, 0));
+}
+
+// Only one holder runs a job until it finishes or its lease runs out
+#[tokio::test]
+async fn test_job_lease_is_single_flight() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let schedule = Schedule::parse("60").unwrap();
+    let lease = chrono::Duration::minutes(10);
+    let now = at(2026, 4, 10, 10, 0);
+
+    assert!(try_lock_job(&mut conn, "sweep", &schedule, "replica-a", now, lease).unwrap());
+    assert!(!try_lock_job(&mut conn, "sweep", &schedule, "replica-b", now, lease).unwrap());
+    // Other jobs aren't affected
+    assert!(try_lock_job(&mut conn, "reminders", &schedule, "replica-b", now, lease).unwrap());
+
+    // Finishing by a holder that doesn't have the lease changes nothing
+    finish_job(
+        &mut conn,
+        "sweep",
+        "replica-b",
+        now,
+        JobOutcome::Succeeded,
+        None,
+        5,
+    )
+    .unwrap();
+    assert!(!try_lock_job(&mut conn, "sweep", &schedule, "replica-b", now, lease).unwrap());
+
+    finish_job(
+        &mut conn,
+        "sweep",
+        "replica-a",
+        now,
+        JobOutcome::Succeeded,
+        None,
+        5,
+    )
+    .unwrap();
+    assert!(try_lock_job(&mut conn, "sweep", &schedule, "replica-b", now, lease).unwrap());
+
+    // replica-b dies mid-run, its lease runs out
+    let later = now + lease;
+    assert!(!try_lock_job(
+        &mut conn,
+        "sweep",
+        &schedule,
+        "replica-a",
+        later - chrono::Duration::seconds(1),
+        lease
+    )
+    .unwrap());
+    assert!(try_lock_job(&mut conn, "sweep", &schedule, "replica-a", later, lease).unwrap());
+}
+
+// Runs are counted per outcome and listed with their last result
+#[tokio::test]
+async fn test_runs_recorded_and_listed() {
+    let app_state = memory_state().await;
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let mut scheduler = Scheduler::new(app_state.clone()).unwrap();
+    let counter = runs.clone();
+    scheduler.register(
+        "counting",
+        Schedule::parse("0 3 * * *").unwrap(),
+        move |_app_state| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(None)
+            }
+        },
+    );
+    scheduler.register(
+        "failing",
+        Schedule::parse("60").unwrap(),
+        |_app_state| async move { Err(AppError::BadRequest("upstream down".to_string())) },
+    );
+
+    assert_eq!(
+        scheduler.run_now("counting").await,
+        Some(JobOutcome::Succeeded)
+    );
+    assert_eq!(scheduler.run_now("failing").await, Some(JobOutcome::Failed));
+    assert_eq!(scheduler.run_now("unknown").await, None);
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+    // Another replica is running the job
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = app_state.clock.now_naive();
+    let schedule = Schedule::parse("0 3 * * *").unwrap();
+    assert!(try_lock_job(
+        &mut conn,
+        "counting",
+        &schedule,
+        "replica-b",
+        now,
+        chrono::Duration::minutes(10)
+    )
+    .unwrap());
+    assert_eq!(scheduler.run_now("counting").await, None);
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+    let metrics = &app_state.metrics;
+    for (job, outcome, expected) in [
+        ("counting", "succeeded", 1),
+        ("counting", "skipped", 1),
+        ("failing", "failed", 1),
+    ] {
+        assert_eq!(
+            metrics.counter(
+                SCHEDULED_JOB_RUNS_TOTAL,
+                &[("job", job), ("outcome", outcome)]
+            ),
+            expected
+        );
+    }
+
+    let app = Router::new()
+        .route("/admin/jobs", get(list_scheduled_jobs))
+        .with_state(app_state.clone());
+    let req = Request::builder()
+        .method("GET")
+        .uri("/admin/jobs")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    let jobs = response["data"].as_array().unwrap();
+    assert_eq!(jobs.len(), 2);
+
+    assert_eq!(jobs[0]["name"], "counting");
+    assert_eq!(jobs[0]["schedule"], "0 3 * * *");
+    assert_eq!(jobs[0]["running"], true);
+    assert_eq!(jobs[0]["locked_by"], "replica-b");
+    assert_eq!(jobs[0]["last_outcome"], "succeeded");
+
+    assert_eq!(jobs[1]["name"], "failing");
+    assert_eq!(jobs[1]["schedule"], "every 60s");
+    assert_eq!(jobs[1]["running"], false);
+    assert!(jobs[1]["locked_by"].is_null());
+    assert_eq!(jobs[1]["last_outcome"], "failed");
+    assert!(jobs[1]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("upstream down"));
+    assert!(jobs[1]["last_finished_at"].is_string());
+}
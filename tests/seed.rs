@@ -0,0 +1,127 @@
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::model::{AdminAuditEntry, Product, PurchaseToken};
+use yral_billing::schema::{admin_audit_log, orders, products, purchase_tokens, revenue_events};
+use yral_billing::seed::{seed_database, SeedReport, SEED_PACKAGE_NAME};
+use yral_billing::types::{PurchaseEnvironment, PurchaseTokenStatus};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        let db_path = format!("./test_seed_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+// Every token status, audit action and catalog state is represented
+#[test]
+fn test_seed_covers_every_status() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let report = seed_database(&mut conn, now).unwrap();
+    assert_eq!(
+        report,
+        SeedReport {
+            purchase_tokens: 6,
+            orders: 3,
+            revenue_events: 3,
+            audit_entries: 3,
+            products: 2,
+        }
+    );
+
+    let tokens: Vec<PurchaseToken> = purchase_tokens::table.load(&mut conn).unwrap();
+    for status in [
+        PurchaseTokenStatus::Pending,
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseTokenStatus::Expired,
+    ] {
+        assert!(tokens.iter().any(|token| token.status == status));
+    }
+    for environment in [
+        PurchaseEnvironment::Production,
+        PurchaseEnvironment::Sandbox,
+        PurchaseEnvironment::Manual,
+    ] {
+        assert!(tokens.iter().any(|token| token.environment == environment));
+    }
+    assert!(tokens
+        .iter()
+        .filter(|token| token.status == PurchaseTokenStatus::Expired)
+        .all(|token| token.expiry_at < now));
+
+    let audit: Vec<AdminAuditEntry> = admin_audit_log::table.load(&mut conn).unwrap();
+    assert_eq!(audit.len(), 3);
+
+    let catalog: Vec<Product> = products::table
+        .filter(products::package_name.eq(SEED_PACKAGE_NAME))
+        .load(&mut conn)
+        .unwrap();
+    assert_eq!(
+        catalog.iter().filter(|product| product.is_active()).count(),
+        1
+    );
+}
+
+// Seeding again replaces the seeded rows and leaves real data alone
+#[test]
+fn test_seed_is_repeatable() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let real = PurchaseToken::new(
+        "real-user".to_string(),
+        "real-token".to_string(),
+        now + chrono::Duration::days(30),
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&real)
+        .execute(&mut conn)
+        .unwrap();
+
+    seed_database(&mut conn, now).unwrap();
+    seed_database(&mut conn, now).unwrap();
+
+    let token_count: i64 = purchase_tokens::table
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(token_count, 7);
+    let order_count: i64 = orders::table.count().get_result(&mut conn).unwrap();
+    assert_eq!(order_count, 3);
+    let revenue_count: i64 = revenue_events::table.count().get_result(&mut conn).unwrap();
+    assert_eq!(revenue_count, 3);
+    let audit_count: i64 = admin_audit_log::table
+        .count()
+        .get_result(&mut conn)
+        .unwrap();
+    assert_eq!(audit_count, 3);
+
+    assert!(purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq("real-token"))
+        .first::<PurchaseToken>(&mut conn)
+        .is_ok());
+}
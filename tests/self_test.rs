@@ -0,0 +1,67 @@
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::self_test::{check_migrations, CheckOutcome};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDb {
+    db_path: String,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        Self {
+            db_path: format!("./test_self_test_{}.db", uuid::Uuid::new_v4()),
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+// A fully migrated database passes
+#[test]
+fn test_migrated_database_passes() {
+    let db = TestDb::new();
+    db.conn().run_pending_migrations(MIGRATIONS).unwrap();
+
+    assert!(matches!(
+        check_migrations(&db.db_path),
+        CheckOutcome::Passed(_)
+    ));
+}
+
+// Pending migrations are reported but applied on startup, so they don't fail the check
+#[test]
+fn test_pending_migrations_pass() {
+    let db = TestDb::new();
+
+    match check_migrations(&db.db_path) {
+        CheckOutcome::Passed(detail) => assert!(detail.starts_with("0 applied")),
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+// A database migrated by a newer build fails the check
+#[test]
+fn test_unknown_migration_fails() {
+    let db = TestDb::new();
+    let mut conn = db.conn();
+    conn.run_pending_migrations(MIGRATIONS).unwrap();
+    diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ('29991231000000')")
+        .execute(&mut conn)
+        .unwrap();
+
+    match check_migrations(&db.db_path) {
+        CheckOutcome::Failed(detail) => assert!(detail.contains("29991231000000")),
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::{middleware, Router};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::cache::{Cache, MemoryCache};
+use yral_billing::metrics::CREDIT_CHANGES_TOTAL;
+use yral_billing::routes::credits::deduct_credits;
+use yral_billing::service_auth::{
+    service_or_jwt_auth, sign, ServiceAuth, ServiceKey, SERVICE_HEADER, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const SECRET: &str = "video-secret";
+const PATH: &str = "/credits/deduct";
+const BODY: &str = r#"{"user_principal": "2vxsx-fae", "amount": 5}"#;
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_service_auth_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+fn service_auth(requests_per_minute: Option<u32>) -> ServiceAuth {
+    let mut keys = HashMap::new();
+    keys.insert(
+        "video".to_string(),
+        ServiceKey {
+            secret: SECRET.to_string(),
+            requests_per_minute,
+        },
+    );
+    ServiceAuth::new(keys, 300)
+}
+
+#[tokio::test]
+async fn test_valid_signature_is_accepted_once() {
+    let auth = service_auth(None);
+    let now = 1_780_000_000;
+    let signature = sign(SECRET, now, "POST", PATH, BODY.as_bytes());
+
+    assert_eq!(
+        auth.verify("video", now, &signature, "POST", PATH, BODY.as_bytes(), now)
+            .await,
+        Ok(())
+    );
+
+    // The exact same request can't be replayed
+    assert_eq!(
+        auth.verify(
+            "video",
+            now,
+            &signature,
+            "POST",
+            PATH,
+            BODY.as_bytes(),
+            now + 1
+        )
+        .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_signatures_are_rejected() {
+    let auth = service_auth(None);
+    let now = 1_780_000_000;
+    let signature = sign(SECRET, now, "POST", PATH, BODY.as_bytes());
+
+    // Tampered body
+    assert_eq!(
+        auth.verify("video", now, &signature, "POST", PATH, b"{}", now)
+            .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+    // Signed for another route
+    assert_eq!(
+        auth.verify(
+            "video",
+            now,
+            &signature,
+            "POST",
+            "/credits/increment",
+            BODY.as_bytes(),
+            now
+        )
+        .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+    // Outside the replay window
+    assert_eq!(
+        auth.verify(
+            "video",
+            now,
+            &signature,
+            "POST",
+            PATH,
+            BODY.as_bytes(),
+            now + 301
+        )
+        .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+    // Unknown caller
+    assert_eq!(
+        auth.verify("other", now, &signature, "POST", PATH, BODY.as_bytes(), now)
+            .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_caller() {
+    let auth = service_auth(Some(2));
+    let now = 1_780_000_020;
+
+    for (i, expected) in [Ok(()), Ok(()), Err(StatusCode::TOO_MANY_REQUESTS)]
+        .into_iter()
+        .enumerate()
+    {
+        let timestamp = now + i as i64;
+        let signature = sign(SECRET, timestamp, "POST", PATH, BODY.as_bytes());
+        assert_eq!(
+            auth.verify(
+                "video",
+                timestamp,
+                &signature,
+                "POST",
+                PATH,
+                BODY.as_bytes(),
+                timestamp
+            )
+            .await,
+            expected
+        );
+    }
+
+    // The next minute starts a new window
+    let timestamp = now + 60;
+    let signature = sign(SECRET, timestamp, "POST", PATH, BODY.as_bytes());
+    assert_eq!(
+        auth.verify(
+            "video",
+            timestamp,
+            &signature,
+            "POST",
+            PATH,
+            BODY.as_bytes(),
+            timestamp
+        )
+        .await,
+        Ok(())
+    );
+}
+
+// Replicas sharing a cache refuse a signature another one accepted, and share the rate limit
+#[tokio::test]
+async fn test_replicas_share_replay_and_rate_limit_state() {
+    let shared: Arc<dyn Cache> = Arc::new(MemoryCache::default());
+    let first = service_auth(Some(2)).with_cache(shared.clone());
+    let second = service_auth(Some(2)).with_cache(shared);
+    let now = 1_780_000_020;
+
+    let signature = sign(SECRET, now, "POST", PATH, BODY.as_bytes());
+    assert_eq!(
+        first
+            .verify("video", now, &signature, "POST", PATH, BODY.as_bytes(), now)
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        second
+            .verify("video", now, &signature, "POST", PATH, BODY.as_bytes(), now)
+            .await,
+        Err(StatusCode::UNAUTHORIZED)
+    );
+
+    let signature = sign(SECRET, now + 1, "POST", PATH, BODY.as_bytes());
+    assert_eq!(
+        second
+            .verify(
+                "video",
+                now + 1,
+                &signature,
+                "POST",
+                PATH,
+                BODY.as_bytes(),
+                now + 1
+            )
+            .await,
+        Ok(())
+    );
+    let signature = sign(SECRET, now + 2, "POST", PATH, BODY.as_bytes());
+    assert_eq!(
+        first
+            .verify(
+                "video",
+                now + 2,
+                &signature,
+                "POST",
+                PATH,
+                BODY.as_bytes(),
+                now + 2
+            )
+            .await,
+        Err(StatusCode::TOO_MANY_REQUESTS)
+    );
+}
+
+// A signed service call reaches the handler and is attributed to the service
+#[tokio::test]
+async fn test_signed_request_deducts_credits() {
+    let _db_guard = TestDbGuard::new();
+    let mut app_state = AppState::new().await;
+    app_state.service_auth = service_auth(None);
+    let metrics = app_state.metrics.clone();
+
+    let app = Router::new()
+        .route(PATH, post(deduct_credits))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            service_or_jwt_auth,
+        ))
+        .with_state(app_state);
+
+    let request = |timestamp: i64, signature: String| {
+        Request::builder()
+            .method("POST")
+            .uri(PATH)
+            .header("content-type", "application/json")
+            .header(SERVICE_HEADER, "video")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::from(BODY))
+            .unwrap()
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let signature = sign(SECRET, now, "POST", PATH, BODY.as_bytes());
+    let response = app.clone().oneshot(request(now, signature)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        metrics.counter(
+            CREDIT_CHANGES_TOTAL,
+            &[("caller", "service:video"), ("action", "deducted")]
+        ),
+        1
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request(now, "00".repeat(32)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Without the service header a JWT is required
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(PATH)
+                .header("content-type", "application/json")
+                .body(Body::from(BODY))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
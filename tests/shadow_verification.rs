@@ -0,0 +1,98 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use chrono::Utc;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::feature_flags::{CANDIDATE_VERIFICATION, SHADOW_VERIFICATION};
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::metrics::SHADOW_COMPARISONS_TOTAL;
+use yral_billing::routes::purchase::verify_purchase;
+use yral_billing::test_support::{memory_state, new_test_user, TEST_PACKAGE_NAME};
+use yral_billing::AppState;
+
+async fn state_with_flag(flag: Option<&str>) -> AppState {
+    let app_state = memory_state().await;
+    if let Some(flag) = flag {
+        app_state
+            .feature_flags
+            .set(
+                &mut app_state.get_db_connection().unwrap(),
+                flag,
+                true,
+                Utc::now().naive_utc(),
+            )
+            .unwrap();
+    }
+    app_state
+}
+
+async fn verify(app_state: &AppState, user: &str) -> (StatusCode, serde_json::Value) {
+    let body = serde_json::json!({
+        "user_id": user,
+        "package_name": TEST_PACKAGE_NAME,
+        "product_id": "mock-product-id",
+        "purchase_token": format!("token_{}", uuid::Uuid::new_v4()),
+    });
+    let res = Router::new()
+        .route("/google/verify", post(verify_purchase))
+        .with_state(app_state.clone())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/google/verify")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+fn comparisons(app_state: &AppState, outcome: &str) -> u64 {
+    app_state.metrics.counter(
+        SHADOW_COMPARISONS_TOTAL,
+        &[("experiment", "verification"), ("outcome", outcome)],
+    )
+}
+
+// In shadow the current rules answer, the candidate's refusal is only counted
+#[tokio::test]
+async fn test_shadow_counts_divergence_without_changing_result() {
+    let app_state = state_with_flag(Some(SHADOW_VERIFICATION)).await;
+
+    let (status, _) = verify(&app_state, &new_test_user()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(comparisons(&app_state, "divergence"), 1);
+
+    let (status, _) = verify(&app_state, MOCK_SUBSCRIPTION_ACCOUNT_ID).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(comparisons(&app_state, "match"), 1);
+}
+
+// With the candidate authoritative its answer is returned, the current rules still run
+#[tokio::test]
+async fn test_candidate_answers_when_authoritative() {
+    let app_state = state_with_flag(Some(CANDIDATE_VERIFICATION)).await;
+
+    let (status, body) = verify(&app_state, &new_test_user()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "ACCOUNT_MISMATCH");
+    assert_eq!(comparisons(&app_state, "divergence"), 1);
+}
+
+// Without either flag the candidate isn't run
+#[tokio::test]
+async fn test_shadow_off_by_default() {
+    let app_state = state_with_flag(None).await;
+
+    let (status, _) = verify(&app_state, &new_test_user()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(comparisons(&app_state, "divergence"), 0);
+    assert_eq!(comparisons(&app_state, "match"), 0);
+}
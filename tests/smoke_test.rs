@@ -0,0 +1,88 @@
+use yral_billing::build_router;
+use yral_billing::integrations::push_auth::MOCK_PUSH_TOKEN;
+use yral_billing::self_test::CheckOutcome;
+use yral_billing::smoke_test::{run_smoke_test, SmokeTarget, SmokeTestReport};
+use yral_billing::test_support::memory_state;
+
+/// Serve the full router with mocked integrations on a local port
+async fn start_service() -> String {
+    let app = build_router(memory_state().await);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}/", addr)
+}
+
+fn outcome<'a>(report: &'a SmokeTestReport, name: &str) -> &'a CheckOutcome {
+    &report
+        .checks
+        .iter()
+        .find(|check| check.name == name)
+        .unwrap()
+        .outcome
+}
+
+// A mock-mode deployment passes every check, the credits round-trip needs a JWT
+#[tokio::test]
+async fn test_mock_deployment_passes() {
+    let mut target = SmokeTarget::new(&start_service().await);
+    target.token = None;
+    target.push_token = Some(MOCK_PUSH_TOKEN.to_string());
+
+    let report = run_smoke_test(&target).await;
+
+    assert!(report.passed(), "{}", report);
+    for name in [
+        "health",
+        "openapi spec",
+        "mock verification",
+        "rtdn test notification",
+    ] {
+        assert!(
+            matches!(outcome(&report, name), CheckOutcome::Passed(_)),
+            "{}",
+            name
+        );
+    }
+    assert!(matches!(
+        outcome(&report, "credits round-trip"),
+        CheckOutcome::Skipped(_)
+    ));
+    assert!(report.to_string().ends_with("Smoke test passed"));
+}
+
+// An invalid JWT fails the credits round-trip and with it the whole run
+#[tokio::test]
+async fn test_rejected_jwt_fails() {
+    let mut target = SmokeTarget::new(&start_service().await);
+    target.token = Some("not-a-jwt".to_string());
+
+    let report = run_smoke_test(&target).await;
+
+    assert!(!report.passed());
+    match outcome(&report, "credits round-trip") {
+        CheckOutcome::Failed(detail) => assert!(detail.starts_with("/credits/increment")),
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+}
+
+// Nothing listening fails every check instead of aborting the run
+#[tokio::test]
+async fn test_unreachable_deployment_fails() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let mut target = SmokeTarget::new(&format!("http://{}", addr));
+    target.token = None;
+    let report = run_smoke_test(&target).await;
+
+    assert!(!report.passed());
+    assert!(matches!(
+        outcome(&report, "health"),
+        CheckOutcome::Failed(_)
+    ));
+    assert!(report.to_string().ends_with("Smoke test failed"));
+}
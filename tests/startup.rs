@@ -0,0 +1,77 @@
+use std::env;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use tower::ServiceExt; // for `oneshot`
+use tower_http::catch_panic::CatchPanicLayer;
+use yral_billing::config::{env_interval_secs, env_number, ConfigError};
+use yral_billing::error::{panic_response, CORRELATION_ID_HEADER};
+
+async fn panicking_handler() -> &'static str {
+    panic!("boom")
+}
+
+// A panicking handler answers with a 500 ApiResponse that carries a correlation id
+#[tokio::test]
+async fn test_panic_becomes_api_response() {
+    let app = Router::new()
+        .route("/panic", get(panicking_handler))
+        .layer(CatchPanicLayer::custom(panic_response));
+
+    let req = Request::builder()
+        .uri("/panic")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let correlation_id = res.headers()[CORRELATION_ID_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(uuid::Uuid::parse_str(&correlation_id).is_ok());
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["success"], false);
+    assert_eq!(response["code"], "INTERNAL_ERROR");
+    assert!(response["error"]
+        .as_str()
+        .unwrap()
+        .contains(&correlation_id));
+}
+
+// Invalid numeric settings are reported as typed errors instead of panicking
+#[test]
+fn test_invalid_numbers_are_config_errors() {
+    env::set_var("STARTUP_TEST_NUMBER", "ten");
+    assert!(matches!(
+        env_number::<u16>("STARTUP_TEST_NUMBER", 3000),
+        Err(ConfigError::InvalidNumber {
+            name: "STARTUP_TEST_NUMBER",
+            ..
+        })
+    ));
+    env::remove_var("STARTUP_TEST_NUMBER");
+    assert_eq!(
+        env_number::<u16>("STARTUP_TEST_NUMBER", 3000).unwrap(),
+        3000
+    );
+
+    env::set_var("STARTUP_TEST_INTERVAL", "0");
+    assert!(matches!(
+        env_interval_secs("STARTUP_TEST_INTERVAL", 30),
+        Err(ConfigError::ZeroInterval { .. })
+    ));
+    env::set_var("STARTUP_TEST_INTERVAL", "45");
+    assert_eq!(
+        env_interval_secs("STARTUP_TEST_INTERVAL", 30).unwrap(),
+        Duration::from_secs(45)
+    );
+    env::remove_var("STARTUP_TEST_INTERVAL");
+}
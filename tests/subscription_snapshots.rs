@@ -0,0 +1,93 @@
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use uuid;
+use yral_billing::debug_log::REDACTED;
+use yral_billing::integrations::google_play::snapshots::{
+    prune_snapshots, snapshot_body, snapshots_for_token, store_snapshot,
+};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const SUBSCRIPTION_V2: &str = include_str!("fixtures/google_play/subscription_v2.json");
+
+struct TestDbGuard {
+    db_path: String,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_snapshots_{}.db", uuid::Uuid::new_v4());
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self { db_path: test_db }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
+
+// Snapshots round-trip with personal data redacted and everything else kept for replay
+#[test]
+fn test_snapshot_is_redacted_and_restored() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut response: serde_json::Value = serde_json::from_str(SUBSCRIPTION_V2).unwrap();
+    response["linkedPurchaseToken"] = "old-token".into();
+    response["subscribeWithGoogleInfo"] = serde_json::json!({
+        "emailAddress": "user@example.com",
+        "givenName": "Jane",
+        "familyName": "Doe",
+        "profileName": "Jane Doe",
+    });
+    let body = serde_json::to_vec(&response).unwrap();
+    store_snapshot(&mut conn, "token-1", &body, now).unwrap();
+    store_snapshot(&mut conn, "token-2", SUBSCRIPTION_V2.as_bytes(), now).unwrap();
+
+    let snapshots = snapshots_for_token(&mut conn, "token-1").unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert!(snapshots[0].body.len() < body.len());
+
+    let restored = snapshot_body(&snapshots[0]).unwrap();
+    let info = &restored["subscribeWithGoogleInfo"];
+    for key in ["emailAddress", "givenName", "familyName", "profileName"] {
+        assert_eq!(info[key], REDACTED);
+    }
+    assert_eq!(restored["linkedPurchaseToken"], "old-token");
+    assert_eq!(restored["lineItems"], response["lineItems"]);
+    assert_eq!(
+        restored["externalAccountIdentifiers"],
+        response["externalAccountIdentifiers"]
+    );
+}
+
+#[test]
+fn test_old_snapshots_are_pruned() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    let body = SUBSCRIPTION_V2.as_bytes();
+
+    store_snapshot(
+        &mut conn,
+        "token-1",
+        body,
+        now - chrono::Duration::days(100),
+    )
+    .unwrap();
+    store_snapshot(&mut conn, "token-1", body, now - chrono::Duration::days(10)).unwrap();
+
+    let pruned = prune_snapshots(&mut conn, now - chrono::Duration::days(90)).unwrap();
+    assert_eq!(pruned, 1);
+    let remaining = snapshots_for_token(&mut conn, "token-1").unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].fetched_at, now - chrono::Duration::days(10));
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::error::{AppError, AppResult};
+use yral_billing::integrations::user_info::UserInfoApi;
+use yral_billing::model::Subscription;
+use yral_billing::routes::entitlements::get_entitlements;
+use yral_billing::schema::{purchase_tokens, subscriptions};
+use yral_billing::subscriptions::{
+    grant_pro_for_subscription, revoke_pro_for_subscription, upsert_subscription,
+};
+use yral_billing::test_support::{test_user, PurchaseTokenBuilder};
+use yral_billing::types::{Plan, PurchaseTokenStatus, SubscriptionSource, SubscriptionStatus};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_subscriptions_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Canister client that fails every call, so a skipped grant or revoke is observable
+struct UnreachableUserInfo;
+
+#[async_trait]
+impl UserInfoApi for UnreachableUserInfo {
+    async fn grant_pro_plan(&self, _product_id: &str, _user_id: &str) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn revoke_pro_plan(&self, _user_id: &str) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn get_plan(&self, _user_id: &str) -> AppResult<Plan> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn get_credits(&self, _user_id: &str) -> AppResult<u32> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn deduct_credits(&self, _user_principal: Principal, _amount: u32) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+
+    async fn increment_credits(&self, _user_principal: Principal, _amount: u32) -> AppResult<()> {
+        Err(AppError::ServiceAccessFailed("unreachable".to_string()))
+    }
+}
+
+fn stripe_subscription(user_id: &str, days: i64) -> Subscription {
+    let now = chrono::Utc::now().naive_utc();
+    Subscription::new(
+        user_id.to_string(),
+        SubscriptionSource::Stripe,
+        format!("sub_{}", uuid::Uuid::new_v4()),
+        SubscriptionStatus::Active,
+        now,
+        now + chrono::Duration::days(days),
+    )
+}
+
+fn subscription_for(conn: &mut SqliteConnection, reference: &str) -> Option<Subscription> {
+    subscriptions::table
+        .filter(subscriptions::source_ref.eq(reference))
+        .first(conn)
+        .optional()
+        .unwrap()
+}
+
+// Every change to a purchase token is mirrored into its Google Play subscription
+#[test]
+fn test_purchase_tokens_are_mirrored() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+
+    let token = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+    let stored = subscription_for(&mut conn, &token.purchase_token).unwrap();
+    assert_eq!(stored.source, SubscriptionSource::GooglePlay);
+    assert_eq!(stored.status, SubscriptionStatus::Active);
+    assert_eq!(stored.user_id, "user_1");
+    assert_eq!(stored.expires_at, token.expiry_at);
+
+    diesel::update(purchase_tokens::table.filter(purchase_tokens::id.eq(&token.id)))
+        .set(purchase_tokens::status.eq(PurchaseTokenStatus::Expired))
+        .execute(&mut conn)
+        .unwrap();
+    let stored = subscription_for(&mut conn, &token.purchase_token).unwrap();
+    assert_eq!(stored.status, SubscriptionStatus::Expired);
+
+    diesel::delete(purchase_tokens::table.filter(purchase_tokens::id.eq(&token.id)))
+        .execute(&mut conn)
+        .unwrap();
+    assert!(subscription_for(&mut conn, &token.purchase_token).is_none());
+}
+
+// A user subscribed on the web and on Google Play is granted and revoked only once
+#[tokio::test]
+async fn test_other_source_covers_grant_and_revoke() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    let web = stripe_subscription("user_1", 30);
+    upsert_subscription(&mut conn, &web).unwrap();
+    let token = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+
+    let granted = grant_pro_for_subscription(
+        &mut conn,
+        &UnreachableUserInfo,
+        "yral_pro",
+        "user_1",
+        SubscriptionSource::GooglePlay,
+        &token.purchase_token,
+        now,
+    )
+    .await
+    .unwrap();
+    assert!(!granted);
+
+    let revoked = revoke_pro_for_subscription(
+        &mut conn,
+        &UnreachableUserInfo,
+        "user_1",
+        SubscriptionSource::GooglePlay,
+        &token.purchase_token,
+        now,
+    )
+    .await
+    .unwrap();
+    assert!(!revoked);
+
+    // Once the web subscription ends, the Google one is the only source and reaches the canister
+    let mut ended = web.clone();
+    ended.status = SubscriptionStatus::Expired;
+    upsert_subscription(&mut conn, &ended).unwrap();
+    let stored: i64 = subscriptions::table.count().get_result(&mut conn).unwrap();
+    assert_eq!(stored, 2);
+
+    let result = revoke_pro_for_subscription(
+        &mut conn,
+        &UnreachableUserInfo,
+        "user_1",
+        SubscriptionSource::GooglePlay,
+        &token.purchase_token,
+        now,
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+// Entitlements report the source of the longest running subscription
+#[tokio::test]
+async fn test_entitlements_use_any_source() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+
+    let user_id = test_user("user_1");
+    PurchaseTokenBuilder::new(&user_id)
+        .expiry_at((chrono::Utc::now() + chrono::Duration::days(5)).naive_utc())
+        .insert(&mut conn);
+    upsert_subscription(&mut conn, &stripe_subscription(&user_id, 30)).unwrap();
+
+    let app_state = AppState::new().await;
+    let app = Router::new()
+        .route(
+            "/entitlements/{user_id}",
+            axum::routing::get(get_entitlements),
+        )
+        .with_state(app_state);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(format!("/entitlements/{}", user_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["plan"], "pro");
+    assert_eq!(response["data"]["source_store"], "stripe");
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::{middleware, Router};
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::model::{AccessOutboxEntry, AdminAuditEntry, PurchaseToken};
+use yral_billing::schema::{access_outbox, admin_audit_log, purchase_tokens};
+use yral_billing::service_auth::{
+    require_service_auth, sign, ServiceAuth, ServiceKey, SERVICE_HEADER, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
+use yral_billing::test_support::{test_app, PurchaseTokenBuilder};
+use yral_billing::types::{AuditAction, OutboxAction, PurchaseEnvironment, PurchaseTokenStatus};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+const SECRET: &str = "accounts-secret";
+const PATH: &str = "/internal/users/user_1/teardown";
+const BODY: &str = r#"{"reason": "account deleted"}"#;
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_teardown_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+/// Test app checking service signatures, as the `Service` auth policy does
+async fn service_app() -> Router {
+    let mut keys = HashMap::new();
+    keys.insert(
+        "accounts".to_string(),
+        ServiceKey {
+            secret: SECRET.to_string(),
+            requests_per_minute: None,
+        },
+    );
+    let mut app_state = AppState::new().await;
+    app_state.service_auth = ServiceAuth::new(keys, 300);
+
+    test_app(app_state.clone()).layer(middleware::from_fn_with_state(
+        app_state,
+        require_service_auth,
+    ))
+}
+
+fn signed_request() -> Request<Body> {
+    let now = chrono::Utc::now().timestamp();
+    Request::builder()
+        .method("POST")
+        .uri(PATH)
+        .header("content-type", "application/json")
+        .header(SERVICE_HEADER, "accounts")
+        .header(TIMESTAMP_HEADER, now.to_string())
+        .header(
+            SIGNATURE_HEADER,
+            sign(SECRET, now, "POST", PATH, BODY.as_bytes()),
+        )
+        .body(Body::from(BODY))
+        .unwrap()
+}
+
+fn stored(conn: &mut SqliteConnection, token: &PurchaseToken) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::id.eq(&token.id))
+        .first(conn)
+        .unwrap()
+}
+
+// One call cancels renewals, expires purchases, queues the revoke and leaves an audit trail
+#[tokio::test]
+async fn test_teardown_cancels_expires_and_revokes() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let google = PurchaseTokenBuilder::new("user_1").insert(&mut conn);
+    // Without a package name Google can't be asked, the teardown goes ahead regardless
+    let unknown_package = PurchaseTokenBuilder::new("user_1")
+        .package_name(None)
+        .insert(&mut conn);
+    let manual = PurchaseTokenBuilder::new("user_1")
+        .package_name(None)
+        .environment(PurchaseEnvironment::Manual)
+        .insert(&mut conn);
+
+    let res = service_app().await.oneshot(signed_request()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["operator"], "service:accounts");
+    assert_eq!(response["data"]["canceled_subscriptions"], 1);
+    assert_eq!(response["data"]["failed_cancellations"], 1);
+    assert_eq!(response["data"]["expired_purchases"], 3);
+
+    for token in [&google, &unknown_package, &manual] {
+        assert_eq!(
+            stored(&mut conn, token).status,
+            PurchaseTokenStatus::Expired
+        );
+    }
+    assert!(!stored(&mut conn, &google).auto_renewing);
+    assert!(stored(&mut conn, &unknown_package).auto_renewing);
+
+    let outbox: Vec<AccessOutboxEntry> = access_outbox::table.load(&mut conn).unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].user_id, "user_1");
+    assert_eq!(outbox[0].action, OutboxAction::RevokePro);
+
+    let audit: Vec<AdminAuditEntry> = admin_audit_log::table.load(&mut conn).unwrap();
+    let actions: Vec<AuditAction> = audit.iter().map(|entry| entry.action).collect();
+    assert_eq!(
+        actions,
+        vec![AuditAction::CancelSubscription, AuditAction::TeardownUser]
+    );
+    assert_eq!(
+        audit[0].purchase_token.as_deref(),
+        Some(google.purchase_token.as_str())
+    );
+    assert!(audit
+        .iter()
+        .all(|entry| entry.operator == "service:accounts" && entry.reason == "account deleted"));
+}
+
+// Users can't reach the route, only signed service calls can
+#[tokio::test]
+async fn test_teardown_requires_signature() {
+    let _db_guard = TestDbGuard::new();
+    let app = service_app().await;
+
+    let unsigned = Request::builder()
+        .method("POST")
+        .uri(PATH)
+        .header("content-type", "application/json")
+        .header("authorization", "Bearer not-a-service")
+        .body(Body::from(BODY))
+        .unwrap();
+    let res = app.clone().oneshot(unsigned).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let mut forged = signed_request();
+    forged
+        .headers_mut()
+        .insert(SIGNATURE_HEADER, "00".repeat(32).parse().unwrap());
+    let res = app.oneshot(forged).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
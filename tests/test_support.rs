@@ -0,0 +1,94 @@
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use base64::prelude::*;
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{
+    DeveloperNotification, PubSubMessage, PurchaseTokenStatus, SubscriptionNotificationType,
+};
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+fn stored_tokens(conn: &mut SqliteConnection) -> i64 {
+    purchase_tokens::table.count().get_result(conn).unwrap()
+}
+
+// Every state gets its own database, without a file or `DATABASE_URL`
+#[tokio::test]
+async fn test_memory_databases_are_isolated() {
+    let first = memory_state().await;
+    let second = memory_state().await;
+
+    PurchaseTokenBuilder::new("user_1").insert(&mut first.get_db_connection().unwrap());
+
+    // Other pooled connections see the same database
+    assert_eq!(stored_tokens(&mut first.get_db_connection().unwrap()), 1);
+    assert_eq!(stored_tokens(&mut second.get_db_connection().unwrap()), 0);
+}
+
+#[tokio::test]
+async fn test_renewal_from_builders() {
+    let now = chrono::Utc::now().naive_utc();
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(SubscriptionResponseBuilder::new().build());
+    let mut conn = app_state.get_db_connection().unwrap();
+    let token = PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .status(PurchaseTokenStatus::Expired)
+        .expiry_at(now - chrono::Duration::days(1))
+        .insert(&mut conn);
+
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state);
+    let rtdn =
+        RtdnBuilder::subscription(SubscriptionNotificationType::Renewed, &token.purchase_token);
+    let res = app.oneshot(rtdn.request()).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let renewed: PurchaseToken = purchase_tokens::table
+        .find(&token.id)
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(renewed.status, PurchaseTokenStatus::AccessGranted);
+    assert!(renewed.expiry_at > now);
+}
+
+#[test]
+fn test_rtdn_envelope_decodes() {
+    let event_time = chrono::Utc::now() - chrono::Duration::minutes(5);
+    let envelope = RtdnBuilder::subscription(SubscriptionNotificationType::Canceled, "token_1")
+        .package_name("com.yral.android")
+        .event_time(event_time)
+        .envelope();
+
+    let message: PubSubMessage = serde_json::from_value(envelope).unwrap();
+    let data = BASE64_STANDARD.decode(message.message.data).unwrap();
+    let notification: DeveloperNotification = serde_json::from_slice(&data).unwrap();
+
+    assert_eq!(notification.package_name, "com.yral.android");
+    assert_eq!(notification.subscription_purchase_token(), Some("token_1"));
+    assert_eq!(
+        notification
+            .event_time()
+            .unwrap()
+            .and_utc()
+            .timestamp_millis(),
+        event_time.timestamp_millis()
+    );
+    assert_eq!(
+        notification
+            .subscription_notification
+            .unwrap()
+            .notification_type,
+        SubscriptionNotificationType::Canceled
+    );
+}
@@ -0,0 +1,97 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use chrono::Utc;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::routes::admin::get_token_diff;
+use yral_billing::test_support::{
+    memory_state, new_test_user, FixedGooglePlay, PurchaseTokenBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{PurchaseTokenStatus, SubscriptionState};
+use yral_billing::AppState;
+
+async fn get_diff(app_state: AppState, id: &str) -> (StatusCode, serde_json::Value) {
+    let app = Router::new()
+        .route("/admin/tokens/{id}/diff", get(get_token_diff))
+        .with_state(app_state);
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/admin/tokens/{}/diff", id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// A token Google agrees with has no mismatches, and is found by its purchase token too
+#[tokio::test]
+async fn test_matching_token_has_no_mismatches() {
+    let mut app_state = memory_state().await;
+    let expiry = Utc::now().naive_utc() + chrono::Duration::days(30);
+    app_state.google_play =
+        FixedGooglePlay::new(SubscriptionResponseBuilder::new().expiry_at(expiry).build());
+    let token = PurchaseTokenBuilder::new(&new_test_user())
+        .expiry_at(expiry)
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    for id in [&token.id, &token.purchase_token] {
+        let (status, body) = get_diff(app_state.clone(), id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["stored"]["id"], token.id.as_str());
+        assert_eq!(
+            body["data"]["google"]["subscriptionState"],
+            "SUBSCRIPTION_STATE_ACTIVE"
+        );
+        assert!(body["data"]["mismatches"].as_array().unwrap().is_empty());
+    }
+}
+
+// "I paid but don't have Pro": Google renewed and acknowledged, the stored token expired
+#[tokio::test]
+async fn test_diverged_token_reports_mismatches() {
+    let mut app_state = memory_state().await;
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .state(SubscriptionState::Active)
+            .auto_renewing(true)
+            .acknowledged()
+            .build(),
+    );
+    let token = PurchaseTokenBuilder::new(&new_test_user())
+        .status(PurchaseTokenStatus::Expired)
+        .expiry_at(Utc::now().naive_utc() - chrono::Duration::days(1))
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    let (status, body) = get_diff(app_state, &token.id).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let kinds: Vec<&str> = body["data"]["mismatches"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|mismatch| mismatch["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            "expiry_drift",
+            "state_divergence",
+            "acknowledgment_mismatch"
+        ]
+    );
+    assert_eq!(body["data"]["mismatches"][1]["stored"], "expired");
+}
+
+#[tokio::test]
+async fn test_unknown_token_is_bad_request() {
+    let (status, _) = get_diff(memory_state().await, "missing").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
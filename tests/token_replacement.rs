@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::routing::post;
+use axum::Router;
+use diesel::prelude::*;
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppResult;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::model::PurchaseToken;
+use yral_billing::routes::rtdn::handle_rtdn_webhook;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::subscriptions::replace_duplicate_tokens;
+use yral_billing::test_support::{
+    memory_state, FixedGooglePlay, PurchaseTokenBuilder, RtdnBuilder, SubscriptionResponseBuilder,
+};
+use yral_billing::types::{Plan, PurchaseTokenStatus, SubscriptionNotificationType};
+use yral_billing::AppState;
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+/// Canister fake counting Pro grants
+#[derive(Clone, Default)]
+struct CountingUserInfo {
+    grants: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl UserInfoApi for CountingUserInfo {
+    async fn grant_pro_plan(&self, _product_id: &str, _user_id: &str) -> AppResult<()> {
+        self.grants.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn revoke_pro_plan(&self, _user_id: &str) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn get_plan(&self, _user_id: &str) -> AppResult<Plan> {
+        Ok(Plan::Pro)
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+fn stored(app_state: &AppState, token: &str) -> PurchaseToken {
+    purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(token))
+        .first(&mut app_state.get_db_connection().unwrap())
+        .unwrap()
+}
+
+async fn notify(
+    app_state: &AppState,
+    notification_type: SubscriptionNotificationType,
+    token: &str,
+    expiry_at: chrono::NaiveDateTime,
+) {
+    let mut app_state = app_state.clone();
+    app_state.google_play = FixedGooglePlay::new(
+        SubscriptionResponseBuilder::new()
+            .expiry_at(expiry_at)
+            .build(),
+    );
+    let app = Router::new()
+        .route("/google/rtdn-webhook", post(handle_rtdn_webhook))
+        .with_state(app_state);
+    let rtdn = RtdnBuilder::subscription(notification_type, token);
+    app.oneshot(rtdn.request()).await.unwrap();
+}
+
+// Only the token lasting longest keeps access, the others point to it
+#[tokio::test]
+async fn test_longest_lasting_token_kept() {
+    let now = chrono::Utc::now().naive_utc();
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    for (token, days) in [("token_1", 5), ("token_2", 30), ("token_3", 10)] {
+        PurchaseTokenBuilder::new("user_1")
+            .purchase_token(token)
+            .expiry_at(now + chrono::Duration::days(days))
+            .insert(&mut conn);
+    }
+    PurchaseTokenBuilder::new("user_2")
+        .purchase_token("token_4")
+        .insert(&mut conn);
+
+    let mut replaced = replace_duplicate_tokens(&mut conn, "user_1").unwrap();
+    replaced.sort();
+    assert_eq!(replaced, vec!["token_1", "token_3"]);
+
+    let kept = stored(&app_state, "token_2");
+    assert_eq!(kept.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(kept.replaced_by, None);
+    for token in ["token_1", "token_3"] {
+        let token = stored(&app_state, token);
+        assert_eq!(token.status, PurchaseTokenStatus::Expired);
+        assert_eq!(token.replaced_by.as_deref(), Some("token_2"));
+    }
+    assert_eq!(
+        stored(&app_state, "token_4").status,
+        PurchaseTokenStatus::AccessGranted
+    );
+
+    // Nothing left to replace
+    assert!(replace_duplicate_tokens(&mut conn, "user_1")
+        .unwrap()
+        .is_empty());
+}
+
+// Buying again from another Google account replaces the older purchase
+#[tokio::test]
+async fn test_purchase_from_another_account_replaces_token() {
+    let now = chrono::Utc::now().naive_utc();
+    let app_state = memory_state().await;
+    PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .purchase_token("token_1")
+        .expiry_at(now + chrono::Duration::days(5))
+        .insert(&mut app_state.get_db_connection().unwrap());
+
+    notify(
+        &app_state,
+        SubscriptionNotificationType::Purchased,
+        "token_2",
+        now + chrono::Duration::days(30),
+    )
+    .await;
+
+    let old = stored(&app_state, "token_1");
+    assert_eq!(old.status, PurchaseTokenStatus::Expired);
+    assert_eq!(old.replaced_by.as_deref(), Some("token_2"));
+    assert_eq!(
+        stored(&app_state, "token_2").status,
+        PurchaseTokenStatus::AccessGranted
+    );
+}
+
+// A replaced token that keeps renewing doesn't grant Pro again
+#[tokio::test]
+async fn test_renewal_of_replaced_token_not_granted() {
+    let now = chrono::Utc::now().naive_utc();
+    let mut app_state = memory_state().await;
+    let user_info = CountingUserInfo::default();
+    app_state.user_info = Arc::new(user_info.clone());
+    let mut conn = app_state.get_db_connection().unwrap();
+    PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .purchase_token("token_1")
+        .status(PurchaseTokenStatus::Expired)
+        .expiry_at(now)
+        .replaced_by("token_2")
+        .insert(&mut conn);
+    PurchaseTokenBuilder::new(MOCK_USER_ID)
+        .purchase_token("token_2")
+        .expiry_at(now + chrono::Duration::days(30))
+        .insert(&mut conn);
+
+    notify(
+        &app_state,
+        SubscriptionNotificationType::Renewed,
+        "token_1",
+        now + chrono::Duration::days(10),
+    )
+    .await;
+
+    assert_eq!(user_info.grants.load(Ordering::SeqCst), 0);
+    let old = stored(&app_state, "token_1");
+    assert_eq!(old.status, PurchaseTokenStatus::Expired);
+    assert_eq!(old.replaced_by.as_deref(), Some("token_2"));
+
+    // Renewed past the other token, it takes over
+    notify(
+        &app_state,
+        SubscriptionNotificationType::Renewed,
+        "token_1",
+        now + chrono::Duration::days(40),
+    )
+    .await;
+
+    assert_eq!(user_info.grants.load(Ordering::SeqCst), 0);
+    let renewed = stored(&app_state, "token_1");
+    assert_eq!(renewed.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(renewed.replaced_by, None);
+    assert_eq!(
+        stored(&app_state, "token_2").replaced_by.as_deref(),
+        Some("token_1")
+    );
+}
@@ -0,0 +1,123 @@
+use yral_billing::error::AppError;
+use yral_billing::token_state::{TokenStateMachine, TransitionReason};
+use yral_billing::types::PurchaseTokenStatus;
+
+const STATUSES: [PurchaseTokenStatus; 3] = [
+    PurchaseTokenStatus::Pending,
+    PurchaseTokenStatus::AccessGranted,
+    PurchaseTokenStatus::Expired,
+];
+
+// Every (status, reason) pair either moves to the reason's one target or is refused with it
+#[test]
+fn test_next_agrees_with_the_table() {
+    for reason in TransitionReason::ALL {
+        let transition = TokenStateMachine::transition(reason);
+        assert_eq!(transition.reason, reason);
+        assert!(!transition.from.is_empty(), "{:?} can never apply", reason);
+
+        for from in STATUSES {
+            match TokenStateMachine::next(from, reason) {
+                Ok(to) => {
+                    assert!(TokenStateMachine::allows(from, reason));
+                    assert_eq!(to, transition.to);
+                }
+                Err(AppError::InvalidTransition {
+                    from: refused_from,
+                    to,
+                    reason: refused_reason,
+                }) => {
+                    assert!(!TokenStateMachine::allows(from, reason));
+                    assert_eq!(
+                        (refused_from, to, refused_reason),
+                        (from, transition.to, reason)
+                    );
+                }
+                Err(e) => panic!("unexpected error {}", e),
+            }
+        }
+    }
+}
+
+// Expired tokens only come back through a payment Google confirmed
+#[test]
+fn test_expired_needs_confirmed_payment() {
+    for reason in TransitionReason::ALL {
+        let to = TokenStateMachine::transition(reason).to;
+        if TokenStateMachine::allows(PurchaseTokenStatus::Expired, reason)
+            && to != PurchaseTokenStatus::Expired
+        {
+            assert!(
+                reason.confirms_payment(),
+                "{:?} revives expired tokens",
+                reason
+            );
+        }
+    }
+    assert!(matches!(
+        TokenStateMachine::next(PurchaseTokenStatus::Expired, TransitionReason::Refreshed),
+        Err(AppError::InvalidTransition { .. })
+    ));
+}
+
+// Only verify puts a token back to pending, and access is only granted on payment
+#[test]
+fn test_pending_and_access_need_google() {
+    for reason in TransitionReason::ALL {
+        let transition = TokenStateMachine::transition(reason);
+        if transition.to == PurchaseTokenStatus::Pending {
+            assert_eq!(reason, TransitionReason::Verified);
+        }
+        if transition.to == PurchaseTokenStatus::AccessGranted {
+            for from in transition.from {
+                assert!(
+                    *from == PurchaseTokenStatus::AccessGranted || reason.confirms_payment(),
+                    "{:?} grants access from {:?}",
+                    reason,
+                    from
+                );
+            }
+        }
+    }
+}
+
+// Ending a subscription twice is a no-op, so redelivered notifications don't fail
+#[test]
+fn test_ending_is_idempotent() {
+    for reason in TransitionReason::ALL {
+        if TokenStateMachine::transition(reason).to == PurchaseTokenStatus::Expired {
+            assert_eq!(
+                TokenStateMachine::next(PurchaseTokenStatus::Expired, reason).unwrap(),
+                PurchaseTokenStatus::Expired
+            );
+        }
+    }
+}
+
+// Every status is reachable from a new purchase, and every status can still end
+#[test]
+fn test_no_unreachable_or_trapped_statuses() {
+    let mut reachable = vec![PurchaseTokenStatus::Pending];
+    let mut i = 0;
+    while i < reachable.len() {
+        let from = reachable[i];
+        for reason in TransitionReason::ALL {
+            if let Ok(to) = TokenStateMachine::next(from, reason) {
+                if !reachable.contains(&to) {
+                    reachable.push(to);
+                }
+            }
+        }
+        i += 1;
+    }
+    assert_eq!(reachable.len(), STATUSES.len());
+
+    for from in STATUSES {
+        assert!(TransitionReason::ALL.iter().any(|reason| {
+            matches!(
+                TokenStateMachine::next(from, *reason),
+                Ok(PurchaseTokenStatus::Expired)
+            )
+        }));
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use yral_billing::db::token_versions::reserve_token;
+use yral_billing::error::AppError;
+use yral_billing::jobs::expiry_sweep::expire_tokens;
+use yral_billing::model::PurchaseToken;
+use yral_billing::schema::purchase_tokens;
+use yral_billing::test_support::{memory_state, new_test_user, PurchaseTokenBuilder};
+use yral_billing::types::PurchaseTokenStatus;
+
+// Any update moves the version on, even from writers that don't set it
+#[tokio::test]
+async fn test_update_bumps_version() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let token = PurchaseTokenBuilder::new(&new_test_user()).insert(&mut conn);
+    assert_eq!(token.version, 0);
+
+    for expected in [1, 2] {
+        diesel::update(purchase_tokens::table.find(&token.id))
+            .set(purchase_tokens::auto_renewing.eq(false))
+            .execute(&mut conn)
+            .unwrap();
+        let stored: PurchaseToken = purchase_tokens::table
+            .find(&token.id)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(stored.version, expected);
+    }
+}
+
+// A writer holding a stale copy fails with a retryable conflict instead of overwriting the
+// change it never saw
+#[tokio::test]
+async fn test_stale_write_conflicts() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = Utc::now().naive_utc();
+    let lapsed = PurchaseTokenBuilder::new(&new_test_user())
+        .expiry_at(now - Duration::minutes(5))
+        .insert(&mut conn);
+
+    // Another request extends the subscription after the sweep loaded it
+    let extended = now + Duration::days(30);
+    diesel::update(purchase_tokens::table.find(&lapsed.id))
+        .set(purchase_tokens::expiry_at.eq(extended))
+        .execute(&mut conn)
+        .unwrap();
+
+    let err = expire_tokens(&mut conn, std::slice::from_ref(&lapsed), now).unwrap_err();
+    assert!(matches!(err, AppError::TokenConflict(ref token) if *token == lapsed.purchase_token));
+    assert!(err.is_retryable());
+
+    let stored: PurchaseToken = purchase_tokens::table
+        .find(&lapsed.id)
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.status, PurchaseTokenStatus::AccessGranted);
+    assert_eq!(stored.expiry_at, extended);
+
+    // Retrying with the current row goes through
+    expire_tokens(&mut conn, &[stored], now).unwrap();
+}
+
+// Reserving a token before an IC call shuts out every other writer holding the same copy, so
+// only one of two racing revocations reaches the IC
+#[tokio::test]
+async fn test_reserve_token_shuts_out_stale_writers() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let token = PurchaseTokenBuilder::new(&new_test_user()).insert(&mut conn);
+
+    let reserved = reserve_token(&mut conn, &token).unwrap();
+    assert_eq!(reserved.version, token.version + 1);
+
+    let err = reserve_token(&mut conn, &token).unwrap_err();
+    assert!(matches!(err, AppError::TokenConflict(ref stale) if *stale == token.purchase_token));
+
+    // The write after the side effect goes through on the reserved version
+    let updated = diesel::update(
+        purchase_tokens::table
+            .find(&reserved.id)
+            .filter(purchase_tokens::version.eq(reserved.version)),
+    )
+    .set(purchase_tokens::status.eq(PurchaseTokenStatus::Expired))
+    .execute(&mut conn)
+    .unwrap();
+    assert_eq!(updated, 1);
+}
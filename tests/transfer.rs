@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::consts::YRAL_PRO_PLAN_PRODUCT_ID;
+use yral_billing::error::AppResult;
+use yral_billing::integrations::user_info::{MockUserInfo, UserInfoApi};
+use yral_billing::line_items::record_line_items;
+use yral_billing::model::{PurchaseToken, Subscription, TokenTransfer};
+use yral_billing::subscriptions::upsert_subscription;
+use yral_billing::test_support::{
+    as_user, memory_state, new_test_user, test_app, test_user, PurchaseTokenBuilder,
+    SubscriptionResponseBuilder,
+};
+use yral_billing::types::{
+    Plan, PurchaseEnvironment, PurchaseTokenStatus, SubscriptionSource, SubscriptionStatus,
+    TransferTokensRequest,
+};
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Canister fake recording plan changes as `grant <product> <user>` and `revoke <user>`
+#[derive(Clone, Default)]
+struct RecordingUserInfo {
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl UserInfoApi for RecordingUserInfo {
+    async fn grant_pro_plan(&self, product_id: &str, user_id: &str) -> AppResult<()> {
+        let call = format!("grant {} {}", product_id, user_id);
+        self.calls.lock().unwrap().push(call);
+        Ok(())
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("revoke {}", user_id));
+        Ok(())
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        MockUserInfo.get_plan(user_id).await
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_transfer_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn db_path(&self) -> &str {
+        &self.db_path
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn post_transfer(app: Router, payload: &TransferTokensRequest) -> axum::response::Response {
+    let req = Request::builder()
+        .method("POST")
+        .uri("/google/transfer")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(payload).unwrap()))
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+// Tokens move to the new user and every move is audited
+#[tokio::test]
+async fn test_transfer_moves_tokens_and_audits() {
+    use yral_billing::schema::{purchase_tokens, token_transfers};
+
+    let db_guard = TestDbGuard::new();
+    let mut conn = SqliteConnection::establish(db_guard.db_path()).unwrap();
+    let token = format!("token_{}", uuid::Uuid::new_v4());
+
+    let expiry_at = (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc();
+    let existing = PurchaseToken::new(
+        test_user("old_user"),
+        token.clone(),
+        expiry_at,
+        PurchaseTokenStatus::AccessGranted,
+        PurchaseEnvironment::Production,
+    );
+    diesel::insert_into(purchase_tokens::table)
+        .values(&existing)
+        .execute(&mut conn)
+        .unwrap();
+
+    // Nobody but the source user may move its tokens
+    let app = as_user(test_app(AppState::new().await), &test_user("new_user"));
+    let request = TransferTokensRequest {
+        from_user_id: test_user("old_user"),
+        to_user_id: test_user("new_user"),
+    };
+    let res = post_transfer(app, &request).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let unmoved: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(unmoved.user_id, test_user("old_user"));
+
+    let app = as_user(test_app(AppState::new().await), &test_user("old_user"));
+    let res = post_transfer(
+        app,
+        &TransferTokensRequest {
+            from_user_id: test_user("old_user"),
+            to_user_id: test_user("new_user"),
+        },
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["data"]["transferred"], 1);
+    assert_eq!(response["data"]["pro_access_moved"], true);
+
+    let stored: PurchaseToken = purchase_tokens::table
+        .filter(purchase_tokens::purchase_token.eq(&token))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(stored.user_id, test_user("new_user"));
+
+    let audit: Vec<TokenTransfer> = token_transfers::table
+        .filter(token_transfers::purchase_token.eq(&token))
+        .load(&mut conn)
+        .unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].from_user_id, test_user("old_user"));
+    assert_eq!(audit[0].to_user_id, test_user("new_user"));
+}
+
+// Transferring from a user without tokens is rejected
+#[tokio::test]
+async fn test_transfer_without_tokens_rejected() {
+    let _db_guard = TestDbGuard::new();
+    let from_user_id = new_test_user();
+
+    let app = as_user(test_app(AppState::new().await), &from_user_id);
+    let res = post_transfer(
+        app,
+        &TransferTokensRequest {
+            from_user_id,
+            to_user_id: test_user("new_user"),
+        },
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+// Pro moves with the token's own product, but not off or onto users another subscription covers
+#[tokio::test]
+async fn test_transfer_respects_other_subscriptions() {
+    let user_info = RecordingUserInfo::default();
+    let mut app_state = memory_state().await;
+    app_state.user_info = Arc::new(user_info.clone());
+    let mut conn = app_state.get_db_connection().unwrap();
+    let now = chrono::Utc::now().naive_utc();
+    let (from, to) = (new_test_user(), new_test_user());
+
+    let token = PurchaseTokenBuilder::new(&from).insert(&mut conn);
+    let subscription = SubscriptionResponseBuilder::new()
+        .product_id(YRAL_PRO_PLAN_PRODUCT_ID)
+        .build();
+    record_line_items(&mut conn, &token.purchase_token, &subscription, now).unwrap();
+
+    // The destination user already pays on the web
+    let mut web = Subscription::new(
+        to.clone(),
+        SubscriptionSource::Stripe,
+        format!("sub_{}", uuid::Uuid::new_v4()),
+        SubscriptionStatus::Active,
+        now,
+        now + chrono::Duration::days(30),
+    );
+    web.product_id = Some(YRAL_PRO_PLAN_PRODUCT_ID.to_string());
+    upsert_subscription(&mut conn, &web).unwrap();
+
+    let app = as_user(test_app(app_state.clone()), &from);
+    let request = TransferTokensRequest {
+        from_user_id: from.clone(),
+        to_user_id: to.clone(),
+    };
+    let res = post_transfer(app.clone(), &request).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        *user_info.calls.lock().unwrap(),
+        vec![format!("revoke {}", from)]
+    );
+
+    // Moving it back reaches the canister for both users, with the token's product
+    user_info.calls.lock().unwrap().clear();
+    let web_ended = Subscription {
+        status: SubscriptionStatus::Expired,
+        ..web
+    };
+    upsert_subscription(&mut conn, &web_ended).unwrap();
+    let app = as_user(test_app(app_state), &to);
+    let request = TransferTokensRequest {
+        from_user_id: to.clone(),
+        to_user_id: from.clone(),
+    };
+    let res = post_transfer(app, &request).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        *user_info.calls.lock().unwrap(),
+        vec![
+            format!("revoke {}", to),
+            format!("grant {} {}", YRAL_PRO_PLAN_PRODUCT_ID, from)
+        ]
+    );
+}
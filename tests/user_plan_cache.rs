@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use ic_agent::export::Principal;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::error::AppResult;
+use yral_billing::integrations::user_info::{CachingUserInfo, MockUserInfo, UserInfoApi};
+use yral_billing::metrics::{Metrics, USER_PLAN_CACHE_TOTAL};
+use yral_billing::routes::admin::lookup_user_plans;
+use yral_billing::test_support::memory_state;
+use yral_billing::types::Plan;
+
+/// Canister fake holding each user's plan and counting plan lookups
+#[derive(Clone, Default)]
+struct PlanStore {
+    plans: Arc<Mutex<HashMap<String, Plan>>>,
+    lookups: Arc<AtomicUsize>,
+}
+
+impl PlanStore {
+    fn set(&self, user_id: &str, plan: Plan) {
+        self.plans.lock().unwrap().insert(user_id.to_string(), plan);
+    }
+
+    fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl UserInfoApi for PlanStore {
+    async fn grant_pro_plan(&self, _product_id: &str, user_id: &str) -> AppResult<()> {
+        self.set(user_id, Plan::Pro);
+        Ok(())
+    }
+
+    async fn revoke_pro_plan(&self, user_id: &str) -> AppResult<()> {
+        self.set(user_id, Plan::Free);
+        Ok(())
+    }
+
+    async fn get_plan(&self, user_id: &str) -> AppResult<Plan> {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        Ok(self
+            .plans
+            .lock()
+            .unwrap()
+            .get(user_id)
+            .copied()
+            .unwrap_or(Plan::Free))
+    }
+
+    async fn get_credits(&self, user_id: &str) -> AppResult<u32> {
+        MockUserInfo.get_credits(user_id).await
+    }
+
+    async fn deduct_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.deduct_credits(user_principal, amount).await
+    }
+
+    async fn increment_credits(&self, user_principal: Principal, amount: u32) -> AppResult<()> {
+        MockUserInfo.increment_credits(user_principal, amount).await
+    }
+}
+
+fn caching(store: &PlanStore, metrics: &Metrics) -> CachingUserInfo {
+    CachingUserInfo::new(
+        Arc::new(store.clone()),
+        Duration::from_secs(60),
+        metrics.clone(),
+    )
+}
+
+// Repeated lookups are served from the cache until we change the plan ourselves
+#[tokio::test]
+async fn test_plan_cached_until_changed() {
+    let store = PlanStore::default();
+    let metrics = Metrics::new();
+    let user_info = caching(&store, &metrics);
+
+    assert_eq!(user_info.get_plan("user_1").await.unwrap(), Plan::Free);
+    assert_eq!(user_info.get_plan("user_1").await.unwrap(), Plan::Free);
+    assert_eq!(store.lookups(), 1);
+
+    user_info
+        .grant_pro_plan("yral_pro", "user_1")
+        .await
+        .unwrap();
+    assert_eq!(user_info.get_plan("user_1").await.unwrap(), Plan::Pro);
+    assert_eq!(store.lookups(), 2);
+
+    user_info.revoke_pro_plan("user_1").await.unwrap();
+    assert_eq!(user_info.get_plan("user_1").await.unwrap(), Plan::Free);
+    assert_eq!(store.lookups(), 3);
+
+    assert_eq!(
+        metrics.counter(USER_PLAN_CACHE_TOTAL, &[("result", "hit")]),
+        1
+    );
+    assert_eq!(
+        metrics.counter(USER_PLAN_CACHE_TOTAL, &[("result", "miss")]),
+        3
+    );
+}
+
+// A batch only asks the canister for users missing from the cache
+#[tokio::test]
+async fn test_batch_looks_up_missing_users() {
+    let store = PlanStore::default();
+    store.set("user_2", Plan::Pro);
+    let user_info = caching(&store, &Metrics::new());
+
+    user_info.get_plan("user_1").await.unwrap();
+    let users: Vec<String> = ["user_1", "user_2", "user_3"]
+        .iter()
+        .map(|user| user.to_string())
+        .collect();
+    let plans = user_info.get_plans(&users).await.unwrap();
+
+    assert_eq!(plans.len(), 3);
+    assert_eq!(plans["user_2"], Plan::Pro);
+    assert_eq!(store.lookups(), 3);
+
+    // All cached now
+    user_info.get_plans(&users).await.unwrap();
+    assert_eq!(store.lookups(), 3);
+}
+
+#[tokio::test]
+async fn test_admin_plan_lookup() {
+    let store = PlanStore::default();
+    store.set("user_2", Plan::Pro);
+    let mut app_state = memory_state().await;
+    app_state.user_info = Arc::new(caching(&store, &Metrics::new()));
+    let app = Router::new()
+        .route("/admin/users/plans", post(lookup_user_plans))
+        .with_state(app_state);
+
+    let lookup = |user_ids: Vec<String>| {
+        Request::builder()
+            .method("POST")
+            .uri("/admin/users/plans")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::to_vec(&serde_json::json!({ "user_ids": user_ids })).unwrap(),
+            ))
+            .unwrap()
+    };
+
+    let res = app
+        .clone()
+        .oneshot(lookup(vec!["user_2".to_string(), "user_1".to_string()]))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(
+        response["data"],
+        serde_json::json!([
+            { "user_id": "user_2", "plan": "pro" },
+            { "user_id": "user_1", "plan": "free" },
+        ])
+    );
+
+    let too_many = (0..101).map(|i| format!("user_{}", i)).collect();
+    let res = app.oneshot(lookup(too_many)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
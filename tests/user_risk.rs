@@ -0,0 +1,197 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use base64::prelude::*;
+use diesel::prelude::*;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tower::ServiceExt; // for `oneshot`
+use uuid;
+use yral_billing::feature_flags::RISK_MANUAL_APPROVAL;
+use yral_billing::integrations::google_play::MOCK_SUBSCRIPTION_ACCOUNT_ID;
+use yral_billing::model::UserRisk;
+use yral_billing::risk::{approve_user, record_risk_event, requires_approval, RiskEvent};
+use yral_billing::schema::user_risk;
+use yral_billing::test_support::{test_app, PurchaseTokenBuilder, VerifyRequestBuilder};
+use yral_billing::types::SubscriptionNotificationType;
+use yral_billing::AppState;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// The mock Google Play client reports this account id for every subscription
+const MOCK_USER_ID: &str = MOCK_SUBSCRIPTION_ACCOUNT_ID;
+
+struct TestDbGuard {
+    db_path: String,
+    original_database_url: Option<String>,
+}
+
+impl TestDbGuard {
+    fn new() -> Self {
+        let test_db = format!("./test_user_risk_{}.db", uuid::Uuid::new_v4());
+        let original_database_url = std::env::var("DATABASE_URL").ok();
+        unsafe {
+            std::env::set_var("DATABASE_URL", &test_db);
+        }
+        let mut conn = SqliteConnection::establish(&test_db).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self {
+            db_path: test_db,
+            original_database_url,
+        }
+    }
+
+    fn conn(&self) -> SqliteConnection {
+        SqliteConnection::establish(&self.db_path).unwrap()
+    }
+}
+
+impl Drop for TestDbGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+        match &self.original_database_url {
+            Some(url) => unsafe { std::env::set_var("DATABASE_URL", url) },
+            None => std::env::remove_var("DATABASE_URL"),
+        }
+    }
+}
+
+async fn post_json(app: Router, uri: &str, body: &serde_json::Value) -> axum::response::Response {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap();
+    app.oneshot(req).await.unwrap()
+}
+
+async fn post_notification(app: Router, notification: serde_json::Value) {
+    let payload = serde_json::json!({
+        "message": {
+            "data": BASE64_STANDARD.encode(notification.to_string()),
+            "messageId": uuid::Uuid::new_v4().to_string(),
+            "publishTime": chrono::Utc::now().to_rfc3339(),
+        }
+    });
+    let res = post_json(app, "/google/rtdn-webhook", &payload).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+// Users are flagged once revocations and voids reach the threshold, and again after approval
+#[tokio::test]
+async fn test_user_flagged_at_threshold() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+
+    for event in [RiskEvent::Revoked, RiskEvent::Voided] {
+        let risk = record_risk_event(&mut conn, "user_1", event, 3, now).unwrap();
+        assert!(risk.flagged_at.is_none());
+    }
+    let risk = record_risk_event(&mut conn, "user_1", RiskEvent::Voided, 3, now).unwrap();
+    assert_eq!((risk.revoked_count, risk.voided_count), (1, 2));
+    assert!(risk.flagged_at.is_some());
+    assert!(requires_approval(&mut conn, "user_1").unwrap());
+    assert!(!requires_approval(&mut conn, "user_2").unwrap());
+
+    let approved = approve_user(&mut conn, "user_1", "ops@yral.com", now)
+        .unwrap()
+        .unwrap();
+    assert_eq!(approved.approved_by.as_deref(), Some("ops@yral.com"));
+    assert!(!requires_approval(&mut conn, "user_1").unwrap());
+
+    // Only flagged users can be approved
+    assert!(approve_user(&mut conn, "user_2", "ops@yral.com", now)
+        .unwrap()
+        .is_none());
+
+    record_risk_event(&mut conn, "user_1", RiskEvent::Revoked, 3, now).unwrap();
+    assert!(requires_approval(&mut conn, "user_1").unwrap());
+}
+
+// Revoked and voided notifications are counted against the purchase's owner
+#[tokio::test]
+async fn test_notifications_are_counted() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let app = test_app(AppState::new().await);
+
+    let revoked = PurchaseTokenBuilder::new(MOCK_USER_ID).insert(&mut conn);
+    post_notification(
+        app.clone(),
+        serde_json::json!({
+            "version": "1.0",
+            "packageName": "com.example",
+            "eventTimeMillis": chrono::Utc::now().timestamp_millis().to_string(),
+            "subscriptionNotification": {
+                "version": "1.0",
+                "notificationType": SubscriptionNotificationType::Revoked,
+                "purchaseToken": revoked.purchase_token,
+                "subscriptionId": "mock-product-id",
+            }
+        }),
+    )
+    .await;
+
+    let voided = PurchaseTokenBuilder::new(MOCK_USER_ID).insert(&mut conn);
+    post_notification(
+        app,
+        serde_json::json!({
+            "version": "1.0",
+            "packageName": "com.example",
+            "eventTimeMillis": chrono::Utc::now().timestamp_millis().to_string(),
+            "voidedPurchaseNotification": {
+                "purchaseToken": voided.purchase_token,
+                "orderId": "GPA.0000-0000-0000-00000",
+                "productType": 1,
+                "refundType": 1,
+            }
+        }),
+    )
+    .await;
+
+    let risk: UserRisk = user_risk::table
+        .filter(user_risk::user_id.eq(MOCK_USER_ID))
+        .first(&mut conn)
+        .unwrap();
+    assert_eq!(risk.revoked_count, 1);
+    assert_eq!(risk.voided_count, 1);
+    assert!(risk.flagged_at.is_none());
+}
+
+// With manual approval on, flagged users can't verify until an operator approves them
+#[tokio::test]
+async fn test_flagged_user_needs_approval() {
+    let db_guard = TestDbGuard::new();
+    let mut conn = db_guard.conn();
+    let now = chrono::Utc::now().naive_utc();
+    for _ in 0..3 {
+        record_risk_event(&mut conn, MOCK_USER_ID, RiskEvent::Voided, 3, now).unwrap();
+    }
+
+    let app_state = AppState::new().await;
+    let app = test_app(app_state.clone());
+
+    // Flagging alone changes nothing until the flag is turned on
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    app_state
+        .feature_flags
+        .set(&mut conn, RISK_MANUAL_APPROVAL, true, now)
+        .unwrap();
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(response["code"], "MANUAL_APPROVAL_REQUIRED");
+
+    approve_user(&mut conn, MOCK_USER_ID, "ops@yral.com", now).unwrap();
+    let req = VerifyRequestBuilder::new(MOCK_USER_ID).request();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
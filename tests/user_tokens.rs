@@ -0,0 +1,122 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::{Extension, Router};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use tower::ServiceExt; // for `oneshot`
+use yral_billing::auth::{Claims, ADMIN_SCOPE};
+use yral_billing::model::SubscriptionEvent;
+use yral_billing::routes::user_tokens::{list_user_tokens, USER_TOKEN_HISTORY_LIMIT};
+use yral_billing::schema::subscription_events;
+use yral_billing::test_support::{memory_state, new_test_user, PurchaseTokenBuilder};
+use yral_billing::types::{PurchaseTokenStatus, SubscriptionEventKind};
+use yral_billing::AppState;
+
+async fn list(
+    app_state: &AppState,
+    caller: &str,
+    scope: Option<&str>,
+    uri: &str,
+) -> (StatusCode, serde_json::Value) {
+    let claims = Claims {
+        aud: "billing".to_string(),
+        exp: 0,
+        sub: Some(caller.to_string()),
+        scope: scope.map(str::to_string),
+    };
+    let res = Router::new()
+        .route("/users/{user_id}/tokens", get(list_user_tokens))
+        .layer(Extension(claims))
+        .with_state(app_state.clone())
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = res.status();
+    let body_bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body_bytes).unwrap())
+}
+
+// Users page through their own purchases newest first, each with its latest history
+#[tokio::test]
+async fn test_user_lists_own_tokens() {
+    let app_state = memory_state().await;
+    let mut conn = app_state.get_db_connection().unwrap();
+    let user = new_test_user();
+    let now = Utc::now().naive_utc();
+
+    let older = PurchaseTokenBuilder::new(&user)
+        .status(PurchaseTokenStatus::Expired)
+        .created_at(now - Duration::days(60))
+        .insert(&mut conn);
+    let newer = PurchaseTokenBuilder::new(&user)
+        .created_at(now - Duration::days(1))
+        .insert(&mut conn);
+    PurchaseTokenBuilder::new(&new_test_user()).insert(&mut conn);
+
+    for day in 0..7 {
+        let kind = if day == 0 {
+            SubscriptionEventKind::Purchase
+        } else {
+            SubscriptionEventKind::Renewal
+        };
+        diesel::insert_into(subscription_events::table)
+            .values(&SubscriptionEvent::new(
+                user.clone(),
+                older.purchase_token.clone(),
+                kind,
+                Some(format!("GPA.{}", day)),
+                "yral_pro_plan".to_string(),
+                now - Duration::days(60 - day),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let uri = format!("/users/{}/tokens?limit=1", user);
+    let (status, body) = list(&app_state, &user, None, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let items = body["data"]["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], newer.id);
+    assert!(items[0]["product_id"].is_null());
+    assert!(items[0].get("purchase_token").is_none());
+    assert_eq!(body["data"]["total_estimate"], 2);
+
+    let cursor = body["data"]["next_cursor"].as_str().unwrap().to_string();
+    let uri = format!("/users/{}/tokens?limit=1&cursor={}", user, cursor);
+    let (_, body) = list(&app_state, &user, None, &uri).await;
+    let items = body["data"]["items"].as_array().unwrap();
+    assert_eq!(items[0]["id"], older.id);
+    assert_eq!(items[0]["status"], "Expired");
+    assert_eq!(items[0]["product_id"], "yral_pro_plan");
+    let history = items[0]["history"].as_array().unwrap();
+    assert_eq!(history.len(), USER_TOKEN_HISTORY_LIMIT);
+    assert_eq!(history[0]["order_id"], "GPA.6");
+    assert!(body["data"]["next_cursor"].is_null());
+
+    let uri = format!("/users/{}/tokens?status=AccessGranted", user);
+    let (_, body) = list(&app_state, &user, None, &uri).await;
+    let items = body["data"]["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["id"], newer.id);
+}
+
+// Another user's purchases are refused, unless the caller is an operator
+#[tokio::test]
+async fn test_other_users_tokens_refused() {
+    let app_state = memory_state().await;
+    let user = new_test_user();
+    PurchaseTokenBuilder::new(&user).insert(&mut app_state.get_db_connection().unwrap());
+    let uri = format!("/users/{}/tokens", user);
+
+    let (status, body) = list(&app_state, &new_test_user(), None, &uri).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "NOT_OWNER");
+
+    let (status, body) = list(&app_state, "ops@yral.com", Some(ADMIN_SCOPE), &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["items"].as_array().unwrap().len(), 1);
+}